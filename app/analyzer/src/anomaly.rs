@@ -0,0 +1,265 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Datelike, Duration, DurationRound, Timelike, Utc};
+use normalizer::NormalizedFlow;
+use serde::{Deserialize, Serialize};
+
+use crate::{is_lan, new_alert_id, Alert, AlertStatus, Detector, Severity};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnomalyConfig {
+    /// EWMA smoothing factor applied to each closed hour.
+    pub alpha: f64,
+    /// Deviations (in EWMA standard deviations) that count as anomalous.
+    pub threshold_sigma: f64,
+    /// Hours of history per entity before alerts are produced.
+    pub warmup_hours: u32,
+    /// Weight of the day-of-week/hour seasonal mean in the expected value.
+    pub seasonal_weight: f64,
+    /// Absolute floor under the expected value so near-silent entities don't
+    /// alert on a handful of extra flows.
+    pub min_flows: f64,
+    pub min_bytes: f64,
+}
+
+impl Default for AnomalyConfig {
+    fn default() -> Self {
+        Self {
+            alpha: 0.3,
+            threshold_sigma: 4.0,
+            warmup_hours: 24,
+            seasonal_weight: 0.5,
+            min_flows: 20.0,
+            min_bytes: 1_048_576.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum EntityKind {
+    Process,
+    LanHost,
+}
+
+/// Smoothed mean/variance of one metric plus a 7x24 seasonal table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SeriesModel {
+    mean: f64,
+    var: f64,
+    seasonal: Vec<f64>,
+    seasonal_seen: Vec<bool>,
+}
+
+impl SeriesModel {
+    fn new() -> Self {
+        Self {
+            mean: 0.0,
+            var: 0.0,
+            seasonal: vec![0.0; 7 * 24],
+            seasonal_seen: vec![false; 7 * 24],
+        }
+    }
+
+    fn expected(&self, slot: usize, seasonal_weight: f64) -> f64 {
+        if self.seasonal_seen[slot] {
+            seasonal_weight * self.seasonal[slot] + (1.0 - seasonal_weight) * self.mean
+        } else {
+            self.mean
+        }
+    }
+
+    fn update(&mut self, value: f64, slot: usize, alpha: f64) {
+        let diff = value - self.mean;
+        self.mean += alpha * diff;
+        self.var = (1.0 - alpha) * (self.var + alpha * diff * diff);
+        if self.seasonal_seen[slot] {
+            self.seasonal[slot] += alpha * (value - self.seasonal[slot]);
+        } else {
+            self.seasonal[slot] = value;
+            self.seasonal_seen[slot] = true;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct EntityModel {
+    hours: u32,
+    flows: SeriesModel,
+    bytes: SeriesModel,
+    current_hour: DateTime<Utc>,
+    current_flows: f64,
+    current_bytes: f64,
+    alerted_hour: Option<DateTime<Utc>>,
+}
+
+/// Models per-hour flow and byte counts per process and per LAN host with an
+/// EWMA and day-of-week seasonality; alerts when the running hour exceeds the
+/// expected value by `threshold_sigma`.
+pub struct AnomalyDetector {
+    config: AnomalyConfig,
+    entities: HashMap<(EntityKind, String), EntityModel>,
+}
+
+impl Default for AnomalyDetector {
+    fn default() -> Self {
+        Self::new(AnomalyConfig::default())
+    }
+}
+
+impl AnomalyDetector {
+    pub fn new(config: AnomalyConfig) -> Self {
+        Self {
+            config,
+            entities: HashMap::new(),
+        }
+    }
+
+    fn observe_entity(
+        &mut self,
+        kind: EntityKind,
+        name: String,
+        flow: &NormalizedFlow,
+    ) -> Option<Alert> {
+        let hour = flow
+            .window_start
+            .duration_trunc(Duration::hours(1))
+            .unwrap_or(flow.window_start);
+        let config = &self.config;
+        let model = self
+            .entities
+            .entry((kind, name.clone()))
+            .or_insert_with(|| EntityModel {
+                hours: 0,
+                flows: SeriesModel::new(),
+                bytes: SeriesModel::new(),
+                current_hour: hour,
+                current_flows: 0.0,
+                current_bytes: 0.0,
+                alerted_hour: None,
+            });
+
+        // Close every hour between the last bucket and this one; silent hours
+        // count as zero so the model learns quiet periods too.
+        while model.current_hour < hour {
+            let slot = seasonal_slot(model.current_hour);
+            model.flows.update(model.current_flows, slot, config.alpha);
+            model.bytes.update(model.current_bytes, slot, config.alpha);
+            model.hours += 1;
+            model.current_flows = 0.0;
+            model.current_bytes = 0.0;
+            model.current_hour += Duration::hours(1);
+            if hour - model.current_hour > Duration::days(7) {
+                model.current_hour = hour;
+            }
+        }
+        model.current_flows += 1.0;
+        model.current_bytes += flow.bytes as f64;
+
+        if model.hours < config.warmup_hours || model.alerted_hour == Some(hour) {
+            return None;
+        }
+        let slot = seasonal_slot(hour);
+        let checks = [
+            ("flows", model.current_flows, &model.flows, config.min_flows),
+            ("bytes", model.current_bytes, &model.bytes, config.min_bytes),
+        ];
+        for (metric, observed, series, floor) in checks {
+            let expected = series.expected(slot, config.seasonal_weight);
+            let limit = expected.max(floor) + config.threshold_sigma * series.var.sqrt();
+            if observed > limit {
+                model.alerted_hour = Some(hour);
+                let label = match kind {
+                    EntityKind::Process => "process",
+                    EntityKind::LanHost => "LAN host",
+                };
+                return Some(Alert {
+                    id: new_alert_id(),
+                    ts: Utc::now(),
+                    severity: Severity::Medium,
+                    rule_id: format!("builtin.anomaly.{metric}"),
+                    summary: format!("Unusual hourly {metric} for {label} {name}"),
+                    flow_refs: flow.flow_id.into_iter().collect(),
+                    process_ref: flow.process.clone(),
+                    rationale: format!(
+                        "expected {:.0} {metric}/h (EWMA {:.0}, σ {:.0}, {} {:02}:00 seasonal), observed {:.0}",
+                        expected,
+                        series.mean,
+                        series.var.sqrt(),
+                        hour.weekday(),
+                        hour.hour(),
+                        observed
+                    ),
+                    suggested_action: Some(
+                        "Compare with recent activity and check for exfiltration or abuse".into(),
+                    ),
+                    status: AlertStatus::New,
+                    assignee: None,
+                    notes: Vec::new(),
+                    evidence: BTreeMap::new(),
+                });
+            }
+        }
+        None
+    }
+}
+
+fn seasonal_slot(hour: DateTime<Utc>) -> usize {
+    hour.weekday().num_days_from_monday() as usize * 24 + hour.hour() as usize
+}
+
+impl Detector for AnomalyDetector {
+    fn name(&self) -> &'static str {
+        "anomaly"
+    }
+
+    fn observe(&mut self, flow: &NormalizedFlow) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        if let Some(process) = flow.process.clone() {
+            alerts.extend(self.observe_entity(EntityKind::Process, process, flow));
+        }
+        if is_lan(&flow.src_ip) {
+            alerts.extend(self.observe_entity(EntityKind::LanHost, flow.src_ip.clone(), flow));
+        }
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn flow(hour: i64, minute: i64) -> NormalizedFlow {
+        let ts = Utc
+            .timestamp_opt(1_700_000_000 - 1_700_000_000 % 3600, 0)
+            .unwrap()
+            + Duration::hours(hour)
+            + Duration::minutes(minute);
+        NormalizedFlow {
+            window_start: ts,
+            window_end: ts,
+            src_ip: "192.168.1.30".into(),
+            dst_ip: "203.0.113.9".into(),
+            bytes: 1_000,
+            process: Some("sync".into()),
+            ..NormalizedFlow::default()
+        }
+    }
+
+    #[test]
+    fn spike_after_warmup() {
+        let mut detector = AnomalyDetector::default();
+        let mut alerts = Vec::new();
+        for hour in 0..48 {
+            for minute in 0..5 {
+                alerts.extend(detector.observe(&flow(hour, minute)));
+            }
+        }
+        assert!(alerts.is_empty());
+        for i in 0..200 {
+            alerts.extend(detector.observe(&flow(48, i % 60)));
+        }
+        assert!(alerts.iter().any(|a| a.rule_id == "builtin.anomaly.flows"));
+        assert!(alerts[0].rationale.contains("expected"));
+    }
+}
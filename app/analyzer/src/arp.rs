@@ -0,0 +1,405 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fs,
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use collector::{FlowEvent, Layer2EventKind};
+use serde::{Deserialize, Serialize};
+
+use crate::{new_alert_id, Alert, AlertStatus, Severity};
+
+/// A handful of vendors common on home and office gateways; extend with
+/// [`GatewayGuard::load_oui`].
+const BUILTIN_OUI: &[(&str, &str)] = &[
+    ("00:0C:29", "VMware"),
+    ("00:50:56", "VMware"),
+    ("08:00:27", "Oracle VirtualBox"),
+    ("00:14:BF", "Cisco-Linksys"),
+    ("00:18:0A", "Cisco Meraki"),
+    ("00:1B:11", "D-Link"),
+    ("14:CC:20", "TP-Link"),
+    ("50:C7:BF", "TP-Link"),
+    ("E4:8D:8C", "MikroTik"),
+    ("4C:5E:0C", "MikroTik"),
+    ("24:A4:3C", "Ubiquiti"),
+    ("FC:EC:DA", "Ubiquiti"),
+    ("00:24:D4", "Freebox"),
+    ("3C:37:86", "Netgear"),
+    ("B8:27:EB", "Raspberry Pi"),
+    ("DC:A6:32", "Raspberry Pi"),
+    ("00:11:32", "Synology"),
+    ("00:90:A9", "Western Digital"),
+    ("F0:9F:C2", "Ubiquiti"),
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayConfig {
+    /// Default gateway addresses to watch.
+    pub gateway_ips: Vec<String>,
+    /// DNS server addresses to watch.
+    pub dns_ips: Vec<String>,
+    pub garp_window_seconds: i64,
+    /// Gratuitous ARP/ND announcements inside the window that count as a flood.
+    pub garp_threshold: usize,
+}
+
+impl Default for GatewayConfig {
+    fn default() -> Self {
+        Self {
+            gateway_ips: Vec::new(),
+            dns_ips: Vec::new(),
+            garp_window_seconds: 10,
+            garp_threshold: 50,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MacBinding {
+    pub mac: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Watches ARP/ND traffic for changes to the MAC behind the gateway and DNS
+/// server addresses and for gratuitous-announcement floods.
+pub struct GatewayGuard {
+    config: GatewayConfig,
+    bindings: HashMap<String, MacBinding>,
+    oui: HashMap<String, String>,
+    announcements: VecDeque<(DateTime<Utc>, String)>,
+    flood_alerted: Option<DateTime<Utc>>,
+}
+
+impl Default for GatewayGuard {
+    fn default() -> Self {
+        Self::new(GatewayConfig::default())
+    }
+}
+
+fn normalize_mac(mac: &str) -> String {
+    mac.trim().replace('-', ":").to_ascii_uppercase()
+}
+
+fn is_gratuitous(event: &collector::Layer2EventMetadata) -> bool {
+    let broadcast = event
+        .mac_dst
+        .as_deref()
+        .map(|mac| {
+            normalize_mac(mac) == "FF:FF:FF:FF:FF:FF" || normalize_mac(mac) == "00:00:00:00:00:00"
+        })
+        .unwrap_or(false);
+    let self_announce = event.ip_src.is_some() && event.ip_src == event.ip_dst;
+    match event.kind {
+        Layer2EventKind::Arp => self_announce && (broadcast || event.mac_dst.is_none()),
+        // Unsolicited neighbor advertisements carry the override flag in the
+        // operation string as reported by the collectors.
+        Layer2EventKind::Nd => {
+            let op = event.operation.to_ascii_lowercase();
+            op.contains("advert") && (op.contains("unsolicited") || self_announce)
+        }
+    }
+}
+
+impl GatewayGuard {
+    pub fn new(config: GatewayConfig) -> Self {
+        Self {
+            config,
+            bindings: HashMap::new(),
+            oui: BUILTIN_OUI
+                .iter()
+                .map(|(prefix, vendor)| (prefix.to_string(), vendor.to_string()))
+                .collect(),
+            announcements: VecDeque::new(),
+            flood_alerted: None,
+        }
+    }
+
+    /// Loads an IEEE `oui.txt` style file (`AA-BB-CC   (hex)\tVendor`).
+    pub fn load_oui<P: AsRef<Path>>(&mut self, path: P) -> Result<usize> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .with_context(|| format!("reading OUI table {}", path.display()))?;
+        let mut added = 0;
+        for line in data.lines() {
+            let Some((prefix, vendor)) = line.split_once("(hex)") else {
+                continue;
+            };
+            let prefix = normalize_mac(prefix);
+            if prefix.len() == 8 {
+                self.oui.insert(prefix, vendor.trim().to_string());
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    pub fn watch_gateway(&mut self, ip: &str) {
+        if !self.config.gateway_ips.iter().any(|known| known == ip) {
+            self.config.gateway_ips.push(ip.to_string());
+        }
+    }
+
+    pub fn watch_dns(&mut self, ip: &str) {
+        if !self.config.dns_ips.iter().any(|known| known == ip) {
+            self.config.dns_ips.push(ip.to_string());
+        }
+    }
+
+    pub fn bindings(&self) -> &HashMap<String, MacBinding> {
+        &self.bindings
+    }
+
+    pub fn vendor(&self, mac: &str) -> String {
+        let mac = normalize_mac(mac);
+        if let Some(vendor) = mac.get(..8).and_then(|prefix| self.oui.get(prefix)) {
+            return vendor.clone();
+        }
+        let first = mac
+            .get(..2)
+            .and_then(|octet| u8::from_str_radix(octet, 16).ok())
+            .unwrap_or(0);
+        if first & 0x02 != 0 {
+            "locally administered".into()
+        } else {
+            "unknown vendor".into()
+        }
+    }
+
+    fn role(&self, ip: &str) -> Option<&'static str> {
+        if self.config.gateway_ips.iter().any(|known| known == ip) {
+            Some("gateway")
+        } else if self.config.dns_ips.iter().any(|known| known == ip) {
+            Some("DNS server")
+        } else {
+            None
+        }
+    }
+
+    pub fn check(&mut self, event: &FlowEvent) -> Vec<Alert> {
+        let Some(layer2) = &event.layer2 else {
+            return Vec::new();
+        };
+        let now = event.ts_first;
+        let mut alerts = Vec::new();
+
+        if let (Some(ip), Some(mac)) = (&layer2.ip_src, &layer2.mac_src) {
+            let mac = normalize_mac(mac);
+            let previous = self.bindings.get(ip).cloned();
+            match previous {
+                Some(binding) if binding.mac != mac => {
+                    if let Some(role) = self.role(ip) {
+                        alerts.push(self.binding_alert(event, role, ip, &binding, &mac));
+                    }
+                    self.bindings.insert(
+                        ip.clone(),
+                        MacBinding {
+                            mac,
+                            first_seen: now,
+                            last_seen: now,
+                        },
+                    );
+                }
+                Some(_) => {
+                    if let Some(binding) = self.bindings.get_mut(ip) {
+                        binding.last_seen = now;
+                    }
+                }
+                None => {
+                    self.bindings.insert(
+                        ip.clone(),
+                        MacBinding {
+                            mac,
+                            first_seen: now,
+                            last_seen: now,
+                        },
+                    );
+                }
+            }
+        }
+
+        if is_gratuitous(layer2) {
+            alerts.extend(self.record_announcement(event, layer2, now));
+        }
+        alerts
+    }
+
+    fn binding_alert(
+        &self,
+        event: &FlowEvent,
+        role: &str,
+        ip: &str,
+        old: &MacBinding,
+        new_mac: &str,
+    ) -> Alert {
+        let old_vendor = self.vendor(&old.mac);
+        let new_vendor = self.vendor(new_mac);
+        let mut evidence = BTreeMap::new();
+        evidence.insert("ip".into(), ip.to_string());
+        evidence.insert("role".into(), role.to_string());
+        evidence.insert("old_mac".into(), old.mac.clone());
+        evidence.insert("old_vendor".into(), old_vendor.clone());
+        evidence.insert("new_mac".into(), new_mac.to_string());
+        evidence.insert("new_vendor".into(), new_vendor.clone());
+        Alert {
+            id: new_alert_id(),
+            ts: Utc::now(),
+            severity: Severity::High,
+            rule_id: "builtin.l2.gateway_mac_change".into(),
+            summary: format!("MAC address of the {role} {ip} changed"),
+            flow_refs: Vec::new(),
+            process_ref: None,
+            rationale: format!(
+                "{role} {ip}: old MAC {} ({old_vendor}, seen since {}), new MAC {new_mac} ({new_vendor}) via {:?} {}",
+                old.mac,
+                old.first_seen.to_rfc3339(),
+                event
+                    .layer2
+                    .as_ref()
+                    .map(|l2| l2.kind.clone())
+                    .unwrap_or(Layer2EventKind::Arp),
+                event
+                    .layer2
+                    .as_ref()
+                    .map(|l2| l2.operation.as_str())
+                    .unwrap_or("")
+            ),
+            suggested_action: Some(
+                "Verify the router was replaced; otherwise look for ARP spoofing on the LAN".into(),
+            ),
+            status: AlertStatus::New,
+            assignee: None,
+            notes: Vec::new(),
+            evidence,
+        }
+    }
+
+    fn record_announcement(
+        &mut self,
+        event: &FlowEvent,
+        layer2: &collector::Layer2EventMetadata,
+        now: DateTime<Utc>,
+    ) -> Option<Alert> {
+        let window = Duration::seconds(self.config.garp_window_seconds);
+        let sender = layer2
+            .mac_src
+            .as_deref()
+            .map(normalize_mac)
+            .unwrap_or_else(|| "unknown".into());
+        self.announcements.push_back((now, sender));
+        while self
+            .announcements
+            .front()
+            .map(|(ts, _)| now - *ts > window)
+            .unwrap_or(false)
+        {
+            self.announcements.pop_front();
+        }
+        let rearmed = self
+            .flood_alerted
+            .map(|ts| now - ts > window)
+            .unwrap_or(true);
+        if self.announcements.len() < self.config.garp_threshold || !rearmed {
+            return None;
+        }
+        self.flood_alerted = Some(now);
+
+        let mut senders: HashMap<&str, usize> = HashMap::new();
+        for (_, mac) in &self.announcements {
+            *senders.entry(mac.as_str()).or_default() += 1;
+        }
+        let mut senders: Vec<(&str, usize)> = senders.into_iter().collect();
+        senders.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        let top = senders
+            .iter()
+            .take(5)
+            .map(|(mac, count)| format!("{mac} ({}) x{count}", self.vendor(mac)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut evidence = BTreeMap::new();
+        evidence.insert("count".into(), self.announcements.len().to_string());
+        evidence.insert(
+            "window_seconds".into(),
+            self.config.garp_window_seconds.to_string(),
+        );
+        evidence.insert("senders".into(), top.clone());
+        Some(Alert {
+            id: new_alert_id(),
+            ts: Utc::now(),
+            severity: Severity::High,
+            rule_id: "builtin.l2.garp_flood".into(),
+            summary: format!(
+                "Gratuitous {:?} flood: {} announcements in {}s",
+                layer2.kind,
+                self.announcements.len(),
+                self.config.garp_window_seconds
+            ),
+            flow_refs: Vec::new(),
+            process_ref: event.process.as_ref().and_then(|p| p.name.clone()),
+            rationale: format!("Top senders: {top}"),
+            suggested_action: Some(
+                "Locate the sending host; floods usually precede or accompany ARP poisoning".into(),
+            ),
+            status: AlertStatus::New,
+            assignee: None,
+            notes: Vec::new(),
+            evidence,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use collector::Layer2EventMetadata;
+
+    fn arp(ip: &str, mac: &str, target: &str, offset: i64) -> FlowEvent {
+        let ts = Utc.timestamp_opt(1_700_000_000 + offset, 0).unwrap();
+        FlowEvent {
+            ts_first: ts,
+            ts_last: ts,
+            layer2: Some(Layer2EventMetadata {
+                kind: Layer2EventKind::Arp,
+                operation: "Reply".into(),
+                mac_src: Some(mac.into()),
+                ip_src: Some(ip.into()),
+                mac_dst: Some("ff:ff:ff:ff:ff:ff".into()),
+                ip_dst: Some(target.into()),
+            }),
+            ..FlowEvent::default()
+        }
+    }
+
+    #[test]
+    fn gateway_mac_change_and_garp_flood() {
+        let mut guard = GatewayGuard::new(GatewayConfig {
+            garp_threshold: 10,
+            ..GatewayConfig::default()
+        });
+        guard.watch_gateway("192.168.1.1");
+        assert!(guard
+            .check(&arp("192.168.1.1", "e4:8d:8c:00:00:01", "192.168.1.20", 0))
+            .is_empty());
+        let alerts = guard.check(&arp("192.168.1.1", "b8:27:eb:00:00:02", "192.168.1.20", 5));
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].rule_id, "builtin.l2.gateway_mac_change");
+        assert!(alerts[0].rationale.contains("MikroTik"));
+        assert!(alerts[0].rationale.contains("Raspberry Pi"));
+
+        let mut flood = Vec::new();
+        for i in 0..20 {
+            flood.extend(guard.check(&arp(
+                "192.168.1.77",
+                "b8:27:eb:00:00:02",
+                "192.168.1.77",
+                10 + i / 5,
+            )));
+        }
+        assert_eq!(flood.len(), 1);
+        assert_eq!(flood[0].rule_id, "builtin.l2.garp_flood");
+    }
+}
@@ -0,0 +1,452 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use collector::FlowDirection;
+use normalizer::NormalizedFlow;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    first_contact::{destination, process_key, DestinationProfile, ProcessDestinations},
+    listener::KnownListener,
+    new_alert_id, Alert, AlertStatus, Severity,
+};
+
+/// Standard deviations above the learned mean before a flow volume is unusual.
+const VOLUME_SIGMA: f64 = 4.0;
+/// Flows a process must have produced while learning before volumes are judged.
+const VOLUME_MIN_SAMPLES: u64 = 30;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BaselineMode {
+    /// No profile is being built and no deviation alerts are produced.
+    #[default]
+    Idle,
+    Learning,
+    Enforcing,
+}
+
+/// Lifecycle change asked for from outside the analyzer, e.g. queued in
+/// storage by `nets-cli baseline` for the daemon to apply.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BaselineCommand {
+    /// Start (or resume) learning, keeping what was learned before.
+    Learn,
+    /// Stop learning and alert on deviations.
+    Freeze,
+    /// Forget everything learned and stop.
+    Reset,
+}
+
+impl BaselineCommand {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BaselineCommand::Learn => "learn",
+            BaselineCommand::Freeze => "freeze",
+            BaselineCommand::Reset => "reset",
+        }
+    }
+}
+
+/// Running mean/variance (Welford) of bytes per flow.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VolumeStats {
+    pub count: u64,
+    pub mean: f64,
+    pub m2: f64,
+    pub max: u64,
+}
+
+impl VolumeStats {
+    pub fn record(&mut self, bytes: u64) {
+        self.count += 1;
+        let value = bytes as f64;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+        self.max = self.max.max(bytes);
+    }
+
+    pub fn stddev(&self) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        (self.m2 / (self.count - 1) as f64).sqrt()
+    }
+}
+
+/// Everything learned about the host while in learning mode.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BaselineProfile {
+    #[serde(default)]
+    pub mode: BaselineMode,
+    pub started_at: Option<DateTime<Utc>>,
+    pub frozen_at: Option<DateTime<Utc>>,
+    pub listeners: HashMap<String, KnownListener>,
+    pub destinations: DestinationProfile,
+    pub volumes: HashMap<String, VolumeStats>,
+}
+
+impl BaselineProfile {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .with_context(|| format!("reading baseline profile {}", path.display()))?;
+        let profile = serde_json::from_str(&data)
+            .with_context(|| format!("parsing baseline profile {}", path.display()))?;
+        Ok(profile)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("writing baseline profile {}", path.display()))?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BaselineStatus {
+    pub mode: BaselineMode,
+    pub started_at: Option<DateTime<Utc>>,
+    pub frozen_at: Option<DateTime<Utc>>,
+    pub listeners: usize,
+    pub processes: usize,
+    pub destinations: usize,
+    pub volume_profiles: usize,
+}
+
+/// Explicit learn/enforce lifecycle: `start` records listeners, destinations and
+/// volumes; `freeze` stops learning and turns deviations into alerts. Nothing
+/// is learned before `start` (`nets-cli baseline learn`): there is no
+/// automatic learning window, and an idle engine raises no alerts.
+#[derive(Default)]
+pub struct BaselineEngine {
+    profile: BaselineProfile,
+    /// Deviations already reported, so a repeated flow alerts only once.
+    reported: HashSet<String>,
+}
+
+impl BaselineEngine {
+    /// Restores a persisted profile together with the mode it was saved in.
+    pub fn from_profile(profile: BaselineProfile) -> Self {
+        Self {
+            profile,
+            reported: HashSet::new(),
+        }
+    }
+
+    pub fn mode(&self) -> &BaselineMode {
+        &self.profile.mode
+    }
+
+    pub fn start(&mut self) {
+        self.profile.mode = BaselineMode::Learning;
+        self.profile.started_at = Some(Utc::now());
+        self.profile.frozen_at = None;
+    }
+
+    pub fn freeze(&mut self) {
+        self.profile.mode = BaselineMode::Enforcing;
+        self.profile.frozen_at = Some(Utc::now());
+        self.reported.clear();
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Fails to freeze a baseline that was never learned, which would turn
+    /// every listener and destination into an alert.
+    pub fn apply(&mut self, command: BaselineCommand) -> Result<()> {
+        match command {
+            BaselineCommand::Learn => self.start(),
+            BaselineCommand::Freeze if self.profile.mode == BaselineMode::Idle => {
+                return Err(anyhow!("no baseline has been learned to freeze"));
+            }
+            BaselineCommand::Freeze => self.freeze(),
+            BaselineCommand::Reset => self.reset(),
+        }
+        Ok(())
+    }
+
+    pub fn profile(&self) -> &BaselineProfile {
+        &self.profile
+    }
+
+    pub fn status(&self) -> BaselineStatus {
+        BaselineStatus {
+            mode: self.profile.mode.clone(),
+            started_at: self.profile.started_at,
+            frozen_at: self.profile.frozen_at,
+            listeners: self.profile.listeners.len(),
+            processes: self.profile.destinations.processes.len(),
+            destinations: self
+                .profile
+                .destinations
+                .processes
+                .values()
+                .map(|p| p.destinations.len())
+                .sum(),
+            volume_profiles: self.profile.volumes.len(),
+        }
+    }
+
+    pub fn observe(&mut self, flow: &NormalizedFlow) -> Vec<Alert> {
+        match self.profile.mode {
+            BaselineMode::Idle => Vec::new(),
+            BaselineMode::Learning => {
+                self.learn(flow);
+                Vec::new()
+            }
+            BaselineMode::Enforcing => self.deviations(flow),
+        }
+    }
+
+    fn learn(&mut self, flow: &NormalizedFlow) {
+        if is_listener(flow) {
+            self.profile
+                .listeners
+                .entry(listener_key(flow))
+                .or_insert_with(|| KnownListener {
+                    proto: flow.proto.clone(),
+                    ip: flow.src_ip.clone(),
+                    port: flow.src_port,
+                    process: flow.process.clone(),
+                    sha256_16: flow.process_hash.clone(),
+                    first_seen: flow.window_start,
+                });
+            return;
+        }
+        let Some(key) = process_key(flow) else {
+            return;
+        };
+        if flow.direction != FlowDirection::Inbound {
+            self.profile
+                .destinations
+                .processes
+                .entry(key.clone())
+                .or_insert_with(|| ProcessDestinations {
+                    first_seen: flow.window_start,
+                    destinations: HashSet::new(),
+                })
+                .destinations
+                .insert(destination(flow));
+        }
+        self.profile
+            .volumes
+            .entry(key)
+            .or_default()
+            .record(flow.bytes);
+    }
+
+    fn deviations(&mut self, flow: &NormalizedFlow) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        if is_listener(flow) {
+            let key = listener_key(flow);
+            match self.profile.listeners.get_mut(&key) {
+                None if self.reported.insert(format!("listener/{key}")) => {
+                    alerts.push(deviation(
+                        flow,
+                        "builtin.baseline.listener",
+                        Severity::Medium,
+                        format!("Listener {}:{} not in baseline", flow.src_ip, flow.src_port),
+                        "Listener was not observed during baseline learning".into(),
+                    ));
+                }
+                None => {}
+                Some(known) => match (&known.sha256_16, &flow.process_hash) {
+                    (Some(old), Some(new)) if old != new => {
+                        alerts.push(deviation(
+                            flow,
+                            "builtin.listener.binary_changed",
+                            Severity::High,
+                            format!(
+                                "Listener on {}:{} is now served by a different binary",
+                                flow.src_ip, flow.src_port
+                            ),
+                            format!("Binary hash changed from {old} to {new}"),
+                        ));
+                        // Reported once per binary; the new one is what listens now.
+                        known.sha256_16 = Some(new.clone());
+                    }
+                    (None, Some(new)) => known.sha256_16 = Some(new.clone()),
+                    _ => {}
+                },
+            }
+            return alerts;
+        }
+        let Some(key) = process_key(flow) else {
+            return alerts;
+        };
+
+        if flow.direction != FlowDirection::Inbound {
+            let dest = destination(flow);
+            let known = self
+                .profile
+                .destinations
+                .processes
+                .get(&key)
+                .map(|p| p.destinations.contains(&dest))
+                .unwrap_or(false);
+            if !known && self.reported.insert(format!("destination/{key}/{dest}")) {
+                alerts.push(deviation(
+                    flow,
+                    "builtin.baseline.destination",
+                    Severity::Low,
+                    format!(
+                        "{} contacted {} outside its baseline",
+                        flow.process.as_deref().unwrap_or("unknown"),
+                        dest
+                    ),
+                    "Destination was not observed for this process during learning".into(),
+                ));
+            }
+        }
+
+        if let Some(stats) = self.profile.volumes.get(&key) {
+            let threshold = stats.mean + VOLUME_SIGMA * stats.stddev();
+            if stats.count >= VOLUME_MIN_SAMPLES
+                && flow.bytes as f64 > threshold
+                && flow.bytes > stats.max
+            {
+                alerts.push(deviation(
+                    flow,
+                    "builtin.baseline.volume",
+                    Severity::Medium,
+                    format!(
+                        "Unusual transfer volume for {}",
+                        flow.process.as_deref().unwrap_or("unknown")
+                    ),
+                    format!(
+                        "expected ≤ {:.0} bytes (mean {:.0}, max {}), observed {}",
+                        threshold, stats.mean, stats.max, flow.bytes
+                    ),
+                ));
+            }
+        }
+        alerts
+    }
+}
+
+fn is_listener(flow: &NormalizedFlow) -> bool {
+    flow.direction == FlowDirection::Inbound && flow.state.as_deref() == Some("LISTEN")
+}
+
+fn listener_key(flow: &NormalizedFlow) -> String {
+    format!(
+        "{}/{}:{}/{}",
+        flow.proto.to_ascii_uppercase(),
+        flow.src_ip,
+        flow.src_port,
+        flow.process.as_deref().unwrap_or("-")
+    )
+}
+
+fn deviation(
+    flow: &NormalizedFlow,
+    rule_id: &str,
+    severity: Severity,
+    summary: String,
+    rationale: String,
+) -> Alert {
+    Alert {
+        id: new_alert_id(),
+        ts: Utc::now(),
+        severity,
+        rule_id: rule_id.into(),
+        summary,
+        flow_refs: flow.flow_id.into_iter().collect(),
+        process_ref: flow.process.clone(),
+        rationale,
+        suggested_action: Some("Review the change or re-learn the baseline".into()),
+        status: AlertStatus::New,
+        assignee: None,
+        notes: Vec::new(),
+        evidence: BTreeMap::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn flow(dst_ip: &str, bytes: u64) -> NormalizedFlow {
+        let ts = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        NormalizedFlow {
+            window_start: ts,
+            window_end: ts + Duration::seconds(60),
+            proto: "TCP".into(),
+            src_ip: "192.168.1.10".into(),
+            dst_ip: dst_ip.into(),
+            dst_port: 443,
+            direction: FlowDirection::Outbound,
+            bytes,
+            process: Some("backup".into()),
+            ..NormalizedFlow::default()
+        }
+    }
+
+    #[test]
+    fn learn_then_enforce() {
+        let mut engine = BaselineEngine::default();
+        assert!(engine.observe(&flow("203.0.113.5", 1_000)).is_empty());
+
+        engine.start();
+        for i in 0..40 {
+            engine.observe(&flow("203.0.113.5", 1_000 + i));
+        }
+        assert_eq!(engine.status().destinations, 1);
+
+        engine.freeze();
+        assert!(engine.observe(&flow("203.0.113.5", 1_010)).is_empty());
+        let new_dest = engine.observe(&flow("198.51.100.9", 1_010));
+        assert_eq!(new_dest[0].rule_id, "builtin.baseline.destination");
+        assert!(engine.observe(&flow("198.51.100.9", 1_010)).is_empty());
+        let volume = engine.observe(&flow("203.0.113.5", 5_000_000));
+        assert_eq!(volume[0].rule_id, "builtin.baseline.volume");
+
+        engine.apply(BaselineCommand::Reset).unwrap();
+        assert_eq!(engine.status().destinations, 0);
+        assert!(engine.apply(BaselineCommand::Freeze).is_err());
+        assert_eq!(engine.mode(), &BaselineMode::Idle);
+    }
+
+    #[test]
+    fn alerts_on_new_listeners_and_swapped_binaries() {
+        let listen = |port: u16, hash: &str| NormalizedFlow {
+            src_ip: "0.0.0.0".into(),
+            src_port: port,
+            dst_ip: String::new(),
+            direction: FlowDirection::Inbound,
+            state: Some("LISTEN".into()),
+            process: Some("svc".into()),
+            process_hash: Some(hash.into()),
+            ..flow("", 0)
+        };
+        let mut engine = BaselineEngine::default();
+        engine.start();
+        engine.observe(&listen(22, "aaaa"));
+        engine.freeze();
+        assert_eq!(engine.status().listeners, 1);
+
+        assert!(engine.observe(&listen(22, "aaaa")).is_empty());
+        let new_port = engine.observe(&listen(8080, "aaaa"));
+        assert_eq!(new_port[0].rule_id, "builtin.baseline.listener");
+        assert!(engine.observe(&listen(8080, "aaaa")).is_empty());
+
+        let swapped = engine.observe(&listen(22, "bbbb"));
+        assert_eq!(swapped[0].rule_id, "builtin.listener.binary_changed");
+        assert_eq!(swapped[0].severity, Severity::High);
+        assert!(engine.observe(&listen(22, "bbbb")).is_empty());
+    }
+}
@@ -3,7 +3,10 @@ use normalizer::NormalizedFlow;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
-use crate::Severity;
+use crate::{
+    fingerprints::{FingerprintLists, FingerprintType},
+    Severity,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Rule {
@@ -13,11 +16,52 @@ pub struct Rule {
     pub rationale: Option<String>,
     pub suggested_action: Option<String>,
     pub expression: String,
+    /// Free-form labels (e.g. `lateral-movement`, `smb`) used by severity
+    /// overrides and reporting.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Flows the rule must or must not match, checked by `rule-test`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tests: Vec<RuleTestCase>,
+}
+
+/// A flow kept next to a rule with the verdict the rule should reach on it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleTestCase {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// `NormalizedFlow` fields (`dst_port`, `process`, ...); the rest keep
+    /// their defaults.
+    pub flow: serde_json::Map<String, serde_json::Value>,
+    /// Whether the rule should fire.
+    #[serde(rename = "match")]
+    pub expect_match: bool,
+}
+
+impl RuleTestCase {
+    pub fn flow(&self) -> Result<NormalizedFlow> {
+        let serde_json::Value::Object(mut flow) = serde_json::to_value(NormalizedFlow::default())?
+        else {
+            unreachable!("NormalizedFlow serializes to an object");
+        };
+        flow.extend(self.flow.clone());
+        Ok(serde_json::from_value(serde_json::Value::Object(flow))?)
+    }
+}
+
+/// Analyzer-side data that some predicates consult.
+#[derive(Default, Clone, Copy)]
+pub struct EvalContext<'a> {
+    pub fingerprints: Option<&'a FingerprintLists>,
 }
 
 impl Rule {
     pub fn matches(&self, flow: &NormalizedFlow) -> bool {
-        match evaluate_expression(&self.expression, flow) {
+        self.matches_with(flow, &EvalContext::default())
+    }
+
+    pub fn matches_with(&self, flow: &NormalizedFlow, context: &EvalContext<'_>) -> bool {
+        match self.evaluate_with(flow, context) {
             Ok(v) => v,
             Err(err) => {
                 tracing::warn!(rule = %self.id, %err, "rule evaluation failed");
@@ -25,10 +69,25 @@ impl Rule {
             }
         }
     }
+
+    /// Like [`Rule::matches_with`] but surfaces evaluation errors.
+    pub fn evaluate_with(&self, flow: &NormalizedFlow, context: &EvalContext<'_>) -> Result<bool> {
+        evaluate_expression_with(&self.expression, flow, context)
+    }
 }
 
 /// Very small interpreter that supports equality and membership tests against flow fields.
 pub fn evaluate_expression(expr: &str, flow: &NormalizedFlow) -> Result<bool> {
+    evaluate_expression_with(expr, flow, &EvalContext::default())
+}
+
+/// Like [`evaluate_expression`], additionally resolving `ja3 in_list "name"` /
+/// `ja4 in_list "name"` against the fingerprint lists in `context`.
+pub fn evaluate_expression_with(
+    expr: &str,
+    flow: &NormalizedFlow,
+    context: &EvalContext<'_>,
+) -> Result<bool> {
     let tokens: Vec<&str> = expr.split_whitespace().collect();
     if tokens.len() < 3 {
         return Err(anyhow!("invalid expression"));
@@ -44,6 +103,38 @@ pub fn evaluate_expression(expr: &str, flow: &NormalizedFlow) -> Result<bool> {
         "dst.port" => Ok(apply_operator(&flow.dst_port.to_string(), op, value)),
         "src.ip" => Ok(apply_operator(&flow.src_ip, op, value)),
         "dst.ip" => Ok(apply_operator(&flow.dst_ip, op, value)),
+        "proto" => Ok(apply_operator(&flow.proto, op, value)),
+        "dns.qname" => Ok(apply_operator(
+            flow.dns_qname.as_deref().unwrap_or(""),
+            op,
+            value,
+        )),
+        "dns.rcode" => Ok(apply_operator(
+            flow.dns_rcode.as_deref().unwrap_or(""),
+            op,
+            value,
+        )),
+        "ja3" | "tls.ja3" | "ja4" | "tls.ja4" => {
+            let fingerprint = if field.ends_with("ja3") {
+                FingerprintType::Ja3
+            } else {
+                FingerprintType::Ja4
+            };
+            if op == "in_list" {
+                let lists = context
+                    .fingerprints
+                    .ok_or_else(|| anyhow!("no fingerprint lists loaded"))?;
+                if lists.get(value).is_none() {
+                    return Err(anyhow!("unknown fingerprint list: {value}"));
+                }
+                return Ok(lists.flow_in_list(flow, fingerprint, value));
+            }
+            let actual = match fingerprint {
+                FingerprintType::Ja3 => flow.ja3.as_deref(),
+                FingerprintType::Ja4 => flow.ja4.as_deref(),
+            };
+            Ok(apply_operator(actual.unwrap_or(""), op, value))
+        }
         other if other.starts_with("regex(") => {
             let re_body = other.trim_start_matches("regex(").trim_end_matches(')');
             let re = Regex::new(re_body)?;
@@ -75,7 +166,7 @@ pub fn load_rules_from_str(data: &str) -> Result<Vec<Rule>> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use chrono::{Duration, TimeZone, Utc};
+    use chrono::{Duration, Utc};
 
     #[test]
     fn basic_rule_match() {
@@ -91,6 +182,17 @@ mod tests {
             bytes: 0,
             packets: 0,
             process: Some("notesync.exe".into()),
+            process_hash: None,
+            process_signed: None,
+            state: None,
+            sni: None,
+            ja3: None,
+            ja4: None,
+            alpn: None,
+            dns_qname: None,
+            dns_rcode: None,
+            dns_answers: Vec::new(),
+            flow_id: None,
         };
         let rule = Rule {
             id: "smb-lateral".into(),
@@ -99,7 +201,36 @@ mod tests {
             rationale: None,
             suggested_action: None,
             expression: "dst.port == 445".into(),
+            tags: Vec::new(),
+            tests: Vec::new(),
         };
         assert!(rule.matches(&flow));
     }
+
+    #[test]
+    fn embedded_test_flows_default_missing_fields() {
+        let rules = load_rules_from_str(
+            r#"
+- id: smb
+  severity: High
+  expression: "dst.port == 445"
+  tests:
+    - flow: {dst_port: 445, process: notesync.exe}
+      match: true
+    - name: https
+      flow: {dst_port: 443}
+      match: false
+"#,
+        )
+        .unwrap();
+        let cases = &rules[0].tests;
+        assert_eq!(cases.len(), 2);
+        for case in cases {
+            assert_eq!(rules[0].matches(&case.flow().unwrap()), case.expect_match);
+        }
+        assert_eq!(
+            cases[0].flow().unwrap().process.as_deref(),
+            Some("notesync.exe")
+        );
+    }
 }
@@ -0,0 +1,375 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use normalizer::NormalizedFlow;
+use serde::{Deserialize, Serialize};
+
+use crate::Alert;
+
+/// One allowlist entry. Every populated field must match for the exception to
+/// apply; unset fields act as wildcards.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Exception {
+    pub id: String,
+    /// Rule id or `*` glob (e.g. `builtin.scan.*`).
+    pub rule_id: Option<String>,
+    pub process: Option<String>,
+    pub process_hash: Option<String>,
+    /// IP, hostname or `*` glob matched against the destination IP and SNI.
+    pub destination: Option<String>,
+    #[serde(default)]
+    pub ports: Vec<u16>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub created_by: Option<String>,
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub hits: u64,
+    pub last_hit: Option<DateTime<Utc>>,
+    /// Alert the exception was snoozed from.
+    #[serde(default)]
+    pub alert_id: Option<String>,
+}
+
+impl Exception {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.map(|ts| ts <= now).unwrap_or(false)
+    }
+
+    pub fn matches(&self, alert: &Alert, flow: &NormalizedFlow) -> bool {
+        if let Some(rule) = &self.rule_id {
+            if !glob_match(rule, &alert.rule_id) {
+                return false;
+            }
+        }
+        if let Some(process) = &self.process {
+            let actual = flow.process.as_deref().or(alert.process_ref.as_deref());
+            if actual != Some(process.as_str()) {
+                return false;
+            }
+        }
+        if let Some(hash) = &self.process_hash {
+            if flow.process_hash.as_deref() != Some(hash.as_str()) {
+                return false;
+            }
+        }
+        if let Some(destination) = &self.destination {
+            let sni_match = flow
+                .sni
+                .as_deref()
+                .map(|sni| glob_match(destination, sni))
+                .unwrap_or(false);
+            if !sni_match && !glob_match(destination, &flow.dst_ip) {
+                return false;
+            }
+        }
+        if !self.ports.is_empty() && !self.ports.contains(&flow.dst_port) {
+            return false;
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ExceptionAction {
+    Added,
+    Removed,
+    Expired,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExceptionAuditEntry {
+    pub ts: DateTime<Utc>,
+    pub action: ExceptionAction,
+    pub exception_id: String,
+    pub actor: Option<String>,
+    pub detail: String,
+}
+
+/// Allowlist consulted before alerts leave the analyzer, with an audit trail of
+/// every addition, removal and expiry.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExceptionList {
+    pub entries: Vec<Exception>,
+    #[serde(default)]
+    pub audit: Vec<ExceptionAuditEntry>,
+    #[serde(default)]
+    next_id: u64,
+}
+
+impl ExceptionList {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .with_context(|| format!("reading exception list {}", path.display()))?;
+        let list = serde_json::from_str(&data)
+            .with_context(|| format!("parsing exception list {}", path.display()))?;
+        Ok(list)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("writing exception list {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Adds an exception, assigning an id when the caller left it empty.
+    pub fn add(&mut self, mut exception: Exception, actor: Option<&str>) -> String {
+        if exception.id.is_empty() {
+            self.next_id += 1;
+            exception.id = format!("exc-{}", self.next_id);
+        }
+        let id = exception.id.clone();
+        self.audit.push(ExceptionAuditEntry {
+            ts: Utc::now(),
+            action: ExceptionAction::Added,
+            exception_id: id.clone(),
+            actor: actor.map(str::to_string),
+            detail: describe(&exception),
+        });
+        self.entries.push(exception);
+        id
+    }
+
+    /// Builds an exception that silences this alert's rule for the same
+    /// process and destination ("never warn about this again").
+    pub fn add_from_alert(
+        &mut self,
+        alert: &Alert,
+        expires_at: Option<DateTime<Utc>>,
+        actor: Option<&str>,
+        reason: Option<String>,
+    ) -> String {
+        let exception = from_alert(alert, expires_at, actor, reason);
+        self.add(exception, actor)
+    }
+
+    /// Temporarily allows what the alert flagged ("snooze for 24h"): an
+    /// exception like [`Self::add_from_alert`] that names the alert and its
+    /// creator and is purged once `duration` has passed. Policy guardrails
+    /// refuse quarantines that would block it meanwhile.
+    pub fn snooze(
+        &mut self,
+        alert: &Alert,
+        duration: Duration,
+        actor: &str,
+        reason: Option<String>,
+    ) -> String {
+        let mut exception = from_alert(alert, Some(Utc::now() + duration), Some(actor), reason);
+        exception.alert_id = Some(alert.id.clone());
+        self.add(exception, Some(actor))
+    }
+
+    /// Exceptions in force at `now` that lapse on their own, snoozes
+    /// included.
+    pub fn snoozes(&self, now: DateTime<Utc>) -> impl Iterator<Item = &Exception> {
+        self.entries
+            .iter()
+            .filter(move |exception| exception.expires_at.is_some() && !exception.is_expired(now))
+    }
+
+    pub fn remove(&mut self, id: &str, actor: Option<&str>) -> Result<Exception> {
+        let index = self
+            .entries
+            .iter()
+            .position(|e| e.id == id)
+            .ok_or_else(|| anyhow!("unknown exception {id}"))?;
+        let removed = self.entries.remove(index);
+        self.audit.push(ExceptionAuditEntry {
+            ts: Utc::now(),
+            action: ExceptionAction::Removed,
+            exception_id: removed.id.clone(),
+            actor: actor.map(str::to_string),
+            detail: describe(&removed),
+        });
+        Ok(removed)
+    }
+
+    /// Drops expired entries, recording each in the audit trail.
+    pub fn purge_expired(&mut self, now: DateTime<Utc>) {
+        let (expired, active): (Vec<_>, Vec<_>) = self
+            .entries
+            .drain(..)
+            .partition(|exception| exception.is_expired(now));
+        self.entries = active;
+        for exception in expired {
+            self.audit.push(ExceptionAuditEntry {
+                ts: now,
+                action: ExceptionAction::Expired,
+                exception_id: exception.id.clone(),
+                actor: None,
+                detail: describe(&exception),
+            });
+        }
+    }
+
+    /// Returns true when an active exception covers the alert, counting the hit.
+    pub fn suppresses(&mut self, alert: &Alert, flow: &NormalizedFlow, now: DateTime<Utc>) -> bool {
+        match self
+            .entries
+            .iter_mut()
+            .find(|exception| !exception.is_expired(now) && exception.matches(alert, flow))
+        {
+            Some(exception) => {
+                exception.hits += 1;
+                exception.last_hit = Some(now);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// An exception for the alert's rule, process and destination.
+fn from_alert(
+    alert: &Alert,
+    expires_at: Option<DateTime<Utc>>,
+    actor: Option<&str>,
+    reason: Option<String>,
+) -> Exception {
+    let destination = alert.evidence.get("dst_ip").cloned();
+    let port = alert
+        .evidence
+        .get("dst_port")
+        .and_then(|port| port.parse::<u16>().ok());
+    Exception {
+        id: String::new(),
+        rule_id: Some(alert.rule_id.clone()),
+        process: alert.process_ref.clone(),
+        process_hash: None,
+        destination,
+        ports: port.into_iter().filter(|p| *p != 0).collect(),
+        expires_at,
+        created_at: Utc::now(),
+        created_by: actor.map(str::to_string),
+        reason,
+        hits: 0,
+        last_hit: None,
+        alert_id: None,
+    }
+}
+
+fn describe(exception: &Exception) -> String {
+    format!(
+        "rule={} process={} hash={} destination={} ports={:?} expires={}{}",
+        exception.rule_id.as_deref().unwrap_or("*"),
+        exception.process.as_deref().unwrap_or("*"),
+        exception.process_hash.as_deref().unwrap_or("*"),
+        exception.destination.as_deref().unwrap_or("*"),
+        exception.ports,
+        exception
+            .expires_at
+            .map(|ts| ts.to_rfc3339())
+            .unwrap_or_else(|| "never".into()),
+        exception
+            .alert_id
+            .as_deref()
+            .map(|alert| format!(" alert={alert}"))
+            .unwrap_or_default()
+    )
+}
+
+/// Case-insensitive glob supporting `*` wildcards.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    let text = text.to_ascii_lowercase();
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == text;
+    }
+    let mut rest = text.as_str();
+    for (i, part) in parts.iter().enumerate() {
+        if part.is_empty() {
+            continue;
+        }
+        if i == 0 {
+            match rest.strip_prefix(part) {
+                Some(stripped) => rest = stripped,
+                None => return false,
+            }
+        } else if i == parts.len() - 1 {
+            return rest.ends_with(part);
+        } else {
+            match rest.find(part) {
+                Some(pos) => rest = &rest[pos + part.len()..],
+                None => return false,
+            }
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AlertStatus, Severity};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn exception_from_alert_suppresses_until_expiry() {
+        let now = Utc::now();
+        let alert = Alert {
+            id: "a1".into(),
+            ts: now,
+            severity: Severity::Low,
+            rule_id: "builtin.first_contact".into(),
+            summary: String::new(),
+            flow_refs: vec![42],
+            process_ref: Some("updater".into()),
+            rationale: String::new(),
+            suggested_action: None,
+            status: AlertStatus::New,
+            assignee: None,
+            notes: Vec::new(),
+            evidence: BTreeMap::from([
+                ("dst_ip".to_string(), "93.184.216.34".to_string()),
+                ("dst_port".to_string(), "443".to_string()),
+            ]),
+        };
+        let flow = NormalizedFlow {
+            dst_ip: "93.184.216.34".into(),
+            dst_port: 443,
+            process: Some("updater".into()),
+            ..NormalizedFlow::default()
+        };
+
+        let mut list = ExceptionList::default();
+        let id = list.add_from_alert(
+            &alert,
+            Some(now + Duration::hours(1)),
+            Some("analyst"),
+            None,
+        );
+        assert!(list.suppresses(&alert, &flow, now));
+        assert_eq!(list.entries[0].hits, 1);
+
+        list.purge_expired(now + Duration::hours(2));
+        assert!(!list.suppresses(&alert, &flow, now + Duration::hours(2)));
+        assert_eq!(list.audit.last().unwrap().exception_id, id);
+        assert_eq!(list.audit.last().unwrap().action, ExceptionAction::Expired);
+
+        // A snooze lapses by itself and remembers the alert and its creator.
+        let snooze = list.snooze(&alert, Duration::hours(24), "analyst", None);
+        let snoozes: Vec<_> = list.snoozes(now).collect();
+        assert_eq!(snoozes.len(), 1);
+        assert_eq!(snoozes[0].id, snooze);
+        assert_eq!(snoozes[0].alert_id.as_deref(), Some("a1"));
+        assert_eq!(snoozes[0].created_by.as_deref(), Some("analyst"));
+        assert!(list.suppresses(&alert, &flow, now));
+        assert_eq!(list.snoozes(now + Duration::hours(25)).count(), 0);
+        list.purge_expired(now + Duration::hours(25));
+        assert!(list.entries.is_empty());
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("*.example.com", "cdn.Example.com"));
+        assert!(glob_match("builtin.scan.*", "builtin.scan.vertical"));
+        assert!(!glob_match("10.0.*.1", "10.1.0.2"));
+    }
+}
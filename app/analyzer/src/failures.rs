@@ -0,0 +1,197 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use chrono::{DateTime, Duration, Utc};
+use normalizer::NormalizedFlow;
+use serde::{Deserialize, Serialize};
+
+use crate::{new_alert_id, Alert, AlertStatus, Detector, Severity};
+
+/// Connection states reported by collectors for attempts that never completed.
+const FAILURE_STATES: &[&str] = &[
+    "SYN_SENT", "SYN-SENT", "RST", "RESET", "REFUSED", "REJECTED", "TIMEOUT",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailureConfig {
+    pub window_seconds: i64,
+    /// Minimum failed attempts inside the window before the ratio is considered.
+    pub min_failures: usize,
+    /// Fraction of attempts from the source that must have failed (0.0–1.0).
+    pub min_ratio: f64,
+}
+
+impl Default for FailureConfig {
+    fn default() -> Self {
+        Self {
+            window_seconds: 300,
+            min_failures: 20,
+            min_ratio: 0.8,
+        }
+    }
+}
+
+pub fn is_failed_state(state: Option<&str>) -> bool {
+    state
+        .map(|s| {
+            FAILURE_STATES
+                .iter()
+                .any(|candidate| s.eq_ignore_ascii_case(candidate))
+        })
+        .unwrap_or(false)
+}
+
+#[derive(Default)]
+struct SourceWindow {
+    attempts: VecDeque<(DateTime<Utc>, bool)>,
+    failures: usize,
+    targets: HashMap<String, usize>,
+    last_alert: Option<DateTime<Utc>>,
+}
+
+/// Tracks failed-connection ratios per source to surface password spraying,
+/// dead C2 retries and similar bursts of refused or half-open attempts.
+pub struct FailureBurstDetector {
+    config: FailureConfig,
+    sources: HashMap<String, SourceWindow>,
+}
+
+impl Default for FailureBurstDetector {
+    fn default() -> Self {
+        Self::new(FailureConfig::default())
+    }
+}
+
+impl FailureBurstDetector {
+    pub fn new(config: FailureConfig) -> Self {
+        Self {
+            config,
+            sources: HashMap::new(),
+        }
+    }
+}
+
+impl Detector for FailureBurstDetector {
+    fn name(&self) -> &'static str {
+        "connection-failures"
+    }
+
+    fn observe(&mut self, flow: &NormalizedFlow) -> Vec<Alert> {
+        if flow.state.is_none() || flow.state.as_deref() == Some("LISTEN") {
+            return Vec::new();
+        }
+        let now = flow.window_start;
+        let window = Duration::seconds(self.config.window_seconds);
+        let failed = is_failed_state(flow.state.as_deref());
+        let entry = self.sources.entry(flow.src_ip.clone()).or_default();
+
+        entry.attempts.push_back((now, failed));
+        if failed {
+            entry.failures += 1;
+            *entry
+                .targets
+                .entry(format!("{}:{}", flow.dst_ip, flow.dst_port))
+                .or_default() += 1;
+        }
+        while let Some((ts, was_failed)) = entry.attempts.front().copied() {
+            if now - ts <= window {
+                break;
+            }
+            entry.attempts.pop_front();
+            if was_failed {
+                entry.failures -= 1;
+            }
+        }
+        if entry.failures == 0 {
+            entry.targets.clear();
+        }
+
+        let total = entry.attempts.len();
+        let ratio = entry.failures as f64 / total.max(1) as f64;
+        let rearmed = entry.last_alert.map(|ts| now - ts > window).unwrap_or(true);
+        if !failed
+            || !rearmed
+            || entry.failures < self.config.min_failures
+            || ratio < self.config.min_ratio
+        {
+            return Vec::new();
+        }
+        entry.last_alert = Some(now);
+
+        let mut targets: Vec<(String, usize)> = entry.targets.drain().collect();
+        targets.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let top = targets
+            .iter()
+            .take(5)
+            .map(|(target, count)| format!("{target} x{count}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let failures = entry.failures;
+
+        vec![Alert {
+            id: new_alert_id(),
+            ts: Utc::now(),
+            severity: Severity::Medium,
+            rule_id: "builtin.conn_failures".into(),
+            summary: format!(
+                "{} failed connection attempts from {}",
+                failures, flow.src_ip
+            ),
+            flow_refs: flow.flow_id.into_iter().collect(),
+            process_ref: flow.process.clone(),
+            rationale: format!(
+                "{failures}/{total} attempts failed ({:.0}%) within {}s; top targets: {top}",
+                ratio * 100.0,
+                self.config.window_seconds
+            ),
+            suggested_action: Some(
+                "Check the source for password spraying or a retrying implant".into(),
+            ),
+            status: AlertStatus::New,
+            assignee: None,
+            notes: Vec::new(),
+            evidence: BTreeMap::from([(
+                "targets".to_string(),
+                targets
+                    .iter()
+                    .map(|(target, _)| target.as_str())
+                    .collect::<Vec<_>>()
+                    .join(","),
+            )]),
+        }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn attempt(state: &str, offset: i64) -> NormalizedFlow {
+        let ts = Utc.timestamp_opt(1_700_000_000 + offset, 0).unwrap();
+        NormalizedFlow {
+            window_start: ts,
+            window_end: ts,
+            proto: "TCP".into(),
+            src_ip: "192.168.1.20".into(),
+            dst_ip: "192.168.1.5".into(),
+            dst_port: 22,
+            state: Some(state.into()),
+            ..NormalizedFlow::default()
+        }
+    }
+
+    #[test]
+    fn burst_of_refused_connections() {
+        let mut detector = FailureBurstDetector::default();
+        let mut alerts = Vec::new();
+        for i in 0..3 {
+            alerts.extend(detector.observe(&attempt("ESTABLISHED", i)));
+        }
+        for i in 0..30 {
+            alerts.extend(detector.observe(&attempt("SYN_SENT", 10 + i)));
+        }
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].severity, Severity::Medium);
+        assert!(alerts[0].rationale.contains("192.168.1.5:22"));
+    }
+}
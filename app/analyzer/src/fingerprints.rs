@@ -0,0 +1,243 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use normalizer::NormalizedFlow;
+use serde::{Deserialize, Serialize};
+
+use crate::{new_alert_id, Alert, AlertStatus, Severity};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ListKind {
+    Allow,
+    Block,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum FingerprintType {
+    Ja3,
+    Ja4,
+}
+
+impl FingerprintType {
+    pub fn label(self) -> &'static str {
+        match self {
+            FingerprintType::Ja3 => "ja3",
+            FingerprintType::Ja4 => "ja4",
+        }
+    }
+
+    fn value(self, flow: &NormalizedFlow) -> Option<&str> {
+        match self {
+            FingerprintType::Ja3 => flow.ja3.as_deref(),
+            FingerprintType::Ja4 => flow.ja4.as_deref(),
+        }
+        .filter(|value| !value.is_empty())
+    }
+}
+
+/// Named set of TLS client fingerprints as shipped by a threat-intel feed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FingerprintList {
+    pub name: String,
+    pub kind: ListKind,
+    #[serde(default)]
+    pub ja3: HashSet<String>,
+    #[serde(default)]
+    pub ja4: HashSet<String>,
+}
+
+impl FingerprintList {
+    pub fn new(name: &str, kind: ListKind) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            ja3: HashSet::new(),
+            ja4: HashSet::new(),
+        }
+    }
+
+    /// Parses a plain feed: one fingerprint per line, `#` comments, optional
+    /// trailing columns after a comma (as in abuse.ch CSV exports).
+    pub fn from_text(name: &str, kind: ListKind, fingerprint: FingerprintType, data: &str) -> Self {
+        let mut list = Self::new(name, kind);
+        let values = data
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or("").trim())
+            .filter(|line| !line.is_empty())
+            .filter_map(|line| line.split(',').next())
+            .map(|value| value.trim().to_ascii_lowercase());
+        match fingerprint {
+            FingerprintType::Ja3 => list.ja3.extend(values),
+            FingerprintType::Ja4 => list.ja4.extend(values),
+        }
+        list
+    }
+
+    pub fn contains(&self, fingerprint: FingerprintType, value: &str) -> bool {
+        let value = value.to_ascii_lowercase();
+        match fingerprint {
+            FingerprintType::Ja3 => self.ja3.contains(&value),
+            FingerprintType::Ja4 => self.ja4.contains(&value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FingerprintMatch {
+    pub list: String,
+    pub kind: ListKind,
+    pub fingerprint: FingerprintType,
+    pub value: String,
+}
+
+/// All fingerprint lists known to the analyzer.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FingerprintLists {
+    pub lists: Vec<FingerprintList>,
+}
+
+impl FingerprintLists {
+    /// Loads a JSON bundle of lists.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .with_context(|| format!("reading fingerprint lists {}", path.display()))?;
+        let lists = serde_json::from_str(&data)
+            .with_context(|| format!("parsing fingerprint lists {}", path.display()))?;
+        Ok(lists)
+    }
+
+    /// Adds a list, replacing any existing list with the same name.
+    pub fn insert(&mut self, list: FingerprintList) {
+        self.lists.retain(|existing| existing.name != list.name);
+        self.lists.push(list);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&FingerprintList> {
+        self.lists.iter().find(|list| list.name == name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lists.is_empty()
+    }
+
+    /// True when the flow's fingerprint of the given type is in the named list.
+    pub fn flow_in_list(
+        &self,
+        flow: &NormalizedFlow,
+        fingerprint: FingerprintType,
+        name: &str,
+    ) -> bool {
+        match (fingerprint.value(flow), self.get(name)) {
+            (Some(value), Some(list)) => list.contains(fingerprint, value),
+            _ => false,
+        }
+    }
+
+    pub fn matches(&self, flow: &NormalizedFlow) -> Vec<FingerprintMatch> {
+        let mut matches = Vec::new();
+        for fingerprint in [FingerprintType::Ja3, FingerprintType::Ja4] {
+            let Some(value) = fingerprint.value(flow) else {
+                continue;
+            };
+            for list in &self.lists {
+                if list.contains(fingerprint, value) {
+                    matches.push(FingerprintMatch {
+                        list: list.name.clone(),
+                        kind: list.kind,
+                        fingerprint,
+                        value: value.to_string(),
+                    });
+                }
+            }
+        }
+        matches
+    }
+
+    /// Raises an `intel.<ja3|ja4>` alert per block-list hit, unless an allow
+    /// list vouches for the same fingerprint.
+    pub fn alerts(&self, flow: &NormalizedFlow) -> Vec<Alert> {
+        let matches = self.matches(flow);
+        let allowed: HashSet<(FingerprintType, &str)> = matches
+            .iter()
+            .filter(|m| m.kind == ListKind::Allow)
+            .map(|m| (m.fingerprint, m.value.as_str()))
+            .collect();
+        matches
+            .iter()
+            .filter(|m| m.kind == ListKind::Block)
+            .filter(|m| !allowed.contains(&(m.fingerprint, m.value.as_str())))
+            .map(|m| Alert {
+                id: new_alert_id(),
+                ts: Utc::now(),
+                severity: Severity::High,
+                rule_id: format!("intel.{}", m.fingerprint.label()),
+                summary: format!(
+                    "TLS client fingerprint listed in {} ({})",
+                    m.list,
+                    flow.process.as_deref().unwrap_or("unknown process")
+                ),
+                flow_refs: flow.flow_id.into_iter().collect(),
+                process_ref: flow.process.clone(),
+                rationale: format!(
+                    "{} {} matched block list \"{}\"",
+                    m.fingerprint.label().to_uppercase(),
+                    m.value,
+                    m.list
+                ),
+                suggested_action: Some(
+                    "Identify the process using this TLS stack and check it for malware".into(),
+                ),
+                status: AlertStatus::New,
+                assignee: None,
+                notes: Vec::new(),
+                // Per-feed hit counts are read back from stored alerts.
+                evidence: BTreeMap::from([
+                    ("feed".to_string(), m.list.clone()),
+                    ("indicator".to_string(), m.value.clone()),
+                ]),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_list_hit_unless_allowed() {
+        let mut lists = FingerprintLists::default();
+        lists.insert(FingerprintList::from_text(
+            "sslbl",
+            ListKind::Block,
+            FingerprintType::Ja3,
+            "# abuse.ch\n51C64C77E60F3980EEA90869B68C58A8,Trickbot\n72a589da586844d7f0818ce684948eea\n",
+        ));
+        let mut flow = NormalizedFlow {
+            ja3: Some("51c64c77e60f3980eea90869b68c58a8".into()),
+            ..NormalizedFlow::default()
+        };
+        let alerts = lists.alerts(&flow);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].rule_id, "intel.ja3");
+        assert!(alerts[0].rationale.contains("\"sslbl\""));
+        assert!(lists.flow_in_list(&flow, FingerprintType::Ja3, "sslbl"));
+
+        lists.insert(FingerprintList::from_text(
+            "corp-tls",
+            ListKind::Allow,
+            FingerprintType::Ja3,
+            "51c64c77e60f3980eea90869b68c58a8",
+        ));
+        assert!(lists.alerts(&flow).is_empty());
+
+        flow.ja3 = Some("ffff".into());
+        assert!(lists.matches(&flow).is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use chrono::{DateTime, Duration, Utc};
+use collector::FlowDirection;
+use normalizer::NormalizedFlow;
+use serde::{Deserialize, Serialize};
+
+use crate::{new_alert_id, Alert, AlertStatus, Detector, Severity};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FirstContactConfig {
+    /// Time after a process is first seen during which its destinations are
+    /// learned silently.
+    pub grace_seconds: i64,
+    /// Upper bound on remembered destinations per process.
+    pub max_destinations: usize,
+}
+
+impl Default for FirstContactConfig {
+    fn default() -> Self {
+        Self {
+            grace_seconds: 600,
+            max_destinations: 4096,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessDestinations {
+    pub first_seen: DateTime<Utc>,
+    pub destinations: HashSet<String>,
+}
+
+/// Per-process destination sets; serializable so the profile can be persisted
+/// between runs.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DestinationProfile {
+    pub processes: HashMap<String, ProcessDestinations>,
+}
+
+/// Raises a low-severity event the first time a process talks to a host or
+/// domain it has never contacted before.
+pub struct FirstContactDetector {
+    config: FirstContactConfig,
+    profile: DestinationProfile,
+}
+
+impl Default for FirstContactDetector {
+    fn default() -> Self {
+        Self::new(FirstContactConfig::default(), DestinationProfile::default())
+    }
+}
+
+impl FirstContactDetector {
+    pub fn new(config: FirstContactConfig, profile: DestinationProfile) -> Self {
+        Self { config, profile }
+    }
+
+    pub fn profile(&self) -> &DestinationProfile {
+        &self.profile
+    }
+}
+
+/// Identity used for the per-process set: the name plus the binary hash when the
+/// collector provides one, so a replaced binary starts with a fresh profile.
+pub fn process_key(flow: &NormalizedFlow) -> Option<String> {
+    let name = flow.process.as_deref()?;
+    Some(match flow.process_hash.as_deref() {
+        Some(hash) => format!("{name}@{hash}"),
+        None => name.to_string(),
+    })
+}
+
+/// Destination label preferring the TLS server name over the raw address.
+pub fn destination(flow: &NormalizedFlow) -> String {
+    flow.sni
+        .as_deref()
+        .filter(|sni| !sni.is_empty())
+        .map(|sni| sni.to_ascii_lowercase())
+        .unwrap_or_else(|| flow.dst_ip.clone())
+}
+
+impl Detector for FirstContactDetector {
+    fn name(&self) -> &'static str {
+        "first-contact"
+    }
+
+    fn observe(&mut self, flow: &NormalizedFlow) -> Vec<Alert> {
+        if flow.direction == FlowDirection::Inbound || flow.dst_ip.is_empty() {
+            return Vec::new();
+        }
+        let Some(key) = process_key(flow) else {
+            return Vec::new();
+        };
+        let now = flow.window_start;
+        let dest = destination(flow);
+        let entry = self
+            .profile
+            .processes
+            .entry(key)
+            .or_insert_with(|| ProcessDestinations {
+                first_seen: now,
+                destinations: HashSet::new(),
+            });
+        if entry.destinations.contains(&dest)
+            || entry.destinations.len() >= self.config.max_destinations
+        {
+            return Vec::new();
+        }
+        entry.destinations.insert(dest.clone());
+        if now - entry.first_seen < Duration::seconds(self.config.grace_seconds) {
+            return Vec::new();
+        }
+
+        let process = flow.process.clone().unwrap_or_default();
+        let severity = if flow.process_signed == Some(false) {
+            Severity::Medium
+        } else {
+            Severity::Low
+        };
+        vec![Alert {
+            id: new_alert_id(),
+            ts: Utc::now(),
+            severity,
+            rule_id: "builtin.first_contact".into(),
+            summary: format!("New destination for process {process}: {dest}"),
+            flow_refs: flow.flow_id.into_iter().collect(),
+            process_ref: flow.process.clone(),
+            rationale: format!(
+                "First contact with {dest} after {} known destinations; hash={} signed={}",
+                entry.destinations.len() - 1,
+                flow.process_hash.as_deref().unwrap_or("unknown"),
+                flow.process_signed
+                    .map(|s| s.to_string())
+                    .unwrap_or_else(|| "unknown".into())
+            ),
+            suggested_action: Some("Confirm the process is expected to reach this host".into()),
+            status: AlertStatus::New,
+            assignee: None,
+            notes: Vec::new(),
+            evidence: BTreeMap::new(),
+        }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn outbound(dst: &str, offset: i64) -> NormalizedFlow {
+        let ts = Utc.timestamp_opt(1_700_000_000 + offset, 0).unwrap();
+        NormalizedFlow {
+            window_start: ts,
+            window_end: ts,
+            proto: "TCP".into(),
+            src_ip: "192.168.1.10".into(),
+            dst_ip: dst.into(),
+            dst_port: 443,
+            direction: FlowDirection::Outbound,
+            process: Some("updater".into()),
+            process_signed: Some(false),
+            ..NormalizedFlow::default()
+        }
+    }
+
+    #[test]
+    fn alerts_on_new_destination_after_grace() {
+        let mut detector = FirstContactDetector::default();
+        assert!(detector.observe(&outbound("203.0.113.1", 0)).is_empty());
+        assert!(detector.observe(&outbound("203.0.113.1", 900)).is_empty());
+        let alerts = detector.observe(&outbound("198.51.100.7", 900));
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].severity, Severity::Medium);
+        assert!(detector.observe(&outbound("198.51.100.7", 960)).is_empty());
+    }
+}
@@ -0,0 +1,258 @@
+//! Address and domain indicators from threat-intel feeds. A flow to or from
+//! a listed address, or naming a listed domain or one of its subdomains,
+//! raises an `intel.ip` or `intel.domain` alert naming the feed. TLS client
+//! fingerprints are matched by [`crate::fingerprints`].
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    net::IpAddr,
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use normalizer::NormalizedFlow;
+use serde::{Deserialize, Serialize};
+
+use crate::{new_alert_id, Alert, AlertStatus, Severity};
+
+/// What the indicators of a feed are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IntelKind {
+    Ip,
+    Domain,
+    Ja3,
+    Ja4,
+}
+
+impl IntelKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            IntelKind::Ip => "ip",
+            IntelKind::Domain => "domain",
+            IntelKind::Ja3 => "ja3",
+            IntelKind::Ja4 => "ja4",
+        }
+    }
+
+    /// The canonical form of `value`, or `None` when it is not an
+    /// indicator of this kind.
+    pub fn normalize(self, value: &str) -> Option<String> {
+        let value = value.trim().trim_matches('"').trim();
+        match self {
+            IntelKind::Ip => value.parse::<IpAddr>().ok().map(|ip| ip.to_string()),
+            IntelKind::Domain => {
+                let name = value.trim_end_matches('.').to_ascii_lowercase();
+                let valid = name.contains('.')
+                    && name.parse::<IpAddr>().is_err()
+                    && name.split('.').all(|label| {
+                        !label.is_empty()
+                            && label
+                                .chars()
+                                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                    });
+                valid.then_some(name)
+            }
+            IntelKind::Ja3 => (value.len() == 32 && value.chars().all(|c| c.is_ascii_hexdigit()))
+                .then(|| value.to_ascii_lowercase()),
+            // t13d1516h2_8daaf6152771_b186095e22b6
+            IntelKind::Ja4 => {
+                let value = value.to_ascii_lowercase();
+                let parts: Vec<&str> = value.split('_').collect();
+                let valid = parts.len() == 3
+                    && parts[0].len() == 10
+                    && parts[1..].iter().all(|part| {
+                        part.len() == 12 && part.chars().all(|c| c.is_ascii_hexdigit())
+                    });
+                valid.then_some(value)
+            }
+        }
+    }
+}
+
+impl fmt::Display for IntelKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for IntelKind {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "ip" => Ok(IntelKind::Ip),
+            "domain" => Ok(IntelKind::Domain),
+            "ja3" => Ok(IntelKind::Ja3),
+            "ja4" => Ok(IntelKind::Ja4),
+            other => Err(anyhow!(
+                "unknown indicator type: {other} (ip, domain, ja3, ja4)"
+            )),
+        }
+    }
+}
+
+/// Indicators read from a feed file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParsedFeed {
+    pub values: Vec<String>,
+    /// Lines holding nothing of the expected kind, such as CSV headers.
+    pub skipped: usize,
+}
+
+/// Reads a plain list or a CSV export: `#` comments are ignored and each
+/// line contributes its first column that holds an indicator of `kind`, so
+/// abuse.ch exports with a leading date column work as they are.
+pub fn parse_feed(kind: IntelKind, data: &str) -> ParsedFeed {
+    let mut feed = ParsedFeed::default();
+    for line in data.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split(',').find_map(|column| kind.normalize(column)) {
+            Some(value) => feed.values.push(value),
+            None => feed.skipped += 1,
+        }
+    }
+    feed.values.sort();
+    feed.values.dedup();
+    feed
+}
+
+/// Address and domain indicators by value, each with the feeds listing it.
+#[derive(Debug, Clone, Default)]
+pub struct IndicatorLists {
+    ips: HashMap<String, Vec<String>>,
+    domains: HashMap<String, Vec<String>>,
+}
+
+impl IndicatorLists {
+    /// Adds `value`, already normalized, as listed by `feed`. Fingerprint
+    /// kinds are ignored here.
+    pub fn insert(&mut self, feed: &str, kind: IntelKind, value: String) {
+        let index = match kind {
+            IntelKind::Ip => &mut self.ips,
+            IntelKind::Domain => &mut self.domains,
+            IntelKind::Ja3 | IntelKind::Ja4 => return,
+        };
+        index.entry(value).or_default().push(feed.to_string());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ips.is_empty() && self.domains.is_empty()
+    }
+
+    pub fn alerts(&self, flow: &NormalizedFlow) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        for (ip, remote_is_source) in [(&flow.dst_ip, false), (&flow.src_ip, true)] {
+            let Some(feeds) = self.ips.get(ip.as_str()) else {
+                continue;
+            };
+            let summary = if remote_is_source {
+                format!("Connection from {ip} listed in {}", feeds.join(", "))
+            } else {
+                format!("Connection to {ip} listed in {}", feeds.join(", "))
+            };
+            alerts.extend(
+                feeds
+                    .iter()
+                    .map(|feed| intel_alert(flow, IntelKind::Ip, feed, ip, &summary)),
+            );
+        }
+        let name = flow
+            .sni
+            .as_deref()
+            .or(flow.dns_qname.as_deref())
+            .map(|name| name.trim_end_matches('.').to_ascii_lowercase());
+        if let Some(name) = name {
+            // The name itself, then each parent domain.
+            let listed = std::iter::successors(Some(name.as_str()), |name| {
+                name.split_once('.').map(|(_, parent)| parent)
+            })
+            .find_map(|candidate| self.domains.get(candidate).map(|feeds| (candidate, feeds)));
+            if let Some((domain, feeds)) = listed {
+                let summary = format!("{name} is listed in {}", feeds.join(", "));
+                alerts.extend(
+                    feeds
+                        .iter()
+                        .map(|feed| intel_alert(flow, IntelKind::Domain, feed, domain, &summary)),
+                );
+            }
+        }
+        alerts
+    }
+}
+
+fn intel_alert(
+    flow: &NormalizedFlow,
+    kind: IntelKind,
+    feed: &str,
+    indicator: &str,
+    summary: &str,
+) -> Alert {
+    Alert {
+        id: new_alert_id(),
+        ts: Utc::now(),
+        severity: Severity::High,
+        rule_id: format!("intel.{kind}"),
+        summary: summary.to_string(),
+        flow_refs: flow.flow_id.into_iter().collect(),
+        process_ref: flow.process.clone(),
+        rationale: format!("{kind} {indicator} matched feed \"{feed}\""),
+        suggested_action: Some(
+            "Check what the process exchanged with the listed host and isolate it if needed".into(),
+        ),
+        status: AlertStatus::New,
+        assignee: None,
+        notes: Vec::new(),
+        evidence: BTreeMap::from([
+            ("feed".to_string(), feed.to_string()),
+            ("indicator".to_string(), indicator.to_string()),
+        ]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_feeds_and_matches_addresses_and_subdomains() {
+        let feed = parse_feed(
+            IntelKind::Ip,
+            "# Feodo Tracker\nfirst_seen_utc,dst_ip,dst_port\n\"2024-05-01 10:00:00\",\"203.0.113.7\",443\n198.51.100.1\n198.51.100.1\n",
+        );
+        assert_eq!(feed.values, ["198.51.100.1", "203.0.113.7"]);
+        assert_eq!(feed.skipped, 1);
+        let domains = parse_feed(IntelKind::Domain, "Evil.Example.\nnot a domain\n");
+        assert_eq!(domains.values, ["evil.example"]);
+
+        let mut lists = IndicatorLists::default();
+        for value in feed.values {
+            lists.insert("feodo", IntelKind::Ip, value);
+        }
+        lists.insert("urlhaus", IntelKind::Domain, "evil.example".into());
+        let flow = NormalizedFlow {
+            src_ip: "192.168.1.10".into(),
+            dst_ip: "203.0.113.7".into(),
+            sni: Some("cdn.evil.example".into()),
+            ..NormalizedFlow::default()
+        };
+        let alerts = lists.alerts(&flow);
+        let hits: Vec<(&str, &str)> = alerts
+            .iter()
+            .map(|alert| (alert.rule_id.as_str(), alert.evidence["feed"].as_str()))
+            .collect();
+        assert_eq!(hits, [("intel.ip", "feodo"), ("intel.domain", "urlhaus")]);
+        assert_eq!(alerts[1].evidence["indicator"], "evil.example");
+
+        let clean = NormalizedFlow {
+            sni: Some("example".into()),
+            ..NormalizedFlow::default()
+        };
+        assert!(lists.alerts(&clean).is_empty());
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+use chrono::{DateTime, Duration, Utc};
+use normalizer::NormalizedFlow;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    failures::is_failed_state, is_lan, new_alert_id, Alert, AlertStatus, Detector, Severity,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LateralConfig {
+    /// Maximum delay between arriving on a host and moving on from it.
+    pub window_seconds: i64,
+    /// Remote-administration services by label; hops only chain within the
+    /// same service.
+    pub services: BTreeMap<String, Vec<u16>>,
+    /// Hosts in a chain before it is reported (A -> B -> C is 3).
+    pub min_hosts: usize,
+    pub max_hops_per_host: usize,
+}
+
+impl Default for LateralConfig {
+    fn default() -> Self {
+        Self {
+            window_seconds: 1800,
+            services: BTreeMap::from([
+                ("smb".to_string(), vec![445, 139]),
+                ("rdp".to_string(), vec![3389]),
+                ("winrm".to_string(), vec![5985, 5986]),
+                ("ssh".to_string(), vec![22]),
+                ("rpc".to_string(), vec![135]),
+            ]),
+            min_hosts: 3,
+            max_hops_per_host: 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Hop {
+    service: String,
+    ts: DateTime<Utc>,
+    /// Hosts from the origin of the chain up to and including the target.
+    path: Vec<String>,
+    times: Vec<DateTime<Utc>>,
+    flow_ids: Vec<i64>,
+}
+
+/// Correlates LAN-internal SMB/RDP/WinRM/SSH/RPC connections into chains: a
+/// host that is reached and then reaches a further host over the same service
+/// within the window extends the chain, reported as one High alert.
+pub struct LateralChainDetector {
+    config: LateralConfig,
+    arrivals: HashMap<String, VecDeque<Hop>>,
+    reported: HashSet<String>,
+    last_prune: Option<DateTime<Utc>>,
+}
+
+impl Default for LateralChainDetector {
+    fn default() -> Self {
+        Self::new(LateralConfig::default())
+    }
+}
+
+impl LateralChainDetector {
+    pub fn new(config: LateralConfig) -> Self {
+        Self {
+            config,
+            arrivals: HashMap::new(),
+            reported: HashSet::new(),
+            last_prune: None,
+        }
+    }
+
+    fn service(&self, port: u16) -> Option<String> {
+        self.config
+            .services
+            .iter()
+            .find(|(_, ports)| ports.contains(&port))
+            .map(|(name, _)| name.clone())
+    }
+
+    fn prune(&mut self, now: DateTime<Utc>) {
+        let window = Duration::seconds(self.config.window_seconds);
+        self.arrivals.retain(|_, hops| {
+            hops.retain(|hop| now - hop.ts <= window);
+            !hops.is_empty()
+        });
+        if self.reported.len() > 10_000 {
+            self.reported.clear();
+        }
+    }
+}
+
+impl Detector for LateralChainDetector {
+    fn name(&self) -> &'static str {
+        "lateral-chain"
+    }
+
+    fn observe(&mut self, flow: &NormalizedFlow) -> Vec<Alert> {
+        if flow.src_ip == flow.dst_ip
+            || !is_lan(&flow.src_ip)
+            || !is_lan(&flow.dst_ip)
+            || is_failed_state(flow.state.as_deref())
+            || flow.state.as_deref() == Some("LISTEN")
+        {
+            return Vec::new();
+        }
+        let Some(service) = self.service(flow.dst_port) else {
+            return Vec::new();
+        };
+        let now = flow.window_start;
+        let window = Duration::seconds(self.config.window_seconds);
+        if self.last_prune.map(|ts| now - ts > window).unwrap_or(true) {
+            self.prune(now);
+            self.last_prune = Some(now);
+        }
+
+        // Longest live chain that ended on the source before this connection.
+        let prior = self
+            .arrivals
+            .get(&flow.src_ip)
+            .into_iter()
+            .flatten()
+            .filter(|hop| {
+                hop.service == service
+                    && hop.ts <= now
+                    && now - hop.ts <= window
+                    && !hop.path.contains(&flow.dst_ip)
+            })
+            .max_by_key(|hop| hop.path.len())
+            .cloned();
+        let (mut path, mut times, mut flow_ids) = match prior {
+            Some(hop) => (hop.path, hop.times, hop.flow_ids),
+            None => (vec![flow.src_ip.clone()], Vec::new(), Vec::new()),
+        };
+        path.push(flow.dst_ip.clone());
+        times.push(now);
+        flow_ids.extend(flow.flow_id);
+
+        let hops = self.arrivals.entry(flow.dst_ip.clone()).or_default();
+        match hops
+            .iter_mut()
+            .find(|hop| hop.service == service && hop.path == path)
+        {
+            Some(existing) => existing.ts = now,
+            None => {
+                if hops.len() >= self.config.max_hops_per_host {
+                    hops.pop_front();
+                }
+                hops.push_back(Hop {
+                    service: service.clone(),
+                    ts: now,
+                    path: path.clone(),
+                    times: times.clone(),
+                    flow_ids: flow_ids.clone(),
+                });
+            }
+        }
+
+        let chain = path.join(" -> ");
+        if path.len() < self.config.min_hosts || !self.reported.insert(format!("{service}|{chain}"))
+        {
+            return Vec::new();
+        }
+        let mut evidence = BTreeMap::new();
+        evidence.insert("service".into(), service.clone());
+        evidence.insert("chain".into(), chain.clone());
+        evidence.insert(
+            "hop_times".into(),
+            times
+                .iter()
+                .map(|ts| ts.to_rfc3339())
+                .collect::<Vec<_>>()
+                .join(","),
+        );
+        let span = times.last().copied().unwrap_or(now) - times.first().copied().unwrap_or(now);
+        vec![Alert {
+            id: new_alert_id(),
+            ts: Utc::now(),
+            severity: Severity::High,
+            rule_id: "builtin.lateral.chain".into(),
+            summary: format!("Lateral movement chain over {service}: {chain}"),
+            flow_refs: flow_ids,
+            process_ref: flow.process.clone(),
+            rationale: format!(
+                "{} hosts reached in sequence over {service} within {}s (each hop within {}s of the previous)",
+                path.len(),
+                span.num_seconds(),
+                self.config.window_seconds
+            ),
+            suggested_action: Some(
+                "Isolate the chain's origin host and review credentials used on each hop".into(),
+            ),
+            status: AlertStatus::New,
+            assignee: None,
+            notes: Vec::new(),
+            evidence,
+        }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn hop(src: &str, dst: &str, port: u16, offset: i64) -> NormalizedFlow {
+        let ts = Utc.timestamp_opt(1_700_000_000 + offset, 0).unwrap();
+        NormalizedFlow {
+            window_start: ts,
+            window_end: ts,
+            proto: "TCP".into(),
+            src_ip: src.into(),
+            dst_ip: dst.into(),
+            dst_port: port,
+            state: Some("ESTABLISHED".into()),
+            ..NormalizedFlow::default()
+        }
+    }
+
+    #[test]
+    fn chains_hops_on_same_service() {
+        let mut detector = LateralChainDetector::default();
+        assert!(detector
+            .observe(&hop("10.0.0.5", "10.0.0.6", 445, 0))
+            .is_empty());
+        // Different service out of B does not chain.
+        assert!(detector
+            .observe(&hop("10.0.0.6", "10.0.0.7", 3389, 60))
+            .is_empty());
+        let alerts = detector.observe(&hop("10.0.0.6", "10.0.0.8", 445, 120));
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].severity, Severity::High);
+        assert_eq!(
+            alerts[0].evidence["chain"],
+            "10.0.0.5 -> 10.0.0.6 -> 10.0.0.8"
+        );
+
+        let longer = detector.observe(&hop("10.0.0.8", "10.0.0.9", 139, 300));
+        assert_eq!(longer.len(), 1);
+        assert!(longer[0].summary.ends_with("10.0.0.8 -> 10.0.0.9"));
+
+        // Too late to extend the chain.
+        assert!(detector
+            .observe(&hop("10.0.0.9", "10.0.0.10", 445, 300 + 3600))
+            .is_empty());
+    }
+}
@@ -1,10 +1,36 @@
+use anyhow::{anyhow, Result};
 use chrono::{DateTime, Duration, Utc};
-use collector::{FlowDirection, FlowEvent};
+use collector::FlowEvent;
 use normalizer::NormalizedFlow;
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+};
 
+pub mod anomaly;
+pub mod arp;
+pub mod baseline;
 pub mod dsl;
+pub mod exceptions;
+pub mod failures;
+pub mod fingerprints;
+pub mod first_contact;
+pub mod intel;
+pub mod lateral;
+pub mod lint;
+pub mod listener;
+pub mod mitre;
+pub mod overrides;
+pub mod pipeline;
+pub mod profiling;
+pub mod ratelimit;
+pub mod retro;
+pub mod risk;
+pub mod scan;
+pub mod scorer;
+pub mod sessions;
+pub mod tls;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Alert {
@@ -13,24 +39,196 @@ pub struct Alert {
     pub severity: Severity,
     pub rule_id: String,
     pub summary: String,
-    pub flow_refs: Vec<String>,
+    /// Storage row ids of the flows behind the alert (see
+    /// [`NormalizedFlow::flow_id`]); empty when the flows were never stored.
+    pub flow_refs: Vec<i64>,
     pub process_ref: Option<String>,
     pub rationale: String,
     pub suggested_action: Option<String>,
+    #[serde(default)]
+    pub status: AlertStatus,
+    #[serde(default)]
+    pub assignee: Option<String>,
+    #[serde(default)]
+    pub notes: Vec<AlertNote>,
+    /// Structured facts behind the alert (e.g. `sni`, `observed_ip`) for
+    /// display and export alongside the free-text rationale.
+    #[serde(default)]
+    pub evidence: BTreeMap<String, String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+/// Time-ordered UUIDv7, so alert ids are unique across restarts and
+/// rule workers and still sort by creation time.
+pub fn new_alert_id() -> String {
+    uuid::Uuid::now_v7().to_string()
+}
+
+/// Triage state of an alert.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum AlertStatus {
+    #[default]
+    New,
+    Acknowledged,
+    Resolved,
+    FalsePositive,
+}
+
+impl AlertStatus {
+    /// Closed alerts can only be reopened; open ones may move anywhere else.
+    pub fn can_transition_to(self, next: AlertStatus) -> bool {
+        use AlertStatus::*;
+        match (self, next) {
+            (a, b) if a == b => false,
+            (New | Acknowledged, _) => true,
+            (Resolved | FalsePositive, New) => true,
+            _ => false,
+        }
+    }
+
+    /// Validates the move to `next` and returns the note text recording it.
+    pub fn transition_note(self, next: AlertStatus, note: Option<&str>) -> Result<String> {
+        if !self.can_transition_to(next) {
+            return Err(anyhow!(
+                "cannot move from {} to {}",
+                self.as_str(),
+                next.as_str()
+            ));
+        }
+        Ok(match note {
+            Some(note) => format!("{} -> {}: {note}", self.as_str(), next.as_str()),
+            None => format!("{} -> {}", self.as_str(), next.as_str()),
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AlertStatus::New => "New",
+            AlertStatus::Acknowledged => "Acknowledged",
+            AlertStatus::Resolved => "Resolved",
+            AlertStatus::FalsePositive => "FalsePositive",
+        }
+    }
+}
+
+impl std::str::FromStr for AlertStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().replace(['_', '-'], "").as_str() {
+            "new" => Ok(AlertStatus::New),
+            "acknowledged" | "ack" => Ok(AlertStatus::Acknowledged),
+            "resolved" => Ok(AlertStatus::Resolved),
+            "falsepositive" | "fp" => Ok(AlertStatus::FalsePositive),
+            other => Err(anyhow!("unknown alert status: {other}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertNote {
+    pub ts: DateTime<Utc>,
+    pub author: Option<String>,
+    pub text: String,
+}
+
+impl Alert {
+    /// Moves the alert to `next`, recording the change (and optional note) in
+    /// the alert's notes.
+    pub fn transition(
+        &mut self,
+        next: AlertStatus,
+        author: Option<&str>,
+        note: Option<&str>,
+    ) -> Result<()> {
+        let text = self
+            .status
+            .transition_note(next, note)
+            .map_err(|err| anyhow!("alert {}: {err}", self.id))?;
+        self.add_note(author, text);
+        self.status = next;
+        Ok(())
+    }
+
+    pub fn assign(&mut self, assignee: Option<String>, author: Option<&str>) {
+        let text = match &assignee {
+            Some(name) => format!("assigned to {name}"),
+            None => "unassigned".into(),
+        };
+        self.add_note(author, text);
+        self.assignee = assignee;
+    }
+
+    pub fn add_note(&mut self, author: Option<&str>, text: String) {
+        self.notes.push(AlertNote {
+            ts: Utc::now(),
+            author: author.map(str::to_string),
+            text,
+        });
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Severity {
     Low,
     Medium,
     High,
 }
 
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Low => "Low",
+            Severity::Medium => "Medium",
+            Severity::High => "High",
+        }
+    }
+}
+
+impl std::str::FromStr for Severity {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "low" => Ok(Severity::Low),
+            "medium" | "med" => Ok(Severity::Medium),
+            "high" => Ok(Severity::High),
+            other => Err(anyhow!("unknown severity: {other}")),
+        }
+    }
+}
+
+/// Stateful detection stage fed with every ingested flow after the DSL rules.
+pub trait Detector: Send {
+    fn name(&self) -> &'static str;
+    fn observe(&mut self, flow: &NormalizedFlow) -> Vec<Alert>;
+}
+
+/// Hook run on every alert just before it leaves the analyzer, e.g. to attach
+/// asset names, owner tags or ticket links via `alert.evidence`.
+pub trait AlertEnricher: Send {
+    fn name(&self) -> &str;
+    fn enrich(&self, alert: &mut Alert, flow: &NormalizedFlow);
+}
+
 pub struct Analyzer {
     _baseline_window: Duration,
     history: VecDeque<NormalizedFlow>,
     max_history: usize,
-    rules: Vec<dsl::Rule>,
+    rules: Arc<Vec<dsl::Rule>>,
+    detectors: Vec<Box<dyn Detector>>,
+    baseline: baseline::BaselineEngine,
+    scorers: Vec<Box<dyn scorer::AnomalyScorer>>,
+    last_anomaly_score: Option<f32>,
+    risk: risk::RiskEngine,
+    exceptions: exceptions::ExceptionList,
+    severity_overrides: overrides::SeverityOverrides,
+    rate_limiter: ratelimit::RateLimiter,
+    fingerprints: Arc<fingerprints::FingerprintLists>,
+    indicators: Arc<intel::IndicatorLists>,
+    gateway_guard: arp::GatewayGuard,
+    enrichers: Vec<Box<dyn AlertEnricher>>,
+    rule_profiler: profiling::RuleProfiler,
+    retro: retro::RetroScheduler,
 }
 
 impl Analyzer {
@@ -40,59 +238,315 @@ impl Analyzer {
             _baseline_window: baseline_window,
             history: VecDeque::with_capacity(max_history),
             max_history,
-            rules,
+            rules: Arc::new(rules),
+            detectors: vec![
+                Box::new(scan::ScanDetector::default()),
+                Box::new(failures::FailureBurstDetector::default()),
+                Box::new(first_contact::FirstContactDetector::default()),
+                Box::new(anomaly::AnomalyDetector::default()),
+                Box::new(sessions::SessionTracker::default()),
+                Box::new(tls::TlsAnomalyDetector::default()),
+                Box::new(lateral::LateralChainDetector::default()),
+            ],
+            baseline: baseline::BaselineEngine::default(),
+            scorers: Vec::new(),
+            last_anomaly_score: None,
+            risk: risk::RiskEngine::default(),
+            exceptions: exceptions::ExceptionList::default(),
+            severity_overrides: overrides::SeverityOverrides::default(),
+            rate_limiter: ratelimit::RateLimiter::default(),
+            fingerprints: Arc::default(),
+            indicators: Arc::default(),
+            gateway_guard: arp::GatewayGuard::default(),
+            enrichers: Vec::new(),
+            rule_profiler: profiling::RuleProfiler::default(),
+            retro: retro::RetroScheduler::default(),
         }
     }
 
+    pub fn exceptions(&self) -> &exceptions::ExceptionList {
+        &self.exceptions
+    }
+
+    pub fn exceptions_mut(&mut self) -> &mut exceptions::ExceptionList {
+        &mut self.exceptions
+    }
+
+    /// Replaces the allowlist, e.g. with one loaded from disk.
+    pub fn set_exceptions(&mut self, list: exceptions::ExceptionList) {
+        self.exceptions = list;
+    }
+
+    pub fn set_severity_overrides(&mut self, overrides: overrides::SeverityOverrides) {
+        self.severity_overrides = overrides;
+    }
+
+    /// Installs the JA3/JA4 lists from the threat-intel feeds; block-list hits
+    /// raise `intel.*` alerts and the lists become available to `in_list`.
+    pub fn set_fingerprint_lists(&mut self, lists: fingerprints::FingerprintLists) {
+        self.fingerprints = Arc::new(lists);
+    }
+
+    pub fn fingerprint_lists(&self) -> &fingerprints::FingerprintLists {
+        &self.fingerprints
+    }
+
+    /// Installs the address and domain indicators of the threat-intel
+    /// feeds; hits raise `intel.ip` and `intel.domain` alerts.
+    pub fn set_indicator_lists(&mut self, lists: intel::IndicatorLists) {
+        self.indicators = Arc::new(lists);
+    }
+
+    /// Gateway/DNS MAC watch; configure the addresses to protect here.
+    pub fn gateway_guard_mut(&mut self) -> &mut arp::GatewayGuard {
+        &mut self.gateway_guard
+    }
+
+    /// Feeds a raw collector event carrying ARP/ND metadata. Layer 2 events
+    /// have no transport flow, so they bypass `ingest` but share its alert
+    /// post-processing.
+    pub fn observe_layer2(&mut self, event: &FlowEvent) -> Vec<Alert> {
+        let alerts = self.gateway_guard.check(event);
+        if alerts.is_empty() {
+            return alerts;
+        }
+        let layer2 = event.layer2.as_ref();
+        let flow = NormalizedFlow {
+            window_start: event.ts_first,
+            window_end: event.ts_last,
+            src_ip: layer2
+                .and_then(|l2| l2.ip_src.clone())
+                .unwrap_or_else(|| event.src_ip.clone()),
+            dst_ip: layer2
+                .and_then(|l2| l2.ip_dst.clone())
+                .unwrap_or_else(|| event.dst_ip.clone()),
+            direction: event.direction.clone(),
+            ..NormalizedFlow::default()
+        };
+        self.finalize_alerts(&flow, alerts)
+    }
+
+    pub fn set_rate_limit(&mut self, config: ratelimit::RateLimitConfig) {
+        self.rate_limiter = ratelimit::RateLimiter::new(config);
+    }
+
+    /// Emits roll-ups for every throttled rule right away, e.g. on shutdown.
+    pub fn flush_rate_limit(&mut self) -> Vec<Alert> {
+        self.rate_limiter.drain(Utc::now())
+    }
+
+    pub fn set_risk_weights(&mut self, weights: risk::RiskWeights) {
+        self.risk = risk::RiskEngine::new(weights);
+    }
+
+    /// Populates `event.risk` from the alerts the last `ingest` produced for it,
+    /// the highest scorer output and any threat-intel list hits.
+    pub fn assess_risk(&self, event: &mut FlowEvent, alerts: &[Alert], intel_hits: &[String]) {
+        self.risk.apply(
+            event,
+            &risk::RiskInputs {
+                alerts,
+                intel_hits,
+                anomaly_score: self.last_anomaly_score,
+            },
+        );
+    }
+
+    /// Plugs in an anomaly model evaluated against every ingested flow.
+    pub fn add_scorer(&mut self, scorer: Box<dyn scorer::AnomalyScorer>) {
+        self.scorers.push(scorer);
+    }
+
+    /// Begins (or restarts) baseline learning; flows are recorded, not alerted on.
+    pub fn start_learning(&mut self) {
+        self.baseline.start();
+    }
+
+    /// Freezes the learned profile and switches to deviation alerts.
+    pub fn stop_learning(&mut self) {
+        self.baseline.freeze();
+    }
+
+    pub fn reset_baseline(&mut self) {
+        self.baseline.reset();
+    }
+
+    /// Learn, freeze or reset, as [`baseline::BaselineEngine::apply`] does.
+    pub fn apply_baseline(&mut self, command: baseline::BaselineCommand) -> Result<()> {
+        self.baseline.apply(command)
+    }
+
+    pub fn baseline_status(&self) -> baseline::BaselineStatus {
+        self.baseline.status()
+    }
+
+    pub fn baseline_profile(&self) -> &baseline::BaselineProfile {
+        self.baseline.profile()
+    }
+
+    /// Restores a previously persisted profile, including its learn/enforce mode.
+    pub fn load_baseline(&mut self, profile: baseline::BaselineProfile) {
+        self.baseline = baseline::BaselineEngine::from_profile(profile);
+    }
+
+    /// Registers an enricher; enrichers run in registration order on alerts
+    /// that survived exceptions and rate limiting.
+    pub fn add_enricher(&mut self, enricher: Box<dyn AlertEnricher>) {
+        self.enrichers.push(enricher);
+    }
+
+    /// Per-rule evaluation time, match and error counts since start (or the
+    /// last reset), most expensive rule first.
+    pub fn rule_stats(&self) -> Vec<profiling::RuleStats> {
+        self.rule_profiler.snapshot()
+    }
+
+    pub fn reset_rule_stats(&mut self) {
+        self.rule_profiler.reset();
+    }
+
+    pub fn set_retro_rules(&mut self, rules: Vec<retro::RetroRule>) {
+        self.retro.set_rules(rules);
+    }
+
+    /// Runs the scheduled retrospective rules that are due against stored
+    /// history; call periodically (e.g. once a minute). Results go through
+    /// the same overrides, exceptions and rate limit as streaming alerts.
+    pub fn run_retro(
+        &mut self,
+        history: &dyn retro::FlowHistory,
+        now: DateTime<Utc>,
+    ) -> Vec<Alert> {
+        let alerts = self.retro.run_due(history, now);
+        if alerts.is_empty() {
+            return alerts;
+        }
+        self.finalize_alerts(&NormalizedFlow::default(), alerts)
+    }
+
+    /// ATT&CK coverage of the loaded rules (technique ids from rule tags)
+    /// plus built-in detectors, with counts of `alerts` raised in the period.
+    pub fn mitre_coverage(
+        &self,
+        alerts: &[Alert],
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> mitre::CoverageMatrix {
+        mitre::CoverageMatrix::build(&self.rules, alerts, from, to)
+    }
+
+    /// Registers an additional detector; built-in detectors are installed by `new`.
+    pub fn add_detector(&mut self, detector: Box<dyn Detector>) {
+        self.detectors.push(detector);
+    }
+
     pub fn ingest(&mut self, flow: NormalizedFlow) -> Vec<Alert> {
+        let matched = self.rule_stage().evaluate(flow);
+        self.ingest_matched(matched)
+    }
+
+    /// The stateful rest of [`Analyzer::ingest`], for flows whose rules ran
+    /// on [`pipeline::Workers`]; feed them in arrival order.
+    pub fn ingest_matched(&mut self, matched: pipeline::MatchedFlow) -> Vec<Alert> {
+        let pipeline::MatchedFlow {
+            flow,
+            mut alerts,
+            timings,
+        } = matched;
+        for timing in timings {
+            self.rule_profiler
+                .record(&timing.rule_id, timing.elapsed, timing.outcome);
+        }
         if self.history.len() >= self.max_history {
             self.history.pop_front();
         }
         self.history.push_back(flow.clone());
-        self.evaluate_rules(&flow)
+        for detector in &mut self.detectors {
+            alerts.extend(detector.observe(&flow));
+        }
+        alerts.extend(self.baseline.observe(&flow));
+        let (scored, max_score) = self.evaluate_scorers(&flow);
+        self.last_anomaly_score = max_score;
+        alerts.extend(scored);
+        self.finalize_alerts(&flow, alerts)
     }
 
-    fn evaluate_rules(&self, flow: &NormalizedFlow) -> Vec<Alert> {
-        let mut alerts = Vec::new();
-        for rule in &self.rules {
-            if rule.matches(flow) {
-                alerts.push(Alert {
-                    id: format!("alert-{}-{}", rule.id, flow.dst_port),
-                    ts: Utc::now(),
-                    severity: rule.severity.clone(),
-                    rule_id: rule.id.clone(),
-                    summary: rule.summary.clone().unwrap_or_else(|| "Rule match".into()),
-                    flow_refs: vec![format!(
-                        "{}:{}->{}:{}",
-                        flow.src_ip, flow.src_port, flow.dst_ip, flow.dst_port
-                    )],
-                    process_ref: flow.process.clone(),
-                    rationale: rule
-                        .rationale
-                        .clone()
-                        .unwrap_or_else(|| "Matched DSL condition".into()),
-                    suggested_action: rule.suggested_action.clone(),
-                });
+    /// Last stage before alerts leave the analyzer.
+    fn finalize_alerts(&mut self, flow: &NormalizedFlow, mut alerts: Vec<Alert>) -> Vec<Alert> {
+        if !self.severity_overrides.is_empty() {
+            for alert in &mut alerts {
+                let tags = self
+                    .rules
+                    .iter()
+                    .find(|rule| rule.id == alert.rule_id)
+                    .map(|rule| rule.tags.as_slice())
+                    .unwrap_or(&[]);
+                self.severity_overrides.apply(alert, tags);
+            }
+        }
+        // Flow refs are now row ids; keep the endpoints readable on the alert.
+        if !flow.dst_ip.is_empty() {
+            for alert in &mut alerts {
+                for (key, value) in [
+                    ("src_ip", flow.src_ip.clone()),
+                    ("dst_ip", flow.dst_ip.clone()),
+                    ("dst_port", flow.dst_port.to_string()),
+                ] {
+                    alert.evidence.entry(key.to_string()).or_insert(value);
+                }
+            }
+        }
+        let now = Utc::now();
+        self.exceptions.purge_expired(now);
+        alerts.retain(|alert| !self.exceptions.suppresses(alert, flow, now));
+        alerts.retain(|alert| self.rate_limiter.admit(alert, now));
+        alerts.extend(self.rate_limiter.rollups(now));
+        for enricher in &self.enrichers {
+            for alert in &mut alerts {
+                enricher.enrich(alert, flow);
             }
         }
         alerts
     }
+
+    fn evaluate_scorers(&self, flow: &NormalizedFlow) -> (Vec<Alert>, Option<f32>) {
+        let context = scorer::ScoringContext {
+            recent: &self.history,
+            baseline_mode: self.baseline.mode(),
+        };
+        let mut alerts = Vec::new();
+        let mut max_score: Option<f32> = None;
+        for scorer in &self.scorers {
+            let score = scorer.score(flow, &context);
+            max_score = Some(max_score.map_or(score, |max| max.max(score)));
+            if score < scorer.threshold() {
+                continue;
+            }
+            alerts.push(Alert {
+                id: new_alert_id(),
+                ts: Utc::now(),
+                severity: Severity::Medium,
+                rule_id: format!("ml.{}", scorer.name()),
+                summary: format!("Model {} flagged flow as anomalous", scorer.name()),
+                flow_refs: flow.flow_id.into_iter().collect(),
+                process_ref: flow.process.clone(),
+                rationale: format!("score {:.3} ≥ threshold {:.3}", score, scorer.threshold()),
+                suggested_action: None,
+                status: AlertStatus::New,
+                assignee: None,
+                notes: Vec::new(),
+                evidence: BTreeMap::new(),
+            });
+        }
+        (alerts, max_score)
+    }
 }
 
-pub fn detect_listener(flow: &FlowEvent) -> Option<Alert> {
-    if flow.direction == FlowDirection::Inbound && flow.state.as_deref() == Some("LISTEN") {
-        Some(Alert {
-            id: format!("listener-{}-{}", flow.src_ip, flow.src_port),
-            ts: Utc::now(),
-            severity: Severity::Medium,
-            rule_id: "builtin.listener".into(),
-            summary: format!("New listener on {}:{}", flow.src_ip, flow.src_port),
-            flow_refs: vec![format!("{}:{}", flow.src_ip, flow.src_port)],
-            process_ref: flow.process.as_ref().and_then(|p| p.name.clone()),
-            rationale: "Listener state observed from collector".into(),
-            suggested_action: Some("Validate service legitimacy or quarantine process".into()),
-        })
-    } else {
-        None
+/// RFC1918, link-local and fe80::/10 addresses, matching the DSL `lan()` helper.
+pub fn is_lan(ip: &str) -> bool {
+    match ip.parse::<std::net::IpAddr>() {
+        Ok(std::net::IpAddr::V4(v4)) => v4.is_private() || v4.is_link_local(),
+        Ok(std::net::IpAddr::V6(v6)) => (v6.segments()[0] & 0xffc0) == 0xfe80,
+        Err(_) => false,
     }
 }
@@ -0,0 +1,408 @@
+//! Static checks for rule files (`nets-cli rules lint`): YAML errors,
+//! duplicate ids, unknown keys, and expressions the evaluator cannot run or
+//! whose result does not depend on the flow. Expression checks mirror
+//! [`dsl::evaluate_expression_with`](crate::dsl::evaluate_expression_with),
+//! which reads `field operator value` and nothing more.
+//!
+//! A file may mix streaming rules (with `expression`) and retrospective
+//! rules (with `filter` or `threshold`); each item is checked against its
+//! own schema.
+
+use std::{collections::HashMap, fmt, net::IpAddr};
+
+use regex::Regex;
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+
+use crate::{dsl::Rule, retro::RetroRule};
+
+const RULE_KEYS: &[&str] = &[
+    "id",
+    "severity",
+    "summary",
+    "rationale",
+    "suggested_action",
+    "expression",
+    "tags",
+    "tests",
+];
+const RETRO_KEYS: &[&str] = &[
+    "id",
+    "severity",
+    "summary",
+    "suggested_action",
+    "filter",
+    "group_by",
+    "distinct",
+    "threshold",
+    "every_seconds",
+    "lookback_seconds",
+    "tags",
+];
+const FIELDS: &[&str] = &[
+    "proc.name",
+    "dst.port",
+    "src.ip",
+    "dst.ip",
+    "proto",
+    "dns.qname",
+    "dns.rcode",
+    "ja3",
+    "tls.ja3",
+    "ja4",
+    "tls.ja4",
+];
+const RCODES: &[&str] = &[
+    "NOERROR", "FORMERR", "SERVFAIL", "NXDOMAIN", "NOTIMP", "REFUSED", "YXDOMAIN", "YXRRSET",
+    "NXRRSET", "NOTAUTH", "NOTZONE",
+];
+
+/// One problem, at a 1-based position in its file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LintIssue {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub rule_id: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for LintIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}: ", self.file, self.line, self.column)?;
+        if let Some(id) = &self.rule_id {
+            write!(f, "[{id}] ")?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+/// Lints files one after another, remembering ids so duplicates across
+/// files are caught too.
+#[derive(Default)]
+pub struct RuleLinter {
+    ids: HashMap<String, (String, usize)>,
+}
+
+impl RuleLinter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lint(&mut self, file: &str, source: &str) -> Vec<LintIssue> {
+        let mut issues = Vec::new();
+        let mut issue = |line: usize, column: usize, rule_id: Option<&str>, message: String| {
+            issues.push(LintIssue {
+                file: file.to_string(),
+                line,
+                column,
+                rule_id: rule_id.map(String::from),
+                message,
+            })
+        };
+        let items = match serde_yaml::from_str::<Value>(source) {
+            Ok(Value::Sequence(items)) => items,
+            Ok(Value::Null) => Vec::new(),
+            Ok(_) => {
+                issue(1, 1, None, "expected a list of rules".into());
+                return issues;
+            }
+            Err(err) => {
+                let (line, column) = err
+                    .location()
+                    .map_or((1, 1), |location| (location.line(), location.column()));
+                issue(line, column, None, format!("invalid YAML: {err}"));
+                return issues;
+            }
+        };
+        let layout = Layout::new(source);
+        for (index, item) in items.into_iter().enumerate() {
+            let start = layout.item_line(index);
+            let Value::Mapping(map) = item else {
+                issue(start, 1, None, "expected a rule mapping".into());
+                continue;
+            };
+            let id = map.get("id").and_then(Value::as_str).map(String::from);
+            let id = id.as_deref();
+            let key_position = |key: &str| layout.key_position(index, key).unwrap_or((start, 1));
+
+            if let Some(id) = id {
+                match self.ids.get(id) {
+                    Some((first_file, first_line)) => issue(
+                        start,
+                        1,
+                        Some(id),
+                        format!("duplicate id, first defined at {first_file}:{first_line}"),
+                    ),
+                    None => {
+                        self.ids.insert(id.to_string(), (file.to_string(), start));
+                    }
+                }
+            }
+
+            let retro = !map.contains_key("expression")
+                && (map.contains_key("filter") || map.contains_key("threshold"));
+            let known = if retro { RETRO_KEYS } else { RULE_KEYS };
+            for key in map.keys() {
+                let name = key.as_str().unwrap_or_default();
+                if !known.contains(&name) {
+                    let (line, column) = key_position(name);
+                    issue(line, column, id, format!("unknown field `{name}`"));
+                }
+            }
+            // Unknown keys are reported above; the rest is the schema's call.
+            let schema = strip_unknown(&map, known);
+            let invalid = if retro {
+                serde_yaml::from_value::<RetroRule>(schema).err()
+            } else {
+                serde_yaml::from_value::<Rule>(schema).err()
+            };
+            if let Some(err) = invalid {
+                issue(start, 1, id, err.to_string());
+            }
+
+            let field = if retro { "filter" } else { "expression" };
+            if let Some(expression) = map.get(field).and_then(Value::as_str) {
+                let (line, column) = key_position(field);
+                for message in check_expression(expression) {
+                    issue(line, column, id, format!("`{expression}`: {message}"));
+                }
+            }
+        }
+        issues
+    }
+}
+
+fn strip_unknown(map: &Mapping, known: &[&str]) -> Value {
+    Value::Mapping(
+        map.iter()
+            .filter(|(key, _)| key.as_str().is_some_and(|key| known.contains(&key)))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+    )
+}
+
+/// What is wrong with `expression`, as the evaluator would run it.
+pub fn check_expression(expression: &str) -> Vec<String> {
+    let tokens: Vec<&str> = expression.split_whitespace().collect();
+    if tokens.len() < 3 {
+        return vec!["expected `field operator value`".into()];
+    }
+    let (field, op, raw) = (tokens[0], tokens[1], tokens[2]);
+    let mut problems = Vec::new();
+    if tokens.len() > 3 {
+        problems.push(format!(
+            "`{}` is ignored; only `{field} {op} {raw}` is evaluated",
+            tokens[3..].join(" ")
+        ));
+    }
+
+    if let Some(body) = field.strip_prefix("regex(") {
+        match Regex::new(body.trim_end_matches(')')) {
+            Err(err) => problems.push(format!("invalid regex: {err}")),
+            // The operator and value are not consulted for regexes.
+            Ok(re)
+                if ["", "0.0.0.0", "255.255.255.255", "::", "fe80::1"]
+                    .iter()
+                    .all(|probe| re.is_match(probe)) =>
+            {
+                problems.push("always true: the regex matches any address".into())
+            }
+            Ok(_) => {}
+        }
+        return problems;
+    }
+    if !FIELDS.contains(&field) {
+        problems.push(format!(
+            "unsupported field `{field}`; supported: {}",
+            FIELDS.join(", ")
+        ));
+        return problems;
+    }
+
+    let fingerprint = field.ends_with("ja3") || field.ends_with("ja4");
+    let value = raw.trim_matches('"');
+    match op {
+        "==" | "!=" => {
+            if let Some(why) = impossible(field, value) {
+                let outcome = if op == "==" {
+                    "always false"
+                } else {
+                    "always true"
+                };
+                problems.push(format!("{outcome}: {why}"));
+            }
+        }
+        "in" => {
+            let candidates: Vec<&str> = value
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .split(',')
+                .map(str::trim)
+                .filter(|candidate| !candidate.is_empty())
+                .collect();
+            if candidates.is_empty() {
+                problems.push("always false: the list is empty".into());
+            } else if let Some(why) = candidates
+                .iter()
+                .map(|candidate| {
+                    if candidate.starts_with('"') {
+                        Some(format!(
+                            "list items keep their quotes, {candidate} included"
+                        ))
+                    } else {
+                        impossible(field, candidate)
+                    }
+                })
+                .collect::<Option<Vec<_>>>()
+            {
+                problems.push(format!("always false: {}", why.join("; ")));
+            }
+        }
+        "in_list" if fingerprint => {}
+        _ => problems.push(format!(
+            "always false: unknown operator `{op}`; supported: ==, !=, in{}",
+            if fingerprint { ", in_list" } else { "" }
+        )),
+    }
+    problems
+}
+
+/// Why `field` never equals `value`, if it cannot.
+fn impossible(field: &str, value: &str) -> Option<String> {
+    let valid = match field {
+        "dst.port" => value.parse::<u16>().is_ok(),
+        "src.ip" | "dst.ip" => value.parse::<IpAddr>().is_ok(),
+        "dns.rcode" => {
+            RCODES.contains(&value)
+                || value
+                    .strip_prefix("RCODE")
+                    .is_some_and(|code| code.parse::<u16>().is_ok())
+        }
+        _ => true,
+    };
+    (!valid).then(|| format!("{field} is never `{value}`"))
+}
+
+/// Where items and their keys start in the source. Block-style sequences
+/// only; positions in flow-style YAML fall back to line 1.
+struct Layout {
+    items: Vec<ItemLayout>,
+}
+
+struct ItemLayout {
+    line: usize,
+    /// `(key, line, column)`.
+    keys: Vec<(String, usize, usize)>,
+}
+
+impl Layout {
+    fn new(source: &str) -> Self {
+        let mut items: Vec<ItemLayout> = Vec::new();
+        let mut item_indent = None;
+        for (index, text) in source.lines().enumerate() {
+            let line = index + 1;
+            let indent = text.len() - text.trim_start().len();
+            let mut content = text.trim_start();
+            if content.starts_with('#') || content.is_empty() {
+                continue;
+            }
+            let mut column = indent + 1;
+            if let Some(rest) = content
+                .strip_prefix("- ")
+                .or_else(|| content.strip_prefix('-'))
+            {
+                if item_indent.is_none_or(|item| item == indent) {
+                    item_indent = Some(indent);
+                    items.push(ItemLayout {
+                        line,
+                        keys: Vec::new(),
+                    });
+                    let skipped = content.len() - rest.trim_start().len();
+                    column += skipped;
+                    content = rest.trim_start();
+                }
+            }
+            if let (Some(item), Some((key, _))) = (items.last_mut(), content.split_once(':')) {
+                if !key.is_empty() && !key.contains(' ') {
+                    item.keys.push((key.to_string(), line, column));
+                }
+            }
+        }
+        Self { items }
+    }
+
+    fn item_line(&self, index: usize) -> usize {
+        self.items.get(index).map_or(1, |item| item.line)
+    }
+
+    fn key_position(&self, index: usize, key: &str) -> Option<(usize, usize)> {
+        self.items
+            .get(index)?
+            .keys
+            .iter()
+            .find(|(name, _, _)| name == key)
+            .map(|(_, line, column)| (*line, *column))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_problems_with_positions() {
+        let source = r#"# lateral movement
+- id: smb
+  severity: High
+  expression: "dst.port in [445,139]"
+- id: smb
+  severity: High
+  serverity: Low
+  expression: "dst.port == smb and proto == TCP"
+- id: retro.fanout
+  severity: Low
+  group_by: src_ip
+  threshold: 10
+- id: nx
+  severity: Medium
+  expression: "rate(\"dns.nxdomain\", \"5m\", 50)"
+"#;
+        let mut linter = RuleLinter::new();
+        let issues: Vec<String> = linter
+            .lint("a.rules", source)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(issues.len(), 5, "{issues:#?}");
+        assert_eq!(
+            issues[0],
+            "a.rules:5:1: [smb] duplicate id, first defined at a.rules:2"
+        );
+        assert_eq!(issues[1], "a.rules:7:3: [smb] unknown field `serverity`");
+        assert!(issues[2].starts_with(
+            "a.rules:8:3: [smb] `dst.port == smb and proto == TCP`: `and proto == TCP` is ignored"
+        ));
+        assert!(issues[3].ends_with("always false: dst.port is never `smb`"));
+        assert!(issues[4].starts_with("a.rules:15:3: [nx]"));
+        assert!(issues[4].contains("unsupported field `rate(\"dns.nxdomain\",`"));
+
+        let issues = linter.lint("b.rules", "- id: nx\n  severity: [\n");
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].message.starts_with("invalid YAML"));
+        assert_eq!(
+            linter.lint("c.rules", source)[0].message,
+            "duplicate id, first defined at a.rules:2"
+        );
+
+        assert!(check_expression("regex(.*) == x")[0].starts_with("always true"));
+        assert!(check_expression("dst.ip != 10.0.0.0/8")[0].starts_with("always true"));
+        assert!(check_expression("dns.rcode == NXDOMAIN").is_empty());
+        assert!(check_expression("ja3 in_list bad").is_empty());
+        assert!(check_expression(r#"proc.name in ["sshd","nginx"]"#)[0].starts_with("always false"));
+        assert_eq!(
+            check_expression("proto > 1"),
+            vec!["always false: unknown operator `>`; supported: ==, !=, in"]
+        );
+    }
+}
@@ -0,0 +1,15 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// A listening socket recorded between `baseline learn` and `freeze`. The
+/// [`crate::baseline::BaselineEngine`] alerts on listeners it did not learn
+/// and on learned ones whose owning binary hash changed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownListener {
+    pub proto: String,
+    pub ip: String,
+    pub port: u16,
+    pub process: Option<String>,
+    pub sha256_16: Option<String>,
+    pub first_seen: DateTime<Utc>,
+}
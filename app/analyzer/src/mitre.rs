@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{dsl::Rule, Alert};
+
+/// ATT&CK techniques of the built-in detectors, by rule id prefix.
+const BUILTIN_TECHNIQUES: &[(&str, &[&str])] = &[
+    ("builtin.scan.", &["T1046"]),
+    ("builtin.tcp.half_open", &["T1046"]),
+    ("builtin.conn_failures", &["T1110"]),
+    ("builtin.lateral.chain", &["T1021"]),
+    ("builtin.l2.", &["T1557.002"]),
+    ("builtin.tls.sni_mismatch", &["T1090.004"]),
+    ("builtin.tls.nonstandard_port", &["T1571"]),
+    ("builtin.tls.alpn", &["T1071.001"]),
+    ("builtin.tcp.long_lived", &["T1071"]),
+    ("builtin.listener", &["T1205"]),
+    ("intel.ja3", &["T1071.001"]),
+    ("intel.ja4", &["T1071.001"]),
+    ("intel.ip", &["T1071"]),
+    ("intel.domain", &["T1071"]),
+];
+
+/// Extracts technique ids from rule tags: `T1021.002`, `t1021` and the
+/// Sigma-style `attack.t1021.002` are all accepted and upper-cased.
+pub fn technique_ids(tags: &[String]) -> Vec<String> {
+    let mut ids: Vec<String> = tags
+        .iter()
+        .filter_map(|tag| {
+            let tag = tag.trim();
+            let tag = tag.strip_prefix("attack.").unwrap_or(tag);
+            let id = tag.to_ascii_uppercase();
+            is_technique_id(&id).then_some(id)
+        })
+        .collect();
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+fn is_technique_id(id: &str) -> bool {
+    let Some(rest) = id.strip_prefix('T') else {
+        return false;
+    };
+    let (base, sub) = match rest.split_once('.') {
+        Some((base, sub)) => (base, Some(sub)),
+        None => (rest, None),
+    };
+    base.len() == 4
+        && base.bytes().all(|b| b.is_ascii_digit())
+        && sub.is_none_or(|sub| sub.len() == 3 && sub.bytes().all(|b| b.is_ascii_digit()))
+}
+
+pub fn builtin_techniques(rule_id: &str) -> Vec<String> {
+    BUILTIN_TECHNIQUES
+        .iter()
+        .filter(|(prefix, _)| rule_id.starts_with(prefix))
+        .flat_map(|(_, ids)| ids.iter().map(|id| id.to_string()))
+        .collect()
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TechniqueCoverage {
+    pub technique: String,
+    pub rules: Vec<String>,
+    pub alerts: usize,
+    pub last_alert: Option<DateTime<Utc>>,
+}
+
+/// Techniques covered by the loaded rules and built-in detectors, with alert
+/// counts over `[from, to]`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoverageMatrix {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub techniques: Vec<TechniqueCoverage>,
+    /// DSL rules without any technique tag.
+    pub untagged_rules: Vec<String>,
+}
+
+impl CoverageMatrix {
+    pub fn build(rules: &[Rule], alerts: &[Alert], from: DateTime<Utc>, to: DateTime<Utc>) -> Self {
+        let mut by_rule: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let mut untagged_rules = Vec::new();
+        for rule in rules {
+            let ids = technique_ids(&rule.tags);
+            if ids.is_empty() {
+                untagged_rules.push(rule.id.clone());
+            }
+            by_rule.insert(rule.id.clone(), ids);
+        }
+        for (prefix, ids) in BUILTIN_TECHNIQUES {
+            by_rule.insert(
+                prefix.trim_end_matches('.').to_string(),
+                ids.iter().map(|id| id.to_string()).collect(),
+            );
+        }
+
+        let mut techniques: BTreeMap<String, TechniqueCoverage> = BTreeMap::new();
+        for (rule_id, ids) in &by_rule {
+            for id in ids {
+                let entry = techniques
+                    .entry(id.clone())
+                    .or_insert_with(|| TechniqueCoverage {
+                        technique: id.clone(),
+                        ..TechniqueCoverage::default()
+                    });
+                entry.rules.push(rule_id.clone());
+            }
+        }
+        for alert in alerts.iter().filter(|a| a.ts >= from && a.ts <= to) {
+            let ids = match by_rule.get(&alert.rule_id) {
+                Some(ids) => ids.clone(),
+                None => builtin_techniques(&alert.rule_id),
+            };
+            for id in ids {
+                let Some(entry) = techniques.get_mut(&id) else {
+                    continue;
+                };
+                entry.alerts += 1;
+                entry.last_alert = entry.last_alert.max(Some(alert.ts));
+            }
+        }
+        Self {
+            from,
+            to,
+            techniques: techniques.into_values().collect(),
+            untagged_rules,
+        }
+    }
+
+    pub fn get(&self, technique: &str) -> Option<&TechniqueCoverage> {
+        self.techniques
+            .iter()
+            .find(|coverage| coverage.technique == technique)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AlertStatus, Severity};
+    use chrono::Duration;
+
+    #[test]
+    fn counts_alerts_per_technique() {
+        let rules = crate::dsl::load_rules_from_str(
+            "- id: smb-lateral\n  severity: High\n  expression: \"dst.port == 445\"\n  tags: [smb, attack.t1021.002]\n- id: plain\n  severity: Low\n  expression: \"dst.port == 1\"\n",
+        )
+        .unwrap();
+        let now = Utc::now();
+        let alert = |rule_id: &str, ts| Alert {
+            id: String::new(),
+            ts,
+            severity: Severity::High,
+            rule_id: rule_id.into(),
+            summary: String::new(),
+            flow_refs: Vec::new(),
+            process_ref: None,
+            rationale: String::new(),
+            suggested_action: None,
+            status: AlertStatus::New,
+            assignee: None,
+            notes: Vec::new(),
+            evidence: BTreeMap::new(),
+        };
+        let alerts = vec![
+            alert("smb-lateral", now),
+            alert("smb-lateral", now - Duration::days(30)),
+            alert("builtin.scan.vertical", now),
+        ];
+        let matrix = CoverageMatrix::build(&rules, &alerts, now - Duration::days(7), now);
+        assert_eq!(matrix.get("T1021.002").unwrap().alerts, 1);
+        assert_eq!(matrix.get("T1021.002").unwrap().rules, vec!["smb-lateral"]);
+        assert_eq!(matrix.get("T1046").unwrap().alerts, 1);
+        assert_eq!(matrix.get("T1557.002").unwrap().alerts, 0);
+        assert_eq!(matrix.untagged_rules, vec!["plain"]);
+        assert!(technique_ids(&["T10".into(), "lateral".into()]).is_empty());
+    }
+}
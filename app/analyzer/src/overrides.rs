@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{exceptions::glob_match, Alert, Severity};
+
+/// `[analyzer.severity_overrides]` config section: effective severities keyed by
+/// rule id (globs allowed, e.g. `builtin.scan.*`) or by rule tag.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SeverityOverrides {
+    #[serde(default)]
+    pub rules: BTreeMap<String, Severity>,
+    #[serde(default)]
+    pub tags: BTreeMap<String, Severity>,
+}
+
+impl SeverityOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.tags.is_empty()
+    }
+
+    /// An exact rule id wins over the most specific (longest) matching glob,
+    /// which wins over tags; among tags the highest severity is used.
+    pub fn resolve(&self, rule_id: &str, tags: &[String]) -> Option<Severity> {
+        if let Some(severity) = self.rules.get(rule_id) {
+            return Some(severity.clone());
+        }
+        let by_glob = self
+            .rules
+            .iter()
+            .filter(|(pattern, _)| pattern.contains('*') && glob_match(pattern, rule_id))
+            .max_by_key(|(pattern, _)| pattern.len())
+            .map(|(_, severity)| severity.clone());
+        if by_glob.is_some() {
+            return by_glob;
+        }
+        tags.iter()
+            .filter_map(|tag| self.tags.get(tag))
+            .max()
+            .cloned()
+    }
+
+    /// Rewrites the alert's severity when an override applies, noting the
+    /// original level in the rationale.
+    pub fn apply(&self, alert: &mut Alert, tags: &[String]) {
+        let Some(severity) = self.resolve(&alert.rule_id, tags) else {
+            return;
+        };
+        if severity != alert.severity {
+            alert.rationale = format!(
+                "{} [severity overridden: {:?} -> {:?}]",
+                alert.rationale, alert.severity, severity
+            );
+            alert.severity = severity;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precedence() {
+        let overrides = SeverityOverrides {
+            rules: BTreeMap::from([
+                ("smb-lateral".to_string(), Severity::Low),
+                ("builtin.scan.*".to_string(), Severity::High),
+            ]),
+            tags: BTreeMap::from([
+                ("lateral-movement".to_string(), Severity::Medium),
+                ("nas".to_string(), Severity::Low),
+            ]),
+        };
+        let tags = vec!["lateral-movement".to_string(), "nas".to_string()];
+        assert_eq!(overrides.resolve("smb-lateral", &tags), Some(Severity::Low));
+        assert_eq!(
+            overrides.resolve("builtin.scan.vertical", &[]),
+            Some(Severity::High)
+        );
+        assert_eq!(
+            overrides.resolve("rdp-burst", &tags),
+            Some(Severity::Medium)
+        );
+        assert_eq!(overrides.resolve("rdp-burst", &[]), None);
+    }
+}
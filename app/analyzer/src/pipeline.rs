@@ -0,0 +1,317 @@
+//! Ingestion split for multi-core hosts. Rule matching reads nothing but
+//! the loaded rules and threat-intel lists, so a [`RuleStage`] snapshot can
+//! run on any number of [`Workers`]. Detectors, baseline and scorers
+//! correlate flows across hosts, so they stay on the one [`Analyzer`],
+//! which takes every matched flow in arrival order through
+//! [`Analyzer::ingest_matched`].
+
+use std::{
+    collections::BTreeMap,
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use normalizer::NormalizedFlow;
+
+use crate::{dsl, fingerprints, intel, new_alert_id, Alert, AlertStatus, Analyzer};
+
+/// Jobs queued per worker before [`Workers::submit`] blocks.
+const QUEUE_PER_WORKER: usize = 64;
+
+/// The stateless part of [`Analyzer::ingest`]: the DSL rules and the
+/// JA3/JA4 and indicator lists as they were when
+/// [`Analyzer::rule_stage`] was called.
+#[derive(Clone)]
+pub struct RuleStage {
+    pub(crate) rules: Arc<Vec<dsl::Rule>>,
+    pub(crate) fingerprints: Arc<fingerprints::FingerprintLists>,
+    pub(crate) indicators: Arc<intel::IndicatorLists>,
+}
+
+/// A flow with the alerts its rules and lists raised, waiting for the
+/// stateful stage.
+pub struct MatchedFlow {
+    pub flow: NormalizedFlow,
+    pub(crate) alerts: Vec<Alert>,
+    pub(crate) timings: Vec<RuleTiming>,
+}
+
+/// One rule evaluation, for the rule profiler.
+pub(crate) struct RuleTiming {
+    pub rule_id: String,
+    pub elapsed: Duration,
+    pub outcome: Result<bool, String>,
+}
+
+impl RuleStage {
+    pub fn evaluate(&self, flow: NormalizedFlow) -> MatchedFlow {
+        let context = dsl::EvalContext {
+            fingerprints: Some(&self.fingerprints),
+        };
+        let mut alerts = Vec::new();
+        let mut timings = Vec::with_capacity(self.rules.len());
+        for rule in self.rules.iter() {
+            let started = Instant::now();
+            let outcome = rule.evaluate_with(&flow, &context);
+            let elapsed = started.elapsed();
+            let matched = match &outcome {
+                Ok(matched) => *matched,
+                Err(err) => {
+                    tracing::warn!(rule = %rule.id, %err, "rule evaluation failed");
+                    false
+                }
+            };
+            timings.push(RuleTiming {
+                rule_id: rule.id.clone(),
+                elapsed,
+                outcome: outcome.map_err(|err| err.to_string()),
+            });
+            if matched {
+                alerts.push(Alert {
+                    id: new_alert_id(),
+                    ts: Utc::now(),
+                    severity: rule.severity.clone(),
+                    rule_id: rule.id.clone(),
+                    summary: rule.summary.clone().unwrap_or_else(|| "Rule match".into()),
+                    flow_refs: flow.flow_id.into_iter().collect(),
+                    process_ref: flow.process.clone(),
+                    rationale: self.rule_rationale(rule, &flow),
+                    suggested_action: rule.suggested_action.clone(),
+                    status: AlertStatus::New,
+                    assignee: None,
+                    notes: Vec::new(),
+                    evidence: BTreeMap::new(),
+                });
+            }
+        }
+        alerts.extend(self.fingerprints.alerts(&flow));
+        alerts.extend(self.indicators.alerts(&flow));
+        MatchedFlow {
+            flow,
+            alerts,
+            timings,
+        }
+    }
+
+    fn rule_rationale(&self, rule: &dsl::Rule, flow: &NormalizedFlow) -> String {
+        let mut rationale = rule
+            .rationale
+            .clone()
+            .unwrap_or_else(|| "Matched DSL condition".into());
+        if rule.expression.contains("in_list") {
+            let lists: Vec<String> = self
+                .fingerprints
+                .matches(flow)
+                .into_iter()
+                .map(|m| format!("{} {} in \"{}\"", m.fingerprint.label(), m.value, m.list))
+                .collect();
+            if !lists.is_empty() {
+                rationale = format!("{rationale}; {}", lists.join(", "));
+            }
+        }
+        rationale
+    }
+}
+
+/// A stateless stage run on a pool of threads; results come back in
+/// submission order, so whatever consumes them sees flows as they arrived.
+pub struct Workers<I, O> {
+    jobs: Option<mpsc::SyncSender<(u64, I)>>,
+    results: mpsc::Receiver<(u64, Option<O>)>,
+    /// Results that finished ahead of an earlier job; `None` for a job
+    /// whose stage panicked.
+    early: BTreeMap<u64, Option<O>>,
+    submitted: u64,
+    returned: u64,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl<I: Send + 'static, O: Send + 'static> Workers<I, O> {
+    /// Starts `count` threads (at least one) running `stage`.
+    pub fn spawn<F>(count: usize, stage: F) -> Result<Self>
+    where
+        F: Fn(I) -> O + Send + Sync + 'static,
+    {
+        let count = count.max(1);
+        let (jobs, queue) = mpsc::sync_channel::<(u64, I)>(count * QUEUE_PER_WORKER);
+        let queue = Arc::new(Mutex::new(queue));
+        let (done, results) = mpsc::channel();
+        let stage = Arc::new(stage);
+        let mut threads = Vec::with_capacity(count);
+        for n in 0..count {
+            let queue = Arc::clone(&queue);
+            let done = done.clone();
+            let stage = Arc::clone(&stage);
+            let thread = thread::Builder::new()
+                .name(format!("nets-rules-{n}"))
+                .spawn(move || loop {
+                    let job = match queue.lock() {
+                        Ok(queue) => queue.recv(),
+                        Err(_) => break,
+                    };
+                    let Ok((seq, input)) = job else {
+                        break;
+                    };
+                    let output = panic::catch_unwind(AssertUnwindSafe(|| stage(input))).ok();
+                    if output.is_none() {
+                        tracing::error!(job = seq, "rule worker panicked; flow skipped");
+                    }
+                    if done.send((seq, output)).is_err() {
+                        break;
+                    }
+                })?;
+            threads.push(thread);
+        }
+        Ok(Self {
+            jobs: Some(jobs),
+            results,
+            early: BTreeMap::new(),
+            submitted: 0,
+            returned: 0,
+            threads,
+        })
+    }
+
+    /// Queues `input`, waiting while every worker is behind.
+    pub fn submit(&mut self, input: I) -> Result<()> {
+        let jobs = self
+            .jobs
+            .as_ref()
+            .ok_or_else(|| anyhow!("rule workers stopped"))?;
+        jobs.send((self.submitted, input))
+            .map_err(|_| anyhow!("rule workers stopped"))?;
+        self.submitted += 1;
+        Ok(())
+    }
+
+    /// Jobs submitted whose results have not been returned yet.
+    pub fn in_flight(&self) -> u64 {
+        self.submitted - self.returned
+    }
+
+    /// The next result in order, if it is ready.
+    pub fn try_next(&mut self) -> Option<O> {
+        while let Ok((seq, output)) = self.results.try_recv() {
+            self.early.insert(seq, output);
+        }
+        self.take_ready()
+    }
+
+    /// Waits for the next result in order; `None` once nothing is in flight.
+    pub fn wait_next(&mut self) -> Option<O> {
+        loop {
+            if let Some(output) = self.take_ready() {
+                return Some(output);
+            }
+            if self.in_flight() == 0 {
+                return None;
+            }
+            let (seq, output) = self.results.recv().ok()?;
+            self.early.insert(seq, output);
+        }
+    }
+
+    fn take_ready(&mut self) -> Option<O> {
+        while let Some(output) = self.early.remove(&self.returned) {
+            self.returned += 1;
+            if output.is_some() {
+                return output;
+            }
+        }
+        None
+    }
+}
+
+impl<I, O> Drop for Workers<I, O> {
+    fn drop(&mut self) {
+        // Closing the queue ends each worker after its current job.
+        self.jobs.take();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Analyzer {
+    /// Snapshot of the rules and lists for [`Workers`]; take a new one
+    /// after replacing either.
+    pub fn rule_stage(&self) -> RuleStage {
+        RuleStage {
+            rules: Arc::clone(&self.rules),
+            fingerprints: Arc::clone(&self.fingerprints),
+            indicators: Arc::clone(&self.indicators),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl::load_rules_from_str;
+    use chrono::Duration as ChronoDuration;
+    use collector::FlowDirection;
+
+    fn probe(port: u16) -> NormalizedFlow {
+        NormalizedFlow {
+            window_start: Utc::now(),
+            window_end: Utc::now(),
+            src_ip: "10.0.0.5".into(),
+            dst_ip: "10.0.0.9".into(),
+            dst_port: port,
+            proto: "tcp".into(),
+            direction: FlowDirection::Outbound,
+            ..NormalizedFlow::default()
+        }
+    }
+
+    #[test]
+    fn keeps_submission_order_and_skips_panicked_jobs() {
+        let mut workers = Workers::spawn(4, |n: u64| {
+            assert_ne!(n, 3, "bad job");
+            thread::sleep(Duration::from_millis((10 - n) * 2));
+            n
+        })
+        .unwrap();
+        for n in 0..10 {
+            workers.submit(n).unwrap();
+        }
+        let mut seen = Vec::new();
+        while let Some(n) = workers.wait_next() {
+            seen.push(n);
+        }
+        assert_eq!(seen, vec![0, 1, 2, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(workers.in_flight(), 0);
+    }
+
+    #[test]
+    fn rules_on_workers_feed_one_stateful_stage() {
+        let rules = load_rules_from_str(
+            "- id: smb_probe\n  severity: Low\n  expression: \"dst.port == 445\"\n",
+        )
+        .unwrap();
+        let mut analyzer = Analyzer::new(ChronoDuration::hours(1), rules);
+        let stage = analyzer.rule_stage();
+        let mut workers = Workers::spawn(3, move |flow| stage.evaluate(flow)).unwrap();
+        for port in 440..470 {
+            workers.submit(probe(port)).unwrap();
+        }
+        let mut rule_ids = Vec::new();
+        while let Some(matched) = workers.wait_next() {
+            rule_ids.extend(
+                analyzer
+                    .ingest_matched(matched)
+                    .into_iter()
+                    .map(|a| a.rule_id),
+            );
+        }
+        assert!(rule_ids.contains(&"smb_probe".to_string()));
+        // Ports spread over the workers still add up to one vertical scan.
+        assert!(rule_ids.contains(&"builtin.scan.vertical".to_string()));
+        let stats = analyzer.rule_stats();
+        assert_eq!(stats[0].evaluations, 30);
+    }
+}
@@ -0,0 +1,110 @@
+use std::{collections::HashMap, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+/// Cumulative evaluation cost of one DSL rule.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RuleStats {
+    pub rule_id: String,
+    pub evaluations: u64,
+    pub matches: u64,
+    pub errors: u64,
+    pub total_time: Duration,
+    pub max_time: Duration,
+    pub last_error: Option<String>,
+    /// Fraction of the time spent on all rules, filled in by
+    /// [`RuleProfiler::snapshot`].
+    pub share: f64,
+}
+
+impl RuleStats {
+    pub fn mean_time(&self) -> Duration {
+        if self.evaluations == 0 {
+            return Duration::ZERO;
+        }
+        self.total_time / self.evaluations.min(u32::MAX as u64) as u32
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RuleProfiler {
+    stats: HashMap<String, RuleStats>,
+}
+
+impl RuleProfiler {
+    pub fn record(&mut self, rule_id: &str, elapsed: Duration, outcome: Result<bool, String>) {
+        let stats = self
+            .stats
+            .entry(rule_id.to_string())
+            .or_insert_with(|| RuleStats {
+                rule_id: rule_id.to_string(),
+                ..RuleStats::default()
+            });
+        stats.evaluations += 1;
+        stats.total_time += elapsed;
+        stats.max_time = stats.max_time.max(elapsed);
+        match outcome {
+            Ok(true) => stats.matches += 1,
+            Ok(false) => {}
+            Err(err) => {
+                stats.errors += 1;
+                stats.last_error = Some(err);
+            }
+        }
+    }
+
+    /// Stats for every rule evaluated so far, most expensive first.
+    pub fn snapshot(&self) -> Vec<RuleStats> {
+        let total: f64 = self
+            .stats
+            .values()
+            .map(|stats| stats.total_time.as_secs_f64())
+            .sum();
+        let mut out: Vec<RuleStats> = self
+            .stats
+            .values()
+            .cloned()
+            .map(|mut stats| {
+                stats.share = if total > 0.0 {
+                    stats.total_time.as_secs_f64() / total
+                } else {
+                    0.0
+                };
+                stats
+            })
+            .collect();
+        out.sort_by(|a, b| {
+            b.total_time
+                .cmp(&a.total_time)
+                .then_with(|| a.rule_id.cmp(&b.rule_id))
+        });
+        out
+    }
+
+    pub fn reset(&mut self) {
+        self.stats.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranks_rules_by_time() {
+        let mut profiler = RuleProfiler::default();
+        profiler.record("cheap", Duration::from_micros(10), Ok(false));
+        profiler.record("regex", Duration::from_micros(70), Ok(true));
+        profiler.record("regex", Duration::from_micros(20), Err("bad regex".into()));
+
+        let stats = profiler.snapshot();
+        assert_eq!(stats[0].rule_id, "regex");
+        assert_eq!(stats[0].evaluations, 2);
+        assert_eq!(stats[0].matches, 1);
+        assert_eq!(stats[0].errors, 1);
+        assert_eq!(stats[0].max_time, Duration::from_micros(70));
+        assert_eq!(stats[0].mean_time(), Duration::from_micros(45));
+        assert!((stats[0].share - 0.9).abs() < 1e-9);
+        assert_eq!(stats[1].last_error, None);
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{new_alert_id, Alert, AlertStatus, Severity};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Alerts a rule may emit back to back before throttling kicks in.
+    pub burst: u32,
+    /// Sustained alerts per minute per rule.
+    pub per_minute: u32,
+    /// How often suppressed counts are summarized in a roll-up alert.
+    pub rollup_seconds: i64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            burst: 20,
+            per_minute: 30,
+            rollup_seconds: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    tokens: f64,
+    updated: DateTime<Utc>,
+    suppressed: u64,
+    suppressed_severity: Severity,
+    first_suppressed: Option<DateTime<Utc>>,
+    sample: Option<String>,
+}
+
+/// Per-rule token buckets. Alerts beyond the budget are dropped and counted;
+/// the counts are reported as one `builtin.rate_limit` alert per rule every
+/// `rollup_seconds`.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: HashMap<String, Bucket>,
+    last_rollup: Option<DateTime<Utc>>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(RateLimitConfig::default())
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: HashMap::new(),
+            last_rollup: None,
+        }
+    }
+
+    /// Takes a token for the alert's rule; returns false when the alert must be
+    /// dropped.
+    pub fn admit(&mut self, alert: &Alert, now: DateTime<Utc>) -> bool {
+        let burst = self.config.burst as f64;
+        let rate = self.config.per_minute as f64 / 60.0;
+        let bucket = self
+            .buckets
+            .entry(alert.rule_id.clone())
+            .or_insert_with(|| Bucket {
+                tokens: burst,
+                updated: now,
+                suppressed: 0,
+                suppressed_severity: Severity::Low,
+                first_suppressed: None,
+                sample: None,
+            });
+        let elapsed = (now - bucket.updated).num_milliseconds().max(0) as f64 / 1000.0;
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return true;
+        }
+        bucket.suppressed += 1;
+        bucket.suppressed_severity = bucket
+            .suppressed_severity
+            .clone()
+            .max(alert.severity.clone());
+        bucket.first_suppressed.get_or_insert(now);
+        bucket.sample.get_or_insert_with(|| alert.summary.clone());
+        false
+    }
+
+    /// Roll-up alerts for rules that were throttled, once per interval.
+    pub fn rollups(&mut self, now: DateTime<Utc>) -> Vec<Alert> {
+        let last = *self.last_rollup.get_or_insert(now);
+        if now - last < Duration::seconds(self.config.rollup_seconds) {
+            return Vec::new();
+        }
+        self.last_rollup = Some(now);
+        self.drain(now)
+    }
+
+    /// Reports every pending suppressed count immediately.
+    pub fn drain(&mut self, now: DateTime<Utc>) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        for (rule_id, bucket) in &mut self.buckets {
+            if bucket.suppressed == 0 {
+                continue;
+            }
+            let since = bucket.first_suppressed.take().unwrap_or(now);
+            alerts.push(Alert {
+                id: new_alert_id(),
+                ts: now,
+                severity: std::mem::replace(&mut bucket.suppressed_severity, Severity::Low),
+                rule_id: "builtin.rate_limit".into(),
+                summary: format!(
+                    "{} alerts from rule {rule_id} suppressed by rate limit",
+                    bucket.suppressed
+                ),
+                flow_refs: Vec::new(),
+                process_ref: None,
+                rationale: format!(
+                    "rule {rule_id} exceeded {} alerts/min (burst {}) since {}; e.g. \"{}\"",
+                    self.config.per_minute,
+                    self.config.burst,
+                    since.to_rfc3339(),
+                    bucket.sample.take().unwrap_or_default()
+                ),
+                suggested_action: Some(
+                    "Check whether the rule is misfiring or a real flood is under way".into(),
+                ),
+                status: AlertStatus::New,
+                assignee: None,
+                notes: Vec::new(),
+                evidence: BTreeMap::new(),
+            });
+            bucket.suppressed = 0;
+        }
+        // Idle buckets are back at full capacity; forget them.
+        let burst = self.config.burst as f64;
+        let rate = self.config.per_minute as f64 / 60.0;
+        self.buckets.retain(|_, bucket| {
+            let elapsed = (now - bucket.updated).num_seconds().max(0) as f64;
+            bucket.tokens + elapsed * rate < burst
+        });
+        alerts.sort_by(|a, b| a.summary.cmp(&b.summary));
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alert(rule: &str) -> Alert {
+        Alert {
+            id: String::new(),
+            ts: Utc::now(),
+            severity: Severity::Medium,
+            rule_id: rule.into(),
+            summary: "match".into(),
+            flow_refs: Vec::new(),
+            process_ref: None,
+            rationale: String::new(),
+            suggested_action: None,
+            status: AlertStatus::New,
+            assignee: None,
+            notes: Vec::new(),
+            evidence: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn throttles_and_rolls_up() {
+        let mut limiter = RateLimiter::new(RateLimitConfig {
+            burst: 5,
+            per_minute: 60,
+            rollup_seconds: 60,
+        });
+        let start = Utc::now();
+        let noisy = alert("noisy");
+        let admitted = (0..100).filter(|_| limiter.admit(&noisy, start)).count();
+        assert_eq!(admitted, 5);
+        assert!(limiter.admit(&alert("quiet"), start));
+        assert!(limiter.rollups(start).is_empty());
+
+        // One token per second refills.
+        assert!(limiter.admit(&noisy, start + Duration::seconds(2)));
+
+        let rollups = limiter.rollups(start + Duration::seconds(61));
+        assert_eq!(rollups.len(), 1);
+        assert_eq!(rollups[0].rule_id, "builtin.rate_limit");
+        assert!(rollups[0].summary.starts_with("95 alerts from rule noisy"));
+        assert!(limiter.rollups(start + Duration::seconds(200)).is_empty());
+    }
+}
@@ -0,0 +1,352 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use collector::FlowEvent;
+use normalizer::{NormalizedFlow, Normalizer};
+use serde::{Deserialize, Serialize};
+
+use crate::{dsl, new_alert_id, Alert, AlertStatus, Severity};
+
+/// Read access to persisted flows; implemented by the storage crate so the
+/// analyzer does not depend on it.
+pub trait FlowHistory {
+    /// Stored flows whose first packet falls in `[from, to]`, with their row ids.
+    fn flows_between(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<(i64, FlowEvent)>>;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupBy {
+    #[default]
+    SrcIp,
+    DstIp,
+    Process,
+}
+
+impl GroupBy {
+    fn key(self, flow: &NormalizedFlow) -> Option<String> {
+        match self {
+            GroupBy::SrcIp => Some(flow.src_ip.clone()),
+            GroupBy::DstIp => Some(flow.dst_ip.clone()),
+            GroupBy::Process => flow.process.clone(),
+        }
+        .filter(|key| !key.is_empty())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DistinctField {
+    DstIp,
+    DstPort,
+    DnsQname,
+    Sni,
+}
+
+impl DistinctField {
+    fn label(self) -> &'static str {
+        match self {
+            DistinctField::DstIp => "dst.ip",
+            DistinctField::DstPort => "dst.port",
+            DistinctField::DnsQname => "dns.qname",
+            DistinctField::Sni => "sni",
+        }
+    }
+
+    fn value(self, flow: &NormalizedFlow) -> Option<String> {
+        match self {
+            DistinctField::DstIp => Some(flow.dst_ip.clone()),
+            DistinctField::DstPort => Some(flow.dst_port.to_string()),
+            DistinctField::DnsQname => flow.dns_qname.as_ref().map(|q| q.to_ascii_lowercase()),
+            DistinctField::Sni => flow.sni.as_ref().map(|s| s.to_ascii_lowercase()),
+        }
+    }
+}
+
+/// Aggregate rule evaluated periodically over stored history, e.g. "every 15
+/// minutes: any host with more than 50 distinct NXDOMAIN names in the last hour".
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetroRule {
+    pub id: String,
+    pub severity: Severity,
+    pub summary: Option<String>,
+    pub suggested_action: Option<String>,
+    /// DSL expression selecting the flows to aggregate; all flows when absent.
+    pub filter: Option<String>,
+    #[serde(default)]
+    pub group_by: GroupBy,
+    /// Count distinct values of this field per group instead of flows.
+    pub distinct: Option<DistinctField>,
+    /// Alert when a group's count exceeds this.
+    pub threshold: usize,
+    #[serde(default = "default_every")]
+    pub every_seconds: i64,
+    #[serde(default = "default_lookback")]
+    pub lookback_seconds: i64,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+fn default_every() -> i64 {
+    900
+}
+
+fn default_lookback() -> i64 {
+    3600
+}
+
+pub fn load_retro_rules_from_str(data: &str) -> Result<Vec<RetroRule>> {
+    let rules: Vec<RetroRule> =
+        serde_yaml::from_str(data).context("parsing retrospective rules")?;
+    Ok(rules)
+}
+
+#[derive(Default)]
+struct Group {
+    flows: usize,
+    values: BTreeSet<String>,
+    flow_ids: Vec<i64>,
+}
+
+/// Runs [`RetroRule`]s when they are due and reports each offending group
+/// at most once per lookback window.
+pub struct RetroScheduler {
+    rules: Vec<RetroRule>,
+    last_run: HashMap<String, DateTime<Utc>>,
+    alerted: HashMap<(String, String), DateTime<Utc>>,
+    normalizer: Normalizer,
+}
+
+impl Default for RetroScheduler {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl RetroScheduler {
+    pub fn new(rules: Vec<RetroRule>) -> Self {
+        Self {
+            rules,
+            last_run: HashMap::new(),
+            alerted: HashMap::new(),
+            normalizer: Normalizer::new(Duration::seconds(60)),
+        }
+    }
+
+    pub fn rules(&self) -> &[RetroRule] {
+        &self.rules
+    }
+
+    pub fn set_rules(&mut self, rules: Vec<RetroRule>) {
+        self.last_run
+            .retain(|id, _| rules.iter().any(|rule| &rule.id == id));
+        self.rules = rules;
+    }
+
+    /// Ids of rules whose interval has elapsed (or that never ran).
+    pub fn due(&self, now: DateTime<Utc>) -> Vec<String> {
+        self.rules
+            .iter()
+            .filter(|rule| {
+                self.last_run
+                    .get(&rule.id)
+                    .map(|last| now - *last >= Duration::seconds(rule.every_seconds))
+                    .unwrap_or(true)
+            })
+            .map(|rule| rule.id.clone())
+            .collect()
+    }
+
+    /// Evaluates every due rule against `history`. A failing rule is logged
+    /// and retried on the next tick; the others still run.
+    pub fn run_due(&mut self, history: &dyn FlowHistory, now: DateTime<Utc>) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        for id in self.due(now) {
+            let Some(rule) = self.rules.iter().find(|rule| rule.id == id).cloned() else {
+                continue;
+            };
+            match self.run_rule(&rule, history, now) {
+                Ok(found) => {
+                    self.last_run.insert(id, now);
+                    alerts.extend(found);
+                }
+                Err(err) => tracing::warn!(rule = %id, %err, "retrospective rule failed"),
+            }
+        }
+        self.alerted.retain(|(id, _), ts| {
+            self.rules
+                .iter()
+                .find(|rule| &rule.id == id)
+                .map(|rule| now - *ts < Duration::seconds(rule.lookback_seconds))
+                .unwrap_or(false)
+        });
+        alerts
+    }
+
+    pub fn run_rule(
+        &mut self,
+        rule: &RetroRule,
+        history: &dyn FlowHistory,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Alert>> {
+        let lookback = Duration::seconds(rule.lookback_seconds);
+        let from = now - lookback;
+        let mut groups: BTreeMap<String, Group> = BTreeMap::new();
+        for (flow_id, event) in history.flows_between(from, now)? {
+            let mut flow = self.normalizer.normalize(event)?;
+            flow.flow_id = Some(flow_id);
+            if let Some(filter) = &rule.filter {
+                if !dsl::evaluate_expression(filter, &flow)? {
+                    continue;
+                }
+            }
+            let Some(key) = rule.group_by.key(&flow) else {
+                continue;
+            };
+            let group = groups.entry(key).or_default();
+            group.flows += 1;
+            if let Some(field) = rule.distinct {
+                let fresh = field
+                    .value(&flow)
+                    .is_some_and(|value| group.values.insert(value));
+                if !fresh {
+                    continue;
+                }
+            }
+            if group.flow_ids.len() < 100 {
+                group.flow_ids.push(flow_id);
+            }
+        }
+
+        let mut alerts = Vec::new();
+        for (key, group) in groups {
+            let count = match rule.distinct {
+                Some(_) => group.values.len(),
+                None => group.flows,
+            };
+            if count <= rule.threshold {
+                continue;
+            }
+            let marker = (rule.id.clone(), key.clone());
+            if self
+                .alerted
+                .get(&marker)
+                .is_some_and(|ts| now - *ts < lookback)
+            {
+                continue;
+            }
+            self.alerted.insert(marker, now);
+            let measure = rule
+                .distinct
+                .map(|field| format!("distinct {}", field.label()))
+                .unwrap_or_else(|| "flows".into());
+            let mut evidence = BTreeMap::new();
+            evidence.insert("group".into(), key.clone());
+            evidence.insert("count".into(), count.to_string());
+            evidence.insert("from".into(), from.to_rfc3339());
+            evidence.insert("to".into(), now.to_rfc3339());
+            if !group.values.is_empty() {
+                evidence.insert(
+                    "sample".into(),
+                    group
+                        .values
+                        .iter()
+                        .take(10)
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join(","),
+                );
+            }
+            alerts.push(Alert {
+                id: new_alert_id(),
+                ts: Utc::now(),
+                severity: rule.severity.clone(),
+                rule_id: rule.id.clone(),
+                summary: rule
+                    .summary
+                    .clone()
+                    .unwrap_or_else(|| format!("{count} {measure} for {key}")),
+                flow_refs: group.flow_ids,
+                process_ref: (rule.group_by == GroupBy::Process).then(|| key.clone()),
+                rationale: format!(
+                    "{count} {measure} for {key} in the last {}s (threshold {})",
+                    rule.lookback_seconds, rule.threshold
+                ),
+                suggested_action: rule.suggested_action.clone(),
+                status: AlertStatus::New,
+                assignee: None,
+                notes: Vec::new(),
+                evidence,
+            });
+        }
+        Ok(alerts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    struct Memory(Vec<(i64, FlowEvent)>);
+
+    impl FlowHistory for Memory {
+        fn flows_between(
+            &self,
+            from: DateTime<Utc>,
+            to: DateTime<Utc>,
+        ) -> Result<Vec<(i64, FlowEvent)>> {
+            Ok(self
+                .0
+                .iter()
+                .filter(|(_, flow)| flow.ts_first >= from && flow.ts_first <= to)
+                .cloned()
+                .collect())
+        }
+    }
+
+    #[test]
+    fn nxdomain_burst_over_history() {
+        let rules = load_retro_rules_from_str(
+            "- id: retro.dns.nxdomain\n  severity: Medium\n  filter: \"dns.rcode == NXDOMAIN\"\n  distinct: dns_qname\n  threshold: 3\n",
+        )
+        .unwrap();
+        let now = Utc.timestamp_opt(1_700_003_600, 0).unwrap();
+        let history = Memory(
+            (0..6)
+                .map(|i| {
+                    let ts = now - Duration::minutes(10 + i);
+                    let event = FlowEvent {
+                        ts_first: ts,
+                        ts_last: ts,
+                        proto: "UDP".into(),
+                        src_ip: "10.0.0.5".into(),
+                        dst_ip: "10.0.0.1".into(),
+                        dst_port: 53,
+                        dns_qname: Some(format!("x{}.example", i % 5)),
+                        dns_rcode: Some("NXDOMAIN".into()),
+                        ..FlowEvent::default()
+                    };
+                    (i + 1, event)
+                })
+                .collect(),
+        );
+
+        let mut scheduler = RetroScheduler::new(rules);
+        let alerts = scheduler.run_due(&history, now);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].evidence["count"], "5");
+        assert_eq!(alerts[0].flow_refs, vec![1, 2, 3, 4, 5]);
+        // Not due again until the interval passes, and not repeated within
+        // the lookback once it is.
+        assert!(scheduler.due(now + Duration::minutes(5)).is_empty());
+        assert!(scheduler
+            .run_due(&history, now + Duration::minutes(15))
+            .is_empty());
+    }
+}
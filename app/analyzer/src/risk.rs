@@ -0,0 +1,201 @@
+use collector::{FlowEvent, FlowRisk};
+use serde::{Deserialize, Serialize};
+
+use crate::{Alert, Severity};
+
+/// Point contributions of each signal to the 0–100 risk score.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RiskWeights {
+    pub high_alert: u32,
+    pub medium_alert: u32,
+    pub low_alert: u32,
+    /// Added per alert beyond the first, capped at `extra_alert_cap`.
+    pub extra_alert: u32,
+    pub extra_alert_cap: u32,
+    pub intel_hit: u32,
+    pub extra_intel_hit: u32,
+    pub unsigned_process: u32,
+    pub unknown_signature: u32,
+    /// Multiplied by the highest anomaly score (0.0–1.0).
+    pub anomaly: f32,
+    pub high_level: u8,
+    pub medium_level: u8,
+}
+
+impl Default for RiskWeights {
+    fn default() -> Self {
+        Self {
+            high_alert: 60,
+            medium_alert: 35,
+            low_alert: 15,
+            extra_alert: 5,
+            extra_alert_cap: 15,
+            intel_hit: 40,
+            extra_intel_hit: 10,
+            unsigned_process: 10,
+            unknown_signature: 3,
+            anomaly: 30.0,
+            high_level: 70,
+            medium_level: 40,
+        }
+    }
+}
+
+/// Signals gathered for one flow by the analyzer and its collaborators.
+#[derive(Debug, Default, Clone)]
+pub struct RiskInputs<'a> {
+    pub alerts: &'a [Alert],
+    /// Names of threat-intel lists the flow matched.
+    pub intel_hits: &'a [String],
+    pub anomaly_score: Option<f32>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RiskEngine {
+    weights: RiskWeights,
+}
+
+impl RiskEngine {
+    pub fn new(weights: RiskWeights) -> Self {
+        Self { weights }
+    }
+
+    pub fn score(&self, flow: &FlowEvent, inputs: &RiskInputs<'_>) -> FlowRisk {
+        let w = &self.weights;
+        let mut score: u32 = 0;
+        let mut reasons = Vec::new();
+
+        let top = inputs
+            .alerts
+            .iter()
+            .max_by(|a, b| a.severity.cmp(&b.severity));
+        if let Some(top) = top {
+            score += match top.severity {
+                Severity::High => w.high_alert,
+                Severity::Medium => w.medium_alert,
+                Severity::Low => w.low_alert,
+            };
+            let extra = (inputs.alerts.len() as u32 - 1) * w.extra_alert;
+            score += extra.min(w.extra_alert_cap);
+            reasons.push(format!(
+                "{} rule match(es), top {} ({:?})",
+                inputs.alerts.len(),
+                top.rule_id,
+                top.severity
+            ));
+        }
+
+        // Intel matches surfaced as alerts count the same as explicit hits.
+        let mut intel: Vec<&str> = inputs.intel_hits.iter().map(String::as_str).collect();
+        intel.extend(
+            inputs
+                .alerts
+                .iter()
+                .filter(|alert| alert.rule_id.starts_with("intel."))
+                .map(|alert| alert.rule_id.as_str()),
+        );
+        if !intel.is_empty() {
+            score += w.intel_hit + (intel.len() as u32 - 1) * w.extra_intel_hit;
+            reasons.push(format!("threat intel: {}", intel.join(", ")));
+        }
+
+        if let Some(process) = &flow.process {
+            match process.signed {
+                Some(false) => {
+                    score += w.unsigned_process;
+                    reasons.push("unsigned process".into());
+                }
+                None => score += w.unknown_signature,
+                Some(true) => {}
+            }
+        }
+
+        if let Some(anomaly) = inputs.anomaly_score.filter(|s| *s > 0.0) {
+            score += (anomaly.clamp(0.0, 1.0) * w.anomaly).round() as u32;
+            reasons.push(format!("anomaly score {anomaly:.2}"));
+        }
+
+        let score = score.min(100) as u8;
+        FlowRisk {
+            score,
+            level: self.level(score).into(),
+            rule_id: top.map(|alert| alert.rule_id.clone()),
+            rationale: if reasons.is_empty() {
+                None
+            } else {
+                Some(reasons.join("; "))
+            },
+        }
+    }
+
+    /// Computes the risk and stores it on the flow.
+    pub fn apply(&self, flow: &mut FlowEvent, inputs: &RiskInputs<'_>) {
+        flow.risk = Some(self.score(flow, inputs));
+    }
+
+    pub fn level(&self, score: u8) -> &'static str {
+        if score >= self.weights.high_level {
+            "High"
+        } else if score >= self.weights.medium_level {
+            "Medium"
+        } else {
+            "Low"
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AlertStatus;
+    use chrono::Utc;
+    use collector::ProcessIdentity;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn combines_signals() {
+        let flow = FlowEvent {
+            process: Some(ProcessIdentity {
+                pid: 42,
+                ppid: None,
+                name: Some("dropper".into()),
+                exe_path: None,
+                sha256_16: None,
+                user: None,
+                signed: Some(false),
+            }),
+            ..FlowEvent::default()
+        };
+        let alert = Alert {
+            id: "a".into(),
+            ts: Utc::now(),
+            severity: Severity::High,
+            rule_id: "smb-lateral".into(),
+            summary: String::new(),
+            flow_refs: Vec::new(),
+            process_ref: None,
+            rationale: String::new(),
+            suggested_action: None,
+            status: AlertStatus::New,
+            assignee: None,
+            notes: Vec::new(),
+            evidence: BTreeMap::new(),
+        };
+        let engine = RiskEngine::default();
+        let quiet = engine.score(&flow, &RiskInputs::default());
+        assert_eq!((quiet.score, quiet.level.as_str()), (10, "Low"));
+
+        let intel = vec!["feodo".to_string()];
+        let risk = engine.score(
+            &flow,
+            &RiskInputs {
+                alerts: std::slice::from_ref(&alert),
+                intel_hits: &intel,
+                anomaly_score: Some(0.5),
+            },
+        );
+        assert_eq!(risk.score, 100);
+        assert_eq!(risk.level, "High");
+        assert_eq!(risk.rule_id.as_deref(), Some("smb-lateral"));
+    }
+}
@@ -0,0 +1,345 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::hash::Hash;
+
+use chrono::{DateTime, Duration, Utc};
+use collector::FlowDirection;
+use normalizer::NormalizedFlow;
+use serde::{Deserialize, Serialize};
+
+use crate::{new_alert_id, Alert, AlertStatus, Detector, Severity};
+
+const MAX_LISTED: usize = 20;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanConfig {
+    /// Window used for fast vertical/horizontal scans.
+    pub window_seconds: i64,
+    /// Long window used to catch slow scans that stay under the fast thresholds.
+    pub slow_window_seconds: i64,
+    /// Distinct destination ports on one host within `window_seconds`.
+    pub vertical_threshold: usize,
+    /// Distinct hosts contacted on one port within `window_seconds`.
+    pub horizontal_threshold: usize,
+    pub slow_vertical_threshold: usize,
+    pub slow_horizontal_threshold: usize,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        Self {
+            window_seconds: 60,
+            slow_window_seconds: 3600,
+            vertical_threshold: 20,
+            horizontal_threshold: 15,
+            slow_vertical_threshold: 50,
+            slow_horizontal_threshold: 40,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScanKind {
+    Vertical,
+    Horizontal,
+    SlowVertical,
+    SlowHorizontal,
+}
+
+impl ScanKind {
+    fn rule_id(self) -> &'static str {
+        match self {
+            ScanKind::Vertical => "builtin.scan.vertical",
+            ScanKind::Horizontal => "builtin.scan.horizontal",
+            ScanKind::SlowVertical => "builtin.scan.slow_vertical",
+            ScanKind::SlowHorizontal => "builtin.scan.slow_horizontal",
+        }
+    }
+}
+
+/// Distinct items seen for a single scan key, with the time each was last observed.
+struct Tracker<T> {
+    seen: HashMap<T, DateTime<Utc>>,
+    last_alert: Option<DateTime<Utc>>,
+    last_slow_alert: Option<DateTime<Utc>>,
+}
+
+impl<T: Eq + Hash + Clone + Ord> Tracker<T> {
+    fn new() -> Self {
+        Self {
+            seen: HashMap::new(),
+            last_alert: None,
+            last_slow_alert: None,
+        }
+    }
+
+    fn record(&mut self, item: T, now: DateTime<Utc>) {
+        self.seen.insert(item, now);
+    }
+
+    fn distinct_since(&self, since: DateTime<Utc>) -> Vec<T> {
+        let mut items: Vec<T> = self
+            .seen
+            .iter()
+            .filter(|(_, ts)| **ts >= since)
+            .map(|(item, _)| item.clone())
+            .collect();
+        items.sort();
+        items
+    }
+}
+
+/// Detects vertical port scans (many ports on one host) and horizontal host
+/// sweeps (one port across many hosts) over a fast and a slow window.
+pub struct ScanDetector {
+    config: ScanConfig,
+    vertical: HashMap<(String, String), Tracker<u16>>,
+    horizontal: HashMap<(String, u16), Tracker<String>>,
+    observations: u64,
+}
+
+impl Default for ScanDetector {
+    fn default() -> Self {
+        Self::new(ScanConfig::default())
+    }
+}
+
+impl ScanDetector {
+    pub fn new(config: ScanConfig) -> Self {
+        Self {
+            config,
+            vertical: HashMap::new(),
+            horizontal: HashMap::new(),
+            observations: 0,
+        }
+    }
+
+    fn window(&self) -> Duration {
+        Duration::seconds(self.config.window_seconds)
+    }
+
+    fn slow_window(&self) -> Duration {
+        Duration::seconds(
+            self.config
+                .slow_window_seconds
+                .max(self.config.window_seconds),
+        )
+    }
+
+    fn prune(&mut self, now: DateTime<Utc>) {
+        let horizon = self.slow_window();
+        self.vertical.retain(|_, t| {
+            t.seen.retain(|_, ts| now - *ts <= horizon);
+            !t.seen.is_empty()
+        });
+        self.horizontal.retain(|_, t| {
+            t.seen.retain(|_, ts| now - *ts <= horizon);
+            !t.seen.is_empty()
+        });
+    }
+}
+
+impl Detector for ScanDetector {
+    fn name(&self) -> &'static str {
+        "scan"
+    }
+
+    fn observe(&mut self, flow: &NormalizedFlow) -> Vec<Alert> {
+        if flow.direction == FlowDirection::Inbound || flow.dst_port == 0 {
+            return Vec::new();
+        }
+        let now = flow.window_start;
+        let window = self.window();
+        let slow_window = self.slow_window();
+        self.observations += 1;
+        if self.observations.is_multiple_of(1024) {
+            self.prune(now);
+        }
+
+        let mut alerts = Vec::new();
+
+        let vertical = self
+            .vertical
+            .entry((flow.src_ip.clone(), flow.dst_ip.clone()))
+            .or_insert_with(Tracker::new);
+        vertical.record(flow.dst_port, now);
+        if let Some((kind, ports)) = evaluate(
+            vertical,
+            now,
+            window,
+            slow_window,
+            (ScanKind::Vertical, self.config.vertical_threshold),
+            (ScanKind::SlowVertical, self.config.slow_vertical_threshold),
+        ) {
+            alerts.push(scan_alert(
+                kind,
+                flow,
+                format!(
+                    "{} scanned {} ports on {}",
+                    flow.src_ip,
+                    ports.len(),
+                    flow.dst_ip
+                ),
+                "ports",
+                &ports,
+            ));
+        }
+
+        let horizontal = self
+            .horizontal
+            .entry((flow.src_ip.clone(), flow.dst_port))
+            .or_insert_with(Tracker::new);
+        horizontal.record(flow.dst_ip.clone(), now);
+        if let Some((kind, hosts)) = evaluate(
+            horizontal,
+            now,
+            window,
+            slow_window,
+            (ScanKind::Horizontal, self.config.horizontal_threshold),
+            (
+                ScanKind::SlowHorizontal,
+                self.config.slow_horizontal_threshold,
+            ),
+        ) {
+            alerts.push(scan_alert(
+                kind,
+                flow,
+                format!(
+                    "{} swept {} hosts on port {}",
+                    flow.src_ip,
+                    hosts.len(),
+                    flow.dst_port
+                ),
+                "hosts",
+                &hosts,
+            ));
+        }
+
+        alerts
+    }
+}
+
+/// Checks the fast window first and falls back to the slow window; each window
+/// re-arms only after a full window has passed since its previous alert.
+fn evaluate<T: Eq + Hash + Clone + Ord>(
+    tracker: &mut Tracker<T>,
+    now: DateTime<Utc>,
+    window: Duration,
+    slow_window: Duration,
+    (kind, threshold): (ScanKind, usize),
+    (slow_kind, slow_threshold): (ScanKind, usize),
+) -> Option<(ScanKind, Vec<T>)> {
+    let armed = |last: Option<DateTime<Utc>>, span: Duration| match last {
+        Some(ts) => now - ts > span,
+        None => true,
+    };
+
+    if armed(tracker.last_alert, window) {
+        let items = tracker.distinct_since(now - window);
+        if items.len() >= threshold {
+            tracker.last_alert = Some(now);
+            tracker.last_slow_alert = Some(now);
+            return Some((kind, items));
+        }
+    }
+    if armed(tracker.last_slow_alert, slow_window) {
+        let items = tracker.distinct_since(now - slow_window);
+        if items.len() >= slow_threshold {
+            tracker.last_slow_alert = Some(now);
+            return Some((slow_kind, items));
+        }
+    }
+    None
+}
+
+fn scan_alert<T: Display>(
+    kind: ScanKind,
+    flow: &NormalizedFlow,
+    summary: String,
+    label: &str,
+    items: &[T],
+) -> Alert {
+    let listed = format_list(items);
+    Alert {
+        id: new_alert_id(),
+        ts: Utc::now(),
+        severity: Severity::Medium,
+        rule_id: kind.rule_id().into(),
+        summary,
+        flow_refs: flow.flow_id.into_iter().collect(),
+        process_ref: flow.process.clone(),
+        rationale: format!("Offending {label}: {listed}"),
+        suggested_action: Some("Identify the scanning process and isolate the source host".into()),
+        status: AlertStatus::New,
+        assignee: None,
+        notes: Vec::new(),
+        evidence: BTreeMap::from([(label.to_string(), listed)]),
+    }
+}
+
+fn format_list<T: Display>(items: &[T]) -> String {
+    let mut out = items
+        .iter()
+        .take(MAX_LISTED)
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if items.len() > MAX_LISTED {
+        out.push_str(&format!(" (+{} more)", items.len() - MAX_LISTED));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn flow(dst_ip: &str, dst_port: u16, offset: i64) -> NormalizedFlow {
+        let ts = Utc.timestamp_opt(1_700_000_000 + offset, 0).unwrap();
+        NormalizedFlow {
+            window_start: ts,
+            window_end: ts + Duration::seconds(60),
+            proto: "TCP".into(),
+            src_ip: "10.0.0.9".into(),
+            src_port: 40000,
+            dst_ip: dst_ip.into(),
+            dst_port,
+            direction: FlowDirection::Lateral,
+            bytes: 0,
+            packets: 1,
+            ..NormalizedFlow::default()
+        }
+    }
+
+    #[test]
+    fn vertical_and_horizontal_scans() {
+        let mut detector = ScanDetector::default();
+        let mut vertical = Vec::new();
+        for port in 1..=25 {
+            vertical.extend(detector.observe(&flow("10.0.0.2", port, 0)));
+        }
+        assert_eq!(vertical.len(), 1);
+        assert_eq!(vertical[0].rule_id, "builtin.scan.vertical");
+
+        let mut horizontal = Vec::new();
+        for host in 10..40 {
+            horizontal.extend(detector.observe(&flow(&format!("10.0.1.{host}"), 445, 1)));
+        }
+        assert_eq!(horizontal.len(), 1);
+        assert_eq!(horizontal[0].rule_id, "builtin.scan.horizontal");
+    }
+
+    #[test]
+    fn slow_vertical_scan() {
+        let mut detector = ScanDetector::default();
+        let mut alerts = Vec::new();
+        for port in 1..=60u16 {
+            alerts.extend(detector.observe(&flow("10.0.0.3", port, port as i64 * 30)));
+        }
+        assert!(alerts
+            .iter()
+            .any(|a| a.rule_id == "builtin.scan.slow_vertical"));
+        assert!(alerts.iter().all(|a| a.rule_id != "builtin.scan.vertical"));
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::VecDeque;
+
+use chrono::Timelike;
+use collector::FlowDirection;
+use normalizer::NormalizedFlow;
+
+use crate::baseline::BaselineMode;
+
+/// Read-only view of analyzer state handed to scorers alongside each flow.
+pub struct ScoringContext<'a> {
+    /// Recently ingested flows, oldest first, including the one being scored.
+    pub recent: &'a VecDeque<NormalizedFlow>,
+    pub baseline_mode: &'a BaselineMode,
+}
+
+/// Pluggable anomaly model. Scores are expected in `0.0..=1.0`; flows scoring at
+/// or above [`AnomalyScorer::threshold`] raise a `ml.<name>` alert.
+pub trait AnomalyScorer: Send {
+    fn name(&self) -> &str;
+    fn score(&self, flow: &NormalizedFlow, context: &ScoringContext<'_>) -> f32;
+
+    fn threshold(&self) -> f32 {
+        0.9
+    }
+}
+
+/// Number of values produced by [`flow_features`].
+pub const FEATURE_COUNT: usize = 9;
+
+/// Fixed numeric feature vector used by model-backed scorers, so trained models
+/// share one input layout regardless of the runtime that evaluates them.
+pub fn flow_features(flow: &NormalizedFlow) -> [f32; FEATURE_COUNT] {
+    let direction = |d: FlowDirection| if flow.direction == d { 1.0 } else { 0.0 };
+    [
+        (flow.bytes as f32).ln_1p(),
+        (flow.packets as f32).ln_1p(),
+        flow.src_port as f32 / u16::MAX as f32,
+        flow.dst_port as f32 / u16::MAX as f32,
+        direction(FlowDirection::Inbound),
+        direction(FlowDirection::Outbound),
+        direction(FlowDirection::Lateral),
+        if flow.proto.eq_ignore_ascii_case("tcp") {
+            1.0
+        } else {
+            0.0
+        },
+        flow.window_start.hour() as f32 / 23.0,
+    ]
+}
+
+#[cfg(feature = "onnx")]
+pub use onnx::OnnxScorer;
+
+#[cfg(feature = "onnx")]
+mod onnx {
+    use std::path::Path;
+
+    use anyhow::{anyhow, Result};
+    use normalizer::NormalizedFlow;
+    use ort::{session::Session, value::Tensor};
+    use parking_lot::Mutex;
+
+    use super::{flow_features, AnomalyScorer, ScoringContext, FEATURE_COUNT};
+
+    /// ONNX Runtime scorer. The model takes a `[1, FEATURE_COUNT]` f32 tensor
+    /// built by [`flow_features`] and returns a single anomaly score.
+    pub struct OnnxScorer {
+        name: String,
+        threshold: f32,
+        session: Mutex<Session>,
+    }
+
+    impl OnnxScorer {
+        pub fn load<P: AsRef<Path>>(name: &str, model: P, threshold: f32) -> Result<Self> {
+            let session = Session::builder()
+                .and_then(|builder| builder.commit_from_file(model.as_ref()))
+                .map_err(|err| anyhow!("loading ONNX model {}: {err}", model.as_ref().display()))?;
+            Ok(Self {
+                name: name.to_string(),
+                threshold,
+                session: Mutex::new(session),
+            })
+        }
+
+        fn run(&self, flow: &NormalizedFlow) -> Result<f32> {
+            let input =
+                Tensor::from_array(([1usize, FEATURE_COUNT], flow_features(flow).to_vec()))?;
+            let mut session = self.session.lock();
+            let outputs = session.run(ort::inputs![input])?;
+            let (_, scores) = outputs[0].try_extract_tensor::<f32>()?;
+            scores
+                .first()
+                .copied()
+                .ok_or_else(|| anyhow!("model returned an empty tensor"))
+        }
+    }
+
+    impl AnomalyScorer for OnnxScorer {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn score(&self, flow: &NormalizedFlow, _context: &ScoringContext<'_>) -> f32 {
+            match self.run(flow) {
+                Ok(score) => score,
+                Err(err) => {
+                    tracing::warn!(scorer = %self.name, %err, "onnx scoring failed");
+                    0.0
+                }
+            }
+        }
+
+        fn threshold(&self) -> f32 {
+            self.threshold
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::Analyzer;
+
+    /// Gives every flow the same score.
+    struct Fixed(&'static str, f32);
+
+    impl AnomalyScorer for Fixed {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn score(&self, _: &NormalizedFlow, _: &ScoringContext<'_>) -> f32 {
+            self.1
+        }
+    }
+
+    #[test]
+    fn alerts_above_the_threshold_and_keeps_the_highest_score() {
+        let mut analyzer = Analyzer::new(Duration::minutes(5), Vec::new());
+        let flow = NormalizedFlow {
+            flow_id: Some(7),
+            process: Some("agent".into()),
+            ..NormalizedFlow::default()
+        };
+        assert_eq!(analyzer.evaluate_scorers(&flow).1, None);
+
+        analyzer.add_scorer(Box::new(Fixed("quiet", 0.3)));
+        analyzer.add_scorer(Box::new(Fixed("beacon", 0.95)));
+        let (alerts, score) = analyzer.evaluate_scorers(&flow);
+        assert_eq!(score, Some(0.95));
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].rule_id, "ml.beacon");
+        assert_eq!(alerts[0].flow_refs, [7]);
+        assert_eq!(alerts[0].process_ref.as_deref(), Some("agent"));
+        assert_eq!(alerts[0].rationale, "score 0.950 ≥ threshold 0.900");
+    }
+}
@@ -0,0 +1,515 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use chrono::{DateTime, Duration, Utc};
+use normalizer::NormalizedFlow;
+use serde::{Deserialize, Serialize};
+
+use crate::{is_lan, new_alert_id, Alert, AlertStatus, Detector, Severity};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionConfig {
+    /// Age after which a session that never reached ESTABLISHED is stuck.
+    pub half_open_seconds: i64,
+    /// Stuck half-open sessions from one source before alerting.
+    pub half_open_min: usize,
+    pub rst_window_seconds: i64,
+    /// Resets involving one server inside the window that count as a storm.
+    pub rst_threshold: usize,
+    /// Duration of an established session to a non-LAN host worth reporting.
+    pub long_lived_seconds: i64,
+    /// Services expected to only receive data (syslog and the like).
+    pub listen_only_ports: Vec<u16>,
+    /// Server-to-client bytes tolerated on a listen-only service.
+    pub listen_only_max_response_bytes: u64,
+    /// Sessions without activity for this long are forgotten.
+    pub idle_seconds: i64,
+    pub max_sessions: usize,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            half_open_seconds: 30,
+            half_open_min: 5,
+            rst_window_seconds: 60,
+            rst_threshold: 100,
+            long_lived_seconds: 8 * 3600,
+            listen_only_ports: vec![514, 601, 6514],
+            listen_only_max_response_bytes: 4096,
+            idle_seconds: 3600,
+            max_sessions: 65_536,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TcpState {
+    SynSent,
+    SynReceived,
+    Established,
+    Closing,
+    Closed,
+    Reset,
+}
+
+impl TcpState {
+    /// Maps the state strings reported by the collectors; LISTEN and unknown
+    /// values yield `None`.
+    pub fn parse(state: &str) -> Option<Self> {
+        let normalized = state.to_ascii_uppercase().replace('-', "_");
+        Some(match normalized.as_str() {
+            "SYN_SENT" => TcpState::SynSent,
+            "SYN_RECV" | "SYN_RECEIVED" => TcpState::SynReceived,
+            "ESTABLISHED" => TcpState::Established,
+            "FIN_WAIT1" | "FIN_WAIT_1" | "FIN_WAIT2" | "FIN_WAIT_2" | "CLOSE_WAIT" | "LAST_ACK"
+            | "CLOSING" | "TIME_WAIT" => TcpState::Closing,
+            "CLOSED" | "CLOSE" => TcpState::Closed,
+            "RST" | "RESET" | "REFUSED" => TcpState::Reset,
+            _ => return None,
+        })
+    }
+
+    fn is_half_open(self) -> bool {
+        matches!(self, TcpState::SynSent | TcpState::SynReceived)
+    }
+
+    fn is_terminal(self) -> bool {
+        matches!(self, TcpState::Closed | TcpState::Reset)
+    }
+}
+
+type Endpoint = (String, u16);
+
+#[derive(Debug, Clone)]
+struct Session {
+    client: Endpoint,
+    server: Endpoint,
+    process: Option<String>,
+    opened: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    transitions: Vec<TcpState>,
+    /// Stored flow rows that make up the session, oldest dropped first.
+    flow_ids: Vec<i64>,
+    bytes_to_server: u64,
+    bytes_to_client: u64,
+    reported_half_open: bool,
+    reported_long_lived: bool,
+    reported_listen_only: bool,
+}
+
+impl Session {
+    fn state(&self) -> TcpState {
+        *self.transitions.last().expect("session has a state")
+    }
+
+    fn established(&self) -> bool {
+        self.transitions.contains(&TcpState::Established)
+    }
+
+    fn describe(&self) -> String {
+        self.transitions
+            .iter()
+            .map(|state| format!("{state:?}"))
+            .collect::<Vec<_>>()
+            .join(" -> ")
+    }
+
+    fn flow_ref(&self) -> String {
+        format!(
+            "{}:{}->{}:{}",
+            self.client.0, self.client.1, self.server.0, self.server.1
+        )
+    }
+}
+
+/// Follows TCP state transitions per session (keyed on the unordered
+/// endpoint pair) and flags half-open pile-ups, RST storms, long-lived
+/// sessions to external hosts and replies from listen-only services.
+pub struct SessionTracker {
+    config: SessionConfig,
+    sessions: HashMap<(Endpoint, Endpoint), Session>,
+    resets: HashMap<String, VecDeque<DateTime<Utc>>>,
+    rst_alerted: HashMap<String, DateTime<Utc>>,
+    last_sweep: Option<DateTime<Utc>>,
+}
+
+impl Default for SessionTracker {
+    fn default() -> Self {
+        Self::new(SessionConfig::default())
+    }
+}
+
+impl SessionTracker {
+    pub fn new(config: SessionConfig) -> Self {
+        Self {
+            config,
+            sessions: HashMap::new(),
+            resets: HashMap::new(),
+            rst_alerted: HashMap::new(),
+            last_sweep: None,
+        }
+    }
+
+    pub fn session_count(&self) -> usize {
+        self.sessions.len()
+    }
+
+    fn track(&mut self, flow: &NormalizedFlow, state: TcpState) -> Option<(Endpoint, Endpoint)> {
+        let src = (flow.src_ip.clone(), flow.src_port);
+        let dst = (flow.dst_ip.clone(), flow.dst_port);
+        let key = if src <= dst {
+            (src.clone(), dst.clone())
+        } else {
+            (dst.clone(), src.clone())
+        };
+        if !self.sessions.contains_key(&key) && self.sessions.len() >= self.config.max_sessions {
+            return None;
+        }
+        let now = flow.window_start;
+        let session = self.sessions.entry(key.clone()).or_insert_with(|| Session {
+            // The first record of a session normally comes from the initiator.
+            client: src.clone(),
+            server: dst,
+            process: flow.process.clone(),
+            opened: now,
+            last_seen: now,
+            transitions: vec![state],
+            flow_ids: Vec::new(),
+            bytes_to_server: 0,
+            bytes_to_client: 0,
+            reported_half_open: false,
+            reported_long_lived: false,
+            reported_listen_only: false,
+        });
+        session.last_seen = session.last_seen.max(flow.window_end.max(now));
+        if session.state() != state {
+            if session.transitions.len() >= 16 {
+                session.transitions.remove(1);
+            }
+            session.transitions.push(state);
+        }
+        if let Some(flow_id) = flow.flow_id {
+            if session.flow_ids.len() >= 16 {
+                session.flow_ids.remove(0);
+            }
+            session.flow_ids.push(flow_id);
+        }
+        if src == session.client {
+            session.bytes_to_server += flow.bytes;
+        } else {
+            session.bytes_to_client += flow.bytes;
+        }
+        if session.process.is_none() {
+            session.process = flow.process.clone();
+        }
+        Some(key)
+    }
+
+    fn check_session(&mut self, key: &(Endpoint, Endpoint)) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        let Some(session) = self.sessions.get_mut(key) else {
+            return alerts;
+        };
+        let age = session.last_seen - session.opened;
+        if !session.reported_long_lived
+            && session.established()
+            && !session.state().is_terminal()
+            && !is_lan(&session.server.0)
+            && age >= Duration::seconds(self.config.long_lived_seconds)
+        {
+            session.reported_long_lived = true;
+            alerts.push(session_alert(
+                session,
+                "builtin.tcp.long_lived",
+                Severity::Low,
+                format!(
+                    "Long-lived session to external host {}:{}",
+                    session.server.0, session.server.1
+                ),
+                format!(
+                    "established for {}h, {} bytes out / {} bytes in; states: {}",
+                    age.num_hours(),
+                    session.bytes_to_server,
+                    session.bytes_to_client,
+                    session.describe()
+                ),
+                "Check for tunnels or persistent C2 channels",
+            ));
+        }
+        if !session.reported_listen_only
+            && self.config.listen_only_ports.contains(&session.server.1)
+            && session.bytes_to_server > 0
+            && session.bytes_to_client > self.config.listen_only_max_response_bytes
+        {
+            session.reported_listen_only = true;
+            alerts.push(session_alert(
+                session,
+                "builtin.tcp.listen_only_data",
+                Severity::Medium,
+                format!(
+                    "Bidirectional traffic on listen-only service {}:{}",
+                    session.server.0, session.server.1
+                ),
+                format!(
+                    "server sent {} bytes (limit {}), client sent {}; states: {}",
+                    session.bytes_to_client,
+                    self.config.listen_only_max_response_bytes,
+                    session.bytes_to_server,
+                    session.describe()
+                ),
+                "Verify the service has not been replaced or used as a covert channel",
+            ));
+        }
+        alerts
+    }
+
+    fn record_reset(&mut self, server: &str, now: DateTime<Utc>) -> Option<Alert> {
+        let window = Duration::seconds(self.config.rst_window_seconds);
+        let resets = self.resets.entry(server.to_string()).or_default();
+        resets.push_back(now);
+        while resets.front().map(|ts| now - *ts > window).unwrap_or(false) {
+            resets.pop_front();
+        }
+        let count = resets.len();
+        let rearmed = self
+            .rst_alerted
+            .get(server)
+            .map(|ts| now - *ts > window)
+            .unwrap_or(true);
+        if count < self.config.rst_threshold || !rearmed {
+            return None;
+        }
+        self.rst_alerted.insert(server.to_string(), now);
+        Some(Alert {
+            id: new_alert_id(),
+            ts: Utc::now(),
+            severity: Severity::Medium,
+            rule_id: "builtin.tcp.rst_storm".into(),
+            summary: format!("RST storm involving {server}"),
+            flow_refs: Vec::new(),
+            process_ref: None,
+            rationale: format!(
+                "{count} reset sessions within {}s (threshold {})",
+                self.config.rst_window_seconds, self.config.rst_threshold
+            ),
+            suggested_action: Some(
+                "Look for scanning, a crashing service or injected resets".into(),
+            ),
+            status: AlertStatus::New,
+            assignee: None,
+            notes: Vec::new(),
+            evidence: BTreeMap::new(),
+        })
+    }
+
+    /// Reports sources with stuck half-open sessions and forgets closed or
+    /// idle sessions.
+    fn sweep(&mut self, now: DateTime<Utc>) -> Vec<Alert> {
+        let half_open = Duration::seconds(self.config.half_open_seconds);
+        let mut stuck: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let mut stuck_flows: BTreeMap<String, Vec<i64>> = BTreeMap::new();
+        for session in self.sessions.values() {
+            if !session.reported_half_open
+                && session.state().is_half_open()
+                && !session.established()
+                && now - session.opened > half_open
+            {
+                stuck
+                    .entry(session.client.0.clone())
+                    .or_default()
+                    .push(format!("{}:{}", session.server.0, session.server.1));
+                stuck_flows
+                    .entry(session.client.0.clone())
+                    .or_default()
+                    .extend(session.flow_ids.iter().copied());
+            }
+        }
+        let mut alerts = Vec::new();
+        for (client, mut targets) in stuck {
+            if targets.len() < self.config.half_open_min {
+                continue;
+            }
+            for session in self.sessions.values_mut() {
+                if session.client.0 == client && session.state().is_half_open() {
+                    session.reported_half_open = true;
+                }
+            }
+            targets.sort();
+            let count = targets.len();
+            alerts.push(Alert {
+                id: new_alert_id(),
+                ts: Utc::now(),
+                severity: Severity::Medium,
+                rule_id: "builtin.tcp.half_open".into(),
+                summary: format!("{count} half-open TCP sessions from {client}"),
+                flow_refs: stuck_flows.remove(&client).unwrap_or_default(),
+                process_ref: None,
+                rationale: format!(
+                    "{count} sessions stuck before ESTABLISHED for over {}s; targets: {}",
+                    self.config.half_open_seconds,
+                    targets
+                        .iter()
+                        .take(10)
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                suggested_action: Some(
+                    "Check for SYN scanning or an unreachable service being retried".into(),
+                ),
+                status: AlertStatus::New,
+                assignee: None,
+                notes: Vec::new(),
+                evidence: BTreeMap::new(),
+            });
+        }
+
+        let idle = Duration::seconds(self.config.idle_seconds);
+        self.sessions
+            .retain(|_, session| !session.state().is_terminal() && now - session.last_seen <= idle);
+        let window = Duration::seconds(self.config.rst_window_seconds);
+        self.resets
+            .retain(|_, resets| resets.back().map(|ts| now - *ts <= window).unwrap_or(false));
+        self.rst_alerted.retain(|_, ts| now - *ts <= window);
+        alerts
+    }
+}
+
+fn session_alert(
+    session: &Session,
+    rule_id: &str,
+    severity: Severity,
+    summary: String,
+    rationale: String,
+    suggested_action: &str,
+) -> Alert {
+    Alert {
+        id: new_alert_id(),
+        ts: Utc::now(),
+        severity,
+        rule_id: rule_id.into(),
+        summary,
+        flow_refs: session.flow_ids.clone(),
+        process_ref: session.process.clone(),
+        rationale,
+        suggested_action: Some(suggested_action.into()),
+        status: AlertStatus::New,
+        assignee: None,
+        notes: Vec::new(),
+        evidence: BTreeMap::from([("session".to_string(), session.flow_ref())]),
+    }
+}
+
+impl Detector for SessionTracker {
+    fn name(&self) -> &'static str {
+        "tcp-sessions"
+    }
+
+    fn observe(&mut self, flow: &NormalizedFlow) -> Vec<Alert> {
+        if !flow.proto.eq_ignore_ascii_case("tcp") {
+            return Vec::new();
+        }
+        let Some(state) = flow.state.as_deref().and_then(TcpState::parse) else {
+            return Vec::new();
+        };
+        let now = flow.window_start;
+        let mut alerts = Vec::new();
+        if let Some(key) = self.track(flow, state) {
+            alerts.extend(self.check_session(&key));
+            if state == TcpState::Reset {
+                let server = self.sessions[&key].server.0.clone();
+                alerts.extend(self.record_reset(&server, now));
+            }
+        }
+        let due = self
+            .last_sweep
+            .map(|ts| now - ts >= Duration::seconds(self.config.half_open_seconds.max(1)))
+            .unwrap_or(true);
+        if due {
+            self.last_sweep = Some(now);
+            alerts.extend(self.sweep(now));
+        }
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn segment(
+        src: (&str, u16),
+        dst: (&str, u16),
+        state: &str,
+        bytes: u64,
+        offset: i64,
+    ) -> NormalizedFlow {
+        let ts = Utc.timestamp_opt(1_700_000_000 + offset, 0).unwrap();
+        NormalizedFlow {
+            window_start: ts,
+            window_end: ts,
+            proto: "TCP".into(),
+            src_ip: src.0.into(),
+            src_port: src.1,
+            dst_ip: dst.0.into(),
+            dst_port: dst.1,
+            bytes,
+            state: Some(state.into()),
+            ..NormalizedFlow::default()
+        }
+    }
+
+    #[test]
+    fn flags_session_anomalies() {
+        let mut tracker = SessionTracker::default();
+        let mut alerts = Vec::new();
+
+        // Half-open pile-up from one host.
+        for port in 0..6 {
+            alerts.extend(tracker.observe(&segment(
+                ("192.168.1.50", 40000 + port),
+                ("192.168.1.1", 8000 + port),
+                "SYN_SENT",
+                60,
+                0,
+            )));
+        }
+        // Syslog server answering with data.
+        let client = ("192.168.1.7", 51000);
+        let syslog = ("192.168.1.2", 514);
+        alerts.extend(tracker.observe(&segment(client, syslog, "ESTABLISHED", 900, 1)));
+        alerts.extend(tracker.observe(&segment(syslog, client, "ESTABLISHED", 50_000, 2)));
+        // Session to an external host kept open for nine hours.
+        let laptop = ("192.168.1.9", 52000);
+        let remote = ("203.0.113.80", 443);
+        alerts.extend(tracker.observe(&segment(laptop, remote, "ESTABLISHED", 100, 3)));
+        alerts.extend(tracker.observe(&segment(laptop, remote, "ESTABLISHED", 100, 31)));
+        alerts.extend(tracker.observe(&segment(laptop, remote, "ESTABLISHED", 100, 9 * 3600)));
+
+        let rules: Vec<&str> = alerts.iter().map(|a| a.rule_id.as_str()).collect();
+        assert!(rules.contains(&"builtin.tcp.half_open"));
+        assert!(rules.contains(&"builtin.tcp.listen_only_data"));
+        assert!(rules.contains(&"builtin.tcp.long_lived"));
+
+        // RST storm against one server.
+        let mut storm = Vec::new();
+        for port in 0..150u16 {
+            let offset = 9 * 3600 + 1 + (port as i64 % 30);
+            storm.extend(tracker.observe(&segment(
+                ("198.51.100.4", 30000 + port),
+                ("192.168.1.3", 80),
+                "RST",
+                0,
+                offset,
+            )));
+        }
+        assert_eq!(
+            storm
+                .iter()
+                .filter(|a| a.rule_id == "builtin.tcp.rst_storm")
+                .count(),
+            1
+        );
+    }
+}
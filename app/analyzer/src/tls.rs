@@ -0,0 +1,421 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use chrono::{DateTime, Duration, Utc};
+use normalizer::NormalizedFlow;
+use serde::{Deserialize, Serialize};
+
+use crate::{new_alert_id, Alert, AlertStatus, Detector, Severity};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// How long a passive DNS answer is trusted for the SNI/IP check.
+    pub dns_max_age_seconds: i64,
+    /// ALPN values considered normal on 443.
+    pub common_alpn: Vec<String>,
+    /// Other ALPN values are rare until seen this many times.
+    pub rare_alpn_max_count: u64,
+    /// Ports where TLS is expected; TLS elsewhere from an unsigned process alerts.
+    pub standard_tls_ports: Vec<u16>,
+    /// Upper bound on tracked passive DNS names.
+    pub max_dns_names: usize,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            dns_max_age_seconds: 3600,
+            common_alpn: vec!["h2".into(), "http/1.1".into(), "h3".into()],
+            rare_alpn_max_count: 5,
+            standard_tls_ports: vec![
+                443, 465, 563, 636, 853, 989, 990, 992, 993, 995, 5061, 5223, 6514, 8443,
+            ],
+            max_dns_names: 50_000,
+        }
+    }
+}
+
+/// Name-to-address resolutions learned from DNS answers seen on the wire.
+#[derive(Debug, Clone, Default)]
+pub struct PassiveDns {
+    by_name: HashMap<String, HashMap<String, DateTime<Utc>>>,
+    by_ip: HashMap<String, HashSet<String>>,
+}
+
+impl PassiveDns {
+    pub fn record(&mut self, name: &str, answers: &[String], ts: DateTime<Utc>) {
+        let name = normalize_name(name);
+        let entry = self.by_name.entry(name.clone()).or_default();
+        for ip in answers {
+            entry.insert(ip.clone(), ts);
+            self.by_ip
+                .entry(ip.clone())
+                .or_default()
+                .insert(name.clone());
+        }
+    }
+
+    /// Addresses `name` resolved to within `max_age` of `now`.
+    pub fn addresses(&self, name: &str, now: DateTime<Utc>, max_age: Duration) -> Vec<String> {
+        let mut ips: Vec<String> = self
+            .by_name
+            .get(&normalize_name(name))
+            .map(|ips| {
+                ips.iter()
+                    .filter(|(_, seen)| now - **seen <= max_age)
+                    .map(|(ip, _)| ip.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+        ips.sort();
+        ips
+    }
+
+    pub fn names(&self, ip: &str) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .by_ip
+            .get(ip)
+            .map(|names| names.iter().cloned().collect())
+            .unwrap_or_default();
+        names.sort();
+        names
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    fn prune(&mut self, now: DateTime<Utc>, max_age: Duration) {
+        self.by_name.retain(|_, ips| {
+            ips.retain(|_, seen| now - *seen <= max_age);
+            !ips.is_empty()
+        });
+        let by_name = &self.by_name;
+        self.by_ip.retain(|ip, names| {
+            names.retain(|name| by_name.get(name).is_some_and(|ips| ips.contains_key(ip)));
+            !names.is_empty()
+        });
+    }
+}
+
+fn normalize_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Last two labels, a cheap stand-in for the registrable domain.
+fn base_domain(name: &str) -> String {
+    let labels: Vec<&str> = name.rsplitn(3, '.').collect();
+    match labels.as_slice() {
+        [tld, domain, ..] => format!("{domain}.{tld}"),
+        _ => name.to_string(),
+    }
+}
+
+fn is_tls(flow: &NormalizedFlow) -> bool {
+    flow.sni.is_some() || flow.ja3.is_some() || flow.ja4.is_some() || flow.alpn.is_some()
+}
+
+/// Flags TLS sessions whose metadata disagrees with the rest of the network:
+/// SNI not matching what DNS returned, missing or rare ALPN on 443, and TLS on
+/// non-standard ports from unsigned processes.
+pub struct TlsAnomalyDetector {
+    config: TlsConfig,
+    dns: PassiveDns,
+    alpn_counts: HashMap<String, u64>,
+    reported: HashSet<String>,
+    last_prune: Option<DateTime<Utc>>,
+}
+
+impl Default for TlsAnomalyDetector {
+    fn default() -> Self {
+        Self::new(TlsConfig::default())
+    }
+}
+
+impl TlsAnomalyDetector {
+    pub fn new(config: TlsConfig) -> Self {
+        Self {
+            config,
+            dns: PassiveDns::default(),
+            alpn_counts: HashMap::new(),
+            reported: HashSet::new(),
+            last_prune: None,
+        }
+    }
+
+    pub fn passive_dns(&self) -> &PassiveDns {
+        &self.dns
+    }
+
+    /// True the first time a finding is seen, so repeated flows of the same
+    /// session shape don't re-alert.
+    fn first_report(&mut self, key: String) -> bool {
+        if self.reported.len() >= 100_000 {
+            self.reported.clear();
+        }
+        self.reported.insert(key)
+    }
+
+    fn sni_mismatch(&mut self, flow: &NormalizedFlow) -> Option<Alert> {
+        let sni = normalize_name(flow.sni.as_deref().filter(|sni| !sni.is_empty())?);
+        let max_age = Duration::seconds(self.config.dns_max_age_seconds);
+        let known = self.dns.addresses(&sni, flow.window_start, max_age);
+        if known.is_empty() || known.contains(&flow.dst_ip) {
+            return None;
+        }
+        let ip_names = self.dns.names(&flow.dst_ip);
+        let base = base_domain(&sni);
+        if ip_names.iter().any(|name| base_domain(name) == base) {
+            return None;
+        }
+        if !self.first_report(format!("sni|{sni}|{}", flow.dst_ip)) {
+            return None;
+        }
+        let mut evidence = BTreeMap::new();
+        evidence.insert("sni".into(), sni.clone());
+        evidence.insert("dst_ip".into(), flow.dst_ip.clone());
+        evidence.insert("dns_addresses".into(), known.join(","));
+        if !ip_names.is_empty() {
+            evidence.insert("dst_ip_names".into(), ip_names.join(","));
+        }
+        Some(tls_alert(
+            flow,
+            "builtin.tls.sni_mismatch",
+            format!("TLS SNI {sni} does not match destination {}", flow.dst_ip),
+            format!(
+                "{sni} resolved to {} in the last {}s, but the session went to {}",
+                known.join(", "),
+                self.config.dns_max_age_seconds,
+                flow.dst_ip
+            ),
+            "Check for domain fronting or a hard-coded C2 address",
+            evidence,
+        ))
+    }
+
+    fn alpn_anomaly(&mut self, flow: &NormalizedFlow) -> Option<Alert> {
+        if flow.dst_port != 443 || !is_tls(flow) {
+            return None;
+        }
+        let process = flow.process.clone().unwrap_or_else(|| "unknown".into());
+        let (kind, alpn) = match flow.alpn.as_deref().filter(|alpn| !alpn.is_empty()) {
+            None => ("missing", String::new()),
+            Some(alpn) => {
+                let alpn = alpn.to_ascii_lowercase();
+                let count = self.alpn_counts.entry(alpn.clone()).or_default();
+                *count += 1;
+                if self.config.common_alpn.contains(&alpn)
+                    || *count > self.config.rare_alpn_max_count
+                {
+                    return None;
+                }
+                ("rare", alpn)
+            }
+        };
+        if !self.first_report(format!("alpn|{kind}|{alpn}|{process}")) {
+            return None;
+        }
+        let mut evidence = BTreeMap::new();
+        evidence.insert(
+            "alpn".into(),
+            if alpn.is_empty() {
+                "<none>".into()
+            } else {
+                alpn.clone()
+            },
+        );
+        evidence.insert("dst_port".into(), flow.dst_port.to_string());
+        if let Some(sni) = &flow.sni {
+            evidence.insert("sni".into(), sni.clone());
+        }
+        if let Some(ja3) = &flow.ja3 {
+            evidence.insert("ja3".into(), ja3.clone());
+        }
+        let (summary, rationale) = if kind == "missing" {
+            (
+                format!("TLS on 443 without ALPN from {process}"),
+                "Client hello carried no ALPN extension; browsers and common libraries send one"
+                    .to_string(),
+            )
+        } else {
+            (
+                format!("Rare ALPN \"{alpn}\" on 443 from {process}"),
+                format!(
+                    "ALPN {alpn} seen {} time(s) on this network",
+                    self.alpn_counts.get(&alpn).copied().unwrap_or(0)
+                ),
+            )
+        };
+        Some(tls_alert(
+            flow,
+            "builtin.tls.alpn",
+            summary,
+            rationale,
+            "Identify the client; custom TLS stacks are typical of implants and tunnels",
+            evidence,
+        ))
+    }
+
+    fn nonstandard_port(&mut self, flow: &NormalizedFlow) -> Option<Alert> {
+        if flow.process_signed != Some(false)
+            || !is_tls(flow)
+            || self.config.standard_tls_ports.contains(&flow.dst_port)
+        {
+            return None;
+        }
+        let process = flow.process.clone().unwrap_or_else(|| "unknown".into());
+        if !self.first_report(format!("port|{process}|{}:{}", flow.dst_ip, flow.dst_port)) {
+            return None;
+        }
+        let mut evidence = BTreeMap::new();
+        evidence.insert("dst_port".into(), flow.dst_port.to_string());
+        evidence.insert("process_signed".into(), "false".into());
+        if let Some(hash) = &flow.process_hash {
+            evidence.insert("process_hash".into(), hash.clone());
+        }
+        if let Some(sni) = &flow.sni {
+            evidence.insert("sni".into(), sni.clone());
+        }
+        Some(tls_alert(
+            flow,
+            "builtin.tls.nonstandard_port",
+            format!(
+                "Unsigned process {process} speaks TLS on port {}",
+                flow.dst_port
+            ),
+            format!(
+                "TLS handshake to {}:{} from an unsigned binary",
+                flow.dst_ip, flow.dst_port
+            ),
+            "Verify the binary and what service it is reaching",
+            evidence,
+        ))
+    }
+}
+
+fn tls_alert(
+    flow: &NormalizedFlow,
+    rule_id: &str,
+    summary: String,
+    rationale: String,
+    suggested_action: &str,
+    evidence: BTreeMap<String, String>,
+) -> Alert {
+    Alert {
+        id: new_alert_id(),
+        ts: Utc::now(),
+        severity: Severity::Medium,
+        rule_id: rule_id.into(),
+        summary,
+        flow_refs: flow.flow_id.into_iter().collect(),
+        process_ref: flow.process.clone(),
+        rationale,
+        suggested_action: Some(suggested_action.into()),
+        status: AlertStatus::New,
+        assignee: None,
+        notes: Vec::new(),
+        evidence,
+    }
+}
+
+impl Detector for TlsAnomalyDetector {
+    fn name(&self) -> &'static str {
+        "tls-anomalies"
+    }
+
+    fn observe(&mut self, flow: &NormalizedFlow) -> Vec<Alert> {
+        let now = flow.window_start;
+        if let Some(qname) = flow.dns_qname.as_deref() {
+            if !flow.dns_answers.is_empty() {
+                self.dns.record(qname, &flow.dns_answers, now);
+            }
+        }
+        let max_age = Duration::seconds(self.config.dns_max_age_seconds);
+        let prune_due = self.last_prune.map(|ts| now - ts > max_age).unwrap_or(true);
+        if prune_due || self.dns.len() > self.config.max_dns_names {
+            self.dns.prune(now, max_age);
+            self.last_prune = Some(now);
+        }
+        if !is_tls(flow) {
+            return Vec::new();
+        }
+        let mut alerts = Vec::new();
+        alerts.extend(self.sni_mismatch(flow));
+        alerts.extend(self.alpn_anomaly(flow));
+        alerts.extend(self.nonstandard_port(flow));
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(offset: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_700_000_000 + offset, 0).unwrap()
+    }
+
+    #[test]
+    fn tls_findings() {
+        let mut detector = TlsAnomalyDetector::default();
+        let dns = NormalizedFlow {
+            window_start: at(0),
+            dns_qname: Some("updates.example.com.".into()),
+            dns_answers: vec!["93.184.216.34".into()],
+            ..NormalizedFlow::default()
+        };
+        assert!(detector.observe(&dns).is_empty());
+
+        let mismatched = NormalizedFlow {
+            window_start: at(10),
+            dst_ip: "198.51.100.66".into(),
+            dst_port: 443,
+            sni: Some("updates.example.com".into()),
+            alpn: Some("h2".into()),
+            process: Some("agent".into()),
+            ..NormalizedFlow::default()
+        };
+        let alerts = detector.observe(&mismatched);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].rule_id, "builtin.tls.sni_mismatch");
+        assert_eq!(alerts[0].severity, Severity::Medium);
+        assert_eq!(alerts[0].evidence["dns_addresses"], "93.184.216.34");
+
+        let matching = NormalizedFlow {
+            dst_ip: "93.184.216.34".into(),
+            ..mismatched.clone()
+        };
+        assert!(detector.observe(&matching).is_empty());
+
+        let implant = NormalizedFlow {
+            window_start: at(20),
+            dst_ip: "203.0.113.5".into(),
+            dst_port: 4444,
+            ja3: Some("e7d705a3286e19ea42f587b344ee6865".into()),
+            process: Some("svchost32".into()),
+            process_signed: Some(false),
+            ..NormalizedFlow::default()
+        };
+        let rules: Vec<String> = detector
+            .observe(&implant)
+            .into_iter()
+            .map(|a| a.rule_id)
+            .collect();
+        assert_eq!(rules, vec!["builtin.tls.nonstandard_port".to_string()]);
+
+        let no_alpn = NormalizedFlow {
+            dst_port: 443,
+            ..implant
+        };
+        let rules: Vec<String> = detector
+            .observe(&no_alpn)
+            .into_iter()
+            .map(|a| a.rule_id)
+            .collect();
+        assert_eq!(rules, vec!["builtin.tls.alpn".to_string()]);
+    }
+}
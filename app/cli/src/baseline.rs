@@ -0,0 +1,191 @@
+//! `nets-cli baseline`: the analyzer's learn → freeze lifecycle without the
+//! GUI. Commands are queued in storage and the running daemon applies them
+//! within seconds; with no daemon running they are applied to the stored
+//! baseline, which the daemon restores when it starts. `show` prints the
+//! learned listener and destination profiles as last saved (the daemon
+//! saves what it learns every minute).
+
+use std::{thread, time::Duration};
+
+use analyzer::{
+    baseline::{BaselineCommand, BaselineEngine, BaselineMode, BaselineStatus, VolumeStats},
+    listener::KnownListener,
+};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use storage::Storage;
+
+use crate::config::Config;
+
+/// How long a command may wait for the daemon to pick it up.
+const DAEMON_WAIT: Duration = Duration::from_secs(10);
+
+/// Where a lifecycle command ended up, for `--output json`.
+#[derive(Serialize)]
+struct Applied {
+    command: BaselineCommand,
+    /// By the running daemon, or to storage for the next daemon start.
+    by_daemon: bool,
+    status: BaselineStatus,
+}
+
+/// What `show` prints.
+#[derive(Serialize)]
+struct Profile<'a> {
+    status: BaselineStatus,
+    listeners: Vec<&'a KnownListener>,
+    processes: Vec<ProcessProfile<'a>>,
+}
+
+#[derive(Serialize)]
+struct ProcessProfile<'a> {
+    process: &'a str,
+    first_seen: DateTime<Utc>,
+    destinations: Vec<&'a str>,
+    /// Bytes per flow while learning.
+    volume: Option<&'a VolumeStats>,
+}
+
+pub fn apply(config: &Config, command: BaselineCommand, json: bool) -> Result<()> {
+    let storage = crate::open_storage(&config.storage)?;
+    let mut engine = stored(&storage)?;
+    // Refused here rather than in the daemon, where nobody would see why.
+    engine.apply(command)?;
+    storage.queue_baseline_command(command, Some(&crate::quarantine::operator()))?;
+    let by_daemon = crate::control::query(&config.daemon).is_ok();
+    if by_daemon {
+        wait_for_daemon(&storage)?;
+        engine = stored(&storage)?;
+    } else {
+        storage.take_baseline_command()?;
+        storage.save_baseline(engine.profile())?;
+    }
+    let applied = Applied {
+        command,
+        by_daemon,
+        status: engine.status(),
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&applied)?);
+        return Ok(());
+    }
+    print_status(&applied.status);
+    if !by_daemon {
+        println!("the daemon is not running; it picks the baseline up when it starts");
+    }
+    Ok(())
+}
+
+pub fn show(config: &Config, process: Option<&str>, json: bool) -> Result<()> {
+    let engine = stored(&crate::open_storage(&config.storage)?)?;
+    let learned = engine.profile();
+    let mut listeners: Vec<_> = learned.listeners.values().collect();
+    listeners.sort_by_key(|listener| (listener.port, &listener.proto, &listener.ip));
+    let mut processes: Vec<_> = learned
+        .destinations
+        .processes
+        .iter()
+        .filter(|(name, _)| process.is_none_or(|wanted| wanted == name.as_str()))
+        .map(|(name, known)| {
+            let mut destinations: Vec<_> = known.destinations.iter().map(String::as_str).collect();
+            destinations.sort_unstable();
+            ProcessProfile {
+                process: name,
+                first_seen: known.first_seen,
+                destinations,
+                volume: learned.volumes.get(name),
+            }
+        })
+        .collect();
+    processes.sort_by_key(|profile| profile.process);
+    let profile = Profile {
+        status: engine.status(),
+        listeners,
+        processes,
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&profile)?);
+        return Ok(());
+    }
+    print_status(&profile.status);
+    if profile.status.mode == BaselineMode::Idle {
+        return Ok(());
+    }
+    println!("\nlisteners ({})", profile.listeners.len());
+    for listener in &profile.listeners {
+        println!(
+            "  {:<4}  {:<24}  {:<20}  since {}",
+            listener.proto.to_ascii_uppercase(),
+            format!("{}:{}", listener.ip, listener.port),
+            listener.process.as_deref().unwrap_or("-"),
+            listener.first_seen.format("%Y-%m-%d %H:%M")
+        );
+    }
+    println!("\ndestinations by process ({})", profile.processes.len());
+    for process in &profile.processes {
+        let volume = process
+            .volume
+            .map(|stats| {
+                format!(
+                    ", {} flows of {:.0} bytes on average, at most {}",
+                    stats.count, stats.mean, stats.max
+                )
+            })
+            .unwrap_or_default();
+        println!(
+            "  {}  since {}{volume}",
+            process.process,
+            process.first_seen.format("%Y-%m-%d %H:%M")
+        );
+        for destination in &process.destinations {
+            println!("    {destination}");
+        }
+    }
+    Ok(())
+}
+
+/// The stored baseline; an idle, empty one if none was saved yet.
+fn stored(storage: &Storage) -> Result<BaselineEngine> {
+    Ok(BaselineEngine::from_profile(
+        storage.load_baseline()?.unwrap_or_default(),
+    ))
+}
+
+/// Waits until the daemon took the queued command.
+fn wait_for_daemon(storage: &Storage) -> Result<()> {
+    let step = Duration::from_millis(200);
+    for _ in 0..DAEMON_WAIT.as_millis() / step.as_millis() {
+        if storage.pending_baseline_command()?.is_none() {
+            return Ok(());
+        }
+        thread::sleep(step);
+    }
+    Err(anyhow!(
+        "the daemon did not pick up the command within {}s; it stays queued until it does",
+        DAEMON_WAIT.as_secs()
+    ))
+}
+
+fn print_status(status: &BaselineStatus) {
+    let time = |at: Option<DateTime<Utc>>| {
+        at.map(|at| at.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_else(|| "-".into())
+    };
+    let mode = match status.mode {
+        BaselineMode::Idle => "idle, nothing learned".to_string(),
+        BaselineMode::Learning => format!("learning since {}", time(status.started_at)),
+        BaselineMode::Enforcing => format!(
+            "enforcing since {} (learned from {})",
+            time(status.frozen_at),
+            time(status.started_at)
+        ),
+    };
+    println!("baseline: {mode}");
+    if status.mode != BaselineMode::Idle {
+        println!(
+            "  {} listeners, {} destinations of {} processes, {} volume profiles",
+            status.listeners, status.destinations, status.processes, status.volume_profiles
+        );
+    }
+}
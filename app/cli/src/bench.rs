@@ -0,0 +1,193 @@
+//! `nets-cli bench`: pushes synthetic flows at a fixed rate through the
+//! daemon's pipeline (normalizer, analyzer with the configured rules,
+//! storage writer) into a throwaway database and reports what kept up.
+//! Alerts are stored but never answered, so nothing is quarantined.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration as StdDuration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use chrono::{Duration, Utc};
+use collector::synthetic::SyntheticFlows;
+use serde::Serialize;
+use storage::{AsyncStorage, Storage, WriterConfig};
+
+use crate::{config::Config, daemon::Pipeline, top::human_bytes};
+
+/// Same depth as the daemon's queue, so drops mean the same thing.
+const FLOW_QUEUE: usize = 4096;
+
+pub struct Bench {
+    pub flows_per_sec: u64,
+    pub duration: Duration,
+    pub seed: u64,
+    pub json: bool,
+}
+
+/// What kept up, as `--output json` prints it.
+#[derive(Serialize)]
+struct Summary {
+    flows_per_sec: u64,
+    seed: u64,
+    /// Flows the generator offered, including the dropped ones.
+    generated: u64,
+    generated_secs: f64,
+    /// Offered while the pipeline queue was full.
+    dropped: u64,
+    processed: u64,
+    processed_secs: f64,
+    alerts: u64,
+    stored: u64,
+    stored_secs: f64,
+    database_bytes: u64,
+}
+
+/// Counts from the generator thread.
+struct Generated {
+    sent: u64,
+    dropped: u64,
+    elapsed: StdDuration,
+}
+
+pub fn run(config: &Config, bench: Bench) -> Result<()> {
+    if bench.flows_per_sec == 0 {
+        return Err(anyhow!("--flows-per-sec must be positive"));
+    }
+    let duration = bench
+        .duration
+        .to_std()
+        .ok()
+        .filter(|duration| !duration.is_zero())
+        .ok_or_else(|| anyhow!("--duration must be positive"))?;
+    let dir = std::env::temp_dir().join(format!("nets-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let result = tokio::runtime::Runtime::new()?.block_on(measure(config, &bench, duration, &dir));
+    let _ = std::fs::remove_dir_all(&dir);
+    result
+}
+
+async fn measure(
+    config: &Config,
+    bench: &Bench,
+    duration: StdDuration,
+    dir: &std::path::Path,
+) -> Result<()> {
+    // The configured backend and compression, but not the real database
+    // or its key.
+    let storage =
+        Storage::open_with_options(dir.join("bench.db"), &[0u8; 32], config.storage.options())?;
+    let writer = AsyncStorage::spawn(storage, WriterConfig::default())?;
+    let pipeline = Pipeline::new(&config.analyzer, writer.clone(), None)?;
+    if !bench.json {
+        println!(
+            "pushing {} flows/s for {}s through {} (seed {})",
+            bench.flows_per_sec,
+            duration.as_secs_f64(),
+            config.analyzer.rules_path.display(),
+            bench.seed
+        );
+    }
+
+    let (flows, queued) = mpsc::sync_channel(FLOW_QUEUE);
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+    let started = Instant::now();
+    let worker = thread::Builder::new()
+        .name("nets-pipeline".into())
+        .spawn(move || pipeline.run(queued, &stopped))?;
+    let (rate, seed) = (bench.flows_per_sec, bench.seed);
+    let generator = thread::Builder::new()
+        .name("nets-bench".into())
+        .spawn(move || {
+            let mut source = SyntheticFlows::new(seed);
+            let mut generated = Generated {
+                sent: 0,
+                dropped: 0,
+                elapsed: StdDuration::ZERO,
+            };
+            let mut total = 0u64;
+            loop {
+                let elapsed = started.elapsed();
+                if elapsed >= duration {
+                    generated.elapsed = elapsed;
+                    break;
+                }
+                // Catch up with the schedule, then yield for a moment.
+                let due = (elapsed.as_secs_f64() * rate as f64) as u64;
+                let now = Utc::now();
+                while total < due {
+                    total += 1;
+                    match flows.try_send(source.next_at(now)) {
+                        Ok(()) => generated.sent += 1,
+                        Err(_) => generated.dropped += 1,
+                    }
+                }
+                thread::sleep(StdDuration::from_millis(1));
+            }
+            generated
+        })?;
+
+    let generated = tokio::task::spawn_blocking(move || generator.join())
+        .await?
+        .map_err(|_| anyhow!("generator thread panicked"))?;
+    stop.store(true, Ordering::Release);
+    let pipeline = tokio::task::spawn_blocking(move || worker.join())
+        .await?
+        .map_err(|_| anyhow!("pipeline thread panicked"))?;
+    let processed_in = started.elapsed();
+    writer.flush().await?;
+    let stored_in = started.elapsed();
+    let metrics = writer.metrics().await?;
+
+    let offered = generated.sent + generated.dropped;
+    let summary = Summary {
+        flows_per_sec: bench.flows_per_sec,
+        seed: bench.seed,
+        generated: offered,
+        generated_secs: generated.elapsed.as_secs_f64(),
+        dropped: generated.dropped,
+        processed: pipeline.flows,
+        processed_secs: processed_in.as_secs_f64(),
+        alerts: pipeline.alerts,
+        stored: metrics.rows.get("flows").copied().unwrap_or_default(),
+        stored_secs: stored_in.as_secs_f64(),
+        database_bytes: metrics.database_bytes,
+    };
+    if bench.json {
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+        return Ok(());
+    }
+    let per_sec = |count: u64, secs: f64| count as f64 / secs;
+    println!(
+        "generated  {} flows in {:.1}s ({:.0}/s)",
+        summary.generated,
+        summary.generated_secs,
+        per_sec(summary.generated, summary.generated_secs)
+    );
+    println!(
+        "dropped    {} ({:.2}%) with the pipeline queue full",
+        summary.dropped,
+        summary.dropped as f64 * 100.0 / summary.generated.max(1) as f64
+    );
+    println!(
+        "pipeline   {} flows in {:.1}s ({:.0}/s), {} alerts",
+        summary.processed,
+        summary.processed_secs,
+        per_sec(summary.processed, summary.processed_secs),
+        summary.alerts
+    );
+    println!(
+        "storage    {} rows in {:.1}s ({:.0}/s), {} on disk",
+        summary.stored,
+        summary.stored_secs,
+        per_sec(summary.stored, summary.stored_secs),
+        human_bytes(summary.database_bytes)
+    );
+    Ok(())
+}
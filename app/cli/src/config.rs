@@ -0,0 +1,530 @@
+//! Typed `config/config.toml`, shared by every subcommand. Missing keys
+//! take their defaults; unknown keys and invalid values are rejected with
+//! the dotted key at fault and its line.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use analyzer::{overrides::SeverityOverrides, ratelimit::RateLimitConfig, Severity};
+use anyhow::{Context, Result};
+use policy::{
+    ApprovalConfig, GuardrailConfig, KillSwitchConfig, NotificationConfig, SinkholeConfig,
+    ThrottleConfig,
+};
+use serde::Deserialize;
+use storage::{
+    keys::{load_or_create_key, resolve_provider, KeyProvider, PassphraseKey, PASSPHRASE_ENV},
+    remote::ShipperConfig,
+    retention::RetentionConfig,
+    Backend, Compression, StorageOptions,
+};
+
+/// Read when `--config` is not given, if it exists.
+pub const DEFAULT_PATH: &str = "./config/config.toml";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub collector: CollectorSection,
+    pub storage: StorageSection,
+    pub export: ShipperConfig,
+    pub analyzer: AnalyzerSection,
+    pub policy: PolicySection,
+    pub ui: UiSection,
+    pub daemon: DaemonSection,
+}
+
+/// A config file that cannot be used. Returned inside `anyhow::Error`;
+/// downcast to get the key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    /// Dotted path, e.g. `analyzer.baseline_hours`; `None` when the file
+    /// is not valid TOML at all.
+    pub key: Option<String>,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.key, self.line) {
+            (Some(key), Some(line)) => write!(f, "`{key}` (line {line}): {}", self.message),
+            (Some(key), None) => write!(f, "`{key}`: {}", self.message),
+            (None, Some(line)) => write!(f, "line {line}: {}", self.message),
+            (None, None) => f.write_str(&self.message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// `path`, or [`DEFAULT_PATH`] when it exists, or the defaults.
+    pub fn resolve(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => Self::load(path),
+            None if Path::new(DEFAULT_PATH).exists() => Self::load(Path::new(DEFAULT_PATH)),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| ConfigError {
+                key: None,
+                line: None,
+                message: err.to_string(),
+            })
+            .with_context(|| format!("cannot read config {}", path.display()))?;
+        Self::parse(&source).with_context(|| format!("invalid config {}", path.display()))
+    }
+
+    pub fn parse(source: &str) -> Result<Self> {
+        let config: Self = toml::from_str(source).map_err(|err| {
+            let (key, line) = match err.span() {
+                Some(span) => key_at(source, span.start),
+                None => (None, None),
+            };
+            ConfigError {
+                key,
+                line,
+                message: err.message().to_string(),
+            }
+        })?;
+        if let Some((key, message)) = config.invalid_key() {
+            return Err(ConfigError {
+                line: line_of(source, key),
+                key: Some(key.to_string()),
+                message: message.to_string(),
+            }
+            .into());
+        }
+        Ok(config)
+    }
+
+    /// The first value serde accepts but nets cannot work with.
+    fn invalid_key(&self) -> Option<(&'static str, &'static str)> {
+        let checks = [
+            (self.collector.sample_rate == 0, "collector.sample_rate"),
+            (
+                self.storage.retention_days == Some(0),
+                "storage.retention_days",
+            ),
+            (
+                self.storage.prune_interval_minutes == 0,
+                "storage.prune_interval_minutes",
+            ),
+            (self.analyzer.baseline_hours <= 0, "analyzer.baseline_hours"),
+            (
+                self.analyzer.rate_limit.per_minute == 0,
+                "analyzer.rate_limit.per_minute",
+            ),
+            (
+                self.policy.approval_timeout_seconds == 0,
+                "policy.approval_timeout_seconds",
+            ),
+            (
+                self.policy.throttle.default_rate_kbit == 0,
+                "policy.throttle.default_rate_kbit",
+            ),
+            (self.ui.auto_refresh_seconds == 0, "ui.auto_refresh_seconds"),
+        ];
+        if let Some((_, key)) = checks.into_iter().find(|(invalid, _)| *invalid) {
+            return Some((key, "must be positive"));
+        }
+        if self.storage.path.as_os_str().is_empty() {
+            return Some(("storage.path", "must not be empty"));
+        }
+        if self.analyzer.rules_path.as_os_str().is_empty() {
+            return Some(("analyzer.rules_path", "must not be empty"));
+        }
+        if self.daemon.control_socket.as_os_str().is_empty() {
+            return Some(("daemon.control_socket", "must not be empty"));
+        }
+        None
+    }
+}
+
+/// Mirrors `[collector]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CollectorSection {
+    pub backend: CollectorKind,
+    pub sample_rate: u32,
+    pub max_header_bytes: usize,
+    pub lan_only: bool,
+}
+
+impl Default for CollectorSection {
+    fn default() -> Self {
+        Self {
+            backend: CollectorKind::Auto,
+            sample_rate: 10,
+            max_header_bytes: 256,
+            lan_only: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CollectorKind {
+    /// The collector of the platform nets runs on.
+    Auto,
+    Linux,
+    Windows,
+    Macos,
+    /// Generated flows, for trying nets out without privileges.
+    Mock,
+}
+
+/// Mirrors `[storage]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageSection {
+    pub path: PathBuf,
+    pub backend: Backend,
+    pub compression: Compression,
+    pub key_source: KeySource,
+    pub max_size_mb: Option<u64>,
+    pub retention_days: Option<u32>,
+    pub keep_open_alerts: bool,
+    pub prune_interval_minutes: u64,
+    pub spool: bool,
+}
+
+impl Default for StorageSection {
+    fn default() -> Self {
+        let retention = RetentionConfig::default();
+        Self {
+            path: PathBuf::from("./nets.db"),
+            backend: Backend::default(),
+            compression: Compression::default(),
+            key_source: KeySource::System,
+            max_size_mb: retention.max_size_mb,
+            retention_days: retention.retention_days,
+            keep_open_alerts: retention.keep_open_alerts,
+            prune_interval_minutes: retention.prune_interval_minutes,
+            spool: false,
+        }
+    }
+}
+
+/// Where the database key lives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeySource {
+    /// The OS keystore, falling back to a passphrase-wrapped key file.
+    System,
+    /// Always the key file next to the database, wrapped with the
+    /// passphrase from `NETS_DB_PASSPHRASE`.
+    File,
+}
+
+impl StorageSection {
+    pub fn options(&self) -> StorageOptions {
+        StorageOptions {
+            backend: self.backend,
+            compression: self.compression,
+            spool: self.spool,
+            ..StorageOptions::default()
+        }
+    }
+
+    pub fn retention(&self) -> RetentionConfig {
+        RetentionConfig {
+            retention_days: self.retention_days,
+            max_size_mb: self.max_size_mb,
+            keep_open_alerts: self.keep_open_alerts,
+            prune_interval_minutes: self.prune_interval_minutes,
+            ..RetentionConfig::default()
+        }
+    }
+
+    /// Where the database key lives under `key_source`.
+    pub fn key_provider(&self) -> Result<Box<dyn KeyProvider>> {
+        self.provider_for(&self.path)
+    }
+
+    /// Holds the outgoing key while `key rotate` re-encrypts the database,
+    /// so a rotation that was cut short can be finished.
+    pub fn previous_key_provider(&self) -> Result<Box<dyn KeyProvider>> {
+        let mut path = self.path.as_os_str().to_owned();
+        path.push(".previous");
+        self.provider_for(Path::new(&path))
+    }
+
+    fn provider_for(&self, path: &Path) -> Result<Box<dyn KeyProvider>> {
+        match self.key_source {
+            KeySource::System => resolve_provider(path, None),
+            KeySource::File => {
+                let passphrase = std::env::var(PASSPHRASE_ENV)
+                    .ok()
+                    .filter(|passphrase| !passphrase.is_empty())
+                    .ok_or_else(|| ConfigError {
+                        key: Some("storage.key_source".into()),
+                        line: None,
+                        message: format!("\"file\" needs {PASSPHRASE_ENV}"),
+                    })?;
+                Ok(Box::new(PassphraseKey::new(passphrase, path)))
+            }
+        }
+    }
+
+    pub fn key(&self) -> Result<Vec<u8>> {
+        load_or_create_key(self.key_provider()?.as_ref())
+    }
+}
+
+/// Mirrors `[analyzer]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnalyzerSection {
+    /// Flow history the analyzer keeps; baseline learning does not start
+    /// on its own but with `nets-cli baseline learn`.
+    pub baseline_hours: i64,
+    pub rules_path: PathBuf,
+    pub retro_rules_path: PathBuf,
+    /// Alert exceptions and snoozes, as `nets-cli exceptions` keeps them.
+    pub exceptions_path: PathBuf,
+    /// Threads for normalization and rule matching; 0 is one per CPU.
+    pub workers: usize,
+    pub rate_limit: RateLimitConfig,
+    pub severity_overrides: SeverityOverrides,
+}
+
+impl Default for AnalyzerSection {
+    fn default() -> Self {
+        Self {
+            baseline_hours: 48,
+            rules_path: PathBuf::from("./rules/default.rules"),
+            retro_rules_path: PathBuf::from("./rules/retro.rules"),
+            exceptions_path: PathBuf::from("./exceptions.json"),
+            workers: 0,
+            rate_limit: RateLimitConfig::default(),
+            severity_overrides: SeverityOverrides::default(),
+        }
+    }
+}
+
+/// Mirrors `[policy]` and its subsections.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicySection {
+    pub confirmation_required: bool,
+    pub approval_timeout_seconds: u64,
+    pub auto_approve: Vec<Severity>,
+    pub apply_on_timeout: bool,
+    pub rollback_timeout_seconds: u64,
+    /// Audit the rules quarantines would install instead of enforcing them.
+    pub dry_run: bool,
+    /// Playbooks are optional; without them high-severity alerts get the
+    /// recommended quarantine.
+    pub playbooks_path: Option<PathBuf>,
+    pub guardrails: GuardrailConfig,
+    pub kill_switch: KillSwitchConfig,
+    pub sinkhole: SinkholeConfig,
+    pub throttle: ThrottleConfig,
+    pub notifications: NotificationConfig,
+}
+
+impl Default for PolicySection {
+    fn default() -> Self {
+        let approval = ApprovalConfig::default();
+        Self {
+            confirmation_required: approval.confirmation_required,
+            approval_timeout_seconds: approval.approval_timeout_seconds,
+            auto_approve: approval.auto_approve,
+            apply_on_timeout: approval.apply_on_timeout,
+            rollback_timeout_seconds: 600,
+            dry_run: false,
+            playbooks_path: None,
+            guardrails: GuardrailConfig::default(),
+            kill_switch: KillSwitchConfig::default(),
+            sinkhole: SinkholeConfig::default(),
+            throttle: ThrottleConfig::default(),
+            notifications: NotificationConfig::default(),
+        }
+    }
+}
+
+impl PolicySection {
+    pub fn approval(&self) -> ApprovalConfig {
+        ApprovalConfig {
+            confirmation_required: self.confirmation_required,
+            approval_timeout_seconds: self.approval_timeout_seconds,
+            auto_approve: self.auto_approve.clone(),
+            apply_on_timeout: self.apply_on_timeout,
+        }
+    }
+}
+
+/// Mirrors `[ui]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UiSection {
+    pub auto_refresh_seconds: u64,
+    pub mask_private_data: bool,
+}
+
+impl Default for UiSection {
+    fn default() -> Self {
+        Self {
+            auto_refresh_seconds: 5,
+            mask_private_data: true,
+        }
+    }
+}
+
+/// Mirrors `[daemon]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonSection {
+    /// Unix socket the daemon answers status requests on;
+    /// `$XDG_RUNTIME_DIR/nets/nets.sock` by default.
+    pub control_socket: PathBuf,
+    /// Loopback port used instead of the socket on Windows.
+    pub control_port: u16,
+}
+
+impl Default for DaemonSection {
+    fn default() -> Self {
+        Self {
+            control_socket: default_control_socket(),
+            control_port: 47815,
+        }
+    }
+}
+
+/// In the user's runtime directory, or in a per-user directory under the
+/// system temp directory where there is none, so the daemon and the CLI
+/// agree on it whatever directory they are started from.
+fn default_control_socket() -> PathBuf {
+    let runtime = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute());
+    let dir = match runtime {
+        Some(dir) => dir.join("nets"),
+        None => {
+            let user = std::env::var("USER")
+                .or_else(|_| std::env::var("USERNAME"))
+                .unwrap_or_default();
+            std::env::temp_dir().join(format!("nets-{user}"))
+        }
+    };
+    dir.join("nets.sock")
+}
+
+/// The table header a line opens, without brackets.
+fn table_header(line: &str) -> Option<String> {
+    let line = line.strip_prefix('[')?;
+    let header = line.trim_start_matches('[').split(']').next()?;
+    Some(header.split('.').map(unquote).collect::<Vec<_>>().join("."))
+}
+
+fn unquote(key: &str) -> &str {
+    key.trim().trim_matches(|c| c == '"' || c == '\'')
+}
+
+/// Dotted key of the line `source[offset]` falls on, and its number.
+/// Approximate for values spanning several lines: those point at the
+/// table.
+fn key_at(source: &str, offset: usize) -> (Option<String>, Option<usize>) {
+    let mut table = String::new();
+    let mut start = 0;
+    for (index, line) in source.split_inclusive('\n').enumerate() {
+        let trimmed = line.trim();
+        if let Some(header) = table_header(trimmed) {
+            table = header;
+        }
+        start += line.len();
+        if offset < start {
+            let key = match trimmed.split_once('=') {
+                Some((key, _)) if !trimmed.starts_with(['[', '#']) => join(&table, key),
+                _ => table,
+            };
+            return (Some(key).filter(|key| !key.is_empty()), Some(index + 1));
+        }
+    }
+    (None, None)
+}
+
+/// Line where `key` is set, if the file sets it.
+fn line_of(source: &str, key: &str) -> Option<usize> {
+    let mut table = String::new();
+    for (index, line) in source.lines().enumerate() {
+        let trimmed = line.trim();
+        if let Some(header) = table_header(trimmed) {
+            table = header;
+        } else if let Some((name, _)) = trimmed.split_once('=') {
+            if !trimmed.starts_with('#') && join(&table, name) == key {
+                return Some(index + 1);
+            }
+        }
+    }
+    None
+}
+
+fn join(table: &str, key: &str) -> String {
+    let key = key.split('.').map(unquote).collect::<Vec<_>>().join(".");
+    if table.is_empty() {
+        key
+    } else {
+        format!("{table}.{key}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_shipped_config() {
+        let config = Config::parse(include_str!("../../../config/config.toml")).unwrap();
+        assert_eq!(config.collector.backend, CollectorKind::Auto);
+        assert_eq!(config.storage.path, PathBuf::from("./nets.db"));
+        assert_eq!(config.storage.retention().retention_days, Some(14));
+        assert_eq!(config.analyzer.rate_limit.per_minute, 30);
+        assert!(config.policy.approval().confirmation_required);
+        assert_eq!(config.policy.approval_timeout_seconds, 120);
+        assert!(!config.policy.dry_run);
+        assert!(config.policy.notifications.channels.contains_key("desktop"));
+
+        let empty = Config::parse("").unwrap();
+        assert_eq!(empty.analyzer.baseline_hours, 48);
+        assert!(empty.policy.playbooks_path.is_none());
+    }
+
+    #[test]
+    fn errors_name_the_offending_key() {
+        let error = |source: &str| {
+            Config::parse(source)
+                .unwrap_err()
+                .downcast::<ConfigError>()
+                .unwrap()
+        };
+        let typo = error("[storage]\npath = \"a.db\"\nretention_dayz = 3\n");
+        assert_eq!(typo.key.as_deref(), Some("storage.retention_dayz"));
+        assert_eq!(typo.line, Some(3));
+
+        let kind = error("[collector]\nbackend = \"pcap\"\n");
+        assert_eq!(kind.key.as_deref(), Some("collector.backend"));
+        assert!(kind.message.contains("unknown variant"));
+
+        let nested =
+            error("[analyzer.rate_limit]\nburst = 1\nper_minute = -1\nrollup_seconds = 5\n");
+        assert_eq!(
+            nested.key.as_deref(),
+            Some("analyzer.rate_limit.per_minute")
+        );
+
+        let invalid = error("[analyzer]\nbaseline_hours = 0\n");
+        assert_eq!(
+            invalid.to_string(),
+            "`analyzer.baseline_hours` (line 2): must be positive"
+        );
+        assert_eq!(error("[ui]\nauto_refresh_seconds = \n").line, Some(2));
+    }
+}
@@ -0,0 +1,267 @@
+//! Local control endpoint of the daemon, which `nets-cli health` asks
+//! whether the daemon is alive. Each connection gets one JSON line of
+//! [`DaemonStatus`]. A Unix socket readable by its owner only, or a
+//! loopback TCP port on Windows, where std has no local sockets.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use collector::capabilities::Capabilities;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::config::DaemonSection;
+
+/// How long a client waits for the daemon's answer.
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub version: String,
+    pub started_at: DateTime<Utc>,
+    /// Probed by the daemon, with its own privileges.
+    pub collector: Capabilities,
+    /// Flows received from the collector since the start.
+    pub flows: u64,
+    /// Of those, dropped because the pipeline was busy.
+    pub dropped: u64,
+}
+
+/// Answers status requests until dropped; stops its thread and removes
+/// its socket then.
+pub struct ControlServer {
+    socket: Option<PathBuf>,
+    stop: Arc<AtomicBool>,
+    /// Connects to the endpoint once, so the blocked `accept` returns and
+    /// sees `stop`.
+    wake: Box<dyn Fn() -> io::Result<()> + Send>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ControlServer {
+    /// Fails when another daemon already answers on the endpoint.
+    pub fn spawn(
+        config: &DaemonSection,
+        status: impl Fn() -> DaemonStatus + Send + 'static,
+    ) -> Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+        #[cfg(unix)]
+        {
+            use std::os::unix::net::UnixStream;
+
+            let path = config.control_socket.clone();
+            if UnixStream::connect(&path).is_ok() {
+                return Err(anyhow::anyhow!(
+                    "another daemon is running (it answers on {})",
+                    path.display()
+                ));
+            }
+            let listener = bind_private(&path)?;
+            let thread = accept_loop(
+                listener,
+                |listener| listener.accept().map(|(s, _)| s),
+                stop.clone(),
+                status,
+            )?;
+            let wake = {
+                let path = path.clone();
+                move || UnixStream::connect(&path).map(drop)
+            };
+            Ok(Self {
+                socket: Some(path),
+                stop,
+                wake: Box::new(wake),
+                thread: Some(thread),
+            })
+        }
+        #[cfg(not(unix))]
+        {
+            use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+            let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, config.control_port))
+                .with_context(|| {
+                    format!(
+                        "cannot listen on port {}; is another daemon running?",
+                        config.control_port
+                    )
+                })?;
+            let address = listener.local_addr()?;
+            let thread = accept_loop(
+                listener,
+                |listener| listener.accept().map(|(s, _)| s),
+                stop.clone(),
+                status,
+            )?;
+            Ok(Self {
+                socket: None,
+                stop,
+                wake: Box::new(move || TcpStream::connect_timeout(&address, TIMEOUT).map(drop)),
+                thread: Some(thread),
+            })
+        }
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Without the wake-up connection `accept` never returns.
+        if (self.wake)().is_ok() {
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+        if let Some(socket) = &self.socket {
+            let _ = std::fs::remove_file(socket);
+        }
+    }
+}
+
+/// Binds the socket in a private staging directory, restricts it to the
+/// owner and only then moves it to `path`, so nobody else can connect in
+/// between. The parent directory is created owner-only if missing.
+#[cfg(unix)]
+fn bind_private(path: &std::path::Path) -> Result<std::os::unix::net::UnixListener> {
+    use std::{
+        fs,
+        os::unix::{
+            fs::{DirBuilderExt, PermissionsExt},
+            net::UnixListener,
+        },
+    };
+
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => std::path::Path::new("."),
+    };
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(parent)
+        .with_context(|| format!("cannot create {}", parent.display()))?;
+    let staging = parent.join(format!(".nets-control-{}", std::process::id()));
+    // Left behind by a daemon that did not exit cleanly.
+    let _ = fs::remove_dir_all(&staging);
+    fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&staging)
+        .with_context(|| format!("cannot create {}", staging.display()))?;
+    let staged = staging.join("nets.sock");
+    let bound = UnixListener::bind(&staged)
+        .and_then(|listener| {
+            fs::set_permissions(&staged, fs::Permissions::from_mode(0o600))?;
+            // Left behind by a daemon that did not exit cleanly.
+            let _ = fs::remove_file(path);
+            fs::rename(&staged, path)?;
+            Ok(listener)
+        })
+        .with_context(|| format!("cannot listen on {}", path.display()));
+    let _ = fs::remove_dir_all(&staging);
+    bound
+}
+
+fn accept_loop<L, S>(
+    listener: L,
+    accept: fn(&L) -> io::Result<S>,
+    stop: Arc<AtomicBool>,
+    status: impl Fn() -> DaemonStatus + Send + 'static,
+) -> Result<JoinHandle<()>>
+where
+    L: Send + 'static,
+    S: Write + 'static,
+{
+    let thread = thread::Builder::new()
+        .name("nets-control".into())
+        .spawn(move || loop {
+            let accepted = accept(&listener);
+            if stop.load(Ordering::SeqCst) {
+                break;
+            }
+            match accepted {
+                Ok(mut stream) => {
+                    let answer = serde_json::to_string(&status()).unwrap_or_default();
+                    // A client that hung up early is its own problem.
+                    let _ = writeln!(stream, "{answer}");
+                }
+                Err(err) => {
+                    warn!(%err, "control endpoint stopped");
+                    break;
+                }
+            }
+        })?;
+    Ok(thread)
+}
+
+/// Status of the running daemon, or an error saying none answered.
+pub fn query(config: &DaemonSection) -> Result<DaemonStatus> {
+    #[cfg(unix)]
+    let stream = std::os::unix::net::UnixStream::connect(&config.control_socket)
+        .with_context(|| format!("no daemon answering on {}", config.control_socket.display()))?;
+    #[cfg(not(unix))]
+    let stream = std::net::TcpStream::connect_timeout(
+        &(std::net::Ipv4Addr::LOCALHOST, config.control_port).into(),
+        TIMEOUT,
+    )
+    .with_context(|| format!("no daemon answering on port {}", config.control_port))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    serde_json::from_str(&line).context("unexpected answer from the daemon")
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn answers_status_and_refuses_a_second_daemon() {
+        let dir = std::env::temp_dir().join(format!("nets-control-{}", std::process::id()));
+        let config = DaemonSection {
+            control_socket: dir.join("run").join("nets.sock"),
+            ..DaemonSection::default()
+        };
+        let started_at = Utc::now();
+        // Held by the accept thread for as long as it runs.
+        let alive = Arc::new(());
+        let held = alive.clone();
+        let status = move || {
+            let _ = &held;
+            DaemonStatus {
+                pid: 42,
+                version: "0.1.0".into(),
+                started_at,
+                collector: Capabilities::mock(),
+                flows: 7,
+                dropped: 1,
+            }
+        };
+        let server = ControlServer::spawn(&config, status.clone()).unwrap();
+        let answer = query(&config).unwrap();
+        assert_eq!((answer.pid, answer.flows, answer.dropped), (42, 7, 1));
+        assert!(ControlServer::spawn(&config, status).is_err());
+        let mode =
+            |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&config.control_socket), 0o600);
+        assert_eq!(mode(&dir.join("run")), 0o700);
+        assert_eq!(std::fs::read_dir(dir.join("run")).unwrap().count(), 1);
+
+        drop(server);
+        assert!(!config.control_socket.exists());
+        assert!(query(&config).is_err());
+        assert_eq!(Arc::strong_count(&alive), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
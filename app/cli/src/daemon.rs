@@ -0,0 +1,1202 @@
+//! `nets-cli daemon`: collector → normalizer → analyzer → storage → policy
+//! in one process, until Ctrl+C or SIGTERM.
+//!
+//! The collector hands flows to a bounded queue; one thread drains it,
+//! stores each flow, analyzes it and responds to the alerts, so the
+//! collector never waits for SQLite or the firewall. Flows arriving while
+//! the queue is full are dropped and counted. Normalization and rule
+//! matching need no state and run on `[analyzer] workers` threads; their
+//! results come back in arrival order to the one analyzer that sees every
+//! flow, since lateral-movement chains, scan fan-in and the baseline
+//! correlate across hosts and their state cannot be split.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration as StdDuration, Instant, SystemTime},
+};
+
+use analyzer::{
+    baseline::BaselineMode,
+    dsl::load_rules_from_str,
+    fingerprints::FingerprintLists,
+    intel::IndicatorLists,
+    pipeline::{MatchedFlow, RuleStage, Workers},
+    retro::load_retro_rules_from_str,
+    Alert, Analyzer,
+};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use collector::{capabilities::Capabilities, CollectorBackend, FlowEvent};
+use normalizer::Normalizer;
+use policy::{
+    load_playbooks_from_str, recommend_quarantine, DnsSinkhole, DryRunBackend, Guardrails,
+    Notifier, PlaybookEngine, PolicyAction, PolicyBackend, QuarantineManager, SinkholeMethod,
+    StepOutcome, Submission, Throttler,
+};
+use storage::{retention::RetentionJob, AsyncStorage, Storage, WriterConfig};
+use tokio::runtime::Handle;
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::{AnalyzerSection, CollectorKind, Config, PolicySection},
+    control::{ControlServer, DaemonStatus},
+};
+
+/// Flows waiting for the pipeline thread before new ones are dropped.
+const FLOW_QUEUE: usize = 4096;
+/// How often the pipeline thread runs due playbook steps and checks for
+/// shutdown while no flows arrive.
+const TICK: StdDuration = StdDuration::from_millis(500);
+/// How often the pipeline thread looks for baseline commands queued by
+/// `nets-cli baseline`.
+const BASELINE_POLL: StdDuration = StdDuration::from_secs(2);
+/// How often what the baseline is learning is saved.
+const BASELINE_SAVE: StdDuration = StdDuration::from_secs(60);
+/// How often the exception list is checked for edits by
+/// `nets-cli exceptions`.
+const EXCEPTIONS_POLL: StdDuration = StdDuration::from_secs(2);
+/// How often retrospective rules are checked for being due; each runs at
+/// its own `every_seconds`.
+const RETRO_TICK: StdDuration = StdDuration::from_secs(60);
+/// Flows taken from the queue at once, so the rule workers share them
+/// before the pipeline thread waits for the results.
+const FLOW_BATCH: usize = 256;
+
+pub fn run(config: Config) -> Result<()> {
+    run_until(config, shutdown_signal())
+}
+
+/// Runs the daemon until `stop` resolves; the Windows service stops on a
+/// request from the service manager instead of a signal.
+pub fn run_until(config: Config, stop: impl Future<Output = Result<()>>) -> Result<()> {
+    let rt = tokio::runtime::Runtime::new()?;
+    rt.block_on(serve(config, stop))
+}
+
+async fn serve(config: Config, stop_requested: impl Future<Output = Result<()>>) -> Result<()> {
+    let received = Arc::new(AtomicU64::new(0));
+    let dropped = Arc::new(AtomicU64::new(0));
+    // First, so a second daemon gives up before touching the database.
+    let _control = {
+        let started_at = Utc::now();
+        let collector = match config.collector.backend {
+            CollectorKind::Mock => Capabilities::mock(),
+            _ => Capabilities::probe(),
+        };
+        let (received, dropped) = (received.clone(), dropped.clone());
+        ControlServer::spawn(&config.daemon, move || DaemonStatus {
+            pid: std::process::id(),
+            version: env!("CARGO_PKG_VERSION").into(),
+            started_at,
+            collector: collector.clone(),
+            flows: received.load(Ordering::Relaxed),
+            dropped: dropped.load(Ordering::Relaxed),
+        })?
+    };
+    let path = config.storage.path.clone();
+    let options = config.storage.options();
+    let key = config.storage.key()?;
+    let writer = AsyncStorage::spawn(
+        Storage::open_with_options(&path, &key, options)?,
+        WriterConfig::default(),
+    )?;
+    let _retention = RetentionJob::spawn(
+        path.clone(),
+        key.clone(),
+        options,
+        config.storage.retention(),
+    )?;
+    // Quarantines and the action log use a connection of their own; the
+    // writer's is busy with flow batches.
+    let store = Storage::open_with_options(&path, &key, options)?;
+    let intel = store.intel_lists()?;
+    let feeds = store.intel_feeds()?.len();
+    if feeds > 0 {
+        info!(feeds, "threat intel loaded");
+    }
+    let store = Arc::new(Mutex::new(store));
+    let responder = Responder::new(&config.policy, store.clone())?;
+    let mut pipeline = Pipeline::new(&config.analyzer, writer.clone(), Some(responder))?
+        .with_baseline(store.clone())?
+        .with_retro(&config.analyzer.retro_rules_path, store)?
+        .with_exceptions(&config.analyzer.exceptions_path)?;
+    pipeline.set_intel(intel);
+    // Detected when the responder installed its policy backend.
+    pipeline.watch_gateways(&Guardrails::current());
+    let collector = collector_backend(config.collector.backend)?;
+
+    let (flows, queued) = mpsc::sync_channel(FLOW_QUEUE);
+    let counter = dropped.clone();
+    collector.subscribe(Arc::new(move |flow: FlowEvent| {
+        received.fetch_add(1, Ordering::Relaxed);
+        if flows.try_send(flow).is_err() {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }));
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+    let worker = thread::Builder::new()
+        .name("nets-pipeline".into())
+        .spawn(move || pipeline.run(queued, &stopped))?;
+
+    let result = async {
+        collector.start().await?;
+        info!("daemon running. press Ctrl+C to stop");
+        stop_requested.await?;
+        info!("shutting down");
+        collector.stop().await
+    }
+    .await;
+    // The queue cannot close while the collector holds its sender, so the
+    // thread is told to drain what is left and exit.
+    stop.store(true, Ordering::Release);
+    tokio::task::spawn_blocking(move || worker.join())
+        .await?
+        .map_err(|_| anyhow!("pipeline thread panicked"))?;
+    writer.flush().await?;
+    let dropped = dropped.load(Ordering::Relaxed);
+    if dropped > 0 {
+        warn!(dropped, "flows dropped while the pipeline was busy");
+    }
+    // Active quarantines stay in force; the next start recovers them.
+    info!("daemon stopped");
+    result
+}
+
+/// The platform's collector serves `auto` and the platform's own name;
+/// naming another platform is an error.
+pub fn collector_backend(kind: CollectorKind) -> Result<Arc<dyn CollectorBackend>> {
+    let native = match kind {
+        CollectorKind::Mock => return Ok(Arc::new(collector::MockCollector::default())),
+        CollectorKind::Auto => true,
+        CollectorKind::Linux => cfg!(target_os = "linux"),
+        CollectorKind::Windows => cfg!(target_os = "windows"),
+        CollectorKind::Macos => cfg!(target_os = "macos"),
+    };
+    if !native {
+        return Err(anyhow!(
+            "collector backend {kind:?} is not available on this platform"
+        ));
+    }
+    collector::default_backend()
+        .context("collector backend unavailable; set [collector] backend = \"mock\" to test")
+}
+
+/// Ctrl+C, or SIGTERM where there is one.
+#[cfg(unix)]
+pub async fn shutdown_signal() -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        _ = terminate.recv() => {}
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub async fn shutdown_signal() -> Result<()> {
+    Ok(tokio::signal::ctrl_c().await?)
+}
+
+/// Everything between the collector queue and storage; lives on the
+/// pipeline thread.
+pub(crate) struct Pipeline {
+    normalizer: Arc<Normalizer>,
+    analyzer: Analyzer,
+    /// Threads for normalization and rule matching; 0 is one per CPU.
+    worker_count: usize,
+    /// Running only inside [`Pipeline::run`].
+    workers: Option<Workers<(FlowEvent, Option<i64>), Prepared>>,
+    /// Rule tags by rule id, which playbooks match on.
+    tags: HashMap<String, Vec<String>>,
+    writer: AsyncStorage,
+    /// None leaves alerts unanswered, as `bench` does.
+    responder: Option<Responder>,
+    runtime: Handle,
+    stats: PipelineStats,
+    /// Only the daemon keeps the baseline in storage.
+    baseline: Option<BaselineSync>,
+    /// Only the daemon runs retrospective rules.
+    retro: Option<RetroTimer>,
+    /// Only the daemon follows the exception list.
+    exceptions: Option<ExceptionsSync>,
+}
+
+/// The stored baseline the analyzer learns into and takes lifecycle
+/// commands from.
+struct BaselineSync {
+    store: Arc<Mutex<Storage>>,
+    polled: Instant,
+    saved: Instant,
+}
+
+impl BaselineSync {
+    /// Applies a queued command, then saves the profile if the command
+    /// changed it, or if it is learning and `save` is set or a save is due.
+    fn step(&mut self, analyzer: &mut Analyzer, save: bool) -> Result<()> {
+        let store = self
+            .store
+            .lock()
+            .map_err(|_| anyhow!("storage lock poisoned"))?;
+        let mut changed = false;
+        if let Some(command) = store.take_baseline_command()? {
+            match analyzer.apply_baseline(command) {
+                Ok(()) => {
+                    info!(command = command.as_str(), "baseline command applied");
+                    changed = true;
+                }
+                Err(err) => warn!(command = command.as_str(), %err, "baseline command refused"),
+            }
+        }
+        let learning = analyzer.baseline_profile().mode == BaselineMode::Learning;
+        if changed || (learning && (save || self.saved.elapsed() >= BASELINE_SAVE)) {
+            store.save_baseline(analyzer.baseline_profile())?;
+            self.saved = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+/// The exception list file the analyzer and the guardrails follow.
+struct ExceptionsSync {
+    path: PathBuf,
+    /// Of the file as last loaded; None while there is none.
+    modified: Option<SystemTime>,
+    polled: Instant,
+}
+
+impl ExceptionsSync {
+    /// Hands the list to the analyzer, and its snoozes to the guardrails,
+    /// when the file changed since the last load.
+    fn reload(&mut self, analyzer: &mut Analyzer) -> Result<()> {
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if modified == self.modified {
+            return Ok(());
+        }
+        let list = crate::exceptions::load(&self.path)?;
+        self.modified = modified;
+        info!(exceptions = list.entries.len(), "exceptions loaded");
+        Guardrails::current()
+            .with_snoozes(list.snoozes(Utc::now()))
+            .install();
+        analyzer.set_exceptions(list);
+        Ok(())
+    }
+}
+
+/// Stored history the retrospective rules run against.
+struct RetroTimer {
+    store: Arc<Mutex<Storage>>,
+    /// None until the first check, which comes right away.
+    checked: Option<Instant>,
+}
+
+/// A stored flow after the stateless stage.
+struct Prepared {
+    flow: FlowEvent,
+    id: Option<i64>,
+    /// None for ARP/ND events and flows that could not be normalized.
+    matched: Option<MatchedFlow>,
+}
+
+impl Prepared {
+    fn new(normalizer: &Normalizer, stage: &RuleStage, flow: FlowEvent, id: Option<i64>) -> Self {
+        // ARP/ND events carry no transport flow to analyze.
+        let matched = if flow.layer2.is_some() {
+            None
+        } else {
+            match normalizer.normalize(flow.clone()) {
+                Ok(mut normalized) => {
+                    normalized.flow_id = id;
+                    Some(stage.evaluate(normalized))
+                }
+                Err(err) => {
+                    debug!(%err, "flow could not be normalized");
+                    None
+                }
+            }
+        };
+        Self { flow, id, matched }
+    }
+}
+
+/// What one pipeline run got through.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct PipelineStats {
+    pub flows: u64,
+    pub alerts: u64,
+    /// Alerts by rule id.
+    pub rules: BTreeMap<String, u64>,
+    /// Playbook steps carried out, when the responder records them.
+    pub steps: Vec<StepOutcome>,
+}
+
+impl Pipeline {
+    /// Must be called inside a Tokio runtime, which storage writes use.
+    pub(crate) fn new(
+        config: &AnalyzerSection,
+        writer: AsyncStorage,
+        responder: Option<Responder>,
+    ) -> Result<Self> {
+        let data = std::fs::read_to_string(&config.rules_path)
+            .with_context(|| format!("cannot read rules {}", config.rules_path.display()))?;
+        let rules = load_rules_from_str(&data)?;
+        let tags = rules
+            .iter()
+            .map(|rule| (rule.id.clone(), rule.tags.clone()))
+            .collect();
+        info!(rules = rules.len(), "rules loaded");
+        let mut analyzer = Analyzer::new(Duration::hours(config.baseline_hours), rules);
+        analyzer.set_rate_limit(config.rate_limit.clone());
+        analyzer.set_severity_overrides(config.severity_overrides.clone());
+        Ok(Self {
+            normalizer: Arc::new(Normalizer::new(Duration::seconds(60))),
+            analyzer,
+            worker_count: config.workers,
+            workers: None,
+            tags,
+            writer,
+            responder,
+            runtime: Handle::current(),
+            stats: PipelineStats::default(),
+            baseline: None,
+            retro: None,
+            exceptions: None,
+        })
+    }
+
+    /// Restores the stored baseline and applies a command queued while the
+    /// daemon was down; from then on learning is saved every minute and on
+    /// exit, and commands queued by `nets-cli baseline` apply within
+    /// seconds.
+    pub(crate) fn with_baseline(mut self, store: Arc<Mutex<Storage>>) -> Result<Self> {
+        let stored = store
+            .lock()
+            .map_err(|_| anyhow!("storage lock poisoned"))?
+            .load_baseline()?;
+        if let Some(profile) = stored {
+            info!(mode = ?profile.mode, "baseline restored");
+            self.analyzer.load_baseline(profile);
+        }
+        self.baseline = Some(BaselineSync {
+            store,
+            polled: Instant::now(),
+            saved: Instant::now(),
+        });
+        self.sync_baseline(true);
+        Ok(self)
+    }
+
+    /// Loads the retrospective rules at `path`, if there is a file, and
+    /// runs them against `store` as they fall due.
+    pub(crate) fn with_retro(mut self, path: &Path, store: Arc<Mutex<Storage>>) -> Result<Self> {
+        if !path.exists() {
+            return Ok(self);
+        }
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("cannot read retrospective rules {}", path.display()))?;
+        let rules = load_retro_rules_from_str(&data)?;
+        info!(rules = rules.len(), "retrospective rules loaded");
+        self.analyzer.set_retro_rules(rules);
+        self.retro = Some(RetroTimer {
+            store,
+            checked: None,
+        });
+        Ok(self)
+    }
+
+    /// Loads the exception list at `path`, if there is one, and follows
+    /// the edits `nets-cli exceptions` makes to it.
+    pub(crate) fn with_exceptions(mut self, path: &Path) -> Result<Self> {
+        let mut sync = ExceptionsSync {
+            path: path.to_path_buf(),
+            modified: None,
+            polled: Instant::now(),
+        };
+        sync.reload(&mut self.analyzer)?;
+        self.exceptions = Some(sync);
+        Ok(self)
+    }
+
+    /// Watches the MAC addresses behind the host's gateways and resolvers.
+    pub(crate) fn watch_gateways(&mut self, guardrails: &Guardrails) {
+        let guard = self.analyzer.gateway_guard_mut();
+        for gateway in &guardrails.gateways {
+            guard.watch_gateway(&gateway.to_string());
+        }
+        for resolver in &guardrails.dns_resolvers {
+            guard.watch_dns(&resolver.to_string());
+        }
+    }
+
+    /// Threat-intel indicators from storage, as `intel import` left them.
+    pub(crate) fn set_intel(
+        &mut self,
+        (indicators, fingerprints): (IndicatorLists, FingerprintLists),
+    ) {
+        self.analyzer.set_indicator_lists(indicators);
+        self.analyzer.set_fingerprint_lists(fingerprints);
+    }
+
+    /// Processes flows until the queue closes, or until `stop` is set and
+    /// what is queued has been processed.
+    pub(crate) fn run(mut self, flows: Receiver<FlowEvent>, stop: &AtomicBool) -> PipelineStats {
+        self.workers = self.spawn_workers();
+        loop {
+            match flows.recv_timeout(TICK) {
+                Ok(flow) => {
+                    self.submit(flow);
+                    for flow in flows.try_iter().take(FLOW_BATCH - 1) {
+                        self.submit(flow);
+                    }
+                    // Alerts do not wait for the next flow to arrive.
+                    self.drain(true);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if stop.load(Ordering::Acquire) {
+                // Whatever the collector queued before it stopped.
+                while let Ok(flow) = flows.try_recv() {
+                    self.submit(flow);
+                }
+                break;
+            }
+            if let Some(responder) = &mut self.responder {
+                responder.run_due(Utc::now());
+            }
+            self.sync_baseline(false);
+            self.run_retro();
+            self.sync_exceptions();
+        }
+        self.drain(true);
+        self.workers = None;
+        for alert in self.analyzer.flush_rate_limit() {
+            self.store_alert(alert);
+        }
+        self.sync_baseline(true);
+        if let Some(steps) = self.responder.as_mut().and_then(|r| r.recorded.take()) {
+            self.stats.steps = steps;
+        }
+        self.stats
+    }
+
+    /// Rule workers over the current rules and lists; without them flows
+    /// are matched on the pipeline thread.
+    fn spawn_workers(&self) -> Option<Workers<(FlowEvent, Option<i64>), Prepared>> {
+        let count = match self.worker_count {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            count => count,
+        };
+        let normalizer = Arc::clone(&self.normalizer);
+        let stage = self.analyzer.rule_stage();
+        let spawned = Workers::spawn(count, move |(flow, id)| {
+            Prepared::new(&normalizer, &stage, flow, id)
+        });
+        match spawned {
+            Ok(workers) => {
+                info!(workers = count, "rule workers started");
+                Some(workers)
+            }
+            Err(err) => {
+                warn!(%err, "rule workers not started; matching on one thread");
+                None
+            }
+        }
+    }
+
+    /// Stores `flow` and hands it to the rule workers, finishing whatever
+    /// they have ready.
+    fn submit(&mut self, flow: FlowEvent) {
+        if self.workers.is_none() {
+            return self.process(flow);
+        }
+        let id = self.store_flow(&flow);
+        if let Some(workers) = &mut self.workers {
+            if let Err(err) = workers.submit((flow, id)) {
+                warn!(%err, "flow not analyzed");
+            }
+        }
+        self.drain(false);
+    }
+
+    /// Finishes worker results in arrival order: those ready, or with
+    /// `wait` all of them.
+    fn drain(&mut self, wait: bool) {
+        loop {
+            let Some(workers) = &mut self.workers else {
+                return;
+            };
+            let next = if wait {
+                workers.wait_next()
+            } else {
+                workers.try_next()
+            };
+            let Some(prepared) = next else {
+                return;
+            };
+            self.finish(prepared);
+        }
+    }
+
+    fn process(&mut self, flow: FlowEvent) {
+        let id = self.store_flow(&flow);
+        let stage = self.analyzer.rule_stage();
+        let prepared = Prepared::new(&self.normalizer, &stage, flow, id);
+        self.finish(prepared);
+    }
+
+    /// Stored first: alerts reference the flow by its row id.
+    fn store_flow(&mut self, flow: &FlowEvent) -> Option<i64> {
+        self.stats.flows += 1;
+        match self.runtime.block_on(self.writer.store_flow(flow.clone())) {
+            Ok(id) => Some(id),
+            Err(err) => {
+                warn!(%err, "failed to store flow");
+                None
+            }
+        }
+    }
+
+    /// The stateful stage: detectors, baseline and scorers, then risk and
+    /// the response.
+    fn finish(&mut self, prepared: Prepared) {
+        let Prepared { flow, id, matched } = prepared;
+        if flow.layer2.is_some() {
+            let mut alerts = self.analyzer.observe_layer2(&flow);
+            for alert in &mut alerts {
+                alert.flow_refs.extend(id);
+            }
+            self.answer(alerts, &flow);
+            return;
+        }
+        let Some(matched) = matched else {
+            return;
+        };
+        let alerts = self.analyzer.ingest_matched(matched);
+        if let Some(id) = id {
+            let mut assessed = flow.clone();
+            // Intel hits are among the alerts, as `intel.*` rules.
+            self.analyzer.assess_risk(&mut assessed, &alerts, &[]);
+            if let Some(risk) = assessed.risk {
+                if let Err(err) = self.runtime.block_on(self.writer.set_flow_risk(id, risk)) {
+                    warn!(%err, "failed to queue flow risk for storage");
+                }
+            }
+        }
+        self.answer(alerts, &flow);
+    }
+
+    /// Responds to the alerts `flow` raised and stores them.
+    fn answer(&mut self, alerts: Vec<Alert>, flow: &FlowEvent) {
+        for alert in alerts {
+            let tags = self
+                .tags
+                .get(&alert.rule_id)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            if let Some(responder) = &mut self.responder {
+                responder.respond(&alert, tags, flow);
+            }
+            self.store_alert(alert);
+        }
+    }
+
+    /// Runs a [`BaselineSync`] step when one is due, or now with `save`.
+    fn sync_baseline(&mut self, save: bool) {
+        let Some(sync) = &mut self.baseline else {
+            return;
+        };
+        if !save && sync.polled.elapsed() < BASELINE_POLL {
+            return;
+        }
+        sync.polled = Instant::now();
+        if let Err(err) = sync.step(&mut self.analyzer, save) {
+            warn!(%err, "baseline not synced with storage");
+        }
+    }
+
+    fn sync_exceptions(&mut self) {
+        let Some(sync) = &mut self.exceptions else {
+            return;
+        };
+        if sync.polled.elapsed() < EXCEPTIONS_POLL {
+            return;
+        }
+        sync.polled = Instant::now();
+        if let Err(err) = sync.reload(&mut self.analyzer) {
+            warn!(%err, "exception list not reloaded");
+        }
+    }
+
+    /// Runs the retrospective rules that are due, once a [`RETRO_TICK`].
+    fn run_retro(&mut self) {
+        let Some(retro) = &mut self.retro else {
+            return;
+        };
+        if retro
+            .checked
+            .is_some_and(|checked| checked.elapsed() < RETRO_TICK)
+        {
+            return;
+        }
+        retro.checked = Some(Instant::now());
+        let alerts = match retro.store.lock() {
+            Ok(store) => self.analyzer.run_retro(&*store, Utc::now()),
+            Err(_) => {
+                warn!("storage lock poisoned; retrospective rules skipped");
+                return;
+            }
+        };
+        for alert in alerts {
+            self.store_alert(alert);
+        }
+    }
+
+    fn store_alert(&mut self, alert: Alert) {
+        self.stats.alerts += 1;
+        *self.stats.rules.entry(alert.rule_id.clone()).or_default() += 1;
+        info!(alert = %alert.id, rule = %alert.rule_id, severity = ?alert.severity, "{}", alert.summary);
+        if let Err(err) = self.runtime.block_on(self.writer.put_alert(alert)) {
+            warn!(%err, "failed to queue alert for storage");
+        }
+    }
+}
+
+/// The host's firewall backend behind the installed guardrails, or one
+/// that only audits with `dry_run`.
+pub fn policy_backend(
+    config: &PolicySection,
+    store: &Arc<Mutex<Storage>>,
+) -> Box<dyn PolicyBackend + Send + Sync> {
+    Guardrails::detect(&config.guardrails).install();
+    let backend = policy::default_backend();
+    if config.dry_run {
+        info!("policy dry run: quarantines are audited, not enforced");
+        Box::new(DryRunBackend::with_log(backend, Box::new(store.clone())))
+    } else {
+        backend
+    }
+}
+
+/// Turns alerts into policy actions: the first matching playbook, or the
+/// recommended quarantine when none matches.
+pub(crate) struct Responder {
+    manager: Arc<QuarantineManager>,
+    playbooks: Option<PlaybookEngine>,
+    /// Where triage of the alerts playbooks run for is read from.
+    store: Arc<Mutex<Storage>>,
+    /// Playbook steps carried out so far; only `simulate` keeps them.
+    recorded: Option<Vec<StepOutcome>>,
+}
+
+impl Responder {
+    fn new(config: &PolicySection, store: Arc<Mutex<Storage>>) -> Result<Self> {
+        let backend = policy_backend(config, &store);
+        let manager = QuarantineManager::with_store(backend, Box::new(store.clone()))?;
+        manager.set_approval_config(config.approval());
+        let report = manager.recover()?;
+        info!(
+            restored = report.restored.len(),
+            lifted = report.lifted.len(),
+            failed = report.failed.len(),
+            orphaned = report.orphaned.len(),
+            "quarantines recovered"
+        );
+        let manager = Arc::new(manager);
+        let playbooks = match &config.playbooks_path {
+            Some(path) if path.exists() => Some(Self::playbook_engine(
+                config,
+                path,
+                &manager,
+                store.clone(),
+            )?),
+            _ => None,
+        };
+        Ok(Self {
+            manager,
+            playbooks,
+            store,
+            recorded: None,
+        })
+    }
+
+    /// For `simulate`: quarantines only go through the dry-run backend
+    /// (audited, never enforced, not kept), playbooks notify but cannot
+    /// throttle or sinkhole, and every step is recorded.
+    pub(crate) fn simulated(config: &PolicySection, store: Arc<Mutex<Storage>>) -> Result<Self> {
+        let backend = DryRunBackend::with_log(policy::default_backend(), Box::new(store.clone()));
+        let manager = Arc::new(QuarantineManager::new(Box::new(backend))?);
+        let playbooks = match &config.playbooks_path {
+            Some(path) if path.exists() => {
+                let data = std::fs::read_to_string(path)
+                    .with_context(|| format!("cannot read playbooks {}", path.display()))?;
+                Some(
+                    PlaybookEngine::new(load_playbooks_from_str(&data)?)
+                        .with_quarantine(manager.clone())
+                        .with_notifier(Notifier::new(&config.notifications)),
+                )
+            }
+            _ => None,
+        };
+        Ok(Self {
+            manager,
+            playbooks,
+            store,
+            recorded: Some(Vec::new()),
+        })
+    }
+
+    fn playbook_engine(
+        config: &PolicySection,
+        path: &Path,
+        manager: &Arc<QuarantineManager>,
+        store: Arc<Mutex<Storage>>,
+    ) -> Result<PlaybookEngine> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("cannot read playbooks {}", path.display()))?;
+        let playbooks = load_playbooks_from_str(&data)?;
+        info!(playbooks = playbooks.len(), "playbooks loaded");
+        let mut engine = PlaybookEngine::new(playbooks)
+            .with_quarantine(manager.clone())
+            .with_sinkhole(DnsSinkhole::new(SinkholeMethod::from_config(
+                &config.sinkhole,
+            )))
+            .with_notifier(Notifier::new(&config.notifications))
+            .with_log(Box::new(store));
+        // Steps that throttle fail on their own when shaping is unavailable.
+        match Throttler::new(&config.throttle) {
+            Ok(throttler) => engine = engine.with_throttler(throttler),
+            Err(err) => warn!(%err, "throttling unavailable"),
+        }
+        Ok(engine)
+    }
+
+    fn respond(&mut self, alert: &Alert, tags: &[String], flow: &FlowEvent) {
+        if let Some(engine) = &mut self.playbooks {
+            if engine
+                .playbooks()
+                .iter()
+                .any(|playbook| playbook.matches.matches(alert, tags))
+            {
+                let outcomes = engine.trigger(alert, tags, Some(flow), Utc::now());
+                debug!(alert = %alert.id, steps = outcomes.len(), "playbook triggered");
+                if let Some(recorded) = &mut self.recorded {
+                    recorded.extend(outcomes);
+                }
+                return;
+            }
+        }
+        let Some(decision) = recommend_quarantine(alert, flow) else {
+            return;
+        };
+        let action = PolicyAction {
+            id: alert.id.clone(),
+            description: alert.summary.clone(),
+            severity: alert.severity.clone(),
+            quarantine: true,
+        };
+        match self.manager.submit(action, decision) {
+            Ok(Submission::Applied { quarantine }) => {
+                info!(alert = %alert.id, quarantine = %quarantine.id, "quarantine applied")
+            }
+            Ok(Submission::Pending { pending }) => {
+                info!(alert = %alert.id, quarantine = %pending.id, "quarantine awaits approval")
+            }
+            Err(err) => warn!(alert = %alert.id, %err, "quarantine refused"),
+        }
+    }
+
+    /// Alerts are acknowledged and closed in the UI and CLI, which write
+    /// to storage; running playbooks follow along before their next steps.
+    fn sync_triage(store: &Mutex<Storage>, engine: &mut PlaybookEngine) {
+        let running = engine.running_alerts();
+        if running.is_empty() {
+            return;
+        }
+        let Ok(store) = store.lock() else {
+            warn!("storage lock poisoned; alert triage not read");
+            return;
+        };
+        for id in running {
+            match store.alert_triage(&id) {
+                Ok((status, _, _)) => engine.set_alert_status(&id, status),
+                // Not written yet: the writer stores alerts in batches.
+                Err(err) => debug!(alert = %id, %err, "alert triage not read"),
+            }
+        }
+    }
+
+    fn run_due(&mut self, now: DateTime<Utc>) {
+        if let Some(engine) = &mut self.playbooks {
+            Self::sync_triage(&self.store, engine);
+            let outcomes = engine.run_due(now);
+            if let Some(recorded) = &mut self.recorded {
+                recorded.extend(outcomes);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use analyzer::AlertStatus;
+    use collector::{Layer2EventKind, Layer2EventMetadata};
+    use storage::actions::ActionOutcome;
+
+    use super::*;
+
+    #[test]
+    fn stores_flows_before_alerting_on_them() {
+        let dir = std::env::temp_dir().join(format!("nets-daemon-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rules = dir.join("test.rules");
+        std::fs::write(
+            &rules,
+            "- id: backdoor-port\n  severity: High\n  summary: \"Backdoor port\"\n  expression: \"dst.port == 4444\"\n",
+        )
+        .unwrap();
+        let config = AnalyzerSection {
+            rules_path: rules,
+            ..AnalyzerSection::default()
+        };
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let writer = AsyncStorage::spawn(
+            Storage::open(":memory:", &[5u8; 32]).unwrap(),
+            WriterConfig::default(),
+        )
+        .unwrap();
+        let mut pipeline = {
+            let _runtime = rt.enter();
+            Pipeline::new(&config, writer.clone(), None).unwrap()
+        };
+        let now = Utc::now();
+        pipeline.process(FlowEvent {
+            ts_first: now,
+            ts_last: now,
+            proto: "tcp".into(),
+            src_ip: "10.0.0.5".into(),
+            src_port: 50123,
+            dst_ip: "198.51.100.9".into(),
+            dst_port: 4444,
+            ..FlowEvent::default()
+        });
+        pipeline.watch_gateways(&Guardrails {
+            gateways: vec!["10.0.0.1".parse().unwrap()],
+            dns_resolvers: Vec::new(),
+            critical_processes: Vec::new(),
+            own_pid: 0,
+            own_exe: None,
+            snoozes: Vec::new(),
+        });
+        for mac in ["50:c7:bf:00:00:01", "02:00:00:00:00:66"] {
+            pipeline.process(FlowEvent {
+                ts_first: now,
+                ts_last: now,
+                proto: "arp".into(),
+                layer2: Some(Layer2EventMetadata {
+                    kind: Layer2EventKind::Arp,
+                    operation: "reply".into(),
+                    mac_src: Some(mac.into()),
+                    ip_src: Some("10.0.0.1".into()),
+                    mac_dst: None,
+                    ip_dst: Some("10.0.0.5".into()),
+                }),
+                ..FlowEvent::default()
+            });
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let alerts = rt
+            .block_on(writer.query_alerts(Default::default()))
+            .unwrap();
+        let alert = alerts
+            .iter()
+            .find(|alert| alert.rule_id == "backdoor-port")
+            .unwrap();
+        let id = alert.flow_refs[0];
+        let stored = rt
+            .block_on(writer.call(move |storage| storage.get_flow(id)))
+            .unwrap();
+        let risk = stored.risk.unwrap();
+        assert_eq!(risk.rule_id.as_deref(), Some("backdoor-port"));
+        assert!(risk.score >= 60, "{risk:?}");
+
+        let spoofed = alerts
+            .iter()
+            .find(|alert| alert.rule_id == "builtin.l2.gateway_mac_change")
+            .unwrap();
+        let id = spoofed.flow_refs[0];
+        let event = rt
+            .block_on(writer.call(move |storage| storage.get_flow(id)))
+            .unwrap();
+        assert_eq!(
+            event.layer2.unwrap().mac_src.as_deref(),
+            Some("02:00:00:00:00:66")
+        );
+    }
+
+    #[test]
+    fn matches_rules_on_workers_and_correlates_on_one_analyzer() {
+        let dir = std::env::temp_dir().join(format!("nets-daemon-workers-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rules = dir.join("test.rules");
+        std::fs::write(
+            &rules,
+            "- id: smb-probe\n  severity: Low\n  expression: \"dst.port == 445\"\n",
+        )
+        .unwrap();
+        let config = AnalyzerSection {
+            rules_path: rules,
+            workers: 3,
+            ..AnalyzerSection::default()
+        };
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let writer = AsyncStorage::spawn(
+            Storage::open(":memory:", &[8u8; 32]).unwrap(),
+            WriterConfig::default(),
+        )
+        .unwrap();
+        let pipeline = {
+            let _runtime = rt.enter();
+            Pipeline::new(&config, writer.clone(), None).unwrap()
+        };
+        std::fs::remove_dir_all(&dir).unwrap();
+        let (queue, queued) = mpsc::sync_channel(64);
+        let now = Utc::now();
+        for port in 440..470 {
+            queue
+                .send(FlowEvent {
+                    ts_first: now,
+                    ts_last: now,
+                    proto: "tcp".into(),
+                    src_ip: "10.0.0.5".into(),
+                    src_port: 50123,
+                    dst_ip: "10.0.0.9".into(),
+                    dst_port: port,
+                    direction: collector::FlowDirection::Lateral,
+                    ..FlowEvent::default()
+                })
+                .unwrap();
+        }
+        drop(queue);
+
+        let stats = pipeline.run(queued, &AtomicBool::new(false));
+        assert_eq!(stats.flows, 30);
+        assert_eq!(stats.rules.get("smb-probe"), Some(&1));
+        // The ports were spread over the workers, the scan count was not.
+        assert!(
+            stats.rules.contains_key("builtin.scan.vertical"),
+            "{stats:?}"
+        );
+    }
+
+    #[test]
+    fn skips_delayed_steps_for_alerts_acknowledged_in_storage() {
+        let dir = std::env::temp_dir().join(format!("nets-daemon-triage-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rules = dir.join("test.rules");
+        std::fs::write(
+            &rules,
+            "- id: backdoor-port\n  severity: High\n  expression: \"dst.port == 4444\"\n",
+        )
+        .unwrap();
+        let playbooks = dir.join("playbooks.yaml");
+        std::fs::write(
+            &playbooks,
+            "- id: contain\n  match:\n    rule_ids: [\"backdoor-*\"]\n  steps:\n    - action: quarantine\n      after_minutes: 10\n      unless_acknowledged: true\n",
+        )
+        .unwrap();
+        let analyzer = AnalyzerSection {
+            rules_path: rules,
+            ..AnalyzerSection::default()
+        };
+        let policy = PolicySection {
+            playbooks_path: Some(playbooks),
+            ..PolicySection::default()
+        };
+        let db = dir.join("nets.db");
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let writer = AsyncStorage::spawn(
+            Storage::open(&db, &[9u8; 32]).unwrap(),
+            WriterConfig::default(),
+        )
+        .unwrap();
+        let store = Arc::new(Mutex::new(Storage::open(&db, &[9u8; 32]).unwrap()));
+        let responder = Responder::simulated(&policy, store.clone()).unwrap();
+        let mut pipeline = {
+            let _runtime = rt.enter();
+            Pipeline::new(&analyzer, writer.clone(), Some(responder)).unwrap()
+        };
+        let now = Utc::now();
+        for dst_ip in ["198.51.100.9", "198.51.100.10"] {
+            pipeline.process(FlowEvent {
+                ts_first: now,
+                ts_last: now,
+                proto: "tcp".into(),
+                src_ip: "10.0.0.5".into(),
+                src_port: 50123,
+                dst_ip: dst_ip.into(),
+                dst_port: 4444,
+                ..FlowEvent::default()
+            });
+        }
+        rt.block_on(writer.flush()).unwrap();
+        let alerts = rt
+            .block_on(writer.query_alerts(Default::default()))
+            .unwrap();
+        assert_eq!(alerts.len(), 2);
+        let (acknowledged, open) = (&alerts[0].id, &alerts[1].id);
+        store
+            .lock()
+            .unwrap()
+            .set_alert_status(acknowledged, AlertStatus::Acknowledged, None, None)
+            .unwrap();
+
+        let responder = pipeline.responder.as_mut().unwrap();
+        responder.run_due(now + Duration::minutes(11));
+        let steps = responder.recorded.take().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let outcome = |id: &String| {
+            steps
+                .iter()
+                .find(|step| &step.alert_id == id)
+                .map(|step| step.outcome)
+                .unwrap()
+        };
+        assert_eq!(outcome(acknowledged), ActionOutcome::RolledBack);
+        assert_ne!(outcome(open), ActionOutcome::RolledBack);
+    }
+
+    #[test]
+    fn follows_the_exception_list() {
+        let dir = std::env::temp_dir().join(format!("nets-exceptions-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (rules, exceptions) = (dir.join("test.rules"), dir.join("exceptions.json"));
+        std::fs::write(
+            &rules,
+            "- id: backdoor-port\n  severity: High\n  expression: \"dst.port == 4444\"\n",
+        )
+        .unwrap();
+        let config = AnalyzerSection {
+            rules_path: rules,
+            ..AnalyzerSection::default()
+        };
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let writer = AsyncStorage::spawn(
+            Storage::open(":memory:", &[7u8; 32]).unwrap(),
+            WriterConfig::default(),
+        )
+        .unwrap();
+        let mut pipeline = {
+            let _runtime = rt.enter();
+            Pipeline::new(&config, writer.clone(), None)
+                .unwrap()
+                .with_exceptions(&exceptions)
+                .unwrap()
+        };
+        let flow = |port: u16| FlowEvent {
+            proto: "tcp".into(),
+            src_ip: "10.0.0.5".into(),
+            dst_ip: "198.51.100.9".into(),
+            dst_port: port,
+            ..FlowEvent::default()
+        };
+        pipeline.process(flow(4444));
+
+        let now = Utc::now();
+        let list = serde_json::json!({ "entries": [{
+            "id": "exc-1",
+            "rule_id": "backdoor-port",
+            "destination": "198.51.100.9",
+            "expires_at": now + Duration::hours(24),
+            "created_at": now,
+            "created_by": "cli:ops",
+            "alert_id": "a1",
+        }]});
+        std::fs::write(&exceptions, list.to_string()).unwrap();
+        pipeline
+            .exceptions
+            .as_mut()
+            .unwrap()
+            .reload(&mut pipeline.analyzer)
+            .unwrap();
+        pipeline.process(flow(4444));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let alerts = rt
+            .block_on(writer.query_alerts(Default::default()))
+            .unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(pipeline.analyzer.exceptions().entries[0].hits, 1);
+        let snoozes = Guardrails::current().snoozes;
+        assert_eq!(snoozes[0].alert_id.as_deref(), Some("a1"));
+    }
+
+    #[test]
+    fn runs_due_retrospective_rules_against_storage() {
+        let dir = std::env::temp_dir().join(format!("nets-retro-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (rules, retro_rules) = (dir.join("test.rules"), dir.join("retro.rules"));
+        std::fs::write(&rules, "[]\n").unwrap();
+        std::fs::write(
+            &retro_rules,
+            "- id: retro.fanout\n  severity: Low\n  group_by: src_ip\n  distinct: dst_ip\n  threshold: 3\n",
+        )
+        .unwrap();
+        let config = AnalyzerSection {
+            rules_path: rules,
+            ..AnalyzerSection::default()
+        };
+        let store = Storage::open(":memory:", &[6u8; 32]).unwrap();
+        let start = Utc::now() - Duration::minutes(10);
+        let flows: Vec<_> = (1..=5)
+            .map(|host| FlowEvent {
+                ts_first: start,
+                ts_last: start,
+                proto: "tcp".into(),
+                src_ip: "10.0.0.5".into(),
+                dst_ip: format!("10.0.1.{host}"),
+                dst_port: 22,
+                ..FlowEvent::default()
+            })
+            .collect();
+        let ids = store.put_flows(&flows).unwrap();
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let writer = AsyncStorage::spawn(
+            Storage::open(":memory:", &[6u8; 32]).unwrap(),
+            WriterConfig::default(),
+        )
+        .unwrap();
+        let mut pipeline = {
+            let _runtime = rt.enter();
+            Pipeline::new(&config, writer.clone(), None)
+                .unwrap()
+                .with_retro(&retro_rules, Arc::new(Mutex::new(store)))
+                .unwrap()
+        };
+        std::fs::remove_dir_all(&dir).unwrap();
+        pipeline.run_retro();
+        // Not due again before the next tick.
+        pipeline.run_retro();
+
+        let alerts = rt
+            .block_on(writer.query_alerts(Default::default()))
+            .unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].rule_id, "retro.fanout");
+        assert_eq!(alerts[0].flow_refs, ids);
+    }
+}
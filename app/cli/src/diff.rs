@@ -0,0 +1,68 @@
+//! `nets-cli diff`: what appeared in one period that was not there in
+//! another — listening ports, destinations per process and LAN devices.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use storage::Period;
+
+use crate::{config::StorageSection, top::human_bytes};
+
+pub fn run(
+    config: &StorageSection,
+    before: Period,
+    after: Period,
+    limit: usize,
+    json: bool,
+) -> Result<()> {
+    let diff = crate::open_storage(config)?.diff_periods(before, after, limit)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+        return Ok(());
+    }
+    println!(
+        "new in {} compared to {} (UTC)",
+        span(&after),
+        span(&before)
+    );
+    println!("\nlisteners ({})", diff.listeners.len());
+    for listener in &diff.listeners {
+        println!(
+            "  {:<20}  {:<4} {:<5}  first {}  {} flows",
+            listener.process,
+            listener.proto,
+            listener.port,
+            time(listener.first_seen),
+            listener.flows
+        );
+    }
+    println!("\ndestinations ({})", diff.destinations.len());
+    for destination in &diff.destinations {
+        println!(
+            "  {:<20}  {:<40}  first {}  {} flows, {}",
+            destination.process.as_deref().unwrap_or("-"),
+            destination.destination,
+            time(destination.first_seen),
+            destination.flows,
+            human_bytes(destination.bytes)
+        );
+    }
+    println!("\nLAN devices ({})", diff.devices.len());
+    for device in &diff.devices {
+        println!(
+            "  {:<20}  {:<40}  first {}  {} flows",
+            device.address,
+            device.name.as_deref().unwrap_or("-"),
+            time(device.first_seen),
+            device.flows
+        );
+    }
+    Ok(())
+}
+
+fn span(period: &Period) -> String {
+    format!("{} .. {}", time(period.from), time(period.to))
+}
+
+fn time(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%d %H:%M").to_string()
+}
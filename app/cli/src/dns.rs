@@ -0,0 +1,96 @@
+//! `nets-cli dns top|nxdomain|rare`: quick triage views over the DNS
+//! queries in storage.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use storage::DomainStats;
+
+use crate::config::StorageSection;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum View {
+    Top,
+    Nxdomain,
+    Rare,
+}
+
+pub fn run(
+    config: &StorageSection,
+    view: View,
+    since: DateTime<Utc>,
+    limit: usize,
+    json: bool,
+) -> Result<()> {
+    let summary = crate::open_storage(config)?.dns_summary(since, Utc::now())?;
+    match view {
+        View::Top | View::Rare => {
+            let domains = if view == View::Top {
+                summary.top(limit)
+            } else {
+                summary.rare(limit)
+            };
+            if json {
+                println!("{}", serde_json::to_string_pretty(&domains)?);
+            } else {
+                print_domains(&domains);
+            }
+        }
+        View::Nxdomain => {
+            let sources = summary.nxdomain_sources(limit);
+            if json {
+                println!("{}", serde_json::to_string_pretty(&sources)?);
+                return Ok(());
+            }
+            if sources.is_empty() {
+                println!(
+                    "no NXDOMAIN answers since {}",
+                    since.format("%Y-%m-%d %H:%M")
+                );
+                return Ok(());
+            }
+            println!(
+                "{:<39}  {:>8}  {:>6}  {:>8}  {:<16}  {:<20}  SAMPLE",
+                "SOURCE", "NXDOMAIN", "NAMES", "PEAK/MIN", "PEAK AT", "PROCESS"
+            );
+            for source in &sources {
+                println!(
+                    "{:<39}  {:>8}  {:>6}  {:>8}  {:<16}  {:<20}  {}",
+                    source.source,
+                    source.nxdomain,
+                    source.distinct_names,
+                    source.peak_per_minute,
+                    source.peak_at.format("%Y-%m-%d %H:%M"),
+                    if source.processes.is_empty() {
+                        "-".to_string()
+                    } else {
+                        source.processes.join(",")
+                    },
+                    source.sample.join(" ")
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_domains(domains: &[DomainStats]) {
+    if domains.is_empty() {
+        println!("no DNS queries in this period");
+        return;
+    }
+    println!(
+        "{:>7}  {:>8}  {:>7}  {:<16}  {:<16}  DOMAIN",
+        "QUERIES", "NXDOMAIN", "CLIENTS", "FIRST SEEN", "LAST SEEN"
+    );
+    for domain in domains {
+        println!(
+            "{:>7}  {:>8}  {:>7}  {:<16}  {:<16}  {}",
+            domain.queries,
+            domain.nxdomain,
+            domain.clients,
+            domain.first_seen.format("%Y-%m-%d %H:%M"),
+            domain.last_seen.format("%Y-%m-%d %H:%M"),
+            domain.domain
+        );
+    }
+}
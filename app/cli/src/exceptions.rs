@@ -0,0 +1,225 @@
+//! `nets-cli exceptions`: the analyzer's allowlist. Exceptions silence an
+//! alert's rule for its process and destination for good or until they
+//! expire; snoozes expire on their own and also keep quarantines from
+//! blocking what they allow. The list lives in `[analyzer] exceptions_path`,
+//! every change is recorded in the audit log, and a running daemon picks
+//! the file up within seconds.
+
+use std::path::Path;
+
+use analyzer::exceptions::{Exception, ExceptionList};
+use anyhow::{anyhow, Result};
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use storage::{audit::AUDIT_EXCEPTION_EDIT, Storage};
+
+use crate::config::Config;
+
+/// A change to the list, for `--output json`.
+#[derive(Serialize)]
+struct Changed<'a> {
+    action: &'a str,
+    exception: &'a Exception,
+}
+
+/// The stored list; an empty one if none was saved yet.
+pub(crate) fn load(path: &Path) -> Result<ExceptionList> {
+    if path.exists() {
+        ExceptionList::load(path)
+    } else {
+        Ok(ExceptionList::default())
+    }
+}
+
+pub fn list(config: &Config, json: bool) -> Result<()> {
+    let exceptions = load(&config.analyzer.exceptions_path)?;
+    let now = Utc::now();
+    let active: Vec<_> = exceptions
+        .entries
+        .iter()
+        .filter(|exception| !exception.is_expired(now))
+        .collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&active)?);
+        return Ok(());
+    }
+    if active.is_empty() {
+        println!("no exceptions");
+        return Ok(());
+    }
+    for exception in active {
+        print_exception(exception);
+    }
+    Ok(())
+}
+
+/// Silences what the stored alert `alert_id` flagged, for good or for
+/// `expires_in`.
+pub fn allow(
+    config: &Config,
+    alert_id: &str,
+    expires_in: Option<Duration>,
+    reason: Option<String>,
+    json: bool,
+) -> Result<()> {
+    let storage = crate::open_storage(&config.storage)?;
+    let alert = storage.get_alert(alert_id)?;
+    let actor = crate::quarantine::operator();
+    let expires_at = expires_in.map(|duration| Utc::now() + duration);
+    let exception = edit(&config.analyzer.exceptions_path, &storage, |list| {
+        Ok(list.add_from_alert(&alert, expires_at, Some(&actor), reason))
+    })?;
+    print_change("added", &exception, json)
+}
+
+/// Allows what the stored alert `alert_id` flagged for `duration`.
+pub fn snooze(
+    config: &Config,
+    alert_id: &str,
+    duration: Duration,
+    reason: Option<String>,
+    json: bool,
+) -> Result<()> {
+    if duration <= Duration::zero() {
+        return Err(anyhow!("--for must be positive"));
+    }
+    let storage = crate::open_storage(&config.storage)?;
+    let alert = storage.get_alert(alert_id)?;
+    let actor = crate::quarantine::operator();
+    let exception = edit(&config.analyzer.exceptions_path, &storage, |list| {
+        Ok(list.snooze(&alert, duration, &actor, reason))
+    })?;
+    print_change("snoozed", &exception, json)
+}
+
+pub fn remove(config: &Config, id: &str, json: bool) -> Result<()> {
+    let storage = crate::open_storage(&config.storage)?;
+    let actor = crate::quarantine::operator();
+    let exception = edit(&config.analyzer.exceptions_path, &storage, |list| {
+        list.remove(id, Some(&actor)).map(|removed| removed.id)
+    })?;
+    print_change("removed", &exception, json)
+}
+
+/// Applies `change` to the list at `path`, saves it and records the entry
+/// the list added to its own trail in the audit log. `change` returns the
+/// id of the exception it touched.
+fn edit(
+    path: &Path,
+    storage: &Storage,
+    change: impl FnOnce(&mut ExceptionList) -> Result<String>,
+) -> Result<Exception> {
+    let mut list = load(path)?;
+    let before = list.entries.clone();
+    let id = change(&mut list)?;
+    let exception = list
+        .entries
+        .iter()
+        .chain(&before)
+        .find(|exception| exception.id == id)
+        .cloned()
+        .ok_or_else(|| anyhow!("unknown exception {id}"))?;
+    list.save(path)?;
+    if let Some(entry) = list.audit.last() {
+        storage.append_audit(AUDIT_EXCEPTION_EDIT, &id, &serde_json::to_value(entry)?)?;
+    }
+    Ok(exception)
+}
+
+fn print_change(action: &str, exception: &Exception, json: bool) -> Result<()> {
+    if json {
+        let changed = Changed { action, exception };
+        println!("{}", serde_json::to_string_pretty(&changed)?);
+        return Ok(());
+    }
+    println!("{action}:");
+    print_exception(exception);
+    Ok(())
+}
+
+fn print_exception(exception: &Exception) {
+    let expires = exception
+        .expires_at
+        .map(|at| format!("until {}", at.format("%Y-%m-%d %H:%M")))
+        .unwrap_or_else(|| "permanent".into());
+    println!(
+        "  {:<8}  {:<28}  {:<16}  {:<24}  {expires}, {} hits",
+        exception.id,
+        exception.rule_id.as_deref().unwrap_or("*"),
+        exception.process.as_deref().unwrap_or("*"),
+        match (&exception.destination, exception.ports.as_slice()) {
+            (Some(destination), [port, ..]) => format!("{destination}:{port}"),
+            (Some(destination), []) => destination.clone(),
+            (None, []) => "*".into(),
+            (None, ports) => format!("*:{}", ports[0]),
+        },
+        exception.hits
+    );
+    if let Some(reason) = &exception.reason {
+        println!("            {reason}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use analyzer::{Alert, AlertStatus, Severity};
+
+    use super::*;
+
+    #[test]
+    fn saves_and_audits_every_change() {
+        let storage = Storage::open(":memory:", &[8u8; 32]).unwrap();
+        let alert = Alert {
+            id: "a1".into(),
+            ts: Utc::now(),
+            severity: Severity::Medium,
+            rule_id: "builtin.first_contact".into(),
+            summary: String::new(),
+            flow_refs: Vec::new(),
+            process_ref: Some("backup-agent".into()),
+            rationale: String::new(),
+            suggested_action: None,
+            status: AlertStatus::New,
+            assignee: None,
+            notes: Vec::new(),
+            evidence: BTreeMap::from([
+                ("dst_ip".to_string(), "203.0.113.4".to_string()),
+                ("dst_port".to_string(), "443".to_string()),
+            ]),
+        };
+        storage.put_alert(&alert).unwrap();
+        let path =
+            std::env::temp_dir().join(format!("nets-exceptions-{}.json", std::process::id()));
+
+        let stored = storage.get_alert("a1").unwrap();
+        let snoozed = edit(&path, &storage, |list| {
+            Ok(list.snooze(&stored, Duration::hours(24), "cli:ops", None))
+        })
+        .unwrap();
+        assert_eq!(snoozed.destination.as_deref(), Some("203.0.113.4"));
+        assert_eq!(load(&path).unwrap().snoozes(Utc::now()).count(), 1);
+        let removed = edit(&path, &storage, |list| {
+            list.remove(&snoozed.id, Some("cli:ops"))
+                .map(|removed| removed.id)
+        })
+        .unwrap();
+        assert_eq!(removed.id, snoozed.id);
+        let unknown = edit(&path, &storage, |list| {
+            list.remove("exc-9", None).map(|removed| removed.id)
+        });
+        assert!(unknown.is_err());
+        assert!(load(&path).unwrap().entries.is_empty());
+        std::fs::remove_file(&path).unwrap();
+
+        let edits: Vec<_> = storage
+            .audit_entries(0, 10)
+            .unwrap()
+            .into_iter()
+            .filter(|entry| entry.kind == AUDIT_EXCEPTION_EDIT)
+            .collect();
+        assert_eq!(edits.len(), 2);
+        assert!(edits.iter().all(|entry| entry.subject == snoozed.id));
+    }
+}
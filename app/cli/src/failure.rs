@@ -0,0 +1,149 @@
+//! How a failed command ends: the error is sorted into a category with its
+//! own exit code, a stable error code and a remediation hint, then printed
+//! for people or, with `--output json`, as one JSON object on stderr for
+//! wrappers and installers.
+
+use std::{io, process::ExitCode};
+
+use serde::Serialize;
+
+use crate::config::ConfigError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    /// The config file is missing, unreadable or holds a bad value.
+    Config,
+    /// The command needs privileges it does not have.
+    Permission,
+    /// Anything else that went wrong while running.
+    Runtime,
+}
+
+impl Category {
+    /// 1 for runtime failures; the others follow sysexits.h (`EX_CONFIG`,
+    /// `EX_NOPERM`). Clap exits with 2 on bad arguments, and `health`
+    /// keeps its own 0/1/2.
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Category::Runtime => 1,
+            Category::Permission => 77,
+            Category::Config => 78,
+        }
+    }
+
+    /// Stable identifier for scripts to match on.
+    fn code(self) -> &'static str {
+        match self {
+            Category::Config => "config_invalid",
+            Category::Permission => "permission_denied",
+            Category::Runtime => "runtime_error",
+        }
+    }
+}
+
+/// The envelope `--output json` prints as `{"error": ...}`.
+#[derive(Debug, Serialize)]
+pub struct Failure {
+    pub code: &'static str,
+    pub category: Category,
+    pub exit_code: u8,
+    /// The error and its causes, outermost first, joined with `: `.
+    pub message: String,
+    pub hint: String,
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    error: &'a Failure,
+}
+
+impl Failure {
+    pub fn classify(err: &anyhow::Error) -> Self {
+        let config = err
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<ConfigError>());
+        // Firewall and service tools report refusals only in their output.
+        let denied = err.chain().any(|cause| {
+            let text = cause.to_string().to_ascii_lowercase();
+            cause
+                .downcast_ref::<io::Error>()
+                .is_some_and(|err| err.kind() == io::ErrorKind::PermissionDenied)
+                || [
+                    "permission denied",
+                    "operation not permitted",
+                    "access is denied",
+                ]
+                .iter()
+                .any(|refusal| text.contains(refusal))
+        });
+        let (category, hint) = match (config, denied) {
+            (Some(ConfigError { key: Some(key), .. }), _) => (
+                Category::Config,
+                format!("fix `{key}` in the config file and run the command again"),
+            ),
+            (Some(_), _) => (
+                Category::Config,
+                "check that the config file exists, is readable and is valid TOML; \
+                 pass another one with --config"
+                    .to_string(),
+            ),
+            (None, true) => (
+                Category::Permission,
+                "run as root (Administrator on Windows) or grant the missing capabilities; \
+                 `nets-cli health` lists what the collector and firewall can do"
+                    .to_string(),
+            ),
+            (None, false) => (
+                Category::Runtime,
+                "see the log lines above; `nets-cli health` checks the collector, daemon, \
+                 storage, rules and firewall"
+                    .to_string(),
+            ),
+        };
+        Self {
+            code: category.code(),
+            category,
+            exit_code: category.exit_code(),
+            message: format!("{err:#}"),
+            hint,
+        }
+    }
+
+    /// Prints the failure on stderr and returns the exit code for it.
+    pub fn report(err: &anyhow::Error, json: bool) -> ExitCode {
+        let failure = Self::classify(err);
+        let envelope = Envelope { error: &failure };
+        match serde_json::to_string(&envelope) {
+            Ok(line) if json => eprintln!("{line}"),
+            _ => eprintln!("Error: {err:?}\n\nhint: {}", failure.hint),
+        }
+        ExitCode::from(failure.exit_code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn sorts_errors_into_categories() {
+        let config = crate::config::Config::parse("[storage]\nretention_dayz = 3\n")
+            .context("invalid config c.toml")
+            .unwrap_err();
+        let failure = Failure::classify(&config);
+        assert_eq!((failure.code, failure.exit_code), ("config_invalid", 78));
+        assert!(failure.hint.contains("storage.retention_dayz"));
+
+        let denied = anyhow::Error::from(io::Error::from(io::ErrorKind::PermissionDenied))
+            .context("cannot open /sys/fs/bpf");
+        assert_eq!(Failure::classify(&denied).category, Category::Permission);
+        let refused = anyhow::anyhow!("nft add table: Operation not permitted (you must be root)");
+        assert_eq!(Failure::classify(&refused).exit_code, 77);
+
+        let runtime = Failure::classify(&anyhow::anyhow!("daemon is not running"));
+        assert_eq!(runtime.category, Category::Runtime);
+        assert_eq!(runtime.message, "daemon is not running");
+    }
+}
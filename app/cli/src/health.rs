@@ -0,0 +1,320 @@
+//! `nets-cli health`: one pass over what nets depends on (collector
+//! privileges, the running daemon, the database, the rules and the firewall
+//! backend) for monitoring scripts. The exit code follows the Nagios
+//! convention: 0 when all is well, 1 on warnings, 2 on failures.
+
+use std::path::PathBuf;
+
+use analyzer::lint::{LintIssue, RuleLinter};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use collector::capabilities::Capabilities;
+use serde::Serialize;
+
+use crate::{
+    config::{CollectorKind, Config},
+    control::DaemonStatus,
+};
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl Level {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Level::Ok => 0,
+            Level::Warn => 1,
+            Level::Fail => 2,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Ok => "ok",
+            Level::Warn => "warn",
+            Level::Fail => "fail",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Check<T> {
+    status: Level,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<T>,
+}
+
+impl<T> Check<T> {
+    fn new(status: Level, message: impl Into<String>, detail: Option<T>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            detail,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct StorageDetail {
+    path: PathBuf,
+    database_bytes: u64,
+    flows: u64,
+    alerts: u64,
+    newest_flow: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct RulesDetail {
+    path: PathBuf,
+    rules: usize,
+    problems: Vec<LintIssue>,
+}
+
+#[derive(Serialize)]
+struct PolicyDetail {
+    backend: &'static str,
+    dry_run: bool,
+}
+
+#[derive(Serialize)]
+struct Health {
+    /// The worst of the checks.
+    status: Level,
+    checked_at: DateTime<Utc>,
+    collector: Check<Capabilities>,
+    daemon: Check<DaemonStatus>,
+    storage: Check<StorageDetail>,
+    rules: Check<RulesDetail>,
+    policy: Check<PolicyDetail>,
+}
+
+/// Prints the checks and returns the overall level.
+pub fn run(config: &Config, json: bool) -> Result<Level> {
+    let daemon = crate::control::query(&config.daemon);
+    let collector = check_collector(config, daemon.as_ref().ok());
+    let daemon = match daemon {
+        Ok(status) => {
+            let level = if status.dropped > 0 {
+                Level::Warn
+            } else {
+                Level::Ok
+            };
+            Check::new(
+                level,
+                format!(
+                    "pid {} up since {}, {} flows ({} dropped)",
+                    status.pid,
+                    status.started_at.format("%Y-%m-%d %H:%M"),
+                    status.flows,
+                    status.dropped
+                ),
+                Some(status),
+            )
+        }
+        Err(err) => Check::new(Level::Fail, format!("{err:#}"), None),
+    };
+    let health = {
+        let storage = check_storage(config);
+        let rules = check_rules(config);
+        let policy = check_policy(config);
+        let status = [
+            collector.status,
+            daemon.status,
+            storage.status,
+            rules.status,
+            policy.status,
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or(Level::Ok);
+        Health {
+            status,
+            checked_at: Utc::now(),
+            collector,
+            daemon,
+            storage,
+            rules,
+            policy,
+        }
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&health)?);
+    } else {
+        let line = |name: &str, status: Level, message: &str| {
+            println!("{:<5} {name:<10} {message}", status.as_str());
+        };
+        line(
+            "collector",
+            health.collector.status,
+            &health.collector.message,
+        );
+        line("daemon", health.daemon.status, &health.daemon.message);
+        line("storage", health.storage.status, &health.storage.message);
+        line("rules", health.rules.status, &health.rules.message);
+        line("policy", health.policy.status, &health.policy.message);
+        if let Some(rules) = &health.rules.detail {
+            for problem in &rules.problems {
+                println!("      {problem}");
+            }
+        }
+        println!("status: {}", health.status.as_str());
+    }
+    Ok(health.status)
+}
+
+/// The daemon's own view when it answers; otherwise this process's, which
+/// may hold fewer privileges than the daemon would.
+fn check_collector(config: &Config, daemon: Option<&DaemonStatus>) -> Check<Capabilities> {
+    let (capabilities, source) = match daemon {
+        Some(status) => (status.collector.clone(), "the daemon"),
+        None if config.collector.backend == CollectorKind::Mock => {
+            (Capabilities::mock(), "this process")
+        }
+        None => (Capabilities::probe(), "this process"),
+    };
+    let mut missing = Vec::new();
+    if capabilities.capture == Some(false) {
+        missing.push("packet capture");
+    }
+    if capabilities.process_attribution == Some(false) {
+        missing.push("process attribution");
+    }
+    let (level, message) = if missing.is_empty() {
+        (
+            Level::Ok,
+            format!("{} collector, probed by {source}", capabilities.backend),
+        )
+    } else {
+        // Without a daemon the probe only speaks for this process.
+        let level = match daemon {
+            Some(_) if capabilities.capture == Some(false) => Level::Fail,
+            _ => Level::Warn,
+        };
+        (
+            level,
+            format!(
+                "{} collector lacks {} (probed by {source})",
+                capabilities.backend,
+                missing.join(" and ")
+            ),
+        )
+    };
+    Check::new(level, message, Some(capabilities))
+}
+
+fn check_storage(config: &Config) -> Check<StorageDetail> {
+    let path = &config.storage.path;
+    if !path.exists() {
+        return Check::new(
+            Level::Fail,
+            format!("no database at {}", path.display()),
+            None,
+        );
+    }
+    let metrics = match crate::open_storage(&config.storage).and_then(|s| s.metrics()) {
+        Ok(metrics) => metrics,
+        Err(err) => {
+            return Check::new(
+                Level::Fail,
+                format!("cannot read {}: {err:#}", path.display()),
+                None,
+            )
+        }
+    };
+    let detail = StorageDetail {
+        path: path.clone(),
+        database_bytes: metrics.database_bytes,
+        flows: metrics.rows.get("flows").copied().unwrap_or_default(),
+        alerts: metrics.rows.get("alerts").copied().unwrap_or_default(),
+        newest_flow: metrics.newest_flow,
+    };
+    let mut message = format!(
+        "{}: {}, {} flows, {} alerts",
+        path.display(),
+        crate::top::human_bytes(detail.database_bytes),
+        detail.flows,
+        detail.alerts
+    );
+    let mut level = Level::Ok;
+    if let Some(max_mb) = config.storage.max_size_mb {
+        if detail.database_bytes > max_mb * 1024 * 1024 {
+            level = Level::Warn;
+            message.push_str(&format!(", over max_size_mb = {max_mb}"));
+        }
+    }
+    Check::new(level, message, Some(detail))
+}
+
+fn check_rules(config: &Config) -> Check<RulesDetail> {
+    let path = &config.analyzer.rules_path;
+    let rules = match crate::replay::load_rules(path) {
+        Ok(rules) => rules,
+        Err(err) => return Check::new(Level::Fail, format!("{err:#}"), None),
+    };
+    let problems = lint(path).unwrap_or_else(|err| {
+        vec![LintIssue {
+            file: path.display().to_string(),
+            line: 0,
+            column: 0,
+            rule_id: None,
+            message: format!("{err:#}"),
+        }]
+    });
+    let mut message = format!("{}: {} rules", path.display(), rules.len());
+    let level = if problems.is_empty() {
+        Level::Ok
+    } else {
+        message.push_str(&format!(", {} lint problems", problems.len()));
+        Level::Warn
+    };
+    Check::new(
+        level,
+        message,
+        Some(RulesDetail {
+            path: path.clone(),
+            rules: rules.len(),
+            problems,
+        }),
+    )
+}
+
+fn lint(path: &std::path::Path) -> Result<Vec<LintIssue>> {
+    let files = if path.is_dir() {
+        crate::rule_files(path)?
+    } else {
+        vec![path.to_path_buf()]
+    };
+    let mut linter = RuleLinter::new();
+    let mut problems = Vec::new();
+    for file in files {
+        let source = std::fs::read_to_string(&file)
+            .map_err(|err| anyhow!("cannot read {}: {err}", file.display()))?;
+        problems.extend(linter.lint(&file.display().to_string(), &source));
+    }
+    Ok(problems)
+}
+
+fn check_policy(config: &Config) -> Check<PolicyDetail> {
+    let backend = policy::default_backend().name();
+    let dry_run = config.policy.dry_run;
+    let (level, message) = if backend == "noop" {
+        (
+            Level::Warn,
+            "no supported firewall found; quarantines will not be enforced".to_string(),
+        )
+    } else if dry_run {
+        (
+            Level::Warn,
+            format!("{backend}, dry run: quarantines are only audited"),
+        )
+    } else {
+        (Level::Ok, backend.to_string())
+    };
+    Check::new(level, message, Some(PolicyDetail { backend, dry_run }))
+}
@@ -0,0 +1,172 @@
+//! `nets-cli intel import|refresh|stats|remove`: the threat-intel feeds in
+//! storage. The daemon loads them when it starts; flows matching a feed
+//! raise `intel.*` alerts, which `stats` counts per feed.
+
+use std::path::Path;
+
+use analyzer::intel::{parse_feed, IntelKind};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use storage::{IntelFeed, IntelImport, Storage};
+
+use crate::config::StorageSection;
+
+const RESTART_NOTE: &str = "restart the daemon to use the new indicators";
+
+/// One feed read from its file.
+#[derive(Serialize)]
+struct Loaded {
+    #[serde(flatten)]
+    import: IntelImport,
+    kind: IntelKind,
+    /// Lines holding no indicator of the feed's kind.
+    skipped: usize,
+}
+
+#[derive(Serialize)]
+struct Removed<'a> {
+    removed: &'a str,
+}
+
+/// Imports `file` as feed `name`, by default the file name without its
+/// extension.
+pub fn import(
+    config: &StorageSection,
+    file: &Path,
+    kind: IntelKind,
+    name: Option<String>,
+    json: bool,
+) -> Result<()> {
+    let name = match name {
+        Some(name) => name,
+        None => file
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("pass --name; cannot name a feed after {}", file.display()))?,
+    };
+    // Relative paths would not resolve for a refresh from elsewhere.
+    let source =
+        std::fs::canonicalize(file).with_context(|| format!("cannot read {}", file.display()))?;
+    let storage = crate::open_storage(config)?;
+    let loaded = load(&storage, &name, kind, &source)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&loaded)?);
+        return Ok(());
+    }
+    print_loaded(&loaded);
+    println!("{RESTART_NOTE}");
+    Ok(())
+}
+
+/// Reads every feed, or the named one, again from the file it came from.
+pub fn refresh(config: &StorageSection, name: Option<&str>, json: bool) -> Result<()> {
+    let storage = crate::open_storage(config)?;
+    let feeds: Vec<IntelFeed> = storage
+        .intel_feeds()?
+        .into_iter()
+        .filter(|feed| name.is_none_or(|name| feed.name == name))
+        .collect();
+    if feeds.is_empty() {
+        return Err(match name {
+            Some(name) => anyhow!("no feed named {name}"),
+            None => anyhow!("no feeds yet; add one with `intel import`"),
+        });
+    }
+    // One unreadable file should not keep the other feeds stale.
+    let mut refreshed = Vec::new();
+    for feed in &feeds {
+        match load(&storage, &feed.name, feed.kind, Path::new(&feed.source)) {
+            Ok(loaded) if json => refreshed.push(loaded),
+            Ok(loaded) => {
+                print_loaded(&loaded);
+                refreshed.push(loaded);
+            }
+            Err(err) => eprintln!("{}: {err:#}", feed.name),
+        }
+    }
+    let failed = feeds.len() - refreshed.len();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&refreshed)?);
+    } else if !refreshed.is_empty() {
+        println!("{RESTART_NOTE}");
+    }
+    match failed {
+        0 => Ok(()),
+        _ => Err(anyhow!("{failed} of {} feeds not refreshed", feeds.len())),
+    }
+}
+
+fn load(storage: &Storage, name: &str, kind: IntelKind, source: &Path) -> Result<Loaded> {
+    let data = std::fs::read_to_string(source)
+        .with_context(|| format!("cannot read {}", source.display()))?;
+    let parsed = parse_feed(kind, &data);
+    if parsed.values.is_empty() {
+        return Err(anyhow!(
+            "{} holds no {kind} indicators ({} lines skipped)",
+            source.display(),
+            parsed.skipped
+        ));
+    }
+    let import = storage.import_intel(name, kind, &source.display().to_string(), &parsed.values)?;
+    Ok(Loaded {
+        import,
+        kind,
+        skipped: parsed.skipped,
+    })
+}
+
+fn print_loaded(loaded: &Loaded) {
+    let import = &loaded.import;
+    println!(
+        "{}: {} {} indicators, {} added, {} removed, {} lines skipped",
+        import.feed, import.entries, loaded.kind, import.added, import.removed, loaded.skipped
+    );
+}
+
+pub fn stats(config: &StorageSection, since: DateTime<Utc>, json: bool) -> Result<()> {
+    let stats = crate::open_storage(config)?.intel_stats(since)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+    if stats.is_empty() {
+        println!("no feeds yet; add one with `intel import`");
+        return Ok(());
+    }
+    println!(
+        "{:<20}  {:<6}  {:>8}  {:<16}  {:>6}  {:<16}  SOURCE",
+        "FEED", "TYPE", "ENTRIES", "IMPORTED", "HITS", "LAST HIT"
+    );
+    for feed in &stats {
+        println!(
+            "{:<20}  {:<6}  {:>8}  {:<16}  {:>6}  {:<16}  {}",
+            feed.feed.name,
+            feed.feed.kind.as_str(),
+            feed.feed.entries,
+            feed.feed.imported_at.format("%Y-%m-%d %H:%M"),
+            feed.hits,
+            feed.last_hit
+                .map(|ts| ts.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_else(|| "-".into()),
+            feed.feed.source
+        );
+    }
+    Ok(())
+}
+
+pub fn remove(config: &StorageSection, name: &str, json: bool) -> Result<()> {
+    if !crate::open_storage(config)?.remove_intel_feed(name)? {
+        return Err(anyhow!("no feed named {name}"));
+    }
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&Removed { removed: name })?
+        );
+        return Ok(());
+    }
+    println!("removed feed {name}; {RESTART_NOTE}");
+    Ok(())
+}
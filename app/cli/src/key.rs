@@ -0,0 +1,163 @@
+//! `nets-cli key`: creating, rotating and inspecting the database key in
+//! the keystore (or passphrase-wrapped key file) `[storage] key_source`
+//! points at.
+
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use storage::{crypto::key_id, keys::generate_key, Storage};
+
+use crate::config::StorageSection;
+
+#[derive(Serialize)]
+struct KeyStatus {
+    provider: &'static str,
+    /// `None` until the key is generated.
+    key_id: Option<String>,
+    database: PathBuf,
+    database_exists: bool,
+    /// Key the interrupted rotation was moving away from.
+    previous_key_id: Option<String>,
+    /// Key the interrupted rotation was moving to.
+    pending_rotation: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Generated {
+    provider: &'static str,
+    key_id: String,
+}
+
+/// Creates the key ahead of the first run. Refuses to replace an existing
+/// key, or to create one for a database sealed under a key that is gone.
+pub fn generate(config: &StorageSection, json: bool) -> Result<()> {
+    let provider = config.key_provider()?;
+    if provider.load()?.is_some() {
+        return Err(anyhow!(
+            "a database key already exists in {}; use `key rotate` to replace it",
+            provider.name()
+        ));
+    }
+    if config.path.exists() {
+        return Err(anyhow!(
+            "{} exists but its key is missing from {}; a new key cannot read it",
+            config.path.display(),
+            provider.name()
+        ));
+    }
+    let key = generate_key()?;
+    provider.store(&key)?;
+    if json {
+        let generated = Generated {
+            provider: provider.name(),
+            key_id: key_id(&key),
+        };
+        println!("{}", serde_json::to_string_pretty(&generated)?);
+        return Ok(());
+    }
+    println!("generated key {} in {}", key_id(&key), provider.name());
+    Ok(())
+}
+
+/// Re-encrypts the database under a fresh key. The outgoing key is kept
+/// in the previous-key slot until every row is re-sealed, so running this
+/// again after an interruption finishes the job.
+pub fn rotate(config: &StorageSection, json: bool) -> Result<()> {
+    if !config.path.exists() {
+        return Err(anyhow!("no database at {}", config.path.display()));
+    }
+    let provider = config.key_provider()?;
+    let previous = config.previous_key_provider()?;
+    let current = provider
+        .load()?
+        .ok_or_else(|| anyhow!("no database key in {}; nothing to rotate", provider.name()))?;
+    let (old, new) = match previous.load()? {
+        Some(old) if old != current => {
+            eprintln!("resuming the rotation to key {}", key_id(&current));
+            (old, current)
+        }
+        _ => {
+            let new = generate_key()?;
+            previous.store(&current)?;
+            provider.store(&new)?;
+            (current, new)
+        }
+    };
+    let mut storage = Storage::open_with_options(&config.path, &old, config.options())?;
+    let report = storage.rotate_key_with_progress(&old, &new, |progress| {
+        eprint!(
+            "\rre-encrypting flows {}/{}",
+            progress.rotated, progress.total
+        );
+    })?;
+    eprintln!();
+    previous.remove()?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    println!(
+        "rotated {} flows from key {} to {} in {:.1?}",
+        report.rotated, report.old_key_id, report.new_key_id, report.elapsed
+    );
+    if !report.failed.is_empty() {
+        let ids: Vec<_> = report.failed.iter().map(i64::to_string).collect();
+        println!(
+            "{} flows could not be decrypted and were left as they were: {}",
+            report.failed.len(),
+            ids.join(", ")
+        );
+    }
+    Ok(())
+}
+
+pub fn status(config: &StorageSection, json: bool) -> Result<()> {
+    let provider = config.key_provider()?;
+    let key = provider.load()?;
+    let previous = config.previous_key_provider()?.load()?;
+    let database_exists = config.path.exists();
+    let pending_rotation = match (&key, database_exists) {
+        (Some(key), true) => {
+            Storage::open_with_options(&config.path, key, config.options())?.pending_rotation()?
+        }
+        _ => None,
+    };
+    let status = KeyStatus {
+        provider: provider.name(),
+        key_id: key.as_deref().map(key_id),
+        database: config.path.clone(),
+        database_exists,
+        previous_key_id: previous.as_deref().map(key_id),
+        pending_rotation,
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(());
+    }
+    println!("provider: {}", status.provider);
+    println!(
+        "key:      {}",
+        status
+            .key_id
+            .as_deref()
+            .unwrap_or("none (run `key generate`)")
+    );
+    println!(
+        "database: {}{}",
+        status.database.display(),
+        if database_exists {
+            ""
+        } else {
+            " (not created yet)"
+        }
+    );
+    match (&status.previous_key_id, &status.pending_rotation) {
+        (None, None) => println!("rotation: none in progress"),
+        (previous, _) => println!(
+            "rotation: interrupted, from {} (run `key rotate` to finish)",
+            previous.as_deref().unwrap_or("an unknown key")
+        ),
+    }
+    Ok(())
+}
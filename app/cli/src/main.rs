@@ -1,18 +1,70 @@
-use std::sync::Arc;
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
-use analyzer::{dsl::load_rules_from_str, Analyzer};
-use anyhow::Result;
-use chrono::Duration;
-use clap::{Parser, Subcommand};
-use collector::{self, CollectorBackend, FlowEvent};
-use storage::Storage;
-use tracing::{info, warn};
+use analyzer::{
+    baseline::BaselineCommand as Lifecycle, intel::IntelKind, lint::RuleLinter, Alert, AlertStatus,
+    Severity,
+};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use collector::scenario::Scenario;
+use policy::{Direction, IpNetwork};
+use serde::Serialize;
+use storage::{
+    backup::BACKUP_PASSPHRASE_ENV,
+    keys::{KeyProvider, PassphraseKey},
+    retention::RetentionConfig,
+    AlertQuery, ExportFormat, ExportQuery, FlowQuery, Period, PolicyActionQuery, ReportFormat,
+    Storage,
+};
+
+use crate::{
+    config::{Config, StorageSection},
+    failure::Failure,
+    processes::ProcessSort,
+    top::{TopBy, TopRank},
+};
+
+mod baseline;
+mod bench;
+mod config;
+mod control;
+mod daemon;
+mod diff;
+mod dns;
+mod exceptions;
+mod failure;
+mod health;
+mod intel;
+mod key;
+mod pcap;
+mod processes;
+mod quarantine;
+mod replay;
+mod report;
+mod rule_test;
+mod service;
+mod services;
+mod simulate;
+mod tail;
+mod top;
+mod tui;
+mod watch;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Local Monitoring CLI")]
 struct Args {
-    #[arg(long, default_value = "./config/config.toml")]
-    config: String,
+    /// Defaults to ./config/config.toml when it exists
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// `json` prints every view and result as JSON (the same as `--json`
+    /// where a command has it); the field names are kept stable
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Table)]
+    output: OutputFormat,
 
     #[command(subcommand)]
     command: Command,
@@ -20,87 +72,1588 @@ struct Args {
 
 #[derive(Subcommand, Debug)]
 enum Command {
-    /// Start the collector and print flows to stdout
+    /// Run collector, analyzer, storage and policy until Ctrl+C or SIGTERM
+    Daemon,
+    /// Live flows, processes and alerts in an interactive console
     Tui,
+    /// Print live flows (or alerts) as JSON lines until Ctrl+C
+    Tail {
+        /// Alerts raised on the live flows instead of the flows
+        #[arg(long)]
+        alerts: bool,
+        /// DSL expression selecting flows, e.g. `dst.port == 443`
+        #[arg(long)]
+        filter: Option<String>,
+    },
+    /// Highlight live flows of given processes, ports or hosts until Ctrl+C
+    Watch {
+        /// Process name or executable file name, e.g. `chrome.exe`
+        #[arg(long = "proc")]
+        process: Vec<String>,
+        /// Local or remote port
+        #[arg(long)]
+        port: Vec<u16>,
+        /// Local or remote address or CIDR block
+        #[arg(long)]
+        host: Vec<IpNetwork>,
+        /// Ring the terminal bell on every match
+        #[arg(long)]
+        bell: bool,
+        /// Shell command to run on every match; the flow is in NETS_* variables
+        #[arg(long)]
+        exec: Option<String>,
+        /// Quiet period per connection after it matched, e.g. 30s or 5m
+        #[arg(long, default_value = "30s", value_parser = parse_age)]
+        cooldown: Duration,
+    },
+    /// Processes with open sockets: ports, exe, hash, signature, user
+    Processes {
+        /// Only processes listening for connections
+        #[arg(long)]
+        listening: bool,
+        #[arg(long, value_enum, default_value_t = ProcessSort::Pid)]
+        sort: ProcessSort,
+        /// Skip hashing executables
+        #[arg(long)]
+        no_hash: bool,
+        #[arg(long)]
+        json: bool,
+    },
+    /// LAN services discovered over mDNS, SSDP and DHCP
+    Services {
+        /// Look for services on the network before listing them
+        #[arg(long)]
+        scan: bool,
+        /// Keep scanning and print services as they appear, change or go away
+        #[arg(long)]
+        watch: bool,
+        /// Time between scans with --watch, e.g. 30s or 5m
+        #[arg(long, default_value = "30s", value_parser = parse_age)]
+        interval: Duration,
+        /// How long a scan waits for answers
+        #[arg(long, default_value = "3s", value_parser = parse_age)]
+        window: Duration,
+        #[arg(long, default_value_t = 200)]
+        limit: usize,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Byte and flow leaders over stored history
+    Top {
+        #[arg(long, value_enum, default_value_t = TopBy::Process)]
+        by: TopBy,
+        /// RFC 3339 time, date, or age such as `1h` or `7d`
+        #[arg(long, default_value = "1h", value_parser = parse_time)]
+        since: DateTime<Utc>,
+        #[arg(long, value_enum, default_value_t = TopRank::Bytes)]
+        rank: TopRank,
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+        #[arg(long)]
+        json: bool,
+    },
     /// List the most recent flows from storage
     Flows {
         #[arg(long, default_value_t = 10)]
         limit: usize,
     },
-    /// Evaluate DSL rules against a mock flow
+    /// Search stored flows, newest first
+    Query {
+        /// Either endpoint: address or CIDR block
+        #[arg(long)]
+        ip: Option<String>,
+        #[arg(long)]
+        src: Option<String>,
+        #[arg(long)]
+        dst: Option<String>,
+        /// Source or destination port (repeatable)
+        #[arg(long)]
+        port: Vec<u16>,
+        #[arg(long)]
+        proto: Option<String>,
+        /// Process name or its beginning, e.g. `notesync` for notesync.exe
+        #[arg(long = "proc")]
+        process: Option<String>,
+        /// RFC 3339 time, date, or age such as `2h` or `7d`
+        #[arg(long, value_parser = parse_time)]
+        since: Option<DateTime<Utc>>,
+        #[arg(long, value_parser = parse_time)]
+        until: Option<DateTime<Utc>>,
+        #[arg(long, default_value_t = 100)]
+        limit: usize,
+        /// Comma-separated columns to print
+        #[arg(
+            long,
+            value_delimiter = ',',
+            default_value = "ts_first,proto,src_ip,src_port,dst_ip,dst_port,bytes,process"
+        )]
+        columns: Vec<String>,
+        /// Print the flows as JSON objects with the selected columns
+        #[arg(long)]
+        json: bool,
+    },
+    /// List stored alerts, newest first
+    Alerts {
+        /// Any of these severities (repeatable)
+        #[arg(long)]
+        severity: Vec<Severity>,
+        /// Rule id, or a prefix ending in `*`
+        #[arg(long)]
+        rule: Option<String>,
+        /// RFC 3339 time, date, or age such as `24h` or `7d`
+        #[arg(long, value_parser = parse_time)]
+        since: Option<DateTime<Utc>>,
+        #[arg(long, value_parser = parse_time)]
+        until: Option<DateTime<Utc>>,
+        /// Any of these triage states (repeatable)
+        #[arg(long)]
+        status: Vec<AlertStatus>,
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+        /// Print the alerts as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Write stored flows or alerts to a file for external analysis
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportWhat::Flows)]
+        what: ExportWhat,
+        /// csv, json (one object per line) or parquet (built with the
+        /// `parquet` feature); taken from the file extension by default
+        #[arg(long)]
+        format: Option<ExportFormat>,
+        /// RFC 3339 time, date, or age such as `24h` or `7d`
+        #[arg(long, value_parser = parse_time)]
+        since: Option<DateTime<Utc>>,
+        #[arg(long, value_parser = parse_time)]
+        until: Option<DateTime<Utc>>,
+        /// Rows written at most (0 = all)
+        #[arg(long, default_value_t = 0)]
+        limit: usize,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Offline report of alerts, incidents, top talkers, new devices and
+    /// ATT&CK coverage
+    Report {
+        /// RFC 3339 time, date, or age such as `24h` or `7d`
+        #[arg(long, default_value = "7d", value_parser = parse_time)]
+        since: DateTime<Utc>,
+        #[arg(long, value_parser = parse_time)]
+        until: Option<DateTime<Utc>>,
+        /// html, md or pdf; taken from the file extension by default
+        #[arg(long)]
+        format: Option<ReportFormat>,
+        /// Defaults to nets-report-<time>.<ext> in the current directory
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Run a capture or NDJSON flow log through the analyzer offline
+    Replay {
+        /// `.pcap` capture, or NDJSON flows one object per line
+        input: PathBuf,
+        /// Rules file or directory of `*.rules`; defaults to `[analyzer] rules_path`
+        #[arg(long)]
+        rules: Option<PathBuf>,
+        /// Also write the flows and alerts to the database
+        #[arg(long)]
+        store: bool,
+        /// Print the alerts as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Rule file tools
+    Rules {
+        #[command(subcommand)]
+        command: RulesCommand,
+    },
+    /// Show which rules fire on which flows and run the rules' test cases
     RuleTest {
+        /// Rules file or directory of `*.rules`; defaults to `[analyzer] rules_path`
+        #[arg(long)]
+        rule_file: Option<PathBuf>,
+        /// Fixture flows (NDJSON or `.pcap`); a built-in sample flow otherwise
+        #[arg(long)]
+        flows: Option<PathBuf>,
+    },
+    /// Database maintenance
+    Db {
+        #[command(subcommand)]
+        command: DbCommand,
+    },
+    /// Create, rotate or inspect the database encryption key
+    Key {
+        #[command(subcommand)]
+        command: KeyCommand,
+    },
+    /// Run the daemon at boot under systemd, launchd or as a Windows service
+    Service {
+        #[command(subcommand)]
+        command: ServiceCommand,
+    },
+    /// Check collector privileges, the daemon, storage, rules and the
+    /// firewall; exits 0 (ok), 1 (warnings) or 2 (failures)
+    Health {
+        #[arg(long)]
+        json: bool,
+    },
+    /// Push synthetic flows through the pipeline into a throwaway
+    /// database and report throughput and drops
+    Bench {
+        #[arg(long, default_value_t = 10_000)]
+        flows_per_sec: u64,
+        #[arg(long, default_value = "60s", value_parser = parse_age)]
+        duration: Duration,
+        /// Seed of the synthetic traffic, to repeat a run
+        #[arg(long, default_value_t = 1)]
+        seed: u64,
+    },
+    /// Play a scripted attack through the pipeline (rules, intel, storage,
+    /// playbooks) and report which alerts and playbook steps it set off;
+    /// quarantines are dry runs
+    Simulate {
+        /// scan, beacon, exfil or dns-tunnel
+        scenario: Scenario,
+    },
+    /// Capture the packets of a stored flow's 5-tuple, both ways, into a
+    /// pcapng file; needs tcpdump (pktmon on Windows) and root
+    Pcap {
+        /// Flow id, as `flows` and `query` list it
+        #[arg(long)]
+        flow: i64,
+        /// How long to capture, e.g. 60s or 5m
+        #[arg(long, default_value = "60s", value_parser = parse_age)]
+        duration: Duration,
+        /// Defaults to flow-<id>.pcapng in the current directory
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Learn what is normal on this host, then alert on what is not
+    Baseline {
+        #[command(subcommand)]
+        command: BaselineCommand,
+    },
+    /// Stop alerting on what an alert flagged, for good or for a while
+    Exceptions {
+        #[command(subcommand)]
+        command: ExceptionsCommand,
+    },
+    /// List, apply and release quarantines on this host
+    Quarantine {
+        #[command(subcommand)]
+        command: QuarantineCommand,
+    },
+    /// Most queried domains, NXDOMAIN bursts and one-off names
+    Dns {
+        #[command(subcommand)]
+        command: DnsCommand,
+    },
+    /// Listeners, destinations per process and LAN devices that appeared
+    /// in one period but not in another
+    Diff {
+        /// `today`, `yesterday`, a date, an age such as `24h` (up to now)
+        /// or a range `FROM..TO` of RFC 3339 times, dates or ages
+        #[arg(long, default_value = "yesterday", value_parser = parse_period)]
+        before: Period,
+        #[arg(long, default_value = "today", value_parser = parse_period)]
+        after: Period,
+        /// Rows listed per section
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Import and refresh threat-intel feeds and see what they caught
+    Intel {
+        #[command(subcommand)]
+        command: IntelCommand,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Table,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ExportWhat {
+    Flows,
+    Alerts,
+}
+
+#[derive(Subcommand, Debug)]
+enum ServiceCommand {
+    /// Register the daemon with the service manager and start it; needs
+    /// root or an elevated prompt
+    Install {
+        /// Register it to start at boot only
+        #[arg(long)]
+        no_start: bool,
+        /// Print the unit, plist or service command line instead
+        #[arg(long)]
+        print: bool,
+    },
+    /// Stop the daemon and unregister it
+    Uninstall,
+    /// Whether the service is installed, enabled at boot and running
+    Status {
+        #[arg(long)]
+        json: bool,
+    },
+    /// Entry point the Windows service manager starts
+    #[command(hide = true)]
+    Run {
+        #[arg(long)]
+        workdir: PathBuf,
+        #[arg(long)]
+        log: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum KeyCommand {
+    /// Create the key before the first run
+    Generate,
+    /// Re-encrypt the database under a new key; rerun to finish an
+    /// interrupted rotation
+    Rotate,
+    /// Show where the key lives and whether a rotation is unfinished
+    Status {
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum DnsCommand {
+    /// Most queried domains
+    Top {
+        /// RFC 3339 time, date, or age such as `24h` or `7d`
+        #[arg(long, default_value = "24h", value_parser = parse_time)]
+        since: DateTime<Utc>,
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Hosts whose queries fail with NXDOMAIN, sharpest burst first
+    Nxdomain {
+        /// RFC 3339 time, date, or age such as `24h` or `7d`
+        #[arg(long, default_value = "24h", value_parser = parse_time)]
+        since: DateTime<Utc>,
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Domains queried only once, newest first
+    Rare {
+        /// RFC 3339 time, date, or age such as `24h` or `7d`
+        #[arg(long, default_value = "24h", value_parser = parse_time)]
+        since: DateTime<Utc>,
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum IntelCommand {
+    /// Add a feed from a plain list or CSV export, or replace its indicators
+    Import {
+        file: PathBuf,
+        #[arg(long = "type")]
+        kind: IntelKind,
+        /// Feed name; the file name without its extension by default
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Read feeds again from the files they were imported from
+    Refresh {
+        /// Only this feed
+        name: Option<String>,
+    },
+    /// Feeds with their size and the alerts they raised
+    Stats {
+        /// RFC 3339 time, date, or age such as `24h` or `7d`
+        #[arg(long, default_value = "30d", value_parser = parse_time)]
+        since: DateTime<Utc>,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Drop a feed and its indicators
+    Remove { name: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum BaselineCommand {
+    /// Start learning listeners, destinations and volumes, or resume it
+    /// keeping what was learned
+    Learn,
+    /// Stop learning; listeners, destinations and volumes outside the
+    /// baseline raise alerts
+    Freeze,
+    /// Print the learned listener and destination profiles
+    Show {
+        /// Only this process's destinations
+        #[arg(long)]
+        process: Option<String>,
+    },
+    /// Forget everything learned and stop learning
+    Reset,
+}
+
+#[derive(Subcommand, Debug)]
+enum ExceptionsCommand {
+    /// Exceptions in force
+    List,
+    /// Never alert on what a stored alert flagged again: its rule for the
+    /// same process and destination
+    Allow {
+        /// Alert id
+        alert: String,
+        /// Let it expire, e.g. `7d`
+        #[arg(long = "for", value_parser = parse_age)]
+        duration: Option<Duration>,
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Allow what a stored alert flagged for a while; quarantines do not
+    /// block it meanwhile either
+    Snooze {
+        /// Alert id
+        alert: String,
+        #[arg(long = "for", default_value = "24h", value_parser = parse_age)]
+        duration: Duration,
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Drop an exception before it expires
+    Remove {
+        /// Exception id, as `list` prints it
+        id: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum QuarantineCommand {
+    /// Quarantines in force
+    List {
+        /// Also list the rules nets has in the firewall
+        #[arg(long)]
+        rules: bool,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Block a program, ports or remote peers now
+    Apply {
+        /// Process to block (with --suspend, to stop)
+        #[arg(long)]
+        pid: Option<i32>,
+        /// Executable to block, every instance of it
+        #[arg(long)]
+        exe: Option<String>,
+        /// Port to block (repeatable); all ports of the program otherwise
+        #[arg(long)]
+        port: Vec<u16>,
+        /// Only traffic with this address or CIDR block (repeatable)
+        #[arg(long)]
+        remote: Vec<IpNetwork>,
+        /// Only traffic with this domain's addresses (repeatable)
+        #[arg(long)]
+        domain: Vec<String>,
+        /// inbound, outbound or both
+        #[arg(long, default_value = "both", value_parser = quarantine::parse_direction)]
+        direction: Direction,
+        /// How long it lasts, e.g. `30m` or `2h`; `0s` until released
+        #[arg(long = "for", default_value = "1h", value_parser = parse_age)]
+        duration: Duration,
+        /// Also stop the process while quarantined (needs --pid)
         #[arg(long)]
-        rule_file: String,
+        suspend: bool,
+        /// Print the firewall rules instead of installing them
+        #[arg(long)]
+        plan: bool,
+    },
+    /// Lift a quarantine before it expires
+    Release {
+        /// Quarantine id, or enough of its beginning to be unique
+        id: String,
+    },
+    /// Policy actions recorded in the audit table, newest first
+    History {
+        /// Only this kind of action, e.g. `quarantine` or `release`
+        #[arg(long)]
+        action: Option<String>,
+        /// RFC 3339 time, date, or age such as `24h` or `7d`
+        #[arg(long, value_parser = parse_time)]
+        since: Option<DateTime<Utc>>,
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+        #[arg(long)]
+        json: bool,
     },
 }
 
-fn main() -> Result<()> {
-    tracing_subscriber::fmt().with_env_filter("info").init();
+#[derive(Subcommand, Debug)]
+enum RulesCommand {
+    /// Check rule files for errors; fails when any are found
+    Lint {
+        /// Rule files, or directories whose `*.rules` files are checked
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Print the problems as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum DbCommand {
+    /// Run integrity, ciphertext, link-table and audit-chain checks
+    Check {
+        /// Encrypted flows to decrypt and verify (0 = all)
+        #[arg(long, default_value_t = 256)]
+        sample: usize,
+        /// Delete orphaned links and rebuild the search index
+        #[arg(long)]
+        repair: bool,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Write a consistent copy of the database to a new file
+    Backup {
+        dest: PathBuf,
+        /// Re-seal the copy under a fresh key wrapped in `<dest>.key` with
+        /// the passphrase from NETS_BACKUP_PASSPHRASE, for restoring on
+        /// another machine
+        #[arg(long)]
+        portable: bool,
+    },
+    /// Replace the database contents with a backup
+    Restore { src: PathBuf },
+    /// Summarize database size, row counts and the time span kept
+    Stats {
+        /// Print the storage metrics as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Delete history now under the `[storage]` retention settings
+    Prune {
+        /// Keep this many days instead of `retention_days`
+        #[arg(long)]
+        days: Option<u32>,
+        /// Size cap instead of `max_size_mb`
+        #[arg(long)]
+        max_size_mb: Option<u64>,
+        /// Delete old New/Acknowledged alerts too
+        #[arg(long)]
+        include_open_alerts: bool,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Rebuild the database file and give free space back to the disk
+    Vacuum {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print size, row counts and record time span
+    Metrics {
+        /// Prometheus text format instead of JSON
+        #[arg(long)]
+        prometheus: bool,
+    },
+}
+
+fn main() -> ExitCode {
     let args = Args::parse();
-    match args.command {
-        Command::Tui => run_tui(),
-        Command::Flows { limit } => show_flows(limit),
-        Command::RuleTest { rule_file } => run_rule_test(&rule_file),
+    let json = args.output == OutputFormat::Json;
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => Failure::report(&err, json),
     }
 }
 
-fn run_tui() -> Result<()> {
-    info!("starting CLI TUI mode");
-    let rt = tokio::runtime::Runtime::new()?;
-    rt.block_on(async move {
-        let backend: Arc<dyn CollectorBackend> = match collector::default_backend() {
-            Ok(backend) => backend,
-            Err(err) => {
-                warn!(error = ?err, "collector backend unavailable, using mock event generator");
-                Arc::new(collector::MockCollector::default())
+fn run(args: Args) -> Result<()> {
+    // Logs go to stderr so JSON and metrics output stays parseable; the
+    // TUI owns the terminal and shows problems itself.
+    let filter = match args.command {
+        Command::Tui => "off",
+        // Per-flow and per-alert logging would swamp the summary (and slow
+        // the pipeline being measured).
+        Command::Bench { .. } | Command::Simulate { .. } => "error",
+        _ => "info",
+    };
+    let logs = tracing_subscriber::fmt().with_env_filter(filter);
+    match &args.command {
+        // A Windows service has no console to write to.
+        Command::Service {
+            command: ServiceCommand::Run { log, .. },
+        } => logs
+            .with_ansi(false)
+            .with_writer(service::log_writer(log)?)
+            .init(),
+        _ => logs.with_writer(std::io::stderr).init(),
+    }
+    let config_path = args.config.clone().or_else(|| {
+        let default = Path::new(config::DEFAULT_PATH);
+        default.exists().then(|| default.to_path_buf())
+    });
+    let config = Config::resolve(args.config.as_deref())?;
+    let storage = &config.storage;
+    let as_json = args.output == OutputFormat::Json;
+    match args.command {
+        Command::Daemon => daemon::run(config),
+        Command::Tui => tui::run(&config),
+        Command::Tail { alerts, filter } => tail::run(&config, tail::Tail { alerts, filter }),
+        Command::Watch {
+            process,
+            port,
+            host,
+            bell,
+            exec,
+            cooldown,
+        } => watch::run(
+            &config,
+            watch::Watch {
+                processes: process,
+                ports: port,
+                hosts: host,
+                bell,
+                exec,
+                cooldown: cooldown.to_std()?,
+                json: as_json,
+            },
+        ),
+        Command::Processes {
+            listening,
+            sort,
+            no_hash,
+            json,
+        } => processes::run(processes::Processes {
+            listening,
+            sort,
+            hashes: !no_hash,
+            json: json || as_json,
+        }),
+        Command::Services {
+            scan,
+            watch,
+            interval,
+            window,
+            limit,
+            json,
+        } => services::run(
+            storage,
+            services::Services {
+                scan,
+                watch,
+                interval: interval.to_std()?,
+                window: window.to_std()?,
+                limit,
+                json: json || as_json,
+            },
+        ),
+        Command::Top {
+            by,
+            since,
+            rank,
+            limit,
+            json,
+        } => top::run(
+            storage,
+            top::Top {
+                by,
+                since,
+                rank,
+                limit,
+                json: json || as_json,
+            },
+        ),
+        Command::Flows { limit } => show_flows(storage, limit, as_json),
+        Command::Query {
+            ip,
+            src,
+            dst,
+            port,
+            proto,
+            process,
+            since,
+            until,
+            limit,
+            columns,
+            json,
+        } => {
+            let query = FlowQuery {
+                from: since,
+                to: until,
+                src,
+                dst,
+                host: ip,
+                ports: port,
+                proto,
+                process: process.map(|name| {
+                    if name.ends_with('*') {
+                        name
+                    } else {
+                        format!("{name}*")
+                    }
+                }),
+                limit,
+                ..FlowQuery::default()
+            };
+            query_flows(storage, &query, &columns, json || as_json)
+        }
+        Command::Alerts {
+            severity,
+            rule,
+            since,
+            until,
+            status,
+            limit,
+            json,
+        } => {
+            let query = AlertQuery {
+                from: since,
+                to: until,
+                severities: severity,
+                statuses: status,
+                rule_id: rule,
+                limit,
+                ..AlertQuery::default()
+            };
+            show_alerts(storage, &query, json || as_json)
+        }
+        Command::Export {
+            what,
+            format,
+            since,
+            until,
+            limit,
+            out,
+        } => {
+            let limit = if limit == 0 { usize::MAX } else { limit };
+            let query = match what {
+                ExportWhat::Flows => ExportQuery::Flows(FlowQuery {
+                    from: since,
+                    to: until,
+                    limit,
+                    ..FlowQuery::default()
+                }),
+                ExportWhat::Alerts => ExportQuery::Alerts(AlertQuery {
+                    from: since,
+                    to: until,
+                    limit,
+                    ..AlertQuery::default()
+                }),
+            };
+            export(storage, &query, format, &out, as_json)
+        }
+        Command::Report {
+            since,
+            until,
+            format,
+            out,
+        } => report::run(
+            &config,
+            report::Report {
+                since,
+                until,
+                format,
+                out,
+                json: as_json,
+            },
+        ),
+        Command::Replay {
+            input,
+            rules,
+            store,
+            json,
+        } => {
+            let replay = replay::Replay {
+                input,
+                rules: rules.unwrap_or_else(|| config.analyzer.rules_path.clone()),
+                store,
+            };
+            let report = replay::run(&config, &replay)?;
+            if json || as_json {
+                println!("{}", serde_json::to_string_pretty(&report.alerts)?);
+            } else {
+                print_alerts(&report.alerts);
+                println!(
+                    "replayed {} flows, {} alerts",
+                    report.flows,
+                    report.alerts.len()
+                );
             }
-        };
+            Ok(())
+        }
+        Command::Rules {
+            command: RulesCommand::Lint { paths, json },
+        } => lint_rules(&paths, json || as_json),
+        Command::RuleTest { rule_file, flows } => rule_test::run(
+            rule_file.as_ref().unwrap_or(&config.analyzer.rules_path),
+            flows.as_deref(),
+            as_json,
+        ),
+        Command::Db {
+            command:
+                DbCommand::Check {
+                    sample,
+                    repair,
+                    json,
+                },
+        } => check_database(storage, sample, repair, json || as_json),
+        Command::Db {
+            command: DbCommand::Backup { dest, portable },
+        } => backup_database(storage, &dest, portable, as_json),
+        Command::Db {
+            command: DbCommand::Restore { src },
+        } => restore_database(storage, &src, as_json),
+        Command::Db {
+            command: DbCommand::Stats { json },
+        } => print_stats(storage, json || as_json),
+        Command::Db {
+            command:
+                DbCommand::Prune {
+                    days,
+                    max_size_mb,
+                    include_open_alerts,
+                    json,
+                },
+        } => {
+            let mut retention = storage.retention();
+            retention.retention_days = days.or(retention.retention_days);
+            retention.max_size_mb = max_size_mb.or(retention.max_size_mb);
+            retention.keep_open_alerts &= !include_open_alerts;
+            prune_database(storage, &retention, json || as_json)
+        }
+        Command::Db {
+            command: DbCommand::Vacuum { json },
+        } => vacuum_database(storage, json || as_json),
+        Command::Db {
+            command: DbCommand::Metrics { prometheus },
+        } => print_metrics(storage, prometheus),
+        Command::Service {
+            command: ServiceCommand::Install { no_start, print },
+        } => service::install(
+            &service::Spec::current(config_path.as_deref())?,
+            service::Install {
+                start: !no_start,
+                print,
+                json: as_json,
+            },
+        ),
+        Command::Service {
+            command: ServiceCommand::Uninstall,
+        } => service::uninstall(as_json),
+        Command::Service {
+            command: ServiceCommand::Status { json },
+        } => service::status(json || as_json),
+        Command::Service {
+            command: ServiceCommand::Run { workdir, .. },
+        } => service::run(config, &workdir),
+        Command::Health { json } => {
+            let level = health::run(&config, json || as_json)?;
+            if level != health::Level::Ok {
+                std::process::exit(level.exit_code());
+            }
+            Ok(())
+        }
+        Command::Bench {
+            flows_per_sec,
+            duration,
+            seed,
+        } => bench::run(
+            &config,
+            bench::Bench {
+                flows_per_sec,
+                duration,
+                seed,
+                json: as_json,
+            },
+        ),
+        Command::Simulate { scenario } => simulate::run(&config, scenario, as_json),
+        Command::Pcap {
+            flow,
+            duration,
+            out,
+        } => pcap::run(
+            storage,
+            pcap::Pcap {
+                flow,
+                duration,
+                out,
+                json: as_json,
+            },
+        ),
+        Command::Key {
+            command: KeyCommand::Generate,
+        } => key::generate(storage, as_json),
+        Command::Key {
+            command: KeyCommand::Rotate,
+        } => key::rotate(storage, as_json),
+        Command::Key {
+            command: KeyCommand::Status { json },
+        } => key::status(storage, json || as_json),
+        Command::Baseline { command } => match command {
+            BaselineCommand::Learn => baseline::apply(&config, Lifecycle::Learn, as_json),
+            BaselineCommand::Freeze => baseline::apply(&config, Lifecycle::Freeze, as_json),
+            BaselineCommand::Show { process } => {
+                baseline::show(&config, process.as_deref(), as_json)
+            }
+            BaselineCommand::Reset => baseline::apply(&config, Lifecycle::Reset, as_json),
+        },
+        Command::Exceptions { command } => match command {
+            ExceptionsCommand::List => exceptions::list(&config, as_json),
+            ExceptionsCommand::Allow {
+                alert,
+                duration,
+                reason,
+            } => exceptions::allow(&config, &alert, duration, reason, as_json),
+            ExceptionsCommand::Snooze {
+                alert,
+                duration,
+                reason,
+            } => exceptions::snooze(&config, &alert, duration, reason, as_json),
+            ExceptionsCommand::Remove { id } => exceptions::remove(&config, &id, as_json),
+        },
+        Command::Quarantine {
+            command: QuarantineCommand::List { rules, json },
+        } => quarantine::list(&config, rules, json || as_json),
+        Command::Quarantine {
+            command:
+                QuarantineCommand::Apply {
+                    pid,
+                    exe,
+                    port,
+                    remote,
+                    domain,
+                    direction,
+                    duration,
+                    suspend,
+                    plan,
+                },
+        } => quarantine::apply(
+            &config,
+            quarantine::Apply {
+                pid,
+                exe,
+                ports: port,
+                remotes: remote,
+                domains: domain,
+                direction,
+                expires_in_seconds: duration.num_seconds().max(0) as u64,
+                suspend,
+                plan,
+            },
+            as_json,
+        ),
+        Command::Dns { command } => {
+            let (view, since, limit, json) = match command {
+                DnsCommand::Top { since, limit, json } => (dns::View::Top, since, limit, json),
+                DnsCommand::Nxdomain { since, limit, json } => {
+                    (dns::View::Nxdomain, since, limit, json)
+                }
+                DnsCommand::Rare { since, limit, json } => (dns::View::Rare, since, limit, json),
+            };
+            dns::run(storage, view, since, limit, json || as_json)
+        }
+        Command::Diff {
+            before,
+            after,
+            limit,
+            json,
+        } => diff::run(storage, before, after, limit, json || as_json),
+        Command::Intel {
+            command: IntelCommand::Import { file, kind, name },
+        } => intel::import(storage, &file, kind, name, as_json),
+        Command::Intel {
+            command: IntelCommand::Refresh { name },
+        } => intel::refresh(storage, name.as_deref(), as_json),
+        Command::Intel {
+            command: IntelCommand::Stats { since, json },
+        } => intel::stats(storage, since, json || as_json),
+        Command::Intel {
+            command: IntelCommand::Remove { name },
+        } => intel::remove(storage, &name, as_json),
+        Command::Quarantine {
+            command: QuarantineCommand::Release { id },
+        } => quarantine::release(&config, &id, as_json),
+        Command::Quarantine {
+            command:
+                QuarantineCommand::History {
+                    action,
+                    since,
+                    limit,
+                    json,
+                },
+        } => quarantine::history(
+            &config,
+            &PolicyActionQuery {
+                from: since,
+                action,
+                limit,
+                ..PolicyActionQuery::default()
+            },
+            json || as_json,
+        ),
+    }
+}
+
+/// Rows still sealed under the outgoing key of an interrupted rotation
+/// stay readable until `key rotate` is run again.
+fn open_storage(config: &StorageSection) -> Result<Storage> {
+    let mut storage = Storage::open_with_options(&config.path, &config.key()?, config.options())?;
+    if storage.pending_rotation()?.is_some() {
+        tracing::warn!("key rotation unfinished; run `key rotate` to complete it");
+        if let Some(previous) = config.previous_key_provider()?.load()? {
+            storage.add_read_key(&previous)?;
+        }
+    }
+    Ok(storage)
+}
+
+fn backup_passphrase() -> Result<String> {
+    std::env::var(BACKUP_PASSPHRASE_ENV)
+        .ok()
+        .filter(|passphrase| !passphrase.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("set {BACKUP_PASSPHRASE_ENV} to protect the backup key"))
+}
+
+fn backup_database(config: &StorageSection, dest: &Path, portable: bool, json: bool) -> Result<()> {
+    let key = config.key()?;
+    let storage = Storage::open_with_options(&config.path, &key, config.options())?;
+    let info = if portable {
+        let wrapped = PassphraseKey::new(backup_passphrase()?, dest);
+        if wrapped.load()?.is_some() {
+            return Err(anyhow::anyhow!(
+                "a key file for {} already exists",
+                dest.display()
+            ));
+        }
+        let transfer_key = storage::keys::load_or_create_key(&wrapped)?;
+        storage.backup(dest, Some((&key, &transfer_key)))?
+    } else {
+        storage.backup(dest, None)?
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+    println!(
+        "backed up {} flows and {} alerts to {} (schema {}, key {})",
+        info.flows,
+        info.alerts,
+        dest.display(),
+        info.schema_version,
+        info.key_id
+    );
+    Ok(())
+}
+
+/// Backups made with `--portable` carry their key in `<src>.key`; others
+/// are sealed under this machine's key.
+fn restore_database(config: &StorageSection, src: &Path, json: bool) -> Result<()> {
+    let key = config.key()?;
+    let mut storage = Storage::open_with_options(&config.path, &key, config.options())?;
+    let mut key_file = src.as_os_str().to_owned();
+    key_file.push(".key");
+    let backup_key = if Path::new(&key_file).exists() {
+        PassphraseKey::new(backup_passphrase()?, src)
+            .load()?
+            .ok_or_else(|| anyhow::anyhow!("key file for {} disappeared", src.display()))?
+    } else {
+        key.clone()
+    };
+    let info = storage.restore(src, &backup_key, &key)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+    println!(
+        "restored {} flows and {} alerts from backup taken {}",
+        info.flows, info.alerts, info.created_at
+    );
+    Ok(())
+}
 
-        backend.subscribe(Arc::new(|flow: FlowEvent| {
-            println!(
-                "{:?} {}:{} -> {}:{} bytes={}",
-                flow.state, flow.src_ip, flow.src_port, flow.dst_ip, flow.dst_port, flow.bytes
-            );
-        }));
-        backend.start().await?;
-        info!(message = "collector running. press Ctrl+C to stop");
-        tokio::signal::ctrl_c().await?;
-        backend.stop().await?;
-        Ok(())
-    })
-}
-
-fn show_flows(limit: usize) -> Result<()> {
-    let storage = Storage::open("./nets.db", &[0u8; 32])?;
-    let flows = storage.query_flows(limit)?;
+fn show_flows(config: &StorageSection, limit: usize, json: bool) -> Result<()> {
+    let storage = open_storage(config)?;
+    let flows = storage.query_flows(&FlowQuery {
+        limit,
+        ..FlowQuery::default()
+    })?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&flows)?);
+        return Ok(());
+    }
     for flow in flows {
         println!(
-            "#{} {} {}:{} -> {}:{} bytes={}",
-            flow.id, flow.proto, flow.src_ip, flow.src_port, flow.dst_ip, flow.dst_port, flow.bytes
+            "#{} {} {}:{} -> {}:{} bytes={}{}",
+            flow.id,
+            flow.proto,
+            flow.src_ip,
+            flow.src_port,
+            flow.dst_ip,
+            flow.dst_port,
+            flow.bytes,
+            if flow.tampered { " [TAMPERED]" } else { "" }
         );
     }
     Ok(())
 }
 
-fn run_rule_test(path: &str) -> Result<()> {
-    let data = std::fs::read_to_string(path)?;
-    let rules = load_rules_from_str(&data)?;
-    let mut analyzer = Analyzer::new(Duration::hours(1), rules);
-    let mock_flow = normalizer::NormalizedFlow {
-        window_start: chrono::Utc::now(),
-        window_end: chrono::Utc::now(),
-        proto: "TCP".into(),
-        src_ip: "10.0.0.5".into(),
-        src_port: 51515,
-        dst_ip: "10.0.0.8".into(),
-        dst_port: 445,
-        direction: collector::FlowDirection::Lateral,
-        bytes: 4096,
-        packets: 12,
-        process: Some("notesync.exe".into()),
+/// Columns `query` can print: the fields of [`storage::StoredFlow`].
+const FLOW_COLUMNS: [&str; 13] = [
+    "id",
+    "ts_first",
+    "ts_last",
+    "proto",
+    "src_ip",
+    "src_port",
+    "dst_ip",
+    "dst_port",
+    "bytes",
+    "direction",
+    "process",
+    "domain",
+    "tampered",
+];
+
+fn query_flows(
+    config: &StorageSection,
+    query: &FlowQuery,
+    columns: &[String],
+    json: bool,
+) -> Result<()> {
+    if let Some(unknown) = columns
+        .iter()
+        .find(|column| !FLOW_COLUMNS.contains(&column.as_str()))
+    {
+        return Err(anyhow!(
+            "unknown column {unknown}; expected any of {}",
+            FLOW_COLUMNS.join(", ")
+        ));
+    }
+    let flows = open_storage(config)?.query_flows(query)?;
+    let mut rows = Vec::with_capacity(flows.len());
+    for flow in &flows {
+        let serde_json::Value::Object(mut fields) = serde_json::to_value(flow)? else {
+            return Err(anyhow!("flow {} is not a JSON object", flow.id));
+        };
+        let row: serde_json::Map<_, _> = columns
+            .iter()
+            .map(|column| {
+                let value = fields.remove(column).unwrap_or_default();
+                (column.clone(), value)
+            })
+            .collect();
+        rows.push(row);
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+        return Ok(());
+    }
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| columns.iter().map(|column| cell(&row[column])).collect())
+        .collect();
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(index, column)| {
+            cells
+                .iter()
+                .map(|row| row[index].len())
+                .fold(column.len(), usize::max)
+        })
+        .collect();
+    let line = |values: Vec<String>| {
+        let padded: Vec<String> = values
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{value:<width$}"))
+            .collect();
+        println!("{}", padded.join("  ").trim_end());
+    };
+    line(columns.iter().map(|column| column.to_uppercase()).collect());
+    for row in cells {
+        line(row);
+    }
+    Ok(())
+}
+
+fn cell(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(text) => text.clone(),
+        serde_json::Value::Null => "-".into(),
+        other => other.to_string(),
+    }
+}
+
+fn show_alerts(config: &StorageSection, query: &AlertQuery, json: bool) -> Result<()> {
+    let alerts = open_storage(config)?.query_alerts(query)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&alerts)?);
+        return Ok(());
+    }
+    print_alerts(&alerts);
+    Ok(())
+}
+
+fn print_alerts(alerts: &[Alert]) {
+    if alerts.is_empty() {
+        println!("no alerts");
+        return;
+    }
+    println!(
+        "{:<19}  {:<8}  {:<13}  {:<28}  SUMMARY",
+        "TIME", "SEVERITY", "STATUS", "RULE"
+    );
+    for alert in alerts {
+        println!(
+            "{:<19}  {:<8}  {:<13}  {:<28}  {}",
+            alert.ts.format("%Y-%m-%d %H:%M:%S"),
+            alert.severity.as_str(),
+            alert.status.as_str(),
+            alert.rule_id,
+            alert.summary
+        );
+    }
+}
+
+/// What `export` wrote, for `--output json`.
+#[derive(Serialize)]
+struct Written<'a> {
+    path: &'a Path,
+    rows: usize,
+}
+
+fn export(
+    config: &StorageSection,
+    query: &ExportQuery,
+    format: Option<ExportFormat>,
+    out: &Path,
+    json: bool,
+) -> Result<()> {
+    let format = match format {
+        Some(format) => format,
+        None => out
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .ok_or_else(|| anyhow!("pass --format; {} has no extension", out.display()))?
+            .parse()?,
     };
-    for alert in analyzer.ingest(mock_flow) {
-        println!("Alert {} severity {:?}", alert.id, alert.severity);
+    let rows = open_storage(config)?.export(query, format, out)?;
+    if json {
+        let exported = Written { path: out, rows };
+        println!("{}", serde_json::to_string_pretty(&exported)?);
+        return Ok(());
     }
+    println!("exported {rows} rows to {}", out.display());
     Ok(())
 }
+
+/// RFC 3339, a date (midnight UTC), or an age back from now: `90s`,
+/// `30m`, `24h`, `7d`, `2w`.
+fn parse_time(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_time(NaiveTime::MIN).and_utc());
+    }
+    parse_age(value)
+        .map(|age| Utc::now() - age)
+        .map_err(|_| anyhow!("expected an RFC 3339 time, a date or an age like 24h: {value}"))
+}
+
+/// `today` and `yesterday` (UTC days), a whole date, an age counted back
+/// from now, or `FROM..TO` where either side is a time for [`parse_time`]
+/// and a missing `TO` means now.
+fn parse_period(value: &str) -> Result<Period> {
+    let midnight = Utc::now().date_naive().and_time(NaiveTime::MIN).and_utc();
+    let (from, to) = match value {
+        "today" => (midnight, Utc::now()),
+        "yesterday" => (midnight - Duration::days(1), midnight),
+        _ => match value.split_once("..") {
+            Some((from, "")) => (parse_time(from)?, Utc::now()),
+            Some((from, to)) => (parse_time(from)?, parse_time(to)?),
+            None => match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+                Ok(date) => {
+                    let from = date.and_time(NaiveTime::MIN).and_utc();
+                    (from, from + Duration::days(1))
+                }
+                Err(_) => (
+                    Utc::now()
+                        - parse_age(value).map_err(|_| {
+                            anyhow!("expected today, yesterday, a date, an age like 24h or FROM..TO: {value}")
+                        })?,
+                    Utc::now(),
+                ),
+            },
+        },
+    };
+    if from >= to {
+        return Err(anyhow!("{value} ends before it starts"));
+    }
+    Ok(Period { from, to })
+}
+
+/// A span such as `90s`, `30m`, `24h`, `7d` or `2w`.
+fn parse_age(value: &str) -> Result<Duration> {
+    let invalid = || anyhow!("expected a number and a unit (s, m, h, d, w): {value}");
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let amount: i64 = value[..split].parse().map_err(|_| invalid())?;
+    match &value[split..] {
+        "s" => Ok(Duration::seconds(amount)),
+        "m" => Ok(Duration::minutes(amount)),
+        "h" => Ok(Duration::hours(amount)),
+        "d" => Ok(Duration::days(amount)),
+        "w" => Ok(Duration::weeks(amount)),
+        _ => Err(invalid()),
+    }
+}
+
+fn check_database(config: &StorageSection, sample: usize, repair: bool, json: bool) -> Result<()> {
+    let storage = open_storage(config)?;
+    let report = storage.check(&storage::CheckOptions { sample, repair })?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        if report.integrity_errors.is_empty() {
+            println!("integrity: ok");
+        } else {
+            println!("integrity: {} error(s)", report.integrity_errors.len());
+            for message in &report.integrity_errors {
+                println!("  {message}");
+            }
+        }
+        println!(
+            "flows: {} sampled, {} unreadable",
+            report.flows_sampled,
+            report.flow_problems.len()
+        );
+        for problem in &report.flow_problems {
+            let label = if problem.tampering {
+                "tampered"
+            } else {
+                "unreadable"
+            };
+            println!("  #{} {label}: {}", problem.id, problem.error);
+        }
+        println!(
+            "orphaned links: {} alert-flow, {} incident-alert{}",
+            report.orphans.alert_flows,
+            report.orphans.incident_alerts,
+            if report.repaired && report.orphans.total() > 0 {
+                " (deleted)"
+            } else {
+                ""
+            }
+        );
+        println!(
+            "search index: {}",
+            match (report.search_index_ok, report.repaired) {
+                (true, _) => "ok",
+                (false, true) => "rebuilt",
+                (false, false) => "damaged (run with --repair)",
+            }
+        );
+        println!(
+            "audit log: {} entries, {}",
+            report.audit.entries,
+            if report.audit.is_intact() {
+                "intact".to_string()
+            } else {
+                format!("{} problem(s)", report.audit.problems.len())
+            }
+        );
+        for problem in &report.audit.problems {
+            println!("  {problem:?}");
+        }
+    }
+    if !report.is_healthy() {
+        return Err(anyhow::anyhow!("database check found problems"));
+    }
+    Ok(())
+}
+
+fn print_metrics(config: &StorageSection, prometheus: bool) -> Result<()> {
+    let metrics = open_storage(config)?.metrics()?;
+    if prometheus {
+        print!("{}", metrics.to_prometheus());
+    } else {
+        println!("{}", serde_json::to_string_pretty(&metrics)?);
+    }
+    Ok(())
+}
+
+fn print_stats(config: &StorageSection, json: bool) -> Result<()> {
+    let metrics = open_storage(config)?.metrics()?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&metrics)?);
+        return Ok(());
+    }
+    println!("database: {}", config.path.display());
+    println!(
+        "size:     {} on disk, {} in use",
+        megabytes(metrics.database_bytes),
+        megabytes(metrics.used_bytes)
+    );
+    let span = |oldest: Option<DateTime<Utc>>, newest: Option<DateTime<Utc>>| match (oldest, newest)
+    {
+        (Some(oldest), Some(newest)) => format!(
+            "{} .. {} ({} days)",
+            oldest.format("%Y-%m-%d %H:%M"),
+            newest.format("%Y-%m-%d %H:%M"),
+            (newest - oldest).num_days()
+        ),
+        _ => "empty".into(),
+    };
+    println!(
+        "flows:    {}",
+        span(metrics.oldest_flow, metrics.newest_flow)
+    );
+    println!(
+        "alerts:   {}",
+        span(metrics.oldest_alert, metrics.newest_alert)
+    );
+    println!("rows:");
+    for (table, rows) in &metrics.rows {
+        println!("  {table:<18} {rows:>12}");
+    }
+    Ok(())
+}
+
+fn prune_database(config: &StorageSection, retention: &RetentionConfig, json: bool) -> Result<()> {
+    let report = open_storage(config)?.prune(retention)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!(
+            "deleted {} flows and {} alerts in {:.1?}",
+            report.flows_deleted, report.alerts_deleted, report.elapsed
+        );
+        println!(
+            "size: {} -> {} ({} reclaimed)",
+            megabytes(report.bytes_before),
+            megabytes(report.bytes_after),
+            megabytes(report.reclaimed_bytes())
+        );
+    }
+    Ok(())
+}
+
+fn vacuum_database(config: &StorageSection, json: bool) -> Result<()> {
+    let report = open_storage(config)?.vacuum()?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!(
+            "size: {} -> {} ({} reclaimed) in {:.1?}",
+            megabytes(report.bytes_before),
+            megabytes(report.bytes_after),
+            megabytes(report.reclaimed_bytes()),
+            report.elapsed
+        );
+    }
+    Ok(())
+}
+
+fn megabytes(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+/// `*.rules` files in `dir`, sorted.
+fn rule_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map_err(|err| anyhow!("cannot read rules {}: {err}", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<_>>()?;
+    files.retain(|file| {
+        file.extension()
+            .is_some_and(|extension| extension == "rules")
+    });
+    files.sort();
+    Ok(files)
+}
+
+fn lint_rules(paths: &[PathBuf], json: bool) -> Result<()> {
+    let mut linter = RuleLinter::new();
+    let mut issues = Vec::new();
+    let mut checked = 0;
+    for path in paths {
+        let files = if path.is_dir() {
+            rule_files(path)?
+        } else {
+            vec![path.clone()]
+        };
+        for file in files {
+            let source = std::fs::read_to_string(&file)
+                .map_err(|err| anyhow!("cannot read {}: {err}", file.display()))?;
+            issues.extend(linter.lint(&file.display().to_string(), &source));
+            checked += 1;
+        }
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&issues)?);
+    } else {
+        for issue in &issues {
+            println!("{issue}");
+        }
+        println!("{checked} files checked, {} problems", issues.len());
+    }
+    if !issues.is_empty() {
+        return Err(anyhow!("rule lint found problems"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_times_and_ages() {
+        assert_eq!(
+            parse_time("2026-03-01T12:00:00+02:00")
+                .unwrap()
+                .to_rfc3339(),
+            "2026-03-01T10:00:00+00:00"
+        );
+        assert_eq!(
+            parse_time("2026-03-01").unwrap().to_rfc3339(),
+            "2026-03-01T00:00:00+00:00"
+        );
+        let age = Utc::now() - parse_time("24h").unwrap();
+        assert!((age - Duration::hours(24)).num_seconds().abs() < 5);
+        for invalid in ["", "24", "h", "24y", "yesterday"] {
+            assert!(parse_time(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn parses_periods() {
+        let yesterday = parse_period("yesterday").unwrap();
+        let today = parse_period("today").unwrap();
+        assert_eq!(yesterday.to, today.from);
+        assert_eq!(yesterday.to - yesterday.from, Duration::days(1));
+        let range = parse_period("2026-03-01..2026-03-03").unwrap();
+        assert_eq!(range.to - range.from, Duration::days(2));
+        assert_eq!(
+            parse_period("2026-03-01").unwrap().to,
+            range.from + Duration::days(1)
+        );
+        assert!(parse_period("2026-03-03..2026-03-01").is_err());
+        assert!(parse_period("last week").is_err());
+    }
+}
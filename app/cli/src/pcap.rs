@@ -0,0 +1,78 @@
+//! `nets-cli pcap`: packet evidence for a stored flow — its 5-tuple is
+//! captured, both directions, for a set time into a pcapng file that
+//! Wireshark and other tools open as is.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Duration;
+use collector::capture::{self, CaptureStats, FlowFilter};
+use serde::Serialize;
+
+use crate::config::StorageSection;
+
+pub struct Pcap {
+    pub flow: i64,
+    pub duration: Duration,
+    /// `flow-<id>.pcapng` in the current directory by default.
+    pub out: Option<PathBuf>,
+    pub json: bool,
+}
+
+/// What was captured, for `--output json`.
+#[derive(Serialize)]
+struct Captured<'a> {
+    flow: i64,
+    /// The BPF filter the capture ran with.
+    filter: String,
+    path: &'a Path,
+    seconds: i64,
+    #[serde(flatten)]
+    stats: CaptureStats,
+}
+
+pub fn run(config: &StorageSection, pcap: Pcap) -> Result<()> {
+    let wait = pcap
+        .duration
+        .to_std()
+        .ok()
+        .filter(|wait| !wait.is_zero())
+        .ok_or_else(|| anyhow!("--duration must be positive"))?;
+    let flow = crate::open_storage(config)?
+        .get_flow(pcap.flow)
+        .with_context(|| format!("cannot capture flow #{}", pcap.flow))?;
+    let filter = FlowFilter::of(&flow)?;
+    let out = pcap
+        .out
+        .unwrap_or_else(|| PathBuf::from(format!("flow-{}.pcapng", pcap.flow)));
+    // The flow may have ended; only packets sent from now on are caught.
+    eprintln!(
+        "capturing {filter} for {}s into {} ...",
+        pcap.duration.num_seconds(),
+        out.display()
+    );
+    let stats = capture::capture(&filter, wait, &out)
+        .with_context(|| format!("capturing flow #{}", pcap.flow))?;
+    if pcap.json {
+        let captured = Captured {
+            flow: pcap.flow,
+            filter: filter.bpf(),
+            path: &out,
+            seconds: pcap.duration.num_seconds(),
+            stats,
+        };
+        println!("{}", serde_json::to_string_pretty(&captured)?);
+        return Ok(());
+    }
+    println!(
+        "wrote {} packets ({} bytes) of flow #{} to {}",
+        stats.packets,
+        stats.bytes,
+        pcap.flow,
+        out.display()
+    );
+    if stats.packets == 0 {
+        println!("the connection sent nothing while capturing; it may have closed");
+    }
+    Ok(())
+}
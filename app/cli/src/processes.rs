@@ -0,0 +1,118 @@
+//! `nets-cli processes`: processes with sockets open right now, with what
+//! they listen on and who they are (exe, hash, signature, user).
+
+use anyhow::Result;
+use clap::ValueEnum;
+use collector::process::{ListeningSocket, ProcessInfo, ProcessInfoCollector};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProcessSort {
+    Pid,
+    Name,
+    User,
+    /// Most listening sockets first
+    Ports,
+    /// Most other sockets first
+    Connections,
+}
+
+pub struct Processes {
+    /// Only processes with a listening socket.
+    pub listening: bool,
+    pub sort: ProcessSort,
+    pub hashes: bool,
+    pub json: bool,
+}
+
+pub fn run(options: Processes) -> Result<()> {
+    let mut snapshot = ProcessInfoCollector::new()
+        .with_hashes(options.hashes)
+        .snapshot()?;
+    if options.listening {
+        snapshot
+            .processes
+            .retain(|process| !process.listening.is_empty());
+    }
+    sort(&mut snapshot.processes, options.sort);
+    if options.json {
+        println!("{}", serde_json::to_string_pretty(&snapshot)?);
+        return Ok(());
+    }
+    println!(
+        "{:>7}  {:<12}  {:<16}  {:<24}  {:>5}  {:<16}  {:<6}  EXE",
+        "PID", "USER", "NAME", "LISTENING", "CONN", "SHA256", "SIGNED"
+    );
+    for process in &snapshot.processes {
+        let identity = &process.identity;
+        println!(
+            "{:>7}  {:<12}  {:<16}  {:<24}  {:>5}  {:<16}  {:<6}  {}",
+            identity.pid,
+            identity.user.as_deref().unwrap_or("-"),
+            identity.name.as_deref().unwrap_or("-"),
+            listeners(&process.listening),
+            process.connections,
+            identity.sha256_16.as_deref().unwrap_or("-"),
+            match identity.signed {
+                Some(true) => "yes",
+                Some(false) => "no",
+                None => "-",
+            },
+            identity.exe_path.as_deref().unwrap_or("-")
+        );
+    }
+    if !snapshot.unattributed.is_empty() {
+        println!();
+        println!(
+            "listening without a visible owner (run as root to see them): {}",
+            listeners(&snapshot.unattributed)
+        );
+    }
+    Ok(())
+}
+
+fn sort(processes: &mut [ProcessInfo], key: ProcessSort) {
+    match key {
+        ProcessSort::Pid => processes.sort_by_key(|process| process.identity.pid),
+        ProcessSort::Name => processes.sort_by(|a, b| {
+            a.identity
+                .name
+                .cmp(&b.identity.name)
+                .then(a.identity.pid.cmp(&b.identity.pid))
+        }),
+        ProcessSort::User => processes.sort_by(|a, b| {
+            a.identity
+                .user
+                .cmp(&b.identity.user)
+                .then(a.identity.pid.cmp(&b.identity.pid))
+        }),
+        ProcessSort::Ports => processes.sort_by(|a, b| {
+            b.listening
+                .len()
+                .cmp(&a.listening.len())
+                .then(a.identity.pid.cmp(&b.identity.pid))
+        }),
+        ProcessSort::Connections => processes.sort_by(|a, b| {
+            b.connections
+                .cmp(&a.connections)
+                .then(a.identity.pid.cmp(&b.identity.pid))
+        }),
+    }
+}
+
+/// `22/tcp,127.0.0.1:631/tcp`: the address only when it is not a wildcard.
+fn listeners(sockets: &[ListeningSocket]) -> String {
+    if sockets.is_empty() {
+        return "-".into();
+    }
+    sockets
+        .iter()
+        .map(|socket| {
+            let proto = socket.proto.to_ascii_lowercase();
+            match socket.address.as_str() {
+                "0.0.0.0" | "::" => format!("{}/{proto}", socket.port),
+                address => format!("{address}:{}/{proto}", socket.port),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
@@ -0,0 +1,433 @@
+//! `nets-cli quarantine`: listing, applying and lifting quarantines from a
+//! terminal, through the same firewall backend and quarantine table as the
+//! daemon. Nothing talks to a running daemon: it learns about quarantines
+//! applied or released here on its next start, and until then expiry of
+//! the ones applied here is handled by the next `quarantine` command.
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use collector::ProcessIdentity;
+use policy::{
+    is_targetable, validate_decision, ActiveQuarantine, Direction, IpNetwork, QuarantineDecision,
+    QuarantineManager,
+};
+use serde::Serialize;
+use storage::{ActionOutcome, PolicyActionQuery, PolicyActionRecord, Storage};
+use tracing::warn;
+
+use crate::config::Config;
+
+/// What `quarantine apply` blocks.
+pub struct Apply {
+    pub pid: Option<i32>,
+    pub exe: Option<String>,
+    pub ports: Vec<u16>,
+    pub remotes: Vec<IpNetwork>,
+    pub domains: Vec<String>,
+    pub direction: Direction,
+    /// 0 keeps the quarantine until it is released.
+    pub expires_in_seconds: u64,
+    pub suspend: bool,
+    /// Print the firewall rules instead of installing them.
+    pub plan: bool,
+}
+
+impl Apply {
+    fn decision(&self) -> QuarantineDecision {
+        let identity = (self.pid.is_some() || self.exe.is_some()).then(|| ProcessIdentity {
+            pid: self.pid.unwrap_or(0),
+            ppid: None,
+            name: self.exe.as_deref().and_then(|exe| {
+                Path::new(exe)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            }),
+            exe_path: self.exe.clone(),
+            sha256_16: None,
+            user: None,
+            signed: None,
+        });
+        QuarantineDecision {
+            process: identity.as_ref().and_then(|identity| identity.name.clone()),
+            identity,
+            ports: self.ports.clone(),
+            expires_in_seconds: self.expires_in_seconds,
+            suspend: self.suspend,
+            direction: self.direction,
+            remote_networks: self.remotes.clone(),
+            remote_domains: self.domains.clone(),
+        }
+    }
+}
+
+pub fn parse_direction(value: &str) -> Result<Direction> {
+    match value.to_ascii_lowercase().as_str() {
+        "inbound" | "in" => Ok(Direction::Inbound),
+        "outbound" | "out" => Ok(Direction::Outbound),
+        "both" => Ok(Direction::Both),
+        other => Err(anyhow!("expected inbound, outbound or both: {other}")),
+    }
+}
+
+#[derive(Serialize)]
+struct Listing {
+    quarantines: Vec<ActiveQuarantine>,
+    /// Every rule nets has in the firewall, read back from it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rules: Option<Vec<policy::AppliedRule>>,
+}
+
+/// Stored quarantines; with `rules`, also what the firewall actually holds.
+/// Reads only, so expired quarantines are listed until something lifts them.
+pub fn list(config: &Config, rules: bool, json: bool) -> Result<()> {
+    let storage = crate::open_storage(&config.storage)?;
+    let mut quarantines = Vec::new();
+    for record in storage.list_quarantines()? {
+        match serde_json::from_value::<ActiveQuarantine>(record.quarantine) {
+            Ok(quarantine) => quarantines.push(quarantine),
+            Err(err) => warn!(id = %record.id, %err, "unreadable stored quarantine"),
+        }
+    }
+    let rules = if rules {
+        let store = Arc::new(Mutex::new(storage));
+        Some(crate::daemon::policy_backend(&config.policy, &store).list_active()?)
+    } else {
+        None
+    };
+    let listing = Listing { quarantines, rules };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&listing)?);
+        return Ok(());
+    }
+    if listing.quarantines.is_empty() {
+        println!("no active quarantines");
+    } else {
+        println!("{:<36}  {:<19}  {:<12}  TARGET", "ID", "APPLIED", "EXPIRES");
+        let now = Utc::now();
+        for quarantine in &listing.quarantines {
+            let expires = match quarantine.expires_at {
+                Some(at) if at <= now => "due".to_string(),
+                Some(at) => format!("in {}", remaining(at - now)),
+                None => "never".to_string(),
+            };
+            println!(
+                "{:<36}  {:<19}  {:<12}  {}",
+                quarantine.id,
+                quarantine.applied_at.format("%Y-%m-%d %H:%M:%S"),
+                expires,
+                describe(&quarantine.decision)
+            );
+        }
+    }
+    if let Some(rules) = &listing.rules {
+        println!();
+        println!("{} firewall rules", rules.len());
+        for rule in rules {
+            println!("  {} [{}] {}", rule.handle, rule.location, rule.rule);
+        }
+    }
+    Ok(())
+}
+
+pub fn apply(config: &Config, apply: Apply, json: bool) -> Result<()> {
+    let decision = apply.decision();
+    validate_decision(&decision)?;
+    let store = Arc::new(Mutex::new(crate::open_storage(&config.storage)?));
+    let backend = crate::daemon::policy_backend(&config.policy, &store);
+    let commands = backend.plan(&decision)?;
+    if apply.plan && json {
+        println!("{}", serde_json::to_string_pretty(&commands)?);
+        return Ok(());
+    }
+    if apply.plan {
+        for command in &commands {
+            println!("{command}");
+        }
+        return Ok(());
+    }
+    let backend_name = backend.name();
+    let manager = take_over(backend, &store)?;
+    let result = manager.apply(decision.clone());
+    let id = result
+        .as_ref()
+        .map(|quarantine| quarantine.id.as_str())
+        .ok();
+    // A dry-run backend has logged the decision itself.
+    if !config.policy.dry_run {
+        let mut record = action_record("quarantine", &decision, id, backend_name);
+        record.commands = commands;
+        match &result {
+            Ok(_) => {
+                record.applied_at = Some(record.ts);
+                record.outcome = ActionOutcome::Applied;
+            }
+            Err(err) => {
+                record.outcome = ActionOutcome::Failed;
+                record.error = Some(format!("{err:#}"));
+            }
+        }
+        log_action(&store, &record);
+    }
+    let quarantine = result?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&quarantine)?);
+        return Ok(());
+    }
+    println!(
+        "quarantine {} applied: {}{}",
+        quarantine.id,
+        describe(&quarantine.decision),
+        match quarantine.expires_at {
+            Some(at) => format!(", expires {}", at.format("%Y-%m-%d %H:%M:%S UTC")),
+            None => ", until released".to_string(),
+        }
+    );
+    Ok(())
+}
+
+/// Lifts the quarantine whose id is or starts with `id`.
+pub fn release(config: &Config, id: &str, json: bool) -> Result<()> {
+    let store = Arc::new(Mutex::new(crate::open_storage(&config.storage)?));
+    let backend = crate::daemon::policy_backend(&config.policy, &store);
+    let backend_name = backend.name();
+    let manager = take_over(backend, &store)?;
+    let quarantine = find(&manager, id)?;
+    let result = manager.release(&quarantine.id);
+    if !config.policy.dry_run {
+        let mut record = action_record(
+            "release",
+            &quarantine.decision,
+            Some(&quarantine.id),
+            backend_name,
+        );
+        match &result {
+            Ok(()) => {
+                record.rolled_back_at = Some(record.ts);
+                record.outcome = ActionOutcome::RolledBack;
+            }
+            Err(err) => {
+                record.outcome = ActionOutcome::Failed;
+                record.error = Some(format!("{err:#}"));
+            }
+        }
+        log_action(&store, &record);
+    }
+    result?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&quarantine)?);
+        return Ok(());
+    }
+    println!(
+        "quarantine {} released: {}",
+        quarantine.id,
+        describe(&quarantine.decision)
+    );
+    Ok(())
+}
+
+/// Policy actions from the audit table, newest first: quarantines applied
+/// and released here, playbook steps and dry-run decisions of the daemon.
+pub fn history(config: &Config, query: &PolicyActionQuery, json: bool) -> Result<()> {
+    let actions = crate::open_storage(&config.storage)?.query_policy_actions(query)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&actions)?);
+        return Ok(());
+    }
+    if actions.is_empty() {
+        println!("no policy actions recorded");
+        return Ok(());
+    }
+    println!(
+        "{:<19}  {:<11}  {:<10}  {:<9}  TARGET",
+        "TIME", "ACTION", "OUTCOME", "BACKEND"
+    );
+    for action in &actions {
+        let target = match serde_json::from_value::<QuarantineDecision>(action.decision.clone()) {
+            Ok(decision) => describe(&decision),
+            Err(_) => action.decision.to_string(),
+        };
+        println!(
+            "{:<19}  {:<11}  {:<10}  {:<9}  {}",
+            action.ts.format("%Y-%m-%d %H:%M:%S"),
+            action.action,
+            action.outcome.as_str(),
+            action.backend,
+            target
+        );
+        if let Some(error) = &action.error {
+            println!("{:<19}  error: {error}", "");
+        }
+    }
+    Ok(())
+}
+
+/// A manager over the stored quarantines, re-applied (the backends skip
+/// rules already in place) and with the expired ones lifted, so releasing
+/// leaves the rules of overlapping quarantines intact.
+fn take_over(
+    backend: Box<dyn policy::PolicyBackend + Send + Sync>,
+    store: &Arc<Mutex<Storage>>,
+) -> Result<QuarantineManager> {
+    let manager = QuarantineManager::with_store(backend, Box::new(store.clone()))?;
+    let report = manager.recover()?;
+    for id in &report.lifted {
+        println!("quarantine {id} had expired and was lifted");
+    }
+    for (id, error) in &report.failed {
+        warn!(id, error, "stored quarantine could not be restored");
+    }
+    if !report.orphaned.is_empty() {
+        warn!(
+            rules = report.orphaned.len(),
+            "firewall rules without a quarantine; see `quarantine list --rules`"
+        );
+    }
+    Ok(manager)
+}
+
+fn find(manager: &QuarantineManager, id: &str) -> Result<ActiveQuarantine> {
+    let matches: Vec<ActiveQuarantine> = manager
+        .active()
+        .into_iter()
+        .filter(|quarantine| quarantine.id.starts_with(id))
+        .collect();
+    match matches.len() {
+        1 => Ok(matches.into_iter().next().expect("one match")),
+        0 => Err(anyhow!("no active quarantine {id}")),
+        count => Err(anyhow!("{count} active quarantines start with {id}")),
+    }
+}
+
+/// The decision as stored in the audit table, with the quarantine id
+/// added so releases can be matched to what they lift.
+fn action_record(
+    action: &str,
+    decision: &QuarantineDecision,
+    id: Option<&str>,
+    backend: &str,
+) -> PolicyActionRecord {
+    let mut decision = serde_json::to_value(decision).unwrap_or_default();
+    if let (Some(object), Some(id)) = (decision.as_object_mut(), id) {
+        object.insert("id".into(), id.into());
+    }
+    PolicyActionRecord {
+        id: 0,
+        ts: Utc::now(),
+        action: action.into(),
+        decision,
+        backend: backend.into(),
+        rule_id: None,
+        alert_id: None,
+        approved_by: Some(operator()),
+        approved_at: Some(Utc::now()),
+        applied_at: None,
+        rolled_back_at: None,
+        outcome: ActionOutcome::Pending,
+        error: None,
+        commands: Vec::new(),
+    }
+}
+
+fn log_action(store: &Mutex<Storage>, record: &PolicyActionRecord) {
+    let result = store
+        .lock()
+        .map_err(|_| anyhow!("storage lock poisoned"))
+        .and_then(|storage| storage.record_policy_action(record));
+    if let Err(err) = result {
+        warn!("recording the policy action failed: {err:#}");
+    }
+}
+
+/// Who ran the command, for `approved_by`.
+pub(crate) fn operator() -> String {
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".into());
+    format!("cli:{user}")
+}
+
+/// One line: the program (or "any process"), ports, direction, peers.
+fn describe(decision: &QuarantineDecision) -> String {
+    let program = match &decision.identity {
+        Some(identity) if is_targetable(identity) => match (&identity.exe_path, identity.pid) {
+            (Some(exe), pid) if pid > 0 => format!("{exe} (pid {pid})"),
+            (Some(exe), _) => exe.clone(),
+            (None, pid) => format!("pid {pid}"),
+        },
+        _ => decision
+            .process
+            .clone()
+            .unwrap_or_else(|| "any process".into()),
+    };
+    let mut parts = vec![program];
+    if decision.ports.is_empty() {
+        parts.push("all ports".into());
+    } else {
+        let ports: Vec<String> = decision.ports.iter().map(u16::to_string).collect();
+        parts.push(format!("ports {}", ports.join(",")));
+    }
+    match decision.direction {
+        Direction::Inbound => parts.push("inbound".into()),
+        Direction::Outbound => parts.push("outbound".into()),
+        Direction::Both => {}
+    }
+    if decision.is_remote_scoped() {
+        let peers: Vec<String> = decision
+            .remote_networks
+            .iter()
+            .map(ToString::to_string)
+            .chain(decision.remote_domains.iter().cloned())
+            .collect();
+        parts.push(format!("peers {}", peers.join(",")));
+    }
+    if decision.suspend {
+        parts.push("suspended".into());
+    }
+    parts.join(", ")
+}
+
+fn remaining(left: chrono::Duration) -> String {
+    match left.num_seconds() {
+        seconds if seconds < 120 => format!("{seconds}s"),
+        seconds if seconds < 7200 => format!("{}m", seconds / 60),
+        seconds if seconds < 172_800 => format!("{}h", seconds / 3600),
+        seconds => format!("{}d", seconds / 86_400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_and_describes_decisions() {
+        let apply = Apply {
+            pid: None,
+            exe: Some("/usr/bin/notesync".into()),
+            ports: vec![445],
+            remotes: vec!["10.0.0.0/8".parse().unwrap()],
+            domains: Vec::new(),
+            direction: parse_direction("out").unwrap(),
+            expires_in_seconds: 600,
+            suspend: false,
+            plan: false,
+        };
+        let decision = apply.decision();
+        assert_eq!(decision.process.as_deref(), Some("notesync"));
+        assert_eq!(
+            describe(&decision),
+            "/usr/bin/notesync, ports 445, outbound, peers 10.0.0.0/8"
+        );
+
+        let record = action_record("quarantine", &decision, Some("q1"), "noop");
+        assert_eq!(record.decision["id"], "q1");
+        let stored: QuarantineDecision = serde_json::from_value(record.decision).unwrap();
+        assert_eq!(stored.ports, vec![445]);
+        assert!(parse_direction("sideways").is_err());
+    }
+}
@@ -0,0 +1,129 @@
+//! `nets-cli replay`: drives a packet capture or an NDJSON flow log through
+//! the normalizer and analyzer offline, in capture order, and reports the
+//! alerts. Nothing is enforced; with `--store` flows and alerts are written
+//! to the database as if the daemon had seen them.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use analyzer::{
+    dsl::{load_rules_from_str, Rule},
+    Alert, Analyzer,
+};
+use anyhow::{anyhow, Context, Result};
+use chrono::Duration;
+use collector::FlowEvent;
+use normalizer::Normalizer;
+use storage::import::{ImportOptions, NdjsonImporter};
+use tracing::{debug, info, warn};
+
+use crate::config::Config;
+
+pub struct Replay {
+    /// `.pcap`/`.cap` capture; anything else is read as NDJSON flows.
+    pub input: PathBuf,
+    /// A rules file, or a directory whose `*.rules` files are all loaded.
+    pub rules: PathBuf,
+    pub store: bool,
+}
+
+pub struct ReplayReport {
+    pub flows: usize,
+    pub alerts: Vec<Alert>,
+}
+
+pub fn run(config: &Config, replay: &Replay) -> Result<ReplayReport> {
+    let mut flows = read_flows(&replay.input)?;
+    flows.sort_by_key(|flow| flow.ts_first);
+    let rules = load_rules(&replay.rules)?;
+    info!(flows = flows.len(), rules = rules.len(), "replaying");
+
+    let storage = if replay.store {
+        Some(crate::open_storage(&config.storage)?)
+    } else {
+        None
+    };
+    // Alerts reference the stored rows when there are any.
+    let ids = match &storage {
+        Some(storage) => storage.put_flows(&flows)?.into_iter().map(Some).collect(),
+        None => vec![None; flows.len()],
+    };
+
+    let normalizer = Normalizer::new(Duration::seconds(60));
+    let mut analyzer = Analyzer::new(Duration::hours(config.analyzer.baseline_hours), rules);
+    analyzer.set_rate_limit(config.analyzer.rate_limit.clone());
+    analyzer.set_severity_overrides(config.analyzer.severity_overrides.clone());
+    let mut alerts = Vec::new();
+    for (flow, id) in flows.iter().zip(ids) {
+        match normalizer.normalize(flow.clone()) {
+            Ok(mut normalized) => {
+                normalized.flow_id = id;
+                alerts.extend(analyzer.ingest(normalized));
+            }
+            Err(err) => debug!(%err, "flow could not be normalized"),
+        }
+    }
+    alerts.extend(analyzer.flush_rate_limit());
+
+    if let Some(storage) = &storage {
+        for alert in &alerts {
+            storage.put_alert(alert)?;
+        }
+    }
+    Ok(ReplayReport {
+        flows: flows.len(),
+        alerts,
+    })
+}
+
+pub fn read_flows(path: &Path) -> Result<Vec<FlowEvent>> {
+    let capture = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| matches!(extension, "pcap" | "cap" | "pcapng"));
+    if capture {
+        return collector::pcap::read_pcap(path);
+    }
+    let file = File::open(path).with_context(|| format!("cannot read {}", path.display()))?;
+    let importer = NdjsonImporter::new(ImportOptions::default());
+    let mut flows = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(flow) = importer
+            .parse_line(&line)
+            .with_context(|| format!("{} line {}", path.display(), number + 1))?
+        {
+            flows.push(flow);
+        }
+    }
+    Ok(flows)
+}
+
+/// Files in a directory that are not streaming rules (retrospective rules,
+/// say) are skipped with a warning.
+pub fn load_rules(path: &Path) -> Result<Vec<Rule>> {
+    if !path.is_dir() {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("cannot read rules {}", path.display()))?;
+        return load_rules_from_str(&data)
+            .with_context(|| format!("invalid rules {}", path.display()));
+    }
+    let mut rules = Vec::new();
+    for file in crate::rule_files(path)? {
+        let data = std::fs::read_to_string(&file)?;
+        match load_rules_from_str(&data) {
+            Ok(loaded) => rules.extend(loaded),
+            Err(err) => warn!(file = %file.display(), %err, "skipping rules file"),
+        }
+    }
+    if rules.is_empty() {
+        return Err(anyhow!("no rules found in {}", path.display()));
+    }
+    Ok(rules)
+}
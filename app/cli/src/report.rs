@@ -0,0 +1,81 @@
+//! `nets-cli report`: a shareable offline report of alerts, incidents, top
+//! talkers, new devices and ATT&CK coverage over a period.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use storage::ReportFormat;
+use tracing::warn;
+
+use crate::config::Config;
+
+pub struct Report {
+    pub since: DateTime<Utc>,
+    pub until: Option<DateTime<Utc>>,
+    /// Taken from the extension of `out` when not given, else HTML.
+    pub format: Option<ReportFormat>,
+    /// `nets-report-<time>.<ext>` in the current directory by default.
+    pub out: Option<PathBuf>,
+    pub json: bool,
+}
+
+/// Where the report went, for `--output json`.
+#[derive(Serialize)]
+struct Written<'a> {
+    path: &'a Path,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+}
+
+pub fn run(config: &Config, report: Report) -> Result<()> {
+    let to = report.until.unwrap_or_else(Utc::now);
+    if report.since >= to {
+        return Err(anyhow!("--since must be before --until"));
+    }
+    let format = match (report.format, &report.out) {
+        (Some(format), _) => format,
+        (None, Some(out)) => format_of(out)?,
+        (None, None) => ReportFormat::Html,
+    };
+    let out = report.out.unwrap_or_else(|| {
+        PathBuf::from(format!(
+            "nets-report-{}.{}",
+            to.format("%Y%m%d-%H%M%S"),
+            format.extension()
+        ))
+    });
+    // A report without the coverage section beats no report at all.
+    let rules = crate::replay::load_rules(&config.analyzer.rules_path).unwrap_or_else(|err| {
+        warn!(%err, "ATT&CK coverage left out: cannot load rules");
+        Vec::new()
+    });
+    let storage = crate::open_storage(&config.storage)?;
+    let gathered = storage.report(report.since, to, &rules)?;
+    std::fs::write(&out, gathered.render(format))
+        .with_context(|| format!("cannot write {}", out.display()))?;
+    if report.json {
+        let written = Written {
+            path: &out,
+            from: report.since,
+            to,
+        };
+        println!("{}", serde_json::to_string_pretty(&written)?);
+        return Ok(());
+    }
+    println!(
+        "wrote report for {} .. {} to {}",
+        report.since.format("%Y-%m-%d %H:%M"),
+        to.format("%Y-%m-%d %H:%M"),
+        out.display()
+    );
+    Ok(())
+}
+
+fn format_of(out: &Path) -> Result<ReportFormat> {
+    out.extension()
+        .and_then(|extension| extension.to_str())
+        .ok_or_else(|| anyhow!("pass --format; {} has no extension", out.display()))?
+        .parse()
+}
@@ -0,0 +1,226 @@
+//! `nets-cli rule-test`: evaluates each rule on fixture flows (or one
+//! built-in sample) and prints which rules fired on which flows, then runs
+//! the test cases rules carry in `tests`. Fails when a test case does not
+//! get its expected verdict.
+
+use std::path::Path;
+
+use analyzer::dsl::{EvalContext, Rule};
+use anyhow::{anyhow, Result};
+use chrono::{Duration, Utc};
+use normalizer::{NormalizedFlow, Normalizer};
+use serde::Serialize;
+
+/// Matrix mark for a rule that failed to evaluate on a flow.
+const ERROR_MARK: char = '!';
+
+/// The whole run, as `--output json` prints it.
+#[derive(Serialize)]
+struct Outcome {
+    flows: Vec<NormalizedFlow>,
+    rules: Vec<RuleRow>,
+    cases: Vec<CaseResult>,
+    failed: usize,
+}
+
+/// How one rule did on every flow.
+#[derive(Serialize)]
+struct RuleRow {
+    id: String,
+    /// `Some(true)` fired, `Some(false)` did not, `None` failed to evaluate.
+    results: Vec<Option<bool>>,
+    /// The first evaluation error.
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CaseResult {
+    rule: String,
+    name: String,
+    expected: bool,
+    /// `None` when the case flow or the rule failed to evaluate.
+    actual: Option<bool>,
+    error: Option<String>,
+    passed: bool,
+}
+
+pub fn run(rules_path: &Path, flows_path: Option<&Path>, json: bool) -> Result<()> {
+    let rules = crate::replay::load_rules(rules_path)?;
+    let flows = match flows_path {
+        Some(path) => {
+            let normalizer = Normalizer::new(Duration::seconds(60));
+            crate::replay::read_flows(path)?
+                .into_iter()
+                .map(|flow| normalizer.normalize(flow))
+                .collect::<Result<Vec<_>>>()?
+        }
+        None => vec![sample_flow()],
+    };
+    let rows = evaluate(&rules, &flows);
+    let cases = run_cases(&rules);
+    let failed = cases.iter().filter(|case| !case.passed).count();
+    if json {
+        let outcome = Outcome {
+            flows,
+            rules: rows,
+            cases,
+            failed,
+        };
+        println!("{}", serde_json::to_string_pretty(&outcome)?);
+    } else {
+        print_matrix(&rows, &flows);
+        print_cases(&cases);
+    }
+    if failed > 0 {
+        return Err(anyhow!("{failed} rule test cases failed"));
+    }
+    Ok(())
+}
+
+fn evaluate(rules: &[Rule], flows: &[NormalizedFlow]) -> Vec<RuleRow> {
+    let context = EvalContext::default();
+    rules
+        .iter()
+        .map(|rule| {
+            let mut error = None;
+            let results = flows
+                .iter()
+                .map(|flow| match rule.evaluate_with(flow, &context) {
+                    Ok(fired) => Some(fired),
+                    Err(err) => {
+                        error.get_or_insert_with(|| err.to_string());
+                        None
+                    }
+                })
+                .collect();
+            RuleRow {
+                id: rule.id.clone(),
+                results,
+                error,
+            }
+        })
+        .collect()
+}
+
+/// One row per rule, one column per flow: `x` fired, `.` did not, `!`
+/// failed to evaluate (the first error per rule is listed below).
+fn print_matrix(rows: &[RuleRow], flows: &[NormalizedFlow]) {
+    let width = rows
+        .iter()
+        .map(|row| row.id.len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    let header: String = (1..=flows.len()).map(|n| format!(" {:>3}", n)).collect();
+    println!("{:<width$} {header}", "RULE");
+    for row in rows {
+        let marks: String = row
+            .results
+            .iter()
+            .map(|result| {
+                let mark = match result {
+                    Some(true) => 'x',
+                    Some(false) => '.',
+                    None => ERROR_MARK,
+                };
+                format!("   {mark}")
+            })
+            .collect();
+        let fired = row
+            .results
+            .iter()
+            .filter(|result| **result == Some(true))
+            .count();
+        println!("{:<width$} {marks}   ({fired}/{})", row.id, flows.len());
+    }
+    println!();
+    for (index, flow) in flows.iter().enumerate() {
+        println!(
+            "{:>4}  {} {}:{} -> {}:{}  {}",
+            index + 1,
+            flow.proto,
+            flow.src_ip,
+            flow.src_port,
+            flow.dst_ip,
+            flow.dst_port,
+            flow.process.as_deref().unwrap_or("-")
+        );
+    }
+    for row in rows {
+        if let Some(err) = &row.error {
+            println!("{ERROR_MARK} {}: {err}", row.id);
+        }
+    }
+}
+
+/// Runs every embedded case against its rule.
+fn run_cases(rules: &[Rule]) -> Vec<CaseResult> {
+    let context = EvalContext::default();
+    let mut results = Vec::new();
+    for rule in rules {
+        for (index, case) in rule.tests.iter().enumerate() {
+            let actual = case
+                .flow()
+                .and_then(|flow| rule.evaluate_with(&flow, &context));
+            let (actual, error) = match actual {
+                Ok(matched) => (Some(matched), None),
+                Err(err) => (None, Some(err.to_string())),
+            };
+            results.push(CaseResult {
+                rule: rule.id.clone(),
+                name: case
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("#{}", index + 1)),
+                expected: case.expect_match,
+                actual,
+                error,
+                passed: actual == Some(case.expect_match),
+            });
+        }
+    }
+    results
+}
+
+/// Prints expected against actual for every case.
+fn print_cases(cases: &[CaseResult]) {
+    if cases.is_empty() {
+        return;
+    }
+    let verdict = |matched: bool| if matched { "match" } else { "no match" };
+    println!();
+    for case in cases {
+        let actual = match case.actual {
+            Some(matched) => verdict(matched).into(),
+            None => format!("error: {}", case.error.as_deref().unwrap_or_default()),
+        };
+        println!(
+            "{:<4}  {} {}: expected {}, got {actual}",
+            if case.passed { "ok" } else { "FAIL" },
+            case.rule,
+            case.name,
+            verdict(case.expected)
+        );
+    }
+    let failed = cases.iter().filter(|case| !case.passed).count();
+    println!("{} test cases, {failed} failed", cases.len());
+}
+
+/// An SMB connection from an unusual process, for trying rules without
+/// a fixture file.
+fn sample_flow() -> NormalizedFlow {
+    NormalizedFlow {
+        window_start: Utc::now(),
+        window_end: Utc::now(),
+        proto: "TCP".into(),
+        src_ip: "10.0.0.5".into(),
+        src_port: 51515,
+        dst_ip: "10.0.0.8".into(),
+        dst_port: 445,
+        direction: collector::FlowDirection::Lateral,
+        bytes: 4096,
+        packets: 12,
+        process: Some("notesync.exe".into()),
+        ..NormalizedFlow::default()
+    }
+}
@@ -0,0 +1,742 @@
+//! `nets-cli service install|uninstall|status`: registers the daemon with
+//! the platform's service manager (a systemd unit, a launchd daemon or a
+//! Windows service) so it starts at boot.
+//!
+//! The service runs as root or LocalSystem: capture, process attribution
+//! and firewall quarantines all need it. On systemd the capabilities are
+//! narrowed to the ones those use.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+
+use crate::config::Config;
+
+/// systemd unit and Windows service name.
+const SERVICE_NAME: &str = "nets";
+const SYSTEMD_UNIT: &str = "nets.service";
+const LAUNCHD_LABEL: &str = "com.nets.daemon";
+const DESCRIPTION: &str = "Nets local network monitor";
+
+/// What the service runs, and where.
+pub struct Spec {
+    pub exe: PathBuf,
+    /// Absolute; `None` runs on the built-in defaults.
+    pub config: Option<PathBuf>,
+    /// Relative paths in the config resolve against it.
+    pub workdir: PathBuf,
+}
+
+impl Spec {
+    /// This executable, with the config and working directory it runs with
+    /// now.
+    pub fn current(config: Option<&Path>) -> Result<Self> {
+        let config = config
+            .map(|path| {
+                fs::canonicalize(path)
+                    .with_context(|| format!("cannot find config {}", path.display()))
+            })
+            .transpose()?;
+        Ok(Self {
+            exe: std::env::current_exe()?,
+            config,
+            workdir: std::env::current_dir()?,
+        })
+    }
+
+    fn config_args(&self) -> Vec<String> {
+        match &self.config {
+            Some(config) => vec!["--config".into(), config.display().to_string()],
+            None => Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Manager {
+    Systemd,
+    Launchd,
+    Windows,
+}
+
+impl Manager {
+    /// The manager of this platform, in use or not.
+    fn platform() -> Result<Self> {
+        if cfg!(target_os = "linux") {
+            Ok(Manager::Systemd)
+        } else if cfg!(target_os = "macos") {
+            Ok(Manager::Launchd)
+        } else if cfg!(windows) {
+            Ok(Manager::Windows)
+        } else {
+            Err(anyhow!("no supported service manager on this platform"))
+        }
+    }
+
+    /// The platform's manager, when it is the one running.
+    fn native() -> Result<Self> {
+        let manager = Self::platform()?;
+        if manager == Manager::Systemd && !Path::new("/run/systemd/system").is_dir() {
+            return Err(anyhow!(
+                "systemd is not running; start `nets-cli daemon` from your init system"
+            ));
+        }
+        Ok(manager)
+    }
+
+    /// Unit file or plist; Windows keeps services in the registry.
+    fn definition_path(self) -> Option<PathBuf> {
+        match self {
+            Manager::Systemd => Some(Path::new("/etc/systemd/system").join(SYSTEMD_UNIT)),
+            Manager::Launchd => {
+                Some(Path::new("/Library/LaunchDaemons").join(format!("{LAUNCHD_LABEL}.plist")))
+            }
+            Manager::Windows => None,
+        }
+    }
+
+    fn log_path(self) -> PathBuf {
+        match self {
+            Manager::Systemd => PathBuf::from("/var/log/nets/daemon.log"),
+            Manager::Launchd => PathBuf::from("/Library/Logs/nets/daemon.log"),
+            Manager::Windows => {
+                let data = std::env::var_os("ProgramData")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| PathBuf::from(r"C:\ProgramData"));
+                data.join("nets").join("logs").join("daemon.log")
+            }
+        }
+    }
+
+    /// The unit file, the plist, or the service command line.
+    fn definition(self, spec: &Spec) -> String {
+        let log = self.log_path();
+        match self {
+            Manager::Systemd => systemd_unit(spec, &log),
+            Manager::Launchd => launchd_plist(spec, &log),
+            Manager::Windows => windows_command_line(spec, &log),
+        }
+    }
+}
+
+pub struct Install {
+    pub start: bool,
+    /// Print the definition instead of installing it.
+    pub print: bool,
+    pub json: bool,
+}
+
+/// Where install or uninstall left the service, for `--output json`.
+#[derive(Serialize)]
+struct Changed {
+    manager: Manager,
+    installed: bool,
+    running: bool,
+    log: PathBuf,
+}
+
+pub fn install(spec: &Spec, install: Install) -> Result<()> {
+    if install.print {
+        println!("{}", Manager::platform()?.definition(spec));
+        return Ok(());
+    }
+    let manager = Manager::native()?;
+    let definition = manager.definition(spec);
+    let log = manager.log_path();
+    match manager {
+        Manager::Systemd => {
+            // systemd creates the log directory itself (LogsDirectory=).
+            write_definition(manager, &definition)?;
+            invoke("systemctl", &["daemon-reload"])?;
+            invoke("systemctl", &["enable", SYSTEMD_UNIT])?;
+            if install.start {
+                // Restart so a reinstall picks up the new unit.
+                invoke("systemctl", &["restart", SYSTEMD_UNIT])?;
+            }
+        }
+        Manager::Launchd => {
+            create_log_dir(&log)?;
+            let target = format!("system/{LAUNCHD_LABEL}");
+            // Unload an earlier install; it is fine if there is none.
+            let _ = output("launchctl", &["bootout", &target]);
+            write_definition(manager, &definition)?;
+            invoke("launchctl", &["enable", &target])?;
+            if install.start {
+                let plist = manager.definition_path().expect("launchd uses a plist");
+                invoke(
+                    "launchctl",
+                    &["bootstrap", "system", &plist.display().to_string()],
+                )?;
+            }
+        }
+        Manager::Windows => {
+            create_log_dir(&log)?;
+            let exists = output("sc.exe", &["query", SERVICE_NAME]).is_some_and(|out| out.success);
+            if exists {
+                invoke(
+                    "sc.exe",
+                    &[
+                        "config",
+                        SERVICE_NAME,
+                        "binPath=",
+                        &definition,
+                        "start=",
+                        "auto",
+                    ],
+                )?;
+            } else {
+                invoke(
+                    "sc.exe",
+                    &[
+                        "create",
+                        SERVICE_NAME,
+                        "binPath=",
+                        &definition,
+                        "start=",
+                        "auto",
+                        "DisplayName=",
+                        DESCRIPTION,
+                    ],
+                )?;
+            }
+            invoke("sc.exe", &["description", SERVICE_NAME, DESCRIPTION])?;
+            invoke(
+                "sc.exe",
+                &[
+                    "failure",
+                    SERVICE_NAME,
+                    "reset=",
+                    "86400",
+                    "actions=",
+                    "restart/5000/restart/5000/restart/60000",
+                ],
+            )?;
+            if install.start {
+                invoke("sc.exe", &["start", SERVICE_NAME])?;
+            }
+        }
+    }
+    if install.json {
+        let changed = Changed {
+            manager,
+            installed: true,
+            running: install.start,
+            log,
+        };
+        println!("{}", serde_json::to_string_pretty(&changed)?);
+        return Ok(());
+    }
+    println!(
+        "installed the {} service{}; logs go to {}",
+        manager_name(manager),
+        if install.start { " and started it" } else { "" },
+        log.display()
+    );
+    Ok(())
+}
+
+/// Stops and unregisters the service. Logs are left in place.
+pub fn uninstall(json: bool) -> Result<()> {
+    let manager = Manager::native()?;
+    match manager {
+        Manager::Systemd => {
+            let unit = manager.definition_path().expect("systemd uses a unit file");
+            if !unit.exists() {
+                return Err(anyhow!("no service installed at {}", unit.display()));
+            }
+            invoke("systemctl", &["disable", "--now", SYSTEMD_UNIT])?;
+            fs::remove_file(&unit).with_context(|| format!("cannot remove {}", unit.display()))?;
+            invoke("systemctl", &["daemon-reload"])?;
+        }
+        Manager::Launchd => {
+            let plist = manager.definition_path().expect("launchd uses a plist");
+            if !plist.exists() {
+                return Err(anyhow!("no service installed at {}", plist.display()));
+            }
+            // Not loaded when installed with --no-start and never booted.
+            let _ = output(
+                "launchctl",
+                &["bootout", &format!("system/{LAUNCHD_LABEL}")],
+            );
+            fs::remove_file(&plist)
+                .with_context(|| format!("cannot remove {}", plist.display()))?;
+        }
+        Manager::Windows => {
+            // Already stopped is fine; a missing service fails the delete.
+            let _ = output("sc.exe", &["stop", SERVICE_NAME]);
+            invoke("sc.exe", &["delete", SERVICE_NAME])?;
+        }
+    }
+    if json {
+        let changed = Changed {
+            manager,
+            installed: false,
+            running: false,
+            log: manager.log_path(),
+        };
+        println!("{}", serde_json::to_string_pretty(&changed)?);
+        return Ok(());
+    }
+    println!(
+        "removed the {} service; logs are kept in {}",
+        manager_name(manager),
+        manager.log_path().display()
+    );
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct Status {
+    manager: Manager,
+    installed: bool,
+    /// Starts at boot.
+    enabled: bool,
+    running: bool,
+    definition: Option<PathBuf>,
+    log: PathBuf,
+}
+
+pub fn status(json: bool) -> Result<()> {
+    let manager = Manager::native()?;
+    let definition = manager.definition_path();
+    let (installed, enabled, running) = match manager {
+        Manager::Systemd => {
+            let query = |what| {
+                output("systemctl", &[what, SYSTEMD_UNIT])
+                    .map(|out| out.stdout.trim().to_string())
+                    .unwrap_or_default()
+            };
+            let installed = definition.as_ref().is_some_and(|unit| unit.exists());
+            (
+                installed,
+                query("is-enabled") == "enabled",
+                query("is-active") == "active",
+            )
+        }
+        Manager::Launchd => {
+            let installed = definition.as_ref().is_some_and(|plist| plist.exists());
+            let running = output("launchctl", &["print", &format!("system/{LAUNCHD_LABEL}")])
+                .is_some_and(|out| out.success && out.stdout.contains("state = running"));
+            // Plists in /Library/LaunchDaemons load at boot.
+            (installed, installed, running)
+        }
+        Manager::Windows => {
+            let query = output("sc.exe", &["query", SERVICE_NAME]).filter(|out| out.success);
+            let config = output("sc.exe", &["qc", SERVICE_NAME]).filter(|out| out.success);
+            (
+                query.is_some(),
+                config.is_some_and(|out| out.stdout.contains("AUTO_START")),
+                query.is_some_and(|out| out.stdout.contains("RUNNING")),
+            )
+        }
+    };
+    let status = Status {
+        manager,
+        installed,
+        enabled,
+        running,
+        definition,
+        log: manager.log_path(),
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(());
+    }
+    if !status.installed {
+        println!(
+            "no {} service installed; run `service install`",
+            manager_name(manager)
+        );
+        return Ok(());
+    }
+    println!("manager:    {}", manager_name(manager));
+    if let Some(definition) = &status.definition {
+        println!("definition: {}", definition.display());
+    }
+    println!("at boot:    {}", if status.enabled { "yes" } else { "no" });
+    println!("running:    {}", if status.running { "yes" } else { "no" });
+    println!("log:        {}", status.log.display());
+    Ok(())
+}
+
+/// Entry point of the Windows service: hands the daemon to the service
+/// control manager, which starts and stops it.
+pub fn run(config: Config, workdir: &Path) -> Result<()> {
+    std::env::set_current_dir(workdir)
+        .with_context(|| format!("cannot enter {}", workdir.display()))?;
+    #[cfg(windows)]
+    {
+        scm::dispatch(config)
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = config;
+        Err(anyhow!(
+            "`service run` is started by the Windows service manager; use `nets-cli daemon`"
+        ))
+    }
+}
+
+/// Log sink of the Windows service, which has no console.
+pub fn log_writer(path: &Path) -> Result<Mutex<File>> {
+    create_log_dir(path)?;
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("cannot open log {}", path.display()))?;
+    Ok(Mutex::new(file))
+}
+
+fn manager_name(manager: Manager) -> &'static str {
+    match manager {
+        Manager::Systemd => "systemd",
+        Manager::Launchd => "launchd",
+        Manager::Windows => "Windows",
+    }
+}
+
+fn write_definition(manager: Manager, definition: &str) -> Result<()> {
+    let path = manager
+        .definition_path()
+        .expect("only systemd and launchd keep a definition file");
+    fs::write(&path, definition).with_context(|| {
+        format!(
+            "cannot write {}; installing a service needs root",
+            path.display()
+        )
+    })
+}
+
+fn create_log_dir(log: &Path) -> Result<()> {
+    if let Some(dir) = log.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("cannot create {}", dir.display()))?;
+    }
+    Ok(())
+}
+
+struct Output {
+    success: bool,
+    stdout: String,
+}
+
+/// `None` when the tool cannot be started at all.
+fn output(program: &str, args: &[&str]) -> Option<Output> {
+    let out = Command::new(program).args(args).output().ok()?;
+    Some(Output {
+        success: out.status.success(),
+        stdout: String::from_utf8_lossy(&out.stdout).into_owned(),
+    })
+}
+
+fn invoke(program: &str, args: &[&str]) -> Result<()> {
+    let out = Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("cannot run {program}"))?;
+    if out.status.success() {
+        return Ok(());
+    }
+    // sc.exe reports failures on stdout.
+    let mut message = String::from_utf8_lossy(&out.stderr).trim().to_string();
+    if message.is_empty() {
+        message = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    }
+    Err(anyhow!(
+        "{program} {} failed ({}): {message}",
+        args.join(" "),
+        out.status
+    ))
+}
+
+fn systemd_unit(spec: &Spec, log: &Path) -> String {
+    let mut command = vec![spec.exe.display().to_string()];
+    command.extend(spec.config_args());
+    command.push("daemon".into());
+    let command: Vec<String> = command.iter().map(|arg| systemd_quote(arg)).collect();
+    let log = log.display();
+    format!(
+        "[Unit]
+Description={DESCRIPTION}
+After=network-online.target
+Wants=network-online.target
+
+[Service]
+Type=simple
+ExecStart={command}
+WorkingDirectory={workdir}
+Restart=on-failure
+RestartSec=5
+LogsDirectory=nets
+StandardOutput=append:{log}
+StandardError=append:{log}
+CapabilityBoundingSet=CAP_NET_ADMIN CAP_NET_RAW CAP_SYS_PTRACE CAP_DAC_OVERRIDE CAP_DAC_READ_SEARCH CAP_KILL
+NoNewPrivileges=yes
+PrivateTmp=yes
+
+[Install]
+WantedBy=multi-user.target
+",
+        command = command.join(" "),
+        // A plain path, not a command line: only specifiers need escaping.
+        workdir = spec.workdir.display().to_string().replace('%', "%%"),
+    )
+}
+
+/// Double-quoted, with the characters systemd expands escaped.
+fn systemd_quote(arg: &str) -> String {
+    let escaped = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    format!("\"{escaped}\"")
+}
+
+fn launchd_plist(spec: &Spec, log: &Path) -> String {
+    let mut args = vec![spec.exe.display().to_string()];
+    args.extend(spec.config_args());
+    args.push("daemon".into());
+    let args: String = args
+        .iter()
+        .map(|arg| format!("        <string>{}</string>\n", xml_escape(arg)))
+        .collect();
+    let log = xml_escape(&log.display().to_string());
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{LAUNCHD_LABEL}</string>
+    <key>ProgramArguments</key>
+    <array>
+{args}    </array>
+    <key>WorkingDirectory</key>
+    <string>{workdir}</string>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>StandardOutPath</key>
+    <string>{log}</string>
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+</dict>
+</plist>
+"#,
+        workdir = xml_escape(&spec.workdir.display().to_string()),
+    )
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// `binPath=` of the service: the hidden `service run` command, which
+/// talks to the service manager and logs to a file.
+fn windows_command_line(spec: &Spec, log: &Path) -> String {
+    let mut args = vec![spec.exe.display().to_string()];
+    args.extend(spec.config_args());
+    args.extend([
+        "service".into(),
+        "run".into(),
+        "--workdir".into(),
+        spec.workdir.display().to_string(),
+        "--log".into(),
+        log.display().to_string(),
+    ]);
+    args.iter()
+        .map(|arg| {
+            if arg.contains([' ', '\t', '"']) || arg.is_empty() {
+                format!("\"{}\"", arg.replace('"', "\\\""))
+            } else {
+                arg.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The service control manager side of the Windows service.
+#[cfg(windows)]
+mod scm {
+    use std::{
+        ffi::{c_void, OsStr},
+        iter,
+        os::windows::ffi::OsStrExt,
+        ptr,
+        sync::{
+            atomic::{AtomicPtr, Ordering},
+            Mutex, OnceLock,
+        },
+    };
+
+    use anyhow::{anyhow, Context, Result};
+    use tokio::sync::Notify;
+    use tracing::error;
+    use windows_sys::{
+        core::PWSTR,
+        Win32::{
+            Foundation::{ERROR_CALL_NOT_IMPLEMENTED, ERROR_SERVICE_SPECIFIC_ERROR, NO_ERROR},
+            System::Services::{
+                RegisterServiceCtrlHandlerExW, SetServiceStatus, StartServiceCtrlDispatcherW,
+                SERVICE_ACCEPT_SHUTDOWN, SERVICE_ACCEPT_STOP, SERVICE_CONTROL_INTERROGATE,
+                SERVICE_CONTROL_SHUTDOWN, SERVICE_CONTROL_STOP, SERVICE_RUNNING, SERVICE_STATUS,
+                SERVICE_STOPPED, SERVICE_STOP_PENDING, SERVICE_TABLE_ENTRYW,
+                SERVICE_WIN32_OWN_PROCESS,
+            },
+        },
+    };
+
+    use super::SERVICE_NAME;
+    use crate::config::Config;
+
+    /// Handed from [`dispatch`] to [`service_main`], which the service
+    /// manager calls on a thread of its own.
+    static CONFIG: Mutex<Option<Config>> = Mutex::new(None);
+    static STATUS_HANDLE: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+    static STOP: OnceLock<Notify> = OnceLock::new();
+
+    /// Blocks until the service stops.
+    pub(super) fn dispatch(config: Config) -> Result<()> {
+        *CONFIG.lock().expect("config lock poisoned") = Some(config);
+        let mut name = wide(SERVICE_NAME);
+        let table = [
+            SERVICE_TABLE_ENTRYW {
+                lpServiceName: name.as_mut_ptr(),
+                lpServiceProc: Some(service_main),
+            },
+            SERVICE_TABLE_ENTRYW {
+                lpServiceName: ptr::null_mut(),
+                lpServiceProc: None,
+            },
+        ];
+        if unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } == 0 {
+            return Err(std::io::Error::last_os_error())
+                .context("not started by the service manager; use `nets-cli daemon`");
+        }
+        Ok(())
+    }
+
+    unsafe extern "system" fn service_main(_argc: u32, _argv: *mut PWSTR) {
+        let name = wide(SERVICE_NAME);
+        let handle = RegisterServiceCtrlHandlerExW(name.as_ptr(), Some(on_control), ptr::null());
+        if handle.is_null() {
+            error!(err = %std::io::Error::last_os_error(), "cannot register the service handler");
+            return;
+        }
+        STATUS_HANDLE.store(handle, Ordering::Release);
+        set_state(SERVICE_RUNNING, NO_ERROR);
+        let config = CONFIG.lock().expect("config lock poisoned").take();
+        let result = match config {
+            Some(config) => crate::daemon::run_until(config, async {
+                STOP.get_or_init(Notify::new).notified().await;
+                Ok(())
+            }),
+            None => Err(anyhow!("the service was started twice")),
+        };
+        let exit = match result {
+            Ok(()) => NO_ERROR,
+            Err(err) => {
+                error!("daemon failed: {err:#}");
+                ERROR_SERVICE_SPECIFIC_ERROR
+            }
+        };
+        set_state(SERVICE_STOPPED, exit);
+    }
+
+    unsafe extern "system" fn on_control(
+        control: u32,
+        _event_type: u32,
+        _event_data: *mut c_void,
+        _context: *mut c_void,
+    ) -> u32 {
+        match control {
+            SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+                set_state(SERVICE_STOP_PENDING, NO_ERROR);
+                // Kept as a permit if the daemon is not waiting yet.
+                STOP.get_or_init(Notify::new).notify_one();
+                NO_ERROR
+            }
+            SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+            _ => ERROR_CALL_NOT_IMPLEMENTED,
+        }
+    }
+
+    fn set_state(state: u32, exit: u32) {
+        let status = SERVICE_STATUS {
+            dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+            dwCurrentState: state,
+            dwControlsAccepted: if state == SERVICE_RUNNING {
+                SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN
+            } else {
+                0
+            },
+            dwWin32ExitCode: exit,
+            dwServiceSpecificExitCode: u32::from(exit != NO_ERROR),
+            dwCheckPoint: 0,
+            // Flushing the writer and lifting nothing else takes seconds.
+            dwWaitHint: if state == SERVICE_STOP_PENDING {
+                30_000
+            } else {
+                0
+            },
+        };
+        unsafe { SetServiceStatus(STATUS_HANDLE.load(Ordering::Acquire), &status) };
+    }
+
+    fn wide(text: &str) -> Vec<u16> {
+        OsStr::new(text)
+            .encode_wide()
+            .chain(iter::once(0))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn definitions_quote_paths() {
+        let spec = Spec {
+            exe: PathBuf::from("/opt/nets/bin/nets-cli"),
+            config: Some(PathBuf::from("/srv/nets 100%/config.toml")),
+            workdir: PathBuf::from("/srv/nets 100%"),
+        };
+        let unit = systemd_unit(&spec, Path::new("/var/log/nets/daemon.log"));
+        assert!(unit.contains(
+            "ExecStart=\"/opt/nets/bin/nets-cli\" \"--config\" \"/srv/nets 100%%/config.toml\" \"daemon\"\n"
+        ));
+        assert!(unit.contains("WorkingDirectory=/srv/nets 100%%\n"));
+        assert!(unit.contains("StandardError=append:/var/log/nets/daemon.log\n"));
+
+        let plist = launchd_plist(&spec, Path::new("/Library/Logs/nets/daemon.log"));
+        assert!(plist.contains("<string>/srv/nets 100%/config.toml</string>"));
+        assert!(plist.contains(&format!("<string>{LAUNCHD_LABEL}</string>")));
+
+        let spec = Spec {
+            exe: PathBuf::from(r"C:\Program Files\NETS\nets-cli.exe"),
+            config: None,
+            workdir: PathBuf::from(r"C:\ProgramData\nets"),
+        };
+        assert_eq!(
+            windows_command_line(&spec, Path::new(r"C:\ProgramData\nets\logs\daemon.log")),
+            r#""C:\Program Files\NETS\nets-cli.exe" service run --workdir C:\ProgramData\nets --log C:\ProgramData\nets\logs\daemon.log"#
+        );
+    }
+}
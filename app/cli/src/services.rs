@@ -0,0 +1,190 @@
+//! `nets-cli services`: LAN services discovered over mDNS, SSDP and DHCP.
+//! Lists the inventory in storage; `--scan` refreshes it first and
+//! `--watch` keeps scanning and prints what appears, changes or goes away.
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    time::Duration,
+};
+
+use anyhow::Result;
+use chrono::Utc;
+use collector::discovery::{DiscoveredService, ServiceDiscovery};
+use serde::Serialize;
+use storage::{ServiceRecord, Storage};
+
+use crate::config::StorageSection;
+
+/// Scans a watched service may be missing from before it counts as gone;
+/// multicast answers get lost now and then.
+const GONE_AFTER_SCANS: u32 = 3;
+
+pub struct Services {
+    pub scan: bool,
+    pub watch: bool,
+    /// Time between scans with `watch`.
+    pub interval: Duration,
+    /// How long each scan listens for answers.
+    pub window: Duration,
+    pub limit: usize,
+    pub json: bool,
+}
+
+pub fn run(config: &StorageSection, options: Services) -> Result<()> {
+    let storage = crate::open_storage(config)?;
+    let discovery = ServiceDiscovery::new().with_window(options.window);
+    if options.watch {
+        return watch(&storage, &discovery, &options);
+    }
+    if options.scan {
+        record(&storage, &discovery.scan()?)?;
+    }
+    let services = storage.list_services(options.limit)?;
+    if options.json {
+        println!("{}", serde_json::to_string_pretty(&services)?);
+        return Ok(());
+    }
+    if services.is_empty() {
+        println!("no services discovered yet; run with --scan to look for some");
+        return Ok(());
+    }
+    println!(
+        "{:<6}  {:<36}  {:<21}  {:<20}  LAST SEEN",
+        "PROTO", "NAME", "ADDRESS", "FIRST SEEN"
+    );
+    for service in &services {
+        println!(
+            "{:<6}  {:<36}  {:<21}  {:<20}  {}",
+            service.protocol,
+            service.name,
+            endpoint(service),
+            service.first_seen.format("%Y-%m-%d %H:%M:%S"),
+            service.last_seen.format("%Y-%m-%d %H:%M:%S")
+        );
+    }
+    Ok(())
+}
+
+/// Upserts the scan results; stored services keep their first sighting.
+fn record(storage: &Storage, found: &[DiscoveredService]) -> Result<Vec<ServiceRecord>> {
+    let now = Utc::now();
+    found
+        .iter()
+        .map(|service| {
+            let record = ServiceRecord {
+                id: service.id.clone(),
+                name: service.name.clone(),
+                protocol: service.protocol.clone(),
+                address: service.address.clone(),
+                port: service.port,
+                process: None,
+                first_seen: now,
+                last_seen: now,
+            };
+            storage.put_service(&record)?;
+            Ok(storage.get_service(&record.id)?.unwrap_or(record))
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Change {
+    New,
+    Changed,
+    Gone,
+}
+
+#[derive(Serialize)]
+struct WatchEvent<'a> {
+    event: Change,
+    service: &'a ServiceRecord,
+}
+
+/// Scans until interrupted or the reader goes away. Services already in
+/// storage are the baseline, so only news is printed.
+fn watch(storage: &Storage, discovery: &ServiceDiscovery, options: &Services) -> Result<()> {
+    let mut known: HashMap<String, ServiceRecord> = storage
+        .list_services(i64::MAX as usize)?
+        .into_iter()
+        .map(|service| (service.id.clone(), service))
+        .collect();
+    // Only services seen during this watch can be reported gone.
+    let mut missed: HashMap<String, u32> = HashMap::new();
+    let mut out = io::stdout().lock();
+    loop {
+        let mut events = Vec::new();
+        let found = record(storage, &discovery.scan()?)?;
+        for service in &found {
+            missed.insert(service.id.clone(), 0);
+            match known.get(&service.id) {
+                None => events.push((Change::New, service.clone())),
+                Some(previous)
+                    if (&previous.name, &previous.address, previous.port)
+                        != (&service.name, &service.address, service.port) =>
+                {
+                    events.push((Change::Changed, service.clone()))
+                }
+                Some(_) => {}
+            }
+            known.insert(service.id.clone(), service.clone());
+        }
+        let mut gone = Vec::new();
+        for (id, count) in missed.iter_mut() {
+            if found.iter().any(|service| &service.id == id) {
+                continue;
+            }
+            *count += 1;
+            if *count >= GONE_AFTER_SCANS {
+                gone.push(id.clone());
+            }
+        }
+        gone.sort();
+        for id in gone {
+            missed.remove(&id);
+            if let Some(service) = known.get(&id) {
+                events.push((Change::Gone, service.clone()));
+            }
+        }
+        for (change, service) in events {
+            let written = if options.json {
+                serde_json::to_writer(
+                    &mut out,
+                    &WatchEvent {
+                        event: change,
+                        service: &service,
+                    },
+                )
+                .map_err(io::Error::from)
+                .and_then(|()| writeln!(out))
+            } else {
+                let mark = match change {
+                    Change::New => '+',
+                    Change::Changed => '~',
+                    Change::Gone => '-',
+                };
+                writeln!(
+                    out,
+                    "{}  {mark} {:<6}  {:<36}  {}",
+                    Utc::now().format("%Y-%m-%d %H:%M:%S"),
+                    service.protocol,
+                    service.name,
+                    endpoint(&service)
+                )
+            };
+            match written.and_then(|()| out.flush()) {
+                Err(err) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+                result => result?,
+            }
+        }
+        std::thread::sleep(options.interval);
+    }
+}
+
+fn endpoint(service: &ServiceRecord) -> String {
+    match service.address.parse::<std::net::Ipv6Addr>() {
+        Ok(_) => format!("[{}]:{}", service.address, service.port),
+        Err(_) => format!("{}:{}", service.address, service.port),
+    }
+}
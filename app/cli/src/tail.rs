@@ -0,0 +1,154 @@
+//! `nets-cli tail`: live flows (or the alerts the analyzer raises on them)
+//! as one JSON object per line on stdout, for jq, grep and friends. Runs
+//! until Ctrl+C, SIGTERM or the reader going away.
+
+use std::{
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use analyzer::{
+    dsl::{evaluate_expression, evaluate_expression_with, EvalContext},
+    Analyzer,
+};
+use anyhow::{anyhow, Result};
+use chrono::Duration;
+use collector::FlowEvent;
+use normalizer::{NormalizedFlow, Normalizer};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::config::Config;
+
+/// Flows waiting to be printed before new ones are dropped.
+const FLOW_QUEUE: usize = 4096;
+
+pub struct Tail {
+    pub alerts: bool,
+    /// DSL expression flows must match, e.g. `dst.port == 443`; with
+    /// `alerts`, the flow that raised the alert must match.
+    pub filter: Option<String>,
+}
+
+pub fn run(config: &Config, tail: Tail) -> Result<()> {
+    if let Some(filter) = &tail.filter {
+        evaluate_expression(filter, &NormalizedFlow::default())
+            .map_err(|err| anyhow!("invalid --filter `{filter}`: {err}"))?;
+    }
+    // No mock fallback: made-up flows must not end up in someone's pipe.
+    let backend = crate::daemon::collector_backend(config.collector.backend)?;
+    let rt = tokio::runtime::Runtime::new()?;
+    rt.block_on(async {
+        let (flows, mut queued) = mpsc::channel(FLOW_QUEUE);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let counter = dropped.clone();
+        backend.subscribe(Arc::new(move |flow: FlowEvent| {
+            if flows.try_send(flow).is_err() {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        }));
+        backend.start().await?;
+
+        let mut printer = Printer::new(config, tail);
+        let shutdown = crate::daemon::shutdown_signal();
+        tokio::pin!(shutdown);
+        let result = loop {
+            tokio::select! {
+                flow = queued.recv() => match flow {
+                    Some(flow) => match printer.flow(flow) {
+                        Ok(true) => {}
+                        Ok(false) => break Ok(()),
+                        Err(err) => break Err(err),
+                    },
+                    None => break Ok(()),
+                },
+                result = &mut shutdown => break result,
+            }
+        };
+        let result = result.and_then(|_| printer.finish());
+        backend.stop().await?;
+        let dropped = dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            warn!(dropped, "flows dropped while the output was blocked");
+        }
+        result
+    })
+}
+
+struct Printer {
+    filter: Option<String>,
+    normalizer: Normalizer,
+    /// Present with `--alerts`.
+    analyzer: Option<Analyzer>,
+}
+
+impl Printer {
+    fn new(config: &Config, tail: Tail) -> Self {
+        let analyzer = tail.alerts.then(|| {
+            let rules =
+                crate::replay::load_rules(&config.analyzer.rules_path).unwrap_or_else(|err| {
+                    warn!(%err, "rules unavailable, only built-in detectors run");
+                    Vec::new()
+                });
+            let mut analyzer =
+                Analyzer::new(Duration::hours(config.analyzer.baseline_hours), rules);
+            analyzer.set_rate_limit(config.analyzer.rate_limit.clone());
+            analyzer.set_severity_overrides(config.analyzer.severity_overrides.clone());
+            analyzer
+        });
+        Self {
+            filter: tail.filter,
+            normalizer: Normalizer::new(Duration::seconds(60)),
+            analyzer,
+        }
+    }
+
+    /// `false` once stdout is closed.
+    fn flow(&mut self, flow: FlowEvent) -> Result<bool> {
+        let normalized = self.normalizer.normalize(flow.clone())?;
+        let selected = match &self.filter {
+            Some(filter) => evaluate_expression_with(filter, &normalized, &EvalContext::default())?,
+            None => true,
+        };
+        match &mut self.analyzer {
+            // Every flow feeds the analyzer; the filter picks what is shown.
+            Some(analyzer) => {
+                for alert in analyzer.ingest(normalized) {
+                    if selected && !emit(&alert)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            None if selected => emit(&flow),
+            None => Ok(true),
+        }
+    }
+
+    /// Alerts the rate limiter held back.
+    fn finish(&mut self) -> Result<()> {
+        if let Some(analyzer) = &mut self.analyzer {
+            for alert in analyzer.flush_rate_limit() {
+                if !emit(&alert)? {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes one line; `false` when the reader has gone (`| head`).
+fn emit(value: &impl Serialize) -> Result<bool> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    match io::stdout().lock().write_all(&line) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
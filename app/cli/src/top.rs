@@ -0,0 +1,151 @@
+//! `nets-cli top`: byte and flow leaders over stored history, read from the
+//! rollup tables rather than live counters.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+use storage::rollup::{RankBy, RollupDimension, RollupPeriod, RollupTotal, TopQuery};
+
+use crate::config::StorageSection;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TopBy {
+    Process,
+    /// Destination address
+    Dst,
+    /// Destination port
+    Port,
+    /// TLS SNI, else the DNS query name
+    Domain,
+    Proto,
+}
+
+impl TopBy {
+    fn dimension(self) -> RollupDimension {
+        match self {
+            TopBy::Process => RollupDimension::Process,
+            TopBy::Dst => RollupDimension::Destination,
+            TopBy::Port => RollupDimension::Port,
+            TopBy::Domain => RollupDimension::Domain,
+            TopBy::Proto => RollupDimension::Protocol,
+        }
+    }
+
+    fn heading(self) -> &'static str {
+        match self {
+            TopBy::Process => "PROCESS",
+            TopBy::Dst => "DESTINATION",
+            TopBy::Port => "PORT",
+            TopBy::Domain => "DOMAIN",
+            TopBy::Proto => "PROTO",
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TopRank {
+    Bytes,
+    Flows,
+}
+
+pub struct Top {
+    pub by: TopBy,
+    pub since: DateTime<Utc>,
+    pub rank: TopRank,
+    pub limit: usize,
+    pub json: bool,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    period: RollupPeriod,
+    leaders: &'a [RollupTotal],
+}
+
+pub fn run(config: &StorageSection, top: Top) -> Result<()> {
+    let storage = crate::open_storage(config)?;
+    let to = Utc::now();
+    let period = RollupPeriod::for_span(to - top.since);
+    let leaders = storage.top(&TopQuery {
+        dimension: top.by.dimension(),
+        period,
+        from: top.since,
+        to,
+        rank_by: match top.rank {
+            TopRank::Bytes => RankBy::Bytes,
+            TopRank::Flows => RankBy::Flows,
+        },
+        limit: top.limit,
+    })?;
+    if top.json {
+        let report = Report {
+            from: top.since,
+            to,
+            period,
+            leaders: &leaders,
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    if leaders.is_empty() {
+        println!("no flows since {}", top.since.format("%Y-%m-%d %H:%M"));
+        return Ok(());
+    }
+    // Shares are of the leaders shown, not of all traffic.
+    let total: u64 = leaders
+        .iter()
+        .map(|leader| match top.rank {
+            TopRank::Bytes => leader.bytes,
+            TopRank::Flows => leader.flows,
+        })
+        .sum();
+    println!(
+        "{:>3}  {:>10}  {:>8}  {:>6}  {}",
+        "#",
+        "BYTES",
+        "FLOWS",
+        "SHARE",
+        top.by.heading()
+    );
+    for (rank, leader) in leaders.iter().enumerate() {
+        let value = match top.rank {
+            TopRank::Bytes => leader.bytes,
+            TopRank::Flows => leader.flows,
+        };
+        println!(
+            "{:>3}  {:>10}  {:>8}  {:>5.1}%  {}",
+            rank + 1,
+            human_bytes(leader.bytes),
+            leader.flows,
+            value as f64 * 100.0 / total.max(1) as f64,
+            leader.key
+        );
+    }
+    if period == RollupPeriod::Day {
+        println!();
+        println!(
+            "counted in daily buckets from {} UTC",
+            top.since.format("%Y-%m-%d")
+        );
+    }
+    Ok(())
+}
+
+/// `532 B`, `14.2 KiB`, `3.1 GiB`.
+pub(crate) fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
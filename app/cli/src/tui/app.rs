@@ -0,0 +1,516 @@
+//! State of the console UI and what keys do to it; rendering lives in
+//! `ui`, so everything here can be driven without a terminal.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use analyzer::Alert;
+use chrono::{DateTime, Utc};
+use collector::FlowEvent;
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Flows kept for display (and held while paused); older ones scroll away.
+pub const MAX_FLOWS: usize = 5000;
+pub const MAX_ALERTS: usize = 500;
+const PAGE: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum View {
+    Flows,
+    Processes,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Time,
+    Proto,
+    Source,
+    Destination,
+    Bytes,
+    Packets,
+    Process,
+}
+
+impl SortKey {
+    const ALL: [SortKey; 7] = [
+        SortKey::Time,
+        SortKey::Proto,
+        SortKey::Source,
+        SortKey::Destination,
+        SortKey::Bytes,
+        SortKey::Packets,
+        SortKey::Process,
+    ];
+
+    fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|key| *key == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SortKey::Time => "time",
+            SortKey::Proto => "proto",
+            SortKey::Source => "source",
+            SortKey::Destination => "destination",
+            SortKey::Bytes => "bytes",
+            SortKey::Packets => "packets",
+            SortKey::Process => "process",
+        }
+    }
+}
+
+/// What typed characters go to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Normal,
+    Filter,
+    Search,
+}
+
+/// Space-separated terms that must all match: `proto:`, `src:`, `dst:`,
+/// `ip:` (either end), `port:` (either end), `proc:`, `state:`, or bare
+/// text found in any column. Matching ignores case.
+#[derive(Debug, Default)]
+pub struct Filter {
+    terms: Vec<(Option<&'static str>, String)>,
+}
+
+impl Filter {
+    pub fn parse(text: &str) -> Self {
+        let terms = text
+            .split_whitespace()
+            .map(|term| {
+                let term = term.to_lowercase();
+                let field = term.split_once(':').and_then(|(field, value)| {
+                    let field = ["proto", "src", "dst", "ip", "port", "proc", "state"]
+                        .into_iter()
+                        .find(|known| *known == field)?;
+                    Some((field, value.to_string()))
+                });
+                match field {
+                    Some((field, value)) => (Some(field), value),
+                    None => (None, term),
+                }
+            })
+            .collect();
+        Self { terms }
+    }
+
+    pub fn matches(&self, flow: &FlowEvent) -> bool {
+        self.terms.iter().all(|(field, value)| {
+            let has = |text: &str| text.to_lowercase().contains(value.as_str());
+            match field {
+                Some("proto") => flow.proto.eq_ignore_ascii_case(value),
+                Some("src") => has(&flow.src_ip),
+                Some("dst") => has(&flow.dst_ip),
+                Some("ip") => has(&flow.src_ip) || has(&flow.dst_ip),
+                Some("port") => value
+                    .parse::<u16>()
+                    .is_ok_and(|port| flow.src_port == port || flow.dst_port == port),
+                Some("proc") => has(&process_name(flow)),
+                Some("state") => flow.state.as_deref().is_some_and(has),
+                _ => flow_cells(flow).iter().any(|cell| has(cell)),
+            }
+        })
+    }
+}
+
+/// Flows of one process among the visible ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessRow {
+    pub name: String,
+    pub flows: usize,
+    pub bytes: u64,
+    pub packets: u64,
+    pub destinations: usize,
+    pub last_seen: DateTime<Utc>,
+}
+
+pub struct App {
+    flows: VecDeque<FlowEvent>,
+    /// Flows that arrived while paused, shown on resume.
+    held: VecDeque<FlowEvent>,
+    alerts: VecDeque<Alert>,
+    pub paused: bool,
+    pub view: View,
+    pub sort: SortKey,
+    pub descending: bool,
+    pub filter: String,
+    pub search: String,
+    pub input: Input,
+    /// Row selected in the current view; clamped when drawing.
+    pub selected: usize,
+    /// Where flows come from, for the title bar.
+    pub source: String,
+    /// Problems worth showing, e.g. rules that failed to load.
+    pub notice: Option<String>,
+    pub quit: bool,
+}
+
+impl App {
+    pub fn new(source: String) -> Self {
+        Self {
+            flows: VecDeque::new(),
+            held: VecDeque::new(),
+            alerts: VecDeque::new(),
+            paused: false,
+            view: View::Flows,
+            sort: SortKey::Time,
+            descending: true,
+            filter: String::new(),
+            search: String::new(),
+            input: Input::Normal,
+            selected: 0,
+            source,
+            notice: None,
+            quit: false,
+        }
+    }
+
+    pub fn push_flow(&mut self, flow: FlowEvent) {
+        let queue = if self.paused {
+            &mut self.held
+        } else {
+            &mut self.flows
+        };
+        if queue.len() == MAX_FLOWS {
+            queue.pop_front();
+        }
+        queue.push_back(flow);
+    }
+
+    /// Alerts keep arriving while the flow table is paused.
+    pub fn push_alert(&mut self, alert: Alert) {
+        if self.alerts.len() == MAX_ALERTS {
+            self.alerts.pop_back();
+        }
+        self.alerts.push_front(alert);
+    }
+
+    /// Newest first.
+    pub fn alerts(&self) -> impl Iterator<Item = &Alert> {
+        self.alerts.iter()
+    }
+
+    pub fn total_flows(&self) -> usize {
+        self.flows.len()
+    }
+
+    pub fn held_flows(&self) -> usize {
+        self.held.len()
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        if !self.paused {
+            for flow in std::mem::take(&mut self.held) {
+                self.push_flow(flow);
+            }
+        }
+    }
+
+    /// Flows passing the filter, in sort order.
+    pub fn visible_flows(&self) -> Vec<&FlowEvent> {
+        let filter = Filter::parse(&self.filter);
+        let mut flows: Vec<&FlowEvent> = self
+            .flows
+            .iter()
+            .filter(|flow| filter.matches(flow))
+            .collect();
+        flows.sort_by(|a, b| {
+            let order = match self.sort {
+                SortKey::Time => a.ts_last.cmp(&b.ts_last),
+                SortKey::Proto => a.proto.cmp(&b.proto),
+                SortKey::Source => (&a.src_ip, a.src_port).cmp(&(&b.src_ip, b.src_port)),
+                SortKey::Destination => (&a.dst_ip, a.dst_port).cmp(&(&b.dst_ip, b.dst_port)),
+                SortKey::Bytes => a.bytes.cmp(&b.bytes),
+                SortKey::Packets => a.packets.cmp(&b.packets),
+                SortKey::Process => process_name(a).cmp(&process_name(b)),
+            };
+            if self.descending {
+                order.reverse()
+            } else {
+                order
+            }
+        });
+        flows
+    }
+
+    /// Visible flows rolled up by process, most bytes first.
+    pub fn processes(&self) -> Vec<ProcessRow> {
+        let mut rows: HashMap<String, (ProcessRow, HashSet<&str>)> = HashMap::new();
+        for flow in self.visible_flows() {
+            let name = process_name(flow);
+            let (row, destinations) = rows.entry(name.clone()).or_insert_with(|| {
+                let row = ProcessRow {
+                    name,
+                    flows: 0,
+                    bytes: 0,
+                    packets: 0,
+                    destinations: 0,
+                    last_seen: flow.ts_last,
+                };
+                (row, HashSet::new())
+            });
+            row.flows += 1;
+            row.bytes += flow.bytes;
+            row.packets += flow.packets;
+            row.last_seen = row.last_seen.max(flow.ts_last);
+            destinations.insert(&flow.dst_ip);
+            row.destinations = destinations.len();
+        }
+        let mut rows: Vec<ProcessRow> = rows.into_values().map(|(row, _)| row).collect();
+        rows.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.name.cmp(&b.name)));
+        rows
+    }
+
+    /// Text of each row in the current view, as drawn.
+    pub fn rows(&self) -> Vec<Vec<String>> {
+        match self.view {
+            View::Flows => self
+                .visible_flows()
+                .into_iter()
+                .map(|flow| flow_cells(flow).to_vec())
+                .collect(),
+            View::Processes => self.processes().iter().map(process_cells).collect(),
+        }
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return;
+        }
+        match self.input {
+            Input::Normal => self.normal_key(key.code),
+            Input::Filter | Input::Search => self.edit_key(key.code),
+        }
+    }
+
+    fn normal_key(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Char(' ') | KeyCode::Char('p') => self.toggle_pause(),
+            KeyCode::Tab => {
+                self.view = match self.view {
+                    View::Flows => View::Processes,
+                    View::Processes => View::Flows,
+                };
+                self.selected = 0;
+            }
+            KeyCode::Char('s') => self.sort = self.sort.next(),
+            KeyCode::Char('r') => self.descending = !self.descending,
+            KeyCode::Char('f') => self.input = Input::Filter,
+            KeyCode::Char('/') => {
+                self.search.clear();
+                self.input = Input::Search;
+            }
+            KeyCode::Char('n') => self.find(self.selected + 1, true),
+            KeyCode::Char('N') => self.find(self.selected.saturating_sub(1), false),
+            KeyCode::Char('c') => {
+                self.filter.clear();
+                self.search.clear();
+            }
+            KeyCode::Down | KeyCode::Char('j') => self.selected += 1,
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::PageDown => self.selected += PAGE,
+            KeyCode::PageUp => self.selected = self.selected.saturating_sub(PAGE),
+            KeyCode::Home | KeyCode::Char('g') => self.selected = 0,
+            KeyCode::End | KeyCode::Char('G') => self.selected = usize::MAX,
+            _ => {}
+        }
+    }
+
+    /// Enter keeps what was typed, Esc discards it.
+    fn edit_key(&mut self, code: KeyCode) {
+        let searching = self.input == Input::Search;
+        let text = if searching {
+            &mut self.search
+        } else {
+            &mut self.filter
+        };
+        match code {
+            KeyCode::Enter => self.input = Input::Normal,
+            KeyCode::Esc => {
+                text.clear();
+                self.input = Input::Normal;
+            }
+            KeyCode::Backspace => {
+                text.pop();
+            }
+            KeyCode::Char(c) => text.push(c),
+            _ => return,
+        }
+        if searching {
+            self.find(self.selected, true);
+        } else {
+            self.selected = 0;
+        }
+    }
+
+    /// Selects the first row from `start` (wrapping) containing the search
+    /// text; stays put when none does.
+    fn find(&mut self, start: usize, forward: bool) {
+        let needle = self.search.to_lowercase();
+        if needle.is_empty() {
+            return;
+        }
+        let rows = self.rows();
+        let count = rows.len();
+        if count == 0 {
+            return;
+        }
+        let start = start.min(count - 1);
+        let hit = (0..count)
+            .map(|step| {
+                if forward {
+                    (start + step) % count
+                } else {
+                    (start + count - step) % count
+                }
+            })
+            .find(|index| {
+                rows[*index]
+                    .iter()
+                    .any(|cell| cell.to_lowercase().contains(&needle))
+            });
+        if let Some(index) = hit {
+            self.selected = index;
+        }
+    }
+}
+
+pub fn process_name(flow: &FlowEvent) -> String {
+    flow.process
+        .as_ref()
+        .and_then(|process| process.name.clone())
+        .unwrap_or_else(|| "-".into())
+}
+
+pub const FLOW_HEADER: [&str; 9] = [
+    "LAST SEEN",
+    "PROTO",
+    "SOURCE",
+    "DESTINATION",
+    "STATE",
+    "BYTES",
+    "PKTS",
+    "PROCESS",
+    "NAME",
+];
+
+/// The cells of a flow row; NAME is the TLS server name or DNS query.
+pub fn flow_cells(flow: &FlowEvent) -> [String; 9] {
+    [
+        flow.ts_last.format("%H:%M:%S").to_string(),
+        flow.proto.clone(),
+        format!("{}:{}", flow.src_ip, flow.src_port),
+        format!("{}:{}", flow.dst_ip, flow.dst_port),
+        flow.state.clone().unwrap_or_default(),
+        flow.bytes.to_string(),
+        flow.packets.to_string(),
+        process_name(flow),
+        flow.sni
+            .clone()
+            .or_else(|| flow.dns_qname.clone())
+            .unwrap_or_default(),
+    ]
+}
+
+pub const PROCESS_HEADER: [&str; 6] = [
+    "PROCESS",
+    "FLOWS",
+    "BYTES",
+    "PACKETS",
+    "DESTINATIONS",
+    "LAST SEEN",
+];
+
+pub fn process_cells(row: &ProcessRow) -> Vec<String> {
+    vec![
+        row.name.clone(),
+        row.flows.to_string(),
+        row.bytes.to_string(),
+        row.packets.to_string(),
+        row.destinations.to_string(),
+        row.last_seen.format("%H:%M:%S").to_string(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use collector::ProcessIdentity;
+
+    fn flow(second: u32, dst: &str, port: u16, bytes: u64, process: &str) -> FlowEvent {
+        let ts = Utc.with_ymd_and_hms(2026, 1, 1, 12, 0, second).unwrap();
+        FlowEvent {
+            ts_first: ts,
+            ts_last: ts,
+            proto: "TCP".into(),
+            src_ip: "10.0.0.5".into(),
+            src_port: 50000 + port,
+            dst_ip: dst.into(),
+            dst_port: port,
+            bytes,
+            packets: 1,
+            process: Some(ProcessIdentity {
+                pid: 1,
+                ppid: None,
+                name: Some(process.into()),
+                exe_path: None,
+                sha256_16: None,
+                user: None,
+                signed: None,
+            }),
+            ..FlowEvent::default()
+        }
+    }
+
+    fn press(app: &mut App, keys: &str) {
+        for c in keys.chars() {
+            app.handle_key(KeyEvent::from(KeyCode::Char(c)));
+        }
+    }
+
+    #[test]
+    fn filters_sorts_searches_and_pauses() {
+        let mut app = App::new("mock".into());
+        app.push_flow(flow(1, "10.0.0.8", 445, 4096, "notesync.exe"));
+        app.push_flow(flow(2, "93.184.216.34", 443, 100, "firefox"));
+        app.push_flow(flow(3, "93.184.216.35", 443, 900, "firefox"));
+
+        // Newest first by default; `s` moves to the next column.
+        assert_eq!(app.visible_flows()[0].dst_ip, "93.184.216.35");
+        app.sort = SortKey::Destination;
+        press(&mut app, "sr");
+        assert_eq!(app.sort, SortKey::Bytes);
+        assert_eq!(app.visible_flows()[0].bytes, 100);
+
+        press(&mut app, "fport:443 FIRE");
+        app.handle_key(KeyEvent::from(KeyCode::Enter));
+        assert_eq!(app.input, Input::Normal);
+        assert_eq!(app.visible_flows().len(), 2);
+        press(&mut app, "c");
+        assert_eq!(app.visible_flows().len(), 3);
+
+        press(&mut app, "/notes");
+        assert_eq!(app.selected, 2);
+        app.handle_key(KeyEvent::from(KeyCode::Enter));
+
+        app.handle_key(KeyEvent::from(KeyCode::Tab));
+        let processes = app.processes();
+        assert_eq!(processes[0].name, "notesync.exe");
+        assert_eq!((processes[1].flows, processes[1].destinations), (2, 2));
+
+        press(&mut app, " ");
+        app.push_flow(flow(4, "10.0.0.9", 22, 10, "ssh"));
+        assert_eq!((app.total_flows(), app.held_flows()), (3, 1));
+        press(&mut app, "p");
+        assert_eq!((app.total_flows(), app.held_flows()), (4, 0));
+
+        press(&mut app, "q");
+        assert!(app.quit);
+    }
+}
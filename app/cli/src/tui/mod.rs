@@ -0,0 +1,114 @@
+//! `nets-cli tui`: a console for hosts without the desktop app. Live flows
+//! from the collector in a sortable, filterable table (or rolled up by
+//! process) with the alerts the analyzer raises on them. Nothing is stored
+//! or enforced; the daemon does that.
+
+mod app;
+mod ui;
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    time::Duration as StdDuration,
+};
+
+use analyzer::Analyzer;
+use anyhow::Result;
+use chrono::Duration;
+use collector::{CollectorBackend, FlowEvent};
+use normalizer::Normalizer;
+use ratatui::{
+    crossterm::event::{self, Event, KeyEventKind},
+    DefaultTerminal,
+};
+
+use crate::config::Config;
+
+use self::app::App;
+
+/// Flows waiting for the UI thread before new ones are dropped.
+const FLOW_QUEUE: usize = 4096;
+/// Redraw interval while no key is pressed.
+const TICK: StdDuration = StdDuration::from_millis(250);
+
+pub fn run(config: &Config) -> Result<()> {
+    let rt = tokio::runtime::Runtime::new()?;
+    let (backend, source): (Arc<dyn CollectorBackend>, String) =
+        match crate::daemon::collector_backend(config.collector.backend) {
+            Ok(backend) => (backend, format!("{:?} collector", config.collector.backend)),
+            Err(err) => (
+                Arc::new(collector::MockCollector::default()),
+                format!("mock flows ({err:#})"),
+            ),
+        };
+    let mut app = App::new(source);
+    let mut analyzer = analyzer(config, &mut app);
+
+    let (flows, queued) = mpsc::sync_channel(FLOW_QUEUE);
+    let dropped = Arc::new(AtomicU64::new(0));
+    let counter = dropped.clone();
+    backend.subscribe(Arc::new(move |flow: FlowEvent| {
+        if flows.try_send(flow).is_err() {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }));
+    rt.block_on(backend.start())?;
+
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut app, &mut analyzer, &queued, &dropped);
+    ratatui::restore();
+    rt.block_on(backend.stop())?;
+    result
+}
+
+/// Rules that fail to load leave the built-in detectors running, with a
+/// notice in the title bar.
+fn analyzer(config: &Config, app: &mut App) -> Analyzer {
+    let rules = match crate::replay::load_rules(&config.analyzer.rules_path) {
+        Ok(rules) => rules,
+        Err(err) => {
+            app.notice = Some(format!("{err:#}"));
+            Vec::new()
+        }
+    };
+    let mut analyzer = Analyzer::new(Duration::hours(config.analyzer.baseline_hours), rules);
+    analyzer.set_rate_limit(config.analyzer.rate_limit.clone());
+    analyzer.set_severity_overrides(config.analyzer.severity_overrides.clone());
+    analyzer
+}
+
+fn event_loop(
+    terminal: &mut DefaultTerminal,
+    app: &mut App,
+    analyzer: &mut Analyzer,
+    flows: &Receiver<FlowEvent>,
+    dropped: &AtomicU64,
+) -> Result<()> {
+    let normalizer = Normalizer::new(Duration::seconds(60));
+    while !app.quit {
+        while let Ok(flow) = flows.try_recv() {
+            if let Ok(normalized) = normalizer.normalize(flow.clone()) {
+                for alert in analyzer.ingest(normalized) {
+                    app.push_alert(alert);
+                }
+            }
+            app.push_flow(flow);
+        }
+        let dropped = dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            app.notice = Some(format!("{dropped} flows dropped"));
+        }
+        terminal.draw(|frame| ui::draw(frame, app))?;
+        if event::poll(TICK)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    app.handle_key(key);
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,170 @@
+//! Draws an [`App`]: title bar, flow or process table, alert pane and a
+//! status line with the keys.
+
+use analyzer::Severity;
+use ratatui::{
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph, Row, Table, TableState},
+    Frame,
+};
+
+use super::app::{App, Input, View, FLOW_HEADER, PROCESS_HEADER};
+
+pub fn draw(frame: &mut Frame, app: &mut App) {
+    let [title, main, alerts, status] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(5),
+        Constraint::Length(9),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    draw_title(frame, app, title);
+    draw_table(frame, app, main);
+    draw_alerts(frame, app, alerts);
+    draw_status(frame, app, status);
+}
+
+fn draw_title(frame: &mut Frame, app: &App, area: Rect) {
+    let mut spans = vec![
+        Span::from(" nets ").bold().reversed(),
+        Span::from(format!(" {} ", app.source)),
+    ];
+    if app.paused {
+        spans.push(
+            Span::from(format!(" PAUSED, {} new ", app.held_flows()))
+                .black()
+                .on_yellow(),
+        );
+    }
+    if let Some(notice) = &app.notice {
+        spans.push(Span::from(format!(" {notice}")).red());
+    }
+    frame.render_widget(Line::from(spans), area);
+}
+
+fn draw_table(frame: &mut Frame, app: &mut App, area: Rect) {
+    let rows = app.rows();
+    app.selected = app.selected.min(rows.len().saturating_sub(1));
+    let (header, widths, title) = match app.view {
+        View::Flows => (
+            FLOW_HEADER.to_vec(),
+            vec![
+                Constraint::Length(9),
+                Constraint::Length(6),
+                Constraint::Min(21),
+                Constraint::Min(21),
+                Constraint::Length(11),
+                Constraint::Length(10),
+                Constraint::Length(6),
+                Constraint::Min(12),
+                Constraint::Fill(1),
+            ],
+            format!(
+                " Flows {}/{}, by {}{} ",
+                rows.len(),
+                app.total_flows(),
+                app.sort.label(),
+                if app.descending { " ↓" } else { " ↑" }
+            ),
+        ),
+        View::Processes => (
+            PROCESS_HEADER.to_vec(),
+            vec![
+                Constraint::Fill(1),
+                Constraint::Length(7),
+                Constraint::Length(12),
+                Constraint::Length(10),
+                Constraint::Length(13),
+                Constraint::Length(10),
+            ],
+            format!(" Processes {}, by bytes ", rows.len()),
+        ),
+    };
+    let needle = app.search.to_lowercase();
+    let rows = rows.into_iter().map(|cells| {
+        let hit = !needle.is_empty()
+            && cells
+                .iter()
+                .any(|cell| cell.to_lowercase().contains(&needle));
+        let row = Row::new(cells);
+        if hit {
+            row.style(Style::new().fg(Color::Yellow))
+        } else {
+            row
+        }
+    });
+    let table = Table::new(rows, widths)
+        .header(Row::new(header).style(Style::new().add_modifier(Modifier::BOLD)))
+        .block(Block::new().borders(Borders::TOP).title(title))
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    let mut state = TableState::default().with_selected(Some(app.selected));
+    frame.render_stateful_widget(table, area, &mut state);
+}
+
+fn draw_alerts(frame: &mut Frame, app: &App, area: Rect) {
+    let rows = app.alerts().map(|alert| {
+        let color = match alert.severity {
+            Severity::High => Color::Red,
+            Severity::Medium => Color::Yellow,
+            Severity::Low => Color::Reset,
+        };
+        Row::new(vec![
+            alert.ts.format("%H:%M:%S").to_string(),
+            alert.severity.as_str().to_string(),
+            alert.rule_id.clone(),
+            alert.summary.clone(),
+        ])
+        .style(Style::new().fg(color))
+    });
+    let count = app.alerts().count();
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(9),
+            Constraint::Length(9),
+            Constraint::Length(28),
+            Constraint::Fill(1),
+        ],
+    )
+    .block(
+        Block::new()
+            .borders(Borders::TOP)
+            .title(format!(" Alerts {count} ")),
+    );
+    frame.render_widget(table, area);
+}
+
+fn draw_status(frame: &mut Frame, app: &App, area: Rect) {
+    let line = match app.input {
+        Input::Filter => Line::from(vec![
+            Span::from(" filter: ").bold(),
+            Span::from(app.filter.as_str()),
+            Span::from("▏"),
+            Span::from("   proto: src: dst: ip: port: proc: state: or text; Enter keep, Esc clear")
+                .dim(),
+        ]),
+        Input::Search => Line::from(vec![
+            Span::from(" search: ").bold(),
+            Span::from(app.search.as_str()),
+            Span::from("▏"),
+            Span::from("   Enter keep, Esc clear").dim(),
+        ]),
+        Input::Normal => {
+            let mut spans = Vec::new();
+            if !app.filter.is_empty() {
+                spans.push(Span::from(format!(" [{}]", app.filter)).cyan());
+            }
+            spans.push(
+                Span::from(
+                    " q quit  space pause  tab flows/processes  s sort  r reverse  \
+                     f filter  / search  n/N next/prev  c clear",
+                )
+                .dim(),
+            );
+            Line::from(spans)
+        }
+    };
+    frame.render_widget(Paragraph::new(line), area);
+}
@@ -0,0 +1,302 @@
+//! `nets-cli watch`: follows the live flow stream and calls attention to
+//! the flows of given processes, ports or hosts, by printing them
+//! highlighted, ringing the terminal bell or running a command.
+
+use std::{
+    collections::HashMap,
+    io::{self, IsTerminal, Write},
+    net::IpAddr,
+    process::Command,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use collector::FlowEvent;
+use policy::IpNetwork;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::config::Config;
+
+/// Flows waiting to be matched before new ones are dropped.
+const FLOW_QUEUE: usize = 4096;
+
+pub struct Watch {
+    /// Process names or executable file names, case-insensitive.
+    pub processes: Vec<String>,
+    /// Either end of the flow.
+    pub ports: Vec<u16>,
+    /// Either end of the flow.
+    pub hosts: Vec<IpNetwork>,
+    pub bell: bool,
+    /// Shell command run for every match, with the flow in `NETS_*`
+    /// environment variables.
+    pub exec: Option<String>,
+    /// A connection that matched is not reported again for this long.
+    pub cooldown: Duration,
+    /// One JSON object per matching flow, as `tail` prints them, without
+    /// color or bell.
+    pub json: bool,
+}
+
+/// Matches when every kind of criterion given matches; values of one kind
+/// are alternatives.
+struct Matcher {
+    processes: Vec<String>,
+    ports: Vec<u16>,
+    hosts: Vec<IpNetwork>,
+}
+
+impl Matcher {
+    fn matches(&self, flow: &FlowEvent) -> bool {
+        let process = || {
+            let identity = flow.process.as_ref();
+            let name = identity.and_then(|p| p.name.as_deref());
+            let exe = identity
+                .and_then(|p| p.exe_path.as_deref())
+                .and_then(|path| path.rsplit(['/', '\\']).next());
+            self.processes.iter().any(|wanted| {
+                [name, exe]
+                    .into_iter()
+                    .flatten()
+                    .any(|candidate| candidate.eq_ignore_ascii_case(wanted))
+            })
+        };
+        let port = || {
+            self.ports
+                .iter()
+                .any(|port| *port == flow.src_port || *port == flow.dst_port)
+        };
+        let host = || {
+            [&flow.src_ip, &flow.dst_ip]
+                .into_iter()
+                .filter_map(|ip| ip.parse::<IpAddr>().ok())
+                .any(|ip| self.hosts.iter().any(|network| network.contains(&ip)))
+        };
+        (self.processes.is_empty() || process())
+            && (self.ports.is_empty() || port())
+            && (self.hosts.is_empty() || host())
+    }
+}
+
+pub fn run(config: &Config, watch: Watch) -> Result<()> {
+    if watch.processes.is_empty() && watch.ports.is_empty() && watch.hosts.is_empty() {
+        return Err(anyhow!(
+            "nothing to watch: give at least one --proc, --port or --host"
+        ));
+    }
+    let backend = crate::daemon::collector_backend(config.collector.backend)?;
+    let rt = tokio::runtime::Runtime::new()?;
+    rt.block_on(async {
+        let (flows, mut queued) = mpsc::channel(FLOW_QUEUE);
+        backend.subscribe(Arc::new(move |flow: FlowEvent| {
+            // Under a flood the highlights are behind anyway; skip rather
+            // than stall the collector.
+            let _ = flows.try_send(flow);
+        }));
+        backend.start().await?;
+
+        let mut alarm = Alarm::new(watch);
+        let shutdown = crate::daemon::shutdown_signal();
+        tokio::pin!(shutdown);
+        let result = loop {
+            tokio::select! {
+                flow = queued.recv() => match flow {
+                    Some(flow) => match alarm.flow(&flow) {
+                        Ok(true) => {}
+                        Ok(false) => break Ok(()),
+                        Err(err) => break Err(err),
+                    },
+                    None => break Ok(()),
+                },
+                result = &mut shutdown => break result,
+            }
+        };
+        backend.stop().await?;
+        result
+    })
+}
+
+struct Alarm {
+    matcher: Matcher,
+    bell: bool,
+    exec: Option<String>,
+    cooldown: Duration,
+    json: bool,
+    color: bool,
+    /// Last report per connection, pruned as entries expire.
+    reported: HashMap<(String, String, u16, String, u16), Instant>,
+}
+
+impl Alarm {
+    fn new(watch: Watch) -> Self {
+        Self {
+            matcher: Matcher {
+                processes: watch.processes,
+                ports: watch.ports,
+                hosts: watch.hosts,
+            },
+            bell: watch.bell,
+            exec: watch.exec,
+            cooldown: watch.cooldown,
+            json: watch.json,
+            color: !watch.json && io::stdout().is_terminal(),
+            reported: HashMap::new(),
+        }
+    }
+
+    /// `false` once stdout is closed.
+    fn flow(&mut self, flow: &FlowEvent) -> Result<bool> {
+        if !self.matcher.matches(flow) {
+            return Ok(true);
+        }
+        let now = Instant::now();
+        let cooldown = self.cooldown;
+        self.reported
+            .retain(|_, at| now.duration_since(*at) < cooldown);
+        let key = (
+            flow.proto.clone(),
+            flow.src_ip.clone(),
+            flow.src_port,
+            flow.dst_ip.clone(),
+            flow.dst_port,
+        );
+        if self.reported.contains_key(&key) {
+            return Ok(true);
+        }
+        self.reported.insert(key, now);
+
+        let mut line = if self.json {
+            serde_json::to_string(flow)?
+        } else {
+            describe(flow)
+        };
+        if self.color {
+            line = format!("\x1b[1;33m{line}\x1b[0m");
+        }
+        if self.bell && !self.json {
+            line.push('\x07');
+        }
+        line.push('\n');
+        let mut out = io::stdout().lock();
+        match out.write_all(line.as_bytes()).and_then(|()| out.flush()) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(false),
+            Err(err) => return Err(err.into()),
+        }
+        if let Some(command) = &self.exec {
+            run_hook(command, flow);
+        }
+        Ok(true)
+    }
+}
+
+/// `2026-10-16 15:00:01  chrome.exe[1234]  TCP 10.0.0.2:50122 -> 10.0.0.5:3389  1.2 KiB`
+fn describe(flow: &FlowEvent) -> String {
+    let process = match &flow.process {
+        Some(identity) => format!(
+            "{}[{}]",
+            identity.name.as_deref().unwrap_or("?"),
+            identity.pid
+        ),
+        None => "-".into(),
+    };
+    let mut line = format!(
+        "{}  {process}  {} {}:{} -> {}:{}  {}",
+        flow.ts_first.format("%Y-%m-%d %H:%M:%S"),
+        flow.proto,
+        flow.src_ip,
+        flow.src_port,
+        flow.dst_ip,
+        flow.dst_port,
+        crate::top::human_bytes(flow.bytes)
+    );
+    if let Some(domain) = flow.sni.as_deref().or(flow.dns_qname.as_deref()) {
+        line.push_str("  ");
+        line.push_str(domain);
+    }
+    line
+}
+
+/// Starts `command` through the shell without waiting for it; a thread
+/// reaps it and reports a failure.
+fn run_hook(command: &str, flow: &FlowEvent) {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let process = flow.process.as_ref();
+    shell
+        .arg(command)
+        .env("NETS_PROTO", &flow.proto)
+        .env("NETS_SRC_IP", &flow.src_ip)
+        .env("NETS_SRC_PORT", flow.src_port.to_string())
+        .env("NETS_DST_IP", &flow.dst_ip)
+        .env("NETS_DST_PORT", flow.dst_port.to_string())
+        .env(
+            "NETS_PROCESS",
+            process.and_then(|p| p.name.as_deref()).unwrap_or_default(),
+        )
+        .env(
+            "NETS_PID",
+            process.map(|p| p.pid.to_string()).unwrap_or_default(),
+        )
+        .env("NETS_FLOW", serde_json::to_string(flow).unwrap_or_default());
+    match shell.spawn() {
+        Ok(mut child) => {
+            let command = command.to_string();
+            std::thread::spawn(move || match child.wait() {
+                Ok(status) if !status.success() => {
+                    warn!(%command, %status, "watch command failed")
+                }
+                Err(err) => warn!(%command, %err, "watch command failed"),
+                Ok(_) => {}
+            });
+        }
+        Err(err) => warn!(%command, %err, "cannot start watch command"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use collector::ProcessIdentity;
+
+    #[test]
+    fn matches_every_given_kind() {
+        let flow = FlowEvent {
+            proto: "TCP".into(),
+            src_ip: "10.0.0.2".into(),
+            src_port: 50122,
+            dst_ip: "10.0.0.5".into(),
+            dst_port: 3389,
+            process: Some(ProcessIdentity {
+                pid: 1234,
+                ppid: None,
+                name: Some("mstsc".into()),
+                exe_path: Some(r"C:\Windows\System32\mstsc.exe".into()),
+                sha256_16: None,
+                user: None,
+                signed: None,
+            }),
+            ..FlowEvent::default()
+        };
+        let matcher = |processes: &[&str], ports: &[u16], hosts: &[&str]| Matcher {
+            processes: processes.iter().map(|p| p.to_string()).collect(),
+            ports: ports.to_vec(),
+            hosts: hosts.iter().map(|h| h.parse().unwrap()).collect(),
+        };
+        assert!(matcher(&["MSTSC.EXE"], &[], &[]).matches(&flow));
+        assert!(matcher(&["mstsc"], &[3389], &["10.0.0.0/24"]).matches(&flow));
+        assert!(matcher(&[], &[22, 3389], &[]).matches(&flow));
+        assert!(!matcher(&["chrome.exe"], &[3389], &[]).matches(&flow));
+        assert!(!matcher(&[], &[3389], &["192.168.0.0/16"]).matches(&flow));
+        assert!(describe(&flow).contains("mstsc[1234]  TCP 10.0.0.2:50122 -> 10.0.0.5:3389"));
+    }
+}
@@ -0,0 +1,117 @@
+//! What the collector can observe on this host with the privileges the
+//! current process holds, for health checks. `None` marks what cannot be
+//! told on this platform.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// `linux`, `windows`, `macos` or `mock`.
+    pub backend: String,
+    /// Raw packets can be read: CAP_NET_RAW on Linux, a readable BPF
+    /// device on macOS, an elevated token on Windows.
+    pub capture: Option<bool>,
+    /// Sockets of other users' processes can be tied to their owners.
+    pub process_attribution: Option<bool>,
+    /// Kernel BTF is present and BPF programs may be loaded (Linux).
+    pub ebpf: Option<bool>,
+}
+
+impl Capabilities {
+    /// The generated flows of the mock collector need nothing.
+    pub fn mock() -> Self {
+        Self {
+            backend: "mock".into(),
+            capture: Some(true),
+            process_attribution: Some(true),
+            ebpf: None,
+        }
+    }
+
+    /// Probes the platform's native collector.
+    pub fn probe() -> Self {
+        #[cfg(target_os = "linux")]
+        {
+            let effective = std::fs::read_to_string("/proc/self/status")
+                .ok()
+                .and_then(|status| effective_capabilities(&status));
+            let has = |bit: u32| effective.map(|caps| caps & (1 << bit) != 0);
+            Self {
+                backend: "linux".into(),
+                capture: has(CAP_NET_RAW),
+                // The socket inodes of other processes are behind their
+                // fd directories.
+                process_attribution: Some(std::fs::read_dir("/proc/1/fd").is_ok()),
+                ebpf: Some(
+                    std::path::Path::new("/sys/kernel/btf/vmlinux").exists()
+                        && (has(CAP_BPF) == Some(true) || has(CAP_SYS_ADMIN) == Some(true)),
+                ),
+            }
+        }
+        #[cfg(target_os = "macos")]
+        {
+            // Opening a BPF device needs root or the access_bpf group.
+            let capture = (0..8).any(|n| std::fs::File::open(format!("/dev/bpf{n}")).is_ok());
+            Self {
+                backend: "macos".into(),
+                capture: Some(capture),
+                process_attribution: None,
+                ebpf: None,
+            }
+        }
+        #[cfg(target_os = "windows")]
+        {
+            // `net session` only succeeds from an elevated prompt.
+            let elevated = std::process::Command::new("net")
+                .arg("session")
+                .output()
+                .map(|out| out.status.success())
+                .ok();
+            Self {
+                backend: "windows".into(),
+                capture: elevated,
+                process_attribution: elevated,
+                ebpf: None,
+            }
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+        {
+            Self {
+                backend: std::env::consts::OS.into(),
+                capture: None,
+                process_attribution: None,
+                ebpf: None,
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+const CAP_NET_RAW: u32 = 13;
+#[cfg(target_os = "linux")]
+const CAP_SYS_ADMIN: u32 = 21;
+#[cfg(target_os = "linux")]
+const CAP_BPF: u32 = 39;
+
+/// The `CapEff:` mask of a `/proc/<pid>/status` file.
+#[cfg(target_os = "linux")]
+fn effective_capabilities(status: &str) -> Option<u64> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))
+        .and_then(|mask| u64::from_str_radix(mask.trim(), 16).ok())
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_effective_mask() {
+        let status = "Name:\tnets-cli\nCapPrm:\t0000000000000000\nCapEff:\t0000000000003000\n";
+        let caps = effective_capabilities(status).unwrap();
+        assert_ne!(caps & (1 << CAP_NET_RAW), 0);
+        assert_eq!(caps & (1 << CAP_SYS_ADMIN), 0);
+        assert_eq!(effective_capabilities("Name:\tx\n"), None);
+    }
+}
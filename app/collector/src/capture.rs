@@ -0,0 +1,470 @@
+//! Targeted packet captures for evidence: the packets of one flow's
+//! 5-tuple, both ways, for a fixed time, written as pcapng (one section,
+//! one interface, enhanced packet blocks). tcpdump captures on Linux and
+//! macOS and its libpcap stream is rewritten as pcapng on the fly; on
+//! Windows pktmon captures and converts its own trace.
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::IpAddr,
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+
+use crate::FlowEvent;
+
+const SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const INTERFACE_DESCRIPTION: u32 = 1;
+const SIMPLE_PACKET: u32 = 3;
+const ENHANCED_PACKET: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+/// Larger records in the libpcap stream mean it is not one.
+const MAX_RECORD: usize = 256 * 1024;
+
+/// Both directions of one connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlowFilter {
+    /// Lowercase, as BPF spells it.
+    pub proto: String,
+    pub src: (IpAddr, u16),
+    pub dst: (IpAddr, u16),
+}
+
+impl FlowFilter {
+    pub fn of(flow: &FlowEvent) -> Result<Self> {
+        let ip = |value: &str| {
+            value
+                .parse::<IpAddr>()
+                .map_err(|_| anyhow!("flow address {value:?} is not an IP address"))
+        };
+        Ok(Self {
+            proto: flow.proto.to_ascii_lowercase(),
+            src: (ip(&flow.src_ip)?, flow.src_port),
+            dst: (ip(&flow.dst_ip)?, flow.dst_port),
+        })
+    }
+
+    fn has_ports(&self) -> bool {
+        matches!(self.proto.as_str(), "tcp" | "udp")
+    }
+
+    /// Capture filter expression for tcpdump/libpcap. Ports only count for
+    /// TCP and UDP, and an unknown (zero) port matches any.
+    pub fn bpf(&self) -> String {
+        let end = |(ip, port): (IpAddr, u16), side: &str| {
+            if self.has_ports() && port != 0 {
+                format!("{side} host {ip} and {side} port {port}")
+            } else {
+                format!("{side} host {ip}")
+            }
+        };
+        let tuple = format!(
+            "({} and {}) or ({} and {})",
+            end(self.src, "src"),
+            end(self.dst, "dst"),
+            end(self.dst, "src"),
+            end(self.src, "dst")
+        );
+        if self.has_ports() {
+            format!("{} and ({tuple})", self.proto)
+        } else {
+            tuple
+        }
+    }
+}
+
+impl fmt::Display for FlowFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let end = |(ip, port): (IpAddr, u16)| match ip {
+            IpAddr::V4(ip) => format!("{ip}:{port}"),
+            IpAddr::V6(ip) => format!("[{ip}]:{port}"),
+        };
+        write!(
+            f,
+            "{} {} <-> {}",
+            self.proto.to_ascii_uppercase(),
+            end(self.src),
+            end(self.dst)
+        )
+    }
+}
+
+/// What went into a capture file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CaptureStats {
+    pub packets: u64,
+    /// Captured bytes, link-layer headers included.
+    pub bytes: u64,
+}
+
+/// Captures `filter` for `duration` into the pcapng file `out`. Needs the
+/// privileges the platform's capture tool needs.
+pub fn capture(filter: &FlowFilter, duration: Duration, out: &Path) -> Result<CaptureStats> {
+    platform_capture(filter, duration, out)
+}
+
+#[cfg(not(windows))]
+fn platform_capture(filter: &FlowFilter, duration: Duration, out: &Path) -> Result<CaptureStats> {
+    use std::{
+        fs::File,
+        io::{BufReader, BufWriter},
+        process::{Command, Stdio},
+        thread,
+        time::Instant,
+    };
+
+    let file = File::create(out).with_context(|| format!("creating {}", out.display()))?;
+    // -U hands over every packet as it arrives, so killing tcpdump at the
+    // deadline loses at most the one being written.
+    let mut child = Command::new("tcpdump")
+        .args(["-i", "any", "-n", "-U", "-s", "0", "-w", "-"])
+        .arg(filter.bpf())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("running tcpdump; is it installed?")?;
+    let stdout = child.stdout.take().context("stdout not captured")?;
+    let comment = format!("nets capture of {filter}");
+    let writer = thread::spawn(move || {
+        pcap_to_pcapng(BufReader::new(stdout), BufWriter::new(file), &comment)
+    });
+
+    let deadline = Instant::now() + duration;
+    let exited = loop {
+        if let Some(status) = child.try_wait()? {
+            break Some(status);
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            break None;
+        }
+        thread::sleep((deadline - now).min(Duration::from_millis(100)));
+    };
+    if exited.is_none() {
+        // Already gone if it exited since the last check.
+        let _ = child.kill();
+        child.wait()?;
+    }
+    let written = writer
+        .join()
+        .map_err(|_| anyhow!("capture writer panicked"))?;
+    if let Some(status) = exited.filter(|status| !status.success()) {
+        let mut stderr = String::new();
+        if let Some(mut pipe) = child.stderr.take() {
+            pipe.read_to_string(&mut stderr)?;
+        }
+        // Nothing worth keeping was written.
+        let _ = std::fs::remove_file(out);
+        return Err(anyhow!("tcpdump failed ({status}): {}", stderr.trim()));
+    }
+    written
+}
+
+#[cfg(windows)]
+fn platform_capture(filter: &FlowFilter, duration: Duration, out: &Path) -> Result<CaptureStats> {
+    use std::process::Command;
+
+    let pktmon = |args: &[&str]| -> Result<()> {
+        let output = Command::new("pktmon")
+            .args(args)
+            .output()
+            .context("running pktmon")?;
+        if !output.status.success() {
+            // pktmon reports errors on stdout.
+            return Err(anyhow!(
+                "pktmon {} failed ({}): {}",
+                args.join(" "),
+                output.status,
+                String::from_utf8_lossy(&output.stdout).trim()
+            ));
+        }
+        Ok(())
+    };
+    let (src, dst) = (filter.src.0.to_string(), filter.dst.0.to_string());
+    let (src_port, dst_port) = (filter.src.1.to_string(), filter.dst.1.to_string());
+    let mut add = vec![
+        "filter",
+        "add",
+        "nets-pcap",
+        "-i",
+        src.as_str(),
+        dst.as_str(),
+    ];
+    if filter.has_ports() {
+        let transport = if filter.proto == "tcp" { "TCP" } else { "UDP" };
+        add.extend(["-t", transport]);
+        let ports: Vec<&str> = [src_port.as_str(), dst_port.as_str()]
+            .into_iter()
+            .filter(|port| *port != "0")
+            .collect();
+        if !ports.is_empty() {
+            add.push("-p");
+            add.extend(ports);
+        }
+    }
+    let etl = out.with_extension("etl");
+    let etl_path = etl.to_string_lossy().into_owned();
+    let out_path = out.to_string_lossy().into_owned();
+
+    // pktmon filters are global; the capture runs with ours alone.
+    pktmon(&["filter", "remove"])?;
+    pktmon(&add)?;
+    let started = pktmon(&[
+        "start",
+        "--capture",
+        "--pkt-size",
+        "0",
+        "--file-name",
+        etl_path.as_str(),
+    ]);
+    if started.is_ok() {
+        std::thread::sleep(duration);
+    }
+    let stopped = started.and_then(|_| pktmon(&["stop"]));
+    let _ = pktmon(&["filter", "remove"]);
+    stopped?;
+    pktmon(&["etl2pcap", etl_path.as_str(), "--out", out_path.as_str()])?;
+    let _ = std::fs::remove_file(&etl);
+    let data = std::fs::read(out).with_context(|| format!("reading {}", out.display()))?;
+    pcapng_stats(&data)
+}
+
+/// Rewrites a libpcap stream (any byte order, micro- or nanosecond
+/// timestamps) as pcapng, with `comment` on the section. A record cut
+/// short at the end of the stream, as a killed capture leaves it, is
+/// dropped.
+pub fn pcap_to_pcapng(
+    mut input: impl Read,
+    output: impl Write,
+    comment: &str,
+) -> Result<CaptureStats> {
+    let mut header = [0u8; 24];
+    input
+        .read_exact(&mut header)
+        .context("the capture produced no pcap header")?;
+    let magic = u32::from_le_bytes(header[..4].try_into()?);
+    let (little_endian, nanos) = match magic {
+        0xa1b2_c3d4 => (true, false),
+        0xa1b2_3c4d => (true, true),
+        0xd4c3_b2a1 => (false, false),
+        0x4d3c_b2a1 => (false, true),
+        _ => return Err(anyhow!("not a pcap stream")),
+    };
+    let read_u32 = |bytes: &[u8]| {
+        let bytes: [u8; 4] = bytes[..4].try_into().expect("four bytes");
+        if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    };
+    let snaplen = read_u32(&header[16..]);
+    // The upper bits of the field carry FCS details pcapng has no room for.
+    let link_type = (read_u32(&header[20..]) & 0xffff) as u16;
+    let mut writer = PcapngWriter::new(output, link_type, snaplen, nanos, comment)?;
+
+    let mut stats = CaptureStats::default();
+    let mut record = [0u8; 16];
+    let mut packet = Vec::new();
+    loop {
+        if !read_or_end(&mut input, &mut record)? {
+            break;
+        }
+        let captured = read_u32(&record[8..]) as usize;
+        if captured > MAX_RECORD {
+            return Err(anyhow!("corrupt pcap record of {captured} bytes"));
+        }
+        packet.resize(captured, 0);
+        if !read_or_end(&mut input, &mut packet)? {
+            break;
+        }
+        let unit = if nanos { 1_000_000_000 } else { 1_000_000 };
+        let ts = u64::from(read_u32(&record)) * unit + u64::from(read_u32(&record[4..]));
+        writer.packet(ts, read_u32(&record[12..]), &packet)?;
+        stats.packets += 1;
+        stats.bytes += captured as u64;
+    }
+    writer.out.flush()?;
+    Ok(stats)
+}
+
+/// Fills `buf`; false when the stream ends first.
+fn read_or_end(input: &mut impl Read, buf: &mut [u8]) -> Result<bool> {
+    match input.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Counts the packets in a pcapng file, checking the block framing on the
+/// way.
+pub fn pcapng_stats(data: &[u8]) -> Result<CaptureStats> {
+    if data.len() < 12 || u32::from_le_bytes(data[..4].try_into()?) != SECTION_HEADER {
+        return Err(anyhow!("not a pcapng file"));
+    }
+    let little_endian = u32::from_le_bytes(data[8..12].try_into()?) == BYTE_ORDER_MAGIC;
+    let read_u32 = |offset: usize| -> Result<u32> {
+        let bytes: [u8; 4] = data
+            .get(offset..offset + 4)
+            .ok_or_else(|| anyhow!("truncated block at byte {offset}"))?
+            .try_into()?;
+        Ok(if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    };
+    let mut stats = CaptureStats::default();
+    let mut offset = 0;
+    while offset < data.len() {
+        let kind = read_u32(offset)?;
+        let length = read_u32(offset + 4)? as usize;
+        if length < 12
+            || !length.is_multiple_of(4)
+            || read_u32(offset + length - 4)? as usize != length
+        {
+            return Err(anyhow!("malformed block at byte {offset}"));
+        }
+        let captured = match kind {
+            ENHANCED_PACKET => Some(read_u32(offset + 20)?),
+            // Its captured length is the block's, less padding unknown here.
+            SIMPLE_PACKET => Some(length as u32 - 16),
+            _ => None,
+        };
+        if let Some(captured) = captured {
+            stats.packets += 1;
+            stats.bytes += u64::from(captured);
+        }
+        offset += length;
+    }
+    Ok(stats)
+}
+
+struct PcapngWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Writes the section header and the one interface description.
+    fn new(mut out: W, link_type: u16, snaplen: u32, nanos: bool, comment: &str) -> Result<Self> {
+        let mut section = Vec::new();
+        section.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        section.extend_from_slice(&1u16.to_le_bytes());
+        section.extend_from_slice(&0u16.to_le_bytes());
+        // Section length not given.
+        section.extend_from_slice(&(-1i64).to_le_bytes());
+        // opt_comment, shb_userappl.
+        push_option(&mut section, 1, comment.as_bytes());
+        let application = concat!("nets ", env!("CARGO_PKG_VERSION"));
+        push_option(&mut section, 4, application.as_bytes());
+        push_option(&mut section, 0, &[]);
+        write_block(&mut out, SECTION_HEADER, &section)?;
+
+        let mut interface = Vec::new();
+        interface.extend_from_slice(&link_type.to_le_bytes());
+        interface.extend_from_slice(&0u16.to_le_bytes());
+        interface.extend_from_slice(&snaplen.to_le_bytes());
+        // if_tsresol: 10^-9 or 10^-6 seconds.
+        push_option(&mut interface, 9, &[if nanos { 9 } else { 6 }]);
+        push_option(&mut interface, 0, &[]);
+        write_block(&mut out, INTERFACE_DESCRIPTION, &interface)?;
+        Ok(Self { out })
+    }
+
+    /// `ts` is in the interface's resolution.
+    fn packet(&mut self, ts: u64, original: u32, data: &[u8]) -> Result<()> {
+        let mut body = Vec::with_capacity(20 + data.len() + 3);
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&((ts >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(ts as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&original.to_le_bytes());
+        body.extend_from_slice(data);
+        write_block(&mut self.out, ENHANCED_PACKET, &body)
+    }
+}
+
+/// Option code, length and value padded to 32 bits.
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    body.extend_from_slice(value);
+    body.resize(body.len().next_multiple_of(4), 0);
+}
+
+/// Frames `body`, padded to 32 bits, with the block type and the total
+/// length before and after it.
+fn write_block(out: &mut impl Write, kind: u32, body: &[u8]) -> Result<()> {
+    let padded = body.len().next_multiple_of(4);
+    let total = (12 + padded) as u32;
+    out.write_all(&kind.to_le_bytes())?;
+    out.write_all(&total.to_le_bytes())?;
+    out.write_all(body)?;
+    out.write_all(&[0; 3][..padded - body.len()])?;
+    out.write_all(&total.to_le_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrites_pcap_stream_as_pcapng() {
+        let mut stream = Vec::new();
+        // Magic, version 2.4, zone, accuracy, snaplen, Linux cooked v1.
+        for value in [0xa1b2_c3d4u32, 0x0004_0002, 0, 0, 262_144, 113] {
+            stream.extend_from_slice(&value.to_le_bytes());
+        }
+        for (seconds, micros, frame) in [(100u32, 250u32, vec![7u8; 61]), (101, 0, vec![9; 40])] {
+            for value in [seconds, micros, frame.len() as u32, 1500] {
+                stream.extend_from_slice(&value.to_le_bytes());
+            }
+            stream.extend_from_slice(&frame);
+        }
+        // A record tcpdump was killed in the middle of.
+        stream.extend_from_slice(&[1, 2, 3]);
+
+        let mut file = Vec::new();
+        let stats = pcap_to_pcapng(stream.as_slice(), &mut file, "nets capture").unwrap();
+        assert_eq!(
+            stats,
+            CaptureStats {
+                packets: 2,
+                bytes: 101
+            }
+        );
+        assert_eq!(pcapng_stats(&file).unwrap(), stats);
+        assert_eq!(file.len() % 4, 0);
+        // The first packet block follows the section and interface blocks.
+        let section = u32::from_le_bytes(file[4..8].try_into().unwrap()) as usize;
+        let interface = u32::from_le_bytes(file[section + 4..section + 8].try_into().unwrap());
+        let packet = &file[section + interface as usize..];
+        assert_eq!(packet[..4], ENHANCED_PACKET.to_le_bytes());
+        let ts = u64::from(u32::from_le_bytes(packet[12..16].try_into().unwrap())) << 32
+            | u64::from(u32::from_le_bytes(packet[16..20].try_into().unwrap()));
+        assert_eq!(ts, 100_000_250);
+        assert!(pcap_to_pcapng(&b"nope"[..], Vec::new(), "").is_err());
+
+        let flow = FlowEvent {
+            proto: "TCP".into(),
+            src_ip: "10.0.0.5".into(),
+            src_port: 51000,
+            dst_ip: "10.0.0.8".into(),
+            dst_port: 445,
+            ..FlowEvent::default()
+        };
+        let filter = FlowFilter::of(&flow).unwrap();
+        assert_eq!(
+            filter.bpf(),
+            "tcp and ((src host 10.0.0.5 and src port 51000 and dst host 10.0.0.8 and dst port 445) \
+             or (src host 10.0.0.8 and src port 445 and dst host 10.0.0.5 and dst port 51000))"
+        );
+        assert_eq!(filter.to_string(), "TCP 10.0.0.5:51000 <-> 10.0.0.8:445");
+    }
+}
@@ -0,0 +1,480 @@
+//! LAN services announced over mDNS (DNS-SD), SSDP and DHCP. A scan asks
+//! the multicast groups once and listens for the length of its window;
+//! DHCP is only heard passively, from clients that happen to request a
+//! lease while the scan runs. IPv4 only.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::pcap::dns_name;
+
+const MDNS_GROUP: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)), 5353);
+const SSDP_GROUP: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(239, 255, 255, 250)), 1900);
+const DNS_SD_META: &str = "_services._dns-sd._udp.local";
+const DHCP_MAGIC: [u8; 4] = [99, 130, 83, 99];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiscoveredService {
+    /// `mdns:<instance>`, `ssdp:<uuid>` or `dhcp:<mac>`: stable across
+    /// scans as long as the device keeps announcing the same identity.
+    pub id: String,
+    pub name: String,
+    /// `mDNS`, `SSDP` or `DHCP`.
+    pub protocol: String,
+    pub address: String,
+    pub port: u16,
+}
+
+/// Runs discovery scans; see the module docs for what a scan does.
+#[derive(Debug, Clone)]
+pub struct ServiceDiscovery {
+    window: Duration,
+    mdns: bool,
+    ssdp: bool,
+    dhcp: bool,
+}
+
+impl Default for ServiceDiscovery {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(3),
+            mdns: true,
+            ssdp: true,
+            dhcp: true,
+        }
+    }
+}
+
+impl ServiceDiscovery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How long a scan waits for answers.
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Services that answered, sorted by id. A protocol whose socket
+    /// cannot be opened is skipped with a warning, so an unprivileged
+    /// scan still returns mDNS and SSDP results without DHCP.
+    pub fn scan(&self) -> Result<Vec<DiscoveredService>> {
+        let window = self.window;
+        let found = std::thread::scope(|scope| {
+            let mut scans = Vec::new();
+            if self.mdns {
+                scans.push(("mDNS", scope.spawn(move || scan_mdns(window))));
+            }
+            if self.ssdp {
+                scans.push(("SSDP", scope.spawn(move || scan_ssdp(window))));
+            }
+            if self.dhcp {
+                scans.push(("DHCP", scope.spawn(move || listen_dhcp(window))));
+            }
+            let mut found = Vec::new();
+            for (protocol, scan) in scans {
+                match scan.join() {
+                    Ok(Ok(services)) => found.extend(services),
+                    Ok(Err(err)) => warn!("{protocol} discovery skipped: {err:#}"),
+                    Err(_) => warn!("{protocol} discovery panicked"),
+                }
+            }
+            found
+        });
+        let mut by_id: HashMap<String, DiscoveredService> = HashMap::new();
+        for service in found {
+            by_id.insert(service.id.clone(), service);
+        }
+        let mut services: Vec<_> = by_id.into_values().collect();
+        services.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(services)
+    }
+}
+
+/// Datagrams that arrive on `socket` before `deadline`, with their sender.
+fn receive_until(socket: &UdpSocket, deadline: Instant) -> Vec<(Vec<u8>, SocketAddr)> {
+    let mut datagrams = Vec::new();
+    let mut buffer = vec![0u8; 9000];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() || socket.set_read_timeout(Some(left)).is_err() {
+            return datagrams;
+        }
+        match socket.recv_from(&mut buffer) {
+            Ok((read, from)) => datagrams.push((buffer[..read].to_vec(), from)),
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                return datagrams
+            }
+            // ICMP errors from earlier sends surface here on some systems.
+            Err(_) => continue,
+        }
+    }
+}
+
+/// Asks for every advertised service type, then for the instances of
+/// each type. Queries go from an ephemeral port, so responders answer by
+/// unicast (RFC 6762, section 6.7) and port 5353 can stay with the OS's
+/// own responder.
+fn scan_mdns(window: Duration) -> Result<Vec<DiscoveredService>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_multicast_ttl_v4(255)?;
+    let deadline = Instant::now() + window;
+    socket.send_to(&mdns_query(&[DNS_SD_META]), MDNS_GROUP)?;
+    // A third of the window to learn the types, the rest for instances.
+    let mut datagrams = receive_until(&socket, Instant::now() + window / 3);
+    let mut types: Vec<String> = datagrams
+        .iter()
+        .flat_map(|(message, _)| parse_mdns_records(message))
+        .filter_map(|record| match record {
+            MdnsRecord::Ptr { name, target } if name == DNS_SD_META => Some(target),
+            _ => None,
+        })
+        .collect();
+    types.sort();
+    types.dedup();
+    // Names are at most 255 bytes, so a few dozen questions fit in one
+    // datagram comfortably.
+    for chunk in types.chunks(16) {
+        let names: Vec<&str> = chunk.iter().map(String::as_str).collect();
+        socket.send_to(&mdns_query(&names), MDNS_GROUP)?;
+    }
+    datagrams.extend(receive_until(&socket, deadline));
+    Ok(assemble_mdns(&datagrams))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum MdnsRecord {
+    Ptr {
+        name: String,
+        target: String,
+    },
+    Srv {
+        name: String,
+        port: u16,
+        target: String,
+    },
+    Address {
+        name: String,
+        address: IpAddr,
+    },
+}
+
+/// A query with one PTR question per name.
+fn mdns_query(names: &[&str]) -> Vec<u8> {
+    let mut message = vec![0u8; 12];
+    message[4..6].copy_from_slice(&(names.len() as u16).to_be_bytes());
+    for name in names {
+        for label in name.split('.') {
+            message.push(label.len() as u8);
+            message.extend_from_slice(label.as_bytes());
+        }
+        message.push(0);
+        // PTR, class IN.
+        message.extend_from_slice(&[0, 12, 0, 1]);
+    }
+    message
+}
+
+/// PTR, SRV, A and AAAA records from every section of a response.
+fn parse_mdns_records(message: &[u8]) -> Vec<MdnsRecord> {
+    let word = |offset: usize| -> Option<u16> {
+        Some(u16::from_be_bytes(
+            message.get(offset..offset + 2)?.try_into().ok()?,
+        ))
+    };
+    let mut records = Vec::new();
+    let (Some(flags), Some(questions)) = (word(2), word(4)) else {
+        return records;
+    };
+    // Only responses.
+    if flags & 0x8000 == 0 {
+        return records;
+    }
+    let count = [word(6), word(8), word(10)]
+        .into_iter()
+        .map(|count| usize::from(count.unwrap_or(0)))
+        .sum::<usize>();
+    let mut offset = 12;
+    for _ in 0..questions {
+        let Some((_, end)) = dns_name(message, offset) else {
+            return records;
+        };
+        offset = end + 4;
+    }
+    for _ in 0..count {
+        let Some((name, end)) = dns_name(message, offset) else {
+            break;
+        };
+        let (Some(kind), Some(length)) = (word(end), word(end + 8)) else {
+            break;
+        };
+        let start = end + 10;
+        let length = usize::from(length);
+        let Some(data) = message.get(start..start + length) else {
+            break;
+        };
+        let record = match (kind, length) {
+            (1, 4) => <[u8; 4]>::try_from(data)
+                .ok()
+                .map(|octets| MdnsRecord::Address {
+                    name,
+                    address: IpAddr::from(octets),
+                }),
+            (28, 16) => <[u8; 16]>::try_from(data)
+                .ok()
+                .map(|octets| MdnsRecord::Address {
+                    name,
+                    address: IpAddr::from(octets),
+                }),
+            (12, _) => dns_name(message, start).map(|(target, _)| MdnsRecord::Ptr { name, target }),
+            (33, _) => word(start + 4)
+                .zip(dns_name(message, start + 6))
+                .map(|(port, (target, _))| MdnsRecord::Srv { name, port, target }),
+            _ => None,
+        };
+        records.extend(record);
+        offset = start + length;
+    }
+    records
+}
+
+/// One service per SRV record, addressed by the host's A record when the
+/// responder sent one and by the responder itself otherwise.
+fn assemble_mdns(datagrams: &[(Vec<u8>, SocketAddr)]) -> Vec<DiscoveredService> {
+    let mut hosts: HashMap<String, IpAddr> = HashMap::new();
+    let mut instances = Vec::new();
+    for (message, from) in datagrams {
+        for record in parse_mdns_records(message) {
+            match record {
+                // Prefer IPv4 addresses, which is what the scan speaks.
+                MdnsRecord::Address { name, address } => {
+                    let entry = hosts.entry(name).or_insert(address);
+                    if address.is_ipv4() {
+                        *entry = address;
+                    }
+                }
+                MdnsRecord::Srv { name, port, target } => {
+                    instances.push((name, port, target, from.ip()))
+                }
+                MdnsRecord::Ptr { .. } => {}
+            }
+        }
+    }
+    instances
+        .into_iter()
+        .map(|(instance, port, target, from)| DiscoveredService {
+            id: format!("mdns:{instance}"),
+            name: instance
+                .strip_suffix(".local")
+                .unwrap_or(&instance)
+                .to_string(),
+            protocol: "mDNS".into(),
+            address: hosts.get(&target).copied().unwrap_or(from).to_string(),
+            port,
+        })
+        .collect()
+}
+
+fn scan_ssdp(window: Duration) -> Result<Vec<DiscoveredService>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_multicast_ttl_v4(2)?;
+    // Devices spread their answers over MX seconds.
+    let mx = window.as_secs().clamp(1, 5);
+    let search = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: {mx}\r\nST: ssdp:all\r\n\r\n"
+    );
+    socket.send_to(search.as_bytes(), SSDP_GROUP)?;
+    let datagrams = receive_until(&socket, Instant::now() + window);
+    Ok(datagrams
+        .iter()
+        .filter_map(|(message, from)| parse_ssdp_response(message, from.ip()))
+        .collect())
+}
+
+/// A device answers once per service type it has; all answers share the
+/// `uuid:` of the USN, which becomes the id.
+fn parse_ssdp_response(message: &[u8], from: IpAddr) -> Option<DiscoveredService> {
+    let text = std::str::from_utf8(message).ok()?;
+    let mut lines = text.lines();
+    if !lines.next()?.starts_with("HTTP/1.1 200") {
+        return None;
+    }
+    let headers: HashMap<String, &str> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_uppercase(), value.trim()))
+        .collect();
+    let usn = headers.get("USN")?;
+    let uuid = usn.split("::").next().unwrap_or(usn);
+    let location = headers.get("LOCATION").and_then(|url| url_host(url));
+    let name = headers
+        .get("SERVER")
+        .or_else(|| headers.get("ST"))
+        .copied()
+        .filter(|name| !name.is_empty())
+        .unwrap_or(uuid);
+    Some(DiscoveredService {
+        id: format!("ssdp:{uuid}"),
+        name: name.to_string(),
+        protocol: "SSDP".into(),
+        address: location.map_or(from, |(host, _)| host).to_string(),
+        port: location.map_or(SSDP_GROUP.port(), |(_, port)| port),
+    })
+}
+
+/// Host and port of an `http://host:port/...` URL with an IP literal.
+fn url_host(url: &str) -> Option<(IpAddr, u16)> {
+    let (scheme, rest) = url.split_once("://")?;
+    let authority = rest.split('/').next()?;
+    let default_port = if scheme.eq_ignore_ascii_case("https") {
+        443
+    } else {
+        80
+    };
+    match authority.parse::<SocketAddr>() {
+        Ok(address) => Some((address.ip(), address.port())),
+        Err(_) => Some((authority.parse().ok()?, default_port)),
+    }
+}
+
+/// Needs the DHCP server port, so root and no DHCP server on this host.
+fn listen_dhcp(window: Duration) -> Result<Vec<DiscoveredService>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 67))?;
+    socket.set_broadcast(true)?;
+    let datagrams = receive_until(&socket, Instant::now() + window);
+    Ok(datagrams
+        .iter()
+        .filter_map(|(message, _)| parse_dhcp_request(message))
+        .collect())
+}
+
+/// The client behind a DHCPDISCOVER or DHCPREQUEST: named by its host
+/// name (option 12) or vendor class (option 60), addressed by the lease
+/// it asks for (option 50) or already holds.
+fn parse_dhcp_request(message: &[u8]) -> Option<DiscoveredService> {
+    // BOOTREQUEST from an Ethernet client with the DHCP magic cookie.
+    if message.first() != Some(&1) || message.get(236..240)? != DHCP_MAGIC {
+        return None;
+    }
+    let hlen = usize::from(*message.get(2)?).min(16);
+    let mac = message
+        .get(28..28 + hlen)?
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<_>>()
+        .join(":");
+    let client = Ipv4Addr::from(<[u8; 4]>::try_from(message.get(12..16)?).ok()?);
+    let mut hostname = None;
+    let mut vendor = None;
+    let mut requested = None;
+    let mut offset = 240;
+    while let Some(&code) = message.get(offset) {
+        match code {
+            0 => {
+                offset += 1;
+                continue;
+            }
+            255 => break,
+            _ => {}
+        }
+        let length = usize::from(*message.get(offset + 1)?);
+        let data = message.get(offset + 2..offset + 2 + length)?;
+        match code {
+            12 => hostname = Some(String::from_utf8_lossy(data).into_owned()),
+            50 => requested = <[u8; 4]>::try_from(data).ok().map(Ipv4Addr::from),
+            60 => vendor = Some(String::from_utf8_lossy(data).into_owned()),
+            _ => {}
+        }
+        offset += 2 + length;
+    }
+    let address = requested.or((!client.is_unspecified()).then_some(client))?;
+    Some(DiscoveredService {
+        id: format!("dhcp:{mac}"),
+        name: hostname.or(vendor).unwrap_or_else(|| mac.clone()),
+        protocol: "DHCP".into(),
+        address: address.to_string(),
+        port: 68,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(message: &mut Vec<u8>, name: &str) {
+        for label in name.split('.') {
+            message.push(label.len() as u8);
+            message.extend_from_slice(label.as_bytes());
+        }
+        message.push(0);
+    }
+
+    fn record(message: &mut Vec<u8>, owner: &str, kind: u16, data: &[u8]) {
+        name(message, owner);
+        message.extend_from_slice(&kind.to_be_bytes());
+        message.extend_from_slice(&[0x80, 1, 0, 0, 0x11, 0x94]);
+        message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        message.extend_from_slice(data);
+    }
+
+    #[test]
+    fn parses_mdns_ssdp_and_dhcp_answers() {
+        let query = mdns_query(&[DNS_SD_META]);
+        assert_eq!(&query[4..6], &[0, 1]);
+        assert_eq!(query.len(), 12 + DNS_SD_META.len() + 2 + 4);
+
+        let mut response = vec![0, 0, 0x84, 0, 0, 0, 0, 2, 0, 0, 0, 1];
+        let mut ptr = Vec::new();
+        name(&mut ptr, "printer._ipp._tcp.local");
+        record(&mut response, "_ipp._tcp.local", 12, &ptr);
+        let mut srv = vec![0, 0, 0, 0, 0x02, 0x77];
+        name(&mut srv, "printer-host.local");
+        record(&mut response, "printer._ipp._tcp.local", 33, &srv);
+        record(&mut response, "printer-host.local", 1, &[192, 168, 1, 40]);
+        let from: SocketAddr = "192.168.1.99:5353".parse().unwrap();
+        assert_eq!(
+            assemble_mdns(&[(response, from)]),
+            [DiscoveredService {
+                id: "mdns:printer._ipp._tcp.local".into(),
+                name: "printer._ipp._tcp".into(),
+                protocol: "mDNS".into(),
+                address: "192.168.1.40".into(),
+                port: 631,
+            }]
+        );
+
+        let ssdp = b"HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=1800\r\nLocation: http://192.168.1.1:49152/rootDesc.xml\r\nSERVER: Linux UPnP/1.0 MiniUPnPd/2.2\r\nST: upnp:rootdevice\r\nUSN: uuid:1234-abcd::upnp:rootdevice\r\n\r\n";
+        let service = parse_ssdp_response(ssdp, "192.168.1.1".parse().unwrap()).unwrap();
+        assert_eq!(service.id, "ssdp:uuid:1234-abcd");
+        assert_eq!(service.name, "Linux UPnP/1.0 MiniUPnPd/2.2");
+        assert_eq!(
+            (service.address.as_str(), service.port),
+            ("192.168.1.1", 49152)
+        );
+
+        let mut dhcp = vec![0u8; 240];
+        dhcp[0] = 1;
+        dhcp[2] = 6;
+        dhcp[28..34].copy_from_slice(&[0xaa, 0xbb, 0xcc, 0, 0x11, 0x22]);
+        dhcp[236..240].copy_from_slice(&DHCP_MAGIC);
+        dhcp.extend_from_slice(&[53, 1, 3, 50, 4, 192, 168, 1, 77, 12, 6]);
+        dhcp.extend_from_slice(b"laptop");
+        dhcp.push(255);
+        let client = parse_dhcp_request(&dhcp).unwrap();
+        assert_eq!(client.id, "dhcp:aa:bb:cc:00:11:22");
+        assert_eq!(client.name, "laptop");
+        assert_eq!(client.address, "192.168.1.77");
+    }
+}
@@ -67,9 +67,13 @@ pub struct FlowEvent {
     pub sni: Option<String>,
     pub alpn: Option<String>,
     pub ja3: Option<String>,
+    pub ja4: Option<String>,
     pub dns_qname: Option<String>,
     pub dns_qtype: Option<String>,
     pub dns_rcode: Option<String>,
+    /// Addresses returned in the DNS answer, for passive DNS.
+    #[serde(default)]
+    pub dns_answers: Vec<String>,
 }
 
 impl Default for FlowEvent {
@@ -94,9 +98,11 @@ impl Default for FlowEvent {
             sni: None,
             alpn: None,
             ja3: None,
+            ja4: None,
             dns_qname: None,
             dns_qtype: None,
             dns_rcode: None,
+            dns_answers: Vec::new(),
         }
     }
 }
@@ -151,6 +157,14 @@ impl SharedHandlers {
     }
 }
 
+pub mod capabilities;
+pub mod capture;
+pub mod discovery;
+pub mod pcap;
+pub mod process;
+pub mod scenario;
+pub mod synthetic;
+
 #[cfg(target_os = "linux")]
 pub mod linux;
 
@@ -226,7 +240,7 @@ impl CollectorBackend for MockCollector {
                         let event = FlowEvent {
                             ts_first: now,
                             ts_last: now,
-                            proto: if counter % 2 == 0 { "TCP".into() } else { "UDP".into() },
+                            proto: if counter.is_multiple_of(2) { "TCP".into() } else { "UDP".into() },
                             src_ip: "127.0.0.1".into(),
                             src_port: port,
                             dst_ip: "127.0.0.1".into(),
@@ -0,0 +1,517 @@
+//! Flows from packet captures, for offline replay: classic libpcap files
+//! (micro- or nanosecond timestamps, either byte order) framed as
+//! Ethernet, Linux cooked (SLL/SLL2), BSD loopback or raw IP. Packets are
+//! folded into one [`FlowEvent`] per 5-tuple, oriented from the side that
+//! sent first; TCP flags give the state and DNS over UDP/53 fills the
+//! `dns_*` fields.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::Path,
+};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::{FlowDirection, FlowEvent};
+
+const PCAPNG_MAGIC: u32 = 0x0a0d_0d0a;
+
+/// Flows in `path`, ordered by first packet.
+pub fn read_pcap(path: &Path) -> Result<Vec<FlowEvent>> {
+    let data = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    parse_pcap(&data).with_context(|| format!("parsing {}", path.display()))
+}
+
+pub fn parse_pcap(data: &[u8]) -> Result<Vec<FlowEvent>> {
+    if data.len() < 24 {
+        return Err(anyhow!("truncated pcap header"));
+    }
+    let magic = u32::from_le_bytes(data[..4].try_into()?);
+    let (little_endian, nanos) = match magic {
+        0xa1b2_c3d4 => (true, false),
+        0xa1b2_3c4d => (true, true),
+        0xd4c3_b2a1 => (false, false),
+        0x4d3c_b2a1 => (false, true),
+        PCAPNG_MAGIC => {
+            return Err(anyhow!(
+                "pcapng is not supported; convert with `editcap -F pcap`"
+            ))
+        }
+        _ => return Err(anyhow!("not a pcap file")),
+    };
+    let read_u32 = |bytes: &[u8]| {
+        let bytes: [u8; 4] = bytes[..4].try_into().expect("four bytes");
+        if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    };
+    let link_type = read_u32(&data[20..]) & 0x0fff_ffff;
+    let mut table = FlowTable::default();
+    let mut offset = 24;
+    while offset + 16 <= data.len() {
+        let seconds = read_u32(&data[offset..]);
+        let fraction = read_u32(&data[offset + 4..]);
+        let captured = read_u32(&data[offset + 8..]) as usize;
+        let start = offset + 16;
+        let packet = data
+            .get(start..start + captured)
+            .ok_or_else(|| anyhow!("truncated packet at byte {offset}"))?;
+        offset = start + captured;
+        let nanos = if nanos { fraction } else { fraction * 1000 };
+        let Some(ts) = Utc.timestamp_opt(i64::from(seconds), nanos).single() else {
+            continue;
+        };
+        if let Some(ip) = network_layer(link_type, packet) {
+            if let Some(packet) = parse_ip(ip) {
+                table.observe(ts, packet);
+            }
+        }
+    }
+    let mut flows = table.flows;
+    flows.sort_by_key(|flow| flow.ts_first);
+    Ok(flows)
+}
+
+/// The IP packet inside a frame of `link_type`.
+fn network_layer(link_type: u32, frame: &[u8]) -> Option<&[u8]> {
+    match link_type {
+        // Ethernet, skipping 802.1Q/802.1ad tags.
+        1 => {
+            let mut offset = 12;
+            loop {
+                let ether_type =
+                    u16::from_be_bytes(frame.get(offset..offset + 2)?.try_into().ok()?);
+                match ether_type {
+                    0x8100 | 0x88a8 => offset += 4,
+                    0x0800 | 0x86dd => return frame.get(offset + 2..),
+                    _ => return None,
+                }
+            }
+        }
+        // BSD loopback: address family in host order.
+        0 => frame.get(4..),
+        // Raw IP.
+        12 | 14 | 101 | 228 | 229 => Some(frame),
+        // Linux cooked capture v1 and v2.
+        113 => frame.get(16..),
+        276 => frame.get(20..),
+        _ => None,
+    }
+}
+
+struct Packet<'a> {
+    proto: &'static str,
+    src: IpAddr,
+    dst: IpAddr,
+    src_port: u16,
+    dst_port: u16,
+    /// Bytes on the wire from the IP header on.
+    length: u64,
+    tcp_flags: u8,
+    payload: &'a [u8],
+}
+
+fn parse_ip(ip: &[u8]) -> Option<Packet<'_>> {
+    let (src, dst, protocol, length, transport) = match ip.first()? >> 4 {
+        4 => {
+            let header = usize::from(ip.first()? & 0x0f) * 4;
+            let total = usize::from(u16::from_be_bytes(ip.get(2..4)?.try_into().ok()?));
+            // Only the first fragment carries the transport header.
+            if u16::from_be_bytes(ip.get(6..8)?.try_into().ok()?) & 0x1fff != 0 {
+                return None;
+            }
+            let src: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+            (
+                IpAddr::from(Ipv4Addr::from(src)),
+                IpAddr::from(Ipv4Addr::from(dst)),
+                *ip.get(9)?,
+                total,
+                ip.get(header..total.min(ip.len()))?,
+            )
+        }
+        6 => {
+            let payload = usize::from(u16::from_be_bytes(ip.get(4..6)?.try_into().ok()?));
+            let src: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+            let mut next = *ip.get(6)?;
+            let mut offset = 40;
+            // Hop-by-hop, routing, fragment and destination options.
+            while matches!(next, 0 | 43 | 44 | 60) {
+                let extension = ip.get(offset..offset + 2)?;
+                next = extension[0];
+                offset += if next == 44 {
+                    8
+                } else {
+                    (usize::from(extension[1]) + 1) * 8
+                };
+            }
+            (
+                IpAddr::from(Ipv6Addr::from(src)),
+                IpAddr::from(Ipv6Addr::from(dst)),
+                next,
+                40 + payload,
+                ip.get(offset..(40 + payload).min(ip.len()))?,
+            )
+        }
+        _ => return None,
+    };
+    let ports = |segment: &[u8]| -> Option<(u16, u16)> {
+        Some((
+            u16::from_be_bytes(segment.get(0..2)?.try_into().ok()?),
+            u16::from_be_bytes(segment.get(2..4)?.try_into().ok()?),
+        ))
+    };
+    let (proto, src_port, dst_port, tcp_flags, payload) = match protocol {
+        6 => {
+            let (src_port, dst_port) = ports(transport)?;
+            let header = usize::from(transport.get(12)? >> 4) * 4;
+            let flags = *transport.get(13)?;
+            ("TCP", src_port, dst_port, flags, transport.get(header..)?)
+        }
+        17 => {
+            let (src_port, dst_port) = ports(transport)?;
+            ("UDP", src_port, dst_port, 0, transport.get(8..)?)
+        }
+        1 => ("ICMP", 0, 0, 0, &[][..]),
+        58 => ("ICMPv6", 0, 0, 0, &[][..]),
+        _ => return None,
+    };
+    Some(Packet {
+        proto,
+        src,
+        dst,
+        src_port,
+        dst_port,
+        length: length as u64,
+        tcp_flags,
+        payload,
+    })
+}
+
+type FlowKey = (&'static str, IpAddr, u16, IpAddr, u16);
+
+#[derive(Default)]
+struct FlowTable {
+    flows: Vec<FlowEvent>,
+    index: HashMap<FlowKey, usize>,
+}
+
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const RST: u8 = 0x04;
+const ACK: u8 = 0x10;
+
+impl FlowTable {
+    fn observe(&mut self, ts: DateTime<Utc>, packet: Packet<'_>) {
+        let forward = (
+            packet.proto,
+            packet.src,
+            packet.src_port,
+            packet.dst,
+            packet.dst_port,
+        );
+        let reverse = (
+            packet.proto,
+            packet.dst,
+            packet.dst_port,
+            packet.src,
+            packet.src_port,
+        );
+        let index = match self
+            .index
+            .get(&forward)
+            .or_else(|| self.index.get(&reverse))
+        {
+            Some(index) => *index,
+            None => {
+                self.flows.push(FlowEvent {
+                    ts_first: ts,
+                    ts_last: ts,
+                    proto: packet.proto.into(),
+                    src_ip: packet.src.to_string(),
+                    src_port: packet.src_port,
+                    dst_ip: packet.dst.to_string(),
+                    dst_port: packet.dst_port,
+                    direction: direction(&packet.src, &packet.dst),
+                    ..FlowEvent::default()
+                });
+                self.index.insert(forward, self.flows.len() - 1);
+                self.flows.len() - 1
+            }
+        };
+        let flow = &mut self.flows[index];
+        flow.ts_last = flow.ts_last.max(ts);
+        flow.bytes += packet.length;
+        flow.packets += 1;
+        if packet.proto == "TCP" {
+            if let Some(state) = tcp_state(flow.state.as_deref(), packet.tcp_flags) {
+                flow.state = Some(state.into());
+            }
+        }
+        if packet.proto == "UDP" && (packet.src_port == 53 || packet.dst_port == 53) {
+            if let Some(message) = parse_dns(packet.payload) {
+                flow.dns_qname = flow.dns_qname.take().or(message.qname);
+                flow.dns_qtype = flow.dns_qtype.take().or(message.qtype);
+                if message.response {
+                    flow.dns_rcode = Some(message.rcode);
+                    flow.dns_answers.extend(message.answers);
+                }
+            }
+        }
+    }
+}
+
+/// The state a segment with `flags` moves the flow to, if it changes;
+/// resets and closes stick.
+fn tcp_state(current: Option<&str>, flags: u8) -> Option<&'static str> {
+    Some(match current {
+        Some("RST") => return None,
+        _ if flags & RST != 0 => "RST",
+        Some("CLOSED") => return None,
+        Some("CLOSING") if flags & FIN != 0 => "CLOSED",
+        Some("CLOSING") => return None,
+        _ if flags & FIN != 0 => "CLOSING",
+        _ if flags & SYN != 0 && flags & ACK != 0 => "SYN_RECV",
+        _ if flags & SYN != 0 => "SYN_SENT",
+        _ if flags & ACK != 0 => "ESTABLISHED",
+        _ => return None,
+    })
+}
+
+/// Lateral between private addresses, outbound from one, inbound otherwise.
+fn direction(src: &IpAddr, dst: &IpAddr) -> FlowDirection {
+    match (is_private(src), is_private(dst)) {
+        (true, true) => FlowDirection::Lateral,
+        (true, false) => FlowDirection::Outbound,
+        _ => FlowDirection::Inbound,
+    }
+}
+
+fn is_private(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => v4.is_private() || v4.is_loopback() || v4.is_link_local(),
+        IpAddr::V6(v6) => {
+            v6.is_loopback()
+                || (v6.segments()[0] & 0xfe00) == 0xfc00
+                || (v6.segments()[0] & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+struct DnsMessage {
+    response: bool,
+    rcode: String,
+    qname: Option<String>,
+    qtype: Option<String>,
+    answers: Vec<String>,
+}
+
+fn parse_dns(message: &[u8]) -> Option<DnsMessage> {
+    let word = |offset: usize| -> Option<u16> {
+        Some(u16::from_be_bytes(
+            message.get(offset..offset + 2)?.try_into().ok()?,
+        ))
+    };
+    let flags = word(2)?;
+    let questions = word(4)?;
+    let answers = word(6)?;
+    let mut offset = 12;
+    let mut qname = None;
+    let mut qtype = None;
+    for _ in 0..questions {
+        let (name, end) = dns_name(message, offset)?;
+        qname.get_or_insert(name);
+        qtype.get_or_insert(dns_type(word(end)?));
+        offset = end + 4;
+    }
+    let mut addresses = Vec::new();
+    for _ in 0..answers {
+        let (_, end) = dns_name(message, offset)?;
+        let kind = word(end)?;
+        let length = usize::from(word(end + 8)?);
+        let data = message.get(end + 10..end + 10 + length)?;
+        match (kind, length) {
+            (1, 4) => addresses.push(Ipv4Addr::from(<[u8; 4]>::try_from(data).ok()?).to_string()),
+            (28, 16) => {
+                addresses.push(Ipv6Addr::from(<[u8; 16]>::try_from(data).ok()?).to_string())
+            }
+            _ => {}
+        }
+        offset = end + 10 + length;
+    }
+    let rcode = match flags & 0x000f {
+        0 => "NOERROR".to_string(),
+        1 => "FORMERR".to_string(),
+        2 => "SERVFAIL".to_string(),
+        3 => "NXDOMAIN".to_string(),
+        4 => "NOTIMP".to_string(),
+        5 => "REFUSED".to_string(),
+        other => format!("RCODE{other}"),
+    };
+    Some(DnsMessage {
+        response: flags & 0x8000 != 0,
+        rcode,
+        qname,
+        qtype,
+        answers: addresses,
+    })
+}
+
+/// The name at `offset` and the offset right after it, following
+/// compression pointers.
+pub(crate) fn dns_name(message: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Bounds pointer loops in malformed messages.
+    for _ in 0..128 {
+        let length = *message.get(offset)?;
+        match length {
+            0 => {
+                let name = labels.join(".");
+                return Some((name, end.unwrap_or(offset + 1)));
+            }
+            _ if length & 0xc0 == 0xc0 => {
+                let pointer = usize::from(
+                    u16::from_be_bytes(message.get(offset..offset + 2)?.try_into().ok()?) & 0x3fff,
+                );
+                end.get_or_insert(offset + 2);
+                offset = pointer;
+            }
+            _ => {
+                let label = message.get(offset + 1..offset + 1 + usize::from(length))?;
+                labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+                offset += 1 + usize::from(length);
+            }
+        }
+    }
+    None
+}
+
+fn dns_type(kind: u16) -> String {
+    match kind {
+        1 => "A".into(),
+        2 => "NS".into(),
+        5 => "CNAME".into(),
+        12 => "PTR".into(),
+        15 => "MX".into(),
+        16 => "TXT".into(),
+        28 => "AAAA".into(),
+        33 => "SRV".into(),
+        65 => "HTTPS".into(),
+        255 => "ANY".into(),
+        other => format!("TYPE{other}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture(packets: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        // Magic, version 2.4, zone, accuracy, snaplen, Ethernet.
+        for value in [0xa1b2_c3d4u32, 0x0004_0002, 0, 0, 65535, 1] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        for (seconds, frame) in packets {
+            for value in [*seconds, 0, frame.len() as u32, frame.len() as u32] {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            data.extend_from_slice(frame);
+        }
+        data
+    }
+
+    fn frame(src: [u8; 4], dst: [u8; 4], protocol: u8, transport: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0; 12];
+        frame.extend_from_slice(&[0x08, 0x00]);
+        let total = (20 + transport.len()) as u16;
+        frame.extend_from_slice(&[0x45, 0]);
+        frame.extend_from_slice(&total.to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0x40, 0, 64, protocol, 0, 0]);
+        frame.extend_from_slice(&src);
+        frame.extend_from_slice(&dst);
+        frame.extend_from_slice(transport);
+        frame
+    }
+
+    fn tcp(src_port: u16, dst_port: u16, flags: u8) -> Vec<u8> {
+        let mut segment = Vec::new();
+        segment.extend_from_slice(&src_port.to_be_bytes());
+        segment.extend_from_slice(&dst_port.to_be_bytes());
+        segment.extend_from_slice(&[0; 8]);
+        segment.extend_from_slice(&[0x50, flags, 0, 0, 0, 0, 0, 0]);
+        segment
+    }
+
+    fn dns(src_port: u16, dst_port: u16, flags: u16, answer: Option<[u8; 4]>) -> Vec<u8> {
+        let mut message = vec![0x12, 0x34];
+        message.extend_from_slice(&flags.to_be_bytes());
+        message.extend_from_slice(&[0, 1, 0, u8::from(answer.is_some()), 0, 0, 0, 0]);
+        message.extend_from_slice(b"\x04evil\x07example\x00\x00\x01\x00\x01");
+        if let Some(address) = answer {
+            message.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+            message.extend_from_slice(&address);
+        }
+        let mut datagram = Vec::new();
+        datagram.extend_from_slice(&src_port.to_be_bytes());
+        datagram.extend_from_slice(&dst_port.to_be_bytes());
+        datagram.extend_from_slice(&((8 + message.len()) as u16).to_be_bytes());
+        datagram.extend_from_slice(&[0, 0]);
+        datagram.extend_from_slice(&message);
+        datagram
+    }
+
+    #[test]
+    fn folds_packets_into_flows() {
+        let (client, server, resolver) = ([10, 0, 0, 5], [10, 0, 0, 8], [1, 1, 1, 1]);
+        let data = capture(&[
+            (100, frame(client, server, 6, &tcp(51000, 445, SYN))),
+            (100, frame(server, client, 6, &tcp(445, 51000, SYN | ACK))),
+            (101, frame(client, server, 6, &tcp(51000, 445, ACK))),
+            (
+                102,
+                frame(client, resolver, 17, &dns(53000, 53, 0x0100, None)),
+            ),
+            (
+                102,
+                frame(
+                    resolver,
+                    client,
+                    17,
+                    &dns(53, 53000, 0x8183, Some([203, 0, 113, 9])),
+                ),
+            ),
+        ]);
+        let flows = parse_pcap(&data).unwrap();
+        assert_eq!(flows.len(), 2);
+
+        let smb = &flows[0];
+        assert_eq!(
+            (smb.src_ip.as_str(), smb.src_port, smb.dst_port),
+            ("10.0.0.5", 51000, 445)
+        );
+        assert_eq!(smb.packets, 3);
+        assert_eq!(smb.bytes, 3 * 40);
+        assert_eq!(smb.state.as_deref(), Some("ESTABLISHED"));
+        assert_eq!(smb.direction, FlowDirection::Lateral);
+        assert_eq!((smb.ts_last - smb.ts_first).num_seconds(), 1);
+
+        let lookup = &flows[1];
+        assert_eq!(lookup.direction, FlowDirection::Outbound);
+        assert_eq!(lookup.dns_qname.as_deref(), Some("evil.example"));
+        assert_eq!(lookup.dns_qtype.as_deref(), Some("A"));
+        assert_eq!(lookup.dns_rcode.as_deref(), Some("NXDOMAIN"));
+        assert_eq!(lookup.dns_answers, vec!["203.0.113.9".to_string()]);
+
+        assert!(parse_pcap(&PCAPNG_MAGIC.to_le_bytes().repeat(8))
+            .unwrap_err()
+            .to_string()
+            .contains("pcapng"));
+    }
+}
@@ -0,0 +1,360 @@
+//! Which processes on the host have sockets open, and what they listen on.
+//! On Linux the inventory is read from `/proc`; processes of other users
+//! are only visible to root.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::ProcessIdentity;
+
+/// TCP_LISTEN in `/proc/net/tcp`.
+const TCP_LISTEN: u8 = 0x0A;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ListeningSocket {
+    pub proto: String,
+    pub address: String,
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessInfo {
+    #[serde(flatten)]
+    pub identity: ProcessIdentity,
+    /// Listening TCP sockets and bound, unconnected UDP sockets.
+    pub listening: Vec<ListeningSocket>,
+    /// All other sockets, mostly established connections.
+    pub connections: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProcessSnapshot {
+    pub processes: Vec<ProcessInfo>,
+    /// Listening sockets whose owner could not be inspected, usually for
+    /// lack of privileges.
+    pub unattributed: Vec<ListeningSocket>,
+}
+
+/// Takes [`ProcessSnapshot`]s of the host.
+#[derive(Debug, Clone)]
+pub struct ProcessInfoCollector {
+    hashes: bool,
+}
+
+impl Default for ProcessInfoCollector {
+    fn default() -> Self {
+        Self { hashes: true }
+    }
+}
+
+impl ProcessInfoCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether to hash executables for `sha256_16`; each distinct file is
+    /// read once per snapshot.
+    pub fn with_hashes(mut self, hashes: bool) -> Self {
+        self.hashes = hashes;
+        self
+    }
+
+    /// Processes owning at least one socket, by pid.
+    pub fn snapshot(&self) -> Result<ProcessSnapshot> {
+        #[cfg(target_os = "linux")]
+        {
+            linux::snapshot(self.hashes)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            Err(anyhow::anyhow!(
+                "process inventory is not available on this platform yet"
+            ))
+        }
+    }
+}
+
+/// A row of `/proc/net/{tcp,udp}{,6}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SocketEntry {
+    proto: &'static str,
+    local: (IpAddr, u16),
+    remote: (IpAddr, u16),
+    state: u8,
+    inode: u64,
+}
+
+impl SocketEntry {
+    fn is_listening(&self) -> bool {
+        match self.proto {
+            "TCP" => self.state == TCP_LISTEN,
+            _ => self.remote.1 == 0 && self.remote.0.is_unspecified(),
+        }
+    }
+
+    fn listener(&self) -> ListeningSocket {
+        ListeningSocket {
+            proto: self.proto.to_string(),
+            address: self.local.0.to_string(),
+            port: self.local.1,
+        }
+    }
+}
+
+fn parse_proc_net(proto: &'static str, text: &str) -> Vec<SocketEntry> {
+    text.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            Some(SocketEntry {
+                proto,
+                local: parse_hex_address(fields.get(1)?)?,
+                remote: parse_hex_address(fields.get(2)?)?,
+                state: u8::from_str_radix(fields.get(3)?, 16).ok()?,
+                inode: fields.get(9)?.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// `0100007F:0016` is 127.0.0.1:22: the address is in host byte order,
+/// 32 bits at a time (four words for IPv6), the port is big-endian.
+fn parse_hex_address(field: &str) -> Option<(IpAddr, u16)> {
+    let (address, port) = field.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let words = (0..address.len() / 8)
+        .map(|word| {
+            let hex = address.get(word * 8..word * 8 + 8)?;
+            u32::from_str_radix(hex, 16).ok().map(u32::swap_bytes)
+        })
+        .collect::<Option<Vec<u32>>>()?;
+    let address = match words.as_slice() {
+        [word] => IpAddr::V4(Ipv4Addr::from(*word)),
+        [a, b, c, d] => {
+            let v6 = Ipv6Addr::from(
+                (u128::from(*a) << 96)
+                    | (u128::from(*b) << 64)
+                    | (u128::from(*c) << 32)
+                    | u128::from(*d),
+            );
+            // Dual-stack sockets bound to an IPv4 address.
+            v6.to_ipv4_mapped().map_or(IpAddr::V6(v6), IpAddr::V4)
+        }
+        _ => return None,
+    };
+    Some((address, port))
+}
+
+/// uid to user name, from `/etc/passwd`.
+fn parse_passwd(text: &str) -> HashMap<u32, String> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let uid = fields.nth(1)?.parse().ok()?;
+            Some((uid, name.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{
+        collections::{HashMap, HashSet},
+        fs,
+        io::Read,
+        path::Path,
+    };
+
+    use anyhow::{Context, Result};
+    use ring::digest;
+
+    use super::{parse_passwd, parse_proc_net, ProcessInfo, ProcessSnapshot, SocketEntry};
+    use crate::ProcessIdentity;
+
+    pub fn snapshot(hashes: bool) -> Result<ProcessSnapshot> {
+        let mut sockets: HashMap<u64, SocketEntry> = HashMap::new();
+        for (proto, file) in [
+            ("TCP", "tcp"),
+            ("TCP", "tcp6"),
+            ("UDP", "udp"),
+            ("UDP", "udp6"),
+        ] {
+            // Missing without IPv6 support.
+            if let Ok(text) = fs::read_to_string(format!("/proc/net/{file}")) {
+                for entry in parse_proc_net(proto, &text) {
+                    if entry.inode != 0 {
+                        sockets.insert(entry.inode, entry);
+                    }
+                }
+            }
+        }
+        let users = fs::read_to_string("/etc/passwd")
+            .map(|text| parse_passwd(&text))
+            .unwrap_or_default();
+        let mut digests = HashMap::new();
+        let mut owned = HashSet::new();
+        let mut processes = Vec::new();
+        for entry in fs::read_dir("/proc").context("reading /proc")?.flatten() {
+            let Some(pid) = entry
+                .file_name()
+                .to_str()
+                .and_then(|n| n.parse::<i32>().ok())
+            else {
+                continue;
+            };
+            // Other users' descriptors need root.
+            let Ok(fds) = fs::read_dir(format!("/proc/{pid}/fd")) else {
+                continue;
+            };
+            let mut listening = Vec::new();
+            let mut connections = 0;
+            for fd in fds.flatten() {
+                let Some(inode) = fs::read_link(fd.path())
+                    .ok()
+                    .and_then(|target| socket_inode(&target))
+                else {
+                    continue;
+                };
+                let Some(socket) = sockets.get(&inode) else {
+                    continue;
+                };
+                owned.insert(inode);
+                if socket.is_listening() {
+                    listening.push(socket.listener());
+                } else {
+                    connections += 1;
+                }
+            }
+            if listening.is_empty() && connections == 0 {
+                continue;
+            }
+            listening.sort();
+            listening.dedup();
+            let identity = identity(pid, &users, hashes.then_some(&mut digests));
+            processes.push(ProcessInfo {
+                identity,
+                listening,
+                connections,
+            });
+        }
+        processes.sort_by_key(|process| process.identity.pid);
+        let mut unattributed: Vec<_> = sockets
+            .values()
+            .filter(|socket| socket.is_listening() && !owned.contains(&socket.inode))
+            .map(SocketEntry::listener)
+            .collect();
+        unattributed.sort();
+        unattributed.dedup();
+        Ok(ProcessSnapshot {
+            processes,
+            unattributed,
+        })
+    }
+
+    /// `socket:[12345]` to 12345.
+    fn socket_inode(target: &Path) -> Option<u64> {
+        target
+            .to_str()?
+            .strip_prefix("socket:[")?
+            .strip_suffix(']')?
+            .parse()
+            .ok()
+    }
+
+    fn identity(
+        pid: i32,
+        users: &HashMap<u32, String>,
+        digests: Option<&mut HashMap<String, Option<String>>>,
+    ) -> ProcessIdentity {
+        let status = fs::read_to_string(format!("/proc/{pid}/status")).unwrap_or_default();
+        let field = |name: &str| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .map(str::trim)
+        };
+        let exe_path = fs::read_link(format!("/proc/{pid}/exe"))
+            .ok()
+            .map(|exe| exe.display().to_string());
+        let sha256_16 = match (digests, &exe_path) {
+            (Some(digests), Some(exe)) => digests
+                .entry(exe.clone())
+                .or_insert_with(|| sha256_16(Path::new(exe)))
+                .clone(),
+            _ => None,
+        };
+        ProcessIdentity {
+            pid,
+            ppid: field("PPid:").and_then(|ppid| ppid.parse().ok()),
+            name: field("Name:").map(str::to_string),
+            exe_path,
+            sha256_16,
+            user: field("Uid:")
+                .and_then(|uids| uids.split_whitespace().next())
+                .and_then(|uid| uid.parse::<u32>().ok())
+                .map(|uid| users.get(&uid).cloned().unwrap_or_else(|| uid.to_string())),
+            // Linux has no code signing to check.
+            signed: None,
+        }
+    }
+
+    /// First 8 bytes of the file's SHA-256 in hex; `None` when it cannot be
+    /// read, e.g. after the file was replaced.
+    fn sha256_16(path: &Path) -> Option<String> {
+        let mut file = fs::File::open(path).ok()?;
+        let mut context = digest::Context::new(&digest::SHA256);
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let read = file.read(&mut buffer).ok()?;
+            if read == 0 {
+                break;
+            }
+            context.update(&buffer[..read]);
+        }
+        Some(hex::encode(&context.finish().as_ref()[..8]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_proc_net_sockets() {
+        let tcp = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n\
+            0: 0100007F:0016 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 1001 1 0\n\
+            1: 0500000A:C350 0800000A:01BB 01 00000000:00000000 00:00000000 00000000  1000        0 1002 1 0\n";
+        let entries = parse_proc_net("TCP", tcp);
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].is_listening());
+        assert_eq!(
+            entries[0].listener(),
+            ListeningSocket {
+                proto: "TCP".into(),
+                address: "127.0.0.1".into(),
+                port: 22
+            }
+        );
+        assert_eq!(entries[1].remote, ("10.0.0.8".parse().unwrap(), 443));
+        assert!(!entries[1].is_listening());
+
+        let udp6 = "  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops\n\
+            0: 00000000000000000000000000000000:14E9 00000000000000000000000000000000:0000 07 00000000:00000000 00:00000000 00000000   107        0 2001 2 0 0\n\
+            1: 0000000000000000FFFF00000100007F:0035 00000000000000000000000000000000:0000 07 00000000:00000000 00:00000000 00000000     0        0 2002 2 0 0\n";
+        let entries = parse_proc_net("UDP", udp6);
+        assert!(entries.iter().all(SocketEntry::is_listening));
+        assert_eq!(entries[0].local, ("::".parse().unwrap(), 5353));
+        assert_eq!(entries[1].local, ("127.0.0.1".parse().unwrap(), 53));
+
+        let users = parse_passwd(
+            "root:x:0:0:root:/root:/bin/bash\nnobody:x:65534:65534::/:/sbin/nologin\n",
+        );
+        assert_eq!(users.get(&65534).map(String::as_str), Some("nobody"));
+    }
+}
@@ -0,0 +1,207 @@
+//! Scripted attack traffic for `nets-cli simulate`: short flow sequences
+//! that look like a port scan, C2 beaconing, a bulk upload or DNS
+//! tunnelling from one workstation, for checking that rules and
+//! notifications fire. All flows belong to a process named
+//! [`SIMULATED_PROCESS`] so they are easy to find afterwards, and remote
+//! ends are in documentation ranges.
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{FlowDirection, FlowEvent, ProcessIdentity};
+
+pub const SIMULATED_PROCESS: &str = "nets-simulate";
+const WORKSTATION: &str = "192.168.1.10";
+const RESOLVER: &str = "192.168.1.1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scenario {
+    /// 100 ports on one LAN host, then SMB on 30 hosts, within seconds.
+    Scan,
+    /// An hour of HTTPS check-ins every minute, same size each time.
+    Beacon,
+    /// 500 MiB uploaded to one address in ten minutes.
+    Exfil,
+    /// 300 TXT queries for long random names under one domain.
+    DnsTunnel,
+}
+
+impl Scenario {
+    pub const ALL: [Scenario; 4] = [
+        Scenario::Scan,
+        Scenario::Beacon,
+        Scenario::Exfil,
+        Scenario::DnsTunnel,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scenario::Scan => "scan",
+            Scenario::Beacon => "beacon",
+            Scenario::Exfil => "exfil",
+            Scenario::DnsTunnel => "dns-tunnel",
+        }
+    }
+
+    /// The flows of the scenario, oldest first, ending at `now`.
+    pub fn flows(self, now: DateTime<Utc>) -> Vec<FlowEvent> {
+        match self {
+            Scenario::Scan => scan(now),
+            Scenario::Beacon => beacon(now),
+            Scenario::Exfil => exfil(now),
+            Scenario::DnsTunnel => dns_tunnel(now),
+        }
+    }
+}
+
+impl fmt::Display for Scenario {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scenario {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Scenario::ALL
+            .into_iter()
+            .find(|scenario| scenario.as_str() == value)
+            .ok_or_else(|| anyhow!("unknown scenario: {value} (scan, beacon, exfil, dns-tunnel)"))
+    }
+}
+
+fn flow(at: DateTime<Utc>, proto: &str, (dst_ip, dst_port): (&str, u16)) -> FlowEvent {
+    FlowEvent {
+        ts_first: at,
+        ts_last: at,
+        proto: proto.into(),
+        src_ip: WORKSTATION.into(),
+        src_port: 40_000 + (at.timestamp_subsec_millis() as u16 % 20_000),
+        dst_ip: dst_ip.into(),
+        dst_port,
+        direction: FlowDirection::Outbound,
+        packets: 2,
+        bytes: 120,
+        process: Some(ProcessIdentity {
+            pid: std::process::id() as i32,
+            ppid: None,
+            name: Some(SIMULATED_PROCESS.into()),
+            exe_path: None,
+            sha256_16: None,
+            user: None,
+            signed: None,
+        }),
+        ..FlowEvent::default()
+    }
+}
+
+fn scan(now: DateTime<Utc>) -> Vec<FlowEvent> {
+    let start = now - Duration::seconds(10);
+    let probe = |index: i64, dst: &str, port: u16| FlowEvent {
+        direction: FlowDirection::Lateral,
+        state: Some("SYN_SENT".into()),
+        ..flow(
+            start + Duration::milliseconds(index * 50),
+            "TCP",
+            (dst, port),
+        )
+    };
+    let vertical = (1..=100u16).map(|port| probe(port as i64, "192.168.1.20", port));
+    let horizontal = (30..60u16).map(|host| {
+        let dst = format!("192.168.1.{host}");
+        probe(100 + host as i64, &dst, 445)
+    });
+    vertical.chain(horizontal).collect()
+}
+
+fn beacon(now: DateTime<Utc>) -> Vec<FlowEvent> {
+    (0..60i64)
+        .map(|minute| {
+            // A couple of seconds of jitter, as implants add.
+            let at = now - Duration::minutes(60 - minute) + Duration::seconds(minute % 3);
+            FlowEvent {
+                state: Some("ESTABLISHED".into()),
+                bytes: 1_840,
+                packets: 8,
+                sni: Some("cdn-telemetry.example.net".into()),
+                ..flow(at, "TCP", ("203.0.113.50", 443))
+            }
+        })
+        .collect()
+}
+
+fn exfil(now: DateTime<Utc>) -> Vec<FlowEvent> {
+    (0..10i64)
+        .map(|minute| FlowEvent {
+            state: Some("ESTABLISHED".into()),
+            bytes: 50 * 1024 * 1024,
+            packets: 36_000,
+            sni: Some("files.example.org".into()),
+            ..flow(
+                now - Duration::minutes(10 - minute),
+                "TCP",
+                ("198.51.100.23", 443),
+            )
+        })
+        .collect()
+}
+
+fn dns_tunnel(now: DateTime<Utc>) -> Vec<FlowEvent> {
+    let mut state = 0x5eed_u64;
+    (0..300i64)
+        .map(|index| {
+            // 48 hex digits, as encoded chunks of a file would look.
+            let label: String = (0..3)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    format!("{state:016x}")
+                })
+                .collect();
+            FlowEvent {
+                dns_qname: Some(format!("{label}.t.exfil-tunnel.example")),
+                dns_qtype: Some("TXT".into()),
+                dns_rcode: Some(
+                    if index % 10 == 0 {
+                        "NXDOMAIN"
+                    } else {
+                        "NOERROR"
+                    }
+                    .into(),
+                ),
+                bytes: 310,
+                ..flow(
+                    now - Duration::seconds(120) + Duration::milliseconds(index * 400),
+                    "UDP",
+                    (RESOLVER, 53),
+                )
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scenarios_end_now_in_order() {
+        let now = Utc::now();
+        for scenario in Scenario::ALL {
+            let flows = scenario.flows(now);
+            assert!(!flows.is_empty(), "{scenario}");
+            assert!(flows
+                .windows(2)
+                .all(|pair| pair[0].ts_first <= pair[1].ts_first));
+            assert!(flows.iter().all(|flow| flow.ts_first <= now));
+            assert_eq!(scenario.as_str().parse::<Scenario>().unwrap(), scenario);
+        }
+        let tunnel = Scenario::DnsTunnel.flows(now);
+        assert_ne!(tunnel[0].dns_qname, tunnel[1].dns_qname);
+        assert!("ddos".parse::<Scenario>().is_err());
+    }
+}
@@ -0,0 +1,212 @@
+//! Synthetic traffic for benchmarks: a small office network browsing the
+//! web, resolving names, talking to its own servers and taking the odd
+//! SSH probe and port scan. Deterministic for a given seed; the mix only
+//! roughly resembles a real capture, it is not a replay of one.
+
+use chrono::{DateTime, Utc};
+
+use crate::{FlowDirection, FlowEvent, ProcessIdentity};
+
+const RESOLVER: &str = "192.168.1.1";
+/// Hosts are 192.168.1.10 up to this, exclusive.
+const HOSTS_END: u64 = 60;
+const SITES: &[(&str, &str)] = &[
+    ("142.250.74.46", "www.google.com"),
+    ("140.82.121.4", "github.com"),
+    ("151.101.1.140", "www.reddit.com"),
+    ("104.16.132.229", "cdn.cloudflare.com"),
+    ("13.107.42.14", "outlook.office365.com"),
+    ("52.84.150.39", "d1.awsstatic.com"),
+    ("31.13.72.36", "www.facebook.com"),
+    ("17.253.144.10", "www.apple.com"),
+    ("87.250.250.242", "ya.ru"),
+    ("93.184.216.34", "example.com"),
+];
+const BROWSERS: &[(i32, &str, &str)] = &[
+    (2311, "firefox", "/usr/lib/firefox/firefox"),
+    (2417, "chrome", "/opt/google/chrome/chrome"),
+    (3120, "curl", "/usr/bin/curl"),
+    (3388, "python3", "/usr/bin/python3.12"),
+];
+const LATERAL_PORTS: &[u16] = &[22, 80, 445, 3389, 5432];
+const PROBES: &[&str] = &["45.155.205.233", "185.220.101.7", "61.177.172.140"];
+
+pub struct SyntheticFlows {
+    state: u64,
+    /// Next port of the running scan.
+    scan_port: u16,
+}
+
+impl SyntheticFlows {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: seed,
+            scan_port: 1,
+        }
+    }
+
+    /// One flow observed at `now`.
+    pub fn next_at(&mut self, now: DateTime<Utc>) -> FlowEvent {
+        let host = format!("192.168.1.{}", 10 + self.below(HOSTS_END - 10));
+        let flow = FlowEvent {
+            ts_first: now,
+            ts_last: now,
+            iface: Some("eth0".into()),
+            ..FlowEvent::default()
+        };
+        match self.below(100) {
+            0..=49 => {
+                let (dst_ip, sni) = SITES[self.below(SITES.len() as u64) as usize];
+                let (pid, name, exe) = BROWSERS[self.below(BROWSERS.len() as u64) as usize];
+                let bytes = 500 + self.below(200_000);
+                FlowEvent {
+                    proto: "TCP".into(),
+                    src_ip: host,
+                    src_port: self.ephemeral_port(),
+                    dst_ip: dst_ip.into(),
+                    dst_port: 443,
+                    direction: FlowDirection::Outbound,
+                    state: Some("ESTABLISHED".into()),
+                    bytes,
+                    packets: bytes / 1200 + 2,
+                    process: Some(ProcessIdentity {
+                        pid,
+                        ppid: Some(1),
+                        name: Some(name.into()),
+                        exe_path: Some(exe.into()),
+                        sha256_16: None,
+                        user: Some("user".into()),
+                        signed: None,
+                    }),
+                    sni: Some(sni.into()),
+                    alpn: Some("h2".into()),
+                    ..flow
+                }
+            }
+            50..=74 => {
+                // A few lookups of random names, the way malware with a
+                // domain generation algorithm looks.
+                let (qname, rcode, answers) = if self.below(30) == 0 {
+                    let label: String = (0..12)
+                        .map(|_| char::from(b'a' + self.below(26) as u8))
+                        .collect();
+                    (format!("{label}.info"), "NXDOMAIN", Vec::new())
+                } else {
+                    let (ip, name) = SITES[self.below(SITES.len() as u64) as usize];
+                    (name.to_string(), "NOERROR", vec![ip.to_string()])
+                };
+                FlowEvent {
+                    proto: "UDP".into(),
+                    src_ip: host,
+                    src_port: self.ephemeral_port(),
+                    dst_ip: RESOLVER.into(),
+                    dst_port: 53,
+                    direction: FlowDirection::Outbound,
+                    bytes: 60 + qname.len() as u64 * 2,
+                    packets: 2,
+                    dns_qname: Some(qname),
+                    dns_qtype: Some("A".into()),
+                    dns_rcode: Some(rcode.into()),
+                    dns_answers: answers,
+                    ..flow
+                }
+            }
+            75..=89 => {
+                let bytes = 200 + self.below(50_000);
+                FlowEvent {
+                    proto: "TCP".into(),
+                    src_ip: host,
+                    src_port: self.ephemeral_port(),
+                    dst_ip: format!("192.168.1.{}", 2 + self.below(6)),
+                    dst_port: LATERAL_PORTS[self.below(LATERAL_PORTS.len() as u64) as usize],
+                    direction: FlowDirection::Lateral,
+                    state: Some("ESTABLISHED".into()),
+                    bytes,
+                    packets: bytes / 1200 + 2,
+                    ..flow
+                }
+            }
+            90..=96 => FlowEvent {
+                proto: "TCP".into(),
+                src_ip: PROBES[self.below(PROBES.len() as u64) as usize].into(),
+                src_port: self.ephemeral_port(),
+                dst_ip: host,
+                dst_port: 22,
+                direction: FlowDirection::Inbound,
+                state: Some("SYN_RECV".into()),
+                bytes: 60,
+                packets: 1,
+                ..flow
+            },
+            _ => {
+                let dst_port = self.scan_port;
+                self.scan_port = self.scan_port % 1024 + 1;
+                FlowEvent {
+                    proto: "TCP".into(),
+                    src_ip: "192.168.1.66".into(),
+                    src_port: 40_000,
+                    dst_ip: host,
+                    dst_port,
+                    direction: FlowDirection::Lateral,
+                    state: Some("SYN_SENT".into()),
+                    bytes: 60,
+                    packets: 1,
+                    ..flow
+                }
+            }
+        }
+    }
+
+    fn ephemeral_port(&mut self) -> u16 {
+        32_768 + self.below(28_000) as u16
+    }
+
+    /// Uniform enough below `bound`, from splitmix64.
+    fn below(&mut self, bound: u64) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        (z ^ (z >> 31)) % bound
+    }
+}
+
+impl Iterator for SyntheticFlows {
+    type Item = FlowEvent;
+
+    fn next(&mut self) -> Option<FlowEvent> {
+        Some(self.next_at(Utc::now()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_traffic_with_every_direction() {
+        let now = Utc::now();
+        let flows: Vec<_> = {
+            let mut generator = SyntheticFlows::new(7);
+            (0..500).map(|_| generator.next_at(now)).collect()
+        };
+        let mut again = SyntheticFlows::new(7);
+        for flow in &flows {
+            let other = again.next_at(now);
+            assert_eq!(
+                (&flow.src_ip, flow.dst_port, flow.bytes),
+                (&other.src_ip, other.dst_port, other.bytes)
+            );
+        }
+        for direction in [
+            FlowDirection::Inbound,
+            FlowDirection::Outbound,
+            FlowDirection::Lateral,
+        ] {
+            assert!(flows.iter().any(|flow| flow.direction == direction));
+        }
+        assert!(flows
+            .iter()
+            .any(|flow| flow.dns_rcode.as_deref() == Some("NXDOMAIN")));
+    }
+}
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use chrono::{DateTime, Duration, Utc};
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use collector::{FlowDirection, FlowEvent};
 use serde::{Deserialize, Serialize};
 use tracing::debug;
@@ -17,6 +17,51 @@ pub struct NormalizedFlow {
     pub bytes: u64,
     pub packets: u64,
     pub process: Option<String>,
+    pub process_hash: Option<String>,
+    pub process_signed: Option<bool>,
+    pub state: Option<String>,
+    pub sni: Option<String>,
+    pub ja3: Option<String>,
+    pub ja4: Option<String>,
+    pub alpn: Option<String>,
+    pub dns_qname: Option<String>,
+    #[serde(default)]
+    pub dns_rcode: Option<String>,
+    #[serde(default)]
+    pub dns_answers: Vec<String>,
+    /// Row id of the stored flow this was normalized from, once persisted.
+    #[serde(default)]
+    pub flow_id: Option<i64>,
+}
+
+impl Default for NormalizedFlow {
+    fn default() -> Self {
+        let epoch = Utc.timestamp_opt(0, 0).unwrap();
+        Self {
+            window_start: epoch,
+            window_end: epoch,
+            proto: String::new(),
+            src_ip: String::new(),
+            src_port: 0,
+            dst_ip: String::new(),
+            dst_port: 0,
+            direction: FlowDirection::Inbound,
+            bytes: 0,
+            packets: 0,
+            process: None,
+            process_hash: None,
+            process_signed: None,
+            state: None,
+            sni: None,
+            ja3: None,
+            ja4: None,
+            alpn: None,
+            dns_qname: None,
+            dns_rcode: None,
+            dns_answers: Vec::new(),
+            flow_id: None,
+        }
+    }
 }
 
 pub struct Normalizer {
@@ -43,7 +88,18 @@ impl Normalizer {
             direction: event.direction,
             bytes: event.bytes,
             packets: event.packets,
+            process_hash: event.process.as_ref().and_then(|p| p.sha256_16.clone()),
+            process_signed: event.process.as_ref().and_then(|p| p.signed),
             process: event.process.and_then(|p| p.name),
+            state: event.state,
+            sni: event.sni,
+            ja3: event.ja3,
+            ja4: event.ja4,
+            alpn: event.alpn,
+            dns_qname: event.dns_qname,
+            dns_rcode: event.dns_rcode,
+            dns_answers: event.dns_answers,
+            flow_id: None,
         };
         Ok(normalized)
     }
@@ -52,7 +108,6 @@ impl Normalizer {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use chrono::TimeZone;
 
     #[test]
     fn normalize_basic() {
@@ -76,9 +131,11 @@ mod tests {
             sni: None,
             alpn: None,
             ja3: None,
+            ja4: None,
             dns_qname: None,
             dns_qtype: None,
             dns_rcode: None,
+            dns_answers: Vec::new(),
         };
         let normalized = normalizer.normalize(event).unwrap();
         assert_eq!(normalized.bytes, 1024);
@@ -0,0 +1,57 @@
+use analyzer::Severity;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{ActiveQuarantine, PolicyAction, QuarantineDecision};
+
+/// When quarantines wait for an operator; mirrors the `[policy]` section of
+/// the config.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApprovalConfig {
+    /// Guardian mode: submitted decisions wait for confirmation from the
+    /// UI or CLI instead of being enforced right away.
+    pub confirmation_required: bool,
+    /// How long a decision waits for confirmation.
+    pub approval_timeout_seconds: u64,
+    /// Severities enforced without confirmation.
+    pub auto_approve: Vec<Severity>,
+    /// Enforce a decision nobody confirmed in time instead of dropping it.
+    pub apply_on_timeout: bool,
+}
+
+impl Default for ApprovalConfig {
+    fn default() -> Self {
+        Self {
+            confirmation_required: true,
+            approval_timeout_seconds: 120,
+            auto_approve: Vec::new(),
+            apply_on_timeout: false,
+        }
+    }
+}
+
+impl ApprovalConfig {
+    pub fn requires_approval(&self, severity: &Severity) -> bool {
+        self.confirmation_required && !self.auto_approve.contains(severity)
+    }
+}
+
+/// A decision waiting for confirmation. Once approved it is enforced under
+/// the same id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingApproval {
+    pub id: String,
+    pub action: PolicyAction,
+    pub decision: QuarantineDecision,
+    pub requested_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Outcome of [`crate::QuarantineManager::submit`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum Submission {
+    Applied { quarantine: ActiveQuarantine },
+    Pending { pending: PendingApproval },
+}
@@ -0,0 +1,138 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+use anyhow::{anyhow, Context, Result};
+
+/// Runs the firewall tools a backend drives; swapped for a scripted runner
+/// in tests.
+pub trait CommandRunner: Send + Sync {
+    /// Runs `program` with `args` and returns its stdout. A non-zero exit
+    /// status is an error carrying stderr.
+    fn run(&self, program: &str, args: &[&str]) -> Result<String>;
+
+    /// Like [`CommandRunner::run`], writing `input` to the program's stdin.
+    fn run_with_input(&self, program: &str, args: &[&str], input: &str) -> Result<String>;
+}
+
+/// Spawns the real programs.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, program: &str, args: &[&str]) -> Result<String> {
+        let output = Command::new(program)
+            .args(args)
+            .output()
+            .with_context(|| format!("running {program}"))?;
+        checked_stdout(program, args, output)
+    }
+
+    fn run_with_input(&self, program: &str, args: &[&str], input: &str) -> Result<String> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("running {program}"))?;
+        // Dropping stdin after the write closes it, so the program sees EOF.
+        child
+            .stdin
+            .take()
+            .context("stdin not captured")?
+            .write_all(input.as_bytes())
+            .with_context(|| format!("writing to {program}"))?;
+        let output = child
+            .wait_with_output()
+            .with_context(|| format!("running {program}"))?;
+        checked_stdout(program, args, output)
+    }
+}
+
+fn checked_stdout(program: &str, args: &[&str], output: Output) -> Result<String> {
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{program} {} failed ({}): {}",
+            args.join(" "),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// `text` as a PowerShell single-quoted string. PowerShell also takes the
+/// typographic single quotes (U+2018–U+201B) as quotes, so they are
+/// doubled like the ASCII one.
+pub(crate) fn powershell_quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('\'');
+    for c in text.chars() {
+        if matches!(c, '\'' | '\u{2018}'..='\u{201b}') {
+            quoted.push(c);
+        }
+        quoted.push(c);
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// Answer to a command line given to [`ScriptedRunner`].
+#[cfg(test)]
+type Script = Box<dyn Fn(&str) -> Result<String> + Send + Sync>;
+
+/// Records every command line, followed by its stdin if any, and answers
+/// from a script.
+#[cfg(test)]
+pub(crate) struct ScriptedRunner {
+    calls: std::sync::Mutex<Vec<String>>,
+    respond: Script,
+}
+
+#[cfg(test)]
+impl ScriptedRunner {
+    pub fn new(respond: impl Fn(&str) -> Result<String> + Send + Sync + 'static) -> Self {
+        Self {
+            calls: Default::default(),
+            respond: Box::new(respond),
+        }
+    }
+
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+fn command_line(program: &str, args: &[&str]) -> String {
+    std::iter::once(program)
+        .chain(args.iter().copied())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+impl CommandRunner for ScriptedRunner {
+    fn run(&self, program: &str, args: &[&str]) -> Result<String> {
+        let line = command_line(program, args);
+        self.calls.lock().unwrap().push(line.clone());
+        (self.respond)(&line)
+    }
+
+    fn run_with_input(&self, program: &str, args: &[&str], input: &str) -> Result<String> {
+        let line = command_line(program, args);
+        self.calls.lock().unwrap().push(format!("{line}\n{input}"));
+        (self.respond)(&line)
+    }
+}
+
+#[cfg(test)]
+impl<R: CommandRunner> CommandRunner for std::sync::Arc<R> {
+    fn run(&self, program: &str, args: &[&str]) -> Result<String> {
+        (**self).run(program, args)
+    }
+
+    fn run_with_input(&self, program: &str, args: &[&str], input: &str) -> Result<String> {
+        (**self).run_with_input(program, args, input)
+    }
+}
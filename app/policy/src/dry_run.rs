@@ -0,0 +1,172 @@
+use anyhow::Result;
+use chrono::Utc;
+use storage::{ActionOutcome, PolicyActionRecord};
+
+use crate::{store::ActionLog, AppliedRule, KillSwitch, PolicyBackend, QuarantineDecision};
+
+/// Runs a backend in dry-run mode (`[policy] dry_run`): `apply` computes
+/// the exact rules or commands the backend would install, logs them and
+/// records them in the policy action audit table as `DryRun`, and nothing
+/// is enforced. Meant for checking what Guardian mode would do before
+/// turning it on.
+pub struct DryRunBackend {
+    inner: Box<dyn PolicyBackend + Send + Sync>,
+    log: Option<Box<dyn ActionLog>>,
+}
+
+impl DryRunBackend {
+    pub fn new(inner: Box<dyn PolicyBackend + Send + Sync>) -> Self {
+        Self { inner, log: None }
+    }
+
+    pub fn with_log(inner: Box<dyn PolicyBackend + Send + Sync>, log: Box<dyn ActionLog>) -> Self {
+        Self {
+            inner,
+            log: Some(log),
+        }
+    }
+}
+
+impl PolicyBackend for DryRunBackend {
+    fn apply(&self, decision: &QuarantineDecision) -> Result<()> {
+        let backend = self.inner.name();
+        let commands = self.inner.plan(decision)?;
+        for command in &commands {
+            tracing::info!(backend, "dry run, not applied: {command}");
+        }
+        if let Some(log) = &self.log {
+            log.record(&PolicyActionRecord {
+                id: 0,
+                ts: Utc::now(),
+                action: "quarantine".into(),
+                decision: serde_json::to_value(decision)?,
+                backend: backend.to_string(),
+                rule_id: None,
+                alert_id: None,
+                approved_by: None,
+                approved_at: None,
+                applied_at: None,
+                rolled_back_at: None,
+                outcome: ActionOutcome::DryRun,
+                error: None,
+                commands,
+            })?;
+        }
+        Ok(())
+    }
+
+    fn rollback(&self, decision: &QuarantineDecision) -> Result<()> {
+        tracing::info!(
+            backend = self.inner.name(),
+            rules = ?self.inner.rule_handles(decision),
+            "dry run, not rolled back"
+        );
+        Ok(())
+    }
+
+    fn plan(&self, decision: &QuarantineDecision) -> Result<Vec<String>> {
+        self.inner.plan(decision)
+    }
+
+    /// Distinct from the wrapped backend's, so quarantines persisted in a
+    /// dry run are not enforced by a later real run's recovery.
+    fn name(&self) -> &'static str {
+        "dry-run"
+    }
+
+    fn rule_handles(&self, decision: &QuarantineDecision) -> Vec<String> {
+        self.inner.rule_handles(decision)
+    }
+
+    fn engage_kill_switch(&self, switch: &KillSwitch) -> Result<()> {
+        tracing::info!(
+            backend = self.inner.name(),
+            allow = ?switch.allow,
+            "dry run, kill switch not engaged"
+        );
+        Ok(())
+    }
+
+    fn release_kill_switch(&self) -> Result<()> {
+        tracing::info!(
+            backend = self.inner.name(),
+            "dry run, kill switch not released"
+        );
+        Ok(())
+    }
+
+    /// What is really enforced, e.g. by an earlier run without dry run.
+    fn list_active(&self) -> Result<Vec<AppliedRule>> {
+        self.inner.list_active()
+    }
+
+    fn rollback_all(&self) -> Result<()> {
+        tracing::info!(
+            backend = self.inner.name(),
+            "dry run, rules not rolled back"
+        );
+        Ok(())
+    }
+
+    fn suspend_process(&self, pid: i32) -> Result<()> {
+        tracing::info!(pid, "dry run, process not suspended");
+        Ok(())
+    }
+
+    fn resume_process(&self, pid: i32) -> Result<()> {
+        tracing::info!(pid, "dry run, process not resumed");
+        Ok(())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use storage::{PolicyActionQuery, Storage};
+
+    use super::*;
+    use crate::command::ScriptedRunner;
+    use crate::linux::NftablesBackend;
+    use crate::Direction;
+
+    #[test]
+    fn records_planned_rules_without_running_them() {
+        let runner = Arc::new(ScriptedRunner::new(|_| Ok(String::new())));
+        let storage = Arc::new(Mutex::new(Storage::open(":memory:", &[7u8; 32]).unwrap()));
+        let backend = DryRunBackend::with_log(
+            Box::new(NftablesBackend::with_runner(Box::new(runner.clone()))),
+            Box::new(storage.clone()),
+        );
+        let decision = QuarantineDecision {
+            process: None,
+            identity: None,
+            ports: vec![445],
+            expires_in_seconds: 600,
+            suspend: false,
+            direction: Direction::Both,
+            remote_networks: Vec::new(),
+            remote_domains: Vec::new(),
+        };
+        backend.apply(&decision).unwrap();
+        backend.rollback(&decision).unwrap();
+        assert!(runner.calls().is_empty());
+
+        let recorded = storage
+            .lock()
+            .unwrap()
+            .query_policy_actions(&PolicyActionQuery::default())
+            .unwrap();
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].outcome, ActionOutcome::DryRun);
+        assert_eq!(recorded[0].backend, "nftables");
+        assert_eq!(
+            recorded[0].commands[..2],
+            [
+                "nft add rule inet nets input tcp dport 445 drop comment \"nets:tcp/445\"",
+                "nft add rule inet nets input udp dport 445 drop comment \"nets:udp/445\"",
+            ]
+        );
+        assert_eq!(recorded[0].commands.len(), 4);
+    }
+}
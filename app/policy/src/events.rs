@@ -0,0 +1,136 @@
+//! Enforcement state of policy actions, broadcast for the UI: one
+//! [`PolicyEvent`] whenever a quarantine, the kill switch or a playbook
+//! step waits for approval, takes effect, is lifted or fails. The bus is a
+//! tokio broadcast channel like the UI's own event stream, so the UI can
+//! forward it without polling.
+
+use analyzer::Severity;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::manager::QuarantineEvent;
+
+/// Events a subscriber may fall behind by before it misses some.
+const CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum PolicyEvent {
+    PendingApproval {
+        id: String,
+        action: String,
+        description: String,
+        severity: Severity,
+        expires_at: DateTime<Utc>,
+    },
+    /// In force; sent again when the expiry changes.
+    Applied {
+        id: String,
+        action: String,
+        expires_at: Option<DateTime<Utc>>,
+    },
+    /// Lifted or never enforced: `released`, `expired`, `rejected`,
+    /// `approval_timed_out`.
+    RolledBack {
+        id: String,
+        action: String,
+        reason: String,
+    },
+    Failed {
+        id: String,
+        action: String,
+        error: String,
+    },
+}
+
+impl PolicyEvent {
+    /// Quarantine id, `kill-switch`, or `<playbook>/<alert>/<step>`.
+    pub fn id(&self) -> &str {
+        match self {
+            PolicyEvent::PendingApproval { id, .. }
+            | PolicyEvent::Applied { id, .. }
+            | PolicyEvent::RolledBack { id, .. }
+            | PolicyEvent::Failed { id, .. } => id,
+        }
+    }
+
+    /// The state change `event` amounts to, if any.
+    pub(crate) fn from_quarantine(event: &QuarantineEvent) -> Option<Self> {
+        let quarantine = "quarantine".to_string();
+        let rolled_back = |reason: &str| PolicyEvent::RolledBack {
+            id: event.quarantine_id().to_string(),
+            action: quarantine.clone(),
+            reason: reason.into(),
+        };
+        Some(match event {
+            QuarantineEvent::PendingApproval { pending } => PolicyEvent::PendingApproval {
+                id: pending.id.clone(),
+                action: quarantine,
+                description: pending.action.description.clone(),
+                severity: pending.action.severity.clone(),
+                expires_at: pending.expires_at,
+            },
+            QuarantineEvent::Applied { quarantine: active }
+            | QuarantineEvent::Restored { quarantine: active } => PolicyEvent::Applied {
+                id: active.id.clone(),
+                action: quarantine,
+                expires_at: active.expires_at,
+            },
+            QuarantineEvent::Extended { id, expires_at } => PolicyEvent::Applied {
+                id: id.clone(),
+                action: quarantine,
+                expires_at: *expires_at,
+            },
+            QuarantineEvent::Released { .. } => rolled_back("released"),
+            QuarantineEvent::Expired { .. } => rolled_back("expired"),
+            QuarantineEvent::Rejected { .. } => rolled_back("rejected"),
+            QuarantineEvent::ApprovalTimedOut { applied: false, .. } => {
+                rolled_back("approval_timed_out")
+            }
+            QuarantineEvent::RollbackFailed { id, error } => PolicyEvent::Failed {
+                id: id.clone(),
+                action: quarantine,
+                error: error.clone(),
+            },
+            QuarantineEvent::KillSwitchEngaged { .. } => PolicyEvent::Applied {
+                id: event.quarantine_id().to_string(),
+                action: "kill_switch".into(),
+                expires_at: None,
+            },
+            QuarantineEvent::KillSwitchReleased => PolicyEvent::RolledBack {
+                id: event.quarantine_id().to_string(),
+                action: "kill_switch".into(),
+                reason: "released".into(),
+            },
+            // Confirmed ones are followed by `applied`.
+            QuarantineEvent::Approved { .. } | QuarantineEvent::ApprovalTimedOut { .. } => {
+                return None
+            }
+        })
+    }
+}
+
+/// Sending half of the policy event stream; clones share one channel.
+#[derive(Debug, Clone)]
+pub struct PolicyEventBus {
+    sender: broadcast::Sender<PolicyEvent>,
+}
+
+impl Default for PolicyEventBus {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Self { sender }
+    }
+}
+
+impl PolicyEventBus {
+    pub fn subscribe(&self) -> broadcast::Receiver<PolicyEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Nobody listening is not an error.
+    pub fn publish(&self, event: PolicyEvent) {
+        let _ = self.sender.send(event);
+    }
+}
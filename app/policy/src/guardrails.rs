@@ -0,0 +1,467 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    path::Path,
+    sync::RwLock,
+};
+
+use analyzer::exceptions::{glob_match, Exception};
+use chrono::{DateTime, Utc};
+use collector::ProcessIdentity;
+use serde::{Deserialize, Serialize};
+
+use crate::{is_targetable, IpNetwork, QuarantineDecision};
+
+/// DNS over UDP/TCP and over TLS.
+const DNS_PORTS: [u16; 2] = [53, 853];
+/// DHCP server and client: blocking them loses the lease, and with it the
+/// route through the gateway.
+const DHCP_PORTS: [u16; 2] = [67, 68];
+
+/// What quarantines must never cut off; mirrors `[policy.guardrails]`.
+/// Gateways and resolvers of the host are detected and added to the
+/// configured ones.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuardrailConfig {
+    pub gateways: Vec<IpAddr>,
+    pub dns_resolvers: Vec<IpAddr>,
+    /// Process names or executable paths, compared case-insensitively.
+    pub critical_processes: Vec<String>,
+}
+
+impl Default for GuardrailConfig {
+    fn default() -> Self {
+        Self {
+            gateways: Vec::new(),
+            dns_resolvers: Vec::new(),
+            critical_processes: [
+                "systemd",
+                "init",
+                "launchd",
+                "wininit.exe",
+                "csrss.exe",
+                "smss.exe",
+                "services.exe",
+                "lsass.exe",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+/// Why a decision was refused, for the UI to display. Returned inside
+/// `anyhow::Error` by [`crate::validate_decision`]; downcast to get it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, thiserror::Error)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum GuardrailViolation {
+    #[error("quarantine would cut off the default gateway {address}")]
+    DefaultGateway { address: IpAddr },
+    #[error("quarantine would block the DNS resolver {address}")]
+    DnsResolver { address: IpAddr },
+    #[error("quarantine would block the nets daemon itself")]
+    NetsDaemon,
+    #[error("{process} is a critical process and cannot be quarantined")]
+    CriticalProcess { process: String },
+    #[error("quarantine would block exception {exception_id}, snoozed until {expires_at}")]
+    Snoozed {
+        exception_id: String,
+        expires_at: DateTime<Utc>,
+    },
+}
+
+/// The guardrails in force: configuration plus what was detected on the
+/// host and the daemon's own process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Guardrails {
+    pub gateways: Vec<IpAddr>,
+    pub dns_resolvers: Vec<IpAddr>,
+    pub critical_processes: Vec<String>,
+    pub own_pid: i32,
+    pub own_exe: Option<String>,
+    /// Expiring analyzer exceptions ([`analyzer::exceptions::ExceptionList::snoozes`]):
+    /// what they allow is not blocked either until they lapse.
+    pub snoozes: Vec<Exception>,
+}
+
+static CURRENT: RwLock<Option<Guardrails>> = RwLock::new(None);
+
+impl Guardrails {
+    /// `config` plus the host's default gateways and resolvers.
+    pub fn detect(config: &GuardrailConfig) -> Self {
+        let mut gateways = config.gateways.clone();
+        if let Ok(routes) = std::fs::read_to_string("/proc/net/route") {
+            gateways.extend(parse_proc_routes(&routes));
+        }
+        let mut dns_resolvers = config.dns_resolvers.clone();
+        if let Ok(resolv) = std::fs::read_to_string("/etc/resolv.conf") {
+            dns_resolvers.extend(parse_resolv_conf(&resolv));
+        }
+        gateways.dedup();
+        dns_resolvers.dedup();
+        Self {
+            gateways,
+            dns_resolvers,
+            critical_processes: config.critical_processes.clone(),
+            own_pid: std::process::id() as i32,
+            own_exe: std::env::current_exe()
+                .ok()
+                .map(|path| path.display().to_string()),
+            snoozes: Vec::new(),
+        }
+    }
+
+    /// Replaces the snoozes, keeping those that have not lapsed; install
+    /// the result whenever the exception list changes.
+    pub fn with_snoozes<'a>(mut self, snoozes: impl IntoIterator<Item = &'a Exception>) -> Self {
+        let now = Utc::now();
+        self.snoozes = snoozes
+            .into_iter()
+            .filter(|snooze| snooze.expires_at.is_some() && !snooze.is_expired(now))
+            .cloned()
+            .collect();
+        self
+    }
+
+    /// Guardrails checked by [`crate::validate_decision`] from now on.
+    pub fn install(self) {
+        *CURRENT
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(self);
+    }
+
+    /// The installed guardrails, detected with the default config on
+    /// first use.
+    pub fn current() -> Self {
+        if let Some(current) = CURRENT
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .as_ref()
+        {
+            return current.clone();
+        }
+        let detected = Self::detect(&GuardrailConfig::default());
+        detected.clone().install();
+        detected
+    }
+
+    pub fn check(&self, decision: &QuarantineDecision) -> Result<(), GuardrailViolation> {
+        self.check_remotes(decision, &decision.remote_networks)
+    }
+
+    /// [`Self::check`] against the decision's remote peers as resolved: a
+    /// block limited to some peers only endangers the gateways and
+    /// resolvers among them, but all of their ports.
+    pub fn check_remotes(
+        &self,
+        decision: &QuarantineDecision,
+        remotes: &[IpNetwork],
+    ) -> Result<(), GuardrailViolation> {
+        if let Some(identity) = &decision.identity {
+            self.check_process(identity)?;
+        } else if let Some(process) = &decision.process {
+            if self.is_critical(process) {
+                return Err(GuardrailViolation::CriticalProcess {
+                    process: process.clone(),
+                });
+            }
+        }
+        // Port rules scoped to a process leave everyone else's DNS and
+        // DHCP alone.
+        let now = Utc::now();
+        if let Some(snooze) = self
+            .snoozes
+            .iter()
+            .find(|snooze| !snooze.is_expired(now) && blocks_snoozed(decision, remotes, snooze))
+        {
+            return Err(GuardrailViolation::Snoozed {
+                exception_id: snooze.id.clone(),
+                expires_at: snooze.expires_at.unwrap_or(now),
+            });
+        }
+        // Queries to the resolvers are outbound; DHCP goes both ways.
+        if decision.identity.is_none() {
+            let all_ports = decision.ports.is_empty() && !remotes.is_empty();
+            let blocks =
+                |ports: &[u16]| all_ports || decision.ports.iter().any(|port| ports.contains(port));
+            let covered = |addresses: &[IpAddr]| {
+                addresses.iter().copied().find(|address| {
+                    remotes.is_empty() || remotes.iter().any(|remote| remote.contains(address))
+                })
+            };
+            if blocks(&DNS_PORTS) && decision.direction.outbound() {
+                if let Some(address) = covered(&self.dns_resolvers) {
+                    return Err(GuardrailViolation::DnsResolver { address });
+                }
+            }
+            if blocks(&DHCP_PORTS) {
+                if let Some(address) = covered(&self.gateways) {
+                    return Err(GuardrailViolation::DefaultGateway { address });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_process(&self, identity: &ProcessIdentity) -> Result<(), GuardrailViolation> {
+        let exe = identity.exe_path.as_deref();
+        if identity.pid == self.own_pid || exe.is_some() && exe == self.own_exe.as_deref() {
+            return Err(GuardrailViolation::NetsDaemon);
+        }
+        let critical = identity.pid == 1
+            || identity
+                .name
+                .as_deref()
+                .is_some_and(|name| self.is_critical(name))
+            || exe.is_some_and(|exe| self.is_critical(exe));
+        if critical {
+            return Err(GuardrailViolation::CriticalProcess {
+                process: exe
+                    .or(identity.name.as_deref())
+                    .map_or_else(|| format!("pid {}", identity.pid), String::from),
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether `process`, a name or a path, is listed as critical.
+    fn is_critical(&self, process: &str) -> bool {
+        let file_name = Path::new(process)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(process);
+        self.critical_processes.iter().any(|critical| {
+            critical.eq_ignore_ascii_case(process) || critical.eq_ignore_ascii_case(file_name)
+        })
+    }
+}
+
+/// Whether `decision` could block traffic `snooze` allows: it reaches the
+/// snoozed process (or every process), port and destination. Destinations
+/// that are globs only meet the decision's domains.
+fn blocks_snoozed(
+    decision: &QuarantineDecision,
+    remotes: &[IpNetwork],
+    snooze: &Exception,
+) -> bool {
+    if let Some(identity) = decision.identity.as_ref().filter(|id| is_targetable(id)) {
+        if let Some(process) = &snooze.process {
+            let names = [
+                identity.name.as_deref(),
+                identity.exe_path.as_deref(),
+                identity
+                    .exe_path
+                    .as_deref()
+                    .and_then(|exe| Path::new(exe).file_name()?.to_str()),
+            ];
+            if !names.contains(&Some(process.as_str())) {
+                return false;
+            }
+        }
+        if let Some(hash) = &snooze.process_hash {
+            if identity
+                .sha256_16
+                .as_deref()
+                .is_some_and(|actual| actual != hash)
+            {
+                return false;
+            }
+        }
+    }
+    if !decision.ports.is_empty()
+        && !snooze.ports.is_empty()
+        && !snooze
+            .ports
+            .iter()
+            .any(|port| decision.ports.contains(port))
+    {
+        return false;
+    }
+    match &snooze.destination {
+        Some(destination) if decision.is_remote_scoped() => {
+            let address = destination.parse::<IpAddr>().ok();
+            address.is_some_and(|address| remotes.iter().any(|remote| remote.contains(&address)))
+                || decision
+                    .remote_domains
+                    .iter()
+                    .any(|domain| glob_match(destination, domain))
+        }
+        _ => true,
+    }
+}
+
+/// Gateways of the default routes in `/proc/net/route`, whose addresses
+/// are little-endian hex.
+fn parse_proc_routes(routes: &str) -> Vec<IpAddr> {
+    routes
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.get(1) != Some(&"00000000") {
+                return None;
+            }
+            let gateway = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+            (gateway != 0).then(|| IpAddr::V4(Ipv4Addr::from(gateway.swap_bytes())))
+        })
+        .collect()
+}
+
+fn parse_resolv_conf(resolv: &str) -> Vec<IpAddr> {
+    resolv
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            (words.next() == Some("nameserver"))
+                .then(|| words.next()?.split('%').next()?.parse().ok())
+                .flatten()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Direction;
+
+    #[test]
+    fn refuses_infrastructure_and_critical_processes() {
+        let routes = "Iface\tDestination\tGateway \tFlags\n\
+                      eth0\t00000000\t0101A8C0\t0003\n\
+                      eth0\t0001A8C0\t00000000\t0001\n";
+        let resolv = "# generated\nnameserver 192.168.1.53\nnameserver fe80::1%eth0\n";
+        let guardrails = Guardrails {
+            gateways: parse_proc_routes(routes),
+            dns_resolvers: parse_resolv_conf(resolv),
+            critical_processes: GuardrailConfig::default().critical_processes,
+            own_pid: 4242,
+            own_exe: Some("/usr/bin/netsd".into()),
+            snoozes: Vec::new(),
+        };
+        assert_eq!(
+            guardrails.gateways,
+            ["192.168.1.1".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(guardrails.dns_resolvers.len(), 2);
+
+        let ports = |ports: Vec<u16>| QuarantineDecision {
+            process: None,
+            identity: None,
+            ports,
+            expires_in_seconds: 60,
+            suspend: false,
+            direction: Direction::Both,
+            remote_networks: Vec::new(),
+            remote_domains: Vec::new(),
+        };
+        let process = |pid, name: &str, exe: &str| QuarantineDecision {
+            identity: Some(ProcessIdentity {
+                pid,
+                ppid: None,
+                name: Some(name.into()),
+                exe_path: Some(exe.into()),
+                sha256_16: None,
+                user: None,
+                signed: None,
+            }),
+            ..ports(vec![])
+        };
+        assert!(guardrails.check(&ports(vec![445])).is_ok());
+        assert_eq!(
+            guardrails.check(&ports(vec![445, 53])),
+            Err(GuardrailViolation::DnsResolver {
+                address: "192.168.1.53".parse().unwrap()
+            })
+        );
+        assert!(matches!(
+            guardrails.check(&ports(vec![67])),
+            Err(GuardrailViolation::DefaultGateway { .. })
+        ));
+        assert_eq!(
+            guardrails.check(&process(7, "netsd", "/usr/bin/netsd")),
+            Err(GuardrailViolation::NetsDaemon)
+        );
+        assert_eq!(
+            guardrails.check(&process(
+                800,
+                "LSASS.EXE",
+                "C:\\Windows\\System32\\lsass.exe"
+            )),
+            Err(GuardrailViolation::CriticalProcess {
+                process: "C:\\Windows\\System32\\lsass.exe".into()
+            })
+        );
+        // Limited to other peers or to inbound traffic, so is a DNS port
+        // block; limited to the resolver's network, any block is not.
+        let peers = |networks: &[&str], blocked: Vec<u16>| QuarantineDecision {
+            remote_networks: networks.iter().map(|n| n.parse().unwrap()).collect(),
+            ..ports(blocked)
+        };
+        assert!(guardrails
+            .check(&peers(&["203.0.113.0/24"], vec![53]))
+            .is_ok());
+        assert!(guardrails
+            .check(&QuarantineDecision {
+                direction: Direction::Inbound,
+                ..ports(vec![53])
+            })
+            .is_ok());
+        assert_eq!(
+            guardrails.check(&peers(&["192.168.1.0/24"], vec![])),
+            Err(GuardrailViolation::DnsResolver {
+                address: "192.168.1.53".parse().unwrap()
+            })
+        );
+        // What a snooze allows stays reachable until it lapses.
+        let snooze = Exception {
+            id: "exc-1".into(),
+            rule_id: Some("builtin.first_contact".into()),
+            process: Some("updater".into()),
+            process_hash: None,
+            destination: Some("203.0.113.9".into()),
+            ports: vec![443],
+            expires_at: Some(Utc::now() + chrono::Duration::hours(24)),
+            created_at: Utc::now(),
+            created_by: Some("analyst".into()),
+            reason: None,
+            hits: 0,
+            last_hit: None,
+            alert_id: Some("a1".into()),
+        };
+        let snoozed = guardrails.clone().with_snoozes([&snooze]);
+        assert!(matches!(
+            snoozed.check(&peers(&["203.0.113.0/24"], vec![443])),
+            Err(GuardrailViolation::Snoozed { exception_id, .. }) if exception_id == "exc-1"
+        ));
+        assert!(snoozed
+            .check(&peers(&["198.51.100.0/24"], vec![443]))
+            .is_ok());
+        assert!(snoozed.check(&ports(vec![8443])).is_ok());
+        assert!(snoozed
+            .check(&QuarantineDecision {
+                ports: vec![443],
+                ..process(900, "agent", "/tmp/agent")
+            })
+            .is_ok());
+        let lapsed = Exception {
+            expires_at: Some(Utc::now() - chrono::Duration::hours(1)),
+            ..snooze
+        };
+        assert!(guardrails
+            .clone()
+            .with_snoozes([&lapsed])
+            .snoozes
+            .is_empty());
+
+        // Scoped to a process, a DNS port block is fine.
+        assert!(guardrails
+            .check(&QuarantineDecision {
+                ports: vec![53],
+                ..process(900, "agent", "/tmp/agent")
+            })
+            .is_ok());
+
+        let violation = serde_json::to_value(GuardrailViolation::NetsDaemon).unwrap();
+        assert_eq!(violation["reason"], "nets_daemon");
+    }
+}
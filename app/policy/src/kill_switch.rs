@@ -0,0 +1,96 @@
+use std::net::{IpAddr, ToSocketAddrs};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Guardrails;
+
+/// Tag of the kill switch rules, so they are told apart from quarantines.
+pub(crate) const KILL_SWITCH_TAG: &str = "nets:kill-switch";
+
+/// Destinations the kill switch leaves reachable besides the detected
+/// gateways and DNS resolvers; mirrors `[policy.kill_switch]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KillSwitchConfig {
+    pub allow: Vec<IpAddr>,
+    /// Update servers and the like, resolved when the switch is engaged.
+    pub allow_hosts: Vec<String>,
+}
+
+/// Emergency containment: all outbound traffic is blocked except loopback,
+/// DHCP and the `allow` addresses. Engaged and released as a whole through
+/// [`crate::PolicyBackend::engage_kill_switch`] and
+/// [`crate::PolicyBackend::release_kill_switch`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KillSwitch {
+    pub allow: Vec<IpAddr>,
+}
+
+impl KillSwitch {
+    /// The configured allowlist plus the gateways and resolvers of
+    /// `guardrails`. Hosts that do not resolve are skipped with a warning.
+    pub fn new(config: &KillSwitchConfig, guardrails: &Guardrails) -> Self {
+        let mut allow: Vec<IpAddr> = config
+            .allow
+            .iter()
+            .chain(&guardrails.gateways)
+            .chain(&guardrails.dns_resolvers)
+            .copied()
+            .collect();
+        for host in &config.allow_hosts {
+            match (host.as_str(), 443).to_socket_addrs() {
+                Ok(addrs) => allow.extend(addrs.map(|addr| addr.ip())),
+                Err(err) => tracing::warn!(host, "kill switch allowlist host unresolved: {err}"),
+            }
+        }
+        allow.sort_unstable();
+        allow.dedup();
+        Self { allow }
+    }
+
+    pub fn ipv4(&self) -> impl Iterator<Item = &IpAddr> {
+        self.allow.iter().filter(|addr| addr.is_ipv4())
+    }
+
+    pub fn ipv6(&self) -> impl Iterator<Item = &IpAddr> {
+        self.allow.iter().filter(|addr| addr.is_ipv6())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_guardrail_infrastructure() {
+        let guardrails = Guardrails {
+            gateways: vec!["192.168.1.1".parse().unwrap()],
+            dns_resolvers: vec!["fe80::1".parse().unwrap(), "192.168.1.1".parse().unwrap()],
+            critical_processes: Vec::new(),
+            own_pid: 1,
+            own_exe: None,
+            snoozes: Vec::new(),
+        };
+        let switch = KillSwitch::new(
+            &KillSwitchConfig {
+                allow: vec!["10.0.0.5".parse().unwrap()],
+                allow_hosts: vec!["localhost".into()],
+            },
+            &guardrails,
+        );
+        assert!(switch.allow.contains(&"10.0.0.5".parse().unwrap()));
+        assert_eq!(
+            switch.ipv6().count(),
+            switch.allow.len() - switch.ipv4().count()
+        );
+        assert_eq!(
+            switch
+                .allow
+                .iter()
+                .filter(|addr| **addr == "192.168.1.1".parse::<IpAddr>().unwrap())
+                .count(),
+            1
+        );
+        assert!(switch.allow.iter().any(|addr| addr.is_loopback()));
+    }
+}
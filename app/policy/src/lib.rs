@@ -1,9 +1,43 @@
 use analyzer::{Alert, Severity};
 use anyhow::{anyhow, Result};
-use collector::FlowEvent;
+use collector::{FlowEvent, ProcessIdentity};
 use serde::{Deserialize, Serialize};
 use tracing::info;
 
+pub mod approval;
+pub mod command;
+pub mod dry_run;
+pub mod events;
+pub mod guardrails;
+pub mod kill_switch;
+#[cfg(target_os = "linux")]
+pub mod linux;
+#[cfg(target_os = "macos")]
+pub mod macos;
+pub mod manager;
+pub mod network;
+pub mod notify;
+pub mod playbook;
+pub mod process;
+pub mod sinkhole;
+pub mod store;
+pub mod throttle;
+#[cfg(target_os = "windows")]
+pub mod windows;
+
+pub use approval::{ApprovalConfig, PendingApproval, Submission};
+pub use dry_run::DryRunBackend;
+pub use events::{PolicyEvent, PolicyEventBus};
+pub use guardrails::{GuardrailConfig, GuardrailViolation, Guardrails};
+pub use kill_switch::{KillSwitch, KillSwitchConfig};
+pub use manager::{ActiveQuarantine, QuarantineEvent, QuarantineManager, RecoveryReport};
+pub use network::IpNetwork;
+pub use notify::{NotificationConfig, NotificationPayload, Notifier, NotifyChannel};
+pub use playbook::{load_playbooks_from_str, Playbook, PlaybookEngine, StepOutcome};
+pub use sinkhole::{DnsSinkhole, SinkholeConfig, SinkholeDecision, SinkholeKind, SinkholeMethod};
+pub use store::{ActionLog, QuarantineStore};
+pub use throttle::{ThrottleConfig, ThrottleDecision, ThrottleMethod, Throttler};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PolicyAction {
     pub id: String,
@@ -15,19 +49,127 @@ pub struct PolicyAction {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QuarantineDecision {
     pub process: Option<String>,
+    /// The program to block, as the collector attributed the flow. With no
+    /// ports, all of its traffic is blocked.
+    #[serde(default)]
+    pub identity: Option<ProcessIdentity>,
     pub ports: Vec<u16>,
     pub expires_in_seconds: u64,
+    /// Also suspend the identified process while the quarantine lasts, a
+    /// reversible alternative to terminating it.
+    #[serde(default)]
+    pub suspend: bool,
+    /// Which side of the host the blocked traffic starts on.
+    #[serde(default)]
+    pub direction: Direction,
+    /// Limits the block to these remote peers; with no ports, all traffic
+    /// to and from them is blocked.
+    #[serde(default)]
+    pub remote_networks: Vec<IpNetwork>,
+    /// Remote peers by name, resolved each time the rules are installed.
+    #[serde(default)]
+    pub remote_domains: Vec<String>,
+}
+
+impl QuarantineDecision {
+    /// Whether the decision is limited to some remote peers.
+    pub fn is_remote_scoped(&self) -> bool {
+        !self.remote_networks.is_empty() || !self.remote_domains.is_empty()
+    }
+}
+
+/// Traffic a quarantine blocks: connections the host accepts, those it
+/// opens, or both. Ports are local ports inbound and remote ports outbound.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Inbound,
+    Outbound,
+    #[default]
+    Both,
+}
+
+impl Direction {
+    pub fn inbound(self) -> bool {
+        self != Direction::Outbound
+    }
+
+    pub fn outbound(self) -> bool {
+        self != Direction::Inbound
+    }
+}
+
+/// A rule nets has in place, as read back from the firewall.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppliedRule {
+    /// Tag or filter name, as in [`PolicyBackend::rule_handles`]; those
+    /// of the kill switch start with `nets:kill-switch`.
+    pub handle: String,
+    /// Chain, anchor or filter id holding the rule.
+    pub location: String,
+    /// The rule as the firewall lists it.
+    pub rule: String,
 }
 
 pub trait PolicyBackend {
     fn apply(&self, decision: &QuarantineDecision) -> Result<()>;
     fn rollback(&self, decision: &QuarantineDecision) -> Result<()>;
+
+    /// Recorded with persisted quarantines, e.g. `nftables`.
+    fn name(&self) -> &'static str;
+
+    /// The rules or commands `apply` would install for `decision`, as if
+    /// none were in place yet, without touching the firewall.
+    fn plan(&self, decision: &QuarantineDecision) -> Result<Vec<String>>;
+
+    /// Handles (tags, filter names) of the rules `apply` installs for
+    /// `decision`.
+    fn rule_handles(&self, _decision: &QuarantineDecision) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Blocks all outbound traffic except what `switch` allows, replacing
+    /// the allowlist if the kill switch is already engaged.
+    fn engage_kill_switch(&self, _switch: &KillSwitch) -> Result<()> {
+        Err(anyhow!("the {} backend has no kill switch", self.name()))
+    }
+
+    /// Lifts the kill switch in one call; a no-op when it is not engaged.
+    fn release_kill_switch(&self) -> Result<()> {
+        Err(anyhow!("the {} backend has no kill switch", self.name()))
+    }
+
+    /// Every rule nets has in the firewall, quarantines and kill switch
+    /// alike, read from the firewall rather than from the manager's state,
+    /// so leftovers of a crash show up too.
+    fn list_active(&self) -> Result<Vec<AppliedRule>> {
+        Ok(Vec::new())
+    }
+
+    /// Removes every rule nets applied, kill switch included, whether a
+    /// quarantine still refers to it or not.
+    fn rollback_all(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Stops `pid` without terminating it, for decisions with `suspend`.
+    fn suspend_process(&self, pid: i32) -> Result<()> {
+        process::suspend_process(pid)
+    }
+
+    fn resume_process(&self, pid: i32) -> Result<()> {
+        process::resume_process(pid)
+    }
 }
 
 #[derive(Default)]
 pub struct NoopBackend;
 
 impl PolicyBackend for NoopBackend {
+    fn name(&self) -> &'static str {
+        "noop"
+    }
+
     fn apply(&self, decision: &QuarantineDecision) -> Result<()> {
         info!(?decision, "noop quarantine apply");
         Ok(())
@@ -37,23 +179,227 @@ impl PolicyBackend for NoopBackend {
         info!(?decision, "noop quarantine rollback");
         Ok(())
     }
+
+    fn plan(&self, _decision: &QuarantineDecision) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn engage_kill_switch(&self, switch: &KillSwitch) -> Result<()> {
+        info!(allow = ?switch.allow, "noop kill switch engaged");
+        Ok(())
+    }
+
+    fn release_kill_switch(&self) -> Result<()> {
+        info!("noop kill switch released");
+        Ok(())
+    }
+
+    fn rollback_all(&self) -> Result<()> {
+        info!("noop rollback of all rules");
+        Ok(())
+    }
+
+    fn suspend_process(&self, pid: i32) -> Result<()> {
+        info!(pid, "noop process suspend");
+        Ok(())
+    }
+
+    fn resume_process(&self, pid: i32) -> Result<()> {
+        info!(pid, "noop process resume");
+        Ok(())
+    }
+}
+
+/// Backend enforcing quarantines on this host: nftables, or iptables on
+/// distributions without it, on Linux; the Windows Filtering Platform on
+/// Windows; a pf anchor on macOS. Falls back to [`NoopBackend`] where no supported firewall is
+/// found.
+pub fn default_backend() -> Box<dyn PolicyBackend + Send + Sync> {
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(backend) = linux::detect_backend() {
+            return backend;
+        }
+    }
+    #[cfg(target_os = "macos")]
+    {
+        if let Some(backend) = macos::detect_backend() {
+            return backend;
+        }
+    }
+    #[cfg(target_os = "windows")]
+    {
+        if let Some(backend) = windows::detect_backend() {
+            return backend;
+        }
+    }
+    tracing::warn!("no supported firewall found; quarantines will not be enforced");
+    Box::new(NoopBackend)
 }
 
 pub fn recommend_quarantine(alert: &Alert, flow: &FlowEvent) -> Option<QuarantineDecision> {
-    if alert.severity == Severity::High {
-        Some(QuarantineDecision {
-            process: flow.process.as_ref().and_then(|p| p.name.clone()),
-            ports: vec![flow.dst_port],
-            expires_in_seconds: 600,
-        })
+    (alert.severity == Severity::High).then(|| quarantine_decision_for(flow, 600))
+}
+
+/// Quarantine of the program behind `flow` or, when it cannot be matched,
+/// of the flow's destination port.
+pub fn quarantine_decision_for(flow: &FlowEvent, expires_in_seconds: u64) -> QuarantineDecision {
+    let identity = flow.process.clone();
+    // A blocked port is easily swapped for another; cut off the whole
+    // program when it can be matched.
+    let ports = if identity.as_ref().is_some_and(is_targetable) {
+        Vec::new()
     } else {
-        None
+        vec![flow.dst_port]
+    };
+    QuarantineDecision {
+        process: flow.process.as_ref().and_then(|p| p.name.clone()),
+        identity,
+        ports,
+        expires_in_seconds,
+        suspend: false,
+        direction: Direction::Both,
+        remote_networks: Vec::new(),
+        remote_domains: Vec::new(),
+    }
+}
+
+/// Checks that `decision` targets something and passes the installed
+/// [`Guardrails`]; a refusal by the latter is a [`GuardrailViolation`]
+/// inside the error. Returns the remote peers the rules are limited to,
+/// with `remote_domains` resolved; empty when they are not.
+pub fn validate_decision(decision: &QuarantineDecision) -> Result<Vec<IpNetwork>> {
+    if decision.ports.is_empty()
+        && !decision.identity.as_ref().is_some_and(is_targetable)
+        && !decision.is_remote_scoped()
+    {
+        return Err(anyhow!(
+            "quarantine must target at least one port, remote network or identified process"
+        ));
+    }
+    if decision.suspend && decision.identity.as_ref().is_none_or(|p| p.pid <= 0) {
+        return Err(anyhow!("suspending needs the pid of the process"));
+    }
+    let mut remotes = decision.remote_networks.clone();
+    for domain in &decision.remote_domains {
+        remotes.extend(network::resolve_domain(domain)?);
+    }
+    remotes.sort_unstable();
+    remotes.dedup();
+    Guardrails::current().check_remotes(decision, &remotes)?;
+    Ok(remotes)
+}
+
+/// Whether a backend can match the process: by executable, or through the
+/// pid by its cgroup or owner.
+pub fn is_targetable(identity: &ProcessIdentity) -> bool {
+    identity.exe_path.is_some() || identity.pid > 0
+}
+
+/// A drop rule of a decision, for one protocol and a port or all of them.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TaggedRule {
+    pub proto: &'static str,
+    pub port: Option<u16>,
+    /// `nets:<proto>/<port or *>`, then the [`flow_label`] of the decision
+    /// and `@<process>` when the rule only matches the decision's process.
+    pub tag: String,
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+impl TaggedRule {
+    pub fn is_scoped(&self) -> bool {
+        self.tag.contains('@')
     }
 }
 
-pub fn validate_decision(decision: &QuarantineDecision) -> Result<()> {
-    if decision.ports.is_empty() {
-        return Err(anyhow!("quarantine must target at least one port"));
+/// One drop rule per protocol and port of a decision, tagged so it can be
+/// found again. Rules of a decision with a targetable process are scoped to
+/// it, so the backend must be able to match it. Decisions are keyed by
+/// their ports and process, so two decisions for the same port and process
+/// share a rule and rolling back either lifts it, unless their direction
+/// or remote peers differ.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub(crate) fn tagged_rules(decision: &QuarantineDecision) -> Vec<TaggedRule> {
+    let scope = decision
+        .identity
+        .as_ref()
+        .filter(|identity| is_targetable(identity))
+        .map(scope_label);
+    if scope.is_none() && decision.process.is_some() {
+        tracing::debug!(
+            process = ?decision.process,
+            "port quarantine applies to every process"
+        );
     }
-    Ok(())
+    let mut ports: Vec<Option<u16>> = decision.ports.iter().copied().map(Some).collect();
+    ports.sort_unstable();
+    ports.dedup();
+    if ports.is_empty() && (scope.is_some() || decision.is_remote_scoped()) {
+        ports.push(None);
+    }
+    let flow = flow_label(decision);
+    let suffix = scope.map(|scope| format!("@{scope}")).unwrap_or_default();
+    ports
+        .into_iter()
+        .flat_map(|port| {
+            let target = port.map_or_else(|| "*".to_string(), |port| port.to_string());
+            ["tcp", "udp"].map(|proto| TaggedRule {
+                proto,
+                port,
+                tag: format!("nets:{proto}/{target}{flow}{suffix}"),
+            })
+        })
+        .collect()
+}
+
+/// Names the process a rule is scoped to: `pid<pid>`, or `exe<hash>` of
+/// the executable path without one. Built from the decision alone, so a
+/// rollback finds the rules after the process has exited; short enough for
+/// the comment and label limits of nftables and pf.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn scope_label(identity: &ProcessIdentity) -> String {
+    if identity.pid > 0 {
+        return format!("pid{}", identity.pid);
+    }
+    let path = identity.exe_path.as_deref().unwrap_or_default();
+    format!("exe{:016x}", fnv1a(path.as_bytes()))
+}
+
+/// What tells rules of a decision with a direction or remote peers apart
+/// from the plain ones: `:in` or `:out`, then `~<hash>` of the configured
+/// networks and domains. Empty for decisions without either, whose tags
+/// predate both. Built from the decision, not the resolved addresses, so
+/// a rollback finds the rules after a domain has moved.
+pub(crate) fn flow_label(decision: &QuarantineDecision) -> String {
+    let mut label = match decision.direction {
+        Direction::Inbound => ":in".to_string(),
+        Direction::Outbound => ":out".to_string(),
+        Direction::Both => String::new(),
+    };
+    if decision.is_remote_scoped() {
+        let mut peers: Vec<String> = decision
+            .remote_networks
+            .iter()
+            .map(ToString::to_string)
+            .chain(
+                decision
+                    .remote_domains
+                    .iter()
+                    .map(|d| d.to_ascii_lowercase()),
+            )
+            .collect();
+        peers.sort_unstable();
+        peers.dedup();
+        label.push_str(&format!("~{:016x}", fnv1a(peers.join(",").as_bytes())));
+    }
+    label
+}
+
+/// FNV-1a: stable across releases, unlike the std hasher.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
 }
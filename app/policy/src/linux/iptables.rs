@@ -0,0 +1,441 @@
+use anyhow::Result;
+
+use super::{rule_paths, ProcessMatch, RulePath};
+use crate::command::{CommandRunner, SystemRunner};
+use crate::kill_switch::KILL_SWITCH_TAG;
+use crate::{
+    tagged_rules, validate_decision, AppliedRule, Direction, IpNetwork, KillSwitch, PolicyBackend,
+    QuarantineDecision, TaggedRule,
+};
+
+/// Chain holding the port quarantine rules, jumped to from INPUT and OUTPUT.
+const CHAIN: &str = "NETS_QUARANTINE";
+/// Chain holding one-way rules and those limited to remote peers, whose
+/// peer is the source address of incoming packets.
+const INPUT_CHAIN: &str = "NETS_QUARANTINE_IN";
+/// Chain holding rules scoped to a process, and the outbound side of the
+/// one-way and peer-limited ones. The cgroup and owner matches are refused
+/// in any chain reachable from INPUT, so it hangs off OUTPUT.
+const OUTPUT_CHAIN: &str = "NETS_QUARANTINE_OUT";
+const CHAINS: [(&str, &[&str]); 3] = [
+    (CHAIN, &["INPUT", "OUTPUT"]),
+    (INPUT_CHAIN, &["INPUT"]),
+    (OUTPUT_CHAIN, &["OUTPUT"]),
+];
+/// Chain of the kill switch, jumped to first from OUTPUT.
+const KILL_SWITCH_CHAIN: &str = "NETS_KILL_SWITCH";
+
+/// Quarantine through iptables and ip6tables, for distributions without
+/// nftables. Rules live in the `NETS_QUARANTINE` chain of the filter table
+/// and drop traffic to the quarantined ports in both directions; rules for
+/// one process, one direction or some remote peers live in
+/// `NETS_QUARANTINE_IN` and `NETS_QUARANTINE_OUT`.
+pub struct IptablesBackend {
+    runner: Box<dyn CommandRunner>,
+    /// `iptables`, plus `ip6tables` when the host has it.
+    tools: Vec<&'static str>,
+}
+
+impl IptablesBackend {
+    pub fn new() -> Self {
+        Self::with_runner(Box::new(SystemRunner))
+    }
+
+    pub fn with_runner(runner: Box<dyn CommandRunner>) -> Self {
+        let mut tools = vec!["iptables"];
+        if runner.run("ip6tables", &["--version"]).is_ok() {
+            tools.push("ip6tables");
+        } else {
+            tracing::warn!("ip6tables not found; IPv6 traffic will not be quarantined");
+        }
+        Self { runner, tools }
+    }
+
+    /// Runs one `iptables` invocation, waiting for the xtables lock.
+    fn run(&self, tool: &str, args: &[&str]) -> Result<String> {
+        let args: Vec<&str> = ["-w"].iter().chain(args).copied().collect();
+        self.runner.run(tool, &args)
+    }
+
+    fn ensure_chains(&self, tool: &str) -> Result<()> {
+        for (chain, hooks) in CHAINS {
+            if self.run(tool, &["-n", "-L", chain]).is_err() {
+                self.run(tool, &["-N", chain])?;
+            }
+            for hook in hooks {
+                if self.run(tool, &["-C", hook, "-j", chain]).is_err() {
+                    self.run(tool, &["-I", hook, "1", "-j", chain])?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes the rules of `chain` carrying one of `tags`. Their match
+    /// cannot be rebuilt once the process is gone or a domain has moved, so
+    /// the rules are replayed from `-S` with `-A` turned into `-D`.
+    fn delete_tagged_rules(&self, tool: &str, chain: &str, tags: &[&str]) -> Result<()> {
+        let Ok(listing) = self.run(tool, &["-S", chain]) else {
+            return Ok(());
+        };
+        for line in listing.lines() {
+            let args: Vec<&str> = line
+                .split_whitespace()
+                .map(|arg| arg.trim_matches('"'))
+                .collect();
+            let tagged = args
+                .windows(2)
+                .any(|pair| pair[0] == "--comment" && tags.contains(&pair[1]));
+            if tagged && args.first() == Some(&"-A") {
+                let delete: Vec<&str> = ["-D"].iter().chain(&args[1..]).copied().collect();
+                self.run(tool, &delete)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl IptablesBackend {
+    /// The kill switch rules for one address family, in order.
+    fn kill_switch_rules(tool: &str, switch: &KillSwitch) -> Vec<Vec<String>> {
+        let (dhcp, addrs): (&str, Vec<_>) = if tool == "ip6tables" {
+            ("547", switch.ipv6().collect())
+        } else {
+            ("67", switch.ipv4().collect())
+        };
+        let mut rules = vec![
+            vec!["-o".to_string(), "lo".into(), "-j".into(), "ACCEPT".into()],
+            ["-p", "udp", "--dport", dhcp, "-j", "ACCEPT"]
+                .map(String::from)
+                .to_vec(),
+        ];
+        rules.extend(
+            addrs
+                .into_iter()
+                .map(|addr| vec!["-d".into(), addr.to_string(), "-j".into(), "ACCEPT".into()]),
+        );
+        rules.push(
+            ["-m", "comment", "--comment", KILL_SWITCH_TAG, "-j", "DROP"]
+                .map(String::from)
+                .to_vec(),
+        );
+        rules
+    }
+}
+
+impl Default for IptablesBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Match and target of `rule` on `path`, limited to the process when it is
+/// scoped and to `remote` when given.
+fn rule_spec(
+    process: Option<&ProcessMatch>,
+    rule: &TaggedRule,
+    path: RulePath,
+    remote: Option<&IpNetwork>,
+) -> Vec<String> {
+    let mut spec: Vec<String> = Vec::new();
+    if let Some(remote) = remote {
+        let peer = if path.input { "-s" } else { "-d" };
+        spec.extend([peer.into(), remote.to_string()]);
+    }
+    spec.extend(["-p".into(), rule.proto.into()]);
+    if let Some(port) = rule.port {
+        let side = if path.source_port {
+            "--sport"
+        } else {
+            "--dport"
+        };
+        spec.extend([side.into(), port.to_string()]);
+    }
+    match process {
+        Some(ProcessMatch::Cgroup(path)) => {
+            spec.extend(["-m", "cgroup", "--path", path].map(String::from))
+        }
+        Some(ProcessMatch::Uid(user)) => {
+            spec.extend(["-m", "owner", "--uid-owner", user].map(String::from))
+        }
+        None => {}
+    }
+    spec.extend(["-m", "comment", "--comment", &rule.tag, "-j", "DROP"].map(String::from));
+    spec
+}
+
+/// Whether the decision's rules go to the shared chain: unscoped, both
+/// ways and to any peer, as before the other chains existed.
+fn is_plain(decision: &QuarantineDecision, process: Option<&ProcessMatch>) -> bool {
+    process.is_none() && decision.direction == Direction::Both && !decision.is_remote_scoped()
+}
+
+/// The chains and rule specs of a decision for one address family. None at
+/// all when its peers are all of the other family.
+fn rule_specs(
+    ipv6: bool,
+    decision: &QuarantineDecision,
+    process: Option<&ProcessMatch>,
+    remotes: &[IpNetwork],
+) -> Vec<(&'static str, Vec<String>)> {
+    let rules = tagged_rules(decision);
+    if is_plain(decision, process) {
+        let path = RulePath {
+            input: false,
+            source_port: false,
+        };
+        return rules
+            .iter()
+            .map(|rule| (CHAIN, rule_spec(None, rule, path, None)))
+            .collect();
+    }
+    let remotes: Vec<Option<&IpNetwork>> = if remotes.is_empty() {
+        vec![None]
+    } else {
+        remotes
+            .iter()
+            .filter(|remote| remote.is_ipv4() != ipv6)
+            .map(Some)
+            .collect()
+    };
+    let mut specs = Vec::new();
+    for path in rule_paths(decision, process.is_some()) {
+        let chain = if path.input {
+            INPUT_CHAIN
+        } else {
+            OUTPUT_CHAIN
+        };
+        for rule in &rules {
+            for remote in &remotes {
+                specs.push((chain, rule_spec(process, rule, path, *remote)));
+            }
+        }
+    }
+    specs
+}
+
+impl PolicyBackend for IptablesBackend {
+    fn apply(&self, decision: &QuarantineDecision) -> Result<()> {
+        let remotes = validate_decision(decision)?;
+        let process = ProcessMatch::for_decision(decision)?;
+        for tool in &self.tools {
+            self.ensure_chains(tool)?;
+            let ipv6 = *tool == "ip6tables";
+            for (chain, spec) in rule_specs(ipv6, decision, process.as_ref(), &remotes) {
+                let spec = spec.iter().map(String::as_str);
+                let check: Vec<&str> = ["-C", chain].into_iter().chain(spec.clone()).collect();
+                if self.run(tool, &check).is_err() {
+                    let append: Vec<&str> = ["-A", chain].into_iter().chain(spec).collect();
+                    self.run(tool, &append)?;
+                }
+            }
+        }
+        tracing::info!(ports = ?decision.ports, "iptables quarantine applied");
+        Ok(())
+    }
+
+    fn rollback(&self, decision: &QuarantineDecision) -> Result<()> {
+        let rules = tagged_rules(decision);
+        let plain = !rules.iter().any(TaggedRule::is_scoped) && is_plain(decision, None);
+        for tool in &self.tools {
+            if !plain {
+                let tags: Vec<&str> = rules.iter().map(|rule| rule.tag.as_str()).collect();
+                for chain in [INPUT_CHAIN, OUTPUT_CHAIN] {
+                    self.delete_tagged_rules(tool, chain, &tags)?;
+                }
+                continue;
+            }
+            for (_, spec) in rule_specs(*tool == "ip6tables", decision, None, &[]) {
+                let spec = spec.iter().map(String::as_str);
+                let check: Vec<&str> = ["-C", CHAIN].into_iter().chain(spec.clone()).collect();
+                if self.run(tool, &check).is_ok() {
+                    let delete: Vec<&str> = ["-D", CHAIN].into_iter().chain(spec).collect();
+                    self.run(tool, &delete)?;
+                }
+            }
+        }
+        tracing::info!(ports = ?decision.ports, "iptables quarantine rolled back");
+        Ok(())
+    }
+
+    fn plan(&self, decision: &QuarantineDecision) -> Result<Vec<String>> {
+        let remotes = validate_decision(decision)?;
+        let process = ProcessMatch::for_decision(decision)?;
+        let process = process.as_ref();
+        Ok(self
+            .tools
+            .iter()
+            .flat_map(|tool| {
+                rule_specs(*tool == "ip6tables", decision, process, &remotes)
+                    .into_iter()
+                    .map(move |(chain, spec)| format!("{tool} -w -A {chain} {}", spec.join(" ")))
+            })
+            .collect())
+    }
+
+    fn name(&self) -> &'static str {
+        "iptables"
+    }
+
+    /// The chain is refilled before OUTPUT jumps to it, so a half-built
+    /// allowlist never takes effect.
+    fn engage_kill_switch(&self, switch: &KillSwitch) -> Result<()> {
+        for tool in &self.tools {
+            if self.run(tool, &["-n", "-L", KILL_SWITCH_CHAIN]).is_err() {
+                self.run(tool, &["-N", KILL_SWITCH_CHAIN])?;
+            }
+            self.run(tool, &["-F", KILL_SWITCH_CHAIN])?;
+            for rule in Self::kill_switch_rules(tool, switch) {
+                let append: Vec<&str> = ["-A", KILL_SWITCH_CHAIN]
+                    .into_iter()
+                    .chain(rule.iter().map(String::as_str))
+                    .collect();
+                self.run(tool, &append)?;
+            }
+            if self
+                .run(tool, &["-C", "OUTPUT", "-j", KILL_SWITCH_CHAIN])
+                .is_err()
+            {
+                self.run(tool, &["-I", "OUTPUT", "1", "-j", KILL_SWITCH_CHAIN])?;
+            }
+        }
+        tracing::warn!(allow = ?switch.allow, "iptables kill switch engaged");
+        Ok(())
+    }
+
+    fn release_kill_switch(&self) -> Result<()> {
+        for tool in &self.tools {
+            while self
+                .run(tool, &["-C", "OUTPUT", "-j", KILL_SWITCH_CHAIN])
+                .is_ok()
+            {
+                self.run(tool, &["-D", "OUTPUT", "-j", KILL_SWITCH_CHAIN])?;
+            }
+            if self.run(tool, &["-n", "-L", KILL_SWITCH_CHAIN]).is_ok() {
+                self.run(tool, &["-F", KILL_SWITCH_CHAIN])?;
+                self.run(tool, &["-X", KILL_SWITCH_CHAIN])?;
+            }
+        }
+        tracing::warn!("iptables kill switch released");
+        Ok(())
+    }
+
+    fn rule_handles(&self, decision: &QuarantineDecision) -> Vec<String> {
+        tagged_rules(decision)
+            .into_iter()
+            .map(|rule| rule.tag)
+            .collect()
+    }
+
+    fn list_active(&self) -> Result<Vec<AppliedRule>> {
+        let mut applied = Vec::new();
+        for tool in &self.tools {
+            for chain in [CHAIN, INPUT_CHAIN, OUTPUT_CHAIN, KILL_SWITCH_CHAIN] {
+                let Ok(listing) = self.run(tool, &["-S", chain]) else {
+                    continue;
+                };
+                applied.extend(listing.lines().filter_map(|line| {
+                    let (_, tag) = line.split_once("--comment ")?;
+                    let tag = tag.split_whitespace().next()?.trim_matches('"');
+                    Some(AppliedRule {
+                        handle: tag.to_string(),
+                        location: format!("{tool} {chain}"),
+                        rule: line.to_string(),
+                    })
+                }));
+            }
+        }
+        Ok(applied)
+    }
+
+    /// Unhooks and deletes the nets chains.
+    fn rollback_all(&self) -> Result<()> {
+        self.release_kill_switch()?;
+        for tool in &self.tools {
+            for (chain, hooks) in CHAINS {
+                for hook in hooks {
+                    while self.run(tool, &["-C", hook, "-j", chain]).is_ok() {
+                        self.run(tool, &["-D", hook, "-j", chain])?;
+                    }
+                }
+                if self.run(tool, &["-n", "-L", chain]).is_ok() {
+                    self.run(tool, &["-F", chain])?;
+                    self.run(tool, &["-X", chain])?;
+                }
+            }
+        }
+        tracing::warn!("iptables rules of nets removed");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use anyhow::anyhow;
+
+    use super::*;
+    use crate::command::ScriptedRunner;
+
+    #[test]
+    fn applies_and_rolls_back_rules_for_both_families() {
+        let applied = Arc::new(AtomicBool::new(false));
+        let state = applied.clone();
+        // Nothing exists before the first apply; the rules do afterwards.
+        let runner = Arc::new(ScriptedRunner::new(move |line| {
+            let exists = state.load(Ordering::SeqCst);
+            if line.contains(" -C NETS_QUARANTINE ") && !exists
+                || line.contains(" -L ")
+                || line.contains(" -C INPUT ") && !exists
+            {
+                return Err(anyhow!("no such rule"));
+            }
+            Ok(String::new())
+        }));
+        let backend = IptablesBackend::with_runner(Box::new(runner.clone()));
+        let decision = QuarantineDecision {
+            process: Some("python3".into()),
+            identity: None,
+            ports: vec![8080, 8080],
+            expires_in_seconds: 600,
+            suspend: false,
+            direction: Direction::Both,
+            remote_networks: Vec::new(),
+            remote_domains: Vec::new(),
+        };
+        backend.apply(&decision).unwrap();
+        let calls = runner.calls();
+        for tool in ["iptables", "ip6tables"] {
+            assert!(calls.contains(&format!("{tool} -w -N NETS_QUARANTINE")));
+            assert!(calls.contains(&format!("{tool} -w -I INPUT 1 -j NETS_QUARANTINE")));
+            assert!(calls.contains(&format!(
+                "{tool} -w -A NETS_QUARANTINE -p udp --dport 8080 -m comment --comment nets:udp/8080 -j DROP"
+            )));
+        }
+        // Duplicate ports collapse into one rule per protocol and family.
+        assert_eq!(calls.iter().filter(|c| c.contains(" -A ")).count(), 4);
+
+        applied.store(true, Ordering::SeqCst);
+        backend.rollback(&decision).unwrap();
+        let deletes: Vec<_> = runner
+            .calls()
+            .into_iter()
+            .filter(|c| c.contains(" -D "))
+            .collect();
+        assert_eq!(deletes.len(), 4);
+        assert!(deletes.contains(
+            &"ip6tables -w -D NETS_QUARANTINE -p tcp --dport 8080 -m comment --comment nets:tcp/8080 -j DROP"
+                .to_string()
+        ));
+        assert!(backend
+            .apply(&QuarantineDecision {
+                ports: vec![],
+                ..decision
+            })
+            .is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//! Linux firewall backends: nftables where available, iptables/ip6tables
+//! on older distributions.
+
+mod iptables;
+mod nftables;
+
+pub use iptables::IptablesBackend;
+pub use nftables::NftablesBackend;
+
+use anyhow::{anyhow, Result};
+use collector::ProcessIdentity;
+use serde::{Deserialize, Serialize};
+
+use crate::command::{CommandRunner, SystemRunner};
+use crate::{is_targetable, PolicyBackend, QuarantineDecision};
+
+/// Packet-filter framework found on the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Firewall {
+    Nftables,
+    Iptables,
+}
+
+impl Firewall {
+    /// The framework whose tool runs here, preferring nftables. Distributions
+    /// that ship iptables as a front-end to nftables (`iptables-nft`) but no
+    /// `nft` binary get the iptables backend, which works there too.
+    pub fn detect(runner: &dyn CommandRunner) -> Option<Firewall> {
+        if runner.run("nft", &["--version"]).is_ok() {
+            Some(Firewall::Nftables)
+        } else if runner.run("iptables", &["--version"]).is_ok() {
+            Some(Firewall::Iptables)
+        } else {
+            None
+        }
+    }
+}
+
+pub(crate) fn detect_backend() -> Option<Box<dyn PolicyBackend + Send + Sync>> {
+    let firewall = Firewall::detect(&SystemRunner)?;
+    tracing::info!(?firewall, "quarantine firewall backend selected");
+    Some(match firewall {
+        Firewall::Nftables => Box::new(NftablesBackend::new()),
+        Firewall::Iptables => Box::new(IptablesBackend::new()),
+    })
+}
+
+/// How the Linux backends tell a quarantined process's packets apart. Both
+/// matches look at the owning socket, which is known for locally generated
+/// packets only, so scoped rules sit on the output path; inbound traffic is
+/// cut off there by dropping the server's replies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ProcessMatch {
+    /// cgroup v2 path of the process, relative to the hierarchy root: its
+    /// systemd service, or the scope an application was started in.
+    Cgroup(String),
+    /// Owner of the process; a user name or uid.
+    Uid(String),
+}
+
+impl ProcessMatch {
+    /// The match for a decision's process, `None` when its rules are not
+    /// scoped. A targetable process that cannot be matched is an error:
+    /// widening the block to every process would not be what was asked for.
+    pub(crate) fn for_decision(decision: &QuarantineDecision) -> Result<Option<Self>> {
+        let Some(identity) = decision.identity.as_ref().filter(|id| is_targetable(id)) else {
+            return Ok(None);
+        };
+        Self::resolve(identity).map(Some).ok_or_else(|| {
+            anyhow!(
+                "cannot match process {} (pid {}): no cgroup or owner known",
+                identity
+                    .exe_path
+                    .as_deref()
+                    .or(identity.name.as_deref())
+                    .unwrap_or("?"),
+                identity.pid
+            )
+        })
+    }
+
+    fn resolve(identity: &ProcessIdentity) -> Option<Self> {
+        let cgroup = (identity.pid > 0)
+            .then(|| std::fs::read_to_string(format!("/proc/{}/cgroup", identity.pid)).ok())
+            .flatten()
+            .and_then(|contents| cgroup_path(&contents));
+        if let Some(path) = cgroup {
+            return Some(Self::Cgroup(path));
+        }
+        // Blocking root would cut off the host.
+        identity
+            .user
+            .clone()
+            .filter(|user| user != "root" && user != "0")
+            .map(Self::Uid)
+    }
+}
+
+/// A packet path a decision's rules are installed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RulePath {
+    /// Packets reaching the host rather than leaving it; the remote peer is
+    /// their source.
+    pub input: bool,
+    /// The decision's port is the packet's source port: replies of a
+    /// quarantined server, on the output path.
+    pub source_port: bool,
+}
+
+/// Paths covering the decision's direction. Inbound connections are
+/// matched on input, or by their replies when the rules are scoped to a
+/// process; outbound ones on output by their destination port.
+pub(crate) fn rule_paths(decision: &QuarantineDecision, scoped: bool) -> Vec<RulePath> {
+    let mut paths = Vec::new();
+    if decision.direction.inbound() {
+        paths.push(RulePath {
+            input: !scoped,
+            source_port: scoped && !decision.ports.is_empty(),
+        });
+    }
+    if decision.direction.outbound() {
+        paths.push(RulePath {
+            input: false,
+            source_port: false,
+        });
+    }
+    // A whole-program block has no port to tell replies apart by.
+    paths.dedup();
+    paths
+}
+
+/// The cgroup v2 path in a `/proc/<pid>/cgroup` file, without the leading
+/// slash. `None` for the root cgroup, which every process falls back to.
+fn cgroup_path(contents: &str) -> Option<String> {
+    contents
+        .lines()
+        .find_map(|line| line.strip_prefix("0::/"))
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::ScriptedRunner;
+    use crate::{tagged_rules, Direction};
+
+    #[test]
+    fn scopes_rules_to_the_process_cgroup() {
+        let cgroup = "0::/user.slice/user-1000.slice/app-firefox.scope\n";
+        assert_eq!(
+            cgroup_path(cgroup).as_deref(),
+            Some("user.slice/user-1000.slice/app-firefox.scope")
+        );
+        assert_eq!(cgroup_path("0::/\n"), None);
+        assert_eq!(cgroup_path("12:pids:/user.slice\n"), None);
+
+        let decision = QuarantineDecision {
+            process: Some("agent".into()),
+            identity: Some(ProcessIdentity {
+                pid: i32::MAX,
+                ppid: None,
+                name: Some("agent".into()),
+                exe_path: None,
+                sha256_16: None,
+                user: Some("alice".into()),
+                signed: None,
+            }),
+            ports: vec![],
+            expires_in_seconds: 60,
+            suspend: false,
+            direction: Direction::Both,
+            remote_networks: Vec::new(),
+            remote_domains: Vec::new(),
+        };
+        // No such pid: falls back to the owner, and blocks every port.
+        assert_eq!(
+            ProcessMatch::for_decision(&decision).unwrap(),
+            Some(ProcessMatch::Uid("alice".into()))
+        );
+        let tags: Vec<_> = tagged_rules(&decision)
+            .into_iter()
+            .map(|rule| rule.tag)
+            .collect();
+        assert_eq!(
+            tags,
+            [
+                format!("nets:tcp/*@pid{}", i32::MAX),
+                format!("nets:udp/*@pid{}", i32::MAX)
+            ]
+        );
+
+        let runner = ScriptedRunner::new(|_| Ok(String::new()));
+        let backend = NftablesBackend::with_runner(Box::new(runner));
+        let root = QuarantineDecision {
+            identity: decision.identity.clone().map(|identity| ProcessIdentity {
+                user: Some("root".into()),
+                ..identity
+            }),
+            ..decision
+        };
+        assert!(backend.apply(&root).is_err());
+    }
+}
@@ -0,0 +1,450 @@
+use anyhow::{anyhow, Result};
+
+use super::{rule_paths, ProcessMatch, RulePath};
+use crate::command::{CommandRunner, SystemRunner};
+use crate::kill_switch::KILL_SWITCH_TAG;
+use crate::{
+    tagged_rules, validate_decision, AppliedRule, IpNetwork, KillSwitch, PolicyBackend,
+    QuarantineDecision, TaggedRule,
+};
+
+const TABLE: &str = "nets";
+/// Base chains of the `inet nets` table and their hooks.
+const CHAINS: [(&str, &str); 2] = [("input", "input"), ("output", "output")];
+/// Output chain of the kill switch, evaluated before the quarantine chains.
+const KILL_SWITCH_CHAIN: &str = "kill_switch";
+
+/// Quarantine through nftables: drop rules in the `inet nets` table, which
+/// covers IPv4 and IPv6 and is left alone by other firewall managers.
+pub struct NftablesBackend {
+    runner: Box<dyn CommandRunner>,
+}
+
+impl NftablesBackend {
+    pub fn new() -> Self {
+        Self::with_runner(Box::new(SystemRunner))
+    }
+
+    pub fn with_runner(runner: Box<dyn CommandRunner>) -> Self {
+        Self { runner }
+    }
+
+    fn nft(&self, args: &[&str]) -> Result<String> {
+        self.runner.run("nft", args)
+    }
+
+    /// `add` is a no-op for a table or chain that already exists.
+    fn ensure_table(&self) -> Result<()> {
+        self.nft(&["add", "table", "inet", TABLE])?;
+        for (chain, hook) in CHAINS {
+            let spec = format!("{{ type filter hook {hook} priority 0 ; policy accept ; }}");
+            self.nft(&["add", "chain", "inet", TABLE, chain, &spec])?;
+        }
+        Ok(())
+    }
+
+    fn list_chain(&self, chain: &str) -> Result<String> {
+        self.nft(&["-a", "list", "chain", "inet", TABLE, chain])
+    }
+}
+
+impl Default for NftablesBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Handles of the rules carrying `tag` in a `nft -a list chain` listing;
+/// one per address family when the rule is limited to remote peers.
+fn rule_handles(listing: &str, tag: &str) -> Vec<u64> {
+    let comment = format!("comment \"{tag}\"");
+    listing
+        .lines()
+        .filter(|line| line.contains(&comment))
+        .filter_map(|line| line.rsplit_once("# handle ")?.1.trim().parse().ok())
+        .collect()
+}
+
+/// The tagged rules of a `nft -a list table` listing, with their chain.
+fn applied_rules(listing: &str) -> Vec<AppliedRule> {
+    let mut chain = "";
+    listing
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            if let Some(name) = line.strip_prefix("chain ") {
+                chain = name.split_whitespace().next().unwrap_or_default();
+                return None;
+            }
+            let (tag, _) = line.split_once("comment \"nets:")?.1.split_once('"')?;
+            let rule = line
+                .rsplit_once(" # handle ")
+                .map_or(line, |(rule, _)| rule);
+            Some(AppliedRule {
+                handle: format!("nets:{tag}"),
+                location: format!("inet {TABLE} {chain}"),
+                rule: rule.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// The nft rules for `rule` on `path`, limited to the process when it is
+/// scoped: one, or one per address family of `remotes`.
+fn nft_rules(
+    process: Option<&ProcessMatch>,
+    rule: &TaggedRule,
+    path: RulePath,
+    remotes: &[IpNetwork],
+) -> Vec<String> {
+    let owner = match process {
+        Some(ProcessMatch::Cgroup(path)) => {
+            format!(
+                "socket cgroupv2 level {} \"{path}\" ",
+                path.split('/').count()
+            )
+        }
+        Some(ProcessMatch::Uid(user)) => format!("meta skuid {user} "),
+        None => String::new(),
+    };
+    let traffic = match rule.port {
+        Some(port) if path.source_port => format!("{} sport {port}", rule.proto),
+        Some(port) => format!("{} dport {port}", rule.proto),
+        None => format!("meta l4proto {}", rule.proto),
+    };
+    let peer = if path.input { "saddr" } else { "daddr" };
+    let peers: Vec<String> = if remotes.is_empty() {
+        vec![String::new()]
+    } else {
+        [("ip", true), ("ip6", false)]
+            .into_iter()
+            .filter_map(|(family, ipv4)| {
+                let networks: Vec<String> = remotes
+                    .iter()
+                    .filter(|network| network.is_ipv4() == ipv4)
+                    .map(ToString::to_string)
+                    .collect();
+                (!networks.is_empty())
+                    .then(|| format!("{family} {peer} {{ {} }} ", networks.join(", ")))
+            })
+            .collect()
+    };
+    peers
+        .into_iter()
+        .map(|peers| format!("{owner}{peers}{traffic} drop comment \"{}\"", rule.tag))
+        .collect()
+}
+
+/// `nft -f` script (re)loading the kill switch chain in one transaction.
+fn kill_switch_script(switch: &KillSwitch) -> String {
+    let mut script = format!(
+        "add table inet {TABLE}\n\
+         add chain inet {TABLE} {KILL_SWITCH_CHAIN} {{ type filter hook output priority -10 ; policy accept ; }}\n\
+         flush chain inet {TABLE} {KILL_SWITCH_CHAIN}\n"
+    );
+    let rule = |rule: &str| format!("add rule inet {TABLE} {KILL_SWITCH_CHAIN} {rule}\n");
+    script.push_str(&rule("oifname \"lo\" accept"));
+    script.push_str(&rule("udp dport { 67, 547 } accept"));
+    for (family, addrs) in [
+        ("ip", switch.ipv4().collect::<Vec<_>>()),
+        ("ip6", switch.ipv6().collect()),
+    ] {
+        if !addrs.is_empty() {
+            let addrs: Vec<String> = addrs.iter().map(|addr| addr.to_string()).collect();
+            script.push_str(&rule(&format!(
+                "{family} daddr {{ {} }} accept",
+                addrs.join(", ")
+            )));
+        }
+    }
+    script.push_str(&rule(&format!("drop comment \"{KILL_SWITCH_TAG}\"")));
+    script
+}
+
+/// The base chains a decision's rules go to, with the paths on each.
+fn chains_for(
+    decision: &QuarantineDecision,
+    process: Option<&ProcessMatch>,
+) -> Vec<(&'static str, Vec<RulePath>)> {
+    let paths = rule_paths(decision, process.is_some());
+    CHAINS
+        .into_iter()
+        .filter_map(|(chain, hook)| {
+            let on_chain: Vec<RulePath> = paths
+                .iter()
+                .copied()
+                .filter(|path| path.input == (hook == "input"))
+                .collect();
+            (!on_chain.is_empty()).then_some((chain, on_chain))
+        })
+        .collect()
+}
+
+impl PolicyBackend for NftablesBackend {
+    fn apply(&self, decision: &QuarantineDecision) -> Result<()> {
+        let remotes = validate_decision(decision)?;
+        let process = ProcessMatch::for_decision(decision)?;
+        self.ensure_table()?;
+        let rules = tagged_rules(decision);
+        for (chain, paths) in chains_for(decision, process.as_ref()) {
+            let listing = self.list_chain(chain)?;
+            for rule in &rules {
+                if !rule_handles(&listing, &rule.tag).is_empty() {
+                    continue;
+                }
+                for path in &paths {
+                    for rule in nft_rules(process.as_ref(), rule, *path, &remotes) {
+                        self.nft(&["add", "rule", "inet", TABLE, chain, &rule])?;
+                    }
+                }
+            }
+        }
+        tracing::info!(ports = ?decision.ports, "nftables quarantine applied");
+        Ok(())
+    }
+
+    fn rollback(&self, decision: &QuarantineDecision) -> Result<()> {
+        self.ensure_table()?;
+        let rules = tagged_rules(decision);
+        for (chain, _) in CHAINS {
+            let listing = self.list_chain(chain)?;
+            for TaggedRule { tag, .. } in &rules {
+                for handle in rule_handles(&listing, tag) {
+                    self.nft(&[
+                        "delete",
+                        "rule",
+                        "inet",
+                        TABLE,
+                        chain,
+                        "handle",
+                        &handle.to_string(),
+                    ])
+                    .map_err(|err| anyhow!("removing rule {tag}: {err:#}"))?;
+                }
+            }
+        }
+        tracing::info!(ports = ?decision.ports, "nftables quarantine rolled back");
+        Ok(())
+    }
+
+    fn plan(&self, decision: &QuarantineDecision) -> Result<Vec<String>> {
+        let remotes = validate_decision(decision)?;
+        let process = ProcessMatch::for_decision(decision)?;
+        let process = process.as_ref();
+        let rules = tagged_rules(decision);
+        let mut plan = Vec::new();
+        for (chain, paths) in chains_for(decision, process) {
+            for rule in &rules {
+                for path in &paths {
+                    plan.extend(
+                        nft_rules(process, rule, *path, &remotes)
+                            .into_iter()
+                            .map(|rule| format!("nft add rule inet {TABLE} {chain} {rule}")),
+                    );
+                }
+            }
+        }
+        Ok(plan)
+    }
+
+    fn name(&self) -> &'static str {
+        "nftables"
+    }
+
+    /// Established connections are cut too: the chain accepts nothing by
+    /// connection state.
+    fn engage_kill_switch(&self, switch: &KillSwitch) -> Result<()> {
+        self.runner
+            .run_with_input("nft", &["-f", "-"], &kill_switch_script(switch))?;
+        tracing::warn!(allow = ?switch.allow, "nftables kill switch engaged");
+        Ok(())
+    }
+
+    fn release_kill_switch(&self) -> Result<()> {
+        if self.list_chain(KILL_SWITCH_CHAIN).is_err() {
+            return Ok(());
+        }
+        let script = format!(
+            "flush chain inet {TABLE} {KILL_SWITCH_CHAIN}\n\
+             delete chain inet {TABLE} {KILL_SWITCH_CHAIN}\n"
+        );
+        self.runner.run_with_input("nft", &["-f", "-"], &script)?;
+        tracing::warn!("nftables kill switch released");
+        Ok(())
+    }
+
+    fn rule_handles(&self, decision: &QuarantineDecision) -> Vec<String> {
+        tagged_rules(decision)
+            .into_iter()
+            .map(|rule| rule.tag)
+            .collect()
+    }
+
+    fn list_active(&self) -> Result<Vec<AppliedRule>> {
+        // No table, nothing applied.
+        Ok(self
+            .nft(&["-a", "list", "table", "inet", TABLE])
+            .map(|listing| applied_rules(&listing))
+            .unwrap_or_default())
+    }
+
+    /// The table holds nothing but nets rules, so it goes as a whole.
+    fn rollback_all(&self) -> Result<()> {
+        if self.nft(&["list", "table", "inet", TABLE]).is_ok() {
+            self.nft(&["delete", "table", "inet", TABLE])?;
+        }
+        tracing::warn!("nftables rules of nets removed");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::command::ScriptedRunner;
+    use crate::{flow_label, Direction};
+
+    const LISTING: &str = r#"table inet nets {
+	chain output { # handle 2
+		type filter hook output priority filter; policy accept;
+		tcp dport 8080 drop comment "nets:tcp/8080" # handle 5
+		udp dport 8080 drop comment "nets:udp/8080" # handle 6
+		tcp dport 443 drop comment "nets:tcp/443" # handle 7
+	}
+}
+"#;
+
+    #[test]
+    fn removes_rules_by_handle() {
+        assert_eq!(rule_handles(LISTING, "nets:udp/8080"), [6]);
+        assert!(rule_handles(LISTING, "nets:tcp/80").is_empty());
+        let applied = applied_rules(LISTING);
+        assert_eq!(applied.len(), 3);
+        assert_eq!(
+            applied[1],
+            AppliedRule {
+                handle: "nets:udp/8080".into(),
+                location: "inet nets output".into(),
+                rule: "udp dport 8080 drop comment \"nets:udp/8080\"".into(),
+            }
+        );
+
+        let runner = Arc::new(ScriptedRunner::new(|line| {
+            Ok(if line.contains("list chain inet nets output") {
+                LISTING.to_string()
+            } else {
+                String::new()
+            })
+        }));
+        let backend = NftablesBackend::with_runner(Box::new(runner.clone()));
+        let decision = QuarantineDecision {
+            process: None,
+            identity: None,
+            ports: vec![8080],
+            expires_in_seconds: 60,
+            suspend: false,
+            direction: Direction::Both,
+            remote_networks: Vec::new(),
+            remote_domains: Vec::new(),
+        };
+        backend.rollback(&decision).unwrap();
+        let deletes: Vec<_> = runner
+            .calls()
+            .into_iter()
+            .filter(|call| call.starts_with("nft delete"))
+            .collect();
+        assert_eq!(
+            deletes,
+            [
+                "nft delete rule inet nets output handle 5",
+                "nft delete rule inet nets output handle 6"
+            ]
+        );
+
+        // Applying adds only what the chain lacks: everything on input,
+        // nothing on output.
+        backend.apply(&decision).unwrap();
+        let adds: Vec<_> = runner
+            .calls()
+            .into_iter()
+            .filter(|call| call.starts_with("nft add rule"))
+            .collect();
+        assert_eq!(
+            adds,
+            [
+                "nft add rule inet nets input tcp dport 8080 drop comment \"nets:tcp/8080\"",
+                "nft add rule inet nets input udp dport 8080 drop comment \"nets:udp/8080\""
+            ]
+        );
+    }
+
+    #[test]
+    fn limits_rules_to_the_direction_and_peers() {
+        let backend =
+            NftablesBackend::with_runner(Box::new(ScriptedRunner::new(|_| Ok(String::new()))));
+        let outbound = QuarantineDecision {
+            process: None,
+            identity: None,
+            ports: vec![443],
+            expires_in_seconds: 60,
+            suspend: false,
+            direction: Direction::Outbound,
+            remote_networks: vec![
+                "203.0.113.0/24".parse().unwrap(),
+                "2001:db8::/32".parse().unwrap(),
+            ],
+            remote_domains: Vec::new(),
+        };
+        let flow = flow_label(&outbound);
+        assert!(flow.starts_with(":out~"));
+        assert_eq!(
+            backend.plan(&outbound).unwrap(),
+            [
+                format!("nft add rule inet nets output ip daddr {{ 203.0.113.0/24 }} tcp dport 443 drop comment \"nets:tcp/443{flow}\""),
+                format!("nft add rule inet nets output ip6 daddr {{ 2001:db8::/32 }} tcp dport 443 drop comment \"nets:tcp/443{flow}\""),
+                format!("nft add rule inet nets output ip daddr {{ 203.0.113.0/24 }} udp dport 443 drop comment \"nets:udp/443{flow}\""),
+                format!("nft add rule inet nets output ip6 daddr {{ 2001:db8::/32 }} udp dport 443 drop comment \"nets:udp/443{flow}\""),
+            ]
+        );
+
+        // Inbound from one network, on any local port.
+        let inbound = QuarantineDecision {
+            ports: vec![],
+            direction: Direction::Inbound,
+            remote_networks: vec!["203.0.113.0/24".parse().unwrap()],
+            ..outbound
+        };
+        let flow = flow_label(&inbound);
+        assert_eq!(
+            backend.plan(&inbound).unwrap()[0],
+            format!("nft add rule inet nets input ip saddr {{ 203.0.113.0/24 }} meta l4proto tcp drop comment \"nets:tcp/*{flow}\"")
+        );
+    }
+
+    #[test]
+    fn loads_the_kill_switch_in_one_transaction() {
+        let runner = Arc::new(ScriptedRunner::new(|_| Ok(String::new())));
+        let backend = NftablesBackend::with_runner(Box::new(runner.clone()));
+        let switch = KillSwitch {
+            allow: vec!["192.168.1.1".parse().unwrap(), "fe80::1".parse().unwrap()],
+        };
+        backend.engage_kill_switch(&switch).unwrap();
+        backend.release_kill_switch().unwrap();
+        let calls = runner.calls();
+        assert_eq!(calls.len(), 3);
+        let engage: Vec<&str> = calls[0].lines().collect();
+        assert_eq!(engage[0], "nft -f -");
+        assert_eq!(
+            engage[6..],
+            [
+                "add rule inet nets kill_switch ip daddr { 192.168.1.1 } accept",
+                "add rule inet nets kill_switch ip6 daddr { fe80::1 } accept",
+                "add rule inet nets kill_switch drop comment \"nets:kill-switch\"",
+            ]
+        );
+        assert_eq!(calls[1], "nft -a list chain inet nets kill_switch");
+        assert!(calls[2].ends_with("delete chain inet nets kill_switch\n"));
+    }
+}
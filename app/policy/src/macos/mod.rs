@@ -0,0 +1,418 @@
+//! macOS firewall backend: pf rules kept in an anchor of their own.
+
+use anyhow::{anyhow, Result};
+
+use crate::command::{CommandRunner, SystemRunner};
+use crate::kill_switch::KILL_SWITCH_TAG;
+use crate::{
+    tagged_rules, validate_decision, AppliedRule, Direction, IpNetwork, KillSwitch, PolicyBackend,
+    QuarantineDecision, TaggedRule,
+};
+
+/// The stock `/etc/pf.conf` evaluates `anchor "com.apple/*"`, so rules in a
+/// sub-anchor there take effect without editing the main ruleset.
+const ANCHOR: &str = "com.apple/nets";
+/// Sibling anchor of the kill switch, so engaging and releasing it never
+/// rewrites the quarantine rules.
+const KILL_SWITCH_ANCHOR: &str = "com.apple/nets-kill-switch";
+
+pub(crate) fn detect_backend() -> Option<Box<dyn PolicyBackend + Send + Sync>> {
+    let backend = PfBackend::new();
+    if let Err(err) = backend.pfctl(&["-s", "info"]) {
+        tracing::warn!("pf unavailable: {err:#}");
+        return None;
+    }
+    tracing::info!(anchor = ANCHOR, "quarantine firewall backend selected: pf");
+    Some(Box::new(backend))
+}
+
+/// Quarantine through pf. The anchor's ruleset is always loaded whole with
+/// `pfctl -f`, which swaps it atomically, so applying and rolling back
+/// rewrite it from the rules currently loaded. Rules scoped to a process
+/// match the user owning its sockets, the closest pf gets to a program.
+pub struct PfBackend {
+    runner: Box<dyn CommandRunner>,
+}
+
+impl PfBackend {
+    pub fn new() -> Self {
+        Self::with_runner(Box::new(SystemRunner))
+    }
+
+    pub fn with_runner(runner: Box<dyn CommandRunner>) -> Self {
+        Self { runner }
+    }
+
+    fn pfctl(&self, args: &[&str]) -> Result<String> {
+        self.runner.run("pfctl", args)
+    }
+
+    /// Tags (`nets:<proto>/<port>[@<process>]`) of the rules loaded in the
+    /// anchor.
+    pub fn list(&self) -> Result<Vec<String>> {
+        Ok(self.loaded()?.into_iter().map(|(tag, _)| tag).collect())
+    }
+
+    /// Tag and text of the loaded rules; `pfctl -s rules` prints them in a
+    /// form it loads back.
+    fn loaded(&self) -> Result<Vec<(String, String)>> {
+        Ok(tagged_lines(&self.pfctl(&["-a", ANCHOR, "-s", "rules"])?))
+    }
+
+    /// Owner of the decision's process, for rules scoped to it.
+    fn owner(&self, decision: &QuarantineDecision) -> Result<String> {
+        let identity = decision
+            .identity
+            .as_ref()
+            .ok_or_else(|| anyhow!("scoped quarantine without a process"))?;
+        let user = match &identity.user {
+            Some(user) => user.clone(),
+            None if identity.pid > 0 => self
+                .runner
+                .run("ps", &["-o", "user=", "-p", &identity.pid.to_string()])?
+                .trim()
+                .to_string(),
+            None => String::new(),
+        };
+        // Blocking root would cut off the host.
+        if user.is_empty() || user == "root" || user == "0" {
+            return Err(anyhow!(
+                "cannot match process {} (pid {}) by its owner",
+                identity
+                    .exe_path
+                    .as_deref()
+                    .or(identity.name.as_deref())
+                    .unwrap_or("?"),
+                identity.pid
+            ));
+        }
+        Ok(user)
+    }
+
+    /// Removes every quarantine rule.
+    pub fn flush(&self) -> Result<()> {
+        self.pfctl(&["-a", ANCHOR, "-F", "rules"])?;
+        tracing::info!("pf quarantine anchor flushed");
+        Ok(())
+    }
+
+    /// Rules in a disabled pf are loaded but never evaluated. pf is left
+    /// enabled afterwards; with only the stock ruleset that passes all
+    /// traffic.
+    fn ensure_enabled(&self) -> Result<()> {
+        if !self.pfctl(&["-s", "info"])?.contains("Status: Enabled") {
+            self.pfctl(&["-E"])?;
+        }
+        Ok(())
+    }
+
+    fn load(&self, rules: &[String]) -> Result<()> {
+        if rules.is_empty() {
+            return self.flush();
+        }
+        let ruleset = rules.join("\n");
+        self.runner
+            .run_with_input("pfctl", &["-a", ANCHOR, "-f", "-"], &format!("{ruleset}\n"))?;
+        Ok(())
+    }
+}
+
+impl Default for PfBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The kill switch ruleset: `quick` passes for what stays reachable, then a
+/// `quick` block of everything else going out.
+fn kill_switch_rules(switch: &KillSwitch) -> String {
+    let mut rules = vec![
+        "pass out quick on lo0 all".to_string(),
+        "pass out quick proto udp from any to any port { 67, 547 }".to_string(),
+    ];
+    if !switch.allow.is_empty() {
+        let addrs: Vec<String> = switch.allow.iter().map(|addr| addr.to_string()).collect();
+        rules.push(format!(
+            "pass out quick from any to {{ {} }}",
+            addrs.join(", ")
+        ));
+    }
+    rules.push(format!(
+        "block drop out quick all label \"{KILL_SWITCH_TAG}\""
+    ));
+    rules.join("\n") + "\n"
+}
+
+/// Label and text of the nets rules in a `pfctl -s rules` listing.
+fn tagged_lines(listing: &str) -> Vec<(String, String)> {
+    listing
+        .lines()
+        .filter_map(|line| {
+            let (tag, _) = line.split_once("label \"nets:")?.1.split_once('"')?;
+            Some((format!("nets:{tag}"), line.trim().to_string()))
+        })
+        .collect()
+}
+
+/// The block rules for `rule`. `port` matches the destination port, so
+/// one `quick` rule without a direction stops outbound connections to it
+/// and inbound ones to the local service alike; one-way decisions and
+/// those limited to `remotes` get an `in` and an `out` rule instead, with
+/// the peers as source and destination respectively.
+fn pf_rules(
+    decision: &QuarantineDecision,
+    rule: &TaggedRule,
+    owner: Option<&str>,
+    remotes: &[IpNetwork],
+) -> Vec<String> {
+    let port = rule
+        .port
+        .map(|port| format!(" port {port}"))
+        .unwrap_or_default();
+    let user = owner
+        .map(|user| format!(" user {user}"))
+        .unwrap_or_default();
+    let peers = if remotes.is_empty() {
+        "any".to_string()
+    } else {
+        let networks: Vec<String> = remotes.iter().map(ToString::to_string).collect();
+        format!("{{ {} }}", networks.join(", "))
+    };
+    let block = |direction: &str, from: &str, to: &str| {
+        format!(
+            "block drop {direction}quick proto {} from {from} to {to}{port}{user} label \"{}\"",
+            rule.proto, rule.tag
+        )
+    };
+    if decision.direction == Direction::Both && remotes.is_empty() {
+        return vec![block("", "any", "any")];
+    }
+    let mut rules = Vec::new();
+    if decision.direction.inbound() {
+        rules.push(block("in ", &peers, "any"));
+    }
+    if decision.direction.outbound() {
+        rules.push(block("out ", "any", &peers));
+    }
+    rules
+}
+
+impl PolicyBackend for PfBackend {
+    fn apply(&self, decision: &QuarantineDecision) -> Result<()> {
+        let remotes = validate_decision(decision)?;
+        let rules = tagged_rules(decision);
+        let owner = if rules.iter().any(TaggedRule::is_scoped) {
+            Some(self.owner(decision)?)
+        } else {
+            None
+        };
+        self.ensure_enabled()?;
+        let loaded = self.loaded()?;
+        let added: Vec<String> = rules
+            .iter()
+            .filter(|rule| !loaded.iter().any(|(tag, _)| *tag == rule.tag))
+            .flat_map(|rule| pf_rules(decision, rule, owner.as_deref(), &remotes))
+            .collect();
+        if !added.is_empty() {
+            let lines: Vec<String> = loaded
+                .into_iter()
+                .map(|(_, line)| line)
+                .chain(added)
+                .collect();
+            self.load(&lines)?;
+        }
+        tracing::info!(ports = ?decision.ports, "pf quarantine applied");
+        Ok(())
+    }
+
+    fn rollback(&self, decision: &QuarantineDecision) -> Result<()> {
+        let lifted: Vec<String> = tagged_rules(decision)
+            .into_iter()
+            .map(|rule| rule.tag)
+            .collect();
+        let mut loaded = self.loaded()?;
+        let before = loaded.len();
+        loaded.retain(|(tag, _)| !lifted.contains(tag));
+        if loaded.len() != before {
+            let lines: Vec<String> = loaded.into_iter().map(|(_, line)| line).collect();
+            self.load(&lines)?;
+        }
+        tracing::info!(ports = ?decision.ports, "pf quarantine rolled back");
+        Ok(())
+    }
+
+    /// The pf rules `apply` adds to the anchor.
+    fn plan(&self, decision: &QuarantineDecision) -> Result<Vec<String>> {
+        let remotes = validate_decision(decision)?;
+        let rules = tagged_rules(decision);
+        let owner = if rules.iter().any(TaggedRule::is_scoped) {
+            Some(self.owner(decision)?)
+        } else {
+            None
+        };
+        Ok(rules
+            .iter()
+            .flat_map(|rule| pf_rules(decision, rule, owner.as_deref(), &remotes))
+            .collect())
+    }
+
+    fn name(&self) -> &'static str {
+        "pf"
+    }
+
+    /// pf passes packets of existing states without evaluating rules, so
+    /// the states are killed afterwards; allowed connections reconnect.
+    fn engage_kill_switch(&self, switch: &KillSwitch) -> Result<()> {
+        self.ensure_enabled()?;
+        self.runner.run_with_input(
+            "pfctl",
+            &["-a", KILL_SWITCH_ANCHOR, "-f", "-"],
+            &kill_switch_rules(switch),
+        )?;
+        for network in ["0.0.0.0/0", "::/0"] {
+            if let Err(err) = self.pfctl(&["-k", network]) {
+                tracing::warn!(network, "killing pf states failed: {err:#}");
+            }
+        }
+        tracing::warn!(allow = ?switch.allow, "pf kill switch engaged");
+        Ok(())
+    }
+
+    fn release_kill_switch(&self) -> Result<()> {
+        self.pfctl(&["-a", KILL_SWITCH_ANCHOR, "-F", "rules"])?;
+        tracing::warn!("pf kill switch released");
+        Ok(())
+    }
+
+    fn rule_handles(&self, decision: &QuarantineDecision) -> Vec<String> {
+        tagged_rules(decision)
+            .into_iter()
+            .map(|rule| rule.tag)
+            .collect()
+    }
+
+    fn list_active(&self) -> Result<Vec<AppliedRule>> {
+        let mut applied = Vec::new();
+        for anchor in [ANCHOR, KILL_SWITCH_ANCHOR] {
+            let listing = self.pfctl(&["-a", anchor, "-s", "rules"])?;
+            applied.extend(
+                tagged_lines(&listing)
+                    .into_iter()
+                    .map(|(handle, rule)| AppliedRule {
+                        handle,
+                        location: anchor.to_string(),
+                        rule,
+                    }),
+            );
+        }
+        Ok(applied)
+    }
+
+    fn rollback_all(&self) -> Result<()> {
+        self.flush()?;
+        self.release_kill_switch()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use collector::ProcessIdentity;
+
+    use super::*;
+    use crate::command::ScriptedRunner;
+
+    const LISTING: &str = "\
+block drop quick proto tcp from any to any port = 8080 label \"nets:tcp/8080\"
+block drop quick proto udp from any to any port = 8080 label \"nets:udp/8080\"
+block drop quick proto tcp from any to any port = 443 label \"nets:tcp/443\"
+";
+
+    fn loads(runner: &ScriptedRunner) -> Vec<String> {
+        runner
+            .calls()
+            .into_iter()
+            .filter(|call| call.starts_with("pfctl -a com.apple/nets -f -"))
+            .collect()
+    }
+
+    #[test]
+    fn rewrites_the_anchor_from_loaded_tags() {
+        let tags: Vec<_> = tagged_lines(LISTING)
+            .into_iter()
+            .map(|(tag, _)| tag)
+            .collect();
+        assert_eq!(tags, ["nets:tcp/8080", "nets:udp/8080", "nets:tcp/443"]);
+
+        let runner = Arc::new(ScriptedRunner::new(|line| {
+            Ok(match line {
+                "pfctl -s info" => "Status: Disabled since 0 days\n".into(),
+                "pfctl -a com.apple/nets -s rules" => LISTING.into(),
+                "ps -o user= -p 42" => "alice\n".into(),
+                _ => String::new(),
+            })
+        }));
+        let backend = PfBackend::with_runner(Box::new(runner.clone()));
+        let decision = QuarantineDecision {
+            process: None,
+            identity: None,
+            ports: vec![1900],
+            expires_in_seconds: 60,
+            suspend: false,
+            direction: Direction::Both,
+            remote_networks: Vec::new(),
+            remote_domains: Vec::new(),
+        };
+        backend.apply(&decision).unwrap();
+        assert!(runner.calls().contains(&"pfctl -E".to_string()));
+        let loaded = loads(&runner);
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].lines().count(), 6);
+        assert!(loaded[0].ends_with(
+            "block drop quick proto udp from any to any port 1900 label \"nets:udp/1900\"\n"
+        ));
+
+        // Already loaded: nothing to rewrite.
+        backend
+            .apply(&QuarantineDecision {
+                ports: vec![8080],
+                ..decision.clone()
+            })
+            .unwrap();
+        assert_eq!(loads(&runner).len(), 1);
+
+        backend
+            .rollback(&QuarantineDecision {
+                ports: vec![8080],
+                ..decision.clone()
+            })
+            .unwrap();
+        assert_eq!(
+            loads(&runner)[1],
+            "pfctl -a com.apple/nets -f -\n\
+             block drop quick proto tcp from any to any port = 443 label \"nets:tcp/443\"\n"
+        );
+
+        // A process without ports loses all its traffic, matched by owner.
+        backend
+            .apply(&QuarantineDecision {
+                identity: Some(ProcessIdentity {
+                    pid: 42,
+                    ppid: None,
+                    name: Some("agent".into()),
+                    exe_path: Some("/tmp/agent".into()),
+                    sha256_16: None,
+                    user: None,
+                    signed: None,
+                }),
+                ports: vec![],
+                ..decision
+            })
+            .unwrap();
+        assert!(runner.calls().contains(&"ps -o user= -p 42".to_string()));
+        assert!(loads(&runner)[2].ends_with(
+            "block drop quick proto udp from any to any user alice label \"nets:udp/*@pid42\"\n"
+        ));
+    }
+}
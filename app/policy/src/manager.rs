@@ -0,0 +1,1017 @@
+use std::{
+    collections::BTreeMap,
+    sync::{mpsc, Arc, Condvar, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use collector::ProcessIdentity;
+use serde::{Deserialize, Serialize};
+use storage::QuarantineRecord;
+
+use crate::{
+    approval::{ApprovalConfig, PendingApproval, Submission},
+    events::{PolicyEvent, PolicyEventBus},
+    kill_switch::KILL_SWITCH_TAG,
+    store::QuarantineStore,
+    AppliedRule, KillSwitch, PolicyAction, PolicyBackend, QuarantineDecision,
+};
+
+/// How long the expiry thread sleeps with nothing scheduled.
+const IDLE_WAIT: Duration = Duration::from_secs(3600);
+/// Delay before retrying a rollback that failed at expiry.
+const RETRY_SECONDS: i64 = 30;
+/// Id the kill switch events carry.
+const KILL_SWITCH_ID: &str = "kill-switch";
+
+/// A quarantine the manager has applied and not yet lifted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActiveQuarantine {
+    pub id: String,
+    pub decision: QuarantineDecision,
+    pub applied_at: DateTime<Utc>,
+    /// `None` when the decision has no expiry (`expires_in_seconds == 0`).
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Lifecycle of a quarantine, for the UI and the audit log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum QuarantineEvent {
+    /// Submitted and waiting for confirmation.
+    PendingApproval {
+        pending: PendingApproval,
+    },
+    /// Confirmed and enforced, right after its `applied` event.
+    Approved {
+        id: String,
+    },
+    Rejected {
+        id: String,
+    },
+    /// Nobody confirmed in time; `applied` tells whether it was enforced
+    /// anyway.
+    ApprovalTimedOut {
+        id: String,
+        applied: bool,
+    },
+    Applied {
+        quarantine: ActiveQuarantine,
+    },
+    /// Re-applied from the store after a restart.
+    Restored {
+        quarantine: ActiveQuarantine,
+    },
+    Extended {
+        id: String,
+        expires_at: Option<DateTime<Utc>>,
+    },
+    /// Lifted on request before expiry.
+    Released {
+        id: String,
+    },
+    Expired {
+        id: String,
+    },
+    /// Rolling back at expiry failed; retried after a short delay.
+    RollbackFailed {
+        id: String,
+        error: String,
+    },
+    KillSwitchEngaged {
+        switch: KillSwitch,
+    },
+    KillSwitchReleased,
+}
+
+impl QuarantineEvent {
+    pub fn quarantine_id(&self) -> &str {
+        match self {
+            QuarantineEvent::PendingApproval { pending } => &pending.id,
+            QuarantineEvent::Applied { quarantine } | QuarantineEvent::Restored { quarantine } => {
+                &quarantine.id
+            }
+            QuarantineEvent::Approved { id }
+            | QuarantineEvent::Rejected { id }
+            | QuarantineEvent::ApprovalTimedOut { id, .. }
+            | QuarantineEvent::Extended { id, .. }
+            | QuarantineEvent::Released { id }
+            | QuarantineEvent::Expired { id }
+            | QuarantineEvent::RollbackFailed { id, .. } => id,
+            QuarantineEvent::KillSwitchEngaged { .. } | QuarantineEvent::KillSwitchReleased => {
+                KILL_SWITCH_ID
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    active: BTreeMap<String, ActiveQuarantine>,
+    pending: BTreeMap<String, PendingApproval>,
+    approval: ApprovalConfig,
+    kill_switch: Option<KillSwitch>,
+    subscribers: Vec<mpsc::Sender<QuarantineEvent>>,
+    events: Option<PolicyEventBus>,
+    stopping: bool,
+}
+
+impl State {
+    fn emit(&mut self, event: QuarantineEvent) {
+        tracing::info!(id = event.quarantine_id(), ?event, "quarantine event");
+        if let Some(policy_event) = PolicyEvent::from_quarantine(&event) {
+            self.publish(policy_event);
+        }
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    fn publish(&self, event: PolicyEvent) {
+        if let Some(bus) = &self.events {
+            bus.publish(event);
+        }
+    }
+
+    /// Tells the UI an action on `id` failed; the caller gets the error.
+    fn failed(&self, id: &str, action: &str, err: &anyhow::Error) {
+        self.publish(PolicyEvent::Failed {
+            id: id.to_string(),
+            action: action.to_string(),
+            error: format!("{err:#}"),
+        });
+    }
+
+    fn next_expiry(&self) -> Option<DateTime<Utc>> {
+        let lifts = self.active.values().filter_map(|q| q.expires_at);
+        let timeouts = self.pending.values().map(|p| p.expires_at);
+        lifts.chain(timeouts).min()
+    }
+}
+
+/// What [`QuarantineManager::recover`] did with the stored quarantines.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecoveryReport {
+    /// Still due to run: enforced again.
+    pub restored: Vec<String>,
+    /// Expired while the daemon was down: rolled back.
+    pub lifted: Vec<String>,
+    /// Could not be restored or lifted, with the reason. Those with a
+    /// readable decision stay scheduled, so lifting them is retried.
+    pub failed: Vec<(String, String)>,
+    /// Rules in the firewall no quarantine accounts for, e.g. applied
+    /// just before a crash; [`QuarantineManager::rollback_all`] clears
+    /// them. The kill switch is not counted.
+    #[serde(default)]
+    pub orphaned: Vec<AppliedRule>,
+}
+
+struct Shared {
+    backend: Box<dyn PolicyBackend + Send + Sync>,
+    store: Option<Box<dyn QuarantineStore>>,
+    state: Mutex<State>,
+    /// Signalled when the schedule changes or the manager stops.
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn persist(&self, quarantine: &ActiveQuarantine) -> Result<()> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        store.save(&QuarantineRecord {
+            id: quarantine.id.clone(),
+            backend: self.backend.name().to_string(),
+            quarantine: serde_json::to_value(quarantine)?,
+            rules: self.backend.rule_handles(&quarantine.decision),
+            expires_at: quarantine.expires_at,
+            updated_at: Utc::now(),
+        })
+    }
+
+    /// Like [`Shared::persist`] where failing to persist must not undo what
+    /// the backend already did.
+    fn persist_or_warn(&self, quarantine: &ActiveQuarantine) {
+        if let Err(err) = self.persist(quarantine) {
+            tracing::warn!(id = %quarantine.id, "persisting quarantine failed: {err:#}");
+        }
+    }
+
+    fn forget(&self, id: &str) {
+        if let Some(store) = &self.store {
+            if let Err(err) = store.remove(id) {
+                tracing::warn!(id, "removing stored quarantine failed: {err:#}");
+            }
+        }
+    }
+
+    /// Rolls back `quarantine`, then re-applies the remaining quarantines
+    /// sharing a port with it: backends key rules by port and process, so
+    /// the rollback may have lifted their rules too.
+    fn lift(&self, state: &State, quarantine: &ActiveQuarantine) -> Result<()> {
+        self.backend.rollback(&quarantine.decision)?;
+        if let Some(identity) = suspended(&quarantine.decision) {
+            // The process may have been killed meanwhile.
+            if let Err(err) = self.backend.resume_process(identity.pid) {
+                tracing::warn!(id = %quarantine.id, "resuming process failed: {err:#}");
+            }
+        }
+        for other in state.active.values() {
+            if other.id != quarantine.id && overlaps(&other.decision, &quarantine.decision) {
+                if let Err(err) = self.backend.apply(&other.decision) {
+                    tracing::warn!(id = %other.id, "re-applying quarantine failed: {err:#}");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Enforces `decision` through the backend under `id` and schedules
+    /// its expiry.
+    fn enforce(
+        &self,
+        state: &mut State,
+        id: String,
+        decision: QuarantineDecision,
+    ) -> Result<ActiveQuarantine> {
+        let result = self.enforce_and_schedule(state, id.clone(), decision);
+        if let Err(err) = &result {
+            state.failed(&id, "quarantine", err);
+        }
+        result
+    }
+
+    fn enforce_and_schedule(
+        &self,
+        state: &mut State,
+        id: String,
+        decision: QuarantineDecision,
+    ) -> Result<ActiveQuarantine> {
+        self.backend.apply(&decision)?;
+        let applied_at = Utc::now();
+        let quarantine = ActiveQuarantine {
+            id,
+            expires_at: expiry(applied_at, decision.expires_in_seconds),
+            decision,
+            applied_at,
+        };
+        if let Some(identity) = suspended(&quarantine.decision) {
+            if let Err(err) = self.backend.suspend_process(identity.pid) {
+                self.lift(state, &quarantine)?;
+                return Err(err.context("suspending process; rolled back"));
+            }
+        }
+        // Rules nobody would find after a restart are the orphans the
+        // store exists to prevent.
+        if let Err(err) = self.persist(&quarantine) {
+            self.lift(state, &quarantine)?;
+            return Err(err.context("persisting quarantine; rolled back"));
+        }
+        state
+            .active
+            .insert(quarantine.id.clone(), quarantine.clone());
+        state.emit(QuarantineEvent::Applied {
+            quarantine: quarantine.clone(),
+        });
+        self.changed.notify_all();
+        Ok(quarantine)
+    }
+
+    /// Settles the approvals nobody answered before `now`.
+    fn time_out_pending(&self, state: &mut State, now: DateTime<Utc>) {
+        let due: Vec<String> = state
+            .pending
+            .values()
+            .filter(|p| p.expires_at <= now)
+            .map(|p| p.id.clone())
+            .collect();
+        for id in due {
+            let Some(pending) = state.pending.remove(&id) else {
+                continue;
+            };
+            let applied = state.approval.apply_on_timeout;
+            state.emit(QuarantineEvent::ApprovalTimedOut {
+                id: id.clone(),
+                applied,
+            });
+            if applied {
+                if let Err(err) = self.enforce(state, id.clone(), pending.decision) {
+                    tracing::warn!(id, "enforcing unconfirmed quarantine failed: {err:#}");
+                }
+            }
+        }
+    }
+
+    fn expire_due(&self, now: DateTime<Utc>) -> Vec<String> {
+        let mut state = self.lock();
+        self.time_out_pending(&mut state, now);
+        let due: Vec<ActiveQuarantine> = state
+            .active
+            .values()
+            .filter(|q| q.expires_at.is_some_and(|at| at <= now))
+            .cloned()
+            .collect();
+        let mut expired = Vec::new();
+        for quarantine in due {
+            let id = quarantine.id.clone();
+            state.active.remove(&id);
+            match self.lift(&state, &quarantine) {
+                Ok(()) => {
+                    self.forget(&id);
+                    state.emit(QuarantineEvent::Expired { id: id.clone() });
+                    expired.push(id);
+                }
+                Err(err) => {
+                    let retry = ActiveQuarantine {
+                        expires_at: Some(now + ChronoDuration::seconds(RETRY_SECONDS)),
+                        ..quarantine
+                    };
+                    self.persist_or_warn(&retry);
+                    state.active.insert(id.clone(), retry);
+                    state.emit(QuarantineEvent::RollbackFailed {
+                        id,
+                        error: format!("{err:#}"),
+                    });
+                }
+            }
+        }
+        expired
+    }
+}
+
+/// The process `decision` suspends, if any.
+fn suspended(decision: &QuarantineDecision) -> Option<&ProcessIdentity> {
+    decision.identity.as_ref().filter(|_| decision.suspend)
+}
+
+/// Whether lifting one decision's rules can lift the other's.
+fn overlaps(a: &QuarantineDecision, b: &QuarantineDecision) -> bool {
+    a.ports.iter().any(|port| b.ports.contains(port))
+        || a.ports.is_empty()
+            && b.ports.is_empty()
+            && a.identity.as_ref().map(|id| id.pid) == b.identity.as_ref().map(|id| id.pid)
+}
+
+/// Tracks applied quarantines and lifts them when `expires_in_seconds`
+/// runs out, on a background thread. Dropping the manager stops the thread
+/// and leaves active quarantines in place; with a store, the next manager
+/// picks them up in [`QuarantineManager::recover`].
+pub struct QuarantineManager {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl QuarantineManager {
+    pub fn new(backend: Box<dyn PolicyBackend + Send + Sync>) -> Result<Self> {
+        Self::spawn(backend, None)
+    }
+
+    /// A manager persisting every quarantine to `store`.
+    pub fn with_store(
+        backend: Box<dyn PolicyBackend + Send + Sync>,
+        store: Box<dyn QuarantineStore>,
+    ) -> Result<Self> {
+        Self::spawn(backend, Some(store))
+    }
+
+    fn spawn(
+        backend: Box<dyn PolicyBackend + Send + Sync>,
+        store: Option<Box<dyn QuarantineStore>>,
+    ) -> Result<Self> {
+        let shared = Arc::new(Shared {
+            backend,
+            store,
+            state: Mutex::default(),
+            changed: Condvar::new(),
+        });
+        let worker = shared.clone();
+        let thread = thread::Builder::new()
+            .name("nets-quarantine".into())
+            .spawn(move || run_expiry(&worker))?;
+        Ok(Self {
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    /// Enforces `decision` through the backend and schedules its expiry,
+    /// without asking for confirmation.
+    pub fn apply(&self, decision: QuarantineDecision) -> Result<ActiveQuarantine> {
+        let mut state = self.shared.lock();
+        self.shared
+            .enforce(&mut state, uuid::Uuid::now_v7().to_string(), decision)
+    }
+
+    /// Enforces `decision` on behalf of `action`, or queues it for
+    /// confirmation when the approval config asks for it.
+    pub fn submit(&self, action: PolicyAction, decision: QuarantineDecision) -> Result<Submission> {
+        let mut state = self.shared.lock();
+        let id = uuid::Uuid::now_v7().to_string();
+        if !state.approval.requires_approval(&action.severity) {
+            let quarantine = self.shared.enforce(&mut state, id, decision)?;
+            return Ok(Submission::Applied { quarantine });
+        }
+        let requested_at = Utc::now();
+        let pending = PendingApproval {
+            id: id.clone(),
+            action,
+            decision,
+            requested_at,
+            expires_at: requested_at + seconds_duration(state.approval.approval_timeout_seconds),
+        };
+        state.pending.insert(id, pending.clone());
+        state.emit(QuarantineEvent::PendingApproval {
+            pending: pending.clone(),
+        });
+        self.shared.changed.notify_all();
+        Ok(Submission::Pending { pending })
+    }
+
+    /// Confirms a pending decision and enforces it. If the backend fails,
+    /// the decision stays pending.
+    pub fn approve(&self, id: &str) -> Result<ActiveQuarantine> {
+        let mut state = self.shared.lock();
+        let pending = state
+            .pending
+            .remove(id)
+            .ok_or_else(|| anyhow!("no pending approval {id}"))?;
+        match self
+            .shared
+            .enforce(&mut state, id.to_string(), pending.decision.clone())
+        {
+            Ok(quarantine) => {
+                state.emit(QuarantineEvent::Approved { id: id.to_string() });
+                Ok(quarantine)
+            }
+            Err(err) => {
+                state.pending.insert(id.to_string(), pending);
+                Err(err)
+            }
+        }
+    }
+
+    /// Drops a pending decision.
+    pub fn reject(&self, id: &str) -> Result<()> {
+        let mut state = self.shared.lock();
+        state
+            .pending
+            .remove(id)
+            .ok_or_else(|| anyhow!("no pending approval {id}"))?;
+        state.emit(QuarantineEvent::Rejected { id: id.to_string() });
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    /// Decisions waiting for confirmation, oldest first.
+    pub fn pending(&self) -> Vec<PendingApproval> {
+        self.shared.lock().pending.values().cloned().collect()
+    }
+
+    pub fn approval_config(&self) -> ApprovalConfig {
+        self.shared.lock().approval.clone()
+    }
+
+    /// Applies to decisions submitted from now on, e.g. when the UI
+    /// switches between Observer and Guardian mode.
+    pub fn set_approval_config(&self, config: ApprovalConfig) {
+        self.shared.lock().approval = config;
+    }
+
+    /// Publishes the enforcement state of quarantines and the kill switch
+    /// on `bus` from now on.
+    pub fn set_event_bus(&self, bus: PolicyEventBus) {
+        self.shared.lock().events = Some(bus);
+    }
+
+    /// Pushes the expiry of `id` back by `seconds`, counted from now if it
+    /// is already due. A quarantine without expiry keeps none.
+    pub fn extend(&self, id: &str, seconds: u64) -> Result<ActiveQuarantine> {
+        let mut state = self.shared.lock();
+        let quarantine = state
+            .active
+            .get_mut(id)
+            .ok_or_else(|| anyhow!("no active quarantine {id}"))?;
+        if let Some(at) = quarantine.expires_at {
+            quarantine.expires_at = Some(at.max(Utc::now()) + seconds_duration(seconds));
+        }
+        let quarantine = quarantine.clone();
+        self.shared.persist_or_warn(&quarantine);
+        state.emit(QuarantineEvent::Extended {
+            id: id.to_string(),
+            expires_at: quarantine.expires_at,
+        });
+        self.shared.changed.notify_all();
+        Ok(quarantine)
+    }
+
+    /// Lifts `id` now.
+    pub fn release(&self, id: &str) -> Result<()> {
+        let mut state = self.shared.lock();
+        let quarantine = state
+            .active
+            .remove(id)
+            .ok_or_else(|| anyhow!("no active quarantine {id}"))?;
+        if let Err(err) = self.shared.lift(&state, &quarantine) {
+            state.active.insert(id.to_string(), quarantine);
+            state.failed(id, "quarantine", &err);
+            return Err(err);
+        }
+        self.shared.forget(id);
+        state.emit(QuarantineEvent::Released { id: id.to_string() });
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    /// Takes over the quarantines in the store: those still running are
+    /// enforced again (their rules may be gone after a reboot), those that
+    /// expired meanwhile are rolled back. Call once after subscribing.
+    /// Suspended processes are not suspended again: after a reboot their
+    /// pid may belong to another program.
+    /// Rules left in the firewall that no quarantine accounts for are
+    /// reported as orphaned, for [`QuarantineManager::rollback_all`].
+    pub fn recover(&self) -> Result<RecoveryReport> {
+        let Some(store) = &self.shared.store else {
+            return Ok(RecoveryReport::default());
+        };
+        let mut report = RecoveryReport::default();
+        let mut expired = Vec::new();
+        let now = Utc::now();
+        let mut state = self.shared.lock();
+        for record in store.load()? {
+            let quarantine: ActiveQuarantine = match serde_json::from_value(record.quarantine) {
+                Ok(quarantine) => quarantine,
+                Err(err) => {
+                    report
+                        .failed
+                        .push((record.id, format!("unreadable: {err}")));
+                    continue;
+                }
+            };
+            if record.backend != self.shared.backend.name() {
+                report.failed.push((
+                    record.id,
+                    format!(
+                        "enforced by {} but the backend is now {}",
+                        record.backend,
+                        self.shared.backend.name()
+                    ),
+                ));
+                continue;
+            }
+            let id = quarantine.id.clone();
+            if quarantine.expires_at.is_some_and(|at| at <= now) {
+                // Left to the expiry below, which also schedules retries.
+                expired.push(id.clone());
+                state.active.insert(id, quarantine);
+                continue;
+            }
+            match self.shared.backend.apply(&quarantine.decision) {
+                Ok(()) => report.restored.push(id.clone()),
+                Err(err) => report.failed.push((id.clone(), format!("{err:#}"))),
+            }
+            state.active.insert(id, quarantine.clone());
+            state.emit(QuarantineEvent::Restored { quarantine });
+        }
+        drop(state);
+        report.lifted = self.shared.expire_due(now);
+        for id in expired {
+            if !report.lifted.contains(&id) {
+                report
+                    .failed
+                    .push((id, "rollback failed; retrying".to_string()));
+            }
+        }
+        report.orphaned = self.orphaned_rules();
+        self.shared.changed.notify_all();
+        Ok(report)
+    }
+
+    /// Firewall rules neither an active quarantine nor the kill switch
+    /// accounts for.
+    fn orphaned_rules(&self) -> Vec<AppliedRule> {
+        let applied = match self.shared.backend.list_active() {
+            Ok(applied) => applied,
+            Err(err) => {
+                tracing::warn!("listing applied rules failed: {err:#}");
+                return Vec::new();
+            }
+        };
+        let state = self.shared.lock();
+        let owned: Vec<String> = state
+            .active
+            .values()
+            .flat_map(|q| self.shared.backend.rule_handles(&q.decision))
+            .collect();
+        applied
+            .into_iter()
+            .filter(|rule| {
+                !rule.handle.starts_with(KILL_SWITCH_TAG) && !owned.contains(&rule.handle)
+            })
+            .collect()
+    }
+
+    /// Every rule nets has in the firewall, read back from it.
+    pub fn applied_rules(&self) -> Result<Vec<AppliedRule>> {
+        self.shared.backend.list_active()
+    }
+
+    /// Removes everything nets applied, orphans and kill switch included,
+    /// and releases every quarantine; returns their ids. Pending approvals
+    /// are left queued.
+    pub fn rollback_all(&self) -> Result<Vec<String>> {
+        let mut state = self.shared.lock();
+        self.shared.backend.rollback_all()?;
+        let released: Vec<ActiveQuarantine> =
+            std::mem::take(&mut state.active).into_values().collect();
+        for quarantine in &released {
+            if let Some(identity) = suspended(&quarantine.decision) {
+                if let Err(err) = self.shared.backend.resume_process(identity.pid) {
+                    tracing::warn!(id = %quarantine.id, "resuming process failed: {err:#}");
+                }
+            }
+            self.shared.forget(&quarantine.id);
+            state.emit(QuarantineEvent::Released {
+                id: quarantine.id.clone(),
+            });
+        }
+        if state.kill_switch.take().is_some() {
+            state.emit(QuarantineEvent::KillSwitchReleased);
+        }
+        self.shared.changed.notify_all();
+        Ok(released.into_iter().map(|q| q.id).collect())
+    }
+
+    /// Blocks all outbound traffic but `switch.allow`, on top of the
+    /// quarantines; engaging it again replaces the allowlist. Emergency
+    /// containment skips the approval queue.
+    pub fn engage_kill_switch(&self, switch: KillSwitch) -> Result<()> {
+        let mut state = self.shared.lock();
+        if let Err(err) = self.shared.backend.engage_kill_switch(&switch) {
+            state.failed(KILL_SWITCH_ID, "kill_switch", &err);
+            return Err(err);
+        }
+        state.kill_switch = Some(switch.clone());
+        state.emit(QuarantineEvent::KillSwitchEngaged { switch });
+        Ok(())
+    }
+
+    pub fn release_kill_switch(&self) -> Result<()> {
+        let mut state = self.shared.lock();
+        if let Err(err) = self.shared.backend.release_kill_switch() {
+            state.failed(KILL_SWITCH_ID, "kill_switch", &err);
+            return Err(err);
+        }
+        if state.kill_switch.take().is_some() {
+            state.emit(QuarantineEvent::KillSwitchReleased);
+        }
+        Ok(())
+    }
+
+    /// The kill switch engaged through this manager, if any.
+    pub fn kill_switch(&self) -> Option<KillSwitch> {
+        self.shared.lock().kill_switch.clone()
+    }
+
+    /// Lifts every quarantine due at `now`, returning their ids, and
+    /// settles approvals that timed out. The background thread does this
+    /// on schedule.
+    pub fn expire_due(&self, now: DateTime<Utc>) -> Vec<String> {
+        self.shared.expire_due(now)
+    }
+
+    pub fn get(&self, id: &str) -> Option<ActiveQuarantine> {
+        self.shared.lock().active.get(id).cloned()
+    }
+
+    /// Active quarantines, oldest first.
+    pub fn active(&self) -> Vec<ActiveQuarantine> {
+        self.shared.lock().active.values().cloned().collect()
+    }
+
+    /// Events from now on; the receiver is dropped from the list once it
+    /// hangs up.
+    pub fn subscribe(&self) -> mpsc::Receiver<QuarantineEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.shared.lock().subscribers.push(tx);
+        rx
+    }
+}
+
+impl Drop for QuarantineManager {
+    fn drop(&mut self) {
+        self.shared.lock().stopping = true;
+        self.shared.changed.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn expiry(from: DateTime<Utc>, seconds: u64) -> Option<DateTime<Utc>> {
+    (seconds > 0).then(|| from + seconds_duration(seconds))
+}
+
+/// `seconds` as a chrono duration, capped at ten years.
+fn seconds_duration(seconds: u64) -> ChronoDuration {
+    ChronoDuration::seconds(seconds.min(10 * 365 * 86_400) as i64)
+}
+
+fn run_expiry(shared: &Shared) {
+    loop {
+        let state = shared.lock();
+        if state.stopping {
+            return;
+        }
+        let wait = state.next_expiry().map_or(IDLE_WAIT, |at| {
+            (at - Utc::now()).to_std().unwrap_or_default()
+        });
+        let (state, _) = shared
+            .changed
+            .wait_timeout(state, wait)
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if state.stopping {
+            return;
+        }
+        drop(state);
+        shared.expire_due(Utc::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use analyzer::Severity;
+
+    use super::*;
+    use crate::command::{CommandRunner, ScriptedRunner};
+    use crate::Direction;
+
+    /// Records applies and rollbacks as `apply <ports>` / `rollback <ports>`.
+    struct Recording(Arc<ScriptedRunner>);
+
+    impl PolicyBackend for Recording {
+        fn apply(&self, decision: &QuarantineDecision) -> Result<()> {
+            self.0.run("apply", &[&format!("{:?}", decision.ports)])?;
+            Ok(())
+        }
+
+        fn rollback(&self, decision: &QuarantineDecision) -> Result<()> {
+            self.0
+                .run("rollback", &[&format!("{:?}", decision.ports)])?;
+            Ok(())
+        }
+
+        fn plan(&self, decision: &QuarantineDecision) -> Result<Vec<String>> {
+            Ok(vec![format!("apply {:?}", decision.ports)])
+        }
+
+        fn name(&self) -> &'static str {
+            "recording"
+        }
+
+        fn suspend_process(&self, pid: i32) -> Result<()> {
+            self.0.run("suspend", &[&pid.to_string()])?;
+            Ok(())
+        }
+
+        fn resume_process(&self, pid: i32) -> Result<()> {
+            self.0.run("resume", &[&pid.to_string()])?;
+            Ok(())
+        }
+    }
+
+    fn decision(ports: Vec<u16>, expires_in_seconds: u64) -> QuarantineDecision {
+        QuarantineDecision {
+            process: None,
+            identity: None,
+            ports,
+            expires_in_seconds,
+            suspend: false,
+            direction: Direction::Both,
+            remote_networks: Vec::new(),
+            remote_domains: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn expires_extends_and_releases() {
+        let runner = Arc::new(ScriptedRunner::new(|_| Ok(String::new())));
+        let manager = QuarantineManager::new(Box::new(Recording(runner.clone()))).unwrap();
+        let events = manager.subscribe();
+
+        let short = manager.apply(decision(vec![8080], 60)).unwrap();
+        let shared = manager.apply(decision(vec![8080, 443], 600)).unwrap();
+        let forever = manager.apply(decision(vec![22], 0)).unwrap();
+        assert_eq!(forever.expires_at, None);
+
+        let later = short.applied_at + ChronoDuration::seconds(120);
+        assert_eq!(manager.expire_due(later), std::slice::from_ref(&short.id));
+        // The expired rule shared port 8080, so the other one is restored.
+        assert_eq!(
+            runner.calls()[3..],
+            ["rollback [8080]", "apply [8080, 443]"]
+        );
+
+        let extended = manager.extend(&shared.id, 3600).unwrap();
+        assert!(extended.expires_at > shared.expires_at);
+        assert!(manager
+            .expire_due(later + ChronoDuration::seconds(600))
+            .is_empty());
+
+        manager.release(&forever.id).unwrap();
+        assert!(manager.release(&forever.id).is_err());
+        assert_eq!(manager.active().len(), 1);
+
+        let kinds: Vec<String> = events
+            .try_iter()
+            .map(|event| serde_json::to_value(&event).unwrap()["event"].to_string())
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                "\"applied\"",
+                "\"applied\"",
+                "\"applied\"",
+                "\"expired\"",
+                "\"extended\"",
+                "\"released\""
+            ]
+        );
+
+        // A suspended process is resumed once its quarantine is lifted.
+        let frozen = manager
+            .apply(QuarantineDecision {
+                identity: Some(ProcessIdentity {
+                    pid: 4242,
+                    ppid: None,
+                    name: Some("agent".into()),
+                    exe_path: None,
+                    sha256_16: None,
+                    user: None,
+                    signed: None,
+                }),
+                suspend: true,
+                ..decision(vec![9001], 60)
+            })
+            .unwrap();
+        manager.release(&frozen.id).unwrap();
+        let calls = runner.calls();
+        assert_eq!(
+            calls[calls.len() - 4..],
+            [
+                "apply [9001]",
+                "suspend 4242",
+                "rollback [9001]",
+                "resume 4242"
+            ]
+        );
+    }
+
+    #[test]
+    fn recovers_stored_quarantines_after_restart() {
+        let runner = Arc::new(ScriptedRunner::new(|_| Ok(String::new())));
+        let store = Arc::new(Mutex::new(
+            storage::Storage::open(":memory:", &[5u8; 32]).unwrap(),
+        ));
+        let first = QuarantineManager::with_store(
+            Box::new(Recording(runner.clone())),
+            Box::new(store.clone()),
+        )
+        .unwrap();
+        let running = first.apply(decision(vec![445], 600)).unwrap();
+        let released = first.apply(decision(vec![22], 0)).unwrap();
+        first.release(&released.id).unwrap();
+        drop(first);
+
+        // Expired while the daemon was down.
+        let stale = ActiveQuarantine {
+            id: "stale".into(),
+            decision: decision(vec![3389], 60),
+            applied_at: Utc::now() - ChronoDuration::hours(2),
+            expires_at: Some(Utc::now() - ChronoDuration::hours(1)),
+        };
+        store
+            .save(&QuarantineRecord {
+                id: stale.id.clone(),
+                backend: "recording".into(),
+                quarantine: serde_json::to_value(&stale).unwrap(),
+                rules: Vec::new(),
+                expires_at: stale.expires_at,
+                updated_at: Utc::now(),
+            })
+            .unwrap();
+
+        let second = QuarantineManager::with_store(
+            Box::new(Recording(runner.clone())),
+            Box::new(store.clone()),
+        )
+        .unwrap();
+        let report = second.recover().unwrap();
+        assert_eq!(report.restored, std::slice::from_ref(&running.id));
+        assert_eq!(report.lifted, ["stale"]);
+        assert!(report.failed.is_empty());
+        assert_eq!(runner.calls()[3..], ["apply [445]", "rollback [3389]"]);
+        assert_eq!(
+            second.get(&running.id).unwrap().expires_at,
+            running.expires_at
+        );
+
+        let stored: Vec<String> = store.load().unwrap().into_iter().map(|r| r.id).collect();
+        assert_eq!(stored, [running.id]);
+    }
+
+    #[test]
+    fn queues_decisions_until_approved() {
+        let runner = Arc::new(ScriptedRunner::new(|_| Ok(String::new())));
+        let manager = QuarantineManager::new(Box::new(Recording(runner.clone()))).unwrap();
+        manager.set_approval_config(ApprovalConfig {
+            auto_approve: vec![Severity::High],
+            ..ApprovalConfig::default()
+        });
+        let events = manager.subscribe();
+        let bus = PolicyEventBus::default();
+        manager.set_event_bus(bus.clone());
+        let mut policy_events = bus.subscribe();
+        let action = |severity| PolicyAction {
+            id: "alert-1".into(),
+            description: "listener on 8080".into(),
+            severity,
+            quarantine: true,
+        };
+
+        let Submission::Applied { .. } = manager
+            .submit(action(Severity::High), decision(vec![22], 0))
+            .unwrap()
+        else {
+            panic!("auto-approved severity was queued");
+        };
+        let Submission::Pending { pending } = manager
+            .submit(action(Severity::Medium), decision(vec![8080], 0))
+            .unwrap()
+        else {
+            panic!("decision was not queued");
+        };
+        assert_eq!(runner.calls(), ["apply [22]"]);
+        let applied = manager.approve(&pending.id).unwrap();
+        assert_eq!(applied.id, pending.id);
+        assert!(manager.approve(&pending.id).is_err());
+
+        let Submission::Pending { pending: rejected } = manager
+            .submit(action(Severity::Low), decision(vec![445], 0))
+            .unwrap()
+        else {
+            panic!("decision was not queued");
+        };
+        manager.reject(&rejected.id).unwrap();
+        let Submission::Pending { pending: stale } = manager
+            .submit(action(Severity::Low), decision(vec![3389], 0))
+            .unwrap()
+        else {
+            panic!("decision was not queued");
+        };
+        manager.expire_due(stale.expires_at);
+        assert!(manager.pending().is_empty());
+        assert_eq!(runner.calls(), ["apply [22]", "apply [8080]"]);
+
+        let kinds: Vec<String> = events
+            .try_iter()
+            .map(|event| serde_json::to_value(&event).unwrap()["event"].to_string())
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                "\"applied\"",
+                "\"pending_approval\"",
+                "\"applied\"",
+                "\"approved\"",
+                "\"pending_approval\"",
+                "\"rejected\"",
+                "\"pending_approval\"",
+                "\"approval_timed_out\"",
+            ]
+        );
+        let states: Vec<String> = std::iter::from_fn(|| policy_events.try_recv().ok())
+            .map(|event| serde_json::to_value(&event).unwrap()["state"].to_string())
+            .collect();
+        assert_eq!(
+            states,
+            [
+                "\"applied\"",
+                "\"pending_approval\"",
+                "\"applied\"",
+                "\"pending_approval\"",
+                "\"rolled_back\"",
+                "\"pending_approval\"",
+                "\"rolled_back\"",
+            ]
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Address ranges quarantines are scoped to: an address with a prefix
+//! length, written `203.0.113.0/24`, or a bare address for a single host.
+
+use std::fmt;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    /// Fails when `prefix` is longer than the address; host bits of `addr`
+    /// are cleared.
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Self> {
+        if prefix > max_prefix(&addr) {
+            return Err(anyhow!("prefix /{prefix} is too long for {addr}"));
+        }
+        let addr = match addr {
+            IpAddr::V4(v4) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+                IpAddr::from((u32::from(v4) & mask).to_be_bytes())
+            }
+            IpAddr::V6(v6) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+                IpAddr::from((u128::from(v6) & mask).to_be_bytes())
+            }
+        };
+        Ok(Self { addr, prefix })
+    }
+
+    pub fn host(addr: IpAddr) -> Self {
+        Self {
+            addr,
+            prefix: max_prefix(&addr),
+        }
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    pub fn is_ipv4(&self) -> bool {
+        self.addr.is_ipv4()
+    }
+
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        Self::new(*addr, self.prefix).is_ok_and(|network| network.addr == self.addr)
+    }
+}
+
+fn max_prefix(addr: &IpAddr) -> u8 {
+    if addr.is_ipv4() {
+        32
+    } else {
+        128
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("invalid network address: {s}"))?;
+        match prefix {
+            Some(prefix) => {
+                let prefix = prefix
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow!("invalid prefix length: {s}"))?;
+                Self::new(addr, prefix)
+            }
+            None => Ok(Self::host(addr)),
+        }
+    }
+}
+
+/// Host addresses print without a prefix, as firewalls list them.
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.prefix == max_prefix(&self.addr) {
+            write!(f, "{}", self.addr)
+        } else {
+            write!(f, "{}/{}", self.addr, self.prefix)
+        }
+    }
+}
+
+impl Serialize for IpNetwork {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for IpNetwork {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// The host addresses `domain` resolves to right now. Resolving to nothing
+/// is an error: dropping the domain would widen the rule to every address.
+pub fn resolve_domain(domain: &str) -> Result<Vec<IpNetwork>> {
+    let mut hosts: Vec<IpNetwork> = (domain, 0)
+        .to_socket_addrs()
+        .map_err(|err| anyhow!("cannot resolve {domain}: {err}"))?
+        .map(|addr| IpNetwork::host(addr.ip()))
+        .collect();
+    hosts.sort_unstable();
+    hosts.dedup();
+    if hosts.is_empty() {
+        return Err(anyhow!("{domain} resolves to no address"));
+    }
+    Ok(hosts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_matches_networks() {
+        let net: IpNetwork = "203.0.113.77/24".parse().unwrap();
+        assert_eq!(net.to_string(), "203.0.113.0/24");
+        assert!(net.contains(&"203.0.113.5".parse().unwrap()));
+        assert!(!net.contains(&"203.0.114.5".parse().unwrap()));
+        assert!(!net.contains(&"::1".parse().unwrap()));
+
+        let host: IpNetwork = "2001:db8::1".parse().unwrap();
+        assert_eq!(host.prefix(), 128);
+        assert_eq!(host.to_string(), "2001:db8::1");
+        let any: IpNetwork = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(&"8.8.8.8".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<IpNetwork>().is_err());
+        assert!("example/24".parse::<IpNetwork>().is_err());
+
+        let json = serde_json::to_string(&vec![net]).unwrap();
+        assert_eq!(json, r#"["203.0.113.0/24"]"#);
+        assert_eq!(
+            serde_json::from_str::<Vec<IpNetwork>>(&json).unwrap(),
+            vec![net]
+        );
+        assert!(!resolve_domain("localhost").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,487 @@
+//! Notification channels: webhooks, desktop notifications and email, sent
+//! from playbook `notify` steps or directly as a response to an alert.
+//! Channels are named in `[policy.notifications]`; their payload carries
+//! the alert, the flow behind it and the suggested remediation, and can be
+//! shaped with `{{path}}` templates (`{{alert.severity}}`,
+//! `{{flow.dst_ip}}`, `{{alert.evidence.sni}}`, `{{message}}`).
+
+use std::{collections::BTreeMap, fmt::Write as _};
+
+use analyzer::{Alert, Severity};
+use anyhow::{anyhow, Context, Result};
+use base64::Engine as _;
+use collector::FlowEvent;
+use serde::{Deserialize, Serialize};
+
+use crate::command::{powershell_quote, CommandRunner, SystemRunner};
+
+/// Mirrors `[policy.notifications]`: channels by name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationConfig {
+    #[serde(flatten)]
+    pub channels: BTreeMap<String, NotifyChannel>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NotifyChannel {
+    /// POSTs the payload as JSON, or the rendered `template` (values are
+    /// JSON-escaped, so it can be a JSON document). Plain http only to
+    /// loopback.
+    Webhook {
+        url: String,
+        /// Environment variable holding a bearer token.
+        #[serde(default)]
+        token_env: Option<String>,
+        #[serde(default)]
+        template: Option<String>,
+    },
+    /// notify-send on Linux, Notification Center on macOS, a toast on
+    /// Windows. Reaches the user only from a process in their session.
+    Desktop {
+        #[serde(default)]
+        title: Option<String>,
+        #[serde(default)]
+        template: Option<String>,
+    },
+    /// Plain-text mail handed to the local MTA (`sendmail -t`).
+    Email {
+        to: Vec<String>,
+        #[serde(default)]
+        from: Option<String>,
+        #[serde(default)]
+        subject: Option<String>,
+        #[serde(default)]
+        template: Option<String>,
+        /// Defaults to `sendmail` on the PATH.
+        #[serde(default)]
+        sendmail: Option<String>,
+    },
+}
+
+/// What a notification is about; the data templates are rendered from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationPayload {
+    /// The alert summary unless a playbook step overrides it.
+    pub message: String,
+    pub alert: Alert,
+    #[serde(default)]
+    pub flow: Option<FlowEvent>,
+    /// The alert's suggested action.
+    #[serde(default)]
+    pub remediation: Option<String>,
+    /// Playbook that sent it, if any.
+    #[serde(default)]
+    pub playbook: Option<String>,
+}
+
+impl NotificationPayload {
+    pub fn new(alert: &Alert, flow: Option<&FlowEvent>) -> Self {
+        Self {
+            message: alert.summary.clone(),
+            alert: alert.clone(),
+            flow: flow.cloned(),
+            remediation: alert.suggested_action.clone(),
+            playbook: None,
+        }
+    }
+
+    /// Replaces every `{{path}}` with the payload field it names, empty
+    /// when there is none. `json` escapes the values for use inside a JSON
+    /// string.
+    pub fn render(&self, template: &str, json: bool) -> String {
+        let value = serde_json::to_value(self).unwrap_or_default();
+        let mut rendered = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            let Some(len) = rest[start..].find("}}") else {
+                break;
+            };
+            rendered.push_str(&rest[..start]);
+            let path = rest[start + 2..start + len].trim();
+            let field = value.pointer(&format!("/{}", path.replace('.', "/")));
+            let text = match field {
+                None | Some(serde_json::Value::Null) => String::new(),
+                Some(serde_json::Value::String(text)) => text.clone(),
+                Some(other) => other.to_string(),
+            };
+            if json {
+                let quoted = serde_json::Value::String(text).to_string();
+                rendered.push_str(&quoted[1..quoted.len() - 1]);
+            } else {
+                rendered.push_str(&text);
+            }
+            rest = &rest[start + len + 2..];
+        }
+        rendered.push_str(rest);
+        rendered
+    }
+
+    /// Default email body: the alert, its evidence and flow, and what to do.
+    fn text(&self) -> String {
+        let alert = &self.alert;
+        let mut text = format!("{}\n\n", self.message);
+        let _ = writeln!(text, "Severity: {}", alert.severity.as_str());
+        let _ = writeln!(text, "Rule: {}", alert.rule_id);
+        let _ = writeln!(text, "Alert: {} ({})", alert.id, alert.ts.to_rfc3339());
+        if let Some(playbook) = &self.playbook {
+            let _ = writeln!(text, "Playbook: {playbook}");
+        }
+        if !alert.rationale.is_empty() {
+            let _ = writeln!(text, "Rationale: {}", alert.rationale);
+        }
+        if let Some(flow) = &self.flow {
+            let _ = writeln!(
+                text,
+                "Flow: {} {}:{} -> {}:{}",
+                flow.proto, flow.src_ip, flow.src_port, flow.dst_ip, flow.dst_port
+            );
+            if let Some(process) = flow.process.as_ref().and_then(|p| p.name.as_deref()) {
+                let _ = writeln!(text, "Process: {process}");
+            }
+        }
+        for (key, value) in &alert.evidence {
+            let _ = writeln!(text, "{key}: {value}");
+        }
+        if let Some(remediation) = &self.remediation {
+            let _ = writeln!(text, "\nSuggested remediation: {remediation}");
+        }
+        text
+    }
+}
+
+/// Sends notifications through the configured channels.
+pub struct Notifier {
+    channels: BTreeMap<String, NotifyChannel>,
+    runner: Box<dyn CommandRunner>,
+}
+
+impl Notifier {
+    pub fn new(config: &NotificationConfig) -> Self {
+        Self::with_runner(config, Box::new(SystemRunner))
+    }
+
+    pub fn with_runner(config: &NotificationConfig, runner: Box<dyn CommandRunner>) -> Self {
+        Self {
+            channels: config.channels.clone(),
+            runner,
+        }
+    }
+
+    pub fn channels(&self) -> impl Iterator<Item = &str> {
+        self.channels.keys().map(String::as_str)
+    }
+
+    pub fn send(&self, channel: &str, payload: &NotificationPayload) -> Result<()> {
+        let config = self
+            .channels
+            .get(channel)
+            .ok_or_else(|| anyhow!("no notification channel named {channel}"))?;
+        match config {
+            NotifyChannel::Webhook {
+                url,
+                token_env,
+                template,
+            } => {
+                let body = match template {
+                    Some(template) => payload.render(template, true),
+                    None => serde_json::to_string(payload)?,
+                };
+                post_webhook(url, token_env.as_deref(), &body)
+            }
+            NotifyChannel::Desktop { title, template } => {
+                let title = match title {
+                    Some(title) => payload.render(title, false),
+                    None => format!(
+                        "nets: {} — {}",
+                        payload.alert.severity.as_str(),
+                        payload.alert.rule_id
+                    ),
+                };
+                let body = match template {
+                    Some(template) => payload.render(template, false),
+                    None => payload.message.clone(),
+                };
+                self.desktop(&title, &body, payload.alert.severity == Severity::High)
+            }
+            NotifyChannel::Email {
+                to,
+                from,
+                subject,
+                template,
+                sendmail,
+            } => {
+                let subject = match subject {
+                    Some(subject) => payload.render(subject, false),
+                    None => format!(
+                        "[nets] {}: {}",
+                        payload.alert.severity.as_str(),
+                        payload.alert.summary
+                    ),
+                };
+                let body = match template {
+                    Some(template) => payload.render(template, false),
+                    None => payload.text(),
+                };
+                let message = mail(to, from.as_deref(), &subject, &body)?;
+                self.runner.run_with_input(
+                    sendmail.as_deref().unwrap_or("sendmail"),
+                    &["-t", "-i"],
+                    &message,
+                )?;
+                Ok(())
+            }
+        }
+        .with_context(|| format!("notifying through {channel}"))?;
+        tracing::info!(channel, alert = %payload.alert.id, "notification sent");
+        Ok(())
+    }
+
+    fn desktop(&self, title: &str, body: &str, urgent: bool) -> Result<()> {
+        if cfg!(windows) {
+            // Toasts need a registered AppUserModelID; PowerShell's is
+            // always present.
+            let script = format!(
+                "[Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, ContentType = WindowsRuntime] > $null\n\
+                 $xml = [Windows.UI.Notifications.ToastNotificationManager]::GetTemplateContent([Windows.UI.Notifications.ToastTemplateType]::ToastText02)\n\
+                 $text = $xml.GetElementsByTagName('text')\n\
+                 $text.Item(0).AppendChild($xml.CreateTextNode({})) > $null\n\
+                 $text.Item(1).AppendChild($xml.CreateTextNode({})) > $null\n\
+                 $id = '{{1AC14E77-02E7-4E5D-B744-2EB1AE5198B7}}\\WindowsPowerShell\\v1.0\\powershell.exe'\n\
+                 [Windows.UI.Notifications.ToastNotificationManager]::CreateToastNotifier($id).Show([Windows.UI.Notifications.ToastNotification]::new($xml))\n",
+                powershell_quote(title),
+                powershell_quote(body)
+            );
+            self.runner.run_with_input(
+                "powershell",
+                &["-NoProfile", "-NonInteractive", "-Command", "-"],
+                &script,
+            )?;
+        } else if cfg!(target_os = "macos") {
+            let script = format!(
+                "display notification {} with title {}",
+                applescript_quote(body),
+                applescript_quote(title)
+            );
+            self.runner.run("osascript", &["-e", &script])?;
+        } else {
+            let urgency = if urgent {
+                "--urgency=critical"
+            } else {
+                "--urgency=normal"
+            };
+            // Rendered from alert data, so a title or body starting with
+            // `-` must not pass for an option.
+            self.runner.run(
+                "notify-send",
+                &["--app-name=nets", urgency, "--", title, body],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "webhook")]
+fn post_webhook(url: &str, token_env: Option<&str>, body: &str) -> Result<()> {
+    let target = webhook_url(url)?;
+    let agent = ureq::AgentBuilder::new()
+        .timeout(std::time::Duration::from_secs(10))
+        .build();
+    let mut request = agent
+        .post(target.as_str())
+        .set("Content-Type", "application/json");
+    if let Some(var) = token_env {
+        let token = std::env::var(var).with_context(|| format!("{var} is not set"))?;
+        request = request.set("Authorization", &format!("Bearer {token}"));
+    }
+    request
+        .send_string(body)
+        .with_context(|| format!("POST {url}"))?;
+    Ok(())
+}
+
+/// The bearer token must not leave the host in clear text: https, or plain
+/// http only to a loopback address or `localhost` itself.
+#[cfg(feature = "webhook")]
+fn webhook_url(url: &str) -> Result<url::Url> {
+    let parsed = url::Url::parse(url).with_context(|| format!("webhook {url} is not a URL"))?;
+    let loopback = match parsed.host() {
+        Some(url::Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(url::Host::Ipv6(ip)) => ip.is_loopback(),
+        Some(url::Host::Domain(domain)) => domain == "localhost",
+        None => false,
+    };
+    match parsed.scheme() {
+        "https" => Ok(parsed),
+        "http" if loopback => Ok(parsed),
+        _ => Err(anyhow!("webhook {url} must use https")),
+    }
+}
+
+#[cfg(not(feature = "webhook"))]
+fn post_webhook(_url: &str, _token_env: Option<&str>, _body: &str) -> Result<()> {
+    Err(anyhow!("webhooks need the policy `webhook` feature"))
+}
+
+/// RFC 5322 message for `sendmail -t`, with the subject encoded for
+/// non-ASCII text (RFC 2047).
+fn mail(to: &[String], from: Option<&str>, subject: &str, body: &str) -> Result<String> {
+    let header = |value: &str| value.replace(['\r', '\n'], " ");
+    if to.is_empty() || to.iter().any(|addr| !addr.contains('@')) {
+        return Err(anyhow!("email needs recipient addresses, got {to:?}"));
+    }
+    let mut message = format!("To: {}\n", header(&to.join(", ")));
+    if let Some(from) = from {
+        let _ = writeln!(message, "From: {}", header(from));
+    }
+    let subject = header(subject);
+    if subject.is_ascii() {
+        let _ = writeln!(message, "Subject: {subject}");
+    } else {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(subject);
+        let _ = writeln!(message, "Subject: =?UTF-8?B?{encoded}?=");
+    }
+    message.push_str(
+        "MIME-Version: 1.0\n\
+         Content-Type: text/plain; charset=utf-8\n\
+         Content-Transfer-Encoding: 8bit\n\n",
+    );
+    message.push_str(body);
+    Ok(message)
+}
+
+fn applescript_quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        sync::Arc,
+    };
+
+    use analyzer::AlertStatus;
+    use chrono::Utc;
+
+    use super::*;
+    use crate::command::ScriptedRunner;
+
+    #[test]
+    fn renders_payloads_for_each_channel() {
+        let alert = Alert {
+            id: "a1".into(),
+            ts: Utc::now(),
+            severity: Severity::High,
+            rule_id: "smb-lateral".into(),
+            summary: "SMB к новому хосту \"srv\"".into(),
+            flow_refs: Vec::new(),
+            process_ref: None,
+            rationale: String::new(),
+            suggested_action: Some("Isolate the host".into()),
+            status: AlertStatus::New,
+            assignee: None,
+            notes: Vec::new(),
+            evidence: [("sni".to_string(), "evil.example".to_string())].into(),
+        };
+        let flow = FlowEvent {
+            dst_ip: "10.0.0.9".into(),
+            dst_port: 445,
+            ..FlowEvent::default()
+        };
+        let payload = NotificationPayload::new(&alert, Some(&flow));
+        assert_eq!(
+            payload.render(
+                "{{alert.severity}} {{ flow.dst_ip }}:{{flow.dst_port}} {{alert.evidence.sni}}{{nope}}",
+                false
+            ),
+            "High 10.0.0.9:445 evil.example"
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&payload.render(r#"{"text": "{{message}}"}"#, true)).unwrap();
+        assert_eq!(json["text"], alert.summary);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            while !String::from_utf8_lossy(&request).ends_with('}') {
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let runner = Arc::new(ScriptedRunner::new(|_| Ok(String::new())));
+        let config: NotificationConfig = serde_json::from_value(serde_json::json!({
+            "soc": { "type": "webhook", "url": url },
+            "oncall": { "type": "email", "to": ["soc@example.com"] },
+            "desk": { "type": "desktop", "title": "-{{alert.rule_id}}" },
+        }))
+        .unwrap();
+        let notifier = Notifier::with_runner(&config, Box::new(runner.clone()));
+        assert_eq!(
+            notifier.channels().collect::<Vec<_>>(),
+            ["desk", "oncall", "soc"]
+        );
+
+        if cfg!(feature = "webhook") {
+            notifier.send("soc", &payload).unwrap();
+            let request = server.join().unwrap();
+            assert!(request.starts_with("POST /hook"));
+            assert!(request.contains("\"remediation\":\"Isolate the host\""));
+        } else {
+            assert!(notifier.send("soc", &payload).is_err());
+        }
+
+        notifier.send("oncall", &payload).unwrap();
+        let calls = runner.calls();
+        assert!(calls[0].starts_with("sendmail -t -i"));
+        assert!(calls[0].contains("To: soc@example.com\n"));
+        assert!(calls[0].contains("Subject: =?UTF-8?B?"));
+        assert!(calls[0].contains("Suggested remediation: Isolate the host"));
+        if cfg!(target_os = "linux") {
+            notifier.send("desk", &payload).unwrap();
+            assert_eq!(
+                runner.calls()[1],
+                format!(
+                    "notify-send --app-name=nets --urgency=critical -- -smb-lateral {}",
+                    alert.summary
+                )
+            );
+        }
+        assert!(notifier.send("pager", &payload).is_err());
+    }
+
+    #[cfg(feature = "webhook")]
+    #[test]
+    fn sends_webhooks_over_https_or_to_loopback_only() {
+        for url in [
+            "http://evil.example/hook",
+            "http://localhost@evil.example/",
+            "http://localhost.evil.example/",
+            "http://127.0.0.1.evil.example/",
+            "ftp://127.0.0.1/hook",
+        ] {
+            assert!(webhook_url(url).is_err(), "{url}");
+        }
+        for url in [
+            "https://hooks.example/x",
+            "http://localhost:8080/x",
+            "http://127.0.0.2/x",
+            "http://[::1]/x",
+        ] {
+            assert!(webhook_url(url).is_ok(), "{url}");
+        }
+    }
+}
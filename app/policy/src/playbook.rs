@@ -0,0 +1,725 @@
+//! Response playbooks: alerts matched by rule id, severity or tags run an
+//! ordered sequence of actions, e.g. notify, throttle at once, quarantine
+//! ten minutes later unless someone acknowledged the alert. Playbooks are
+//! defined in YAML (`[policy] playbooks_path`); every step executed is
+//! recorded in the policy action audit log. `notify` steps also go out
+//! through the [`crate::Notifier`] channels they name.
+
+use std::{
+    net::IpAddr,
+    sync::{mpsc, Arc},
+};
+
+use analyzer::{exceptions::glob_match, Alert, AlertStatus, Severity};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use collector::FlowEvent;
+use serde::{Deserialize, Serialize};
+use storage::{ActionOutcome, PolicyActionRecord};
+
+use crate::{
+    quarantine_decision_for, store::ActionLog, DnsSinkhole, NotificationPayload, Notifier,
+    PolicyAction, PolicyEvent, PolicyEventBus, QuarantineDecision, QuarantineManager,
+    SinkholeDecision, Submission, ThrottleDecision, Throttler,
+};
+
+/// Evidence keys of an alert naming the domain behind it.
+const DOMAIN_EVIDENCE: [&str; 4] = ["domain", "sni", "dns_qname", "qname"];
+/// A year; a step delayed longer is a typo rather than an escalation.
+const MAX_AFTER_MINUTES: u64 = 366 * 24 * 60;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Playbook {
+    pub id: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default, rename = "match")]
+    pub matches: PlaybookMatch,
+    pub steps: Vec<PlaybookStep>,
+}
+
+/// Alerts a playbook responds to; every criterion given must hold.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlaybookMatch {
+    /// Rule ids, globs allowed (`builtin.scan.*`).
+    pub rule_ids: Vec<String>,
+    pub min_severity: Option<Severity>,
+    /// Any of these tags of the alert's rule.
+    pub tags: Vec<String>,
+}
+
+impl PlaybookMatch {
+    pub fn matches(&self, alert: &Alert, tags: &[String]) -> bool {
+        (self.rule_ids.is_empty()
+            || self
+                .rule_ids
+                .iter()
+                .any(|pattern| glob_match(pattern, &alert.rule_id)))
+            && self
+                .min_severity
+                .as_ref()
+                .is_none_or(|min| alert.severity >= *min)
+            && (self.tags.is_empty() || self.tags.iter().any(|tag| tags.contains(tag)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlaybookStep {
+    #[serde(flatten)]
+    pub action: PlaybookAction,
+    /// Delay from the alert, not from the previous step.
+    #[serde(default)]
+    pub after_minutes: u64,
+    /// Skipped once the alert is acknowledged.
+    #[serde(default)]
+    pub unless_acknowledged: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum PlaybookAction {
+    /// Tells the user; the alert summary when no message is given.
+    /// Subscribers always get it, the named `[policy.notifications]`
+    /// channels too.
+    Notify {
+        #[serde(default)]
+        message: Option<String>,
+        #[serde(default)]
+        channels: Vec<String>,
+    },
+    /// Rate-limits traffic to the flow's destination.
+    Throttle {
+        #[serde(default)]
+        rate_kbit: Option<u32>,
+    },
+    /// Submitted to the quarantine manager, so it goes through approval.
+    Quarantine {
+        #[serde(default = "default_quarantine_seconds")]
+        expires_in_seconds: u64,
+        #[serde(default)]
+        suspend: bool,
+    },
+    /// Sinks the domain of the flow (SNI or DNS name) or of the evidence.
+    Sinkhole {
+        #[serde(default)]
+        redirect_to: Option<IpAddr>,
+    },
+}
+
+fn default_quarantine_seconds() -> u64 {
+    600
+}
+
+impl PlaybookAction {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PlaybookAction::Notify { .. } => "notify",
+            PlaybookAction::Throttle { .. } => "throttle",
+            PlaybookAction::Quarantine { .. } => "quarantine",
+            PlaybookAction::Sinkhole { .. } => "sinkhole",
+        }
+    }
+}
+
+/// Parses and checks playbooks: ids are unique, each has steps, steps are
+/// listed in the order they run and none is delayed by more than a year.
+pub fn load_playbooks_from_str(data: &str) -> Result<Vec<Playbook>> {
+    let playbooks: Vec<Playbook> = serde_yaml::from_str(data).context("parsing playbooks")?;
+    for (index, playbook) in playbooks.iter().enumerate() {
+        if playbooks[..index].iter().any(|p| p.id == playbook.id) {
+            return Err(anyhow!("duplicate playbook id {}", playbook.id));
+        }
+        if playbook.steps.is_empty() {
+            return Err(anyhow!("playbook {} has no steps", playbook.id));
+        }
+        if playbook
+            .steps
+            .windows(2)
+            .any(|pair| pair[1].after_minutes < pair[0].after_minutes)
+        {
+            return Err(anyhow!(
+                "playbook {}: steps must be ordered by after_minutes",
+                playbook.id
+            ));
+        }
+        if let Some(step) = playbook
+            .steps
+            .iter()
+            .find(|step| step.after_minutes > MAX_AFTER_MINUTES)
+        {
+            return Err(anyhow!(
+                "playbook {}: after_minutes {} is more than a year ({MAX_AFTER_MINUTES})",
+                playbook.id,
+                step.after_minutes
+            ));
+        }
+    }
+    Ok(playbooks)
+}
+
+/// A `notify` step, for the UI and notification channels.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Notification {
+    pub playbook: String,
+    pub alert_id: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// What a step did.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepOutcome {
+    pub playbook: String,
+    pub alert_id: String,
+    pub step: usize,
+    pub action: String,
+    /// `Pending` for quarantines waiting for approval; `RolledBack` marks
+    /// a step skipped because the alert was acknowledged.
+    pub outcome: ActionOutcome,
+    /// The error, or the quarantine id.
+    pub detail: Option<String>,
+}
+
+/// A playbook running for one alert.
+struct Run {
+    playbook: usize,
+    alert: Alert,
+    flow: Option<FlowEvent>,
+    triggered_at: DateTime<Utc>,
+    next_step: usize,
+    acknowledged: bool,
+}
+
+/// Runs playbooks for alerts. Driven by the caller: [`PlaybookEngine::trigger`]
+/// for each new alert, [`PlaybookEngine::run_due`] when
+/// [`PlaybookEngine::next_due`] comes, [`PlaybookEngine::set_alert_status`]
+/// on triage. Actions without their component configured fail and are
+/// logged as such.
+pub struct PlaybookEngine {
+    playbooks: Vec<Playbook>,
+    runs: Vec<Run>,
+    quarantine: Option<Arc<QuarantineManager>>,
+    throttler: Option<Throttler>,
+    sinkhole: Option<DnsSinkhole>,
+    notifier: Option<Notifier>,
+    log: Option<Box<dyn ActionLog>>,
+    events: Option<PolicyEventBus>,
+    subscribers: Vec<mpsc::Sender<Notification>>,
+}
+
+impl PlaybookEngine {
+    pub fn new(playbooks: Vec<Playbook>) -> Self {
+        Self {
+            playbooks,
+            runs: Vec::new(),
+            quarantine: None,
+            throttler: None,
+            sinkhole: None,
+            notifier: None,
+            log: None,
+            events: None,
+            subscribers: Vec::new(),
+        }
+    }
+
+    pub fn with_quarantine(mut self, manager: Arc<QuarantineManager>) -> Self {
+        self.quarantine = Some(manager);
+        self
+    }
+
+    pub fn with_throttler(mut self, throttler: Throttler) -> Self {
+        self.throttler = Some(throttler);
+        self
+    }
+
+    pub fn with_sinkhole(mut self, sinkhole: DnsSinkhole) -> Self {
+        self.sinkhole = Some(sinkhole);
+        self
+    }
+
+    pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    pub fn with_log(mut self, log: Box<dyn ActionLog>) -> Self {
+        self.log = Some(log);
+        self
+    }
+
+    /// Publishes the steps carried out on `bus`. Quarantine steps are left
+    /// to the manager's own events, under the quarantine id.
+    pub fn with_events(mut self, bus: PolicyEventBus) -> Self {
+        self.events = Some(bus);
+        self
+    }
+
+    pub fn playbooks(&self) -> &[Playbook] {
+        &self.playbooks
+    }
+
+    /// Receives the notifications of `notify` steps.
+    pub fn subscribe(&mut self) -> mpsc::Receiver<Notification> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Starts the first playbook matching `alert`, whose rule carries
+    /// `tags`, and runs its immediate steps. `flow` is what the actions
+    /// target.
+    pub fn trigger(
+        &mut self,
+        alert: &Alert,
+        tags: &[String],
+        flow: Option<&FlowEvent>,
+        now: DateTime<Utc>,
+    ) -> Vec<StepOutcome> {
+        let Some(playbook) = self
+            .playbooks
+            .iter()
+            .position(|playbook| playbook.matches.matches(alert, tags))
+        else {
+            return Vec::new();
+        };
+        if self.runs.iter().any(|run| run.alert.id == alert.id) {
+            return Vec::new();
+        }
+        tracing::info!(
+            playbook = %self.playbooks[playbook].id,
+            alert = %alert.id,
+            "playbook started"
+        );
+        self.runs.push(Run {
+            playbook,
+            alert: alert.clone(),
+            flow: flow.cloned(),
+            triggered_at: now,
+            next_step: 0,
+            acknowledged: alert.status != AlertStatus::New,
+        });
+        self.run_due(now)
+    }
+
+    /// Runs the steps due by `now`, in order.
+    pub fn run_due(&mut self, now: DateTime<Utc>) -> Vec<StepOutcome> {
+        let mut outcomes = Vec::new();
+        let mut runs = std::mem::take(&mut self.runs);
+        for run in &mut runs {
+            let steps = self.playbooks[run.playbook].steps.clone();
+            while let Some(step) = steps.get(run.next_step) {
+                match due_at(run.triggered_at, step.after_minutes) {
+                    Some(due) if due <= now => {}
+                    _ => break,
+                }
+                outcomes.push(self.execute(run, step));
+                run.next_step += 1;
+            }
+        }
+        runs.retain(|run| run.next_step < self.playbooks[run.playbook].steps.len());
+        self.runs = runs;
+        outcomes
+    }
+
+    /// When the next step is due, if any playbook is running.
+    pub fn next_due(&self) -> Option<DateTime<Utc>> {
+        self.runs
+            .iter()
+            .filter_map(|run| {
+                let step = self.playbooks[run.playbook].steps.get(run.next_step)?;
+                due_at(run.triggered_at, step.after_minutes)
+            })
+            .min()
+    }
+
+    /// Alerts whose playbooks still have steps to run, whose triage
+    /// status [`PlaybookEngine::set_alert_status`] should follow.
+    pub fn running_alerts(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.runs.iter().map(|run| run.alert.id.clone()).collect();
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+
+    /// Acknowledging skips the `unless_acknowledged` steps still to come;
+    /// resolving the alert or marking it a false positive stops its
+    /// playbook.
+    pub fn set_alert_status(&mut self, alert_id: &str, status: AlertStatus) {
+        match status {
+            AlertStatus::New => {}
+            AlertStatus::Acknowledged => {
+                for run in self.runs.iter_mut().filter(|run| run.alert.id == alert_id) {
+                    run.acknowledged = true;
+                }
+            }
+            AlertStatus::Resolved | AlertStatus::FalsePositive => {
+                self.runs.retain(|run| run.alert.id != alert_id);
+            }
+        }
+    }
+
+    fn execute(&self, run: &Run, step: &PlaybookStep) -> StepOutcome {
+        let playbook = &self.playbooks[run.playbook].id;
+        let alert = &run.alert;
+        let mut outcome = StepOutcome {
+            playbook: playbook.clone(),
+            alert_id: alert.id.clone(),
+            step: run.next_step,
+            action: step.action.kind().to_string(),
+            outcome: ActionOutcome::Applied,
+            detail: None,
+        };
+        let mut target = serde_json::Value::Null;
+        if step.unless_acknowledged && run.acknowledged {
+            outcome.outcome = ActionOutcome::RolledBack;
+            outcome.detail = Some("alert acknowledged".into());
+        } else {
+            match self.perform(run, &step.action, &mut target) {
+                Ok((result, detail)) => {
+                    outcome.outcome = result;
+                    outcome.detail = detail;
+                }
+                Err(err) => {
+                    outcome.outcome = ActionOutcome::Failed;
+                    outcome.detail = Some(format!("{err:#}"));
+                }
+            }
+        }
+        tracing::info!(?outcome, "playbook step");
+        self.audit(run, step, &outcome, target);
+        self.publish(&step.action, &outcome);
+        outcome
+    }
+
+    /// Carries out `action` for `run`, leaving what it targeted in
+    /// `target` for the audit log.
+    fn perform(
+        &self,
+        run: &Run,
+        action: &PlaybookAction,
+        target: &mut serde_json::Value,
+    ) -> Result<(ActionOutcome, Option<String>)> {
+        let alert = &run.alert;
+        let flow = || {
+            run.flow
+                .as_ref()
+                .ok_or_else(|| anyhow!("alert {} has no flow to act on", alert.id))
+        };
+        match action {
+            PlaybookAction::Notify { message, channels } => {
+                let notification = Notification {
+                    playbook: self.playbooks[run.playbook].id.clone(),
+                    alert_id: alert.id.clone(),
+                    severity: alert.severity.clone(),
+                    message: message.clone().unwrap_or_else(|| alert.summary.clone()),
+                };
+                *target = serde_json::json!({
+                    "message": notification.message,
+                    "channels": channels,
+                });
+                tracing::warn!(alert = %alert.id, "{}", notification.message);
+                for subscriber in &self.subscribers {
+                    let _ = subscriber.send(notification.clone());
+                }
+                if channels.is_empty() {
+                    return Ok((ActionOutcome::Applied, None));
+                }
+                let notifier = self
+                    .notifier
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("notification channels are not configured"))?;
+                let payload = NotificationPayload {
+                    message: notification.message,
+                    playbook: Some(notification.playbook),
+                    ..NotificationPayload::new(alert, run.flow.as_ref())
+                };
+                // One unreachable channel must not keep the others silent.
+                let failed: Vec<String> = channels
+                    .iter()
+                    .filter_map(|channel| notifier.send(channel, &payload).err())
+                    .map(|err| format!("{err:#}"))
+                    .collect();
+                if failed.is_empty() {
+                    Ok((ActionOutcome::Applied, None))
+                } else {
+                    Err(anyhow!(failed.join("; ")))
+                }
+            }
+            PlaybookAction::Throttle { rate_kbit } => {
+                let flow = flow()?;
+                let decision = ThrottleDecision {
+                    process: None,
+                    ports: vec![flow.dst_port],
+                    remote: flow.dst_ip.parse().ok(),
+                    rate_kbit: *rate_kbit,
+                };
+                *target = serde_json::to_value(&decision)?;
+                let throttler = self
+                    .throttler
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("throttling is not configured"))?;
+                throttler.apply(&decision)?;
+                Ok((ActionOutcome::Applied, None))
+            }
+            PlaybookAction::Quarantine {
+                expires_in_seconds,
+                suspend,
+            } => {
+                let decision = QuarantineDecision {
+                    suspend: *suspend,
+                    ..quarantine_decision_for(flow()?, *expires_in_seconds)
+                };
+                *target = serde_json::to_value(&decision)?;
+                let manager = self
+                    .quarantine
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("quarantine is not configured"))?;
+                let action = PolicyAction {
+                    id: alert.id.clone(),
+                    description: alert.summary.clone(),
+                    severity: alert.severity.clone(),
+                    quarantine: true,
+                };
+                Ok(match manager.submit(action, decision)? {
+                    Submission::Applied { quarantine } => {
+                        (ActionOutcome::Applied, Some(quarantine.id))
+                    }
+                    Submission::Pending { pending } => (ActionOutcome::Pending, Some(pending.id)),
+                })
+            }
+            PlaybookAction::Sinkhole { redirect_to } => {
+                let decision = SinkholeDecision {
+                    domains: domains_of(alert, run.flow.as_ref()),
+                    redirect_to: *redirect_to,
+                };
+                *target = serde_json::to_value(&decision)?;
+                if decision.domains.is_empty() {
+                    return Err(anyhow!("alert {} names no domain", alert.id));
+                }
+                let sinkhole = self
+                    .sinkhole
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("the DNS sinkhole is not configured"))?;
+                sinkhole.apply(&decision)?;
+                Ok((ActionOutcome::Applied, None))
+            }
+        }
+    }
+
+    fn publish(&self, action: &PlaybookAction, outcome: &StepOutcome) {
+        let Some(bus) = &self.events else {
+            return;
+        };
+        if let PlaybookAction::Quarantine { .. } = action {
+            return;
+        }
+        let id = format!("{}/{}/{}", outcome.playbook, outcome.alert_id, outcome.step);
+        let action = outcome.action.clone();
+        match outcome.outcome {
+            ActionOutcome::Applied => bus.publish(PolicyEvent::Applied {
+                id,
+                action,
+                expires_at: None,
+            }),
+            ActionOutcome::Failed => bus.publish(PolicyEvent::Failed {
+                id,
+                action,
+                error: outcome.detail.clone().unwrap_or_default(),
+            }),
+            _ => {}
+        }
+    }
+
+    fn audit(
+        &self,
+        run: &Run,
+        step: &PlaybookStep,
+        outcome: &StepOutcome,
+        target: serde_json::Value,
+    ) {
+        let Some(log) = &self.log else {
+            return;
+        };
+        let now = Utc::now();
+        let record = PolicyActionRecord {
+            id: 0,
+            ts: now,
+            action: outcome.action.clone(),
+            decision: serde_json::json!({
+                "playbook": outcome.playbook,
+                "step": outcome.step,
+                "after_minutes": step.after_minutes,
+                "target": target,
+            }),
+            backend: "playbook".into(),
+            rule_id: Some(run.alert.rule_id.clone()),
+            alert_id: Some(run.alert.id.clone()),
+            approved_by: None,
+            approved_at: None,
+            applied_at: (outcome.outcome == ActionOutcome::Applied).then_some(now),
+            rolled_back_at: None,
+            outcome: outcome.outcome,
+            error: match outcome.outcome {
+                ActionOutcome::Failed | ActionOutcome::RolledBack => outcome.detail.clone(),
+                _ => None,
+            },
+            commands: Vec::new(),
+        };
+        if let Err(err) = log.record(&record) {
+            tracing::warn!(playbook = %outcome.playbook, "recording playbook step failed: {err:#}");
+        }
+    }
+}
+
+/// When a step `after_minutes` past `triggered_at` falls due; `None` when
+/// that is past the end of time, for playbooks built without
+/// [`load_playbooks_from_str`].
+fn due_at(triggered_at: DateTime<Utc>, after_minutes: u64) -> Option<DateTime<Utc>> {
+    let after = ChronoDuration::try_minutes(i64::try_from(after_minutes).ok()?)?;
+    triggered_at.checked_add_signed(after)
+}
+
+/// Domains to sink for an alert: those of its flow, else its evidence.
+fn domains_of(alert: &Alert, flow: Option<&FlowEvent>) -> Vec<String> {
+    let mut domains: Vec<String> = flow
+        .into_iter()
+        .flat_map(|flow| [flow.sni.clone(), flow.dns_qname.clone()])
+        .flatten()
+        .collect();
+    if domains.is_empty() {
+        domains.extend(
+            DOMAIN_EVIDENCE
+                .iter()
+                .filter_map(|key| alert.evidence.get(*key).cloned()),
+        );
+    }
+    domains.dedup();
+    domains
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use storage::{PolicyActionQuery, Storage};
+
+    use super::*;
+    use crate::command::ScriptedRunner;
+    use crate::{ApprovalConfig, NoopBackend, ThrottleMethod};
+
+    const PLAYBOOKS: &str = r#"
+- id: smb-containment
+  match:
+    rule_ids: ["smb-*"]
+    min_severity: Medium
+  steps:
+    - action: notify
+    - action: throttle
+      rate_kbit: 32
+    - action: quarantine
+      after_minutes: 10
+      unless_acknowledged: true
+      expires_in_seconds: 3600
+"#;
+
+    fn alert(id: &str) -> Alert {
+        Alert {
+            id: id.into(),
+            ts: Utc::now(),
+            severity: Severity::High,
+            rule_id: "smb-lateral".into(),
+            summary: "SMB to a new host".into(),
+            flow_refs: Vec::new(),
+            process_ref: None,
+            rationale: String::new(),
+            suggested_action: None,
+            status: AlertStatus::New,
+            assignee: None,
+            notes: Vec::new(),
+            evidence: Default::default(),
+        }
+    }
+
+    #[test]
+    fn escalates_unless_acknowledged() {
+        let playbooks = load_playbooks_from_str(PLAYBOOKS).unwrap();
+        load_playbooks_from_str(include_str!("../../../rules/playbooks.yaml")).unwrap();
+        assert!(load_playbooks_from_str(
+            "- id: x\n  steps:\n    - action: notify\n      after_minutes: 5\n    - action: notify\n"
+        )
+        .is_err());
+        let endless =
+            "- id: x\n  steps:\n    - action: notify\n      after_minutes: 18446744073709551615\n";
+        assert!(load_playbooks_from_str(endless).is_err());
+        let mut never = PlaybookEngine::new(serde_yaml::from_str(endless).unwrap());
+        assert!(never
+            .trigger(&alert("a0"), &[], None, Utc::now())
+            .is_empty());
+        assert_eq!(never.next_due(), None);
+        assert!(never.run_due(DateTime::<Utc>::MAX_UTC).is_empty());
+
+        let runner = Arc::new(ScriptedRunner::new(|_| Ok(String::new())));
+        let manager = Arc::new(QuarantineManager::new(Box::new(NoopBackend)).unwrap());
+        manager.set_approval_config(ApprovalConfig {
+            confirmation_required: false,
+            ..ApprovalConfig::default()
+        });
+        let storage = Arc::new(Mutex::new(Storage::open(":memory:", &[3u8; 32]).unwrap()));
+        let mut engine = PlaybookEngine::new(playbooks)
+            .with_quarantine(manager.clone())
+            .with_throttler(Throttler::with_runner(
+                ThrottleMethod::Qos,
+                64,
+                Box::new(runner.clone()),
+            ))
+            .with_log(Box::new(storage.clone()));
+        let notifications = engine.subscribe();
+        let flow = FlowEvent {
+            dst_ip: "10.0.0.9".into(),
+            dst_port: 445,
+            ..FlowEvent::default()
+        };
+
+        let now = Utc::now();
+        let first = engine.trigger(&alert("a1"), &[], Some(&flow), now);
+        assert_eq!(
+            first
+                .iter()
+                .map(|o| (o.action.as_str(), o.outcome))
+                .collect::<Vec<_>>(),
+            [
+                ("notify", ActionOutcome::Applied),
+                ("throttle", ActionOutcome::Applied)
+            ]
+        );
+        assert_eq!(
+            notifications.try_recv().unwrap().message,
+            "SMB to a new host"
+        );
+        assert!(runner
+            .calls()
+            .iter()
+            .any(|call| call.contains("-IPDstPortMatchCondition 445")));
+
+        engine.trigger(&alert("a2"), &[], Some(&flow), now);
+        engine.set_alert_status("a2", AlertStatus::Acknowledged);
+        assert_eq!(engine.next_due(), Some(now + ChronoDuration::minutes(10)));
+        assert!(engine.run_due(now + ChronoDuration::minutes(9)).is_empty());
+
+        let later = engine.run_due(now + ChronoDuration::minutes(10));
+        assert_eq!(later.len(), 2);
+        assert_eq!(later[0].outcome, ActionOutcome::Applied);
+        assert_eq!(later[1].outcome, ActionOutcome::RolledBack);
+        assert_eq!(manager.active().len(), 1);
+        assert_eq!(engine.next_due(), None);
+
+        let audited = storage
+            .lock()
+            .unwrap()
+            .query_policy_actions(&PolicyActionQuery::default())
+            .unwrap();
+        assert_eq!(audited.len(), 6);
+        assert!(audited.iter().all(|record| record.backend == "playbook"));
+    }
+}
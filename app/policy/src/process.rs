@@ -0,0 +1,138 @@
+//! Suspending and resuming processes: the program is frozen, its sockets
+//! and memory left intact for inspection, and carries on where it stopped
+//! once resumed. SIGSTOP/SIGCONT on Unix, `NtSuspendProcess` and
+//! `NtResumeProcess` on Windows.
+
+use anyhow::{anyhow, Result};
+
+/// Refuses the processes nothing may stop: the daemon itself and pid 1.
+/// The full [`crate::Guardrails`] are checked with the decision.
+fn check_pid(pid: i32) -> Result<()> {
+    if pid <= 1 || pid as u32 == std::process::id() {
+        return Err(anyhow!("refusing to suspend pid {pid}"));
+    }
+    Ok(())
+}
+
+pub fn suspend_process(pid: i32) -> Result<()> {
+    check_pid(pid)?;
+    imp::suspend(pid)?;
+    tracing::info!(pid, "process suspended");
+    Ok(())
+}
+
+pub fn resume_process(pid: i32) -> Result<()> {
+    check_pid(pid)?;
+    imp::resume(pid)?;
+    tracing::info!(pid, "process resumed");
+    Ok(())
+}
+
+#[cfg(unix)]
+mod imp {
+    use anyhow::Result;
+
+    use crate::command::{CommandRunner, SystemRunner};
+
+    pub fn suspend(pid: i32) -> Result<()> {
+        signal(pid, "STOP")
+    }
+
+    pub fn resume(pid: i32) -> Result<()> {
+        signal(pid, "CONT")
+    }
+
+    fn signal(pid: i32, signal: &str) -> Result<()> {
+        SystemRunner.run("kill", &[&format!("-{signal}"), &pid.to_string()])?;
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use anyhow::{anyhow, Result};
+    use windows_sys::Win32::{
+        Foundation::{CloseHandle, GetLastError, HANDLE, NTSTATUS},
+        System::Threading::{OpenProcess, PROCESS_SUSPEND_RESUME},
+    };
+
+    // Undocumented but stable since Windows XP; what Process Explorer and
+    // Sysinternals' pssuspend use. Suspends every thread at once, unlike
+    // looping over SuspendThread.
+    #[link(name = "ntdll")]
+    extern "system" {
+        fn NtSuspendProcess(process: HANDLE) -> NTSTATUS;
+        fn NtResumeProcess(process: HANDLE) -> NTSTATUS;
+    }
+
+    pub fn suspend(pid: i32) -> Result<()> {
+        with_process(pid, "NtSuspendProcess", |handle| unsafe {
+            NtSuspendProcess(handle)
+        })
+    }
+
+    pub fn resume(pid: i32) -> Result<()> {
+        with_process(pid, "NtResumeProcess", |handle| unsafe {
+            NtResumeProcess(handle)
+        })
+    }
+
+    fn with_process(pid: i32, call: &str, f: impl FnOnce(HANDLE) -> NTSTATUS) -> Result<()> {
+        // SAFETY: plain Win32 call; the handle is closed below.
+        let handle = unsafe { OpenProcess(PROCESS_SUSPEND_RESUME, 0, pid as u32) };
+        if handle.is_null() {
+            let error = unsafe { GetLastError() };
+            return Err(anyhow!("OpenProcess({pid}) failed: error {error}"));
+        }
+        let status = f(handle);
+        unsafe { CloseHandle(handle) };
+        if status < 0 {
+            return Err(anyhow!("{call}({pid}) failed: NTSTATUS {status:#x}"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+
+    fn state(pid: u32) -> char {
+        let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).unwrap();
+        // The state follows the parenthesised command name.
+        stat.rsplit(')')
+            .next()
+            .unwrap()
+            .trim()
+            .chars()
+            .next()
+            .unwrap()
+    }
+
+    /// Whether the process is stopped, once the signal has been handled:
+    /// `kill` returns as soon as it is queued.
+    fn stopped(pid: u32, expected: bool) -> bool {
+        for _ in 0..100 {
+            if (state(pid) == 'T') == expected {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        state(pid) == 'T'
+    }
+
+    #[test]
+    fn stops_and_continues_a_process() {
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        let pid = child.id();
+        suspend_process(pid as i32).unwrap();
+        assert!(stopped(pid, true));
+        resume_process(pid as i32).unwrap();
+        assert!(!stopped(pid, false));
+        assert!(suspend_process(std::process::id() as i32).is_err());
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
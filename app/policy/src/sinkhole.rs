@@ -0,0 +1,317 @@
+//! DNS sinkhole: answers for flagged domains are redirected to a sink
+//! address (or an analysis host) through the hosts file or a local
+//! dnsmasq, so the name stops resolving to the attacker's infrastructure
+//! whatever address it moves to.
+
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::command::{CommandRunner, SystemRunner};
+
+/// Marks the hosts file lines the sinkhole owns.
+const SINKHOLE_TAG: &str = "nets:sinkhole";
+const DNSMASQ_DROP_IN: &str = "/etc/dnsmasq.d/nets-sinkhole.conf";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SinkholeDecision {
+    pub domains: Vec<String>,
+    /// Answer for the domains; sunk to `0.0.0.0` and `::` when unset.
+    #[serde(default)]
+    pub redirect_to: Option<IpAddr>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkholeKind {
+    /// dnsmasq when its drop-in directory exists, else the hosts file.
+    #[default]
+    Auto,
+    Hosts,
+    Dnsmasq,
+}
+
+/// Mirrors `[policy.sinkhole]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SinkholeConfig {
+    pub method: SinkholeKind,
+    /// Defaults to the platform's hosts file.
+    pub hosts_path: Option<PathBuf>,
+    /// Defaults to `/etc/dnsmasq.d/nets-sinkhole.conf`.
+    pub dnsmasq_conf: Option<PathBuf>,
+}
+
+/// Where sinkhole entries are written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SinkholeMethod {
+    /// Tagged entries in the hosts file. Matches the exact names only.
+    HostsFile(PathBuf),
+    /// `address=` rules in a dnsmasq drop-in owned by nets. Subdomains are
+    /// sunk too.
+    Dnsmasq(PathBuf),
+}
+
+impl SinkholeMethod {
+    pub fn from_config(config: &SinkholeConfig) -> Self {
+        let dnsmasq = config
+            .dnsmasq_conf
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DNSMASQ_DROP_IN));
+        let hosts = config.hosts_path.clone().unwrap_or_else(hosts_file);
+        match config.method {
+            SinkholeKind::Hosts => SinkholeMethod::HostsFile(hosts),
+            SinkholeKind::Dnsmasq => SinkholeMethod::Dnsmasq(dnsmasq),
+            SinkholeKind::Auto if dnsmasq.parent().is_some_and(Path::is_dir) => {
+                SinkholeMethod::Dnsmasq(dnsmasq)
+            }
+            SinkholeKind::Auto => SinkholeMethod::HostsFile(hosts),
+        }
+    }
+
+    /// Line sinking `domain` to `addr`.
+    fn line(&self, domain: &str, addr: IpAddr) -> String {
+        match self {
+            SinkholeMethod::HostsFile(_) => format!("{addr} {domain} # {SINKHOLE_TAG}"),
+            SinkholeMethod::Dnsmasq(_) => format!("address=/{domain}/{addr}"),
+        }
+    }
+
+    /// Domain of a sinkhole line, `None` for lines nets does not own.
+    fn domain_of<'a>(&self, line: &'a str) -> Option<&'a str> {
+        match self {
+            SinkholeMethod::HostsFile(_) => {
+                let entry = line.strip_suffix(&format!("# {SINKHOLE_TAG}"))?;
+                entry.split_whitespace().nth(1)
+            }
+            SinkholeMethod::Dnsmasq(_) => line.strip_prefix("address=/")?.split('/').next(),
+        }
+    }
+
+    fn path(&self) -> &Path {
+        match self {
+            SinkholeMethod::HostsFile(path) | SinkholeMethod::Dnsmasq(path) => path,
+        }
+    }
+}
+
+fn hosts_file() -> PathBuf {
+    if cfg!(windows) {
+        let root = std::env::var("SystemRoot").unwrap_or_else(|_| r"C:\Windows".into());
+        Path::new(&root).join(r"System32\drivers\etc\hosts")
+    } else {
+        PathBuf::from("/etc/hosts")
+    }
+}
+
+/// Sinks domains flagged by the analyzer, e.g. from a playbook.
+pub struct DnsSinkhole {
+    method: SinkholeMethod,
+    runner: Box<dyn CommandRunner>,
+}
+
+impl DnsSinkhole {
+    pub fn new(method: SinkholeMethod) -> Self {
+        Self::with_runner(method, Box::new(SystemRunner))
+    }
+
+    pub fn with_runner(method: SinkholeMethod, runner: Box<dyn CommandRunner>) -> Self {
+        Self { method, runner }
+    }
+
+    pub fn method(&self) -> &SinkholeMethod {
+        &self.method
+    }
+
+    /// Domains currently sunk.
+    pub fn list(&self) -> Result<Vec<String>> {
+        let mut domains: Vec<String> = self
+            .read()?
+            .lines()
+            .filter_map(|line| self.method.domain_of(line))
+            .map(String::from)
+            .collect();
+        domains.dedup();
+        Ok(domains)
+    }
+
+    /// Sinks `decision.domains`, replacing earlier entries for them.
+    pub fn apply(&self, decision: &SinkholeDecision) -> Result<()> {
+        let domains = normalized_domains(&decision.domains)?;
+        let addrs = match decision.redirect_to {
+            Some(addr) => vec![addr],
+            None => vec![
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            ],
+        };
+        let mut lines = self.kept_lines(&domains)?;
+        for domain in &domains {
+            lines.extend(addrs.iter().map(|addr| self.method.line(domain, *addr)));
+        }
+        self.write(&lines)?;
+        tracing::info!(?domains, redirect_to = ?decision.redirect_to, "domains sinkholed");
+        Ok(())
+    }
+
+    pub fn rollback(&self, decision: &SinkholeDecision) -> Result<()> {
+        let domains = normalized_domains(&decision.domains)?;
+        let lines = self.kept_lines(&domains)?;
+        self.write(&lines)?;
+        tracing::info!(?domains, "sinkhole lifted");
+        Ok(())
+    }
+
+    fn read(&self) -> Result<String> {
+        match fs::read_to_string(self.method.path()) {
+            Ok(contents) => Ok(contents),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+            Err(err) => {
+                Err(err).with_context(|| format!("reading {}", self.method.path().display()))
+            }
+        }
+    }
+
+    /// Current lines, minus the sinkhole entries for `domains`.
+    fn kept_lines(&self, domains: &[String]) -> Result<Vec<String>> {
+        Ok(self
+            .read()?
+            .lines()
+            .filter(|line| {
+                self.method
+                    .domain_of(line)
+                    .is_none_or(|domain| !domains.iter().any(|d| d == domain))
+            })
+            .map(String::from)
+            .collect())
+    }
+
+    /// Replaces the file through a rename, so resolvers never read half of
+    /// it, then makes the resolver pick the change up.
+    fn write(&self, lines: &[String]) -> Result<()> {
+        let path = self.method.path();
+        let staging = path.with_extension("nets-tmp");
+        let contents = lines.join("\n") + "\n";
+        fs::write(&staging, contents).with_context(|| format!("writing {}", staging.display()))?;
+        fs::rename(&staging, path).with_context(|| format!("replacing {}", path.display()))?;
+        self.reload()
+    }
+
+    fn reload(&self) -> Result<()> {
+        if let SinkholeMethod::Dnsmasq(_) = self.method {
+            // SIGHUP rereads hosts files only, not `address=` rules.
+            self.runner.run("systemctl", &["restart", "dnsmasq"])?;
+            return Ok(());
+        }
+        let flushes: &[(&str, &[&str])] = if cfg!(windows) {
+            &[("ipconfig", &["/flushdns"])]
+        } else if cfg!(target_os = "macos") {
+            &[
+                ("dscacheutil", &["-flushcache"]),
+                ("killall", &["-HUP", "mDNSResponder"]),
+            ]
+        } else {
+            &[("resolvectl", &["flush-caches"])]
+        };
+        // Without a caching resolver there is nothing to flush.
+        for (program, args) in flushes {
+            if let Err(err) = self.runner.run(program, args) {
+                tracing::debug!("flushing the DNS cache: {err:#}");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Lowercased names without the root dot; refuses anything that is not a
+/// plain domain name, which would corrupt the file it is written to.
+fn normalized_domains(domains: &[String]) -> Result<Vec<String>> {
+    if domains.is_empty() {
+        return Err(anyhow!("sinkhole needs at least one domain"));
+    }
+    let mut normalized = Vec::with_capacity(domains.len());
+    for domain in domains {
+        let domain = domain.trim().trim_end_matches('.').to_ascii_lowercase();
+        let valid = !domain.is_empty()
+            && domain.len() <= 253
+            && domain.contains('.')
+            && domain.split('.').all(|label| {
+                !label.is_empty()
+                    && label.len() <= 63
+                    && !label.starts_with('-')
+                    && label
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            });
+        if !valid {
+            return Err(anyhow!("not a domain name: {domain:?}"));
+        }
+        if !normalized.contains(&domain) {
+            normalized.push(domain);
+        }
+    }
+    Ok(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::command::ScriptedRunner;
+
+    #[test]
+    fn sinks_and_restores_domains_in_the_hosts_file() {
+        let dir = std::env::temp_dir().join(format!("nets-sinkhole-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let hosts = dir.join("hosts");
+        fs::write(&hosts, "127.0.0.1 localhost\n").unwrap();
+        let runner = Arc::new(ScriptedRunner::new(|_| Ok(String::new())));
+        let sinkhole = DnsSinkhole::with_runner(
+            SinkholeMethod::HostsFile(hosts.clone()),
+            Box::new(runner.clone()),
+        );
+
+        let decision = SinkholeDecision {
+            domains: vec!["Evil.example.".into(), "c2.example".into()],
+            redirect_to: None,
+        };
+        sinkhole.apply(&decision).unwrap();
+        sinkhole
+            .apply(&SinkholeDecision {
+                domains: vec!["c2.example".into()],
+                redirect_to: Some("10.0.0.99".parse().unwrap()),
+            })
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&hosts).unwrap(),
+            "127.0.0.1 localhost\n\
+             0.0.0.0 evil.example # nets:sinkhole\n\
+             :: evil.example # nets:sinkhole\n\
+             10.0.0.99 c2.example # nets:sinkhole\n"
+        );
+        assert_eq!(sinkhole.list().unwrap(), ["evil.example", "c2.example"]);
+        assert!(!runner.calls().is_empty());
+
+        sinkhole.rollback(&decision).unwrap();
+        assert_eq!(fs::read_to_string(&hosts).unwrap(), "127.0.0.1 localhost\n");
+        assert!(sinkhole
+            .apply(&SinkholeDecision {
+                domains: vec!["evil.example\n1.2.3.4 bank.example".into()],
+                redirect_to: None,
+            })
+            .is_err());
+
+        let dnsmasq = SinkholeMethod::Dnsmasq(dir.join("nets-sinkhole.conf"));
+        assert_eq!(
+            dnsmasq.domain_of(&dnsmasq.line("evil.example", IpAddr::V4(Ipv4Addr::UNSPECIFIED))),
+            Some("evil.example")
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
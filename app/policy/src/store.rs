@@ -0,0 +1,67 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use storage::{PolicyActionRecord, QuarantineRecord, Storage};
+
+/// Where [`crate::QuarantineManager`] keeps active quarantines, so they can
+/// be re-applied or lifted after a crash or reboot.
+pub trait QuarantineStore: Send + Sync {
+    fn save(&self, record: &QuarantineRecord) -> Result<()>;
+    fn remove(&self, id: &str) -> Result<()>;
+    fn load(&self) -> Result<Vec<QuarantineRecord>>;
+}
+
+/// The policy action audit table, where [`crate::DryRunBackend`] records
+/// what it would have enforced.
+pub trait ActionLog: Send + Sync {
+    fn record(&self, record: &PolicyActionRecord) -> Result<i64>;
+}
+
+impl ActionLog for Mutex<Storage> {
+    fn record(&self, record: &PolicyActionRecord) -> Result<i64> {
+        self.lock()
+            .map_err(|_| anyhow!("storage lock poisoned"))?
+            .record_policy_action(record)
+    }
+}
+
+impl<L: ActionLog + ?Sized> ActionLog for Arc<L> {
+    fn record(&self, record: &PolicyActionRecord) -> Result<i64> {
+        (**self).record(record)
+    }
+}
+
+impl QuarantineStore for Mutex<Storage> {
+    fn save(&self, record: &QuarantineRecord) -> Result<()> {
+        self.lock()
+            .map_err(|_| anyhow!("storage lock poisoned"))?
+            .put_quarantine(record)
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        self.lock()
+            .map_err(|_| anyhow!("storage lock poisoned"))?
+            .delete_quarantine(id)?;
+        Ok(())
+    }
+
+    fn load(&self) -> Result<Vec<QuarantineRecord>> {
+        self.lock()
+            .map_err(|_| anyhow!("storage lock poisoned"))?
+            .list_quarantines()
+    }
+}
+
+impl<S: QuarantineStore + ?Sized> QuarantineStore for Arc<S> {
+    fn save(&self, record: &QuarantineRecord) -> Result<()> {
+        (**self).save(record)
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        (**self).remove(id)
+    }
+
+    fn load(&self) -> Result<Vec<QuarantineRecord>> {
+        (**self).load()
+    }
+}
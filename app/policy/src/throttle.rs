@@ -0,0 +1,405 @@
+//! Throttling: a softer response than quarantine. Matching outbound traffic
+//! is rate-limited to a trickle so a suspicious but unconfirmed flow keeps
+//! working, slowly, while the user investigates.
+
+use std::net::IpAddr;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::command::{powershell_quote, CommandRunner, SystemRunner};
+
+/// Root qdiscs a kernel installs by itself; anything else was configured
+/// by someone and is not replaced.
+const DEFAULT_QDISCS: [&str; 6] = ["noqueue", "pfifo_fast", "fq_codel", "mq", "fq", "pfifo"];
+const QOS_POLICY_PREFIX: &str = "nets-throttle";
+
+/// Mirrors `[policy.throttle]`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThrottleConfig {
+    /// Interface shaped by tc; the one with the default route when unset.
+    pub interface: Option<String>,
+    pub default_rate_kbit: u32,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        Self {
+            interface: None,
+            default_rate_kbit: 64,
+        }
+    }
+}
+
+/// Outbound traffic to throttle: to `remote`, to any of `ports`, or both.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThrottleDecision {
+    /// Executable name or path. Only Windows QoS policies match processes.
+    #[serde(default)]
+    pub process: Option<String>,
+    #[serde(default)]
+    pub ports: Vec<u16>,
+    #[serde(default)]
+    pub remote: Option<IpAddr>,
+    /// `default_rate_kbit` of the config when unset.
+    #[serde(default)]
+    pub rate_kbit: Option<u32>,
+}
+
+impl ThrottleDecision {
+    /// Stable across restarts and rate changes, so a throttle is found
+    /// again to be updated or lifted: FNV-1a of the match, folded into
+    /// the tc class/priority range.
+    fn id(&self) -> u16 {
+        let key = format!("{:?}|{:?}|{:?}", self.process, self.ports, self.remote);
+        let hash = key.bytes().fold(0x811c_9dc5u32, |hash, byte| {
+            (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+        });
+        (hash % 0xfff0) as u16 + 0x10
+    }
+
+    /// Port conditions, `None` standing for any port.
+    fn port_matches(&self) -> Vec<Option<u16>> {
+        if self.ports.is_empty() {
+            vec![None]
+        } else {
+            self.ports.iter().copied().map(Some).collect()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ThrottleMethod {
+    /// An HTB class per throttle under an `htb` root qdisc, fed by u32
+    /// filters. Traffic the filters miss is not shaped.
+    Tc { interface: String },
+    /// Windows QoS policies in the active (non-persistent) store.
+    Qos,
+}
+
+pub struct Throttler {
+    method: ThrottleMethod,
+    default_rate_kbit: u32,
+    runner: Box<dyn CommandRunner>,
+}
+
+impl Throttler {
+    /// tc on Linux, QoS policies on Windows.
+    pub fn new(config: &ThrottleConfig) -> Result<Self> {
+        let method = if cfg!(target_os = "linux") {
+            let interface = match &config.interface {
+                Some(interface) => interface.clone(),
+                None => default_interface()
+                    .ok_or_else(|| anyhow!("no default route to pick an interface to shape"))?,
+            };
+            ThrottleMethod::Tc { interface }
+        } else if cfg!(windows) {
+            ThrottleMethod::Qos
+        } else {
+            return Err(anyhow!("throttling is not supported on this platform"));
+        };
+        Ok(Self::with_runner(
+            method,
+            config.default_rate_kbit,
+            Box::new(SystemRunner),
+        ))
+    }
+
+    pub fn with_runner(
+        method: ThrottleMethod,
+        default_rate_kbit: u32,
+        runner: Box<dyn CommandRunner>,
+    ) -> Self {
+        Self {
+            method,
+            default_rate_kbit,
+            runner,
+        }
+    }
+
+    /// Installs the throttle, replacing an earlier one for the same match.
+    pub fn apply(&self, decision: &ThrottleDecision) -> Result<()> {
+        if decision.ports.is_empty() && decision.remote.is_none() && decision.process.is_none() {
+            return Err(anyhow!(
+                "throttle needs a port, a remote address or a process"
+            ));
+        }
+        let rate_kbit = decision.rate_kbit.unwrap_or(self.default_rate_kbit);
+        if rate_kbit == 0 {
+            return Err(anyhow!(
+                "throttle rate must be positive; quarantine to block"
+            ));
+        }
+        match &self.method {
+            ThrottleMethod::Tc { interface } => self.apply_tc(interface, decision, rate_kbit)?,
+            ThrottleMethod::Qos => self.apply_qos(decision, rate_kbit)?,
+        }
+        tracing::info!(
+            id = decision.id(),
+            rate_kbit,
+            ?decision,
+            "traffic throttled"
+        );
+        Ok(())
+    }
+
+    pub fn rollback(&self, decision: &ThrottleDecision) -> Result<()> {
+        match &self.method {
+            ThrottleMethod::Tc { interface } => self.rollback_tc(interface, decision)?,
+            ThrottleMethod::Qos => self.remove_qos(decision)?,
+        }
+        tracing::info!(id = decision.id(), "throttle lifted");
+        Ok(())
+    }
+
+    fn apply_tc(&self, interface: &str, decision: &ThrottleDecision, rate_kbit: u32) -> Result<()> {
+        if decision.process.is_some() {
+            return Err(anyhow!(
+                "tc cannot match processes; throttle by port or remote address"
+            ));
+        }
+        let root = self
+            .runner
+            .run("tc", &["qdisc", "show", "dev", interface, "root"])?;
+        let kind = root.split_whitespace().nth(1).unwrap_or("noqueue");
+        if kind == "htb" {
+            if !root.contains("htb 1:") {
+                return Err(anyhow!(
+                    "{interface} already has an htb root qdisc of its own"
+                ));
+            }
+        } else if DEFAULT_QDISCS.contains(&kind) {
+            self.runner.run(
+                "tc",
+                &[
+                    "qdisc", "replace", "dev", interface, "root", "handle", "1:", "htb",
+                ],
+            )?;
+        } else {
+            return Err(anyhow!(
+                "{interface} has a configured {kind} root qdisc, not replacing it"
+            ));
+        }
+
+        let id = decision.id();
+        let classid = format!("1:{id:x}");
+        let prio = id.to_string();
+        let rate = format!("{rate_kbit}kbit");
+        self.runner.run(
+            "tc",
+            &[
+                "class", "replace", "dev", interface, "parent", "1:", "classid", &classid, "htb",
+                "rate", &rate, "ceil", &rate,
+            ],
+        )?;
+        // Filters of a previous apply, e.g. with another rate.
+        let _ = self.runner.run(
+            "tc",
+            &[
+                "filter", "del", "dev", interface, "parent", "1:", "prio", &prio,
+            ],
+        );
+
+        let families: &[&str] = match decision.remote {
+            Some(IpAddr::V4(_)) => &["ip"],
+            Some(IpAddr::V6(_)) => &["ip6"],
+            None => &["ip", "ip6"],
+        };
+        for family in families {
+            let protocol = if *family == "ip" { "ip" } else { "ipv6" };
+            for port in decision.port_matches() {
+                let mut selectors = Vec::new();
+                if let Some(remote) = decision.remote {
+                    let prefix = if remote.is_ipv4() { 32 } else { 128 };
+                    selectors.extend(["match".into(), family.to_string(), "dst".into()]);
+                    selectors.push(format!("{remote}/{prefix}"));
+                }
+                if let Some(port) = port {
+                    selectors.extend(["match".into(), family.to_string(), "dport".into()]);
+                    selectors.extend([port.to_string(), "0xffff".into()]);
+                }
+                let mut args = vec![
+                    "filter", "add", "dev", interface, "parent", "1:", "protocol", protocol,
+                    "prio", &prio, "u32",
+                ];
+                args.extend(selectors.iter().map(String::as_str));
+                args.extend(["flowid", &classid]);
+                self.runner.run("tc", &args)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rollback_tc(&self, interface: &str, decision: &ThrottleDecision) -> Result<()> {
+        let id = decision.id();
+        self.runner.run(
+            "tc",
+            &[
+                "filter",
+                "del",
+                "dev",
+                interface,
+                "parent",
+                "1:",
+                "prio",
+                &id.to_string(),
+            ],
+        )?;
+        self.runner.run(
+            "tc",
+            &[
+                "class",
+                "del",
+                "dev",
+                interface,
+                "classid",
+                &format!("1:{id:x}"),
+            ],
+        )?;
+        // The last throttle gone, the kernel's default qdisc comes back.
+        let classes = self
+            .runner
+            .run("tc", &["class", "show", "dev", interface])?;
+        if classes.trim().is_empty() {
+            self.runner
+                .run("tc", &["qdisc", "del", "dev", interface, "root"])?;
+        }
+        Ok(())
+    }
+
+    /// One policy per port, as a QoS policy matches a single port.
+    fn apply_qos(&self, decision: &ThrottleDecision, rate_kbit: u32) -> Result<()> {
+        self.remove_qos(decision)?;
+        let name = format!("{QOS_POLICY_PREFIX}-{:04x}", decision.id());
+        for port in decision.port_matches() {
+            let mut script = format!(
+                "New-NetQosPolicy -Name '{name}-{}' -PolicyStore ActiveStore \
+                 -ThrottleRateActionBitsPerSecond {} -Confirm:$false",
+                port.unwrap_or(0),
+                u64::from(rate_kbit) * 1000,
+            );
+            if let Some(port) = port {
+                script.push_str(&format!(" -IPDstPortMatchCondition {port}"));
+            }
+            if let Some(remote) = decision.remote {
+                let prefix = if remote.is_ipv4() { 32 } else { 128 };
+                script.push_str(&format!(" -IPDstPrefixMatchCondition {remote}/{prefix}"));
+            }
+            if let Some(process) = &decision.process {
+                script.push_str(&format!(
+                    " -AppPathNameMatchCondition {}",
+                    powershell_quote(process)
+                ));
+            }
+            self.powershell(&script)?;
+        }
+        Ok(())
+    }
+
+    fn remove_qos(&self, decision: &ThrottleDecision) -> Result<()> {
+        self.powershell(&format!(
+            "Get-NetQosPolicy -PolicyStore ActiveStore -Name '{QOS_POLICY_PREFIX}-{:04x}-*' \
+             | Remove-NetQosPolicy -PolicyStore ActiveStore -Confirm:$false",
+            decision.id()
+        ))
+    }
+
+    fn powershell(&self, script: &str) -> Result<()> {
+        self.runner.run(
+            "powershell",
+            &["-NoProfile", "-NonInteractive", "-Command", script],
+        )?;
+        Ok(())
+    }
+}
+
+/// Interface of the IPv4 default route in `/proc/net/route`.
+fn default_interface() -> Option<String> {
+    let routes = std::fs::read_to_string("/proc/net/route").ok()?;
+    routes.lines().skip(1).find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let interface = fields.next()?;
+        (fields.next() == Some("00000000")).then(|| interface.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::command::ScriptedRunner;
+
+    #[test]
+    fn shapes_matching_traffic_with_an_htb_class() {
+        let runner = Arc::new(ScriptedRunner::new(|line| {
+            Ok(match line {
+                "tc qdisc show dev eth0 root" => "qdisc fq_codel 0: root refcnt 2 limit 10240p\n",
+                _ => "",
+            }
+            .to_string())
+        }));
+        let throttler = Throttler::with_runner(
+            ThrottleMethod::Tc {
+                interface: "eth0".into(),
+            },
+            64,
+            Box::new(runner.clone()),
+        );
+        let decision = ThrottleDecision {
+            process: None,
+            ports: vec![443],
+            remote: Some("203.0.113.7".parse().unwrap()),
+            rate_kbit: None,
+        };
+        throttler.apply(&decision).unwrap();
+        throttler.rollback(&decision).unwrap();
+
+        let id = decision.id();
+        assert_eq!(
+            runner.calls(),
+            [
+                "tc qdisc show dev eth0 root".to_string(),
+                "tc qdisc replace dev eth0 root handle 1: htb".into(),
+                format!("tc class replace dev eth0 parent 1: classid 1:{id:x} htb rate 64kbit ceil 64kbit"),
+                format!("tc filter del dev eth0 parent 1: prio {id}"),
+                format!(
+                    "tc filter add dev eth0 parent 1: protocol ip prio {id} u32 \
+                     match ip dst 203.0.113.7/32 match ip dport 443 0xffff flowid 1:{id:x}"
+                ),
+                format!("tc filter del dev eth0 parent 1: prio {id}"),
+                format!("tc class del dev eth0 classid 1:{id:x}"),
+                "tc class show dev eth0".into(),
+                "tc qdisc del dev eth0 root".into(),
+            ]
+        );
+        assert!(throttler
+            .apply(&ThrottleDecision {
+                process: Some("agent".into()),
+                ..decision
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn quotes_process_paths_in_qos_policies() {
+        let runner = Arc::new(ScriptedRunner::new(|_| Ok(String::new())));
+        let throttler = Throttler::with_runner(ThrottleMethod::Qos, 64, Box::new(runner.clone()));
+        let decision = ThrottleDecision {
+            // Each of these ends a single-quoted PowerShell string.
+            process: Some("C:\\a'b\u{2018}c\u{2019}d\u{201a}e\u{201b}f.exe".into()),
+            ports: Vec::new(),
+            remote: None,
+            rate_kbit: None,
+        };
+        throttler.apply(&decision).unwrap();
+        let calls = runner.calls();
+        assert!(
+            calls[1].ends_with(
+                " -AppPathNameMatchCondition 'C:\\a''b\u{2018}\u{2018}c\u{2019}\u{2019}d\u{201a}\u{201a}e\u{201b}\u{201b}f.exe'"
+            ),
+            "{calls:?}"
+        );
+    }
+}
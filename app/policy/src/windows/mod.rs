@@ -0,0 +1,800 @@
+//! Quarantine through the Windows Filtering Platform. Block filters are
+//! owned by a persistent `nets` provider and sublayer, so they survive a
+//! service restart and are found again by enumeration instead of being
+//! matched by display name as `netsh advfirewall` rules would be.
+
+use std::{collections::HashSet, ffi::c_void, mem, net::IpAddr, path::Path, ptr};
+
+use anyhow::{anyhow, Result};
+use windows_sys::core::{GUID, PWSTR};
+use windows_sys::Win32::Foundation::{FWP_E_ALREADY_EXISTS, HANDLE};
+use windows_sys::Win32::NetworkManagement::WindowsFilteringPlatform::{
+    FwpmEngineClose0, FwpmEngineOpen0, FwpmFilterAdd0, FwpmFilterCreateEnumHandle0,
+    FwpmFilterDeleteById0, FwpmFilterDestroyEnumHandle0, FwpmFilterEnum0, FwpmFreeMemory0,
+    FwpmGetAppIdFromFileName0, FwpmProviderAdd0, FwpmSubLayerAdd0, FwpmTransactionAbort0,
+    FwpmTransactionBegin0, FwpmTransactionCommit0, FWPM_CONDITION_ALE_APP_ID, FWPM_CONDITION_FLAGS,
+    FWPM_CONDITION_IP_LOCAL_PORT, FWPM_CONDITION_IP_REMOTE_ADDRESS, FWPM_CONDITION_IP_REMOTE_PORT,
+    FWPM_DISPLAY_DATA0, FWPM_FILTER0, FWPM_FILTER_CONDITION0, FWPM_FILTER_ENUM_TEMPLATE0,
+    FWPM_FILTER_FLAG_PERSISTENT, FWPM_LAYER_ALE_AUTH_CONNECT_V4, FWPM_LAYER_ALE_AUTH_CONNECT_V6,
+    FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4, FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6, FWPM_PROVIDER0,
+    FWPM_PROVIDER_FLAG_PERSISTENT, FWPM_SUBLAYER0, FWPM_SUBLAYER_FLAG_PERSISTENT, FWP_ACTION_BLOCK,
+    FWP_ACTION_PERMIT, FWP_ACTION_TYPE, FWP_BYTE_ARRAY16, FWP_BYTE_ARRAY16_TYPE, FWP_BYTE_BLOB,
+    FWP_BYTE_BLOB_TYPE, FWP_CONDITION_FLAG_IS_LOOPBACK, FWP_EMPTY, FWP_FILTER_ENUM_OVERLAPPING,
+    FWP_MATCH_EQUAL, FWP_MATCH_FLAGS_ALL_SET, FWP_UINT16, FWP_UINT32, FWP_UINT8,
+    FWP_V4_ADDR_AND_MASK, FWP_V4_ADDR_MASK, FWP_V6_ADDR_AND_MASK, FWP_V6_ADDR_MASK,
+};
+use windows_sys::Win32::System::Rpc::RPC_C_AUTHN_WINNT;
+
+use crate::kill_switch::KILL_SWITCH_TAG;
+use crate::{
+    flow_label, validate_decision, AppliedRule, IpNetwork, KillSwitch, PolicyBackend,
+    QuarantineDecision,
+};
+
+/// Fixed keys, so filters left by an earlier run (or a crash) are found.
+const PROVIDER_KEY: GUID = GUID::from_u128(0x6e657473_0001_4d0b_9c1d_2f3e4a5b6c7d);
+const SUBLAYER_KEY: GUID = GUID::from_u128(0x6e657473_0002_4d0b_9c1d_2f3e4a5b6c7d);
+/// Evaluated before the sublayers of most other software.
+const SUBLAYER_WEIGHT: u16 = 0x8000;
+/// Filters fetched per `FwpmFilterEnum0` call.
+const ENUM_BATCH: u32 = 256;
+/// Kill switch weights inside the sublayer: the highest matching filter
+/// decides, so the allowlist wins over the block of everything.
+const KILL_SWITCH_PERMIT_WEIGHT: u8 = 15;
+const KILL_SWITCH_BLOCK_WEIGHT: u8 = 1;
+
+/// Where a quarantine blocks, and on which port: outbound connections by
+/// remote port at ALE_AUTH_CONNECT, accepted inbound ones by local port at
+/// ALE_AUTH_RECV_ACCEPT.
+const LAYERS: [(&str, GUID, GUID); 4] = [
+    (
+        "connect-v4",
+        FWPM_LAYER_ALE_AUTH_CONNECT_V4,
+        FWPM_CONDITION_IP_REMOTE_PORT,
+    ),
+    (
+        "connect-v6",
+        FWPM_LAYER_ALE_AUTH_CONNECT_V6,
+        FWPM_CONDITION_IP_REMOTE_PORT,
+    ),
+    (
+        "accept-v4",
+        FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4,
+        FWPM_CONDITION_IP_LOCAL_PORT,
+    ),
+    (
+        "accept-v6",
+        FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6,
+        FWPM_CONDITION_IP_LOCAL_PORT,
+    ),
+];
+
+/// Native WFP backend. Each call opens its own engine session and changes
+/// filters in one transaction, so a failed apply leaves nothing behind.
+#[derive(Debug, Default)]
+pub struct WfpBackend;
+
+impl WfpBackend {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+/// The WFP backend, if the base filtering engine service is reachable.
+pub(crate) fn detect_backend() -> Option<Box<dyn PolicyBackend + Send + Sync>> {
+    match Engine::open() {
+        Ok(_) => {
+            tracing::info!("quarantine firewall backend selected: WFP");
+            Some(Box::new(WfpBackend::new()))
+        }
+        Err(err) => {
+            tracing::warn!("filter engine unavailable: {err:#}");
+            None
+        }
+    }
+}
+
+/// Executable a decision blocks: the identified process's, or a process
+/// given as a full path. A bare name cannot be resolved to the app id WFP
+/// matches on, and neither can a pid alone.
+fn program(decision: &QuarantineDecision) -> Option<&str> {
+    decision
+        .identity
+        .as_ref()
+        .and_then(|identity| identity.exe_path.as_deref())
+        .or(decision.process.as_deref())
+        .filter(|path| Path::new(path).is_absolute())
+}
+
+/// Filter names of a decision, one per port (or `*` for all of them) and
+/// layer of its direction: `nets:<port>[<flow>][:<program>] <layer>`, with
+/// the [`flow_label`] of one-way and peer-limited decisions.
+fn filter_names(decision: &QuarantineDecision) -> Vec<(Option<u16>, usize, String)> {
+    let program = program(decision);
+    let mut ports: Vec<Option<u16>> = decision.ports.iter().copied().map(Some).collect();
+    ports.sort_unstable();
+    ports.dedup();
+    if ports.is_empty() && (program.is_some() || decision.is_remote_scoped()) {
+        ports.push(None);
+    }
+    let program = program
+        .map(|path| format!(":{}", path.to_lowercase()))
+        .unwrap_or_default();
+    let flow = flow_label(decision);
+    let direction = decision.direction;
+    ports
+        .into_iter()
+        .flat_map(|port| {
+            let target = port.map_or_else(|| "*".to_string(), |port| port.to_string());
+            let suffix = format!("{flow}{program}");
+            LAYERS
+                .iter()
+                .enumerate()
+                .filter(move |(_, (name, _, _))| {
+                    if name.starts_with("connect") {
+                        direction.outbound()
+                    } else {
+                        direction.inbound()
+                    }
+                })
+                .map(move |(layer, (name, _, _))| {
+                    (port, layer, format!("nets:{target}{suffix} {name}"))
+                })
+        })
+        .collect()
+}
+
+/// The peers of `remotes` a filter at `LAYERS[layer]` can match, `None`
+/// when the decision is limited to peers of the other address family only
+/// and the layer gets no filter.
+fn layer_remotes(layer: usize, remotes: &[IpNetwork]) -> Option<Vec<IpNetwork>> {
+    let v6 = LAYERS[layer].0.ends_with("v6");
+    let peers: Vec<IpNetwork> = remotes
+        .iter()
+        .filter(|remote| remote.is_ipv4() != v6)
+        .copied()
+        .collect();
+    (remotes.is_empty() || !peers.is_empty()).then_some(peers)
+}
+
+/// What one kill switch filter matches at a connect layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KillSwitchMatch {
+    Loopback,
+    /// DHCP server port, to keep the lease.
+    RemotePort(u16),
+    RemoteAddress(IpAddr),
+    Everything,
+}
+
+/// Filters of a kill switch at the connect layers (`LAYERS` 0 and 1), by
+/// layer, match and name. Only outbound connections are blocked.
+fn kill_switch_filters(switch: &KillSwitch) -> Vec<(usize, KillSwitchMatch, String)> {
+    let mut filters = Vec::new();
+    for (layer, v6) in [(0, false), (1, true)] {
+        let layer_name = LAYERS[layer].0;
+        let dhcp = if v6 { 547 } else { 67 };
+        let mut matches = vec![KillSwitchMatch::Loopback, KillSwitchMatch::RemotePort(dhcp)];
+        matches.extend(
+            switch
+                .allow
+                .iter()
+                .filter(|addr| addr.is_ipv6() == v6)
+                .map(|addr| KillSwitchMatch::RemoteAddress(*addr)),
+        );
+        matches.push(KillSwitchMatch::Everything);
+        filters.extend(matches.into_iter().map(|matched| {
+            let name = match matched {
+                KillSwitchMatch::Loopback => {
+                    format!("{KILL_SWITCH_TAG} allow loopback {layer_name}")
+                }
+                KillSwitchMatch::RemotePort(port) => {
+                    format!("{KILL_SWITCH_TAG} allow port {port} {layer_name}")
+                }
+                KillSwitchMatch::RemoteAddress(addr) => {
+                    format!("{KILL_SWITCH_TAG} allow {addr} {layer_name}")
+                }
+                KillSwitchMatch::Everything => format!("{KILL_SWITCH_TAG} {layer_name}"),
+            };
+            (layer, matched, name)
+        }));
+    }
+    filters
+}
+
+impl PolicyBackend for WfpBackend {
+    fn apply(&self, decision: &QuarantineDecision) -> Result<()> {
+        let remotes = validate_decision(decision)?;
+        let app_id = AppId::for_program(program(decision))?;
+        if app_id.is_none() {
+            if decision.ports.is_empty() && !decision.is_remote_scoped() {
+                return Err(anyhow!(
+                    "cannot match process {:?} without the full path of its executable",
+                    decision.process
+                ));
+            }
+            if decision.process.is_some() {
+                tracing::debug!(
+                    process = ?decision.process,
+                    "not a full path; port quarantine applies to every process"
+                );
+            }
+        }
+        let engine = Engine::open()?;
+        engine.transaction(|engine| {
+            engine.ensure_provider()?;
+            let existing: HashSet<String> = engine
+                .nets_filters()?
+                .into_iter()
+                .map(|(_, name)| name)
+                .collect();
+            for (port, layer, name) in filter_names(decision) {
+                if existing.contains(&name) {
+                    continue;
+                }
+                if let Some(peers) = layer_remotes(layer, &remotes) {
+                    engine.add_block_filter(layer, port, app_id.as_ref(), &peers, &name)?;
+                }
+            }
+            Ok(())
+        })?;
+        tracing::info!(ports = ?decision.ports, "WFP quarantine applied");
+        Ok(())
+    }
+
+    fn rollback(&self, decision: &QuarantineDecision) -> Result<()> {
+        let names: HashSet<String> = filter_names(decision)
+            .into_iter()
+            .map(|(_, _, name)| name)
+            .collect();
+        let engine = Engine::open()?;
+        engine.transaction(|engine| {
+            for (id, name) in engine.nets_filters()? {
+                if names.contains(&name) {
+                    check(
+                        unsafe { FwpmFilterDeleteById0(engine.0, id) },
+                        "deleting a filter",
+                    )?;
+                }
+            }
+            Ok(())
+        })?;
+        tracing::info!(ports = ?decision.ports, "WFP quarantine rolled back");
+        Ok(())
+    }
+
+    /// One line per filter: its name, then the address, port and app id
+    /// conditions.
+    fn plan(&self, decision: &QuarantineDecision) -> Result<Vec<String>> {
+        let remotes = validate_decision(decision)?;
+        let program = program(decision);
+        if program.is_none() && decision.ports.is_empty() && !decision.is_remote_scoped() {
+            return Err(anyhow!(
+                "cannot match process {:?} without the full path of its executable",
+                decision.process
+            ));
+        }
+        Ok(filter_names(decision)
+            .into_iter()
+            .filter_map(|(port, layer, name)| {
+                let peers = layer_remotes(layer, &remotes)?;
+                let peers = if peers.is_empty() {
+                    String::new()
+                } else {
+                    let peers: Vec<String> = peers.iter().map(ToString::to_string).collect();
+                    format!(" remote address {}", peers.join(", "))
+                };
+                let side = if LAYERS[layer].0.starts_with("connect") {
+                    "remote"
+                } else {
+                    "local"
+                };
+                let port = port
+                    .map(|port| format!(" {side} port {port}"))
+                    .unwrap_or_default();
+                let app = program
+                    .map(|path| format!(" app id {path}"))
+                    .unwrap_or_default();
+                Some(format!("add filter \"{name}\": block{peers}{port}{app}"))
+            })
+            .collect())
+    }
+
+    fn name(&self) -> &'static str {
+        "wfp"
+    }
+
+    /// Blocks new outbound connections; established ones are left to run
+    /// down, as ALE authorizes a connection only once.
+    fn engage_kill_switch(&self, switch: &KillSwitch) -> Result<()> {
+        let engine = Engine::open()?;
+        engine.transaction(|engine| {
+            engine.ensure_provider()?;
+            engine.delete_kill_switch()?;
+            for (layer, matched, name) in kill_switch_filters(switch) {
+                engine.add_kill_switch_filter(layer, matched, &name)?;
+            }
+            Ok(())
+        })?;
+        tracing::warn!(allow = ?switch.allow, "WFP kill switch engaged");
+        Ok(())
+    }
+
+    fn release_kill_switch(&self) -> Result<()> {
+        let engine = Engine::open()?;
+        engine.transaction(Engine::delete_kill_switch)?;
+        tracing::warn!("WFP kill switch released");
+        Ok(())
+    }
+
+    fn rule_handles(&self, decision: &QuarantineDecision) -> Vec<String> {
+        filter_names(decision)
+            .into_iter()
+            .map(|(_, _, name)| name)
+            .collect()
+    }
+
+    /// Filters are listed by name; their conditions stay in the engine.
+    fn list_active(&self) -> Result<Vec<AppliedRule>> {
+        let engine = Engine::open()?;
+        Ok(engine
+            .nets_filters()?
+            .into_iter()
+            .map(|(id, name)| AppliedRule {
+                rule: name.clone(),
+                handle: name,
+                location: format!("filter {id}"),
+            })
+            .collect())
+    }
+
+    /// Deletes every filter of the nets provider. The provider and
+    /// sublayer stay, empty.
+    fn rollback_all(&self) -> Result<()> {
+        let engine = Engine::open()?;
+        engine.transaction(|engine| {
+            for (id, _) in engine.nets_filters()? {
+                check(
+                    unsafe { FwpmFilterDeleteById0(engine.0, id) },
+                    "deleting a filter",
+                )?;
+            }
+            Ok(())
+        })?;
+        tracing::warn!("WFP filters of nets removed");
+        Ok(())
+    }
+}
+
+fn check(code: u32, what: &str) -> Result<()> {
+    if code == 0 {
+        Ok(())
+    } else {
+        Err(anyhow!("WFP: {what} failed with 0x{code:08x}"))
+    }
+}
+
+/// Like [`check`], accepting an object that is already there.
+fn check_added(code: u32, what: &str) -> Result<()> {
+    if code == FWP_E_ALREADY_EXISTS as u32 {
+        Ok(())
+    } else {
+        check(code, what)
+    }
+}
+
+/// NUL-terminated UTF-16 for the API; must outlive the call using it.
+fn wide(text: &str) -> Vec<u16> {
+    text.encode_utf16().chain(Some(0)).collect()
+}
+
+/// # Safety
+/// `text` must be null or point to a NUL-terminated UTF-16 string.
+unsafe fn from_wide(text: PWSTR) -> String {
+    if text.is_null() {
+        return String::new();
+    }
+    let mut len = 0;
+    while *text.add(len) != 0 {
+        len += 1;
+    }
+    String::from_utf16_lossy(std::slice::from_raw_parts(text, len))
+}
+
+/// Open session to the filter engine, closed on drop.
+struct Engine(HANDLE);
+
+impl Engine {
+    fn open() -> Result<Self> {
+        let mut handle: HANDLE = ptr::null_mut();
+        check(
+            unsafe {
+                FwpmEngineOpen0(
+                    ptr::null(),
+                    RPC_C_AUTHN_WINNT,
+                    ptr::null(),
+                    ptr::null(),
+                    &mut handle,
+                )
+            },
+            "opening the filter engine",
+        )?;
+        Ok(Self(handle))
+    }
+
+    /// Runs `changes` in a transaction, committed only if they all succeed.
+    fn transaction<T>(&self, changes: impl FnOnce(&Self) -> Result<T>) -> Result<T> {
+        check(
+            unsafe { FwpmTransactionBegin0(self.0, 0) },
+            "beginning a transaction",
+        )?;
+        match changes(self) {
+            Ok(value) => {
+                check(
+                    unsafe { FwpmTransactionCommit0(self.0) },
+                    "committing a transaction",
+                )?;
+                Ok(value)
+            }
+            Err(err) => {
+                unsafe { FwpmTransactionAbort0(self.0) };
+                Err(err)
+            }
+        }
+    }
+
+    /// Adds the persistent provider and sublayer unless they exist.
+    fn ensure_provider(&self) -> Result<()> {
+        let mut name = wide("nets");
+        let mut description = wide("nets quarantine filters");
+        let display = FWPM_DISPLAY_DATA0 {
+            name: name.as_mut_ptr(),
+            description: description.as_mut_ptr(),
+        };
+
+        let mut provider: FWPM_PROVIDER0 = unsafe { mem::zeroed() };
+        provider.providerKey = PROVIDER_KEY;
+        provider.displayData = display;
+        provider.flags = FWPM_PROVIDER_FLAG_PERSISTENT;
+        check_added(
+            unsafe { FwpmProviderAdd0(self.0, &provider, ptr::null_mut()) },
+            "adding the provider",
+        )?;
+
+        let mut provider_key = PROVIDER_KEY;
+        let mut sublayer: FWPM_SUBLAYER0 = unsafe { mem::zeroed() };
+        sublayer.subLayerKey = SUBLAYER_KEY;
+        sublayer.displayData = display;
+        sublayer.flags = FWPM_SUBLAYER_FLAG_PERSISTENT;
+        sublayer.providerKey = &mut provider_key;
+        sublayer.weight = SUBLAYER_WEIGHT;
+        check_added(
+            unsafe { FwpmSubLayerAdd0(self.0, &sublayer, ptr::null_mut()) },
+            "adding the sublayer",
+        )
+    }
+
+    /// Adds a persistent kill switch filter at `LAYERS[layer]`: a permit
+    /// for what `matched` allows, or the block of everything.
+    fn add_kill_switch_filter(
+        &self,
+        layer: usize,
+        matched: KillSwitchMatch,
+        name: &str,
+    ) -> Result<u64> {
+        let mut condition: FWPM_FILTER_CONDITION0 = unsafe { mem::zeroed() };
+        condition.matchType = FWP_MATCH_EQUAL;
+        // Pointed to by the condition of an IPv6 address.
+        let mut v6 = FWP_BYTE_ARRAY16 {
+            byteArray16: [0; 16],
+        };
+        let mut conditions = Vec::with_capacity(1);
+        match matched {
+            KillSwitchMatch::Loopback => {
+                condition.fieldKey = FWPM_CONDITION_FLAGS;
+                condition.matchType = FWP_MATCH_FLAGS_ALL_SET;
+                condition.conditionValue.r#type = FWP_UINT32;
+                condition.conditionValue.Anonymous.uint32 = FWP_CONDITION_FLAG_IS_LOOPBACK;
+                conditions.push(condition);
+            }
+            KillSwitchMatch::RemotePort(port) => {
+                condition.fieldKey = FWPM_CONDITION_IP_REMOTE_PORT;
+                condition.conditionValue.r#type = FWP_UINT16;
+                condition.conditionValue.Anonymous.uint16 = port;
+                conditions.push(condition);
+            }
+            KillSwitchMatch::RemoteAddress(IpAddr::V4(addr)) => {
+                condition.fieldKey = FWPM_CONDITION_IP_REMOTE_ADDRESS;
+                condition.conditionValue.r#type = FWP_UINT32;
+                condition.conditionValue.Anonymous.uint32 = u32::from(addr);
+                conditions.push(condition);
+            }
+            KillSwitchMatch::RemoteAddress(IpAddr::V6(addr)) => {
+                v6.byteArray16 = addr.octets();
+                condition.fieldKey = FWPM_CONDITION_IP_REMOTE_ADDRESS;
+                condition.conditionValue.r#type = FWP_BYTE_ARRAY16_TYPE;
+                condition.conditionValue.Anonymous.byteArray16 = &mut v6;
+                conditions.push(condition);
+            }
+            KillSwitchMatch::Everything => {}
+        }
+        let (action, weight) = if matched == KillSwitchMatch::Everything {
+            (FWP_ACTION_BLOCK, KILL_SWITCH_BLOCK_WEIGHT)
+        } else {
+            (FWP_ACTION_PERMIT, KILL_SWITCH_PERMIT_WEIGHT)
+        };
+        self.add_filter(layer, &mut conditions, action, Some(weight), name)
+    }
+
+    /// Deletes every kill switch filter.
+    fn delete_kill_switch(&self) -> Result<()> {
+        for (id, name) in self.nets_filters()? {
+            if name.starts_with(KILL_SWITCH_TAG) {
+                check(
+                    unsafe { FwpmFilterDeleteById0(self.0, id) },
+                    "deleting a filter",
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a persistent block filter at `LAYERS[layer]` on `port`, or on
+    /// every port without one, restricted to one program when `app_id` is
+    /// given and to `remotes` when there are any: conditions on the same
+    /// field match if any of them does.
+    fn add_block_filter(
+        &self,
+        layer: usize,
+        port: Option<u16>,
+        app_id: Option<&AppId>,
+        remotes: &[IpNetwork],
+        name: &str,
+    ) -> Result<u64> {
+        let (_, _, port_field) = LAYERS[layer];
+        // Pointed to by the address conditions, so filled before any of
+        // them is built.
+        let mut v4 = Vec::new();
+        let mut v6 = Vec::new();
+        for remote in remotes {
+            match remote.addr() {
+                IpAddr::V4(addr) => v4.push(FWP_V4_ADDR_AND_MASK {
+                    addr: u32::from(addr),
+                    mask: u32::MAX
+                        .checked_shl(32 - u32::from(remote.prefix()))
+                        .unwrap_or(0),
+                }),
+                IpAddr::V6(addr) => v6.push(FWP_V6_ADDR_AND_MASK {
+                    addr: addr.octets(),
+                    prefixLength: remote.prefix(),
+                }),
+            }
+        }
+        let mut conditions = Vec::with_capacity(2 + remotes.len());
+        for mask in &mut v4 {
+            let mut address: FWPM_FILTER_CONDITION0 = unsafe { mem::zeroed() };
+            address.fieldKey = FWPM_CONDITION_IP_REMOTE_ADDRESS;
+            address.matchType = FWP_MATCH_EQUAL;
+            address.conditionValue.r#type = FWP_V4_ADDR_MASK;
+            address.conditionValue.Anonymous.v4AddrMask = mask;
+            conditions.push(address);
+        }
+        for mask in &mut v6 {
+            let mut address: FWPM_FILTER_CONDITION0 = unsafe { mem::zeroed() };
+            address.fieldKey = FWPM_CONDITION_IP_REMOTE_ADDRESS;
+            address.matchType = FWP_MATCH_EQUAL;
+            address.conditionValue.r#type = FWP_V6_ADDR_MASK;
+            address.conditionValue.Anonymous.v6AddrMask = mask;
+            conditions.push(address);
+        }
+        if let Some(port) = port {
+            let mut port_condition: FWPM_FILTER_CONDITION0 = unsafe { mem::zeroed() };
+            port_condition.fieldKey = port_field;
+            port_condition.matchType = FWP_MATCH_EQUAL;
+            port_condition.conditionValue.r#type = FWP_UINT16;
+            port_condition.conditionValue.Anonymous.uint16 = port;
+            conditions.push(port_condition);
+        }
+        if let Some(app_id) = app_id {
+            let mut app_condition: FWPM_FILTER_CONDITION0 = unsafe { mem::zeroed() };
+            app_condition.fieldKey = FWPM_CONDITION_ALE_APP_ID;
+            app_condition.matchType = FWP_MATCH_EQUAL;
+            app_condition.conditionValue.r#type = FWP_BYTE_BLOB_TYPE;
+            app_condition.conditionValue.Anonymous.byteBlob = app_id.0;
+            conditions.push(app_condition);
+        }
+        self.add_filter(layer, &mut conditions, FWP_ACTION_BLOCK, None, name)
+    }
+
+    /// Adds a persistent filter of the nets provider and sublayer at
+    /// `LAYERS[layer]`. Without a weight the engine derives one from the
+    /// conditions.
+    fn add_filter(
+        &self,
+        layer: usize,
+        conditions: &mut [FWPM_FILTER_CONDITION0],
+        action: FWP_ACTION_TYPE,
+        weight: Option<u8>,
+        name: &str,
+    ) -> Result<u64> {
+        let mut name = wide(name);
+        let mut provider_key = PROVIDER_KEY;
+        let mut filter: FWPM_FILTER0 = unsafe { mem::zeroed() };
+        filter.displayData.name = name.as_mut_ptr();
+        filter.flags = FWPM_FILTER_FLAG_PERSISTENT;
+        filter.providerKey = &mut provider_key;
+        filter.layerKey = LAYERS[layer].1;
+        filter.subLayerKey = SUBLAYER_KEY;
+        match weight {
+            Some(weight) => {
+                filter.weight.r#type = FWP_UINT8;
+                filter.weight.Anonymous.uint8 = weight;
+            }
+            None => filter.weight.r#type = FWP_EMPTY,
+        }
+        filter.numFilterConditions = conditions.len() as u32;
+        filter.filterCondition = conditions.as_mut_ptr();
+        filter.action.r#type = action;
+        let mut id = 0;
+        check(
+            unsafe { FwpmFilterAdd0(self.0, &filter, ptr::null_mut(), &mut id) },
+            "adding a filter",
+        )?;
+        Ok(id)
+    }
+
+    /// Id and name of every filter owned by the nets provider.
+    fn nets_filters(&self) -> Result<Vec<(u64, String)>> {
+        let mut filters = Vec::new();
+        for (_, layer_key, _) in LAYERS {
+            let mut provider_key = PROVIDER_KEY;
+            let mut template: FWPM_FILTER_ENUM_TEMPLATE0 = unsafe { mem::zeroed() };
+            template.providerKey = &mut provider_key;
+            template.layerKey = layer_key;
+            template.enumType = FWP_FILTER_ENUM_OVERLAPPING;
+            template.actionMask = u32::MAX;
+            let mut handle: HANDLE = ptr::null_mut();
+            check(
+                unsafe { FwpmFilterCreateEnumHandle0(self.0, &template, &mut handle) },
+                "enumerating filters",
+            )?;
+            let result = self.drain_enum(handle, &mut filters);
+            unsafe { FwpmFilterDestroyEnumHandle0(self.0, handle) };
+            result?;
+        }
+        Ok(filters)
+    }
+
+    fn drain_enum(&self, handle: HANDLE, filters: &mut Vec<(u64, String)>) -> Result<()> {
+        loop {
+            let mut entries: *mut *mut FWPM_FILTER0 = ptr::null_mut();
+            let mut count = 0u32;
+            check(
+                unsafe { FwpmFilterEnum0(self.0, handle, ENUM_BATCH, &mut entries, &mut count) },
+                "enumerating filters",
+            )?;
+            for index in 0..count as usize {
+                // SAFETY: the engine returned `count` valid filter pointers.
+                let filter = unsafe { &**entries.add(index) };
+                filters.push((filter.filterId, unsafe {
+                    from_wide(filter.displayData.name)
+                }));
+            }
+            if !entries.is_null() {
+                unsafe { FwpmFreeMemory0(&mut entries as *mut _ as *mut *mut c_void) };
+            }
+            if count < ENUM_BATCH {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        unsafe { FwpmEngineClose0(self.0) };
+    }
+}
+
+/// WFP app id of an executable, freed on drop.
+struct AppId(*mut FWP_BYTE_BLOB);
+
+impl AppId {
+    fn for_program(program: Option<&str>) -> Result<Option<Self>> {
+        let Some(path) = program else {
+            return Ok(None);
+        };
+        let path = wide(path);
+        let mut blob: *mut FWP_BYTE_BLOB = ptr::null_mut();
+        check(
+            unsafe { FwpmGetAppIdFromFileName0(path.as_ptr(), &mut blob) },
+            "resolving the program's app id",
+        )?;
+        Ok(Some(Self(blob)))
+    }
+}
+
+impl Drop for AppId {
+    fn drop(&mut self) {
+        unsafe { FwpmFreeMemory0(&mut self.0 as *mut _ as *mut *mut c_void) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use collector::ProcessIdentity;
+
+    use super::*;
+    use crate::Direction;
+
+    #[test]
+    fn names_one_filter_per_port_and_layer() {
+        let decision = QuarantineDecision {
+            process: Some(r"C:\Tools\Agent.exe".into()),
+            identity: None,
+            ports: vec![443, 53, 443],
+            expires_in_seconds: 60,
+            suspend: false,
+            direction: Direction::Both,
+            remote_networks: Vec::new(),
+            remote_domains: Vec::new(),
+        };
+        let names = filter_names(&decision);
+        assert_eq!(names.len(), 8);
+        assert_eq!(
+            names[0],
+            (Some(53), 0, r"nets:53:c:\tools\agent.exe connect-v4".into())
+        );
+        assert_eq!(names[7].2, r"nets:443:c:\tools\agent.exe accept-v6");
+
+        // A bare name cannot be matched: the filters are per port only.
+        let by_name = QuarantineDecision {
+            process: Some("agent.exe".into()),
+            ..decision.clone()
+        };
+        assert_eq!(filter_names(&by_name)[1].2, "nets:53 connect-v6");
+
+        // An identified program without ports is blocked on all of them.
+        let program = QuarantineDecision {
+            identity: Some(ProcessIdentity {
+                pid: 4242,
+                ppid: None,
+                name: Some("agent.exe".into()),
+                exe_path: Some(r"C:\Tools\Agent.exe".into()),
+                sha256_16: None,
+                user: None,
+                signed: None,
+            }),
+            ports: vec![],
+            ..by_name
+        };
+        let names = filter_names(&program);
+        assert_eq!(names.len(), 4);
+        assert_eq!(
+            names[2],
+            (None, 2, r"nets:*:c:\tools\agent.exe accept-v4".into())
+        );
+
+        // Outbound to some peers: connect layers only, each matching the
+        // peers of its family, and named apart from the plain filters.
+        let outbound = QuarantineDecision {
+            direction: Direction::Outbound,
+            remote_networks: vec!["203.0.113.0/24".parse().unwrap()],
+            ..program
+        };
+        let flow = flow_label(&outbound);
+        assert!(flow.starts_with(":out~"));
+        let names = filter_names(&outbound);
+        assert_eq!(names.len(), 2);
+        assert_eq!(
+            names[0].2,
+            format!(r"nets:*{flow}:c:\tools\agent.exe connect-v4")
+        );
+        assert_eq!(
+            layer_remotes(0, &outbound.remote_networks),
+            Some(outbound.remote_networks.clone())
+        );
+        assert_eq!(layer_remotes(1, &outbound.remote_networks), None);
+        assert_eq!(layer_remotes(1, &[]), Some(Vec::new()));
+    }
+}
@@ -0,0 +1,103 @@
+//! Ingest and read throughput with and without payload compression, plus
+//! the resulting payload bytes. Run with `cargo bench -p storage`.
+
+use chrono::{Duration, Utc};
+use collector::{FlowDirection, FlowEvent, FlowRisk, ProcessIdentity};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use storage::{Compression, FlowQuery, Storage, StorageOptions};
+
+const BATCH: usize = 1000;
+
+/// Flows shaped like real collector output: process identity, TLS and DNS
+/// fields filled, addresses and names drawn from a small pool.
+fn sample_flows() -> Vec<FlowEvent> {
+    let now = Utc::now();
+    (0..BATCH)
+        .map(|i| {
+            let ts_first = now - Duration::seconds(i as i64);
+            FlowEvent {
+                ts_first,
+                ts_last: ts_first + Duration::milliseconds(350),
+                proto: if i % 4 == 0 { "udp" } else { "tcp" }.into(),
+                src_ip: "192.168.1.23".into(),
+                src_port: 40000 + (i % 20000) as u16,
+                dst_ip: format!("93.184.{}.{}", i % 7, i % 251),
+                dst_port: if i % 4 == 0 { 53 } else { 443 },
+                iface: Some("eth0".into()),
+                direction: FlowDirection::Outbound,
+                state: Some("ESTABLISHED".into()),
+                bytes: 1200 + (i as u64 * 37) % 90_000,
+                packets: 4 + (i as u64 % 60),
+                process: Some(ProcessIdentity {
+                    pid: 4000 + (i % 5) as i32,
+                    ppid: Some(1),
+                    name: Some(["firefox", "curl", "slack", "notesync", "git"][i % 5].into()),
+                    exe_path: Some(format!(
+                        "/usr/bin/{}",
+                        ["firefox", "curl", "slack", "notesync", "git"][i % 5]
+                    )),
+                    sha256_16: Some("3f2a9c1b7e6d5a40".into()),
+                    user: Some("alice".into()),
+                    signed: Some(true),
+                }),
+                layer2: None,
+                risk: Some(FlowRisk {
+                    score: (i % 100) as u8,
+                    level: "low".into(),
+                    rule_id: None,
+                    rationale: None,
+                }),
+                sni: Some(format!("cdn{}.example.com", i % 13)),
+                alpn: Some("h2".into()),
+                ja3: Some(
+                    "771,4865-4866-4867-49195-49199,0-23-65281-10-11-35-16,29-23-24,0".into(),
+                ),
+                ja4: Some("t13d1516h2_8daaf6152771_02713d6af862".into()),
+                dns_qname: Some(format!("cdn{}.example.com", i % 13)),
+                dns_qtype: Some("A".into()),
+                dns_rcode: Some("NOERROR".into()),
+                dns_answers: vec![format!("93.184.{}.{}", i % 7, i % 251)],
+            }
+        })
+        .collect()
+}
+
+fn open(compression: Compression) -> Storage {
+    let options = StorageOptions {
+        compression,
+        ..StorageOptions::default()
+    };
+    Storage::open_with_options(":memory:", &[9u8; 32], options).unwrap()
+}
+
+fn bench_compression(c: &mut Criterion) {
+    let flows = sample_flows();
+    for (name, compression) in [("none", Compression::None), ("zstd", Compression::Zstd)] {
+        let storage = open(compression);
+        storage.put_flows(&flows).unwrap();
+        let (_, used) = storage.database_size().unwrap();
+        eprintln!("{name}: {used} bytes used for {BATCH} flows");
+
+        c.bench_function(&format!("put_flows_{BATCH}_{name}"), |b| {
+            b.iter_batched(
+                || open(compression),
+                |storage| storage.put_flows(&flows).unwrap(),
+                BatchSize::PerIteration,
+            )
+        });
+        let query = FlowQuery {
+            limit: BATCH,
+            ..FlowQuery::default()
+        };
+        c.bench_function(&format!("query_flows_full_{BATCH}_{name}"), |b| {
+            b.iter(|| storage.query_flows_full(&query).unwrap())
+        });
+    }
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = bench_compression
+}
+criterion_main!(benches);
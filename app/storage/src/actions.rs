@@ -0,0 +1,418 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, params_from_iter, types::Value, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit,
+    query::{join_clauses, placeholders, SortOrder},
+    timestamp_column, Storage,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ActionOutcome {
+    /// Recorded, not yet applied (e.g. waiting for approval).
+    #[default]
+    Pending,
+    Applied,
+    Failed,
+    RolledBack,
+    /// Computed and logged by a dry-run backend, never enforced.
+    DryRun,
+}
+
+impl ActionOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ActionOutcome::Pending => "Pending",
+            ActionOutcome::Applied => "Applied",
+            ActionOutcome::Failed => "Failed",
+            ActionOutcome::RolledBack => "RolledBack",
+            ActionOutcome::DryRun => "DryRun",
+        }
+    }
+}
+
+impl FromStr for ActionOutcome {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "pending" => Ok(ActionOutcome::Pending),
+            "applied" => Ok(ActionOutcome::Applied),
+            "failed" => Ok(ActionOutcome::Failed),
+            "rolledback" | "rolled_back" => Ok(ActionOutcome::RolledBack),
+            "dryrun" | "dry_run" => Ok(ActionOutcome::DryRun),
+            other => Err(anyhow!("unknown action outcome: {other}")),
+        }
+    }
+}
+
+/// Audit entry for one policy intervention. The decision is kept as the
+/// JSON the policy crate serialized, so new action kinds need no schema
+/// change.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PolicyActionRecord {
+    /// Assigned by [`Storage::record_policy_action`]; ignored on insert.
+    #[serde(default)]
+    pub id: i64,
+    pub ts: DateTime<Utc>,
+    /// Kind of intervention, e.g. `quarantine`.
+    pub action: String,
+    pub decision: serde_json::Value,
+    pub backend: String,
+    pub rule_id: Option<String>,
+    pub alert_id: Option<String>,
+    pub approved_by: Option<String>,
+    pub approved_at: Option<DateTime<Utc>>,
+    pub applied_at: Option<DateTime<Utc>>,
+    pub rolled_back_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub outcome: ActionOutcome,
+    pub error: Option<String>,
+    /// Firewall rules or commands the backend ran or, in a dry run, would
+    /// have run.
+    #[serde(default)]
+    pub commands: Vec<String>,
+}
+
+/// Filters for [`Storage::query_policy_actions`]; unset fields match everything.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PolicyActionQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub action: Option<String>,
+    pub backend: Option<String>,
+    pub rule_id: Option<String>,
+    pub alert_id: Option<String>,
+    pub outcomes: Vec<ActionOutcome>,
+    pub limit: usize,
+    pub offset: usize,
+    pub order: SortOrder,
+}
+
+impl Default for PolicyActionQuery {
+    fn default() -> Self {
+        Self {
+            from: None,
+            to: None,
+            action: None,
+            backend: None,
+            rule_id: None,
+            alert_id: None,
+            outcomes: Vec::new(),
+            limit: 100,
+            offset: 0,
+            order: SortOrder::NewestFirst,
+        }
+    }
+}
+
+impl PolicyActionQuery {
+    fn where_clause(&self) -> (String, Vec<Value>) {
+        let mut clauses = Vec::new();
+        let mut params = Vec::new();
+        if let Some(from) = self.from {
+            clauses.push("ts >= ?".to_string());
+            params.push(Value::Text(from.to_rfc3339()));
+        }
+        if let Some(to) = self.to {
+            clauses.push("ts <= ?".to_string());
+            params.push(Value::Text(to.to_rfc3339()));
+        }
+        for (column, value) in [
+            ("action", &self.action),
+            ("backend", &self.backend),
+            ("rule_id", &self.rule_id),
+            ("alert_id", &self.alert_id),
+        ] {
+            if let Some(value) = value {
+                clauses.push(format!("{column} = ?"));
+                params.push(Value::Text(value.clone()));
+            }
+        }
+        if !self.outcomes.is_empty() {
+            clauses.push(format!(
+                "outcome IN ({})",
+                placeholders(self.outcomes.len())
+            ));
+            params.extend(
+                self.outcomes
+                    .iter()
+                    .map(|outcome| Value::Text(outcome.as_str().into())),
+            );
+        }
+        (join_clauses(clauses), params)
+    }
+}
+
+const ACTION_COLUMNS: &str = "id, ts, action, decision, backend, rule_id, alert_id, approved_by, approved_at, applied_at, rolled_back_at, outcome, error, commands";
+
+pub(crate) fn create_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS policy_actions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            ts TEXT NOT NULL,
+            action TEXT NOT NULL,
+            decision TEXT NOT NULL,
+            backend TEXT NOT NULL,
+            rule_id TEXT,
+            alert_id TEXT,
+            approved_by TEXT,
+            approved_at TEXT,
+            applied_at TEXT,
+            rolled_back_at TEXT,
+            outcome TEXT NOT NULL DEFAULT 'Pending',
+            error TEXT
+        );
+        CREATE INDEX IF NOT EXISTS policy_actions_ts ON policy_actions (ts);
+        CREATE INDEX IF NOT EXISTS policy_actions_alert ON policy_actions (alert_id);
+        "#,
+    )?;
+    Ok(())
+}
+
+pub(crate) fn drop_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch("DROP TABLE IF EXISTS policy_actions;")?;
+    Ok(())
+}
+
+fn optional_timestamp(
+    row: &rusqlite::Row<'_>,
+    idx: usize,
+) -> rusqlite::Result<Option<DateTime<Utc>>> {
+    match row.get_ref(idx)? {
+        rusqlite::types::ValueRef::Null => Ok(None),
+        _ => timestamp_column(row, idx).map(Some),
+    }
+}
+
+fn action_row(
+    row: &rusqlite::Row<'_>,
+) -> rusqlite::Result<(PolicyActionRecord, String, String, String)> {
+    Ok((
+        PolicyActionRecord {
+            id: row.get(0)?,
+            ts: timestamp_column(row, 1)?,
+            action: row.get(2)?,
+            decision: serde_json::Value::Null,
+            backend: row.get(4)?,
+            rule_id: row.get(5)?,
+            alert_id: row.get(6)?,
+            approved_by: row.get(7)?,
+            approved_at: optional_timestamp(row, 8)?,
+            applied_at: optional_timestamp(row, 9)?,
+            rolled_back_at: optional_timestamp(row, 10)?,
+            outcome: ActionOutcome::Pending,
+            error: row.get(12)?,
+            commands: Vec::new(),
+        },
+        row.get(3)?,
+        row.get(11)?,
+        row.get(13)?,
+    ))
+}
+
+fn finish_action(
+    (mut record, decision, outcome, commands): (PolicyActionRecord, String, String, String),
+) -> Result<PolicyActionRecord> {
+    record.decision = serde_json::from_str(&decision)?;
+    record.outcome = outcome.parse()?;
+    record.commands = serde_json::from_str(&commands)?;
+    Ok(record)
+}
+
+impl Storage {
+    /// Appends an audit entry and returns its id.
+    pub fn record_policy_action(&self, record: &PolicyActionRecord) -> Result<i64> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.prepare_cached(
+                "INSERT INTO policy_actions (ts, action, decision, backend, rule_id, alert_id, approved_by, approved_at, applied_at, rolled_back_at, outcome, error, commands)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            )?
+            .execute(params![
+                record.ts.to_rfc3339(),
+                record.action,
+                serde_json::to_string(&record.decision)?,
+                record.backend,
+                record.rule_id,
+                record.alert_id,
+                record.approved_by,
+                record.approved_at.map(|ts| ts.to_rfc3339()),
+                record.applied_at.map(|ts| ts.to_rfc3339()),
+                record.rolled_back_at.map(|ts| ts.to_rfc3339()),
+                record.outcome.as_str(),
+                record.error,
+                serde_json::to_string(&record.commands)?
+            ])?;
+        let id = tx.last_insert_rowid();
+        audit::append(
+            &tx,
+            &self.cipher,
+            audit::AUDIT_POLICY_ACTION,
+            &id.to_string(),
+            &serde_json::json!({
+                "action": record.action,
+                "backend": record.backend,
+                "rule_id": record.rule_id,
+                "alert_id": record.alert_id,
+                "outcome": record.outcome.as_str(),
+                "commands": record.commands,
+            }),
+        )?;
+        tx.commit()?;
+        Ok(id)
+    }
+
+    /// Records the operator who approved a pending action.
+    pub fn approve_policy_action(&self, id: i64, operator: &str) -> Result<()> {
+        self.update_action(
+            id,
+            "approved_by = ?2, approved_at = ?3",
+            &[Value::Text(operator.into()), now()],
+        )
+    }
+
+    /// Records the result of applying an action: `Err` carries the
+    /// backend's error message.
+    pub fn mark_policy_action_applied(
+        &self,
+        id: i64,
+        result: std::result::Result<(), String>,
+    ) -> Result<()> {
+        let (outcome, error) = match result {
+            Ok(()) => (ActionOutcome::Applied, Value::Null),
+            Err(error) => (ActionOutcome::Failed, Value::Text(error)),
+        };
+        self.update_action(
+            id,
+            "applied_at = ?2, outcome = ?3, error = ?4",
+            &[now(), Value::Text(outcome.as_str().into()), error],
+        )
+    }
+
+    /// Records a rollback; a failed rollback keeps the action `Applied`
+    /// with the error, since the intervention is still in place.
+    pub fn mark_policy_action_rolled_back(
+        &self,
+        id: i64,
+        result: std::result::Result<(), String>,
+    ) -> Result<()> {
+        match result {
+            Ok(()) => self.update_action(
+                id,
+                "rolled_back_at = ?2, outcome = ?3, error = NULL",
+                &[
+                    now(),
+                    Value::Text(ActionOutcome::RolledBack.as_str().into()),
+                ],
+            ),
+            Err(error) => self.update_action(id, "error = ?2", &[Value::Text(error)]),
+        }
+    }
+
+    pub fn get_policy_action(&self, id: i64) -> Result<Option<PolicyActionRecord>> {
+        self.conn
+            .prepare_cached(&format!(
+                "SELECT {ACTION_COLUMNS} FROM policy_actions WHERE id = ?1"
+            ))?
+            .query_row(params![id], action_row)
+            .optional()?
+            .map(finish_action)
+            .transpose()
+    }
+
+    pub fn query_policy_actions(
+        &self,
+        query: &PolicyActionQuery,
+    ) -> Result<Vec<PolicyActionRecord>> {
+        let (filter, mut values) = query.where_clause();
+        let direction = match query.order {
+            SortOrder::NewestFirst => "DESC",
+            SortOrder::OldestFirst => "ASC",
+        };
+        values.push(Value::Integer(query.limit as i64));
+        values.push(Value::Integer(query.offset as i64));
+        let rows = self
+            .conn
+            .prepare(&format!(
+                "SELECT {ACTION_COLUMNS} FROM policy_actions {filter} ORDER BY ts {direction}, id {direction} LIMIT ? OFFSET ?"
+            ))?
+            .query_map(params_from_iter(values), action_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.into_iter().map(finish_action).collect()
+    }
+
+    fn update_action(&self, id: i64, assignments: &str, values: &[Value]) -> Result<()> {
+        let mut all = vec![Value::Integer(id)];
+        all.extend_from_slice(values);
+        let updated = self.conn.execute(
+            &format!("UPDATE policy_actions SET {assignments} WHERE id = ?1"),
+            params_from_iter(all),
+        )?;
+        if updated == 0 {
+            return Err(anyhow!("policy action {id} not found"));
+        }
+        Ok(())
+    }
+}
+
+fn now() -> Value {
+    Value::Text(Utc::now().to_rfc3339())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_approval_apply_and_rollback() {
+        let storage = Storage::open(":memory:", &[2u8; 32]).unwrap();
+        let record = PolicyActionRecord {
+            id: 0,
+            ts: Utc::now(),
+            action: "quarantine".into(),
+            decision: serde_json::json!({"process": "notesync.exe", "ports": [445], "expires_in_seconds": 600}),
+            backend: "noop".into(),
+            rule_id: Some("lateral.smb".into()),
+            alert_id: Some("a1".into()),
+            approved_by: None,
+            approved_at: None,
+            applied_at: None,
+            rolled_back_at: None,
+            outcome: ActionOutcome::Pending,
+            error: None,
+            commands: vec!["nft add rule inet nets output tcp dport 445 drop".into()],
+        };
+        let id = storage.record_policy_action(&record).unwrap();
+        storage.approve_policy_action(id, "alice").unwrap();
+        storage.mark_policy_action_applied(id, Ok(())).unwrap();
+        storage
+            .mark_policy_action_rolled_back(id, Err("rule not found".into()))
+            .unwrap();
+
+        let stored = storage.get_policy_action(id).unwrap().unwrap();
+        assert_eq!(stored.approved_by.as_deref(), Some("alice"));
+        assert_eq!(stored.outcome, ActionOutcome::Applied);
+        assert_eq!(stored.error.as_deref(), Some("rule not found"));
+        assert_eq!(stored.decision["ports"][0], 445);
+        assert_eq!(stored.commands, record.commands);
+
+        storage.mark_policy_action_rolled_back(id, Ok(())).unwrap();
+        let rolled_back = storage
+            .query_policy_actions(&PolicyActionQuery {
+                alert_id: Some("a1".into()),
+                outcomes: vec![ActionOutcome::RolledBack],
+                ..PolicyActionQuery::default()
+            })
+            .unwrap();
+        assert_eq!(rolled_back.len(), 1);
+        assert!(rolled_back[0].rolled_back_at.is_some());
+        assert!(rolled_back[0].error.is_none());
+        assert!(storage.approve_policy_action(id + 1, "bob").is_err());
+    }
+}
@@ -0,0 +1,440 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use ring::digest;
+use rusqlite::{params, Connection, OptionalExtension, Transaction, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+
+use crate::{crypto::FlowCipher, timestamp_column, Storage};
+
+/// An alert was stored or updated; `subject` is the alert id.
+pub const AUDIT_ALERT_STORED: &str = "alert.stored";
+/// An alert changed triage state.
+pub const AUDIT_ALERT_STATUS: &str = "alert.status";
+/// Retention removed an alert.
+pub const AUDIT_ALERT_PRUNED: &str = "alert.pruned";
+/// A policy action was recorded; `subject` is its id.
+pub const AUDIT_POLICY_ACTION: &str = "policy.action";
+/// A learned baseline entry was edited by hand; `subject` names it.
+pub const AUDIT_BASELINE_EDIT: &str = "baseline.edit";
+/// An alert exception was added, snoozed or removed; `subject` is its id.
+pub const AUDIT_EXCEPTION_EDIT: &str = "exception.edit";
+
+/// `prev_hash` of the first entry.
+const GENESIS_HASH: [u8; 32] = [0; 32];
+/// Entries re-signed per transaction during a key rotation.
+const RESIGN_BATCH: i64 = 500;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub seq: i64,
+    pub ts: DateTime<Utc>,
+    pub kind: String,
+    pub subject: String,
+    pub payload: serde_json::Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AuditProblem {
+    /// Entries before `seq` are missing (deleted from the middle or start).
+    Gap { seq: i64, expected: i64 },
+    /// `prev_hash` does not match the hash of the entry before.
+    BrokenLink { seq: i64 },
+    /// The entry's content no longer matches its hash.
+    HashMismatch { seq: i64 },
+    /// The signature does not verify: the hash was recomputed without the key.
+    BadSignature { seq: i64 },
+    /// Signed under a key this connection does not know.
+    UnknownKey { seq: i64, key_id: String },
+    /// The log says the alert was stored and never pruned, yet it is gone.
+    MissingAlert { seq: i64, alert_id: String },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditVerification {
+    pub entries: usize,
+    /// Sequence number of the last entry.
+    pub head: Option<i64>,
+    pub problems: Vec<AuditProblem>,
+}
+
+impl AuditVerification {
+    pub fn is_intact(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+pub(crate) fn create_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS audit_log (
+            seq INTEGER PRIMARY KEY,
+            ts TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            subject TEXT NOT NULL,
+            payload TEXT NOT NULL,
+            prev_hash BLOB NOT NULL,
+            hash BLOB NOT NULL,
+            key_id TEXT NOT NULL,
+            signature BLOB NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS audit_log_subject ON audit_log (kind, subject);
+        -- Only the signature may change (re-signing on key rotation).
+        CREATE TRIGGER IF NOT EXISTS audit_log_no_update
+        BEFORE UPDATE OF seq, ts, kind, subject, payload, prev_hash, hash ON audit_log BEGIN
+            SELECT RAISE(ABORT, 'audit log is append-only');
+        END;
+        CREATE TRIGGER IF NOT EXISTS audit_log_no_delete BEFORE DELETE ON audit_log BEGIN
+            SELECT RAISE(ABORT, 'audit log is append-only');
+        END;
+        "#,
+    )?;
+    Ok(())
+}
+
+pub(crate) fn drop_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        r#"
+        DROP TRIGGER IF EXISTS audit_log_no_update;
+        DROP TRIGGER IF EXISTS audit_log_no_delete;
+        DROP TABLE IF EXISTS audit_log;
+        "#,
+    )?;
+    Ok(())
+}
+
+fn entry_hash(
+    prev_hash: &[u8],
+    seq: i64,
+    ts: &str,
+    kind: &str,
+    subject: &str,
+    payload: &str,
+) -> Vec<u8> {
+    let mut context = digest::Context::new(&digest::SHA256);
+    context.update(prev_hash);
+    context.update(&seq.to_be_bytes());
+    for field in [ts, kind, subject, payload] {
+        context.update(&(field.len() as u64).to_be_bytes());
+        context.update(field.as_bytes());
+    }
+    context.finish().as_ref().to_vec()
+}
+
+/// Appends an entry; must run inside a write transaction so the read of
+/// the previous hash and the insert cannot interleave with another writer.
+pub(crate) fn append(
+    conn: &Connection,
+    cipher: &FlowCipher,
+    kind: &str,
+    subject: &str,
+    payload: &serde_json::Value,
+) -> Result<i64> {
+    let last: Option<(i64, Vec<u8>)> = conn
+        .prepare_cached("SELECT seq, hash FROM audit_log ORDER BY seq DESC LIMIT 1")?
+        .query_row([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .optional()?;
+    let (seq, prev_hash) = match last {
+        Some((seq, hash)) => (seq + 1, hash),
+        None => (1, GENESIS_HASH.to_vec()),
+    };
+    let ts = Utc::now().to_rfc3339();
+    let payload = serde_json::to_string(payload)?;
+    let hash = entry_hash(&prev_hash, seq, &ts, kind, subject, &payload);
+    conn.prepare_cached(
+        "INSERT INTO audit_log (seq, ts, kind, subject, payload, prev_hash, hash, key_id, signature)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+    )?
+    .execute(params![
+        seq,
+        ts,
+        kind,
+        subject,
+        payload,
+        prev_hash,
+        hash,
+        cipher.key_id(),
+        cipher.sign_audit(&hash)
+    ])?;
+    Ok(seq)
+}
+
+struct RawEntry {
+    seq: i64,
+    ts: String,
+    kind: String,
+    subject: String,
+    payload: String,
+    prev_hash: Vec<u8>,
+    hash: Vec<u8>,
+    key_id: String,
+    signature: Vec<u8>,
+}
+
+impl Storage {
+    /// Appends an entry to the audit log and returns its sequence number.
+    pub fn append_audit(
+        &self,
+        kind: &str,
+        subject: &str,
+        payload: &serde_json::Value,
+    ) -> Result<i64> {
+        let tx = Transaction::new_unchecked(&self.conn, TransactionBehavior::Immediate)?;
+        let seq = append(&tx, &self.cipher, kind, subject, payload)?;
+        tx.commit()?;
+        Ok(seq)
+    }
+
+    /// Entries with `seq > after`, oldest first.
+    pub fn audit_entries(&self, after: i64, limit: usize) -> Result<Vec<AuditEntry>> {
+        let rows = self
+            .conn
+            .prepare_cached(
+                "SELECT seq, ts, kind, subject, payload FROM audit_log WHERE seq > ?1 ORDER BY seq LIMIT ?2",
+            )?
+            .query_map(params![after, limit as i64], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    timestamp_column(row, 1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.into_iter()
+            .map(|(seq, ts, kind, subject, payload)| {
+                Ok(AuditEntry {
+                    seq,
+                    ts,
+                    kind,
+                    subject,
+                    payload: serde_json::from_str(&payload)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Walks the whole chain checking sequence, links, hashes and
+    /// signatures, then looks for alerts the log recorded but which were
+    /// deleted other than by retention. Truncating the newest entries is
+    /// not detectable from the log alone; compare `head` with a copy kept
+    /// elsewhere for that.
+    pub fn verify_audit_chain(&self) -> Result<AuditVerification> {
+        let mut report = AuditVerification::default();
+        let mut stmt = self.conn.prepare(
+            "SELECT seq, ts, kind, subject, payload, prev_hash, hash, key_id, signature FROM audit_log ORDER BY seq",
+        )?;
+        let mut rows = stmt.query([])?;
+        let mut expected_seq = 1;
+        let mut prev_hash = GENESIS_HASH.to_vec();
+        while let Some(row) = rows.next()? {
+            let entry = RawEntry {
+                seq: row.get(0)?,
+                ts: row.get(1)?,
+                kind: row.get(2)?,
+                subject: row.get(3)?,
+                payload: row.get(4)?,
+                prev_hash: row.get(5)?,
+                hash: row.get(6)?,
+                key_id: row.get(7)?,
+                signature: row.get(8)?,
+            };
+            report.entries += 1;
+            report.head = Some(entry.seq);
+            if entry.seq != expected_seq {
+                report.problems.push(AuditProblem::Gap {
+                    seq: entry.seq,
+                    expected: expected_seq,
+                });
+            } else if entry.prev_hash != prev_hash {
+                report
+                    .problems
+                    .push(AuditProblem::BrokenLink { seq: entry.seq });
+            }
+            let hash = entry_hash(
+                &entry.prev_hash,
+                entry.seq,
+                &entry.ts,
+                &entry.kind,
+                &entry.subject,
+                &entry.payload,
+            );
+            if hash != entry.hash {
+                report
+                    .problems
+                    .push(AuditProblem::HashMismatch { seq: entry.seq });
+            }
+            match self.cipher_for(Some(&entry.key_id)) {
+                Ok(cipher) if cipher.verify_audit(&entry.hash, &entry.signature) => {}
+                Ok(_) => report
+                    .problems
+                    .push(AuditProblem::BadSignature { seq: entry.seq }),
+                Err(_) => report.problems.push(AuditProblem::UnknownKey {
+                    seq: entry.seq,
+                    key_id: entry.key_id.clone(),
+                }),
+            }
+            expected_seq = entry.seq + 1;
+            prev_hash = entry.hash;
+        }
+        drop(rows);
+        drop(stmt);
+
+        let missing = self
+            .conn
+            .prepare(&format!(
+                "SELECT MAX(seq), subject FROM audit_log WHERE kind = '{AUDIT_ALERT_STORED}'
+                   AND subject NOT IN (SELECT subject FROM audit_log WHERE kind = '{AUDIT_ALERT_PRUNED}')
+                   AND subject NOT IN (SELECT id FROM alerts)
+                 GROUP BY subject ORDER BY 1"
+            ))?
+            .query_map([], |row| {
+                Ok(AuditProblem::MissingAlert {
+                    seq: row.get(0)?,
+                    alert_id: row.get(1)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        report.problems.extend(missing);
+        Ok(report)
+    }
+
+    /// Re-signs entries made under other keys with the active key, after
+    /// checking their existing signature, so the log stays verifiable once
+    /// the old key is gone. Entries that fail the check are left as they
+    /// are and show up in [`Storage::verify_audit_chain`].
+    pub(crate) fn resign_audit_log(&self) -> Result<usize> {
+        let active = self.cipher.key_id().to_string();
+        let mut after = 0;
+        let mut resigned = 0;
+        loop {
+            let tx = self.conn.unchecked_transaction()?;
+            let rows = {
+                let mut stmt = tx.prepare(
+                    "SELECT seq, hash, key_id, signature FROM audit_log WHERE key_id != ?1 AND seq > ?2 ORDER BY seq LIMIT ?3",
+                )?;
+                let rows = stmt
+                    .query_map(params![active, after, RESIGN_BATCH], |row| {
+                        Ok((
+                            row.get::<_, i64>(0)?,
+                            row.get::<_, Vec<u8>>(1)?,
+                            row.get::<_, String>(2)?,
+                            row.get::<_, Vec<u8>>(3)?,
+                        ))
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                rows
+            };
+            let Some((last, ..)) = rows.last() else {
+                break;
+            };
+            after = *last;
+            for (seq, hash, key_id, signature) in rows {
+                let trusted = self
+                    .cipher_for(Some(&key_id))
+                    .map(|cipher| cipher.verify_audit(&hash, &signature))
+                    .unwrap_or(false);
+                if !trusted {
+                    tracing::warn!(seq, key_id, "audit entry fails verification, not re-signed");
+                    continue;
+                }
+                tx.execute(
+                    "UPDATE audit_log SET key_id = ?1, signature = ?2 WHERE seq = ?3",
+                    params![active, self.cipher.sign_audit(&hash), seq],
+                )?;
+                resigned += 1;
+            }
+            tx.commit()?;
+        }
+        Ok(resigned)
+    }
+
+    /// Last entry's sequence number and hash, for anchoring elsewhere.
+    pub fn audit_head(&self) -> Result<Option<(i64, Vec<u8>)>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT seq, hash FROM audit_log ORDER BY seq DESC LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use analyzer::{Alert, AlertStatus, Severity};
+
+    fn alert(id: &str) -> Alert {
+        Alert {
+            id: id.into(),
+            ts: Utc::now(),
+            severity: Severity::High,
+            rule_id: "proc.lolbin".into(),
+            summary: "powershell.exe contacted evil.example.com".into(),
+            flow_refs: Vec::new(),
+            process_ref: None,
+            rationale: String::new(),
+            suggested_action: None,
+            status: AlertStatus::New,
+            assignee: None,
+            notes: Vec::new(),
+            evidence: Default::default(),
+        }
+    }
+
+    #[test]
+    fn detects_deleted_alerts_and_rewritten_entries() {
+        let storage = Storage::open(":memory:", &[1u8; 32]).unwrap();
+        storage.put_alert(&alert("a1")).unwrap();
+        storage.put_alert(&alert("a2")).unwrap();
+        storage
+            .set_alert_status("a2", AlertStatus::Acknowledged, Some("alice"), None)
+            .unwrap();
+        let clean = storage.verify_audit_chain().unwrap();
+        assert!(clean.is_intact(), "{:?}", clean.problems);
+        assert_eq!(clean.entries, 3);
+
+        // An attacker removes the alert that exposed them...
+        storage
+            .conn
+            .execute("DELETE FROM alerts WHERE id = 'a1'", [])
+            .unwrap();
+        // ...and rewrites an entry, recomputing its hash but not the signature.
+        storage
+            .conn
+            .execute_batch("DROP TRIGGER audit_log_no_update")
+            .unwrap();
+        let (prev, ts): (Vec<u8>, String) = storage
+            .conn
+            .query_row(
+                "SELECT prev_hash, ts FROM audit_log WHERE seq = 2",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        let forged = entry_hash(&prev, 2, &ts, AUDIT_ALERT_STORED, "a2", "{}");
+        storage
+            .conn
+            .execute(
+                "UPDATE audit_log SET payload = '{}', hash = ?1 WHERE seq = 2",
+                params![forged],
+            )
+            .unwrap();
+
+        let report = storage.verify_audit_chain().unwrap();
+        assert!(report
+            .problems
+            .contains(&AuditProblem::BadSignature { seq: 2 }));
+        assert!(report
+            .problems
+            .contains(&AuditProblem::BrokenLink { seq: 3 }));
+        assert!(report.problems.contains(&AuditProblem::MissingAlert {
+            seq: 1,
+            alert_id: "a1".into(),
+        }));
+    }
+}
@@ -0,0 +1,240 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, DatabaseName, OpenFlags, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::{crypto, Storage};
+
+/// Environment variable holding the passphrase that wraps the key of a
+/// portable backup (`<backup>.key`).
+pub const BACKUP_PASSPHRASE_ENV: &str = "NETS_BACKUP_PASSPHRASE";
+const META_BACKUP: &str = "backup";
+
+/// Written into the copy's `storage_meta` and readable without its key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupInfo {
+    pub created_at: DateTime<Utc>,
+    pub schema_version: u32,
+    /// Key the flow payloads and audit signatures of the copy are sealed
+    /// under.
+    pub key_id: String,
+    pub flows: u64,
+    pub alerts: u64,
+}
+
+impl Storage {
+    /// Writes a consistent copy of the database to `dest` with SQLite's
+    /// online backup API, so writers may keep going meanwhile. With
+    /// `rekey = Some((current, new))` the copy is re-sealed under `new`,
+    /// e.g. a fresh key for moving history to another machine; this
+    /// database is left as it is. `dest` must not exist yet.
+    pub fn backup<P: AsRef<Path>>(
+        &self,
+        dest: P,
+        rekey: Option<(&[u8], &[u8])>,
+    ) -> Result<BackupInfo> {
+        let dest = dest.as_ref();
+        if dest.exists() {
+            return Err(anyhow!("{} already exists", dest.display()));
+        }
+        let result = self.write_backup(dest, rekey);
+        if result.is_err() {
+            remove_database_files(dest);
+        }
+        result
+    }
+
+    fn write_backup(&self, dest: &Path, rekey: Option<(&[u8], &[u8])>) -> Result<BackupInfo> {
+        self.conn
+            .backup(DatabaseName::Main, dest, None)
+            .with_context(|| format!("copying database to {}", dest.display()))?;
+        let key_id = match rekey {
+            Some((current, new)) => {
+                if crypto::key_id(current) != self.cipher.key_id() {
+                    return Err(anyhow!("current key does not match this database"));
+                }
+                let mut copy = Storage::open(dest, current)?;
+                copy.rotate_key(current, new)?;
+                crypto::key_id(new)
+            }
+            None => self.cipher.key_id().to_string(),
+        };
+        let conn = Connection::open(dest)?;
+        let count = |table: &str| -> Result<u64> {
+            let count: i64 =
+                conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                    row.get(0)
+                })?;
+            Ok(count as u64)
+        };
+        let info = BackupInfo {
+            created_at: Utc::now(),
+            schema_version: self.schema_version()?,
+            key_id,
+            flows: count("flows")?,
+            alerts: count("alerts")?,
+        };
+        conn.execute(
+            "INSERT OR REPLACE INTO storage_meta (key, value) VALUES (?1, ?2)",
+            params![META_BACKUP, serde_json::to_string(&info)?],
+        )?;
+        // Leave a single self-contained file behind.
+        conn.execute_batch("PRAGMA journal_mode = DELETE;")?;
+        tracing::info!(dest = %dest.display(), key_id = info.key_id, flows = info.flows, "database backed up");
+        Ok(info)
+    }
+
+    /// Reads the metadata of a backup written by [`Storage::backup`].
+    pub fn backup_info<P: AsRef<Path>>(path: P) -> Result<BackupInfo> {
+        let path = path.as_ref();
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("opening {}", path.display()))?;
+        let value: Option<String> = conn
+            .query_row(
+                "SELECT value FROM storage_meta WHERE key = ?1",
+                params![META_BACKUP],
+                |row| row.get(0),
+            )
+            .optional()
+            .with_context(|| format!("{} is not a nets database", path.display()))?;
+        let value = value.ok_or_else(|| anyhow!("{} is not a backup", path.display()))?;
+        Ok(serde_json::from_str(&value)?)
+    }
+
+    /// Replaces the contents of this database with the backup at `src`,
+    /// sealed under `backup_key`. The backup is verified and re-sealed under
+    /// `key` (this database's key) in a scratch copy first, so a wrong key
+    /// or a damaged backup leaves this database untouched; older backups are
+    /// migrated to the current schema on the way.
+    pub fn restore<P: AsRef<Path>>(
+        &mut self,
+        src: P,
+        backup_key: &[u8],
+        key: &[u8],
+    ) -> Result<BackupInfo> {
+        let src = src.as_ref();
+        let info = Self::backup_info(src)?;
+        if crypto::key_id(backup_key) != info.key_id {
+            return Err(anyhow!(
+                "backup is sealed under key {}, not {}",
+                info.key_id,
+                crypto::key_id(backup_key)
+            ));
+        }
+        if crypto::key_id(key) != self.cipher.key_id() {
+            return Err(anyhow!("key does not match this database"));
+        }
+        // Next to this database when it is a file: the backup may sit on
+        // read-only media.
+        let target = self
+            .conn
+            .path()
+            .filter(|path| !path.is_empty())
+            .map(Path::new);
+        let scratch = scratch_path(target.unwrap_or(src));
+        remove_database_files(&scratch);
+        fs::copy(src, &scratch).with_context(|| format!("copying {}", src.display()))?;
+        let result = self.restore_from_scratch(&scratch, backup_key, key);
+        remove_database_files(&scratch);
+        result?;
+        tracing::info!(src = %src.display(), flows = info.flows, "database restored");
+        Ok(info)
+    }
+
+    fn restore_from_scratch(
+        &mut self,
+        scratch: &Path,
+        backup_key: &[u8],
+        key: &[u8],
+    ) -> Result<()> {
+        {
+            let mut copy = Storage::open(scratch, backup_key)?;
+            let integrity: String = copy
+                .conn
+                .query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+            if integrity != "ok" {
+                return Err(anyhow!("backup is damaged: {integrity}"));
+            }
+            if backup_key != key {
+                copy.rotate_key(backup_key, key)?;
+            }
+            copy.conn.execute(
+                "DELETE FROM storage_meta WHERE key = ?1",
+                params![META_BACKUP],
+            )?;
+            copy.conn
+                .execute_batch("PRAGMA wal_checkpoint(TRUNCATE);")?;
+        }
+        self.conn
+            .restore(
+                DatabaseName::Main,
+                scratch,
+                None::<fn(rusqlite::backup::Progress)>,
+            )
+            .context("replacing database contents")?;
+        Ok(())
+    }
+}
+
+fn scratch_path(src: &Path) -> PathBuf {
+    let mut path = src.as_os_str().to_owned();
+    path.push(".restore");
+    PathBuf::from(path)
+}
+
+/// Deletes a database file and its journals, ignoring missing ones.
+fn remove_database_files(path: &Path) {
+    for suffix in ["", "-wal", "-shm", "-journal"] {
+        let mut file = path.as_os_str().to_owned();
+        file.push(suffix);
+        if let Err(err) = fs::remove_file(&file) {
+            if err.kind() != ErrorKind::NotFound {
+                tracing::warn!(file = ?file, %err, "cannot delete file");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use collector::FlowEvent;
+
+    #[test]
+    fn portable_backup_restores_under_another_key() {
+        let dir = std::env::temp_dir().join(format!(
+            "nets-backup-{}-{}",
+            std::process::id(),
+            Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let (key, transfer_key, other_key) = ([1u8; 32], [2u8; 32], [3u8; 32]);
+        let source = Storage::open(dir.join("source.db"), &key).unwrap();
+        source.put_flows(&vec![FlowEvent::default(); 3]).unwrap();
+
+        let archive = dir.join("archive.db");
+        let info = source
+            .backup(&archive, Some((&key, &transfer_key)))
+            .unwrap();
+        assert_eq!(info.flows, 3);
+        assert_eq!(info.key_id, crypto::key_id(&transfer_key));
+        assert_eq!(Storage::backup_info(&archive).unwrap(), info);
+        assert!(source.backup(&archive, None).is_err());
+
+        let mut target = Storage::open(dir.join("target.db"), &other_key).unwrap();
+        assert!(target.restore(&archive, &key, &other_key).is_err());
+        target.restore(&archive, &transfer_key, &other_key).unwrap();
+        let flows = target.query_flows(&crate::FlowQuery::default()).unwrap();
+        assert_eq!(flows.len(), 3);
+        assert!(target.get_flow(flows[0].id).is_ok());
+        assert!(target.verify_audit_chain().unwrap().is_intact());
+        drop((source, target));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,470 @@
+use std::collections::HashSet;
+
+use analyzer::{
+    baseline::{BaselineCommand, BaselineMode, BaselineProfile, VolumeStats},
+    first_contact::ProcessDestinations,
+    listener::KnownListener,
+};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::{audit, timestamp_column, Storage};
+
+const META_BASELINE: &str = "baseline";
+const META_BASELINE_COMMAND: &str = "baseline_command";
+
+/// Lifecycle part of a [`BaselineProfile`], kept in `storage_meta`; the
+/// learned sets live in their own tables so they can be edited row by row.
+#[derive(Serialize, Deserialize)]
+struct BaselineState {
+    mode: BaselineMode,
+    started_at: Option<DateTime<Utc>>,
+    frozen_at: Option<DateTime<Utc>>,
+}
+
+pub(crate) fn create_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS baseline_listeners (
+            key TEXT PRIMARY KEY,
+            proto TEXT NOT NULL,
+            ip TEXT NOT NULL,
+            port INTEGER NOT NULL,
+            process TEXT,
+            sha256_16 TEXT,
+            first_seen TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS baseline_processes (
+            process TEXT PRIMARY KEY,
+            first_seen TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS baseline_destinations (
+            process TEXT NOT NULL,
+            destination TEXT NOT NULL,
+            PRIMARY KEY (process, destination)
+        ) WITHOUT ROWID;
+        CREATE TABLE IF NOT EXISTS baseline_volumes (
+            process TEXT PRIMARY KEY,
+            count INTEGER NOT NULL,
+            mean REAL NOT NULL,
+            m2 REAL NOT NULL,
+            max INTEGER NOT NULL
+        );
+        "#,
+    )?;
+    Ok(())
+}
+
+pub(crate) fn drop_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        r#"
+        DROP TABLE IF EXISTS baseline_listeners;
+        DROP TABLE IF EXISTS baseline_processes;
+        DROP TABLE IF EXISTS baseline_destinations;
+        DROP TABLE IF EXISTS baseline_volumes;
+        DELETE FROM storage_meta WHERE key IN ('baseline', 'baseline_command');
+        "#,
+    )?;
+    Ok(())
+}
+
+fn delete_all(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        r#"
+        DELETE FROM baseline_listeners;
+        DELETE FROM baseline_processes;
+        DELETE FROM baseline_destinations;
+        DELETE FROM baseline_volumes;
+        "#,
+    )?;
+    Ok(())
+}
+
+impl Storage {
+    /// Replaces the stored baseline with `profile` in one transaction, so
+    /// learning survives a restart. Meant to be called periodically while
+    /// learning and on `freeze`; not audited, unlike the edits below.
+    pub fn save_baseline(&self, profile: &BaselineProfile) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        delete_all(&tx)?;
+        let state = BaselineState {
+            mode: profile.mode.clone(),
+            started_at: profile.started_at,
+            frozen_at: profile.frozen_at,
+        };
+        tx.execute(
+            "INSERT OR REPLACE INTO storage_meta (key, value) VALUES (?1, ?2)",
+            params![META_BASELINE, serde_json::to_string(&state)?],
+        )?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO baseline_listeners (key, proto, ip, port, process, sha256_16, first_seen) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            for (key, listener) in &profile.listeners {
+                stmt.execute(params![
+                    key,
+                    listener.proto,
+                    listener.ip,
+                    listener.port,
+                    listener.process,
+                    listener.sha256_16,
+                    listener.first_seen.to_rfc3339(),
+                ])?;
+            }
+            let mut process_stmt = tx.prepare_cached(
+                "INSERT INTO baseline_processes (process, first_seen) VALUES (?1, ?2)",
+            )?;
+            let mut destination_stmt = tx.prepare_cached(
+                "INSERT INTO baseline_destinations (process, destination) VALUES (?1, ?2)",
+            )?;
+            for (process, known) in &profile.destinations.processes {
+                process_stmt.execute(params![process, known.first_seen.to_rfc3339()])?;
+                for destination in &known.destinations {
+                    destination_stmt.execute(params![process, destination])?;
+                }
+            }
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO baseline_volumes (process, count, mean, m2, max) VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for (process, stats) in &profile.volumes {
+                stmt.execute(params![
+                    process,
+                    stats.count as i64,
+                    stats.mean,
+                    stats.m2,
+                    stats.max as i64,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The stored baseline, or `None` if none was ever saved.
+    pub fn load_baseline(&self) -> Result<Option<BaselineProfile>> {
+        let state: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT value FROM storage_meta WHERE key = ?1",
+                params![META_BASELINE],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(state) = state else {
+            return Ok(None);
+        };
+        let state: BaselineState = serde_json::from_str(&state)?;
+        let mut profile = BaselineProfile {
+            mode: state.mode,
+            started_at: state.started_at,
+            frozen_at: state.frozen_at,
+            ..BaselineProfile::default()
+        };
+
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT key, proto, ip, port, process, sha256_16, first_seen FROM baseline_listeners",
+        )?;
+        let listeners = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    KnownListener {
+                        proto: row.get(1)?,
+                        ip: row.get(2)?,
+                        port: row.get(3)?,
+                        process: row.get(4)?,
+                        sha256_16: row.get(5)?,
+                        first_seen: timestamp_column(row, 6)?,
+                    },
+                ))
+            })?
+            .collect::<Result<_, _>>()?;
+        profile.listeners = listeners;
+
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT process, first_seen FROM baseline_processes")?;
+        let processes = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    ProcessDestinations {
+                        first_seen: timestamp_column(row, 1)?,
+                        destinations: HashSet::new(),
+                    },
+                ))
+            })?
+            .collect::<Result<_, _>>()?;
+        profile.destinations.processes = processes;
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT process, destination FROM baseline_destinations")?;
+        let destinations = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        for (process, destination) in destinations {
+            if let Some(known) = profile.destinations.processes.get_mut(&process) {
+                known.destinations.insert(destination);
+            }
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT process, count, mean, m2, max FROM baseline_volumes")?;
+        let volumes = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    VolumeStats {
+                        count: row.get::<_, i64>(1)? as u64,
+                        mean: row.get(2)?,
+                        m2: row.get(3)?,
+                        max: row.get::<_, i64>(4)? as u64,
+                    },
+                ))
+            })?
+            .collect::<Result<_, _>>()?;
+        profile.volumes = volumes;
+        Ok(Some(profile))
+    }
+
+    /// Leaves a lifecycle command for the daemon, which applies it to its
+    /// analyzer and saves the result; a command still waiting is replaced.
+    /// Recorded in the audit log.
+    pub fn queue_baseline_command(
+        &self,
+        command: BaselineCommand,
+        actor: Option<&str>,
+    ) -> Result<()> {
+        self.edit_baseline(command.as_str(), "lifecycle", actor, |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO storage_meta (key, value) VALUES (?1, ?2)",
+                params![META_BASELINE_COMMAND, serde_json::to_string(&command)?],
+            )?;
+            Ok(true)
+        })?;
+        Ok(())
+    }
+
+    /// The command waiting for the daemon, if any.
+    pub fn pending_baseline_command(&self) -> Result<Option<BaselineCommand>> {
+        let value: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT value FROM storage_meta WHERE key = ?1",
+                params![META_BASELINE_COMMAND],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(value
+            .map(|value| serde_json::from_str(&value))
+            .transpose()?)
+    }
+
+    /// Removes and returns the waiting command, so it is applied once.
+    pub fn take_baseline_command(&self) -> Result<Option<BaselineCommand>> {
+        let value: Option<String> = self
+            .conn
+            .query_row(
+                "DELETE FROM storage_meta WHERE key = ?1 RETURNING value",
+                params![META_BASELINE_COMMAND],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(value
+            .map(|value| serde_json::from_str(&value))
+            .transpose()?)
+    }
+
+    /// Removes a listener (keyed as in [`BaselineProfile::listeners`]) so
+    /// it alerts again. Returns whether it was there.
+    pub fn forget_baseline_listener(&self, key: &str, actor: Option<&str>) -> Result<bool> {
+        self.edit_baseline("forget_listener", key, actor, |conn| {
+            Ok(conn.execute(
+                "DELETE FROM baseline_listeners WHERE key = ?1",
+                params![key],
+            )? > 0)
+        })
+    }
+
+    /// Marks `destination` as normal for `process`, e.g. to accept a
+    /// baseline deviation alert.
+    pub fn allow_baseline_destination(
+        &self,
+        process: &str,
+        destination: &str,
+        actor: Option<&str>,
+    ) -> Result<bool> {
+        let subject = format!("{process} -> {destination}");
+        self.edit_baseline("allow_destination", &subject, actor, |conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO baseline_processes (process, first_seen) VALUES (?1, ?2)",
+                params![process, Utc::now().to_rfc3339()],
+            )?;
+            Ok(conn.execute(
+                "INSERT OR IGNORE INTO baseline_destinations (process, destination) VALUES (?1, ?2)",
+                params![process, destination],
+            )? > 0)
+        })
+    }
+
+    /// Removes one learned destination of `process`. Returns whether it
+    /// was there.
+    pub fn forget_baseline_destination(
+        &self,
+        process: &str,
+        destination: &str,
+        actor: Option<&str>,
+    ) -> Result<bool> {
+        let subject = format!("{process} -> {destination}");
+        self.edit_baseline("forget_destination", &subject, actor, |conn| {
+            Ok(conn.execute(
+                "DELETE FROM baseline_destinations WHERE process = ?1 AND destination = ?2",
+                params![process, destination],
+            )? > 0)
+        })
+    }
+
+    /// Removes everything learned about `process`: its destinations and
+    /// volume statistics.
+    pub fn forget_baseline_process(&self, process: &str, actor: Option<&str>) -> Result<bool> {
+        self.edit_baseline("forget_process", process, actor, |conn| {
+            let mut removed = 0;
+            for table in [
+                "baseline_processes",
+                "baseline_destinations",
+                "baseline_volumes",
+            ] {
+                removed += conn.execute(
+                    &format!("DELETE FROM {table} WHERE process = ?1"),
+                    params![process],
+                )?;
+            }
+            Ok(removed > 0)
+        })
+    }
+
+    /// Applies an edit and records it in the audit log, in one transaction.
+    /// Edits that change nothing are not recorded.
+    fn edit_baseline(
+        &self,
+        action: &str,
+        subject: &str,
+        actor: Option<&str>,
+        edit: impl FnOnce(&Connection) -> Result<bool>,
+    ) -> Result<bool> {
+        let tx = self.conn.unchecked_transaction()?;
+        let changed = edit(&tx)?;
+        if changed {
+            audit::append(
+                &tx,
+                &self.cipher,
+                audit::AUDIT_BASELINE_EDIT,
+                subject,
+                &serde_json::json!({ "action": action, "actor": actor }),
+            )?;
+        }
+        tx.commit()?;
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn saves_loads_and_edits_profile() {
+        let storage = Storage::open(":memory:", &[7u8; 32]).unwrap();
+        assert!(storage.load_baseline().unwrap().is_none());
+
+        let seen = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let mut profile = BaselineProfile {
+            mode: BaselineMode::Enforcing,
+            started_at: Some(seen),
+            frozen_at: Some(seen),
+            ..BaselineProfile::default()
+        };
+        profile.listeners.insert(
+            "TCP/0.0.0.0:22/sshd".into(),
+            KnownListener {
+                proto: "tcp".into(),
+                ip: "0.0.0.0".into(),
+                port: 22,
+                process: Some("sshd".into()),
+                sha256_16: None,
+                first_seen: seen,
+            },
+        );
+        profile.destinations.processes.insert(
+            "backup".into(),
+            ProcessDestinations {
+                first_seen: seen,
+                destinations: ["203.0.113.5".to_string()].into(),
+            },
+        );
+        profile
+            .volumes
+            .entry("backup".into())
+            .or_default()
+            .record(1_000);
+        storage.save_baseline(&profile).unwrap();
+        // Saving again replaces rather than duplicates.
+        storage.save_baseline(&profile).unwrap();
+
+        let loaded = storage.load_baseline().unwrap().unwrap();
+        assert_eq!(loaded.mode, BaselineMode::Enforcing);
+        assert_eq!(loaded.listeners["TCP/0.0.0.0:22/sshd"].port, 22);
+        assert_eq!(
+            loaded.destinations.processes["backup"].destinations.len(),
+            1
+        );
+        assert_eq!(loaded.volumes["backup"].max, 1_000);
+
+        assert!(storage
+            .allow_baseline_destination("backup", "backup.example.net", Some("alice"))
+            .unwrap());
+        assert!(storage
+            .forget_baseline_listener("TCP/0.0.0.0:22/sshd", None)
+            .unwrap());
+        assert!(!storage
+            .forget_baseline_listener("TCP/0.0.0.0:22/sshd", None)
+            .unwrap());
+        let edited = storage.load_baseline().unwrap().unwrap();
+        assert!(edited.listeners.is_empty());
+        assert!(edited.destinations.processes["backup"]
+            .destinations
+            .contains("backup.example.net"));
+
+        storage
+            .queue_baseline_command(BaselineCommand::Learn, None)
+            .unwrap();
+        storage
+            .queue_baseline_command(BaselineCommand::Freeze, Some("alice"))
+            .unwrap();
+        assert_eq!(
+            storage.pending_baseline_command().unwrap(),
+            Some(BaselineCommand::Freeze)
+        );
+        assert_eq!(
+            storage.take_baseline_command().unwrap(),
+            Some(BaselineCommand::Freeze)
+        );
+        assert_eq!(storage.take_baseline_command().unwrap(), None);
+
+        let entries = storage.audit_entries(0, 10).unwrap();
+        assert_eq!(
+            entries
+                .iter()
+                .filter(|entry| entry.kind == audit::AUDIT_BASELINE_EDIT)
+                .count(),
+            4
+        );
+        assert!(storage.verify_audit_chain().unwrap().is_intact());
+    }
+}
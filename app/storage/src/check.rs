@@ -0,0 +1,200 @@
+use anyhow::Result;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+
+use crate::{audit::AuditVerification, FlowReadError, Storage};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CheckOptions {
+    /// Encrypted flow rows picked at random for decryption and metadata
+    /// checks; 0 checks every row.
+    pub sample: usize,
+    /// Delete orphaned link rows and rebuild a damaged search index.
+    pub repair: bool,
+}
+
+impl Default for CheckOptions {
+    fn default() -> Self {
+        Self {
+            sample: 256,
+            repair: false,
+        }
+    }
+}
+
+/// A sampled flow row that could not be read back.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlowProblem {
+    pub id: i64,
+    /// The row was altered after it was written, as opposed to being
+    /// unreadable for another reason (unknown key, missing payload).
+    pub tampering: bool,
+    pub error: String,
+}
+
+/// Link rows pointing at something that no longer exists.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Orphans {
+    pub alert_flows: usize,
+    pub incident_alerts: usize,
+}
+
+impl Orphans {
+    pub fn total(&self) -> usize {
+        self.alert_flows + self.incident_alerts
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckReport {
+    /// Messages from `PRAGMA integrity_check`; empty when it reported ok.
+    pub integrity_errors: Vec<String>,
+    pub flows_sampled: usize,
+    pub flow_problems: Vec<FlowProblem>,
+    pub orphans: Orphans,
+    pub search_index_ok: bool,
+    pub audit: AuditVerification,
+    /// Whether orphans were deleted and the search index rebuilt.
+    pub repaired: bool,
+}
+
+impl CheckReport {
+    /// No problem was found, or every one found was repaired.
+    pub fn is_healthy(&self) -> bool {
+        self.integrity_errors.is_empty()
+            && self.flow_problems.is_empty()
+            && self.audit.is_intact()
+            && (self.repaired || (self.orphans.total() == 0 && self.search_index_ok))
+    }
+}
+
+const ORPHAN_ALERT_FLOWS: &str = "FROM alert_flows WHERE flow_id NOT IN (SELECT id FROM flows) OR alert_id NOT IN (SELECT id FROM alerts)";
+const ORPHAN_INCIDENT_ALERTS: &str = "FROM incident_alerts WHERE incident_id NOT IN (SELECT id FROM incidents) OR alert_id NOT IN (SELECT id FROM alerts)";
+
+impl Storage {
+    /// Checks the file, a sample of encrypted flows, link tables, the
+    /// search index and the audit chain. With `repair`, orphaned links are
+    /// deleted and the search index rebuilt; damaged pages and tampered
+    /// rows are only reported (restore from a backup for those).
+    pub fn check(&self, options: &CheckOptions) -> Result<CheckReport> {
+        let mut report = CheckReport::default();
+
+        let mut stmt = self.conn.prepare("PRAGMA integrity_check")?;
+        let messages = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        drop(stmt);
+        if messages != ["ok"] {
+            report.integrity_errors = messages;
+        }
+
+        let limit = if options.sample == 0 {
+            -1
+        } else {
+            options.sample as i64
+        };
+        let ids = self
+            .conn
+            .prepare(
+                "SELECT id FROM flows WHERE ciphertext IS NOT NULL ORDER BY RANDOM() LIMIT ?1",
+            )?
+            .query_map(params![limit], |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        report.flows_sampled = ids.len();
+        for id in ids {
+            if let Err(err) = self.get_flow(id) {
+                let tampering = err
+                    .downcast_ref::<FlowReadError>()
+                    .is_some_and(FlowReadError::is_tampering);
+                report.flow_problems.push(FlowProblem {
+                    id,
+                    tampering,
+                    error: format!("{err:#}"),
+                });
+            }
+        }
+        report.flow_problems.sort_by_key(|problem| problem.id);
+
+        let count = |from: &str| -> Result<usize> {
+            let count: i64 =
+                self.conn
+                    .query_row(&format!("SELECT COUNT(*) {from}"), [], |row| row.get(0))?;
+            Ok(count as usize)
+        };
+        report.orphans = Orphans {
+            alert_flows: count(ORPHAN_ALERT_FLOWS)?,
+            incident_alerts: count(ORPHAN_INCIDENT_ALERTS)?,
+        };
+        report.search_index_ok = self
+            .conn
+            .execute_batch("INSERT INTO alerts_fts (alerts_fts) VALUES ('integrity-check');")
+            .is_ok();
+        report.audit = self.verify_audit_chain()?;
+
+        if options.repair {
+            let tx = self.conn.unchecked_transaction()?;
+            tx.execute(&format!("DELETE {ORPHAN_ALERT_FLOWS}"), [])?;
+            tx.execute(&format!("DELETE {ORPHAN_INCIDENT_ALERTS}"), [])?;
+            if !report.search_index_ok {
+                tx.execute_batch("INSERT INTO alerts_fts (alerts_fts) VALUES ('rebuild');")?;
+            }
+            tx.commit()?;
+            report.repaired = true;
+        }
+        tracing::info!(
+            integrity_errors = report.integrity_errors.len(),
+            flow_problems = report.flow_problems.len(),
+            orphans = report.orphans.total(),
+            repaired = report.repaired,
+            "database check finished"
+        );
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use collector::FlowEvent;
+
+    #[test]
+    fn reports_tampered_rows_and_repairs_orphans() {
+        let storage = Storage::open(":memory:", &[4u8; 32]).unwrap();
+        let ids = storage
+            .put_flows(&[FlowEvent::default(), FlowEvent::default()])
+            .unwrap();
+        storage
+            .conn
+            .execute(
+                "UPDATE flows SET dst_port = dst_port + 1 WHERE id = ?1",
+                params![ids[0]],
+            )
+            .unwrap();
+        storage
+            .conn
+            .execute(
+                "INSERT INTO alert_flows (alert_id, flow_id) VALUES ('gone', ?1)",
+                params![ids[1]],
+            )
+            .unwrap();
+
+        let report = storage.check(&CheckOptions::default()).unwrap();
+        assert!(report.integrity_errors.is_empty());
+        assert_eq!(report.flows_sampled, 2);
+        assert_eq!(report.flow_problems.len(), 1);
+        assert!(report.flow_problems[0].tampering);
+        assert_eq!(report.orphans.alert_flows, 1);
+        assert!(!report.is_healthy());
+
+        let repaired = storage
+            .check(&CheckOptions {
+                repair: true,
+                ..CheckOptions::default()
+            })
+            .unwrap();
+        assert!(repaired.repaired);
+        let after = storage.check(&CheckOptions::default()).unwrap();
+        assert_eq!(after.orphans.total(), 0);
+    }
+}
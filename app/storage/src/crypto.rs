@@ -0,0 +1,180 @@
+use anyhow::{anyhow, Result};
+use ring::{
+    aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, NONCE_LEN},
+    digest, hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+use thiserror::Error;
+
+/// Associated data of the formats before [`FORMAT_V2`].
+pub(crate) const AAD_CONTEXT: &[u8] = b"nets-local-monitor";
+/// Domain prefix for deriving the audit-log signing key from the database key.
+const AUDIT_KEY_CONTEXT: &[u8] = b"nets-audit-log";
+/// Domain prefix for deriving the key of the per-row MACs over the
+/// plaintext flow columns.
+const ROW_MAC_KEY_CONTEXT: &[u8] = b"nets-flow-row-mac";
+
+/// Rows written before per-record nonces: every blob sealed under the
+/// all-zero nonce. Readable only so they can be migrated.
+pub const FORMAT_LEGACY: i64 = 0;
+/// AES-256-GCM with a random 96-bit nonce stored next to the ciphertext.
+pub const FORMAT_V1: i64 = 1;
+/// As [`FORMAT_V1`], with associated data binding the payload to its row
+/// id, format and key id: a blob copied into another row, relabelled as
+/// another format or attributed to another key no longer opens.
+pub const FORMAT_V2: i64 = 2;
+
+/// Ciphertext (with appended tag) plus what is needed to open it again.
+pub struct Sealed {
+    pub version: i64,
+    pub key_id: String,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum OpenError {
+    #[error("unsupported ciphertext format {0}")]
+    UnsupportedFormat(i64),
+    #[error("missing or malformed nonce")]
+    MalformedNonce,
+    /// The GCM tag did not verify: the ciphertext, nonce or associated
+    /// data was altered, or the key is wrong.
+    #[error("authentication failed")]
+    Authentication,
+}
+
+/// Stable, non-secret identifier of a key: the first 8 bytes of its
+/// SHA-256, hex encoded.
+pub fn key_id(key_bytes: &[u8]) -> String {
+    digest::digest(&digest::SHA256, key_bytes).as_ref()[..8]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Associated data a payload of `version` for row `row_id` is sealed with.
+fn associated_data(row_id: i64, version: i64, key_id: &str) -> Vec<u8> {
+    if version < FORMAT_V2 {
+        return AAD_CONTEXT.to_vec();
+    }
+    let mut aad = Vec::with_capacity(AAD_CONTEXT.len() + 32);
+    aad.extend_from_slice(AAD_CONTEXT);
+    aad.extend_from_slice(&row_id.to_be_bytes());
+    aad.extend_from_slice(&version.to_be_bytes());
+    aad.extend_from_slice(key_id.as_bytes());
+    aad
+}
+
+fn derive_hmac_key(context: &[u8], key_bytes: &[u8]) -> hmac::Key {
+    let mut derived = digest::Context::new(&digest::SHA256);
+    derived.update(context);
+    derived.update(key_bytes);
+    hmac::Key::new(hmac::HMAC_SHA256, derived.finish().as_ref())
+}
+
+pub struct FlowCipher {
+    key: LessSafeKey,
+    key_id: String,
+    audit_key: hmac::Key,
+    row_key: hmac::Key,
+    rng: SystemRandom,
+}
+
+impl FlowCipher {
+    pub fn new(key_bytes: &[u8]) -> Result<Self> {
+        if key_bytes.len() != 32 {
+            return Err(anyhow!("AES-256-GCM key must be 32 bytes"));
+        }
+        let unbound_key = UnboundKey::new(&aead::AES_256_GCM, key_bytes)
+            .map_err(|_| anyhow!("failed to initialize encryption key"))?;
+        Ok(Self {
+            key: LessSafeKey::new(unbound_key),
+            key_id: key_id(key_bytes),
+            audit_key: derive_hmac_key(AUDIT_KEY_CONTEXT, key_bytes),
+            row_key: derive_hmac_key(ROW_MAC_KEY_CONTEXT, key_bytes),
+            rng: SystemRandom::new(),
+        })
+    }
+
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// HMAC-SHA256 of an audit entry hash under a key derived from this one.
+    pub fn sign_audit(&self, hash: &[u8]) -> Vec<u8> {
+        hmac::sign(&self.audit_key, hash).as_ref().to_vec()
+    }
+
+    pub fn verify_audit(&self, hash: &[u8], signature: &[u8]) -> bool {
+        hmac::verify(&self.audit_key, hash, signature).is_ok()
+    }
+
+    /// HMAC-SHA256 of a flow row's plaintext columns under a key derived
+    /// from this one.
+    pub fn sign_row(&self, columns: &[u8]) -> Vec<u8> {
+        hmac::sign(&self.row_key, columns).as_ref().to_vec()
+    }
+
+    pub fn verify_row(&self, columns: &[u8], mac: &[u8]) -> bool {
+        hmac::verify(&self.row_key, columns, mac).is_ok()
+    }
+
+    /// Seals the payload of row `row_id` in the current format.
+    pub fn seal(&self, row_id: i64, plaintext: &[u8]) -> Result<Sealed> {
+        self.seal_as(FORMAT_V2, row_id, plaintext)
+    }
+
+    /// Seals in `version`, which must be [`FORMAT_V1`] or later; for
+    /// handing rows back to builds that predate the current format.
+    pub(crate) fn seal_as(&self, version: i64, row_id: i64, plaintext: &[u8]) -> Result<Sealed> {
+        if !(FORMAT_V1..=FORMAT_V2).contains(&version) {
+            return Err(anyhow!("cannot seal ciphertext format {version}"));
+        }
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("failed to generate nonce"))?;
+        let mut ciphertext = plaintext.to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(associated_data(row_id, version, &self.key_id)),
+                &mut ciphertext,
+            )
+            .map_err(|_| anyhow!("failed to encrypt flow"))?;
+        Ok(Sealed {
+            version,
+            key_id: self.key_id.clone(),
+            nonce: nonce.to_vec(),
+            ciphertext,
+        })
+    }
+
+    pub fn open(
+        &self,
+        row_id: i64,
+        version: i64,
+        nonce: Option<&[u8]>,
+        mut ciphertext: Vec<u8>,
+    ) -> Result<Vec<u8>, OpenError> {
+        let nonce = match version {
+            FORMAT_LEGACY => [0u8; NONCE_LEN],
+            FORMAT_V1 | FORMAT_V2 => nonce
+                .and_then(|nonce| <[u8; NONCE_LEN]>::try_from(nonce).ok())
+                .ok_or(OpenError::MalformedNonce)?,
+            other => return Err(OpenError::UnsupportedFormat(other)),
+        };
+        let len = self
+            .key
+            .open_in_place(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(associated_data(row_id, version, &self.key_id)),
+                &mut ciphertext,
+            )
+            .map_err(|_| OpenError::Authentication)?
+            .len();
+        ciphertext.truncate(len);
+        Ok(ciphertext)
+    }
+}
@@ -0,0 +1,277 @@
+//! What showed up in one period that was absent from another: listening
+//! ports, destinations per process and LAN devices. Answers "what
+//! changed?" for `nets-cli diff`.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use analyzer::is_lan;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+
+use crate::{timestamp_column, NewDevice, Storage};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Period {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+/// A local port that took inbound flows owned by a process.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NewListener {
+    pub process: String,
+    pub proto: String,
+    pub port: u16,
+    pub first_seen: DateTime<Utc>,
+    pub flows: u64,
+}
+
+/// A host a process reached out to, by domain when one is known.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NewDestination {
+    pub process: Option<String>,
+    pub destination: String,
+    pub first_seen: DateTime<Utc>,
+    pub flows: u64,
+    pub bytes: u64,
+}
+
+/// Everything new in `after` compared to `before`, oldest first within
+/// each kind and at most `limit` of each.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeriodDiff {
+    pub before: Period,
+    pub after: Period,
+    pub listeners: Vec<NewListener>,
+    pub destinations: Vec<NewDestination>,
+    pub devices: Vec<NewDevice>,
+}
+
+/// First sighting, flows and bytes of one grouping key in a period.
+struct Seen {
+    first: DateTime<Utc>,
+    flows: u64,
+    bytes: u64,
+}
+
+type Key = (Option<String>, String, Option<u16>);
+
+impl Storage {
+    pub fn diff_periods(&self, before: Period, after: Period, limit: usize) -> Result<PeriodDiff> {
+        // Inbound flows without a process are mostly probes of closed
+        // ports; attribution is what shows something is listening.
+        let listeners_sql = "SELECT process, proto, dst_port, MIN(ts_first), COUNT(*), SUM(bytes)
+             FROM flows WHERE direction = 'Inbound' AND process IS NOT NULL
+               AND ts_first >= ?1 AND ts_first <= ?2
+             GROUP BY process, proto, dst_port";
+        let listeners = new_in(
+            self.grouped(listeners_sql, before)?,
+            self.grouped(listeners_sql, after)?,
+        )
+        .into_iter()
+        .map(|((process, proto, port), seen)| NewListener {
+            process: process.unwrap_or_default(),
+            proto,
+            port: port.unwrap_or_default(),
+            first_seen: seen.first,
+            flows: seen.flows,
+        })
+        .collect();
+
+        let destinations_sql =
+            "SELECT process, COALESCE(domain, dst_ip), NULL, MIN(ts_first), COUNT(*), SUM(bytes)
+             FROM flows WHERE direction IN ('Outbound', 'Lateral')
+               AND ts_first >= ?1 AND ts_first <= ?2
+             GROUP BY process, COALESCE(domain, dst_ip)";
+        let destinations = new_in(
+            self.grouped(destinations_sql, before)?,
+            self.grouped(destinations_sql, after)?,
+        )
+        .into_iter()
+        .map(|((process, destination, _), seen)| NewDestination {
+            process,
+            destination,
+            first_seen: seen.first,
+            flows: seen.flows,
+            bytes: seen.bytes,
+        })
+        .collect();
+
+        let addresses_sql = "SELECT NULL, ip, NULL, MIN(ts), COUNT(*), SUM(bytes) FROM (
+                 SELECT src_ip AS ip, ts_first AS ts, bytes FROM flows
+                 WHERE ts_first >= ?1 AND ts_first <= ?2
+                 UNION ALL SELECT dst_ip, ts_first, bytes FROM flows
+                 WHERE ts_first >= ?1 AND ts_first <= ?2)
+             GROUP BY ip";
+        let names: HashMap<String, String> = self
+            .list_services(u32::MAX as usize)?
+            .into_iter()
+            .map(|service| (service.address, service.name))
+            .collect();
+        let devices = new_in(
+            self.grouped(addresses_sql, before)?,
+            self.grouped(addresses_sql, after)?,
+        )
+        .into_iter()
+        .filter(|((_, address, _), _)| is_lan(address))
+        .map(|((_, address, _), seen)| NewDevice {
+            name: names.get(&address).cloned(),
+            address,
+            first_seen: seen.first,
+            flows: seen.flows,
+        })
+        .collect();
+
+        let mut diff = PeriodDiff {
+            before,
+            after,
+            listeners,
+            destinations,
+            devices,
+        };
+        diff.listeners.sort_by_key(|listener| listener.first_seen);
+        diff.destinations
+            .sort_by_key(|destination| destination.first_seen);
+        diff.devices.sort_by_key(|device| device.first_seen);
+        diff.listeners.truncate(limit);
+        diff.destinations.truncate(limit);
+        diff.devices.truncate(limit);
+        Ok(diff)
+    }
+
+    /// Runs a query grouping flows of `period` by up to three key columns,
+    /// followed by first sighting, flow count and bytes.
+    fn grouped(&self, sql: &str, period: Period) -> Result<BTreeMap<Key, Seen>> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let rows = stmt
+            .query_map(
+                params![period.from.to_rfc3339(), period.to.to_rfc3339()],
+                |row| {
+                    Ok((
+                        (row.get(0)?, row.get(1)?, row.get(2)?),
+                        Seen {
+                            first: timestamp_column(row, 3)?,
+                            flows: row.get::<_, i64>(4)? as u64,
+                            bytes: row.get::<_, Option<i64>>(5)?.unwrap_or_default() as u64,
+                        },
+                    ))
+                },
+            )?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(rows)
+    }
+}
+
+fn new_in(before: BTreeMap<Key, Seen>, after: BTreeMap<Key, Seen>) -> Vec<(Key, Seen)> {
+    let known: HashSet<Key> = before.into_keys().collect();
+    after
+        .into_iter()
+        .filter(|(key, _)| !known.contains(key))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use collector::{FlowDirection, FlowEvent, ProcessIdentity};
+
+    fn flow(
+        at: DateTime<Utc>,
+        direction: FlowDirection,
+        process: &str,
+        (src, dst, port): (&str, &str, u16),
+    ) -> FlowEvent {
+        FlowEvent {
+            ts_first: at,
+            ts_last: at,
+            proto: "TCP".into(),
+            src_ip: src.into(),
+            dst_ip: dst.into(),
+            dst_port: port,
+            direction,
+            bytes: 100,
+            process: Some(ProcessIdentity {
+                pid: 1,
+                ppid: None,
+                name: Some(process.into()),
+                exe_path: None,
+                sha256_16: None,
+                user: None,
+                signed: None,
+            }),
+            ..FlowEvent::default()
+        }
+    }
+
+    #[test]
+    fn reports_what_the_later_period_added() {
+        let storage = Storage::open(":memory:", &[3u8; 32]).unwrap();
+        let now = Utc::now();
+        let yesterday = now - Duration::days(1);
+        for at in [yesterday, now] {
+            storage
+                .put_flow(&flow(
+                    at,
+                    FlowDirection::Outbound,
+                    "curl",
+                    ("192.168.1.10", "93.184.216.34", 443),
+                ))
+                .unwrap();
+        }
+        storage
+            .put_flow(&flow(
+                now,
+                FlowDirection::Outbound,
+                "curl",
+                ("192.168.1.10", "203.0.113.7", 443),
+            ))
+            .unwrap();
+        storage
+            .put_flow(&flow(
+                now,
+                FlowDirection::Outbound,
+                "ssh",
+                ("192.168.1.44", "192.168.1.10", 22),
+            ))
+            .unwrap();
+        storage
+            .put_flow(&flow(
+                now,
+                FlowDirection::Inbound,
+                "nc",
+                ("10.0.0.9", "192.168.1.10", 4444),
+            ))
+            .unwrap();
+
+        let period = |at: DateTime<Utc>| Period {
+            from: at - Duration::hours(1),
+            to: at + Duration::hours(1),
+        };
+        let diff = storage
+            .diff_periods(period(yesterday), period(now), 10)
+            .unwrap();
+        assert_eq!(
+            diff.listeners
+                .iter()
+                .map(|l| (l.process.as_str(), l.port))
+                .collect::<Vec<_>>(),
+            [("nc", 4444)]
+        );
+        let mut destinations: Vec<_> = diff
+            .destinations
+            .iter()
+            .map(|d| (d.process.as_deref().unwrap(), d.destination.as_str()))
+            .collect();
+        destinations.sort();
+        assert_eq!(
+            destinations,
+            [("curl", "203.0.113.7"), ("ssh", "192.168.1.10")]
+        );
+        let mut devices: Vec<_> = diff.devices.iter().map(|d| d.address.as_str()).collect();
+        devices.sort();
+        assert_eq!(devices, ["10.0.0.9", "192.168.1.44"]);
+    }
+}
@@ -0,0 +1,279 @@
+//! Triage views over the DNS queries recorded in flows: the most queried
+//! names, hosts producing bursts of NXDOMAIN answers, and names asked for
+//! only once.
+
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::Result;
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use collector::FlowEvent;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+
+use crate::{SealedRow, Storage};
+
+/// Names kept per NXDOMAIN source as examples.
+const SAMPLE_NAMES: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DomainStats {
+    /// Lowercased, without the trailing dot.
+    pub domain: String,
+    pub queries: u64,
+    pub nxdomain: u64,
+    /// Distinct hosts that asked.
+    pub clients: usize,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NxdomainSource {
+    /// Address of the host that asked.
+    pub source: String,
+    /// Processes behind the queries, when the collector attributed them.
+    pub processes: Vec<String>,
+    pub nxdomain: u64,
+    pub distinct_names: usize,
+    /// Most NXDOMAIN answers within one minute, and when that minute began.
+    pub peak_per_minute: u64,
+    pub peak_at: DateTime<Utc>,
+    pub sample: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct DomainState {
+    queries: u64,
+    nxdomain: u64,
+    clients: BTreeSet<String>,
+    first_seen: Option<DateTime<Utc>>,
+    last_seen: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default)]
+struct SourceState {
+    nxdomain: u64,
+    names: BTreeSet<String>,
+    processes: BTreeSet<String>,
+    per_minute: HashMap<DateTime<Utc>, u64>,
+}
+
+/// Aggregates DNS queries fed one flow at a time, either from storage
+/// ([`Storage::dns_summary`]) or from a live flow stream.
+#[derive(Debug, Default)]
+pub struct DnsSummary {
+    domains: HashMap<String, DomainState>,
+    sources: HashMap<String, SourceState>,
+}
+
+impl DnsSummary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts the flow's query; flows without one are ignored.
+    pub fn observe(&mut self, flow: &FlowEvent) {
+        let Some(domain) = flow
+            .dns_qname
+            .as_deref()
+            .map(|name| name.trim_end_matches('.').to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+        else {
+            return;
+        };
+        let nxdomain = flow
+            .dns_rcode
+            .as_deref()
+            .is_some_and(|rcode| rcode.eq_ignore_ascii_case("NXDOMAIN"));
+        let state = self.domains.entry(domain.clone()).or_default();
+        state.queries += 1;
+        state.nxdomain += u64::from(nxdomain);
+        state.clients.insert(flow.src_ip.clone());
+        state.first_seen = Some(
+            state
+                .first_seen
+                .map_or(flow.ts_first, |ts| ts.min(flow.ts_first)),
+        );
+        state.last_seen = Some(
+            state
+                .last_seen
+                .map_or(flow.ts_first, |ts| ts.max(flow.ts_first)),
+        );
+        if !nxdomain {
+            return;
+        }
+        let source = self.sources.entry(flow.src_ip.clone()).or_default();
+        source.nxdomain += 1;
+        source.names.insert(domain);
+        if let Some(name) = flow.process.as_ref().and_then(|p| p.name.clone()) {
+            source.processes.insert(name);
+        }
+        let minute = flow
+            .ts_first
+            .duration_trunc(TimeDelta::minutes(1))
+            .unwrap_or(flow.ts_first);
+        *source.per_minute.entry(minute).or_default() += 1;
+    }
+
+    /// Most queried names first.
+    pub fn top(&self, limit: usize) -> Vec<DomainStats> {
+        let mut domains = self.domain_stats(|_| true);
+        domains.sort_by(|a, b| {
+            b.queries
+                .cmp(&a.queries)
+                .then_with(|| a.domain.cmp(&b.domain))
+        });
+        domains.truncate(limit);
+        domains
+    }
+
+    /// Hosts with NXDOMAIN answers, the sharpest burst first.
+    pub fn nxdomain_sources(&self, limit: usize) -> Vec<NxdomainSource> {
+        let mut sources: Vec<NxdomainSource> = self
+            .sources
+            .iter()
+            .map(|(source, state)| {
+                let (peak_at, peak) = state
+                    .per_minute
+                    .iter()
+                    .max_by(|a, b| a.1.cmp(b.1).then_with(|| b.0.cmp(a.0)))
+                    .map(|(at, count)| (*at, *count))
+                    .unwrap_or_default();
+                NxdomainSource {
+                    source: source.clone(),
+                    processes: state.processes.iter().cloned().collect(),
+                    nxdomain: state.nxdomain,
+                    distinct_names: state.names.len(),
+                    peak_per_minute: peak,
+                    peak_at,
+                    sample: state.names.iter().take(SAMPLE_NAMES).cloned().collect(),
+                }
+            })
+            .collect();
+        sources.sort_by(|a, b| {
+            (b.peak_per_minute, b.nxdomain)
+                .cmp(&(a.peak_per_minute, a.nxdomain))
+                .then_with(|| a.source.cmp(&b.source))
+        });
+        sources.truncate(limit);
+        sources
+    }
+
+    /// Names queried exactly once, most recent first.
+    pub fn rare(&self, limit: usize) -> Vec<DomainStats> {
+        let mut domains = self.domain_stats(|state| state.queries == 1);
+        domains.sort_by(|a, b| {
+            b.last_seen
+                .cmp(&a.last_seen)
+                .then_with(|| a.domain.cmp(&b.domain))
+        });
+        domains.truncate(limit);
+        domains
+    }
+
+    fn domain_stats(&self, keep: impl Fn(&DomainState) -> bool) -> Vec<DomainStats> {
+        self.domains
+            .iter()
+            .filter(|(_, state)| keep(state))
+            .map(|(domain, state)| DomainStats {
+                domain: domain.clone(),
+                queries: state.queries,
+                nxdomain: state.nxdomain,
+                clients: state.clients.len(),
+                first_seen: state.first_seen.unwrap_or_default(),
+                last_seen: state.last_seen.unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+impl Storage {
+    /// [`DnsSummary`] of the stored flows that started in `[from, to]`.
+    /// Only rows with a domain are decrypted.
+    pub fn dns_summary(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<DnsSummary> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT id, {} FROM flows WHERE ts_first >= ?1 AND ts_first <= ?2 AND domain IS NOT NULL AND ciphertext IS NOT NULL",
+            SealedRow::COLUMNS
+        ))?;
+        let rows = stmt
+            .query_map(params![from.to_rfc3339(), to.to_rfc3339()], |row| {
+                Ok((row.get::<_, i64>(0)?, SealedRow::read(row, 1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut summary = DnsSummary::new();
+        for (id, sealed) in rows {
+            summary.observe(&self.decrypt_flow(id, sealed)?);
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn ranks_names_and_nxdomain_bursts() {
+        let storage = Storage::open(":memory:", &[9u8; 32]).unwrap();
+        let start = Utc::now().duration_trunc(TimeDelta::hours(1)).unwrap() - Duration::hours(1);
+        let query = |src: &str, name: &str, rcode: &str, at: Duration| FlowEvent {
+            ts_first: start + at,
+            ts_last: start + at,
+            proto: "udp".into(),
+            src_ip: src.into(),
+            dst_ip: "192.168.1.1".into(),
+            dst_port: 53,
+            dns_qname: Some(name.into()),
+            dns_rcode: Some(rcode.into()),
+            ..FlowEvent::default()
+        };
+        let mut flows = vec![
+            query("10.0.0.2", "Example.com.", "NOERROR", Duration::minutes(1)),
+            query("10.0.0.3", "example.com", "NOERROR", Duration::minutes(2)),
+            query(
+                "10.0.0.2",
+                "once.example.net",
+                "NOERROR",
+                Duration::minutes(3),
+            ),
+            query("10.0.0.3", "typo.example", "NXDOMAIN", Duration::minutes(4)),
+        ];
+        for n in 0..6 {
+            flows.push(query(
+                "10.0.0.9",
+                &format!("x{n}q.dga.example"),
+                "NXDOMAIN",
+                Duration::minutes(10) + Duration::seconds(n),
+            ));
+        }
+        storage.put_flows(&flows).unwrap();
+        // A flow without DNS is skipped.
+        storage
+            .put_flow(&FlowEvent {
+                ts_first: start,
+                sni: Some("tls.example".into()),
+                ..FlowEvent::default()
+            })
+            .unwrap();
+
+        let summary = storage
+            .dns_summary(start, start + Duration::hours(1))
+            .unwrap();
+        let top = summary.top(1);
+        assert_eq!(top[0].domain, "example.com");
+        assert_eq!((top[0].queries, top[0].clients), (2, 2));
+
+        let sources = summary.nxdomain_sources(10);
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[0].source, "10.0.0.9");
+        assert_eq!((sources[0].nxdomain, sources[0].peak_per_minute), (6, 6));
+        assert_eq!(sources[0].peak_at, start + Duration::minutes(10));
+        assert_eq!(sources[0].sample.len(), SAMPLE_NAMES);
+
+        let rare = summary.rare(100);
+        assert_eq!(rare.len(), 8);
+        assert!(rare.iter().all(|domain| domain.domain != "example.com"));
+        assert!(rare.iter().all(|domain| domain.domain != "tls.example"));
+    }
+}
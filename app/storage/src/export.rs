@@ -0,0 +1,410 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use analyzer::Alert;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use collector::FlowEvent;
+use serde::{Deserialize, Serialize};
+
+use crate::{AlertQuery, FlowQuery, Storage};
+
+/// Rows fetched per query while streaming an export.
+const EXPORT_PAGE: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Ndjson,
+    /// Requires the `parquet` feature.
+    Parquet,
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "ndjson" | "jsonl" | "json" => Ok(ExportFormat::Ndjson),
+            "parquet" => Ok(ExportFormat::Parquet),
+            other => Err(anyhow!("unknown export format: {other}")),
+        }
+    }
+}
+
+/// What to export. `limit` caps the total number of rows and `offset`
+/// skips rows, as for the underlying queries.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ExportQuery {
+    Flows(FlowQuery),
+    Alerts(AlertQuery),
+}
+
+/// A decrypted flow as written to NDJSON: the `FlowEvent` schema plus the
+/// storage row id, so the file can be re-imported.
+#[derive(Serialize)]
+struct FlowLine<'a> {
+    id: i64,
+    #[serde(flatten)]
+    flow: &'a FlowEvent,
+}
+
+/// Flat flow row for CSV and Parquet.
+#[derive(Serialize)]
+struct FlowRecord {
+    id: i64,
+    ts_first: DateTime<Utc>,
+    ts_last: DateTime<Utc>,
+    proto: String,
+    src_ip: String,
+    src_port: u16,
+    dst_ip: String,
+    dst_port: u16,
+    direction: String,
+    state: Option<String>,
+    bytes: u64,
+    packets: u64,
+    pid: Option<i32>,
+    process: Option<String>,
+    exe_path: Option<String>,
+    sni: Option<String>,
+    alpn: Option<String>,
+    ja3: Option<String>,
+    ja4: Option<String>,
+    dns_qname: Option<String>,
+    dns_rcode: Option<String>,
+    /// `;`-separated.
+    dns_answers: String,
+    risk_score: Option<u8>,
+}
+
+impl FlowRecord {
+    fn new(id: i64, flow: FlowEvent) -> Self {
+        let process = flow.process;
+        Self {
+            id,
+            ts_first: flow.ts_first,
+            ts_last: flow.ts_last,
+            proto: flow.proto,
+            src_ip: flow.src_ip,
+            src_port: flow.src_port,
+            dst_ip: flow.dst_ip,
+            dst_port: flow.dst_port,
+            direction: format!("{:?}", flow.direction),
+            state: flow.state,
+            bytes: flow.bytes,
+            packets: flow.packets,
+            pid: process.as_ref().map(|p| p.pid),
+            process: process.as_ref().and_then(|p| p.name.clone()),
+            exe_path: process.and_then(|p| p.exe_path),
+            sni: flow.sni,
+            alpn: flow.alpn,
+            ja3: flow.ja3,
+            ja4: flow.ja4,
+            dns_qname: flow.dns_qname,
+            dns_rcode: flow.dns_rcode,
+            dns_answers: flow.dns_answers.join(";"),
+            risk_score: flow.risk.map(|risk| risk.score),
+        }
+    }
+}
+
+/// Flat alert row for CSV and Parquet.
+#[derive(Serialize)]
+struct AlertRecord {
+    id: String,
+    ts: DateTime<Utc>,
+    severity: String,
+    rule_id: String,
+    status: String,
+    summary: String,
+    rationale: String,
+    suggested_action: Option<String>,
+    process_ref: Option<String>,
+    assignee: Option<String>,
+    /// `;`-separated flow row ids.
+    flow_refs: String,
+    /// JSON object.
+    evidence: String,
+}
+
+impl AlertRecord {
+    fn new(alert: &Alert) -> Result<Self> {
+        Ok(Self {
+            id: alert.id.clone(),
+            ts: alert.ts,
+            severity: alert.severity.as_str().into(),
+            rule_id: alert.rule_id.clone(),
+            status: alert.status.as_str().into(),
+            summary: alert.summary.clone(),
+            rationale: alert.rationale.clone(),
+            suggested_action: alert.suggested_action.clone(),
+            process_ref: alert.process_ref.clone(),
+            assignee: alert.assignee.clone(),
+            flow_refs: alert
+                .flow_refs
+                .iter()
+                .map(i64::to_string)
+                .collect::<Vec<_>>()
+                .join(";"),
+            evidence: serde_json::to_string(&alert.evidence)?,
+        })
+    }
+}
+
+/// Destination for exported rows, opened once per export.
+trait RowSink {
+    fn write_flows(&mut self, rows: Vec<(i64, FlowEvent)>) -> Result<()>;
+    fn write_alerts(&mut self, rows: Vec<Alert>) -> Result<()>;
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+struct CsvSink(csv::Writer<BufWriter<File>>);
+
+impl RowSink for CsvSink {
+    fn write_flows(&mut self, rows: Vec<(i64, FlowEvent)>) -> Result<()> {
+        for (id, flow) in rows {
+            self.0.serialize(FlowRecord::new(id, flow))?;
+        }
+        Ok(())
+    }
+
+    fn write_alerts(&mut self, rows: Vec<Alert>) -> Result<()> {
+        for alert in &rows {
+            self.0.serialize(AlertRecord::new(alert)?)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+struct NdjsonSink(BufWriter<File>);
+
+impl RowSink for NdjsonSink {
+    fn write_flows(&mut self, rows: Vec<(i64, FlowEvent)>) -> Result<()> {
+        for (id, flow) in &rows {
+            serde_json::to_writer(&mut self.0, &FlowLine { id: *id, flow })?;
+            self.0.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn write_alerts(&mut self, rows: Vec<Alert>) -> Result<()> {
+        for alert in &rows {
+            serde_json::to_writer(&mut self.0, alert)?;
+            self.0.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_sink {
+    use std::{fs::File, sync::Arc};
+
+    use analyzer::Alert;
+    use anyhow::Result;
+    use arrow::{
+        datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit},
+        json::ReaderBuilder,
+    };
+    use collector::FlowEvent;
+    use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+    use serde::Serialize;
+
+    use super::{AlertRecord, FlowRecord, RowSink};
+
+    pub(super) struct ParquetSink {
+        schema: SchemaRef,
+        writer: ArrowWriter<File>,
+    }
+
+    fn timestamp() -> DataType {
+        DataType::Timestamp(TimeUnit::Microsecond, Some("+00:00".into()))
+    }
+
+    fn flow_schema() -> Schema {
+        let text = |name: &str, nullable| Field::new(name, DataType::Utf8, nullable);
+        Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("ts_first", timestamp(), false),
+            Field::new("ts_last", timestamp(), false),
+            text("proto", false),
+            text("src_ip", false),
+            Field::new("src_port", DataType::UInt16, false),
+            text("dst_ip", false),
+            Field::new("dst_port", DataType::UInt16, false),
+            text("direction", false),
+            text("state", true),
+            Field::new("bytes", DataType::UInt64, false),
+            Field::new("packets", DataType::UInt64, false),
+            Field::new("pid", DataType::Int32, true),
+            text("process", true),
+            text("exe_path", true),
+            text("sni", true),
+            text("alpn", true),
+            text("ja3", true),
+            text("ja4", true),
+            text("dns_qname", true),
+            text("dns_rcode", true),
+            text("dns_answers", false),
+            Field::new("risk_score", DataType::UInt8, true),
+        ])
+    }
+
+    fn alert_schema() -> Schema {
+        let text = |name: &str, nullable| Field::new(name, DataType::Utf8, nullable);
+        Schema::new(vec![
+            text("id", false),
+            Field::new("ts", timestamp(), false),
+            text("severity", false),
+            text("rule_id", false),
+            text("status", false),
+            text("summary", false),
+            text("rationale", false),
+            text("suggested_action", true),
+            text("process_ref", true),
+            text("assignee", true),
+            text("flow_refs", false),
+            text("evidence", false),
+        ])
+    }
+
+    impl ParquetSink {
+        pub(super) fn create(file: File, alerts: bool) -> Result<Self> {
+            let schema = Arc::new(if alerts {
+                alert_schema()
+            } else {
+                flow_schema()
+            });
+            let props = WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .build();
+            let writer = ArrowWriter::try_new(file, schema.clone(), Some(props))?;
+            Ok(Self { schema, writer })
+        }
+
+        fn write_records<T: Serialize>(&mut self, records: &[T]) -> Result<()> {
+            let mut decoder = ReaderBuilder::new(self.schema.clone()).build_decoder()?;
+            decoder.serialize(records)?;
+            if let Some(batch) = decoder.flush()? {
+                self.writer.write(&batch)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl RowSink for ParquetSink {
+        fn write_flows(&mut self, rows: Vec<(i64, FlowEvent)>) -> Result<()> {
+            let records: Vec<_> = rows
+                .into_iter()
+                .map(|(id, flow)| FlowRecord::new(id, flow))
+                .collect();
+            self.write_records(&records)
+        }
+
+        fn write_alerts(&mut self, rows: Vec<Alert>) -> Result<()> {
+            let records = rows
+                .iter()
+                .map(AlertRecord::new)
+                .collect::<Result<Vec<_>>>()?;
+            self.write_records(&records)
+        }
+
+        fn finish(self: Box<Self>) -> Result<()> {
+            self.writer.close()?;
+            Ok(())
+        }
+    }
+}
+
+fn open_sink(path: &Path, format: ExportFormat, alerts: bool) -> Result<Box<dyn RowSink>> {
+    #[cfg(not(feature = "parquet"))]
+    if format == ExportFormat::Parquet {
+        let _ = alerts;
+        return Err(anyhow!(
+            "Parquet export requires the storage `parquet` feature"
+        ));
+    }
+    let file = File::create(path).with_context(|| format!("creating {}", path.display()))?;
+    match format {
+        ExportFormat::Csv => Ok(Box::new(CsvSink(csv::Writer::from_writer(BufWriter::new(
+            file,
+        ))))),
+        ExportFormat::Ndjson => Ok(Box::new(NdjsonSink(BufWriter::new(file)))),
+        #[cfg(feature = "parquet")]
+        ExportFormat::Parquet => Ok(Box::new(parquet_sink::ParquetSink::create(file, alerts)?)),
+        #[cfg(not(feature = "parquet"))]
+        ExportFormat::Parquet => unreachable!("rejected above"),
+    }
+}
+
+impl Storage {
+    /// Streams the flows or alerts matching `query` to `path`, page by page,
+    /// and returns the number of rows written. Flows are decrypted and
+    /// verified ([`Storage::query_flows_full`]); a row failing verification
+    /// aborts the export.
+    pub fn export(&self, query: &ExportQuery, format: ExportFormat, path: &Path) -> Result<usize> {
+        let mut sink = open_sink(path, format, matches!(query, ExportQuery::Alerts(_)))?;
+        let mut written = 0;
+        match query {
+            ExportQuery::Flows(query) => loop {
+                let page = FlowQuery {
+                    limit: EXPORT_PAGE.min(query.limit - written),
+                    offset: query.offset + written,
+                    ..query.clone()
+                };
+                if page.limit == 0 {
+                    break;
+                }
+                let rows = self.query_flows_full(&page)?;
+                let count = rows.len();
+                sink.write_flows(
+                    rows.into_iter()
+                        .map(|(meta, flow)| (meta.id, flow))
+                        .collect(),
+                )?;
+                written += count;
+                if count < page.limit {
+                    break;
+                }
+            },
+            ExportQuery::Alerts(query) => loop {
+                let page = AlertQuery {
+                    limit: EXPORT_PAGE.min(query.limit - written),
+                    offset: query.offset + written,
+                    ..query.clone()
+                };
+                if page.limit == 0 {
+                    break;
+                }
+                let rows = self.query_alerts(&page)?;
+                let count = rows.len();
+                sink.write_alerts(rows)?;
+                written += count;
+                if count < page.limit {
+                    break;
+                }
+            },
+        }
+        sink.finish()?;
+        tracing::info!(rows = written, path = %path.display(), ?format, "export finished");
+        Ok(written)
+    }
+}
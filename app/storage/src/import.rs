@@ -0,0 +1,225 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use anyhow::{anyhow, Context, Result};
+use collector::FlowEvent;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::Storage;
+
+/// Per-line errors kept in an [`ImportReport`]; later ones are only counted.
+const MAX_REPORTED_ERRORS: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImportOptions {
+    /// Source field (dotted path into nested objects, e.g. `source.ip`) to
+    /// `FlowEvent` field, applied before hooks.
+    pub renames: BTreeMap<String, String>,
+    /// Skip lines that fail to parse or map instead of aborting.
+    pub skip_invalid: bool,
+    /// Flows stored per transaction.
+    pub batch_size: usize,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            renames: BTreeMap::new(),
+            skip_invalid: true,
+            batch_size: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub imported: usize,
+    /// Lines dropped by a hook or (with `skip_invalid`) for being invalid.
+    pub skipped: usize,
+    /// `(line number, error)` for the first invalid lines.
+    pub errors: Vec<(usize, String)>,
+    pub first_id: Option<i64>,
+    pub last_id: Option<i64>,
+}
+
+/// Adjusts a JSON object before it is read as a `FlowEvent`; returning
+/// `Ok(false)` drops the line.
+pub type MappingHook = Box<dyn Fn(&mut Map<String, Value>) -> Result<bool> + Send>;
+
+/// Reads flow logs captured by other tools, one JSON object per line, into
+/// storage. Objects follow the `FlowEvent` schema after renames and hooks;
+/// `ts_first` is required, `ts_last` defaults to it and every other
+/// missing field to its `FlowEvent::default()` value. NDJSON written by
+/// [`Storage::export`] imports as is (its `id` field is ignored).
+pub struct NdjsonImporter {
+    options: ImportOptions,
+    hooks: Vec<MappingHook>,
+}
+
+impl NdjsonImporter {
+    pub fn new(options: ImportOptions) -> Self {
+        Self {
+            options,
+            hooks: Vec::new(),
+        }
+    }
+
+    /// Adds a hook run (in order of registration) after the renames.
+    pub fn with_hook(
+        mut self,
+        hook: impl Fn(&mut Map<String, Value>) -> Result<bool> + Send + 'static,
+    ) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    pub fn import_file(&self, storage: &Storage, path: &Path) -> Result<ImportReport> {
+        let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        self.import(storage, BufReader::new(file))
+    }
+
+    pub fn import(&self, storage: &Storage, reader: impl BufRead) -> Result<ImportReport> {
+        let mut report = ImportReport::default();
+        let mut batch = Vec::with_capacity(self.options.batch_size.max(1));
+        for (idx, line) in reader.lines().enumerate() {
+            let line_no = idx + 1;
+            let line = line.with_context(|| format!("reading line {line_no}"))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match self.parse_line(&line) {
+                Ok(Some(flow)) => batch.push(flow),
+                Ok(None) => report.skipped += 1,
+                Err(err) if self.options.skip_invalid => {
+                    report.skipped += 1;
+                    if report.errors.len() < MAX_REPORTED_ERRORS {
+                        report.errors.push((line_no, format!("{err:#}")));
+                    }
+                }
+                Err(err) => return Err(err.context(format!("line {line_no}"))),
+            }
+            if batch.len() >= self.options.batch_size.max(1) {
+                store_batch(storage, &mut batch, &mut report)?;
+            }
+        }
+        store_batch(storage, &mut batch, &mut report)?;
+        tracing::info!(
+            imported = report.imported,
+            skipped = report.skipped,
+            "NDJSON import finished"
+        );
+        Ok(report)
+    }
+
+    /// One line as a flow, after renames and hooks; `None` when a hook
+    /// dropped it. For callers that want the flows rather than storing them.
+    pub fn parse_line(&self, line: &str) -> Result<Option<FlowEvent>> {
+        let mut object = match serde_json::from_str(line)? {
+            Value::Object(object) => object,
+            _ => return Err(anyhow!("expected a JSON object")),
+        };
+        for (from, to) in &self.options.renames {
+            if let Some(value) = take_path(&mut object, from) {
+                object.insert(to.clone(), value);
+            }
+        }
+        for hook in &self.hooks {
+            if !hook(&mut object)? {
+                return Ok(None);
+            }
+        }
+        let ts_first = object
+            .get("ts_first")
+            .cloned()
+            .ok_or_else(|| anyhow!("missing ts_first"))?;
+        object.entry("ts_last").or_insert(ts_first);
+        let Value::Object(mut flow) = serde_json::to_value(FlowEvent::default())? else {
+            unreachable!("FlowEvent serializes to an object");
+        };
+        flow.extend(object.into_iter().filter(|(_, value)| !value.is_null()));
+        Ok(Some(serde_json::from_value(Value::Object(flow))?))
+    }
+}
+
+fn store_batch(
+    storage: &Storage,
+    batch: &mut Vec<FlowEvent>,
+    report: &mut ImportReport,
+) -> Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let ids = storage.put_flows(batch)?;
+    report.imported += ids.len();
+    report.first_id = report.first_id.or(ids.first().copied());
+    report.last_id = ids.last().copied().or(report.last_id);
+    batch.clear();
+    Ok(())
+}
+
+/// Removes and returns the value at a dotted path, e.g. `source.ip`.
+fn take_path(object: &mut Map<String, Value>, path: &str) -> Option<Value> {
+    match path.split_once('.') {
+        None => object.remove(path),
+        Some((head, rest)) => match object.get_mut(head)? {
+            Value::Object(inner) => take_path(inner, rest),
+            _ => None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FlowQuery;
+
+    #[test]
+    fn imports_mapped_lines_and_skips_bad_ones() {
+        let storage = Storage::open(":memory:", &[5u8; 32]).unwrap();
+        let input = r#"
+{"timestamp":"2024-05-01T10:00:00Z","source":{"ip":"10.0.0.5","port":51000},"destination":{"ip":"1.1.1.1","port":53},"proto":"UDP"}
+not json
+{"timestamp":"2024-05-01T10:00:01Z","source":{"ip":"10.0.0.6"},"destination":{"ip":"8.8.8.8","port":"53"},"proto":"UDP"}
+{"timestamp":"2024-05-01T10:00:02Z","source":{"ip":"127.0.0.1"},"destination":{"ip":"127.0.0.1","port":80},"proto":"TCP"}
+"#;
+        let renames = [
+            ("timestamp", "ts_first"),
+            ("source.ip", "src_ip"),
+            ("source.port", "src_port"),
+            ("destination.ip", "dst_ip"),
+            ("destination.port", "dst_port"),
+        ]
+        .into_iter()
+        .map(|(from, to)| (from.to_string(), to.to_string()))
+        .collect();
+        let importer = NdjsonImporter::new(ImportOptions {
+            renames,
+            ..ImportOptions::default()
+        })
+        .with_hook(|flow| {
+            // Ports arrive as strings from some tools.
+            if let Some(Value::String(port)) = flow.get("dst_port") {
+                let port: u16 = port.parse()?;
+                flow.insert("dst_port".into(), port.into());
+            }
+            Ok(flow.get("src_ip") != Some(&Value::from("127.0.0.1")))
+        });
+        let report = importer.import(&storage, input.as_bytes()).unwrap();
+        assert_eq!(report.imported, 2);
+        assert_eq!(report.skipped, 2);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].0, 3);
+
+        let flows = storage.query_flows(&FlowQuery::default()).unwrap();
+        assert_eq!(flows.len(), 2);
+        assert_eq!(flows[0].dst_ip, "8.8.8.8");
+        assert_eq!(flows[0].dst_port, 53);
+        assert_eq!(flows[0].ts_last, flows[0].ts_first);
+    }
+}
@@ -0,0 +1,377 @@
+use std::str::FromStr;
+
+use analyzer::{Alert, Severity};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, params_from_iter, types::Value, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    query::{join_clauses, placeholders, SortOrder},
+    timestamp_column, Storage, ALERT_COLUMNS,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IncidentStatus {
+    #[default]
+    Open,
+    Investigating,
+    Resolved,
+}
+
+impl IncidentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IncidentStatus::Open => "Open",
+            IncidentStatus::Investigating => "Investigating",
+            IncidentStatus::Resolved => "Resolved",
+        }
+    }
+}
+
+impl FromStr for IncidentStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "open" => Ok(IncidentStatus::Open),
+            "investigating" => Ok(IncidentStatus::Investigating),
+            "resolved" => Ok(IncidentStatus::Resolved),
+            other => Err(anyhow!("unknown incident status: {other}")),
+        }
+    }
+}
+
+/// A group of related alerts, as produced by correlation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Incident {
+    pub id: String,
+    pub title: String,
+    pub severity: Severity,
+    #[serde(default)]
+    pub status: IncidentStatus,
+    pub first_alert: DateTime<Utc>,
+    pub last_alert: DateTime<Utc>,
+    #[serde(default)]
+    pub alert_ids: Vec<String>,
+}
+
+/// Filters for [`Storage::query_incidents`]; unset fields match everything.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IncidentQuery {
+    /// Incidents still active at or after this time (`last_alert >= from`).
+    pub from: Option<DateTime<Utc>>,
+    /// Incidents started at or before this time (`first_alert <= to`).
+    pub to: Option<DateTime<Utc>>,
+    pub severities: Vec<Severity>,
+    pub statuses: Vec<IncidentStatus>,
+    pub limit: usize,
+    pub offset: usize,
+    /// By `last_alert`.
+    pub order: SortOrder,
+}
+
+impl Default for IncidentQuery {
+    fn default() -> Self {
+        Self {
+            from: None,
+            to: None,
+            severities: Vec::new(),
+            statuses: Vec::new(),
+            limit: 100,
+            offset: 0,
+            order: SortOrder::NewestFirst,
+        }
+    }
+}
+
+impl IncidentQuery {
+    fn where_clause(&self) -> (String, Vec<Value>) {
+        let mut clauses = Vec::new();
+        let mut params = Vec::new();
+        if let Some(from) = self.from {
+            clauses.push("last_alert >= ?".to_string());
+            params.push(Value::Text(from.to_rfc3339()));
+        }
+        if let Some(to) = self.to {
+            clauses.push("first_alert <= ?".to_string());
+            params.push(Value::Text(to.to_rfc3339()));
+        }
+        if !self.severities.is_empty() {
+            clauses.push(format!(
+                "severity IN ({})",
+                placeholders(self.severities.len())
+            ));
+            params.extend(
+                self.severities
+                    .iter()
+                    .map(|severity| Value::Text(severity.as_str().into())),
+            );
+        }
+        if !self.statuses.is_empty() {
+            clauses.push(format!("status IN ({})", placeholders(self.statuses.len())));
+            params.extend(
+                self.statuses
+                    .iter()
+                    .map(|status| Value::Text(status.as_str().into())),
+            );
+        }
+        (join_clauses(clauses), params)
+    }
+}
+
+const INCIDENT_COLUMNS: &str = "id, title, severity, status, first_alert, last_alert";
+
+pub(crate) fn create_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS incidents (
+            id TEXT PRIMARY KEY,
+            title TEXT NOT NULL,
+            severity TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'Open',
+            first_alert TEXT NOT NULL,
+            last_alert TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS incidents_last_alert ON incidents (last_alert);
+        CREATE INDEX IF NOT EXISTS incidents_status ON incidents (status, last_alert);
+        CREATE TABLE IF NOT EXISTS incident_alerts (
+            incident_id TEXT NOT NULL,
+            alert_id TEXT NOT NULL,
+            PRIMARY KEY (incident_id, alert_id)
+        );
+        CREATE INDEX IF NOT EXISTS incident_alerts_alert ON incident_alerts (alert_id);
+        "#,
+    )?;
+    Ok(())
+}
+
+pub(crate) fn drop_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch("DROP TABLE IF EXISTS incident_alerts; DROP TABLE IF EXISTS incidents;")?;
+    Ok(())
+}
+
+impl Storage {
+    /// Inserts or replaces an incident together with its alert links.
+    pub fn put_incident(&self, incident: &Incident) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.prepare_cached(&format!(
+            "INSERT OR REPLACE INTO incidents ({INCIDENT_COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+        ))?
+        .execute(params![
+            incident.id,
+            incident.title,
+            incident.severity.as_str(),
+            incident.status.as_str(),
+            incident.first_alert.to_rfc3339(),
+            incident.last_alert.to_rfc3339()
+        ])?;
+        tx.execute(
+            "DELETE FROM incident_alerts WHERE incident_id = ?1",
+            params![incident.id],
+        )?;
+        let mut link = tx.prepare_cached(
+            "INSERT OR IGNORE INTO incident_alerts (incident_id, alert_id) VALUES (?1, ?2)",
+        )?;
+        for alert_id in &incident.alert_ids {
+            link.execute(params![incident.id, alert_id])?;
+        }
+        drop(link);
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Links more alerts to an existing incident, widening its
+    /// `first_alert`/`last_alert` to cover those that are stored.
+    pub fn attach_alerts(&self, incident_id: &str, alert_ids: &[String]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut link = tx.prepare_cached(
+            "INSERT OR IGNORE INTO incident_alerts (incident_id, alert_id) VALUES (?1, ?2)",
+        )?;
+        for alert_id in alert_ids {
+            link.execute(params![incident_id, alert_id])?;
+        }
+        drop(link);
+        let updated = tx.execute(
+            "UPDATE incidents SET
+                first_alert = MIN(first_alert, COALESCE((SELECT MIN(ts) FROM alerts WHERE id IN (SELECT alert_id FROM incident_alerts WHERE incident_id = ?1)), first_alert)),
+                last_alert = MAX(last_alert, COALESCE((SELECT MAX(ts) FROM alerts WHERE id IN (SELECT alert_id FROM incident_alerts WHERE incident_id = ?1)), last_alert))
+             WHERE id = ?1",
+            params![incident_id],
+        )?;
+        if updated == 0 {
+            return Err(anyhow!("incident {incident_id} not found"));
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn set_incident_status(&self, id: &str, status: IncidentStatus) -> Result<()> {
+        let updated = self.conn.execute(
+            "UPDATE incidents SET status = ?1 WHERE id = ?2",
+            params![status.as_str(), id],
+        )?;
+        if updated == 0 {
+            return Err(anyhow!("incident {id} not found"));
+        }
+        Ok(())
+    }
+
+    pub fn get_incident(&self, id: &str) -> Result<Option<Incident>> {
+        let incident = self
+            .conn
+            .prepare_cached(&format!(
+                "SELECT {INCIDENT_COLUMNS} FROM incidents WHERE id = ?1"
+            ))?
+            .query_row(params![id], incident_row)
+            .optional()?;
+        incident.map(|row| self.finish_incident(row)).transpose()
+    }
+
+    pub fn query_incidents(&self, query: &IncidentQuery) -> Result<Vec<Incident>> {
+        let (filter, mut values) = query.where_clause();
+        let direction = match query.order {
+            SortOrder::NewestFirst => "DESC",
+            SortOrder::OldestFirst => "ASC",
+        };
+        values.push(Value::Integer(query.limit as i64));
+        values.push(Value::Integer(query.offset as i64));
+        let rows = self
+            .conn
+            .prepare(&format!(
+                "SELECT {INCIDENT_COLUMNS} FROM incidents {filter} ORDER BY last_alert {direction}, id LIMIT ? OFFSET ?"
+            ))?
+            .query_map(params_from_iter(values), incident_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.into_iter()
+            .map(|row| self.finish_incident(row))
+            .collect()
+    }
+
+    /// Ids of the incidents an alert belongs to.
+    pub fn incidents_for_alert(&self, alert_id: &str) -> Result<Vec<String>> {
+        let ids = self
+            .conn
+            .prepare_cached(
+                "SELECT incident_id FROM incident_alerts WHERE alert_id = ?1 ORDER BY incident_id",
+            )?
+            .query_map(params![alert_id], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ids)
+    }
+
+    /// Stored alerts of an incident, oldest first.
+    pub fn incident_alerts(&self, incident_id: &str) -> Result<Vec<Alert>> {
+        self.read_alerts(
+            &format!(
+                "SELECT {ALERT_COLUMNS} FROM alerts WHERE id IN (SELECT alert_id FROM incident_alerts WHERE incident_id = ?) ORDER BY ts, id"
+            ),
+            vec![Value::Text(incident_id.into())],
+        )
+    }
+
+    fn finish_incident(&self, (mut incident, severity, status): IncidentRow) -> Result<Incident> {
+        incident.severity = severity.parse()?;
+        incident.status = status.parse()?;
+        incident.alert_ids = self
+            .conn
+            .prepare_cached(
+                "SELECT alert_id FROM incident_alerts WHERE incident_id = ?1 ORDER BY alert_id",
+            )?
+            .query_map(params![incident.id], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(incident)
+    }
+}
+
+/// Incident with severity and status still as stored text.
+type IncidentRow = (Incident, String, String);
+
+fn incident_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<IncidentRow> {
+    Ok((
+        Incident {
+            id: row.get(0)?,
+            title: row.get(1)?,
+            severity: Severity::Low,
+            status: IncidentStatus::Open,
+            first_alert: timestamp_column(row, 4)?,
+            last_alert: timestamp_column(row, 5)?,
+            alert_ids: Vec::new(),
+        },
+        row.get(2)?,
+        row.get(3)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use analyzer::AlertStatus;
+    use chrono::Duration;
+
+    fn alert(id: &str, ts: DateTime<Utc>) -> Alert {
+        Alert {
+            id: id.into(),
+            ts,
+            severity: Severity::High,
+            rule_id: "lateral.smb".into(),
+            summary: String::new(),
+            flow_refs: Vec::new(),
+            process_ref: None,
+            rationale: String::new(),
+            suggested_action: None,
+            status: AlertStatus::New,
+            assignee: None,
+            notes: Vec::new(),
+            evidence: Default::default(),
+        }
+    }
+
+    #[test]
+    fn links_alerts_and_filters_by_status() {
+        let storage = Storage::open(":memory:", &[8u8; 32]).unwrap();
+        let start = Utc::now() - Duration::hours(2);
+        for (id, offset) in [("a1", 0), ("a2", 30), ("a3", 90)] {
+            storage
+                .put_alert(&alert(id, start + Duration::minutes(offset)))
+                .unwrap();
+        }
+        storage
+            .put_incident(&Incident {
+                id: "inc-1".into(),
+                title: "SMB spread from 10.0.0.5".into(),
+                severity: Severity::High,
+                status: IncidentStatus::Open,
+                first_alert: start,
+                last_alert: start + Duration::minutes(30),
+                alert_ids: vec!["a1".into(), "a2".into()],
+            })
+            .unwrap();
+        storage.attach_alerts("inc-1", &["a3".into()]).unwrap();
+
+        let incident = storage.get_incident("inc-1").unwrap().unwrap();
+        assert_eq!(incident.alert_ids, ["a1", "a2", "a3"]);
+        assert_eq!(incident.last_alert, start + Duration::minutes(90));
+        assert_eq!(storage.incident_alerts("inc-1").unwrap().len(), 3);
+        assert_eq!(storage.incidents_for_alert("a3").unwrap(), ["inc-1"]);
+
+        storage
+            .set_incident_status("inc-1", IncidentStatus::Resolved)
+            .unwrap();
+        let open = storage
+            .query_incidents(&IncidentQuery {
+                statuses: vec![IncidentStatus::Open],
+                ..IncidentQuery::default()
+            })
+            .unwrap();
+        assert!(open.is_empty());
+        assert_eq!(
+            storage
+                .query_incidents(&IncidentQuery::default())
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
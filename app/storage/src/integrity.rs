@@ -0,0 +1,304 @@
+use std::collections::BTreeMap;
+
+use analyzer::{new_alert_id, Alert, AlertStatus, Severity};
+use anyhow::Result;
+use chrono::Utc;
+use rusqlite::{params, Params};
+
+use crate::{
+    crypto::FlowCipher, stored_flow, FlowReadError, SealedRow, Storage, StoredFlow,
+    STORED_FLOW_COLUMN_COUNT,
+};
+
+/// Rule id of the alerts raised for stored flows that were tampered with.
+pub const TAMPER_RULE_ID: &str = "builtin.storage.tamper";
+
+/// The row MAC and what it is keyed by and bound to, selected right after
+/// the stored-flow columns.
+pub(crate) const ROW_MAC_COLUMNS: &str = "row_mac, key_id, nonce";
+
+/// Canonical encoding of the plaintext columns a row MAC covers, each
+/// length-prefixed. The payload nonce binds them to the ciphertext they
+/// were written with, so columns and MAC copied over from another row do
+/// not verify either. The row id is not covered here: the payload's
+/// associated data binds it.
+fn mac_input(columns: &StoredFlow, nonce: Option<&[u8]>) -> Vec<u8> {
+    let mut input = Vec::with_capacity(192);
+    let mut field = |value: Option<&[u8]>| match value {
+        Some(value) => {
+            input.push(1);
+            input.extend_from_slice(&(value.len() as u32).to_be_bytes());
+            input.extend_from_slice(value);
+        }
+        None => input.push(0),
+    };
+    field(Some(columns.ts_first.to_rfc3339().as_bytes()));
+    field(Some(columns.ts_last.to_rfc3339().as_bytes()));
+    field(Some(columns.proto.as_bytes()));
+    field(Some(columns.src_ip.as_bytes()));
+    field(Some(columns.dst_ip.as_bytes()));
+    field(Some(&columns.src_port.to_be_bytes()));
+    field(Some(&columns.dst_port.to_be_bytes()));
+    field(Some(&columns.bytes.to_be_bytes()));
+    field(columns.direction.as_deref().map(str::as_bytes));
+    field(columns.process.as_deref().map(str::as_bytes));
+    field(columns.domain.as_deref().map(str::as_bytes));
+    field(nonce);
+    input
+}
+
+/// `row_mac` of a row with these columns and payload nonce, under the key
+/// the payload is sealed with.
+pub(crate) fn row_mac(cipher: &FlowCipher, columns: &StoredFlow, nonce: Option<&[u8]>) -> Vec<u8> {
+    cipher.sign_row(&mac_input(columns, nonce))
+}
+
+impl Storage {
+    /// Checks a row's plaintext columns against its MAC. A missing MAC
+    /// fails too: every row gets one when it is written.
+    pub(crate) fn verify_row_mac(
+        &self,
+        columns: &StoredFlow,
+        mac: Option<&[u8]>,
+        key_id: Option<&str>,
+        nonce: Option<&[u8]>,
+    ) -> Result<(), FlowReadError> {
+        let cipher = self
+            .cipher_for(key_id)
+            .map_err(|_| FlowReadError::UnknownKey {
+                id: columns.id,
+                key_id: key_id.unwrap_or_default().to_string(),
+            })?;
+        match mac {
+            Some(mac) if cipher.verify_row(&mac_input(columns, nonce), mac) => Ok(()),
+            Some(_) => Err(FlowReadError::ColumnsTampered { id: columns.id }),
+            None => Err(FlowReadError::MissingMac { id: columns.id }),
+        }
+    }
+
+    /// Runs `sql`, which selects the stored-flow columns followed by
+    /// [`ROW_MAC_COLUMNS`], and flags the rows whose MAC does not verify.
+    /// Rows sealed under a key this connection does not know are returned
+    /// unchecked.
+    pub(crate) fn checked_flows(&self, sql: &str, params: impl Params) -> Result<Vec<StoredFlow>> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let rows = stmt
+            .query_map(params, |row| {
+                Ok((
+                    stored_flow(row)?,
+                    row.get::<_, Option<Vec<u8>>>(STORED_FLOW_COLUMN_COUNT)?,
+                    row.get::<_, Option<String>>(STORED_FLOW_COLUMN_COUNT + 1)?,
+                    row.get::<_, Option<Vec<u8>>>(STORED_FLOW_COLUMN_COUNT + 2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        drop(stmt);
+        Ok(rows
+            .into_iter()
+            .map(|(mut flow, mac, key_id, nonce)| {
+                let verified = self.report_tampering(self.verify_row_mac(
+                    &flow,
+                    mac.as_deref(),
+                    key_id.as_deref(),
+                    nonce.as_deref(),
+                ));
+                flow.tampered = verified.as_ref().is_err_and(FlowReadError::is_tampering);
+                flow
+            })
+            .collect())
+    }
+
+    /// Passes a read through, raising a tamper alert first if it failed
+    /// because the row was tampered with.
+    pub(crate) fn report_tampering<T>(
+        &self,
+        result: Result<T, FlowReadError>,
+    ) -> Result<T, FlowReadError> {
+        if let Err(err) = &result {
+            if err.is_tampering() {
+                if let Err(alert_err) = self.raise_tamper_alert(err) {
+                    tracing::warn!(id = err.flow_id(), %alert_err, "cannot store tamper alert");
+                }
+            }
+        }
+        result
+    }
+
+    /// Stores a tamper alert for the row unless one was raised before: a
+    /// tampered row stays that way and fails every read.
+    fn raise_tamper_alert(&self, err: &FlowReadError) -> Result<()> {
+        let id = err.flow_id();
+        let raised = self
+            .conn
+            .prepare_cached(
+                "SELECT 1 FROM alert_flows JOIN alerts ON alerts.id = alert_flows.alert_id
+                 WHERE alert_flows.flow_id = ?1 AND alerts.rule_id = ?2",
+            )?
+            .exists(params![id, TAMPER_RULE_ID])?;
+        if raised {
+            return Ok(());
+        }
+        tracing::warn!(id, %err, "stored flow was tampered with");
+        self.put_alert(&Alert {
+            id: new_alert_id(),
+            ts: Utc::now(),
+            severity: Severity::High,
+            rule_id: TAMPER_RULE_ID.into(),
+            summary: format!("Stored flow {id} was modified outside nets"),
+            flow_refs: vec![id],
+            process_ref: None,
+            rationale: format!(
+                "{err}. The row changed after it was written, so its addresses, ports and times cannot be trusted."
+            ),
+            suggested_action: Some(
+                "Find out who has write access to the database file and compare the row with a backup."
+                    .into(),
+            ),
+            status: AlertStatus::New,
+            assignee: None,
+            notes: Vec::new(),
+            evidence: BTreeMap::from([
+                ("flow_id".to_string(), id.to_string()),
+                ("error".to_string(), err.to_string()),
+            ]),
+        })
+    }
+
+    /// Signs the rows stored before row MACs existed. The columns are
+    /// signed as the decrypted payload has them rather than as stored, so a
+    /// row edited before the upgrade fails verification afterwards. Rows
+    /// that cannot be decrypted (e.g. sealed under a key the database was
+    /// not opened with) fail verification until a key rotation re-seals
+    /// and signs them.
+    pub(crate) fn backfill_row_macs(&self) -> Result<()> {
+        self.backfill_flows("row_mac IS NULL", SealedRow::COLUMNS, |conn, id, flow| {
+            let (key_id, nonce): (Option<String>, Option<Vec<u8>>) = conn.query_row(
+                "SELECT key_id, nonce FROM flows WHERE id = ?1",
+                params![id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?;
+            let cipher = self.cipher_for(key_id.as_deref())?;
+            let mac = row_mac(cipher, &StoredFlow::columns(id, &flow), nonce.as_deref());
+            conn.execute(
+                "UPDATE flows SET row_mac = ?1 WHERE id = ?2",
+                params![mac, id],
+            )?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AlertQuery;
+    use collector::FlowEvent;
+
+    #[test]
+    fn flags_edited_columns_once() {
+        let mut storage = Storage::open(":memory:", &[3u8; 32]).unwrap();
+        let flow = |port: u16| FlowEvent {
+            dst_ip: "203.0.113.9".into(),
+            dst_port: port,
+            ..FlowEvent::default()
+        };
+        let ids = storage.put_flows(&[flow(443), flow(80)]).unwrap();
+        storage
+            .conn
+            .execute(
+                "UPDATE flows SET dst_ip = '198.51.100.1' WHERE id = ?1",
+                params![ids[0]],
+            )
+            .unwrap();
+
+        let err = storage.get_flow(ids[0]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FlowReadError>(),
+            Some(FlowReadError::ColumnsTampered { .. })
+        ));
+        for _ in 0..2 {
+            let flows = storage.query_flows(&Default::default()).unwrap();
+            let tampered: Vec<_> = flows.iter().filter(|f| f.tampered).map(|f| f.id).collect();
+            assert_eq!(tampered, [ids[0]]);
+        }
+        let alerts = storage.query_alerts(&AlertQuery::default()).unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].rule_id, TAMPER_RULE_ID);
+        assert_eq!(alerts[0].flow_refs, [ids[0]]);
+
+        // Re-signing under a new key keeps the edit detectable.
+        storage.rotate_key(&[3u8; 32], &[4u8; 32]).unwrap();
+        let flows = storage.query_flows(&Default::default()).unwrap();
+        assert_eq!(flows.iter().filter(|f| f.tampered).count(), 1);
+        assert_eq!(storage.get_flow(ids[1]).unwrap().dst_port, 80);
+    }
+
+    #[test]
+    fn tells_tampering_apart() {
+        let storage = Storage::open(":memory:", &[5u8; 32]).unwrap();
+        let flow = FlowEvent {
+            dst_ip: "203.0.113.9".into(),
+            dst_port: 443,
+            ..FlowEvent::default()
+        };
+        let ids = storage.put_flows(&vec![flow; 5]).unwrap();
+        let error = |id: i64| {
+            storage
+                .get_flow(id)
+                .unwrap_err()
+                .downcast::<FlowReadError>()
+                .unwrap()
+        };
+
+        let mut blob: Vec<u8> = storage
+            .conn
+            .query_row(
+                "SELECT ciphertext FROM flows WHERE id = ?1",
+                params![ids[0]],
+                |row| row.get(0),
+            )
+            .unwrap();
+        blob[0] ^= 1;
+        storage
+            .conn
+            .execute(
+                "UPDATE flows SET ciphertext = ?1 WHERE id = ?2",
+                params![blob, ids[0]],
+            )
+            .unwrap();
+        assert!(matches!(error(ids[0]), FlowReadError::Tampered { id } if id == ids[0]));
+
+        // Same columns, so only the row id the payload is bound to differs.
+        storage
+            .conn
+            .execute(
+                "UPDATE flows SET (ciphertext, nonce, row_mac) = (SELECT ciphertext, nonce, row_mac FROM flows WHERE id = ?2) WHERE id = ?1",
+                params![ids[1], ids[4]],
+            )
+            .unwrap();
+        assert!(matches!(
+            error(ids[1]),
+            FlowReadError::Relocated { id, origin } if id == ids[1] && origin == ids[4]
+        ));
+
+        storage
+            .conn
+            .execute(
+                "UPDATE flows SET bytes = bytes + 1 WHERE id = ?1",
+                params![ids[2]],
+            )
+            .unwrap();
+        assert!(matches!(error(ids[2]), FlowReadError::ColumnsTampered { id } if id == ids[2]));
+
+        storage
+            .conn
+            .execute(
+                "UPDATE flows SET row_mac = NULL WHERE id = ?1",
+                params![ids[3]],
+            )
+            .unwrap();
+        assert!(matches!(error(ids[3]), FlowReadError::MissingMac { id } if id == ids[3]));
+
+        assert_eq!(storage.get_flow(ids[4]).unwrap().dst_port, 443);
+    }
+}
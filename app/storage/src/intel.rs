@@ -0,0 +1,270 @@
+//! Threat-intel feeds imported into the database: each feed is a named set
+//! of indicators of one kind, remembered with the file it came from so it
+//! can be re-read. Hits are not counted separately; they are the stored
+//! `intel.*` alerts naming the feed in their evidence.
+
+use std::collections::BTreeSet;
+
+use analyzer::{
+    fingerprints::{FingerprintList, FingerprintLists, ListKind},
+    intel::{IndicatorLists, IntelKind},
+};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::{timestamp_column, Storage};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IntelFeed {
+    pub name: String,
+    pub kind: IntelKind,
+    /// The file the indicators were last read from.
+    pub source: String,
+    pub entries: u64,
+    pub imported_at: DateTime<Utc>,
+}
+
+/// What an import changed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IntelImport {
+    pub feed: String,
+    pub entries: u64,
+    pub added: u64,
+    pub removed: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IntelFeedStats {
+    #[serde(flatten)]
+    pub feed: IntelFeed,
+    /// Stored alerts raised by the feed in the period.
+    pub hits: u64,
+    pub last_hit: Option<DateTime<Utc>>,
+}
+
+pub(crate) fn create_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS intel_feeds (
+            name TEXT PRIMARY KEY,
+            kind TEXT NOT NULL,
+            source TEXT NOT NULL,
+            imported_at TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS intel_indicators (
+            feed TEXT NOT NULL,
+            value TEXT NOT NULL,
+            PRIMARY KEY (feed, value)
+        );
+        "#,
+    )?;
+    Ok(())
+}
+
+pub(crate) fn drop_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch("DROP TABLE IF EXISTS intel_indicators; DROP TABLE IF EXISTS intel_feeds;")?;
+    Ok(())
+}
+
+const FEED_COLUMNS: &str =
+    "name, kind, source, imported_at, (SELECT COUNT(*) FROM intel_indicators WHERE feed = intel_feeds.name)";
+
+fn intel_feed(row: &rusqlite::Row<'_>) -> rusqlite::Result<IntelFeed> {
+    let kind: String = row.get(1)?;
+    Ok(IntelFeed {
+        name: row.get(0)?,
+        kind: kind.parse().map_err(|err: anyhow::Error| {
+            rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, err.into())
+        })?,
+        source: row.get(2)?,
+        imported_at: timestamp_column(row, 3)?,
+        entries: row.get::<_, i64>(4)? as u64,
+    })
+}
+
+impl Storage {
+    /// Replaces the indicators of feed `name` with `values`, creating the
+    /// feed on first import. A feed keeps the kind it was created with.
+    pub fn import_intel(
+        &self,
+        name: &str,
+        kind: IntelKind,
+        source: &str,
+        values: &[String],
+    ) -> Result<IntelImport> {
+        let tx = self.conn.unchecked_transaction()?;
+        let existing: Option<String> = tx
+            .query_row(
+                "SELECT kind FROM intel_feeds WHERE name = ?1",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(existing) = existing.filter(|existing| existing != kind.as_str()) {
+            return Err(anyhow!(
+                "feed {name} holds {existing} indicators; remove it first to import {kind}"
+            ));
+        }
+        let old: BTreeSet<String> = tx
+            .prepare("SELECT value FROM intel_indicators WHERE feed = ?1")?
+            .query_map(params![name], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        let new: BTreeSet<&String> = values.iter().collect();
+        tx.execute(
+            "INSERT OR REPLACE INTO intel_feeds (name, kind, source, imported_at) VALUES (?1, ?2, ?3, ?4)",
+            params![name, kind.as_str(), source, Utc::now().to_rfc3339()],
+        )?;
+        tx.execute(
+            "DELETE FROM intel_indicators WHERE feed = ?1",
+            params![name],
+        )?;
+        {
+            let mut insert =
+                tx.prepare("INSERT OR IGNORE INTO intel_indicators (feed, value) VALUES (?1, ?2)")?;
+            for value in &new {
+                insert.execute(params![name, value])?;
+            }
+        }
+        tx.commit()?;
+        Ok(IntelImport {
+            feed: name.to_string(),
+            entries: new.len() as u64,
+            added: new.iter().filter(|value| !old.contains(**value)).count() as u64,
+            removed: old.iter().filter(|value| !new.contains(value)).count() as u64,
+        })
+    }
+
+    pub fn intel_feeds(&self) -> Result<Vec<IntelFeed>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {FEED_COLUMNS} FROM intel_feeds ORDER BY name"
+        ))?;
+        let feeds = stmt
+            .query_map([], intel_feed)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(feeds)
+    }
+
+    /// Drops a feed and its indicators; false when there was none.
+    pub fn remove_intel_feed(&self, name: &str) -> Result<bool> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM intel_indicators WHERE feed = ?1",
+            params![name],
+        )?;
+        let removed = tx.execute("DELETE FROM intel_feeds WHERE name = ?1", params![name])?;
+        tx.commit()?;
+        Ok(removed > 0)
+    }
+
+    /// Every feed with the alerts it raised since `since`.
+    pub fn intel_stats(&self, since: DateTime<Utc>) -> Result<Vec<IntelFeedStats>> {
+        let mut hits = self.conn.prepare(
+            "SELECT COUNT(*), MAX(ts) FROM alerts
+             WHERE rule_id LIKE 'intel.%' AND json_extract(evidence, '$.feed') = ?1 AND ts >= ?2",
+        )?;
+        self.intel_feeds()?
+            .into_iter()
+            .map(|feed| {
+                let (count, last): (i64, Option<String>) = hits
+                    .query_row(params![feed.name, since.to_rfc3339()], |row| {
+                        Ok((row.get(0)?, row.get(1)?))
+                    })?;
+                let last_hit = last
+                    .map(|ts| DateTime::parse_from_rfc3339(&ts).map(|ts| ts.with_timezone(&Utc)))
+                    .transpose()?;
+                Ok(IntelFeedStats {
+                    feed,
+                    hits: count as u64,
+                    last_hit,
+                })
+            })
+            .collect()
+    }
+
+    /// The feeds as the analyzer takes them: addresses and domains, and
+    /// fingerprints as block lists.
+    pub fn intel_lists(&self) -> Result<(IndicatorLists, FingerprintLists)> {
+        let mut indicators = IndicatorLists::default();
+        let mut fingerprints = FingerprintLists::default();
+        let mut values = self
+            .conn
+            .prepare("SELECT value FROM intel_indicators WHERE feed = ?1")?;
+        for feed in self.intel_feeds()? {
+            let rows = values
+                .query_map(params![feed.name], |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            match feed.kind {
+                IntelKind::Ip | IntelKind::Domain => {
+                    for value in rows {
+                        indicators.insert(&feed.name, feed.kind, value);
+                    }
+                }
+                IntelKind::Ja3 | IntelKind::Ja4 => {
+                    let mut list = FingerprintList::new(&feed.name, ListKind::Block);
+                    if feed.kind == IntelKind::Ja3 {
+                        list.ja3.extend(rows);
+                    } else {
+                        list.ja4.extend(rows);
+                    }
+                    fingerprints.insert(list);
+                }
+            }
+        }
+        Ok((indicators, fingerprints))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use analyzer::{intel::parse_feed, Alert, AlertStatus, Severity};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn imports_refreshes_and_counts_hits() {
+        let storage = Storage::open(":memory:", &[9u8; 32]).unwrap();
+        let feed = parse_feed(IntelKind::Ip, "203.0.113.7\n198.51.100.1\n");
+        let first = storage
+            .import_intel("feodo", IntelKind::Ip, "feodo.csv", &feed.values)
+            .unwrap();
+        assert_eq!((first.entries, first.added, first.removed), (2, 2, 0));
+        let feed = parse_feed(IntelKind::Ip, "203.0.113.7\n192.0.2.9\n");
+        let again = storage
+            .import_intel("feodo", IntelKind::Ip, "feodo.csv", &feed.values)
+            .unwrap();
+        assert_eq!((again.entries, again.added, again.removed), (2, 1, 1));
+        assert!(storage
+            .import_intel("feodo", IntelKind::Domain, "x", &[])
+            .is_err());
+
+        let hit = Alert {
+            id: "a1".into(),
+            ts: Utc::now(),
+            severity: Severity::High,
+            rule_id: "intel.ip".into(),
+            summary: "Connection to 203.0.113.7 listed in feodo".into(),
+            flow_refs: Vec::new(),
+            process_ref: None,
+            rationale: String::new(),
+            suggested_action: None,
+            status: AlertStatus::New,
+            assignee: None,
+            notes: Vec::new(),
+            evidence: BTreeMap::from([("feed".to_string(), "feodo".to_string())]),
+        };
+        storage.put_alert(&hit).unwrap();
+        let stats = storage
+            .intel_stats(Utc::now() - chrono::Duration::days(1))
+            .unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].feed.entries, stats[0].hits), (2, 1));
+        assert!(stats[0].last_hit.is_some());
+
+        let (indicators, _) = storage.intel_lists().unwrap();
+        assert!(!indicators.is_empty());
+        assert!(storage.remove_intel_feed("feodo").unwrap());
+        assert!(storage.intel_feeds().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,371 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::{timestamp_column, Storage};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DnsRecord {
+    pub id: String,
+    pub qname: String,
+    pub qtype: String,
+    pub rcode: String,
+    pub count: u32,
+    pub last_observed: DateTime<Utc>,
+    pub channel: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServiceRecord {
+    pub id: String,
+    pub name: String,
+    pub protocol: String,
+    pub address: String,
+    pub port: u16,
+    pub process: Option<String>,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Per-process accounting, keyed by `(pid, name)` since pids are reused.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProcessActivity {
+    pub pid: i32,
+    pub name: String,
+    pub user: Option<String>,
+    pub signed: Option<bool>,
+    pub hash: Option<String>,
+    pub listening_ports: Vec<u16>,
+    pub total_flows: u64,
+    pub last_active: DateTime<Utc>,
+}
+
+const DNS_COLUMNS: &str = "id, qname, qtype, rcode, count, last_observed, channel";
+const SERVICE_COLUMNS: &str = "id, name, protocol, address, port, process, first_seen, last_seen";
+const PROCESS_COLUMNS: &str =
+    "pid, name, user, signed, hash, listening_ports, total_flows, last_active";
+
+pub(crate) fn create_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS dns_records (
+            id TEXT PRIMARY KEY,
+            qname TEXT NOT NULL,
+            qtype TEXT NOT NULL,
+            rcode TEXT NOT NULL,
+            count INTEGER NOT NULL,
+            last_observed TEXT NOT NULL,
+            channel TEXT
+        );
+        CREATE INDEX IF NOT EXISTS dns_records_last_observed ON dns_records (last_observed);
+        CREATE TABLE IF NOT EXISTS services (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            protocol TEXT NOT NULL,
+            address TEXT NOT NULL,
+            port INTEGER NOT NULL,
+            process TEXT,
+            last_seen TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS services_last_seen ON services (last_seen);
+        CREATE TABLE IF NOT EXISTS process_activity (
+            pid INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            user TEXT,
+            signed INTEGER,
+            hash TEXT,
+            listening_ports TEXT NOT NULL DEFAULT '[]',
+            total_flows INTEGER NOT NULL,
+            last_active TEXT NOT NULL,
+            PRIMARY KEY (pid, name)
+        );
+        CREATE INDEX IF NOT EXISTS process_activity_last_active ON process_activity (last_active);
+        "#,
+    )?;
+    Ok(())
+}
+
+pub(crate) fn drop_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "DROP TABLE IF EXISTS dns_records; DROP TABLE IF EXISTS services; DROP TABLE IF EXISTS process_activity;",
+    )?;
+    Ok(())
+}
+
+fn dns_record(row: &rusqlite::Row<'_>) -> rusqlite::Result<DnsRecord> {
+    Ok(DnsRecord {
+        id: row.get(0)?,
+        qname: row.get(1)?,
+        qtype: row.get(2)?,
+        rcode: row.get(3)?,
+        count: row.get(4)?,
+        last_observed: timestamp_column(row, 5)?,
+        channel: row.get(6)?,
+    })
+}
+
+fn service_record(row: &rusqlite::Row<'_>) -> rusqlite::Result<ServiceRecord> {
+    Ok(ServiceRecord {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        protocol: row.get(2)?,
+        address: row.get(3)?,
+        port: row.get(4)?,
+        process: row.get(5)?,
+        first_seen: timestamp_column(row, 6)?,
+        last_seen: timestamp_column(row, 7)?,
+    })
+}
+
+fn process_activity(row: &rusqlite::Row<'_>) -> rusqlite::Result<ProcessActivity> {
+    let ports: String = row.get(5)?;
+    Ok(ProcessActivity {
+        pid: row.get(0)?,
+        name: row.get(1)?,
+        user: row.get(2)?,
+        signed: row.get(3)?,
+        hash: row.get(4)?,
+        listening_ports: serde_json::from_str(&ports).map_err(|err| {
+            rusqlite::Error::FromSqlConversionFailure(5, rusqlite::types::Type::Text, Box::new(err))
+        })?,
+        total_flows: row.get::<_, i64>(6)? as u64,
+        last_active: timestamp_column(row, 7)?,
+    })
+}
+
+impl Storage {
+    /// Inserts or replaces the record with the same id.
+    pub fn put_dns_record(&self, record: &DnsRecord) -> Result<()> {
+        self.conn
+            .prepare_cached(&format!(
+                "INSERT OR REPLACE INTO dns_records ({DNS_COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"
+            ))?
+            .execute(params![
+                record.id,
+                record.qname,
+                record.qtype,
+                record.rcode,
+                record.count,
+                record.last_observed.to_rfc3339(),
+                record.channel
+            ])?;
+        Ok(())
+    }
+
+    pub fn get_dns_record(&self, id: &str) -> Result<Option<DnsRecord>> {
+        Ok(self
+            .conn
+            .prepare_cached(&format!(
+                "SELECT {DNS_COLUMNS} FROM dns_records WHERE id = ?1"
+            ))?
+            .query_row(params![id], dns_record)
+            .optional()?)
+    }
+
+    /// Most recently observed first.
+    pub fn list_dns_records(&self, limit: usize) -> Result<Vec<DnsRecord>> {
+        let rows = self
+            .conn
+            .prepare_cached(&format!(
+                "SELECT {DNS_COLUMNS} FROM dns_records ORDER BY last_observed DESC, id LIMIT ?1"
+            ))?
+            .query_map(params![limit as i64], dns_record)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Returns whether a record was removed.
+    pub fn delete_dns_record(&self, id: &str) -> Result<bool> {
+        Ok(self
+            .conn
+            .execute("DELETE FROM dns_records WHERE id = ?1", params![id])?
+            > 0)
+    }
+
+    /// Inserts the service or updates the one with the same id; a stored
+    /// service keeps its `first_seen`.
+    pub fn put_service(&self, service: &ServiceRecord) -> Result<()> {
+        self.conn
+            .prepare_cached(&format!(
+                "INSERT INTO services ({SERVICE_COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                 ON CONFLICT (id) DO UPDATE SET name = excluded.name, protocol = excluded.protocol,
+                     address = excluded.address, port = excluded.port, process = excluded.process,
+                     last_seen = excluded.last_seen"
+            ))?
+            .execute(params![
+                service.id,
+                service.name,
+                service.protocol,
+                service.address,
+                service.port,
+                service.process,
+                service.first_seen.to_rfc3339(),
+                service.last_seen.to_rfc3339()
+            ])?;
+        Ok(())
+    }
+
+    pub fn get_service(&self, id: &str) -> Result<Option<ServiceRecord>> {
+        Ok(self
+            .conn
+            .prepare_cached(&format!(
+                "SELECT {SERVICE_COLUMNS} FROM services WHERE id = ?1"
+            ))?
+            .query_row(params![id], service_record)
+            .optional()?)
+    }
+
+    /// Most recently seen first.
+    pub fn list_services(&self, limit: usize) -> Result<Vec<ServiceRecord>> {
+        let rows = self
+            .conn
+            .prepare_cached(&format!(
+                "SELECT {SERVICE_COLUMNS} FROM services ORDER BY last_seen DESC, id LIMIT ?1"
+            ))?
+            .query_map(params![limit as i64], service_record)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    pub fn delete_service(&self, id: &str) -> Result<bool> {
+        Ok(self
+            .conn
+            .execute("DELETE FROM services WHERE id = ?1", params![id])?
+            > 0)
+    }
+
+    /// Inserts or replaces the activity of the same `(pid, name)`.
+    pub fn put_process_activity(&self, activity: &ProcessActivity) -> Result<()> {
+        self.conn
+            .prepare_cached(&format!(
+                "INSERT OR REPLACE INTO process_activity ({PROCESS_COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"
+            ))?
+            .execute(params![
+                activity.pid,
+                activity.name,
+                activity.user,
+                activity.signed,
+                activity.hash,
+                serde_json::to_string(&activity.listening_ports)?,
+                activity.total_flows as i64,
+                activity.last_active.to_rfc3339()
+            ])?;
+        Ok(())
+    }
+
+    pub fn get_process_activity(&self, pid: i32, name: &str) -> Result<Option<ProcessActivity>> {
+        Ok(self
+            .conn
+            .prepare_cached(&format!(
+                "SELECT {PROCESS_COLUMNS} FROM process_activity WHERE pid = ?1 AND name = ?2"
+            ))?
+            .query_row(params![pid, name], process_activity)
+            .optional()?)
+    }
+
+    /// Most recently active first.
+    pub fn list_process_activity(&self, limit: usize) -> Result<Vec<ProcessActivity>> {
+        let rows = self
+            .conn
+            .prepare_cached(&format!(
+                "SELECT {PROCESS_COLUMNS} FROM process_activity ORDER BY last_active DESC, pid LIMIT ?1"
+            ))?
+            .query_map(params![limit as i64], process_activity)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    pub fn delete_process_activity(&self, pid: i32, name: &str) -> Result<bool> {
+        Ok(self.conn.execute(
+            "DELETE FROM process_activity WHERE pid = ?1 AND name = ?2",
+            params![pid, name],
+        )? > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn round_trips_and_lists_newest_first() {
+        let storage = Storage::open(":memory:", &[6u8; 32]).unwrap();
+        let now = Utc::now();
+        let older = DnsRecord {
+            id: "dns-1".into(),
+            qname: "example.com".into(),
+            qtype: "A".into(),
+            rcode: "NOERROR".into(),
+            count: 3,
+            last_observed: now - Duration::minutes(5),
+            channel: None,
+        };
+        let newer = DnsRecord {
+            id: "dns-2".into(),
+            qname: "evil.example".into(),
+            rcode: "NXDOMAIN".into(),
+            last_observed: now,
+            channel: Some("doh".into()),
+            ..older.clone()
+        };
+        storage.put_dns_record(&older).unwrap();
+        storage.put_dns_record(&newer).unwrap();
+        storage
+            .put_dns_record(&DnsRecord {
+                count: 4,
+                ..older.clone()
+            })
+            .unwrap();
+        let listed = storage.list_dns_records(10).unwrap();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].id, "dns-2");
+        assert_eq!(listed[1].count, 4);
+        assert!(storage.delete_dns_record("dns-1").unwrap());
+        assert!(storage.get_dns_record("dns-1").unwrap().is_none());
+
+        let activity = ProcessActivity {
+            pid: 4242,
+            name: "notesync.exe".into(),
+            user: Some("alice".into()),
+            signed: Some(false),
+            hash: None,
+            listening_ports: vec![8080, 8443],
+            total_flows: 17,
+            last_active: now,
+        };
+        storage.put_process_activity(&activity).unwrap();
+        let stored = storage
+            .get_process_activity(4242, "notesync.exe")
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.listening_ports, [8080, 8443]);
+        assert_eq!(stored.signed, Some(false));
+        assert_eq!(storage.list_process_activity(10).unwrap().len(), 1);
+
+        let printer = ServiceRecord {
+            id: "mdns:Office Printer._ipp._tcp.local".into(),
+            name: "Office Printer".into(),
+            protocol: "mDNS".into(),
+            address: "192.168.1.40".into(),
+            port: 631,
+            process: None,
+            first_seen: now - Duration::hours(2),
+            last_seen: now - Duration::hours(2),
+        };
+        storage.put_service(&printer).unwrap();
+        storage
+            .put_service(&ServiceRecord {
+                address: "192.168.1.41".into(),
+                first_seen: now,
+                last_seen: now,
+                ..printer.clone()
+            })
+            .unwrap();
+        let stored = storage.get_service(&printer.id).unwrap().unwrap();
+        assert_eq!(stored.address, "192.168.1.41");
+        assert_eq!(stored.first_seen, printer.first_seen);
+        assert_eq!(stored.last_seen, now);
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    fs,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use ring::{
+    aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, NONCE_LEN},
+    pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+
+pub const KEY_LEN: usize = 32;
+/// Environment variable consulted for the fallback passphrase.
+pub const PASSPHRASE_ENV: &str = "NETS_DB_PASSPHRASE";
+
+#[cfg(feature = "keystore")]
+const KEYSTORE_SERVICE: &str = "nets";
+const PBKDF2_ITERATIONS: u32 = 600_000;
+const WRAP_AAD: &[u8] = b"nets-db-key";
+
+/// Somewhere the database key lives between runs.
+pub trait KeyProvider {
+    fn name(&self) -> &'static str;
+    /// The stored key, or `None` when nothing has been stored yet.
+    fn load(&self) -> Result<Option<Vec<u8>>>;
+    fn store(&self, key: &[u8]) -> Result<()>;
+    /// Forgets the stored key; nothing to do when there is none.
+    fn remove(&self) -> Result<()>;
+}
+
+/// A fresh random database key.
+pub fn generate_key() -> Result<Vec<u8>> {
+    let mut key = vec![0u8; KEY_LEN];
+    SystemRandom::new()
+        .fill(&mut key)
+        .map_err(|_| anyhow!("failed to generate database key"))?;
+    Ok(key)
+}
+
+/// Returns the provider's key, generating and storing a random one on first use.
+pub fn load_or_create_key(provider: &dyn KeyProvider) -> Result<Vec<u8>> {
+    if let Some(key) = provider.load()? {
+        if key.len() != KEY_LEN {
+            return Err(anyhow!(
+                "{} returned a {}-byte key, expected {KEY_LEN}",
+                provider.name(),
+                key.len()
+            ));
+        }
+        return Ok(key);
+    }
+    let key = generate_key()?;
+    provider.store(&key)?;
+    tracing::info!(provider = provider.name(), "generated new database key");
+    Ok(key)
+}
+
+/// Windows Credential Manager (DPAPI-protected), macOS Keychain or the Linux
+/// Secret Service (cached in the kernel keyring), keyed by database path.
+#[cfg(feature = "keystore")]
+pub struct OsKeystore {
+    account: String,
+}
+
+#[cfg(feature = "keystore")]
+impl OsKeystore {
+    pub fn new(db_path: &Path) -> Self {
+        let account = fs::canonicalize(db_path)
+            .unwrap_or_else(|_| db_path.to_path_buf())
+            .display()
+            .to_string();
+        Self { account }
+    }
+
+    fn entry(&self) -> Result<keyring::Entry> {
+        keyring::Entry::new(KEYSTORE_SERVICE, &self.account)
+            .with_context(|| format!("opening keystore entry for {}", self.account))
+    }
+}
+
+#[cfg(feature = "keystore")]
+impl KeyProvider for OsKeystore {
+    fn name(&self) -> &'static str {
+        "os-keystore"
+    }
+
+    fn load(&self) -> Result<Option<Vec<u8>>> {
+        match self.entry()?.get_secret() {
+            Ok(key) => Ok(Some(key)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(err) => Err(anyhow!("reading database key from keystore: {err}")),
+        }
+    }
+
+    fn store(&self, key: &[u8]) -> Result<()> {
+        self.entry()?
+            .set_secret(key)
+            .map_err(|err| anyhow!("writing database key to keystore: {err}"))
+    }
+
+    fn remove(&self) -> Result<()> {
+        match self.entry()?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(err) => Err(anyhow!("deleting database key from keystore: {err}")),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct WrappedKey {
+    version: u32,
+    iterations: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Fallback for hosts without a usable keystore: the random database key is
+/// wrapped with AES-256-GCM under a PBKDF2-HMAC-SHA256 key derived from a
+/// passphrase, and the wrapped form is kept in a file next to the database.
+pub struct PassphraseKey {
+    passphrase: String,
+    path: PathBuf,
+}
+
+impl PassphraseKey {
+    pub fn new(passphrase: impl Into<String>, db_path: &Path) -> Self {
+        let mut path = db_path.as_os_str().to_owned();
+        path.push(".key");
+        Self {
+            passphrase: passphrase.into(),
+            path: PathBuf::from(path),
+        }
+    }
+
+    fn kek(&self, salt: &[u8], iterations: u32) -> Result<LessSafeKey> {
+        let iterations =
+            NonZeroU32::new(iterations).ok_or_else(|| anyhow!("invalid PBKDF2 iteration count"))?;
+        let mut kek = [0u8; KEY_LEN];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            iterations,
+            salt,
+            self.passphrase.as_bytes(),
+            &mut kek,
+        );
+        let unbound = UnboundKey::new(&aead::AES_256_GCM, &kek)
+            .map_err(|_| anyhow!("failed to initialize key-encryption key"))?;
+        Ok(LessSafeKey::new(unbound))
+    }
+}
+
+impl KeyProvider for PassphraseKey {
+    fn name(&self) -> &'static str {
+        "passphrase"
+    }
+
+    fn load(&self) -> Result<Option<Vec<u8>>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read_to_string(&self.path)
+            .with_context(|| format!("reading {}", self.path.display()))?;
+        let wrapped: WrappedKey = serde_json::from_str(&data)
+            .with_context(|| format!("parsing {}", self.path.display()))?;
+        if wrapped.version != 1 {
+            return Err(anyhow!("unsupported key file version {}", wrapped.version));
+        }
+        let kek = self.kek(&hex::decode(&wrapped.salt)?, wrapped.iterations)?;
+        let nonce = <[u8; NONCE_LEN]>::try_from(hex::decode(&wrapped.nonce)?.as_slice())
+            .map_err(|_| anyhow!("malformed nonce in {}", self.path.display()))?;
+        let mut ciphertext = hex::decode(&wrapped.ciphertext)?;
+        let key = kek
+            .open_in_place(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(WRAP_AAD),
+                &mut ciphertext,
+            )
+            .map_err(|_| anyhow!("wrong passphrase or corrupted key file"))?;
+        Ok(Some(key.to_vec()))
+    }
+
+    fn store(&self, key: &[u8]) -> Result<()> {
+        let rng = SystemRandom::new();
+        let mut salt = [0u8; 16];
+        let mut nonce = [0u8; NONCE_LEN];
+        rng.fill(&mut salt)
+            .and_then(|_| rng.fill(&mut nonce))
+            .map_err(|_| anyhow!("failed to generate key-wrapping parameters"))?;
+        let mut ciphertext = key.to_vec();
+        self.kek(&salt, PBKDF2_ITERATIONS)?
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(WRAP_AAD),
+                &mut ciphertext,
+            )
+            .map_err(|_| anyhow!("failed to wrap database key"))?;
+        let wrapped = WrappedKey {
+            version: 1,
+            iterations: PBKDF2_ITERATIONS,
+            salt: hex::encode(salt),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        };
+        let tmp = self.path.with_extension("key.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(&wrapped)?)
+            .with_context(|| format!("writing {}", tmp.display()))?;
+        fs::rename(&tmp, &self.path)
+            .with_context(|| format!("replacing {}", self.path.display()))?;
+        Ok(())
+    }
+
+    fn remove(&self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("removing {}", self.path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Where the key for the database at `db_path` lives: the OS keystore when
+/// it can be read, otherwise a passphrase-wrapped key file (passphrase from
+/// the argument or `NETS_DB_PASSPHRASE`).
+pub fn resolve_provider(db_path: &Path, passphrase: Option<&str>) -> Result<Box<dyn KeyProvider>> {
+    #[cfg(feature = "keystore")]
+    {
+        let keystore = OsKeystore::new(db_path);
+        match keystore.load() {
+            Ok(_) => return Ok(Box::new(keystore)),
+            Err(err) => tracing::warn!(%err, "OS keystore unavailable, falling back to passphrase"),
+        }
+    }
+    let passphrase = passphrase
+        .map(str::to_string)
+        .or_else(|| std::env::var(PASSPHRASE_ENV).ok())
+        .filter(|passphrase| !passphrase.is_empty())
+        .ok_or_else(|| {
+            anyhow!("no OS keystore available; set {PASSPHRASE_ENV} to protect the database key")
+        })?;
+    Ok(Box::new(PassphraseKey::new(passphrase, db_path)))
+}
+
+/// The key for the database at `db_path` from [`resolve_provider`],
+/// generated on first use.
+pub fn resolve_key(db_path: &Path, passphrase: Option<&str>) -> Result<Vec<u8>> {
+    load_or_create_key(resolve_provider(db_path, passphrase)?.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passphrase_key_round_trips_and_is_removed() {
+        let db = std::env::temp_dir().join(format!(
+            "nets-keys-{}-{}.db",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        let provider = PassphraseKey::new("correct horse", &db);
+        assert_eq!(provider.load().unwrap(), None);
+        let key = load_or_create_key(&provider).unwrap();
+        assert_eq!(key.len(), KEY_LEN);
+        assert_eq!(load_or_create_key(&provider).unwrap(), key);
+        assert!(PassphraseKey::new("wrong", &db).load().is_err());
+
+        provider.remove().unwrap();
+        assert_eq!(provider.load().unwrap(), None);
+        provider.remove().unwrap();
+    }
+}
@@ -1,20 +1,250 @@
-use analyzer::Alert;
+use analyzer::{retro::FlowHistory, Alert, AlertNote, AlertStatus};
 use anyhow::{anyhow, Result};
 use chrono::{DateTime, Utc};
 use collector::FlowEvent;
-use ring::aead::{self, Aad, LessSafeKey, UnboundKey};
-use rusqlite::{params, Connection};
+use rusqlite::types::Value;
+use rusqlite::{functions::FunctionFlags, params, params_from_iter, Connection, OptionalExtension};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::{cell::RefCell, collections::HashMap, path::Path};
 
-const AAD_CONTEXT: &[u8] = b"nets-local-monitor";
+pub mod actions;
+pub mod audit;
+pub mod backup;
+pub mod baseline;
+pub mod check;
+pub mod crypto;
+pub mod diff;
+pub mod dns;
+pub mod export;
+pub mod import;
+pub mod incident;
+pub mod integrity;
+pub mod intel;
+pub mod inventory;
+pub mod keys;
+pub mod metrics;
+pub mod migrations;
+pub mod partition;
+pub mod quarantine;
+pub mod query;
+#[cfg(feature = "remote")]
+pub mod remote;
+pub mod report;
+pub mod retention;
+pub mod rollup;
+pub mod rotation;
+pub mod search;
+pub mod spool;
+pub mod upsert;
+pub mod writer;
+
+pub use actions::{ActionOutcome, PolicyActionQuery, PolicyActionRecord};
+pub use audit::{AuditEntry, AuditProblem, AuditVerification};
+pub use backup::BackupInfo;
+pub use check::{CheckOptions, CheckReport};
+use crypto::{FlowCipher, OpenError, FORMAT_LEGACY};
+pub use diff::{NewDestination, NewListener, Period, PeriodDiff};
+pub use dns::{DnsSummary, DomainStats, NxdomainSource};
+pub use export::{ExportFormat, ExportQuery};
+pub use import::{ImportOptions, ImportReport, NdjsonImporter};
+pub use incident::{Incident, IncidentQuery, IncidentStatus};
+use integrity::ROW_MAC_COLUMNS;
+pub use intel::{IntelFeed, IntelFeedStats, IntelImport};
+pub use inventory::{DnsRecord, ProcessActivity, ServiceRecord};
+pub use metrics::StorageMetrics;
+pub use migrations::{MigrationPlan, LATEST_SCHEMA_VERSION};
+pub use partition::PartitionedStorage;
+pub use quarantine::QuarantineRecord;
+pub use query::{AlertQuery, FlowQuery, SortOrder};
+pub use report::{NewDevice, Report, ReportFormat};
+pub use writer::{AsyncStorage, WriterConfig};
+
+/// Rows re-encrypted per transaction when upgrading the ciphertext format.
+const MIGRATION_BATCH: usize = 500;
+const STATEMENT_CACHE_CAPACITY: usize = 32;
 
 pub struct Storage {
     conn: Connection,
-    key: LessSafeKey,
+    /// Key new ciphertexts are sealed under.
+    cipher: FlowCipher,
+    /// Further keys rows may still be sealed under (e.g. mid-rotation), by key id.
+    retired: HashMap<String, FlowCipher>,
+    /// How new flow payloads are compressed before sealing.
+    compression: Compression,
+    /// Whether stored records are queued for the remote shipper.
+    spool: bool,
+    writes: RefCell<metrics::WriteMeter>,
+}
+
+/// How the database file itself is protected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Plain SQLite file; only the flow payload blobs are encrypted.
+    #[default]
+    Sqlite,
+    /// SQLCipher: the whole file, including IPs, ports, timestamps and
+    /// alerts, is encrypted. Requires the `sqlcipher` feature.
+    SqlCipher,
+}
+
+/// Compression of the serialized `FlowEvent` before it is sealed. Stored
+/// per row in `flows.compression`, so rows written either way stay readable
+/// after the setting changes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    /// zstd at level 3; a typical flow payload shrinks by about 40%.
+    #[default]
+    Zstd,
+}
+
+impl Compression {
+    const ZSTD_LEVEL: i32 = 3;
+
+    /// Value of the `flows.compression` column.
+    fn flag(self) -> i64 {
+        match self {
+            Compression::None => 0,
+            Compression::Zstd => 1,
+        }
+    }
+
+    fn compress(self, plaintext: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(match self {
+            Compression::None => plaintext,
+            Compression::Zstd => ZSTD_COMPRESSOR.with(|compressor| {
+                let mut compressor = compressor.borrow_mut();
+                if compressor.is_none() {
+                    *compressor = Some(zstd::bulk::Compressor::new(Self::ZSTD_LEVEL)?);
+                }
+                compressor
+                    .as_mut()
+                    .expect("initialized above")
+                    .compress(&plaintext)
+            })?,
+        })
+    }
+}
+
+/// Largest decompressed flow payload accepted; real ones are under 2 KiB.
+const MAX_FLOW_PAYLOAD: usize = 1 << 20;
+
+thread_local! {
+    // Contexts are reused: setting one up costs more than compressing a
+    // single flow.
+    static ZSTD_COMPRESSOR: RefCell<Option<zstd::bulk::Compressor<'static>>> =
+        const { RefCell::new(None) };
+    static ZSTD_DECOMPRESSOR: RefCell<Option<zstd::bulk::Decompressor<'static>>> =
+        const { RefCell::new(None) };
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct StorageOptions {
+    #[serde(default)]
+    pub backend: Backend,
+    /// Applies to flows written from now on; existing rows are not rewritten.
+    #[serde(default)]
+    pub compression: Compression,
+    /// Open without applying pending schema migrations, e.g. to inspect
+    /// them with [`Storage::migrate_to`] in dry-run mode first.
+    #[serde(default)]
+    pub skip_migrations: bool,
+    /// Queue stored flows and alerts in `export_spool` for a remote shipper
+    /// ([`spool`]). Only enable it when something drains the spool.
+    #[serde(default)]
+    pub spool: bool,
+}
+
+/// Why a stored flow could not be read back. Returned inside
+/// `anyhow::Error`; downcast to tell tampering apart from other failures.
+#[derive(Debug, thiserror::Error)]
+pub enum FlowReadError {
+    #[error("flow {0} not found")]
+    NotFound(i64),
+    #[error("flow {0} has no encrypted payload")]
+    MissingPayload(i64),
+    #[error("flow {id} is sealed under unknown key {key_id}")]
+    UnknownKey { id: i64, key_id: String },
+    /// Authentication of the ciphertext failed: the blob, its nonce or the
+    /// associated data was modified (or the row was sealed under another
+    /// key with the same id, which should not happen).
+    #[error("flow {id} failed authentication; the stored payload was tampered with")]
+    Tampered { id: i64 },
+    /// The payload failed authentication and carries the nonce of flow
+    /// `origin`: it was copied over from that row.
+    #[error("flow {id} holds the encrypted payload of flow {origin}")]
+    Relocated { id: i64, origin: i64 },
+    /// The payload authenticated but disagrees with the plaintext metadata
+    /// columns, which were edited after the row was written.
+    #[error("flow {id}: plaintext column {field} does not match the encrypted payload")]
+    MetadataMismatch { id: i64, field: &'static str },
+    /// The plaintext columns (IPs, ports, timestamps, ...) do not match the
+    /// row's MAC: they or the MAC were edited after the row was written.
+    #[error("flow {id}: plaintext columns fail their integrity check")]
+    ColumnsTampered { id: i64 },
+    /// The row has no MAC, which every row gets when it is written.
+    #[error("flow {id} has no integrity MAC")]
+    MissingMac { id: i64 },
+    #[error("flow {id} payload is unreadable: {reason}")]
+    Malformed { id: i64, reason: String },
+}
+
+impl FlowReadError {
+    pub fn is_tampering(&self) -> bool {
+        matches!(
+            self,
+            FlowReadError::Tampered { .. }
+                | FlowReadError::Relocated { .. }
+                | FlowReadError::MetadataMismatch { .. }
+                | FlowReadError::ColumnsTampered { .. }
+                | FlowReadError::MissingMac { .. }
+        )
+    }
+
+    pub fn flow_id(&self) -> i64 {
+        match self {
+            FlowReadError::NotFound(id) | FlowReadError::MissingPayload(id) => *id,
+            FlowReadError::UnknownKey { id, .. }
+            | FlowReadError::Tampered { id }
+            | FlowReadError::Relocated { id, .. }
+            | FlowReadError::MetadataMismatch { id, .. }
+            | FlowReadError::ColumnsTampered { id }
+            | FlowReadError::MissingMac { id }
+            | FlowReadError::Malformed { id, .. } => *id,
+        }
+    }
+}
+
+/// Encrypted payload columns of a `flows` row.
+struct SealedRow {
+    version: i64,
+    key_id: Option<String>,
+    nonce: Option<Vec<u8>>,
+    ciphertext: Option<Vec<u8>>,
+    /// [`Compression::flag`] of the sealed plaintext.
+    compression: i64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl SealedRow {
+    const COLUMNS: &'static str = "enc_version, key_id, nonce, ciphertext, compression";
+    /// For migration steps that run before the `compression` column exists;
+    /// every row was uncompressed then.
+    const UNCOMPRESSED_COLUMNS: &'static str = "enc_version, key_id, nonce, ciphertext, 0";
+
+    fn read(row: &rusqlite::Row<'_>, offset: usize) -> rusqlite::Result<Self> {
+        Ok(Self {
+            version: row.get(offset)?,
+            key_id: row.get(offset + 1)?,
+            nonce: row.get(offset + 2)?,
+            ciphertext: row.get(offset + 3)?,
+            compression: row.get(offset + 4)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct StoredFlow {
     pub id: i64,
     pub ts_first: DateTime<Utc>,
@@ -25,114 +255,1064 @@ pub struct StoredFlow {
     pub src_port: u16,
     pub dst_port: u16,
     pub bytes: u64,
+    pub direction: Option<String>,
+    pub process: Option<String>,
+    /// See [`flow_domain`].
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// The columns above failed the row's integrity check, so they cannot
+    /// be trusted; a tamper alert was raised for the row.
+    #[serde(default)]
+    pub tampered: bool,
+}
+
+impl StoredFlow {
+    /// The plaintext columns `flow` is stored with.
+    pub(crate) fn columns(id: i64, flow: &FlowEvent) -> Self {
+        Self {
+            id,
+            ts_first: flow.ts_first,
+            ts_last: flow.ts_last,
+            proto: flow.proto.clone(),
+            src_ip: flow.src_ip.clone(),
+            dst_ip: flow.dst_ip.clone(),
+            src_port: flow.src_port,
+            dst_port: flow.dst_port,
+            bytes: flow.bytes,
+            direction: Some(format!("{:?}", flow.direction)),
+            process: flow.process.as_ref().and_then(|p| p.name.clone()),
+            domain: flow_domain(flow),
+            tampered: false,
+        }
+    }
 }
 
+pub(crate) const ALERT_COLUMNS: &str = "id, ts, severity, rule_id, summary, rationale, status, assignee, notes, evidence, process_ref, suggested_action";
+pub(crate) const STORED_FLOW_COLUMN_COUNT: usize = 12;
+pub(crate) const STORED_FLOW_COLUMNS: &str =
+    "id, ts_first, ts_last, proto, src_ip, dst_ip, src_port, dst_port, bytes, direction, process, domain";
+
 impl Storage {
     pub fn open<P: AsRef<Path>>(path: P, key_bytes: &[u8]) -> Result<Self> {
+        Self::open_with_options(path, key_bytes, StorageOptions::default())
+    }
+
+    pub fn open_with_options<P: AsRef<Path>>(
+        path: P,
+        key_bytes: &[u8],
+        options: StorageOptions,
+    ) -> Result<Self> {
         let conn = Connection::open(path)?;
-        if key_bytes.len() != 32 {
-            return Err(anyhow!("AES-256-GCM key must be 32 bytes"));
-        }
-        let unbound_key = UnboundKey::new(&aead::AES_256_GCM, key_bytes)
-            .map_err(|_| anyhow!("failed to initialize encryption key"))?;
-        let key = LessSafeKey::new(unbound_key);
-        let storage = Self { conn, key };
-        storage.migrate()?;
+        let cipher = FlowCipher::new(key_bytes)?;
+        if options.backend == Backend::SqlCipher {
+            unlock_sqlcipher(&conn, key_bytes)?;
+        }
+        conn.create_scalar_function(
+            "cidr_match",
+            2,
+            FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+            |ctx| {
+                let ip = ctx.get::<String>(0)?;
+                let cidr = ctx.get::<String>(1)?;
+                Ok(query::ip_in_cidr(&ip, &cidr))
+            },
+        )?;
+        conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+        // Lets the retention job and readers use their own connections
+        // while the writer holds a transaction.
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+        let storage = Self {
+            conn,
+            cipher,
+            retired: HashMap::new(),
+            compression: options.compression,
+            spool: options.spool,
+            writes: RefCell::default(),
+        };
+        if !options.skip_migrations {
+            storage.migrate()?;
+        }
+        // WAL lets readers run alongside the writer, and with
+        // synchronous=NORMAL a commit no longer waits for an fsync (only a
+        // power loss can drop the last few commits; the file stays
+        // consistent). Set after migrating so a fresh file gets its
+        // auto_vacuum mode first.
+        let journal: String = storage
+            .conn
+            .query_row("PRAGMA journal_mode = WAL", [], |row| row.get(0))?;
+        if !journal.eq_ignore_ascii_case("wal") {
+            tracing::warn!(journal, "database does not support WAL mode");
+        }
+        storage.conn.execute_batch("PRAGMA synchronous = NORMAL;")?;
         Ok(storage)
     }
 
+    /// Writes an SQLCipher-encrypted copy of this (plain SQLite) database to
+    /// `dest`, for switching an existing install to [`Backend::SqlCipher`].
+    pub fn export_sqlcipher<P: AsRef<Path>>(&self, dest: P, key_bytes: &[u8]) -> Result<()> {
+        ensure_sqlcipher(&self.conn)?;
+        let dest = dest.as_ref().display().to_string().replace('\'', "''");
+        self.conn.execute_batch(&format!(
+            "ATTACH DATABASE '{dest}' AS encrypted KEY \"{}\"; SELECT sqlcipher_export('encrypted'); DETACH DATABASE encrypted;",
+            sqlcipher_key(key_bytes)
+        ))?;
+        Ok(())
+    }
+
+    /// Opens the database with its key from `provider`, creating the key on
+    /// first use.
+    pub fn open_with_provider<P: AsRef<Path>>(
+        path: P,
+        provider: &dyn keys::KeyProvider,
+    ) -> Result<Self> {
+        let key = keys::load_or_create_key(provider)?;
+        Self::open(path, &key)
+    }
+
     fn migrate(&self) -> Result<()> {
-        self.conn.execute_batch(
-            r#"
-            CREATE TABLE IF NOT EXISTS flows (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                ts_first TEXT NOT NULL,
-                ts_last TEXT NOT NULL,
-                proto TEXT NOT NULL,
-                src_ip TEXT NOT NULL,
-                dst_ip TEXT NOT NULL,
-                src_port INTEGER NOT NULL,
-                dst_port INTEGER NOT NULL,
-                bytes INTEGER NOT NULL,
-                ciphertext BLOB
-            );
-            CREATE TABLE IF NOT EXISTS alerts (
-                id TEXT PRIMARY KEY,
-                ts TEXT NOT NULL,
-                severity TEXT NOT NULL,
-                rule_id TEXT NOT NULL,
-                summary TEXT NOT NULL,
-                rationale TEXT NOT NULL
-            );
-            "#,
-        )?;
+        // Retention pruning hands freed pages back with incremental vacuum,
+        // which needs auto_vacuum set before the first table is created or
+        // a full VACUUM to convert an existing file (done once).
+        let auto_vacuum: i64 = self
+            .conn
+            .query_row("PRAGMA auto_vacuum", [], |row| row.get(0))?;
+        if auto_vacuum != 2 {
+            self.conn
+                .execute_batch("PRAGMA auto_vacuum = INCREMENTAL;")?;
+            let pages: i64 = self
+                .conn
+                .query_row("PRAGMA page_count", [], |row| row.get(0))?;
+            if pages > 0 {
+                tracing::info!("converting database to incremental auto-vacuum");
+                self.conn.execute_batch("VACUUM;")?;
+            }
+        }
+        self.upgrade_schema()?;
+        Ok(())
+    }
+
+    /// Fills `direction`/`process` for rows stored before those columns
+    /// existed by decrypting their payloads. Rows that cannot be decrypted
+    /// are left NULL and skipped.
+    fn backfill_flow_metadata(&self) -> Result<()> {
+        self.backfill_flows(
+            "direction IS NULL",
+            SealedRow::UNCOMPRESSED_COLUMNS,
+            |conn, id, flow| {
+                conn.execute(
+                    "UPDATE flows SET direction = ?1, process = ?2 WHERE id = ?3",
+                    params![
+                        format!("{:?}", flow.direction),
+                        flow.process.and_then(|p| p.name),
+                        id
+                    ],
+                )?;
+                Ok(())
+            },
+        )
+    }
+
+    /// Fills `domain` for rows stored before the column existed.
+    fn backfill_flow_domains(&self) -> Result<()> {
+        self.backfill_flows("1", SealedRow::COLUMNS, |conn, id, flow| {
+            if let Some(domain) = flow_domain(&flow) {
+                conn.execute(
+                    "UPDATE flows SET domain = ?1 WHERE id = ?2",
+                    params![domain, id],
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Decrypts the rows matching `filter` batch by batch and hands each
+    /// flow to `update`. Rows that cannot be decrypted are skipped.
+    fn backfill_flows(
+        &self,
+        filter: &str,
+        sealed_columns: &str,
+        update: impl Fn(&Connection, i64, FlowEvent) -> Result<()>,
+    ) -> Result<()> {
+        let mut after = 0i64;
+        loop {
+            let tx = self.conn.unchecked_transaction()?;
+            let rows = {
+                let mut stmt = tx.prepare(&format!(
+                    "SELECT id, {sealed_columns} FROM flows WHERE ({filter}) AND ciphertext IS NOT NULL AND id > ?1 ORDER BY id LIMIT ?2",
+                ))?;
+                let rows = stmt
+                    .query_map(params![after, MIGRATION_BATCH as i64], |row| {
+                        Ok((row.get::<_, i64>(0)?, SealedRow::read(row, 1)?))
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                rows
+            };
+            let Some((last, _)) = rows.last() else {
+                return Ok(());
+            };
+            after = *last;
+            for (id, sealed) in rows {
+                match self.decrypt_flow(id, sealed) {
+                    Ok(flow) => update(&tx, id, flow)?,
+                    Err(err) => tracing::warn!(id, %err, "cannot backfill flow metadata"),
+                }
+            }
+            tx.commit()?;
+        }
+    }
+
+    /// Re-seals rows written under the shared all-zero nonce with fresh
+    /// random nonces, one transaction per batch so an interrupted upgrade
+    /// resumes where it stopped. Runs before rows have a `key_id`; they
+    /// stay sealed under the key the database is opened with.
+    fn reencrypt_legacy_flows(&self) -> Result<usize> {
+        let mut migrated = 0;
+        loop {
+            let tx = self.conn.unchecked_transaction()?;
+            let rows = {
+                let mut stmt = tx.prepare(
+                    "SELECT id, ciphertext FROM flows WHERE enc_version = ?1 AND ciphertext IS NOT NULL LIMIT ?2",
+                )?;
+                let rows = stmt
+                    .query_map(params![FORMAT_LEGACY, MIGRATION_BATCH as i64], |row| {
+                        Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?))
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                rows
+            };
+            if rows.is_empty() {
+                return Ok(migrated);
+            }
+            for (id, ciphertext) in &rows {
+                let plaintext = self
+                    .cipher
+                    .open(*id, FORMAT_LEGACY, None, ciphertext.clone())
+                    .map_err(|err| anyhow!("flow {id}: {err}"))?;
+                let sealed = self.cipher.seal(*id, &plaintext)?;
+                tx.execute(
+                    "UPDATE flows SET ciphertext = ?1, nonce = ?2, enc_version = ?3 WHERE id = ?4",
+                    params![sealed.ciphertext, sealed.nonce, sealed.version, id],
+                )?;
+            }
+            tx.commit()?;
+            migrated += rows.len();
+            tracing::info!(migrated, "re-encrypted legacy flow rows");
+        }
+    }
+
+    /// Re-seals the payloads of another format in `version`, one
+    /// transaction per batch. Each row stays under its key; its MAC is
+    /// re-signed over the new nonce if it verified. Rows that cannot be
+    /// opened keep their format for `check` to report. Batches are
+    /// savepoints, so this also runs inside the caller's transaction.
+    fn reformat_flow_payloads(&self, version: i64) -> Result<usize> {
+        let mut after = 0i64;
+        let mut rewritten = 0;
+        loop {
+            let rows = {
+                let mut stmt = self.conn.prepare(&format!(
+                    "SELECT {STORED_FLOW_COLUMNS}, row_mac, {} FROM flows WHERE enc_version != ?1 AND ciphertext IS NOT NULL AND id > ?2 ORDER BY id LIMIT ?3",
+                    SealedRow::COLUMNS
+                ))?;
+                let rows = stmt
+                    .query_map(params![version, after, MIGRATION_BATCH as i64], |row| {
+                        Ok((
+                            stored_flow(row)?,
+                            row.get::<_, Option<Vec<u8>>>(STORED_FLOW_COLUMN_COUNT)?,
+                            SealedRow::read(row, STORED_FLOW_COLUMN_COUNT + 1)?,
+                        ))
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                rows
+            };
+            let Some((last, _, _)) = rows.last() else {
+                return Ok(rewritten);
+            };
+            after = last.id;
+            self.conn.execute_batch("SAVEPOINT reformat_flows")?;
+            for (columns, row_mac, sealed) in rows {
+                let id = columns.id;
+                let key_id = sealed.key_id.clone();
+                let signed = self
+                    .verify_row_mac(
+                        &columns,
+                        row_mac.as_deref(),
+                        key_id.as_deref(),
+                        sealed.nonce.as_deref(),
+                    )
+                    .is_ok();
+                let plaintext = match self.open_sealed(id, sealed) {
+                    Ok(plaintext) => plaintext,
+                    Err(err) => {
+                        tracing::warn!(id, %err, "cannot re-seal flow payload");
+                        continue;
+                    }
+                };
+                let cipher = self.cipher_for(key_id.as_deref())?;
+                let sealed = cipher.seal_as(version, id, &plaintext)?;
+                let row_mac = match signed {
+                    true => Some(integrity::row_mac(cipher, &columns, Some(&sealed.nonce))),
+                    false => row_mac,
+                };
+                self.conn.execute(
+                    "UPDATE flows SET ciphertext = ?1, nonce = ?2, enc_version = ?3, key_id = ?4, row_mac = ?5 WHERE id = ?6",
+                    params![sealed.ciphertext, sealed.nonce, sealed.version, sealed.key_id, row_mac, id],
+                )?;
+                rewritten += 1;
+            }
+            self.conn.execute_batch("RELEASE reformat_flows")?;
+        }
+    }
+
+    /// Re-seals compressed flow payloads uncompressed. Runs inside the
+    /// caller's transaction.
+    fn decompress_flows(&self) -> Result<usize> {
+        let mut rewritten = 0;
+        loop {
+            let rows = self
+                .conn
+                .prepare(&format!(
+                    "SELECT id, {} FROM flows WHERE compression != 0 AND ciphertext IS NOT NULL LIMIT ?1",
+                    SealedRow::COLUMNS
+                ))?
+                .query_map(params![MIGRATION_BATCH as i64], |row| {
+                    Ok((row.get::<_, i64>(0)?, SealedRow::read(row, 1)?))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            if rows.is_empty() {
+                return Ok(rewritten);
+            }
+            for (id, sealed) in rows {
+                let (compression, version) = (sealed.compression, sealed.version);
+                let plaintext = decompress(id, compression, self.open_sealed(id, sealed)?)?;
+                // Keeps the format: this runs on the way down to older builds.
+                let resealed = self.cipher.seal_as(version, id, &plaintext)?;
+                self.conn.execute(
+                    "UPDATE flows SET ciphertext = ?1, nonce = ?2, enc_version = ?3, key_id = ?4, compression = 0 WHERE id = ?5",
+                    params![resealed.ciphertext, resealed.nonce, resealed.version, resealed.key_id, id],
+                )?;
+                rewritten += 1;
+            }
+        }
+    }
+
+    /// Adds a column to databases created before it existed.
+    fn ensure_column(&self, table: &str, column: &str, definition: &str) -> Result<()> {
+        if !self.has_column(table, column)? {
+            self.conn.execute_batch(&format!(
+                "ALTER TABLE {table} ADD COLUMN {column} {definition}"
+            ))?;
+        }
         Ok(())
     }
 
+    pub(crate) fn has_column(&self, table: &str, column: &str) -> Result<bool> {
+        let mut stmt = self.conn.prepare(&format!("PRAGMA table_info({table})"))?;
+        let exists = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<Result<Vec<_>, _>>()?
+            .iter()
+            .any(|name| name == column);
+        Ok(exists)
+    }
+
     pub fn put_flow(&self, flow: &FlowEvent) -> Result<i64> {
-        let serialized = serde_json::to_vec(flow)?;
-        let nonce = aead::Nonce::assume_unique_for_key([0u8; 12]);
-        let mut in_out = serialized.clone();
-        let tag = self
-            .key
-            .seal_in_place_separate_tag(nonce, Aad::from(AAD_CONTEXT), &mut in_out)
-            .map_err(|_| anyhow!("failed to encrypt flow"))?;
-        in_out.extend_from_slice(tag.as_ref());
+        let tx = self.conn.unchecked_transaction()?;
+        let id = insert_flow(&tx, &self.cipher, self.compression, flow)?;
+        self.spool_flows(&tx, &[id])?;
+        tx.commit()?;
+        self.writes.borrow_mut().flows(1);
+        Ok(id)
+    }
+
+    /// Stores `flows` in one transaction and returns their row ids in order.
+    /// Use this for ingestion; committing per row caps throughput at a few
+    /// hundred flows per second.
+    pub fn put_flows(&self, flows: &[FlowEvent]) -> Result<Vec<i64>> {
+        let tx = self.conn.unchecked_transaction()?;
+        let ids = flows
+            .iter()
+            .map(|flow| insert_flow(&tx, &self.cipher, self.compression, flow))
+            .collect::<Result<Vec<_>>>()?;
+        self.spool_flows(&tx, &ids)?;
+        tx.commit()?;
+        self.writes.borrow_mut().flows(ids.len());
+        Ok(ids)
+    }
+
+    pub(crate) fn spool_flows(&self, conn: &Connection, ids: &[i64]) -> Result<()> {
+        if self.spool {
+            for id in ids {
+                spool::enqueue(conn, spool::SpoolKind::Flow, &id.to_string())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn spool_alert(&self, conn: &Connection, id: &str) -> Result<()> {
+        if self.spool {
+            spool::enqueue(conn, spool::SpoolKind::Alert, id)?;
+        }
+        Ok(())
+    }
+
+    /// Registers an additional key for reading rows sealed under it, e.g.
+    /// the previous key while a rotation is incomplete.
+    pub fn add_read_key(&mut self, key_bytes: &[u8]) -> Result<()> {
+        let cipher = FlowCipher::new(key_bytes)?;
+        if cipher.key_id() != self.cipher.key_id() {
+            self.retired.insert(cipher.key_id().to_string(), cipher);
+        }
+        Ok(())
+    }
+
+    fn cipher_for(&self, key_id: Option<&str>) -> Result<&FlowCipher> {
+        match key_id {
+            None => Ok(&self.cipher),
+            Some(id) if id == self.cipher.key_id() => Ok(&self.cipher),
+            Some(id) => self
+                .retired
+                .get(id)
+                .ok_or_else(|| anyhow!("flow sealed under unknown key {id}")),
+        }
+    }
+
+    fn open_sealed(&self, id: i64, sealed: SealedRow) -> Result<Vec<u8>, FlowReadError> {
+        let nonce = sealed.nonce.clone();
+        let ciphertext = sealed.ciphertext.ok_or(FlowReadError::MissingPayload(id))?;
+        let cipher =
+            self.cipher_for(sealed.key_id.as_deref())
+                .map_err(|_| FlowReadError::UnknownKey {
+                    id,
+                    key_id: sealed.key_id.clone().unwrap_or_default(),
+                })?;
+        cipher
+            .open(id, sealed.version, sealed.nonce.as_deref(), ciphertext)
+            .map_err(|err| match err {
+                OpenError::Authentication => match self.payload_origin(id, nonce.as_deref()) {
+                    Some(origin) => FlowReadError::Relocated { id, origin },
+                    None => FlowReadError::Tampered { id },
+                },
+                other => FlowReadError::Malformed {
+                    id,
+                    reason: other.to_string(),
+                },
+            })
+    }
+
+    /// Another row with the payload nonce of row `id`. Nonces are random
+    /// per payload, so a shared one means the payload was copied.
+    fn payload_origin(&self, id: i64, nonce: Option<&[u8]>) -> Option<i64> {
+        self.conn
+            .query_row(
+                "SELECT id FROM flows WHERE nonce = ?1 AND id != ?2 LIMIT 1",
+                params![nonce?, id],
+                |row| row.get(0),
+            )
+            .ok()
+    }
+
+    fn decrypt_flow(&self, id: i64, sealed: SealedRow) -> Result<FlowEvent, FlowReadError> {
+        let compression = sealed.compression;
+        let plaintext = decompress(id, compression, self.open_sealed(id, sealed)?)?;
+        serde_json::from_slice(&plaintext).map_err(|err| FlowReadError::Malformed {
+            id,
+            reason: err.to_string(),
+        })
+    }
+
+    /// Decrypts a row, checks its plaintext columns against its MAC and
+    /// then the payload against them.
+    fn verified_flow(
+        &self,
+        meta: &StoredFlow,
+        row_mac: Option<&[u8]>,
+        sealed: SealedRow,
+    ) -> Result<FlowEvent, FlowReadError> {
+        let key_id = sealed.key_id.clone();
+        let nonce = sealed.nonce.clone();
+        let flow = self.decrypt_flow(meta.id, sealed)?;
+        self.verify_row_mac(meta, row_mac, key_id.as_deref(), nonce.as_deref())?;
+        match metadata_mismatch(meta, &flow) {
+            Some(field) => Err(FlowReadError::MetadataMismatch { id: meta.id, field }),
+            None => Ok(flow),
+        }
+    }
+
+    /// Decrypts one stored flow, failing with a [`FlowReadError`] when it is
+    /// missing or fails verification. A row that was tampered with raises a
+    /// tamper alert.
+    pub fn get_flow(&self, id: i64) -> Result<FlowEvent> {
+        let row = self
+            .conn
+            .prepare_cached(&format!(
+                "SELECT {STORED_FLOW_COLUMNS}, row_mac, {} FROM flows WHERE id = ?1",
+                SealedRow::COLUMNS
+            ))?
+            .query_row(params![id], |row| {
+                Ok((
+                    stored_flow(row)?,
+                    row.get::<_, Option<Vec<u8>>>(STORED_FLOW_COLUMN_COUNT)?,
+                    SealedRow::read(row, STORED_FLOW_COLUMN_COUNT + 1)?,
+                ))
+            })
+            .optional()?;
+        let (meta, row_mac, sealed) = row.ok_or(FlowReadError::NotFound(id))?;
+        Ok(self.report_tampering(self.verified_flow(&meta, row_mac.as_deref(), sealed))?)
+    }
+
+    /// Like [`Storage::query_flows`], with each row's decrypted and verified
+    /// `FlowEvent`. Fails on the first row that does not verify.
+    pub fn query_flows_full(&self, query: &FlowQuery) -> Result<Vec<(StoredFlow, FlowEvent)>> {
+        let (clause, mut values) = query.where_clause()?;
+        let order = query.order.sql();
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {STORED_FLOW_COLUMNS}, row_mac, {} FROM flows {clause} ORDER BY ts_first {order}, id {order} LIMIT ? OFFSET ?",
+            SealedRow::COLUMNS
+        ))?;
+        values.push(Value::Integer(query.limit as i64));
+        values.push(Value::Integer(query.offset as i64));
+        let rows = stmt
+            .query_map(params_from_iter(values), |row| {
+                Ok((
+                    stored_flow(row)?,
+                    row.get::<_, Option<Vec<u8>>>(STORED_FLOW_COLUMN_COUNT)?,
+                    SealedRow::read(row, STORED_FLOW_COLUMN_COUNT + 1)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.into_iter()
+            .map(|(meta, row_mac, sealed)| {
+                let flow =
+                    self.report_tampering(self.verified_flow(&meta, row_mac.as_deref(), sealed))?;
+                Ok((meta, flow))
+            })
+            .collect()
+    }
+
+    pub fn put_alert(&self, alert: &Alert) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut stmt = tx.prepare_cached(
+            // An upsert rather than INSERT OR REPLACE keeps the rowid stable
+            // and fires the update trigger that maintains the search index.
+            // Status, assignee and notes are only set on insert: storing the
+            // alert again must not undo its triage.
+            "INSERT INTO alerts (id, ts, severity, rule_id, summary, rationale, status, assignee, notes, evidence, process_ref, suggested_action) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+             ON CONFLICT (id) DO UPDATE SET ts = excluded.ts, severity = excluded.severity, rule_id = excluded.rule_id, summary = excluded.summary, rationale = excluded.rationale, evidence = excluded.evidence, process_ref = excluded.process_ref, suggested_action = excluded.suggested_action",
+        )?;
+        stmt.execute(params![
+            alert.id,
+            alert.ts.to_rfc3339(),
+            alert.severity.as_str(),
+            alert.rule_id,
+            alert.summary,
+            alert.rationale,
+            alert.status.as_str(),
+            alert.assignee,
+            serde_json::to_string(&alert.notes)?,
+            serde_json::to_string(&alert.evidence)?,
+            alert.process_ref,
+            alert.suggested_action,
+        ])?;
+        let mut link = tx.prepare_cached(
+            "INSERT OR IGNORE INTO alert_flows (alert_id, flow_id) VALUES (?1, ?2)",
+        )?;
+        for flow_id in &alert.flow_refs {
+            link.execute(params![alert.id, flow_id])?;
+        }
+        drop((stmt, link));
+        audit::append(
+            &tx,
+            &self.cipher,
+            audit::AUDIT_ALERT_STORED,
+            &alert.id,
+            &serde_json::json!({
+                "ts": alert.ts,
+                "severity": alert.severity,
+                "rule_id": alert.rule_id,
+                "summary": alert.summary,
+            }),
+        )?;
+        self.spool_alert(&tx, &alert.id)?;
+        tx.commit()?;
+        self.writes.borrow_mut().alert();
+        Ok(())
+    }
+
+    /// Moves a stored alert to a new triage state, enforcing the same
+    /// transitions as [`Alert::transition`] and appending to its notes.
+    pub fn set_alert_status(
+        &self,
+        id: &str,
+        status: AlertStatus,
+        actor: Option<&str>,
+        note: Option<&str>,
+    ) -> Result<()> {
+        let (current, _, notes) = self.alert_triage(id)?;
+        let text = current
+            .transition_note(status, note)
+            .map_err(|err| anyhow!("alert {id}: {err}"))?;
+        let notes = push_note(notes, actor, text);
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "UPDATE alerts SET status = ?1, notes = ?2 WHERE id = ?3",
+            params![status.as_str(), serde_json::to_string(&notes)?, id],
+        )?;
+        audit::append(
+            &tx,
+            &self.cipher,
+            audit::AUDIT_ALERT_STATUS,
+            id,
+            &serde_json::json!({ "from": current, "to": status, "actor": actor }),
+        )?;
+        self.spool_alert(&tx, id)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn assign_alert(
+        &self,
+        id: &str,
+        assignee: Option<&str>,
+        actor: Option<&str>,
+    ) -> Result<()> {
+        let (_, _, notes) = self.alert_triage(id)?;
+        let text = match assignee {
+            Some(name) => format!("assigned to {name}"),
+            None => "unassigned".into(),
+        };
+        let notes = push_note(notes, actor, text);
         self.conn.execute(
-            "INSERT INTO flows (ts_first, ts_last, proto, src_ip, dst_ip, src_port, dst_port, bytes, ciphertext) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
-            params![
-                flow.ts_first.to_rfc3339(),
-                flow.ts_last.to_rfc3339(),
-                flow.proto,
-                flow.src_ip,
-                flow.dst_ip,
-                flow.src_port,
-                flow.dst_port,
-                flow.bytes,
-                in_out,
-            ],
+            "UPDATE alerts SET assignee = ?1, notes = ?2 WHERE id = ?3",
+            params![assignee, serde_json::to_string(&notes)?, id],
         )?;
-        Ok(self.conn.last_insert_rowid())
+        Ok(())
     }
 
-    pub fn put_alert(&self, alert: &Alert) -> Result<()> {
+    pub fn add_alert_note(&self, id: &str, actor: Option<&str>, text: &str) -> Result<()> {
+        let (_, _, notes) = self.alert_triage(id)?;
+        let notes = push_note(notes, actor, text.to_string());
         self.conn.execute(
-            "INSERT OR REPLACE INTO alerts (id, ts, severity, rule_id, summary, rationale) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            params![
-                alert.id,
-                alert.ts.to_rfc3339(),
-                format!("{:?}", alert.severity),
-                alert.rule_id,
-                alert.summary,
-                alert.rationale,
-            ],
+            "UPDATE alerts SET notes = ?1 WHERE id = ?2",
+            params![serde_json::to_string(&notes)?, id],
         )?;
         Ok(())
     }
 
-    pub fn query_flows(&self, limit: usize) -> Result<Vec<StoredFlow>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, ts_first, ts_last, proto, src_ip, dst_ip, src_port, dst_port, bytes FROM flows ORDER BY ts_first DESC LIMIT ?1",
+    /// Current status, assignee and notes of a stored alert.
+    pub fn alert_triage(&self, id: &str) -> Result<(AlertStatus, Option<String>, Vec<AlertNote>)> {
+        let row = self
+            .conn
+            .query_row(
+                "SELECT status, assignee, notes FROM alerts WHERE id = ?1",
+                params![id],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        row.get::<_, String>(2)?,
+                    ))
+                },
+            )
+            .optional()?
+            .ok_or_else(|| anyhow!("unknown alert {id}"))?;
+        Ok((row.0.parse()?, row.1, serde_json::from_str(&row.2)?))
+    }
+
+    pub fn get_alert(&self, id: &str) -> Result<Alert> {
+        self.read_alerts(
+            &format!("SELECT {ALERT_COLUMNS} FROM alerts WHERE id = ?"),
+            vec![Value::Text(id.to_string())],
+        )?
+        .pop()
+        .ok_or_else(|| anyhow!("unknown alert {id}"))
+    }
+
+    /// Stored alerts matching `query`, paginated by `limit`/`offset`, with
+    /// their triage state and flow refs.
+    pub fn query_alerts(&self, query: &AlertQuery) -> Result<Vec<Alert>> {
+        let (clause, mut values) = query.where_clause();
+        let order = query.order.sql();
+        values.push(Value::Integer(query.limit as i64));
+        values.push(Value::Integer(query.offset as i64));
+        self.read_alerts(
+            &format!(
+                "SELECT {ALERT_COLUMNS} FROM alerts {clause} ORDER BY ts {order}, id {order} LIMIT ? OFFSET ?"
+            ),
+            values,
+        )
+    }
+
+    /// Runs a query selecting [`ALERT_COLUMNS`] and builds the alerts,
+    /// including their flow refs.
+    pub(crate) fn read_alerts(&self, sql: &str, values: Vec<Value>) -> Result<Vec<Alert>> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let mut refs = self.conn.prepare_cached(
+            "SELECT flow_id FROM alert_flows WHERE alert_id = ?1 ORDER BY flow_id",
         )?;
-        let flows = stmt
-            .query_map(params![limit as i64], |row| {
-                Ok(StoredFlow {
-                    id: row.get(0)?,
-                    ts_first: DateTime::parse_from_rfc3339(row.get::<_, String>(1)?.as_str())
-                        .unwrap()
-                        .with_timezone(&Utc),
-                    ts_last: DateTime::parse_from_rfc3339(row.get::<_, String>(2)?.as_str())
-                        .unwrap()
-                        .with_timezone(&Utc),
-                    proto: row.get(3)?,
-                    src_ip: row.get(4)?,
-                    dst_ip: row.get(5)?,
-                    src_port: row.get(6)?,
-                    dst_port: row.get(7)?,
-                    bytes: row.get(8)?,
-                })
+        let mut rows = stmt.query(params_from_iter(values))?;
+        let mut alerts = Vec::new();
+        while let Some(row) = rows.next()? {
+            let id: String = row.get(0)?;
+            let ts: String = row.get(1)?;
+            let flow_refs = refs
+                .query_map(params![id], |row| row.get(0))?
+                .collect::<Result<Vec<i64>, _>>()?;
+            alerts.push(Alert {
+                ts: DateTime::parse_from_rfc3339(&ts)?.with_timezone(&Utc),
+                severity: row.get::<_, String>(2)?.parse()?,
+                rule_id: row.get(3)?,
+                summary: row.get(4)?,
+                rationale: row.get(5)?,
+                status: row.get::<_, String>(6)?.parse()?,
+                assignee: row.get(7)?,
+                notes: serde_json::from_str(&row.get::<_, String>(8)?)?,
+                evidence: serde_json::from_str(&row.get::<_, String>(9)?)?,
+                process_ref: row.get(10)?,
+                suggested_action: row.get(11)?,
+                flow_refs,
+                id,
+            });
+        }
+        Ok(alerts)
+    }
+
+    /// Number of alerts matching `query`, ignoring `limit`/`offset`.
+    pub fn count_alerts(&self, query: &AlertQuery) -> Result<usize> {
+        let (clause, values) = query.where_clause();
+        let count: i64 = self.conn.query_row(
+            &format!("SELECT COUNT(*) FROM alerts {clause}"),
+            params_from_iter(values),
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+
+    /// Flow metadata matching `query`, paginated by `limit`/`offset`. Rows
+    /// whose columns fail their integrity check come back flagged
+    /// [`StoredFlow::tampered`].
+    pub fn query_flows(&self, query: &FlowQuery) -> Result<Vec<StoredFlow>> {
+        let (clause, mut values) = query.where_clause()?;
+        let order = query.order.sql();
+        values.push(Value::Integer(query.limit as i64));
+        values.push(Value::Integer(query.offset as i64));
+        self.checked_flows(
+            &format!(
+                "SELECT {STORED_FLOW_COLUMNS}, {ROW_MAC_COLUMNS} FROM flows {clause} ORDER BY ts_first {order}, id {order} LIMIT ? OFFSET ?"
+            ),
+            params_from_iter(values),
+        )
+    }
+
+    /// Flows referenced by an alert's `flow_refs`, oldest first.
+    pub fn flows_for_alert(&self, alert_id: &str) -> Result<Vec<StoredFlow>> {
+        self.checked_flows(
+            &format!("SELECT {STORED_FLOW_COLUMNS}, {ROW_MAC_COLUMNS} FROM flows WHERE id IN (SELECT flow_id FROM alert_flows WHERE alert_id = ?1) ORDER BY ts_first, id"),
+            params![alert_id],
+        )
+    }
+}
+
+impl FlowHistory for Storage {
+    fn flows_between(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<(i64, FlowEvent)>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT id, {} FROM flows WHERE ts_first >= ?1 AND ts_first <= ?2 AND ciphertext IS NOT NULL ORDER BY ts_first, id",
+            SealedRow::COLUMNS
+        ))?;
+        let rows = stmt
+            .query_map(params![from.to_rfc3339(), to.to_rfc3339()], |row| {
+                Ok((row.get::<_, i64>(0)?, SealedRow::read(row, 1)?))
             })?
             .collect::<Result<Vec<_>, _>>()?;
-        Ok(flows)
+        rows.into_iter()
+            .map(|(id, sealed)| Ok((id, self.decrypt_flow(id, sealed)?)))
+            .collect()
+    }
+}
+
+/// Raw SQLCipher key derived from the storage key, so the page key differs
+/// from the one sealing flow payloads.
+fn sqlcipher_key(key_bytes: &[u8]) -> String {
+    let mut input = b"nets-sqlcipher".to_vec();
+    input.extend_from_slice(key_bytes);
+    let digest = ring::digest::digest(&ring::digest::SHA256, &input);
+    format!("x'{}'", hex::encode(digest.as_ref()))
+}
+
+/// Plain SQLite silently ignores `PRAGMA key`; refuse to pretend.
+fn ensure_sqlcipher(conn: &Connection) -> Result<()> {
+    let version: Option<String> = conn
+        .query_row("PRAGMA cipher_version", [], |row| row.get(0))
+        .optional()?;
+    match version {
+        Some(_) => Ok(()),
+        None => Err(anyhow!(
+            "SQLCipher backend requested but storage was built without the `sqlcipher` feature"
+        )),
+    }
+}
+
+fn unlock_sqlcipher(conn: &Connection, key_bytes: &[u8]) -> Result<()> {
+    ensure_sqlcipher(conn)?;
+    conn.execute_batch(&format!("PRAGMA key = \"{}\";", sqlcipher_key(key_bytes)))?;
+    conn.query_row("SELECT count(*) FROM sqlite_master", [], |row| {
+        row.get::<_, i64>(0)
+    })
+    .map_err(|_| anyhow!("cannot unlock database: wrong key or not an SQLCipher database"))?;
+    Ok(())
+}
+
+/// Inverse of [`Compression::compress`] for a row's `compression` flag.
+pub(crate) fn decompress(id: i64, flag: i64, plaintext: Vec<u8>) -> Result<Vec<u8>, FlowReadError> {
+    match flag {
+        0 => Ok(plaintext),
+        1 => ZSTD_DECOMPRESSOR
+            .with(|decompressor| {
+                let mut decompressor = decompressor.borrow_mut();
+                if decompressor.is_none() {
+                    *decompressor = Some(zstd::bulk::Decompressor::new()?);
+                }
+                decompressor
+                    .as_mut()
+                    .expect("initialized above")
+                    .decompress(&plaintext, MAX_FLOW_PAYLOAD)
+            })
+            .map_err(|err| FlowReadError::Malformed {
+                id,
+                reason: format!("zstd: {err}"),
+            }),
+        other => Err(FlowReadError::Malformed {
+            id,
+            reason: format!("unknown compression {other}"),
+        }),
+    }
+}
+
+fn insert_flow(
+    conn: &Connection,
+    cipher: &FlowCipher,
+    compression: Compression,
+    flow: &FlowEvent,
+) -> Result<i64> {
+    let columns = StoredFlow::columns(0, flow);
+    conn.prepare_cached(
+        "INSERT INTO flows (ts_first, ts_last, proto, src_ip, dst_ip, src_port, dst_port, bytes, direction, process, compression, domain) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+    )?
+    .execute(params![
+        columns.ts_first.to_rfc3339(),
+        columns.ts_last.to_rfc3339(),
+        columns.proto,
+        columns.src_ip,
+        columns.dst_ip,
+        columns.src_port,
+        columns.dst_port,
+        columns.bytes,
+        columns.direction,
+        columns.process,
+        compression.flag(),
+        columns.domain,
+    ])?;
+    // Sealed once the row has its id, which the payload is bound to.
+    let id = conn.last_insert_rowid();
+    let sealed = cipher.seal(id, &compression.compress(serde_json::to_vec(flow)?)?)?;
+    conn.prepare_cached(
+        "UPDATE flows SET ciphertext = ?1, nonce = ?2, enc_version = ?3, key_id = ?4, row_mac = ?5 WHERE id = ?6",
+    )?
+    .execute(params![
+        sealed.ciphertext,
+        sealed.nonce,
+        sealed.version,
+        sealed.key_id,
+        integrity::row_mac(cipher, &columns, Some(&sealed.nonce)),
+        id,
+    ])?;
+    rollup::record(conn, flow)?;
+    Ok(id)
+}
+
+/// First plaintext column that disagrees with the decrypted payload.
+fn metadata_mismatch(meta: &StoredFlow, flow: &FlowEvent) -> Option<&'static str> {
+    if meta.ts_first != flow.ts_first {
+        Some("ts_first")
+    } else if meta.ts_last != flow.ts_last {
+        Some("ts_last")
+    } else if meta.proto != flow.proto {
+        Some("proto")
+    } else if meta.src_ip != flow.src_ip || meta.src_port != flow.src_port {
+        Some("src")
+    } else if meta.dst_ip != flow.dst_ip || meta.dst_port != flow.dst_port {
+        Some("dst")
+    } else if meta.bytes != flow.bytes {
+        Some("bytes")
+    } else {
+        None
+    }
+}
+
+/// Domain a flow talked to: the TLS SNI, else the DNS query name,
+/// lowercased and without the trailing dot.
+pub(crate) fn flow_domain(flow: &FlowEvent) -> Option<String> {
+    flow.sni
+        .as_deref()
+        .or(flow.dns_qname.as_deref())
+        .map(|name| name.trim_end_matches('.').to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+}
+
+pub(crate) fn timestamp_column(
+    row: &rusqlite::Row<'_>,
+    idx: usize,
+) -> rusqlite::Result<DateTime<Utc>> {
+    let text: String = row.get(idx)?;
+    DateTime::parse_from_rfc3339(&text)
+        .map(|ts| ts.with_timezone(&Utc))
+        .map_err(|err| {
+            rusqlite::Error::FromSqlConversionFailure(
+                idx,
+                rusqlite::types::Type::Text,
+                Box::new(err),
+            )
+        })
+}
+
+pub(crate) fn stored_flow(row: &rusqlite::Row<'_>) -> rusqlite::Result<StoredFlow> {
+    Ok(StoredFlow {
+        id: row.get(0)?,
+        ts_first: timestamp_column(row, 1)?,
+        ts_last: timestamp_column(row, 2)?,
+        proto: row.get(3)?,
+        src_ip: row.get(4)?,
+        dst_ip: row.get(5)?,
+        src_port: row.get(6)?,
+        dst_port: row.get(7)?,
+        bytes: row.get(8)?,
+        direction: row.get(9)?,
+        process: row.get(10)?,
+        domain: row.get(11)?,
+        tampered: false,
+    })
+}
+
+fn push_note(mut notes: Vec<AlertNote>, actor: Option<&str>, text: String) -> Vec<AlertNote> {
+    notes.push(AlertNote {
+        ts: Utc::now(),
+        author: actor.map(str::to_string),
+        text,
+    });
+    notes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use analyzer::Severity;
+
+    use super::*;
+
+    fn alert(id: &str) -> Alert {
+        Alert {
+            id: id.into(),
+            ts: Utc::now(),
+            severity: Severity::Medium,
+            rule_id: "builtin.first_contact".into(),
+            summary: "backup contacted 203.0.113.4".into(),
+            flow_refs: Vec::new(),
+            process_ref: Some("backup".into()),
+            rationale: String::new(),
+            suggested_action: None,
+            status: AlertStatus::New,
+            assignee: None,
+            notes: Vec::new(),
+            evidence: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn storing_an_alert_again_keeps_its_triage() {
+        let storage = Storage::open(":memory:", &[9u8; 32]).unwrap();
+        let mut stored = alert("a1");
+        storage.put_alert(&stored).unwrap();
+        assert_eq!(storage.alert_triage("a1").unwrap().0, AlertStatus::New);
+
+        storage
+            .set_alert_status(
+                "a1",
+                AlertStatus::Resolved,
+                Some("alice"),
+                Some("known job"),
+            )
+            .unwrap();
+        assert!(storage
+            .set_alert_status("a1", AlertStatus::Acknowledged, None, None)
+            .is_err());
+        storage
+            .assign_alert("a1", Some("bob"), Some("alice"))
+            .unwrap();
+        storage
+            .add_alert_note("a1", Some("bob"), "runs nightly")
+            .unwrap();
+        let (status, assignee, notes) = storage.alert_triage("a1").unwrap();
+        assert_eq!(status, AlertStatus::Resolved);
+        assert_eq!(assignee.as_deref(), Some("bob"));
+        let texts: Vec<_> = notes.iter().map(|note| note.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            [
+                "New -> Resolved: known job",
+                "assigned to bob",
+                "runs nightly"
+            ]
+        );
+        assert_eq!(notes[0].author.as_deref(), Some("alice"));
+
+        // The analyzer stores an alert again when it refreshes it.
+        stored.summary = "backup contacted 203.0.113.4 again".into();
+        storage.put_alert(&stored).unwrap();
+        let triage = storage.alert_triage("a1").unwrap();
+        assert_eq!((triage.0, triage.1), (status, assignee));
+        assert_eq!(triage.2.len(), 3);
+        assert_eq!(storage.get_alert("a1").unwrap().summary, stored.summary);
+
+        assert!(storage.alert_triage("a2").is_err());
+        assert!(storage.assign_alert("a2", None, None).is_err());
+    }
+
+    #[test]
+    fn reads_back_the_flows_an_alert_refers_to() {
+        let storage = Storage::open(":memory:", &[9u8; 32]).unwrap();
+        let start = Utc::now() - chrono::Duration::minutes(10);
+        let flows: Vec<_> = (0..3)
+            .map(|minute| FlowEvent {
+                ts_first: start + chrono::Duration::minutes(minute),
+                ts_last: start + chrono::Duration::minutes(minute),
+                dst_ip: format!("203.0.113.{minute}"),
+                dst_port: 443,
+                ..FlowEvent::default()
+            })
+            .collect();
+        let ids = storage.put_flows(&flows).unwrap();
+        let mut stored = alert("a1");
+        stored.flow_refs = vec![ids[2], ids[0]];
+        storage.put_alert(&stored).unwrap();
+
+        let referenced = storage.flows_for_alert("a1").unwrap();
+        let read: Vec<_> = referenced
+            .iter()
+            .map(|flow| (flow.id, flow.dst_ip.as_str(), flow.tampered))
+            .collect();
+        assert_eq!(
+            read,
+            [
+                (ids[0], "203.0.113.0", false),
+                (ids[2], "203.0.113.2", false)
+            ]
+        );
+        assert_eq!(storage.get_alert("a1").unwrap().flow_refs, [ids[0], ids[2]]);
+        assert!(storage.flows_for_alert("a2").unwrap().is_empty());
     }
 }
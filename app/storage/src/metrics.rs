@@ -0,0 +1,233 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write as _,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::Storage;
+
+/// Window the write rate is averaged over.
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+const COUNTED_TABLES: &[&str] = &[
+    "flows",
+    "alerts",
+    "alert_flows",
+    "incidents",
+    "incident_alerts",
+    "policy_actions",
+    "audit_log",
+    "dns_records",
+    "services",
+    "process_activity",
+    "flow_rollups",
+];
+
+/// Oldest and newest timestamp of a table, `None` when it is empty.
+type TimeSpan = (Option<DateTime<Utc>>, Option<DateTime<Utc>>);
+
+/// Writes made through this connection, for the throughput figures.
+#[derive(Debug, Default)]
+pub(crate) struct WriteMeter {
+    flows: u64,
+    alerts: u64,
+    /// Flow batches committed within the last [`RATE_WINDOW`].
+    recent: VecDeque<(Instant, usize)>,
+}
+
+impl WriteMeter {
+    pub(crate) fn flows(&mut self, count: usize) {
+        self.flows += count as u64;
+        let now = Instant::now();
+        self.recent.push_back((now, count));
+        self.expire(now);
+    }
+
+    pub(crate) fn alert(&mut self) {
+        self.alerts += 1;
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((at, _)) = self.recent.front() {
+            if now.duration_since(*at) <= RATE_WINDOW {
+                break;
+            }
+            self.recent.pop_front();
+        }
+    }
+
+    fn snapshot(&mut self) -> WriteThroughput {
+        self.expire(Instant::now());
+        let recent: usize = self.recent.iter().map(|(_, count)| count).sum();
+        WriteThroughput {
+            flows_written: self.flows,
+            alerts_written: self.alerts,
+            flows_per_second: recent as f64 / RATE_WINDOW.as_secs_f64(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WriteThroughput {
+    /// Since this connection was opened.
+    pub flows_written: u64,
+    pub alerts_written: u64,
+    /// Averaged over the last minute.
+    pub flows_per_second: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StorageMetrics {
+    /// Size of the database file, and of the pages holding data.
+    pub database_bytes: u64,
+    pub used_bytes: u64,
+    pub rows: BTreeMap<String, u64>,
+    pub oldest_flow: Option<DateTime<Utc>>,
+    pub newest_flow: Option<DateTime<Utc>>,
+    pub oldest_alert: Option<DateTime<Utc>>,
+    pub newest_alert: Option<DateTime<Utc>>,
+    pub writes: WriteThroughput,
+}
+
+impl StorageMetrics {
+    /// Prometheus text exposition format, e.g. for the node_exporter
+    /// textfile collector.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, f64)]| {
+            let _ = writeln!(out, "# HELP nets_storage_{name} {help}");
+            let _ = writeln!(out, "# TYPE nets_storage_{name} {kind}");
+            for (labels, value) in samples {
+                let _ = writeln!(out, "nets_storage_{name}{labels} {value}");
+            }
+        };
+        metric(
+            "database_bytes",
+            "gauge",
+            "Size of the database file.",
+            &[(String::new(), self.database_bytes as f64)],
+        );
+        metric(
+            "used_bytes",
+            "gauge",
+            "Bytes of the database file holding data.",
+            &[(String::new(), self.used_bytes as f64)],
+        );
+        let rows: Vec<_> = self
+            .rows
+            .iter()
+            .map(|(table, count)| (format!("{{table=\"{table}\"}}"), *count as f64))
+            .collect();
+        metric("rows", "gauge", "Rows per table.", &rows);
+        for (name, help, pairs) in [
+            (
+                "oldest_record_timestamp_seconds",
+                "Time of the oldest stored record.",
+                [("flow", self.oldest_flow), ("alert", self.oldest_alert)],
+            ),
+            (
+                "newest_record_timestamp_seconds",
+                "Time of the newest stored record.",
+                [("flow", self.newest_flow), ("alert", self.newest_alert)],
+            ),
+        ] {
+            let samples: Vec<_> = pairs
+                .iter()
+                .filter_map(|(kind, ts)| {
+                    ts.map(|ts| (format!("{{kind=\"{kind}\"}}"), ts.timestamp() as f64))
+                })
+                .collect();
+            metric(name, "gauge", help, &samples);
+        }
+        metric(
+            "flows_written_total",
+            "counter",
+            "Flows written since the store was opened.",
+            &[(String::new(), self.writes.flows_written as f64)],
+        );
+        metric(
+            "alerts_written_total",
+            "counter",
+            "Alerts written since the store was opened.",
+            &[(String::new(), self.writes.alerts_written as f64)],
+        );
+        metric(
+            "flows_per_second",
+            "gauge",
+            "Flow write rate over the last minute.",
+            &[(String::new(), self.writes.flows_per_second)],
+        );
+        out
+    }
+}
+
+impl Storage {
+    /// Size, row counts, time span of the stored records and write
+    /// throughput. Row counts scan each table's smallest index, so poll this
+    /// every few seconds at most.
+    pub fn metrics(&self) -> Result<StorageMetrics> {
+        let (database_bytes, used_bytes) = self.database_size()?;
+        let mut rows = BTreeMap::new();
+        for table in COUNTED_TABLES {
+            let count: i64 =
+                self.conn
+                    .query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                        row.get(0)
+                    })?;
+            rows.insert(table.to_string(), count as u64);
+        }
+        let span = |sql: &str| -> Result<TimeSpan> {
+            let (oldest, newest): (Option<String>, Option<String>) =
+                self.conn
+                    .query_row(sql, [], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            let parse = |ts: Option<String>| -> Result<Option<DateTime<Utc>>> {
+                Ok(match ts {
+                    Some(ts) => Some(DateTime::parse_from_rfc3339(&ts)?.with_timezone(&Utc)),
+                    None => None,
+                })
+            };
+            Ok((parse(oldest)?, parse(newest)?))
+        };
+        let (oldest_flow, newest_flow) = span("SELECT MIN(ts_first), MAX(ts_first) FROM flows")?;
+        let (oldest_alert, newest_alert) = span("SELECT MIN(ts), MAX(ts) FROM alerts")?;
+        Ok(StorageMetrics {
+            database_bytes,
+            used_bytes,
+            rows,
+            oldest_flow,
+            newest_flow,
+            oldest_alert,
+            newest_alert,
+            writes: self.writes.borrow_mut().snapshot(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use collector::FlowEvent;
+
+    #[test]
+    fn counts_rows_and_writes() {
+        let storage = Storage::open(":memory:", &[5u8; 32]).unwrap();
+        let empty = storage.metrics().unwrap();
+        assert_eq!(empty.rows["flows"], 0);
+        assert!(empty.oldest_flow.is_none());
+
+        let flow = FlowEvent::default();
+        storage.put_flows(&vec![flow.clone(); 4]).unwrap();
+        let metrics = storage.metrics().unwrap();
+        assert_eq!(metrics.rows["flows"], 4);
+        assert_eq!(metrics.newest_flow, Some(flow.ts_first));
+        assert_eq!(metrics.writes.flows_written, 4);
+        assert!(metrics.writes.flows_per_second > 0.0);
+
+        let text = metrics.to_prometheus();
+        assert!(text.contains("nets_storage_rows{table=\"flows\"} 4\n"));
+        assert!(text.contains("# TYPE nets_storage_flows_written_total counter\n"));
+    }
+}
@@ -0,0 +1,809 @@
+use anyhow::{bail, Result};
+use chrono::Utc;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actions, audit, baseline,
+    crypto::{FORMAT_V1, FORMAT_V2},
+    incident, intel, inventory, quarantine, rollup, search, spool, Storage,
+};
+
+/// One schema change. `up` must be idempotent: databases created before
+/// versioning start at version 0 and replay every step over tables and
+/// columns that may already exist, and a step interrupted before its
+/// version is recorded runs again on the next open.
+struct Migration {
+    version: u32,
+    name: &'static str,
+    up: fn(&Storage) -> Result<()>,
+    /// `None` when the step rewrote data that cannot be restored.
+    down: Option<fn(&Storage) -> Result<()>>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "base tables",
+        up: base_tables_up,
+        down: Some(base_tables_down),
+    },
+    Migration {
+        version: 2,
+        name: "alert triage columns",
+        up: alert_triage_up,
+        down: Some(alert_triage_down),
+    },
+    Migration {
+        version: 3,
+        name: "per-record nonces",
+        up: record_nonces_up,
+        down: None,
+    },
+    Migration {
+        version: 4,
+        name: "flow key ids",
+        up: key_ids_up,
+        down: None,
+    },
+    Migration {
+        version: 5,
+        name: "flow query columns",
+        up: flow_query_up,
+        down: Some(flow_query_down),
+    },
+    Migration {
+        version: 6,
+        name: "alert query columns",
+        up: alert_query_up,
+        down: Some(alert_query_down),
+    },
+    Migration {
+        version: 7,
+        name: "flow rollups",
+        up: rollups_up,
+        down: Some(rollups_down),
+    },
+    Migration {
+        version: 8,
+        name: "alert search index",
+        up: search_up,
+        down: Some(search_down),
+    },
+    Migration {
+        version: 9,
+        name: "inventories",
+        up: inventories_up,
+        down: Some(inventories_down),
+    },
+    Migration {
+        version: 10,
+        name: "incidents",
+        up: incidents_up,
+        down: Some(incidents_down),
+    },
+    Migration {
+        version: 11,
+        name: "policy action audit",
+        up: policy_actions_up,
+        down: Some(policy_actions_down),
+    },
+    Migration {
+        version: 12,
+        name: "audit log",
+        up: audit_log_up,
+        down: Some(audit_log_down),
+    },
+    Migration {
+        version: 13,
+        name: "flow payload compression",
+        up: flow_compression_up,
+        down: Some(flow_compression_down),
+    },
+    Migration {
+        version: 14,
+        name: "flow tuple index",
+        up: flow_tuple_index_up,
+        down: Some(flow_tuple_index_down),
+    },
+    Migration {
+        version: 15,
+        name: "port and domain rollups",
+        up: port_domain_rollups_up,
+        down: Some(port_domain_rollups_down),
+    },
+    Migration {
+        version: 16,
+        name: "baseline profiles",
+        up: baseline_up,
+        down: Some(baseline_down),
+    },
+    Migration {
+        version: 17,
+        name: "export spool",
+        up: spool_up,
+        down: Some(spool_down),
+    },
+    Migration {
+        version: 18,
+        name: "flow row MACs",
+        up: row_macs_up,
+        down: Some(row_macs_down),
+    },
+    Migration {
+        version: 19,
+        name: "active quarantines",
+        up: quarantines_up,
+        down: Some(quarantines_down),
+    },
+    Migration {
+        version: 20,
+        name: "policy action commands",
+        up: action_commands_up,
+        down: Some(action_commands_down),
+    },
+    Migration {
+        version: 21,
+        name: "service first seen",
+        up: service_first_seen_up,
+        down: Some(service_first_seen_down),
+    },
+    Migration {
+        version: 22,
+        name: "threat intel feeds",
+        up: intel_feeds_up,
+        down: Some(intel_feeds_down),
+    },
+    Migration {
+        version: 23,
+        name: "row-bound flow payloads",
+        up: row_bound_payloads_up,
+        down: Some(row_bound_payloads_down),
+    },
+];
+
+/// Schema version this build creates and expects.
+pub const LATEST_SCHEMA_VERSION: u32 = MIGRATIONS[MIGRATIONS.len() - 1].version;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MigrationDirection {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationStep {
+    pub version: u32,
+    pub name: String,
+    pub direction: MigrationDirection,
+}
+
+/// Steps taking the schema from `from` to `to`, in the order they run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationPlan {
+    pub from: u32,
+    pub to: u32,
+    pub steps: Vec<MigrationStep>,
+}
+
+impl Storage {
+    /// Highest applied migration; 0 for a new database or one created
+    /// before versioning.
+    pub fn schema_version(&self) -> Result<u32> {
+        let versioned = self
+            .conn
+            .query_row(
+                "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_version'",
+                [],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        if !versioned {
+            return Ok(0);
+        }
+        let version: Option<u32> =
+            self.conn
+                .query_row("SELECT MAX(version) FROM schema_version", [], |row| {
+                    row.get(0)
+                })?;
+        Ok(version.unwrap_or(0))
+    }
+
+    /// Moves the schema to `target`, upgrading or downgrading as needed.
+    /// With `dry_run` nothing is changed and the returned plan lists the
+    /// steps that would run. Downgrading is for handing the file back to
+    /// an older build: reopen it with `skip_migrations` or this build will
+    /// upgrade it again.
+    pub fn migrate_to(&self, target: u32, dry_run: bool) -> Result<MigrationPlan> {
+        if target > LATEST_SCHEMA_VERSION {
+            bail!("schema version {target} is unknown (latest is {LATEST_SCHEMA_VERSION})");
+        }
+        let from = self.schema_version()?;
+        let plan = plan(from, target)?;
+        if dry_run {
+            return Ok(plan);
+        }
+        self.ensure_version_table()?;
+        for step in &plan.steps {
+            let migration = &MIGRATIONS[step.version as usize - 1];
+            tracing::info!(
+                version = step.version,
+                name = step.name,
+                direction = ?step.direction,
+                "applying schema migration"
+            );
+            match step.direction {
+                MigrationDirection::Up => {
+                    (migration.up)(self)?;
+                    self.conn.execute(
+                        "INSERT OR REPLACE INTO schema_version (version, name, applied_at) VALUES (?1, ?2, ?3)",
+                        params![migration.version, migration.name, Utc::now().to_rfc3339()],
+                    )?;
+                }
+                MigrationDirection::Down => {
+                    let down = migration.down.expect("plan only includes reversible steps");
+                    let tx = self.conn.unchecked_transaction()?;
+                    down(self)?;
+                    tx.execute(
+                        "DELETE FROM schema_version WHERE version = ?1",
+                        params![migration.version],
+                    )?;
+                    tx.commit()?;
+                }
+            }
+        }
+        Ok(plan)
+    }
+
+    /// Applies pending migrations on open, refusing files written by a
+    /// newer build.
+    pub(crate) fn upgrade_schema(&self) -> Result<()> {
+        let current = self.schema_version()?;
+        if current > LATEST_SCHEMA_VERSION {
+            bail!(
+                "database schema version {current} is newer than this build supports ({LATEST_SCHEMA_VERSION})"
+            );
+        }
+        self.migrate_to(LATEST_SCHEMA_VERSION, false)?;
+        Ok(())
+    }
+
+    fn ensure_version_table(&self) -> Result<()> {
+        self.conn.execute_batch(
+            r#"
+            CREATE TABLE IF NOT EXISTS schema_version (
+                version INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                applied_at TEXT NOT NULL
+            );
+            "#,
+        )?;
+        Ok(())
+    }
+}
+
+fn plan(from: u32, to: u32) -> Result<MigrationPlan> {
+    let steps = if to >= from {
+        MIGRATIONS
+            .iter()
+            .filter(|m| m.version > from && m.version <= to)
+            .map(|m| step(m, MigrationDirection::Up))
+            .collect()
+    } else {
+        let mut steps = Vec::new();
+        for migration in MIGRATIONS
+            .iter()
+            .rev()
+            .filter(|m| m.version > to && m.version <= from)
+        {
+            if migration.down.is_none() {
+                bail!(
+                    "migration {} ({}) cannot be reverted",
+                    migration.version,
+                    migration.name
+                );
+            }
+            steps.push(step(migration, MigrationDirection::Down));
+        }
+        steps
+    };
+    Ok(MigrationPlan { from, to, steps })
+}
+
+fn step(migration: &Migration, direction: MigrationDirection) -> MigrationStep {
+    MigrationStep {
+        version: migration.version,
+        name: migration.name.to_string(),
+        direction,
+    }
+}
+
+fn base_tables_up(storage: &Storage) -> Result<()> {
+    storage.conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS flows (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            ts_first TEXT NOT NULL,
+            ts_last TEXT NOT NULL,
+            proto TEXT NOT NULL,
+            src_ip TEXT NOT NULL,
+            dst_ip TEXT NOT NULL,
+            src_port INTEGER NOT NULL,
+            dst_port INTEGER NOT NULL,
+            bytes INTEGER NOT NULL,
+            ciphertext BLOB
+        );
+        CREATE TABLE IF NOT EXISTS alerts (
+            id TEXT PRIMARY KEY,
+            ts TEXT NOT NULL,
+            severity TEXT NOT NULL,
+            rule_id TEXT NOT NULL,
+            summary TEXT NOT NULL,
+            rationale TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS flows_ts_first ON flows (ts_first);
+        CREATE TABLE IF NOT EXISTS alert_flows (
+            alert_id TEXT NOT NULL,
+            flow_id INTEGER NOT NULL,
+            PRIMARY KEY (alert_id, flow_id)
+        );
+        "#,
+    )?;
+    Ok(())
+}
+
+fn base_tables_down(storage: &Storage) -> Result<()> {
+    storage.conn.execute_batch(
+        "DROP TABLE IF EXISTS alert_flows; DROP TABLE IF EXISTS alerts; DROP TABLE IF EXISTS flows;",
+    )?;
+    Ok(())
+}
+
+fn alert_triage_up(storage: &Storage) -> Result<()> {
+    storage.ensure_column("alerts", "status", "TEXT NOT NULL DEFAULT 'New'")?;
+    storage.ensure_column("alerts", "assignee", "TEXT")?;
+    storage.ensure_column("alerts", "notes", "TEXT NOT NULL DEFAULT '[]'")?;
+    storage.ensure_column("alerts", "evidence", "TEXT NOT NULL DEFAULT '{}'")?;
+    Ok(())
+}
+
+fn alert_triage_down(storage: &Storage) -> Result<()> {
+    storage.conn.execute_batch(
+        r#"
+        ALTER TABLE alerts DROP COLUMN status;
+        ALTER TABLE alerts DROP COLUMN assignee;
+        ALTER TABLE alerts DROP COLUMN notes;
+        ALTER TABLE alerts DROP COLUMN evidence;
+        "#,
+    )?;
+    Ok(())
+}
+
+fn record_nonces_up(storage: &Storage) -> Result<()> {
+    // Existing rows predate per-record nonces and default to the legacy format.
+    storage.ensure_column("flows", "nonce", "BLOB")?;
+    storage.ensure_column("flows", "enc_version", "INTEGER NOT NULL DEFAULT 0")?;
+    storage.reencrypt_legacy_flows()?;
+    Ok(())
+}
+
+fn key_ids_up(storage: &Storage) -> Result<()> {
+    // NULL means the row predates key ids and is sealed under the key
+    // the database has always been opened with.
+    storage.ensure_column("flows", "key_id", "TEXT")?;
+    storage.conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS storage_meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);",
+    )?;
+    Ok(())
+}
+
+fn flow_query_up(storage: &Storage) -> Result<()> {
+    // Plaintext copies of payload fields so queries can filter on them.
+    storage.ensure_column("flows", "direction", "TEXT")?;
+    storage.ensure_column("flows", "process", "TEXT")?;
+    storage.conn.execute_batch(
+        r#"
+        CREATE INDEX IF NOT EXISTS flows_src_ip ON flows (src_ip, ts_first);
+        CREATE INDEX IF NOT EXISTS flows_dst_ip ON flows (dst_ip, ts_first);
+        CREATE INDEX IF NOT EXISTS flows_dst_port ON flows (dst_port, ts_first);
+        CREATE INDEX IF NOT EXISTS flows_process ON flows (process, ts_first);
+        "#,
+    )?;
+    storage.backfill_flow_metadata()?;
+    Ok(())
+}
+
+fn flow_query_down(storage: &Storage) -> Result<()> {
+    storage.conn.execute_batch(
+        r#"
+        DROP INDEX IF EXISTS flows_src_ip;
+        DROP INDEX IF EXISTS flows_dst_ip;
+        DROP INDEX IF EXISTS flows_dst_port;
+        DROP INDEX IF EXISTS flows_process;
+        ALTER TABLE flows DROP COLUMN direction;
+        ALTER TABLE flows DROP COLUMN process;
+        "#,
+    )?;
+    Ok(())
+}
+
+fn alert_query_up(storage: &Storage) -> Result<()> {
+    storage.ensure_column("alerts", "process_ref", "TEXT")?;
+    storage.ensure_column("alerts", "suggested_action", "TEXT")?;
+    storage.conn.execute_batch(
+        r#"
+        CREATE INDEX IF NOT EXISTS alerts_ts ON alerts (ts);
+        CREATE INDEX IF NOT EXISTS alerts_rule_id ON alerts (rule_id, ts);
+        CREATE INDEX IF NOT EXISTS alerts_status ON alerts (status, ts);
+        "#,
+    )?;
+    Ok(())
+}
+
+fn alert_query_down(storage: &Storage) -> Result<()> {
+    storage.conn.execute_batch(
+        r#"
+        DROP INDEX IF EXISTS alerts_ts;
+        DROP INDEX IF EXISTS alerts_rule_id;
+        DROP INDEX IF EXISTS alerts_status;
+        ALTER TABLE alerts DROP COLUMN process_ref;
+        ALTER TABLE alerts DROP COLUMN suggested_action;
+        "#,
+    )?;
+    Ok(())
+}
+
+fn rollups_up(storage: &Storage) -> Result<()> {
+    rollup::create_tables(&storage.conn)?;
+    storage.ensure_rollups()
+}
+
+fn rollups_down(storage: &Storage) -> Result<()> {
+    storage.conn.execute_batch(
+        "DROP TABLE IF EXISTS flow_rollups; DELETE FROM storage_meta WHERE key = 'rollups_built';",
+    )?;
+    Ok(())
+}
+
+fn search_up(storage: &Storage) -> Result<()> {
+    search::create_index(&storage.conn)
+}
+
+fn search_down(storage: &Storage) -> Result<()> {
+    storage.conn.execute_batch(
+        r#"
+        DROP TRIGGER IF EXISTS alerts_fts_insert;
+        DROP TRIGGER IF EXISTS alerts_fts_delete;
+        DROP TRIGGER IF EXISTS alerts_fts_update;
+        DROP TABLE IF EXISTS alerts_fts;
+        "#,
+    )?;
+    Ok(())
+}
+
+fn inventories_up(storage: &Storage) -> Result<()> {
+    inventory::create_tables(&storage.conn)
+}
+
+fn inventories_down(storage: &Storage) -> Result<()> {
+    inventory::drop_tables(&storage.conn)
+}
+
+fn incidents_up(storage: &Storage) -> Result<()> {
+    incident::create_tables(&storage.conn)
+}
+
+fn incidents_down(storage: &Storage) -> Result<()> {
+    incident::drop_tables(&storage.conn)
+}
+
+fn policy_actions_up(storage: &Storage) -> Result<()> {
+    actions::create_tables(&storage.conn)
+}
+
+fn policy_actions_down(storage: &Storage) -> Result<()> {
+    actions::drop_tables(&storage.conn)
+}
+
+fn audit_log_up(storage: &Storage) -> Result<()> {
+    audit::create_tables(&storage.conn)
+}
+
+fn audit_log_down(storage: &Storage) -> Result<()> {
+    audit::drop_tables(&storage.conn)
+}
+
+fn flow_compression_up(storage: &Storage) -> Result<()> {
+    storage.ensure_column("flows", "compression", "INTEGER NOT NULL DEFAULT 0")
+}
+
+/// Older builds cannot read compressed payloads, so they are rewritten
+/// uncompressed before the column goes.
+fn flow_compression_down(storage: &Storage) -> Result<()> {
+    storage.decompress_flows()?;
+    storage
+        .conn
+        .execute_batch("ALTER TABLE flows DROP COLUMN compression;")?;
+    Ok(())
+}
+
+/// Finds the row a re-observation merges into (`Storage::upsert_flows`).
+fn flow_tuple_index_up(storage: &Storage) -> Result<()> {
+    storage.conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS flows_tuple ON flows (dst_ip, dst_port, src_ip, src_port, proto, ts_last);",
+    )?;
+    Ok(())
+}
+
+fn flow_tuple_index_down(storage: &Storage) -> Result<()> {
+    storage
+        .conn
+        .execute_batch("DROP INDEX IF EXISTS flows_tuple;")?;
+    Ok(())
+}
+
+fn port_domain_rollups_up(storage: &Storage) -> Result<()> {
+    storage.ensure_column("flows", "domain", "TEXT")?;
+    storage.backfill_flow_domains()?;
+    storage.rebuild_rollups()
+}
+
+fn port_domain_rollups_down(storage: &Storage) -> Result<()> {
+    storage.conn.execute_batch(
+        r#"
+        DELETE FROM flow_rollups WHERE dimension IN ('port', 'domain');
+        ALTER TABLE flows DROP COLUMN domain;
+        "#,
+    )?;
+    Ok(())
+}
+
+fn baseline_up(storage: &Storage) -> Result<()> {
+    baseline::create_tables(&storage.conn)
+}
+
+fn baseline_down(storage: &Storage) -> Result<()> {
+    baseline::drop_tables(&storage.conn)
+}
+
+fn spool_up(storage: &Storage) -> Result<()> {
+    spool::create_tables(&storage.conn)
+}
+
+fn spool_down(storage: &Storage) -> Result<()> {
+    spool::drop_tables(&storage.conn)
+}
+
+fn row_macs_up(storage: &Storage) -> Result<()> {
+    // HMAC over the plaintext columns, so editing them without touching
+    // the ciphertext is caught on every read, not only when decrypting.
+    storage.ensure_column("flows", "row_mac", "BLOB")?;
+    storage.backfill_row_macs()
+}
+
+fn row_macs_down(storage: &Storage) -> Result<()> {
+    storage
+        .conn
+        .execute_batch("ALTER TABLE flows DROP COLUMN row_mac;")?;
+    Ok(())
+}
+
+fn quarantines_up(storage: &Storage) -> Result<()> {
+    quarantine::create_tables(&storage.conn)
+}
+
+fn quarantines_down(storage: &Storage) -> Result<()> {
+    quarantine::drop_tables(&storage.conn)
+}
+
+fn action_commands_up(storage: &Storage) -> Result<()> {
+    storage.ensure_column("policy_actions", "commands", "TEXT NOT NULL DEFAULT '[]'")
+}
+
+fn action_commands_down(storage: &Storage) -> Result<()> {
+    storage
+        .conn
+        .execute_batch("ALTER TABLE policy_actions DROP COLUMN commands;")?;
+    Ok(())
+}
+
+fn service_first_seen_up(storage: &Storage) -> Result<()> {
+    // The earliest sighting of services discovered before this step is lost.
+    storage.ensure_column("services", "first_seen", "TEXT")?;
+    storage
+        .conn
+        .execute_batch("UPDATE services SET first_seen = last_seen WHERE first_seen IS NULL;")?;
+    Ok(())
+}
+
+fn service_first_seen_down(storage: &Storage) -> Result<()> {
+    storage
+        .conn
+        .execute_batch("ALTER TABLE services DROP COLUMN first_seen;")?;
+    Ok(())
+}
+
+fn intel_feeds_up(storage: &Storage) -> Result<()> {
+    intel::create_tables(&storage.conn)
+}
+
+fn intel_feeds_down(storage: &Storage) -> Result<()> {
+    intel::drop_tables(&storage.conn)
+}
+
+/// Binds each payload to its row id, format and key id through its
+/// associated data.
+fn row_bound_payloads_up(storage: &Storage) -> Result<()> {
+    storage.reformat_flow_payloads(FORMAT_V2)?;
+    Ok(())
+}
+
+/// Older builds cannot open row-bound payloads.
+fn row_bound_payloads_down(storage: &Storage) -> Result<()> {
+    storage.reformat_flow_payloads(FORMAT_V1)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns(storage: &Storage, table: &str) -> Vec<String> {
+        let mut stmt = storage
+            .conn
+            .prepare(&format!("PRAGMA table_info({table})"))
+            .unwrap();
+        let names = stmt
+            .query_map([], |row| row.get::<_, String>(1))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        names
+    }
+
+    fn enc_version(storage: &Storage, id: i64) -> i64 {
+        storage
+            .conn
+            .query_row("SELECT enc_version FROM flows WHERE id = ?1", [id], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn plans_applies_and_reverts_in_order() {
+        let storage = Storage::open(":memory:", &[4u8; 32]).unwrap();
+        assert_eq!(storage.schema_version().unwrap(), LATEST_SCHEMA_VERSION);
+        // Replaying every step over the current schema is a no-op.
+        storage
+            .conn
+            .execute_batch("DELETE FROM schema_version")
+            .unwrap();
+        storage.upgrade_schema().unwrap();
+        assert_eq!(storage.schema_version().unwrap(), LATEST_SCHEMA_VERSION);
+
+        let dry = storage.migrate_to(5, true).unwrap();
+        let versions: Vec<u32> = dry.steps.iter().map(|step| step.version).collect();
+        assert_eq!(
+            versions,
+            (6..=LATEST_SCHEMA_VERSION).rev().collect::<Vec<_>>()
+        );
+        assert!(dry
+            .steps
+            .iter()
+            .all(|step| step.direction == MigrationDirection::Down));
+        assert_eq!(storage.schema_version().unwrap(), LATEST_SCHEMA_VERSION);
+        assert!(columns(&storage, "alerts").contains(&"process_ref".to_string()));
+
+        // Compressed payloads are rewritten uncompressed on the way down.
+        let id = storage.put_flow(&collector::FlowEvent::default()).unwrap();
+        storage.migrate_to(5, false).unwrap();
+        assert_eq!(storage.schema_version().unwrap(), 5);
+        assert_eq!(enc_version(&storage, id), FORMAT_V1);
+        assert!(!columns(&storage, "flows").contains(&"compression".to_string()));
+        assert!(!columns(&storage, "alerts").contains(&"process_ref".to_string()));
+        let fts: Option<i64> = storage
+            .conn
+            .query_row(
+                "SELECT 1 FROM sqlite_master WHERE name = 'alerts_fts'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()
+            .unwrap();
+        assert!(fts.is_none());
+
+        assert!(storage.migrate_to(2, true).is_err());
+        let up = storage.migrate_to(LATEST_SCHEMA_VERSION, false).unwrap();
+        assert_eq!(up.steps.len(), dry.steps.len());
+        assert!(columns(&storage, "alerts").contains(&"process_ref".to_string()));
+        assert!(storage.get_flow(id).is_ok());
+        assert_eq!(enc_version(&storage, id), FORMAT_V2);
+    }
+
+    #[test]
+    fn reencrypts_legacy_rows_on_open() {
+        use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+
+        let key = [6u8; 32];
+        let path = std::env::temp_dir().join(format!("nets-legacy-{}.db", std::process::id()));
+        let flow = collector::FlowEvent {
+            dst_ip: "203.0.113.7".into(),
+            dst_port: 8443,
+            ..collector::FlowEvent::default()
+        };
+        {
+            // Flows as stored before versioning: one shared all-zero nonce
+            // and no format, nonce or key id columns.
+            let conn = rusqlite::Connection::open(&path).unwrap();
+            conn.execute_batch(
+                r#"
+                CREATE TABLE flows (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    ts_first TEXT NOT NULL,
+                    ts_last TEXT NOT NULL,
+                    proto TEXT NOT NULL,
+                    src_ip TEXT NOT NULL,
+                    dst_ip TEXT NOT NULL,
+                    src_port INTEGER NOT NULL,
+                    dst_port INTEGER NOT NULL,
+                    bytes INTEGER NOT NULL,
+                    ciphertext BLOB
+                );
+                "#,
+            )
+            .unwrap();
+            let sealing = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &key).unwrap());
+            for _ in 0..2 {
+                let mut blob = serde_json::to_vec(&flow).unwrap();
+                sealing
+                    .seal_in_place_append_tag(
+                        Nonce::assume_unique_for_key([0; NONCE_LEN]),
+                        Aad::from(crate::crypto::AAD_CONTEXT),
+                        &mut blob,
+                    )
+                    .unwrap();
+                conn.execute(
+                    "INSERT INTO flows (ts_first, ts_last, proto, src_ip, dst_ip, src_port, dst_port, bytes, ciphertext) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                    params![
+                        flow.ts_first.to_rfc3339(),
+                        flow.ts_last.to_rfc3339(),
+                        flow.proto,
+                        flow.src_ip,
+                        flow.dst_ip,
+                        flow.src_port,
+                        flow.dst_port,
+                        flow.bytes,
+                        blob
+                    ],
+                )
+                .unwrap();
+            }
+        }
+
+        let storage = Storage::open(&path, &key).unwrap();
+        let rows: Vec<(i64, i64, Vec<u8>, bool)> = storage
+            .conn
+            .prepare("SELECT id, enc_version, nonce, row_mac IS NOT NULL FROM flows ORDER BY id")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_ne!(rows[0].2, rows[1].2);
+        for (id, version, nonce, signed) in rows {
+            assert_eq!(version, FORMAT_V2);
+            assert_ne!(nonce, [0; NONCE_LEN]);
+            assert!(signed);
+            // Reading verifies the row MAC as well as the payload.
+            assert_eq!(storage.get_flow(id).unwrap().dst_ip, "203.0.113.7");
+        }
+        drop(storage);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,456 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use analyzer::Alert;
+use anyhow::{anyhow, Context, Result};
+use chrono::{Duration, NaiveDate, Utc};
+use collector::FlowEvent;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    check::{CheckOptions, CheckReport},
+    query::{AlertQuery, FlowQuery, SortOrder},
+    retention::RetentionConfig,
+    Storage, StorageOptions, StoredFlow,
+};
+
+const MANIFEST_FILE: &str = "manifest.json";
+const MANIFEST_VERSION: u32 = 1;
+
+/// One day's database file, relative to the partition directory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartitionEntry {
+    pub day: NaiveDate,
+    pub file: String,
+}
+
+/// Lists the partitions that belong to the store. A file in the directory
+/// but not in the manifest is ignored, so the manifest is written before a
+/// new file is created and before an old one is deleted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub partitions: Vec<PartitionEntry>,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            partitions: Vec::new(),
+        }
+    }
+}
+
+impl Manifest {
+    fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(MANIFEST_FILE);
+        match fs::read(&path) {
+            Ok(bytes) => {
+                let manifest: Manifest = serde_json::from_slice(&bytes)
+                    .with_context(|| format!("reading {}", path.display()))?;
+                if manifest.version > MANIFEST_VERSION {
+                    return Err(anyhow!(
+                        "partition manifest version {} is newer than this build supports ({MANIFEST_VERSION})",
+                        manifest.version
+                    ));
+                }
+                Ok(manifest)
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_context(|| format!("reading {}", path.display())),
+        }
+    }
+
+    /// Replaces the manifest atomically (write to a temporary file, rename).
+    fn save(&self, dir: &Path) -> Result<()> {
+        let tmp = dir.join(format!("{MANIFEST_FILE}.tmp"));
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&tmp, dir.join(MANIFEST_FILE))?;
+        Ok(())
+    }
+}
+
+/// Flows and alerts split into one SQLite file per UTC day: flows by
+/// `ts_first`, alerts by `ts`. Dropping a day is a file delete, and a
+/// damaged file costs that day only: partitions that fail to open are
+/// listed in [`PartitionedStorage::unavailable`] and left out of queries.
+///
+/// Flow ids are local to their partition; the partition of a stored flow
+/// is the UTC date of its `ts_first`.
+pub struct PartitionedStorage {
+    dir: PathBuf,
+    key: Vec<u8>,
+    options: StorageOptions,
+    manifest: Manifest,
+    partitions: BTreeMap<NaiveDate, Storage>,
+    unavailable: BTreeMap<NaiveDate, String>,
+}
+
+impl PartitionedStorage {
+    /// Opens every partition listed in `dir/manifest.json`, creating the
+    /// directory and an empty manifest on first use.
+    pub fn open<P: AsRef<Path>>(dir: P, key_bytes: &[u8], options: StorageOptions) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let manifest = Manifest::load(&dir)?;
+        let mut store = Self {
+            dir,
+            key: key_bytes.to_vec(),
+            options,
+            manifest: Manifest::default(),
+            partitions: BTreeMap::new(),
+            unavailable: BTreeMap::new(),
+        };
+        for entry in &manifest.partitions {
+            store.open_partition(entry);
+        }
+        store.manifest = manifest;
+        Ok(store)
+    }
+
+    fn open_partition(&mut self, entry: &PartitionEntry) {
+        match Storage::open_with_options(self.dir.join(&entry.file), &self.key, self.options) {
+            Ok(storage) => {
+                self.partitions.insert(entry.day, storage);
+            }
+            Err(err) => {
+                tracing::error!(day = %entry.day, file = entry.file, error = %format!("{err:#}"), "cannot open partition");
+                self.unavailable.insert(entry.day, format!("{err:#}"));
+            }
+        }
+    }
+
+    /// Days with a partition, oldest first, including unavailable ones.
+    pub fn days(&self) -> Vec<NaiveDate> {
+        self.manifest
+            .partitions
+            .iter()
+            .map(|entry| entry.day)
+            .collect()
+    }
+
+    /// Partitions that could not be opened, with the reason.
+    pub fn unavailable(&self) -> &BTreeMap<NaiveDate, String> {
+        &self.unavailable
+    }
+
+    /// The open partition for `day`, for calls the partitioned store does
+    /// not route itself (alert triage, inventory, audit).
+    pub fn partition(&self, day: NaiveDate) -> Option<&Storage> {
+        self.partitions.get(&day)
+    }
+
+    /// The partition for `day`, created and added to the manifest if needed.
+    pub fn partition_for_write(&mut self, day: NaiveDate) -> Result<&Storage> {
+        if let Some(reason) = self.unavailable.get(&day) {
+            return Err(anyhow!("partition {day} is unavailable: {reason}"));
+        }
+        if !self.partitions.contains_key(&day) {
+            let entry = PartitionEntry {
+                day,
+                file: format!("nets-{}.db", day.format("%Y-%m-%d")),
+            };
+            let mut manifest = self.manifest.clone();
+            manifest.partitions.push(entry.clone());
+            manifest.partitions.sort_by_key(|entry| entry.day);
+            manifest.save(&self.dir)?;
+            self.manifest = manifest;
+            let storage =
+                Storage::open_with_options(self.dir.join(&entry.file), &self.key, self.options)?;
+            self.partitions.insert(day, storage);
+        }
+        Ok(&self.partitions[&day])
+    }
+
+    /// Stores flows in the partitions of their `ts_first` days. Returns the
+    /// partition-local ids in input order.
+    pub fn put_flows(&mut self, flows: &[FlowEvent]) -> Result<Vec<i64>> {
+        self.store_by_day(flows, |storage, batch| storage.put_flows(batch))
+    }
+
+    /// [`Storage::upsert_flows`] per day; re-observations only merge into
+    /// flows of the partition their `ts_first` falls in.
+    pub fn upsert_flows(&mut self, flows: &[FlowEvent], window: Duration) -> Result<Vec<i64>> {
+        self.store_by_day(flows, |storage, batch| storage.upsert_flows(batch, window))
+    }
+
+    fn store_by_day(
+        &mut self,
+        flows: &[FlowEvent],
+        store: impl Fn(&Storage, &[FlowEvent]) -> Result<Vec<i64>>,
+    ) -> Result<Vec<i64>> {
+        let mut by_day: BTreeMap<NaiveDate, Vec<usize>> = BTreeMap::new();
+        for (index, flow) in flows.iter().enumerate() {
+            by_day
+                .entry(flow.ts_first.date_naive())
+                .or_default()
+                .push(index);
+        }
+        let mut ids = vec![0; flows.len()];
+        for (day, indices) in by_day {
+            let batch: Vec<FlowEvent> = indices.iter().map(|&i| flows[i].clone()).collect();
+            let stored = store(self.partition_for_write(day)?, &batch)?;
+            for (index, id) in indices.into_iter().zip(stored) {
+                ids[index] = id;
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Stores the alert in the partition of its day; `flow_refs` should
+    /// point at flows of the same day.
+    pub fn put_alert(&mut self, alert: &Alert) -> Result<()> {
+        self.partition_for_write(alert.ts.date_naive())?
+            .put_alert(alert)
+    }
+
+    pub fn get_flow(&self, day: NaiveDate, id: i64) -> Result<FlowEvent> {
+        self.partitions
+            .get(&day)
+            .ok_or_else(|| anyhow!("no open partition for {day}"))?
+            .get_flow(id)
+    }
+
+    /// Open partitions whose day may hold rows in `[from, to]`, in the
+    /// order rows are returned.
+    fn overlapping(
+        &self,
+        from: Option<chrono::DateTime<Utc>>,
+        to: Option<chrono::DateTime<Utc>>,
+        order: SortOrder,
+    ) -> Vec<(NaiveDate, &Storage)> {
+        let from = from.map(|ts| ts.date_naive()).unwrap_or(NaiveDate::MIN);
+        let to = to.map(|ts| ts.date_naive()).unwrap_or(NaiveDate::MAX);
+        let mut days: Vec<_> = self
+            .partitions
+            .range(from..=to)
+            .map(|(day, storage)| (*day, storage))
+            .collect();
+        if order == SortOrder::NewestFirst {
+            days.reverse();
+        }
+        days
+    }
+
+    /// Same as [`Storage::query_flows`] across partitions. Days are disjoint,
+    /// so partitions are read in order and the scan stops once `limit` rows
+    /// are collected.
+    pub fn query_flows(&self, query: &FlowQuery) -> Result<Vec<StoredFlow>> {
+        paged(
+            self.overlapping(query.from, query.to, query.order),
+            query.offset,
+            query.limit,
+            |storage, offset, limit| {
+                storage.query_flows(&FlowQuery {
+                    offset,
+                    limit,
+                    ..query.clone()
+                })
+            },
+        )
+    }
+
+    /// Same as [`Storage::query_alerts`] across partitions.
+    pub fn query_alerts(&self, query: &AlertQuery) -> Result<Vec<Alert>> {
+        paged(
+            self.overlapping(query.from, query.to, query.order),
+            query.offset,
+            query.limit,
+            |storage, offset, limit| {
+                storage.query_alerts(&AlertQuery {
+                    offset,
+                    limit,
+                    ..query.clone()
+                })
+            },
+        )
+    }
+
+    pub fn count_alerts(&self, query: &AlertQuery) -> Result<usize> {
+        let mut total = 0;
+        for (_, storage) in self.overlapping(query.from, query.to, query.order) {
+            total += storage.count_alerts(query)?;
+        }
+        Ok(total)
+    }
+
+    /// Deletes the partitions of days before `cutoff`. Everything in them
+    /// goes, open alerts included.
+    pub fn drop_before(&mut self, cutoff: NaiveDate) -> Result<Vec<NaiveDate>> {
+        let days: Vec<_> = self
+            .manifest
+            .partitions
+            .iter()
+            .map(|entry| entry.day)
+            .filter(|day| *day < cutoff)
+            .collect();
+        for day in &days {
+            self.drop_partition(*day)?;
+        }
+        Ok(days)
+    }
+
+    /// Removes the day from the manifest, then deletes its files.
+    pub fn drop_partition(&mut self, day: NaiveDate) -> Result<bool> {
+        let Some(position) = self
+            .manifest
+            .partitions
+            .iter()
+            .position(|entry| entry.day == day)
+        else {
+            return Ok(false);
+        };
+        let mut manifest = self.manifest.clone();
+        let entry = manifest.partitions.remove(position);
+        manifest.save(&self.dir)?;
+        self.manifest = manifest;
+        self.partitions.remove(&day);
+        self.unavailable.remove(&day);
+        let path = self.dir.join(&entry.file);
+        for suffix in ["", "-wal", "-shm"] {
+            let mut file = path.clone().into_os_string();
+            file.push(suffix);
+            match fs::remove_file(&file) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(err).with_context(|| format!("deleting {file:?}")),
+            }
+        }
+        tracing::info!(%day, "dropped partition");
+        Ok(true)
+    }
+
+    /// Applies `retention_days` and `max_size_mb` by dropping whole days,
+    /// oldest first; today's partition is never dropped for size.
+    /// `keep_open_alerts` does not apply.
+    pub fn prune(&mut self, config: &RetentionConfig) -> Result<Vec<NaiveDate>> {
+        let today = Utc::now().date_naive();
+        let mut dropped = Vec::new();
+        if let Some(days) = config.retention_days {
+            dropped.extend(self.drop_before(today - Duration::days(i64::from(days)))?);
+        }
+        if let Some(max_mb) = config.max_size_mb {
+            let limit = max_mb * 1024 * 1024;
+            while self.size_on_disk()? > limit {
+                match self.days().first() {
+                    Some(&oldest) if oldest < today => {
+                        self.drop_partition(oldest)?;
+                        dropped.push(oldest);
+                    }
+                    _ => break,
+                }
+            }
+        }
+        Ok(dropped)
+    }
+
+    /// Total size of the partition files, write-ahead logs included.
+    pub fn size_on_disk(&self) -> Result<u64> {
+        let mut total = 0;
+        for entry in &self.manifest.partitions {
+            let path = self.dir.join(&entry.file);
+            for suffix in ["", "-wal"] {
+                let mut file = path.clone().into_os_string();
+                file.push(suffix);
+                match fs::metadata(&file) {
+                    Ok(meta) => total += meta.len(),
+                    Err(err) if err.kind() == ErrorKind::NotFound => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        }
+        Ok(total)
+    }
+
+    /// Runs [`Storage::check`] on every open partition.
+    pub fn check(&self, options: &CheckOptions) -> Result<Vec<(NaiveDate, CheckReport)>> {
+        self.partitions
+            .iter()
+            .map(|(day, storage)| Ok((*day, storage.check(options)?)))
+            .collect()
+    }
+}
+
+/// Applies a global `offset`/`limit` over partitions read in order, asking
+/// each for at most the rows still needed.
+fn paged<T>(
+    partitions: Vec<(NaiveDate, &Storage)>,
+    mut offset: usize,
+    limit: usize,
+    mut read: impl FnMut(&Storage, usize, usize) -> Result<Vec<T>>,
+) -> Result<Vec<T>> {
+    let mut rows = Vec::new();
+    for (_, storage) in partitions {
+        if rows.len() >= limit {
+            break;
+        }
+        let wanted = offset + (limit - rows.len());
+        let mut batch = read(storage, 0, wanted)?;
+        if batch.len() <= offset {
+            offset -= batch.len();
+            continue;
+        }
+        rows.extend(batch.drain(offset..));
+        offset = 0;
+    }
+    rows.truncate(limit);
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn queries_span_days_and_pruning_deletes_files() {
+        let dir = std::env::temp_dir().join(format!(
+            "nets-partitions-{}-{}",
+            std::process::id(),
+            Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        let mut store =
+            PartitionedStorage::open(&dir, &[7u8; 32], StorageOptions::default()).unwrap();
+        let flows: Vec<FlowEvent> = (1..=3)
+            .flat_map(|day| {
+                (0..2).map(move |hour| FlowEvent {
+                    ts_first: Utc.with_ymd_and_hms(2026, 3, day, hour, 0, 0).unwrap(),
+                    ..FlowEvent::default()
+                })
+            })
+            .collect();
+        store.put_flows(&flows).unwrap();
+        assert_eq!(store.days().len(), 3);
+
+        let page = store
+            .query_flows(&FlowQuery {
+                offset: 1,
+                limit: 3,
+                ..FlowQuery::default()
+            })
+            .unwrap();
+        let times: Vec<_> = page.iter().map(|flow| flow.ts_first).collect();
+        assert_eq!(
+            times,
+            [flows[4].ts_first, flows[3].ts_first, flows[2].ts_first]
+        );
+        let day = times[0].date_naive();
+        assert!(store.get_flow(day, page[0].id).is_ok());
+
+        drop(store);
+        let mut store =
+            PartitionedStorage::open(&dir, &[7u8; 32], StorageOptions::default()).unwrap();
+        let dropped = store
+            .drop_before(NaiveDate::from_ymd_opt(2026, 3, 3).unwrap())
+            .unwrap();
+        assert_eq!(dropped.len(), 2);
+        assert!(!dir.join("nets-2026-03-01.db").exists());
+        assert_eq!(store.query_flows(&FlowQuery::default()).unwrap().len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
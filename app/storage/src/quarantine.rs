@@ -0,0 +1,154 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::{timestamp_column, Storage};
+
+/// A quarantine in force, kept so a restarted daemon can re-apply it or
+/// lift its rules instead of leaving them orphaned. Like
+/// [`crate::PolicyActionRecord`], the quarantine is the JSON the policy
+/// crate serialized.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuarantineRecord {
+    pub id: String,
+    /// Backend that enforced it, e.g. `nftables`.
+    pub backend: String,
+    pub quarantine: serde_json::Value,
+    /// Handles of the firewall rules the backend installed for it.
+    pub rules: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+}
+
+const QUARANTINE_COLUMNS: &str = "id, backend, quarantine, rules, expires_at, updated_at";
+
+pub(crate) fn create_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS active_quarantines (
+            id TEXT PRIMARY KEY,
+            backend TEXT NOT NULL,
+            quarantine TEXT NOT NULL,
+            rules TEXT NOT NULL,
+            expires_at TEXT,
+            updated_at TEXT NOT NULL
+        );
+        "#,
+    )?;
+    Ok(())
+}
+
+pub(crate) fn drop_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch("DROP TABLE IF EXISTS active_quarantines;")?;
+    Ok(())
+}
+
+fn quarantine_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<(QuarantineRecord, String, String)> {
+    let expires_at = match row.get_ref(4)? {
+        rusqlite::types::ValueRef::Null => None,
+        _ => Some(timestamp_column(row, 4)?),
+    };
+    Ok((
+        QuarantineRecord {
+            id: row.get(0)?,
+            backend: row.get(1)?,
+            quarantine: serde_json::Value::Null,
+            rules: Vec::new(),
+            expires_at,
+            updated_at: timestamp_column(row, 5)?,
+        },
+        row.get(2)?,
+        row.get(3)?,
+    ))
+}
+
+fn finish_quarantine(
+    (mut record, quarantine, rules): (QuarantineRecord, String, String),
+) -> Result<QuarantineRecord> {
+    record.quarantine = serde_json::from_str(&quarantine)?;
+    record.rules = serde_json::from_str(&rules)?;
+    Ok(record)
+}
+
+impl Storage {
+    /// Inserts or replaces the quarantine with `record.id`.
+    pub fn put_quarantine(&self, record: &QuarantineRecord) -> Result<()> {
+        self.conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO active_quarantines (id, backend, quarantine, rules, expires_at, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?
+            .execute(params![
+                record.id,
+                record.backend,
+                serde_json::to_string(&record.quarantine)?,
+                serde_json::to_string(&record.rules)?,
+                record.expires_at.map(|ts| ts.to_rfc3339()),
+                record.updated_at.to_rfc3339(),
+            ])?;
+        Ok(())
+    }
+
+    pub fn get_quarantine(&self, id: &str) -> Result<Option<QuarantineRecord>> {
+        self.conn
+            .prepare_cached(&format!(
+                "SELECT {QUARANTINE_COLUMNS} FROM active_quarantines WHERE id = ?1"
+            ))?
+            .query_row(params![id], quarantine_row)
+            .optional()?
+            .map(finish_quarantine)
+            .transpose()
+    }
+
+    /// Every quarantine in force, by id (oldest first for UUIDv7 ids).
+    pub fn list_quarantines(&self) -> Result<Vec<QuarantineRecord>> {
+        let rows = self
+            .conn
+            .prepare_cached(&format!(
+                "SELECT {QUARANTINE_COLUMNS} FROM active_quarantines ORDER BY id"
+            ))?
+            .query_map([], quarantine_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.into_iter().map(finish_quarantine).collect()
+    }
+
+    /// Forgets a lifted quarantine; `false` if it was not stored.
+    pub fn delete_quarantine(&self, id: &str) -> Result<bool> {
+        Ok(self
+            .conn
+            .execute("DELETE FROM active_quarantines WHERE id = ?1", params![id])?
+            > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_replaces_and_deletes_quarantines() {
+        let storage = Storage::open(":memory:", &[3u8; 32]).unwrap();
+        let mut record = QuarantineRecord {
+            id: "q1".into(),
+            backend: "nftables".into(),
+            quarantine: serde_json::json!({"id": "q1", "decision": {"ports": [445]}}),
+            rules: vec!["nets:tcp/445".into(), "nets:udp/445".into()],
+            expires_at: None,
+            updated_at: Utc::now(),
+        };
+        storage.put_quarantine(&record).unwrap();
+        record.expires_at = Some(Utc::now());
+        storage.put_quarantine(&record).unwrap();
+
+        let stored = storage.list_quarantines().unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].rules, record.rules);
+        assert_eq!(stored[0].quarantine["decision"]["ports"][0], 445);
+        assert!(stored[0].expires_at.is_some());
+
+        assert!(storage.delete_quarantine("q1").unwrap());
+        assert!(!storage.delete_quarantine("q1").unwrap());
+        assert!(storage.get_quarantine("q1").unwrap().is_none());
+    }
+}
@@ -0,0 +1,329 @@
+use std::net::IpAddr;
+
+use analyzer::{AlertStatus, Severity};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use collector::FlowDirection;
+use rusqlite::types::Value;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortOrder {
+    #[default]
+    NewestFirst,
+    OldestFirst,
+}
+
+impl SortOrder {
+    pub(crate) fn sql(self) -> &'static str {
+        match self {
+            SortOrder::NewestFirst => "DESC",
+            SortOrder::OldestFirst => "ASC",
+        }
+    }
+}
+
+/// Filters for [`crate::Storage::query_flows`]; unset fields match everything.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FlowQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// Exact address or CIDR block (`10.0.0.0/8`, `fe80::/10`).
+    pub src: Option<String>,
+    pub dst: Option<String>,
+    /// Either endpoint, given like `src` and `dst`.
+    pub host: Option<String>,
+    pub src_ports: Vec<u16>,
+    pub dst_ports: Vec<u16>,
+    /// Either port.
+    pub ports: Vec<u16>,
+    pub proto: Option<String>,
+    /// Exact name, or a prefix when it ends with `*` (`notesync*`).
+    pub process: Option<String>,
+    pub direction: Option<FlowDirection>,
+    pub limit: usize,
+    pub offset: usize,
+    pub order: SortOrder,
+}
+
+impl Default for FlowQuery {
+    fn default() -> Self {
+        Self {
+            from: None,
+            to: None,
+            src: None,
+            dst: None,
+            host: None,
+            src_ports: Vec::new(),
+            dst_ports: Vec::new(),
+            ports: Vec::new(),
+            proto: None,
+            process: None,
+            direction: None,
+            limit: 100,
+            offset: 0,
+            order: SortOrder::NewestFirst,
+        }
+    }
+}
+
+impl FlowQuery {
+    /// `WHERE` clause (possibly empty) and its positional parameters.
+    pub(crate) fn where_clause(&self) -> Result<(String, Vec<Value>)> {
+        let mut clauses = Vec::new();
+        let mut params = Vec::new();
+        if let Some(from) = self.from {
+            clauses.push("ts_first >= ?".to_string());
+            params.push(Value::Text(from.to_rfc3339()));
+        }
+        if let Some(to) = self.to {
+            clauses.push("ts_first <= ?".to_string());
+            params.push(Value::Text(to.to_rfc3339()));
+        }
+        for (column, filter) in [("src_ip", &self.src), ("dst_ip", &self.dst)] {
+            let Some(filter) = filter else {
+                continue;
+            };
+            clauses.push(address_clause(column, filter)?);
+            params.push(Value::Text(filter.clone()));
+        }
+        if let Some(host) = &self.host {
+            clauses.push(format!(
+                "({} OR {})",
+                address_clause("src_ip", host)?,
+                address_clause("dst_ip", host)?
+            ));
+            params.extend([Value::Text(host.clone()), Value::Text(host.clone())]);
+        }
+        for (column, ports) in [("src_port", &self.src_ports), ("dst_port", &self.dst_ports)] {
+            if ports.is_empty() {
+                continue;
+            }
+            clauses.push(format!("{column} IN ({})", placeholders(ports.len())));
+            params.extend(ports.iter().map(|port| Value::Integer(*port as i64)));
+        }
+        if !self.ports.is_empty() {
+            let list = placeholders(self.ports.len());
+            clauses.push(format!("(src_port IN ({list}) OR dst_port IN ({list}))"));
+            for _ in 0..2 {
+                params.extend(self.ports.iter().map(|port| Value::Integer(*port as i64)));
+            }
+        }
+        if let Some(proto) = &self.proto {
+            clauses.push("proto = ? COLLATE NOCASE".to_string());
+            params.push(Value::Text(proto.clone()));
+        }
+        if let Some(process) = &self.process {
+            match process.strip_suffix('*') {
+                Some(prefix) => {
+                    clauses.push("process LIKE ? ESCAPE '\\'".to_string());
+                    params.push(Value::Text(format!("{}%", escape_like(prefix))));
+                }
+                None => {
+                    clauses.push("process = ? COLLATE NOCASE".to_string());
+                    params.push(Value::Text(process.clone()));
+                }
+            }
+        }
+        if let Some(direction) = &self.direction {
+            clauses.push("direction = ?".to_string());
+            params.push(Value::Text(format!("{direction:?}")));
+        }
+        Ok((join_clauses(clauses), params))
+    }
+}
+
+/// Filters for [`crate::Storage::query_alerts`]; unset fields match everything.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlertQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// Any of these severities; all when empty.
+    pub severities: Vec<Severity>,
+    /// Any of these triage states; all when empty.
+    pub statuses: Vec<AlertStatus>,
+    /// Exact rule id, or a prefix when it ends with `*` (`lateral.*`).
+    pub rule_id: Option<String>,
+    /// Case-insensitive substring of the summary, rationale or rule id.
+    pub text: Option<String>,
+    pub limit: usize,
+    pub offset: usize,
+    pub order: SortOrder,
+}
+
+impl Default for AlertQuery {
+    fn default() -> Self {
+        Self {
+            from: None,
+            to: None,
+            severities: Vec::new(),
+            statuses: Vec::new(),
+            rule_id: None,
+            text: None,
+            limit: 100,
+            offset: 0,
+            order: SortOrder::NewestFirst,
+        }
+    }
+}
+
+impl AlertQuery {
+    pub(crate) fn where_clause(&self) -> (String, Vec<Value>) {
+        let mut clauses = Vec::new();
+        let mut params = Vec::new();
+        if let Some(from) = self.from {
+            clauses.push("ts >= ?".to_string());
+            params.push(Value::Text(from.to_rfc3339()));
+        }
+        if let Some(to) = self.to {
+            clauses.push("ts <= ?".to_string());
+            params.push(Value::Text(to.to_rfc3339()));
+        }
+        if !self.severities.is_empty() {
+            clauses.push(format!(
+                "severity IN ({})",
+                placeholders(self.severities.len())
+            ));
+            params.extend(
+                self.severities
+                    .iter()
+                    .map(|severity| Value::Text(severity.as_str().into())),
+            );
+        }
+        if !self.statuses.is_empty() {
+            clauses.push(format!("status IN ({})", placeholders(self.statuses.len())));
+            params.extend(
+                self.statuses
+                    .iter()
+                    .map(|status| Value::Text(status.as_str().into())),
+            );
+        }
+        if let Some(rule_id) = &self.rule_id {
+            match rule_id.strip_suffix('*') {
+                Some(prefix) => {
+                    clauses.push("rule_id LIKE ? ESCAPE '\\'".to_string());
+                    params.push(Value::Text(format!("{}%", escape_like(prefix))));
+                }
+                None => {
+                    clauses.push("rule_id = ?".to_string());
+                    params.push(Value::Text(rule_id.clone()));
+                }
+            }
+        }
+        if let Some(text) = self.text.as_deref().filter(|text| !text.is_empty()) {
+            let pattern = format!("%{}%", escape_like(text));
+            clauses.push(
+                "(summary LIKE ? ESCAPE '\\' OR rationale LIKE ? ESCAPE '\\' OR rule_id LIKE ? ESCAPE '\\')"
+                    .to_string(),
+            );
+            params.extend(std::iter::repeat_n(Value::Text(pattern), 3));
+        }
+        (join_clauses(clauses), params)
+    }
+}
+
+/// Matches `column` against an exact address or a CIDR block.
+fn address_clause(column: &str, filter: &str) -> Result<String> {
+    if filter.contains('/') {
+        parse_cidr(filter)?;
+        Ok(format!("cidr_match({column}, ?)"))
+    } else {
+        Ok(format!("{column} = ?"))
+    }
+}
+
+pub(crate) fn placeholders(count: usize) -> String {
+    vec!["?"; count].join(", ")
+}
+
+pub(crate) fn join_clauses(clauses: Vec<String>) -> String {
+    if clauses.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", clauses.join(" AND "))
+    }
+}
+
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+pub fn parse_cidr(cidr: &str) -> Result<(IpAddr, u8)> {
+    let (addr, len) = cidr
+        .split_once('/')
+        .ok_or_else(|| anyhow!("invalid CIDR {cidr}"))?;
+    let addr: IpAddr = addr.parse().map_err(|_| anyhow!("invalid CIDR {cidr}"))?;
+    let len: u8 = len.parse().map_err(|_| anyhow!("invalid CIDR {cidr}"))?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    if len > max {
+        return Err(anyhow!("invalid CIDR {cidr}"));
+    }
+    Ok((addr, len))
+}
+
+pub fn ip_in_cidr(ip: &str, cidr: &str) -> bool {
+    let (Ok(ip), Ok((net, len))) = (ip.parse::<IpAddr>(), parse_cidr(cidr)) else {
+        return false;
+    };
+    match (ip, net) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - len as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - len as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cidr_matching_and_clause() {
+        assert!(ip_in_cidr("10.1.2.3", "10.0.0.0/8"));
+        assert!(!ip_in_cidr("11.1.2.3", "10.0.0.0/8"));
+        assert!(ip_in_cidr("1.2.3.4", "0.0.0.0/0"));
+        assert!(ip_in_cidr("fe80::1", "fe80::/10"));
+        assert!(!ip_in_cidr("10.0.0.1", "fe80::/10"));
+
+        let query = FlowQuery {
+            dst: Some("192.168.0.0/16".into()),
+            dst_ports: vec![445, 139],
+            direction: Some(FlowDirection::Lateral),
+            ..FlowQuery::default()
+        };
+        let (clause, params) = query.where_clause().unwrap();
+        assert_eq!(
+            clause,
+            "WHERE cidr_match(dst_ip, ?) AND dst_port IN (?, ?) AND direction = ?"
+        );
+        assert_eq!(params.len(), 4);
+
+        let query = FlowQuery {
+            host: Some("192.168.1.50".into()),
+            ports: vec![445],
+            process: Some("notesync*".into()),
+            ..FlowQuery::default()
+        };
+        let (clause, params) = query.where_clause().unwrap();
+        assert_eq!(
+            clause,
+            "WHERE (src_ip = ? OR dst_ip = ?) AND (src_port IN (?) OR dst_port IN (?)) AND process LIKE ? ESCAPE '\\'"
+        );
+        assert_eq!(params.len(), 5);
+        assert!(FlowQuery {
+            src: Some("10.0.0.0/40".into()),
+            ..FlowQuery::default()
+        }
+        .where_clause()
+        .is_err());
+    }
+}
@@ -0,0 +1,368 @@
+use std::{
+    path::PathBuf,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use ring::{digest, hmac};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    spool::{ExportBatch, ExportTransport},
+    Storage, StorageOptions,
+};
+
+/// Remote shipping of the export spool; mirrors the `[export]` section of
+/// the config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShipperConfig {
+    /// Nothing is shipped without one.
+    pub endpoint: Option<RemoteEndpoint>,
+    /// Name the batches carry; defaults to the host name. Set it when
+    /// several machines ship to one place.
+    pub host: Option<String>,
+    /// Spool entries per batch.
+    pub batch_size: usize,
+    pub interval_secs: u64,
+    /// Upper bound of the exponential backoff after failed deliveries.
+    pub max_backoff_secs: u64,
+    pub timeout_secs: u64,
+}
+
+impl Default for ShipperConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            host: None,
+            batch_size: 500,
+            interval_secs: 10,
+            max_backoff_secs: 600,
+            timeout_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum RemoteEndpoint {
+    /// POSTs each batch as JSON to `url`, with an `Idempotency-Key` header.
+    Https {
+        url: String,
+        /// Environment variable holding a bearer token, if the receiver
+        /// wants one.
+        #[serde(default)]
+        token_env: Option<String>,
+    },
+    /// PUTs each batch as the object
+    /// `<prefix><host>/<first_seq>-<last_seq>.json` (zero-padded, so keys
+    /// sort in shipping order), path-style and signed with AWS Signature
+    /// V4. Credentials come from `AWS_ACCESS_KEY_ID`,
+    /// `AWS_SECRET_ACCESS_KEY` and optionally `AWS_SESSION_TOKEN`.
+    S3 {
+        /// E.g. `https://s3.eu-central-1.amazonaws.com` or a MinIO URL.
+        endpoint: String,
+        bucket: String,
+        region: String,
+        #[serde(default)]
+        prefix: String,
+    },
+}
+
+impl RemoteEndpoint {
+    pub fn transport(&self, timeout: Duration) -> Result<Box<dyn ExportTransport>> {
+        let agent = ureq::AgentBuilder::new().timeout(timeout).build();
+        Ok(match self {
+            RemoteEndpoint::Https { url, token_env } => {
+                require_https(url)?;
+                let token = match token_env {
+                    Some(var) => {
+                        Some(std::env::var(var).with_context(|| format!("{var} is not set"))?)
+                    }
+                    None => None,
+                };
+                Box::new(HttpsTransport {
+                    agent,
+                    url: url.clone(),
+                    token,
+                })
+            }
+            RemoteEndpoint::S3 {
+                endpoint,
+                bucket,
+                region,
+                prefix,
+            } => {
+                require_https(endpoint)?;
+                let endpoint = endpoint.trim_end_matches('/');
+                let host = endpoint
+                    .trim_start_matches("https://")
+                    .split('/')
+                    .next()
+                    .unwrap_or_default()
+                    .to_string();
+                let env =
+                    |var: &str| std::env::var(var).with_context(|| format!("{var} is not set"));
+                Box::new(S3Transport {
+                    agent,
+                    endpoint: endpoint.to_string(),
+                    host,
+                    bucket: bucket.clone(),
+                    region: region.clone(),
+                    prefix: prefix.clone(),
+                    access_key_id: env("AWS_ACCESS_KEY_ID")?,
+                    secret_access_key: env("AWS_SECRET_ACCESS_KEY")?,
+                    session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+                })
+            }
+        })
+    }
+}
+
+fn require_https(url: &str) -> Result<()> {
+    if url.starts_with("https://") {
+        Ok(())
+    } else {
+        Err(anyhow!("export endpoint {url} must use https"))
+    }
+}
+
+struct HttpsTransport {
+    agent: ureq::Agent,
+    url: String,
+    token: Option<String>,
+}
+
+impl ExportTransport for HttpsTransport {
+    fn send(&self, batch: &ExportBatch, body: &[u8]) -> Result<()> {
+        let mut request = self
+            .agent
+            .post(&self.url)
+            .set("Content-Type", "application/json")
+            .set(
+                "Idempotency-Key",
+                &format!("{}-{}-{}", batch.host, batch.first_seq, batch.last_seq),
+            );
+        if let Some(token) = &self.token {
+            request = request.set("Authorization", &format!("Bearer {token}"));
+        }
+        request
+            .send_bytes(body)
+            .with_context(|| format!("POST {}", self.url))?;
+        Ok(())
+    }
+}
+
+struct S3Transport {
+    agent: ureq::Agent,
+    endpoint: String,
+    /// `Host` header value, part of the signature.
+    host: String,
+    bucket: String,
+    region: String,
+    prefix: String,
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+impl S3Transport {
+    fn object_key(&self, batch: &ExportBatch) -> String {
+        // Keys stay within characters that need no URI encoding.
+        let host: String = batch
+            .host
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        format!(
+            "{}{host}/{:020}-{:020}.json",
+            self.prefix, batch.first_seq, batch.last_seq
+        )
+    }
+}
+
+impl ExportTransport for S3Transport {
+    fn send(&self, batch: &ExportBatch, body: &[u8]) -> Result<()> {
+        let path = format!("/{}/{}", self.bucket, self.object_key(batch));
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = hex::encode(digest::digest(&digest::SHA256, body));
+
+        let mut headers = vec![
+            ("host", self.host.clone()),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+            .collect();
+        let canonical_request =
+            format!("PUT\n{path}\n\n{canonical_headers}\n{signed_headers}\n{payload_hash}");
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex::encode(digest::digest(
+                &digest::SHA256,
+                canonical_request.as_bytes()
+            ))
+        );
+        let key = signing_key(&self.secret_access_key, &date, &self.region, "s3");
+        let signature = hex::encode(hmac::sign(&key, string_to_sign.as_bytes()));
+
+        let url = format!("{}{path}", self.endpoint);
+        let mut request = self
+            .agent
+            .put(&url)
+            .set("Content-Type", "application/json")
+            .set(
+                "Authorization",
+                &format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+                    self.access_key_id
+                ),
+            );
+        for (name, value) in &headers {
+            if *name != "host" {
+                request = request.set(name, value);
+            }
+        }
+        request
+            .send_bytes(body)
+            .with_context(|| format!("PUT {url}"))?;
+        Ok(())
+    }
+}
+
+/// AWS Signature V4 signing key for one day, region and service.
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> hmac::Key {
+    let mut key = hmac::Key::new(hmac::HMAC_SHA256, format!("AWS4{secret}").as_bytes());
+    for part in [date, region, service, "aws4_request"] {
+        let tag = hmac::sign(&key, part.as_bytes());
+        key = hmac::Key::new(hmac::HMAC_SHA256, tag.as_ref());
+    }
+    key
+}
+
+fn default_host() -> String {
+    std::env::var("COMPUTERNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "localhost".into())
+}
+
+/// Background thread draining the export spool to the configured endpoint
+/// over its own connection, backing off exponentially while deliveries
+/// fail. Dropping the handle stops it; undelivered entries stay queued.
+pub struct ShipperJob {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ShipperJob {
+    pub fn spawn(
+        path: impl Into<PathBuf>,
+        key: Vec<u8>,
+        options: StorageOptions,
+        config: ShipperConfig,
+    ) -> Result<Self> {
+        let endpoint = config
+            .endpoint
+            .as_ref()
+            .ok_or_else(|| anyhow!("no export endpoint configured"))?;
+        let transport = endpoint.transport(Duration::from_secs(config.timeout_secs.max(1)))?;
+        let storage = Storage::open_with_options(path.into(), &key, options)?;
+        let host = config.host.clone().unwrap_or_else(default_host);
+        let batch_size = config.batch_size.max(1);
+        let interval = Duration::from_secs(config.interval_secs.max(1));
+        let max_backoff = Duration::from_secs(config.max_backoff_secs).max(interval);
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::Builder::new()
+            .name("nets-shipper".into())
+            .spawn(move || {
+                let mut failures = 0u32;
+                loop {
+                    let wait =
+                        match storage.ship_export_batch(transport.as_ref(), &host, batch_size) {
+                            Ok(shipped) => {
+                                failures = 0;
+                                // Keep draining while there is a backlog.
+                                if shipped >= batch_size {
+                                    Duration::ZERO
+                                } else {
+                                    interval
+                                }
+                            }
+                            Err(err) => {
+                                failures += 1;
+                                let backoff = interval
+                                    .saturating_mul(1 << failures.min(16))
+                                    .min(max_backoff);
+                                tracing::warn!(%err, failures, retry_in = ?backoff, "export delivery failed");
+                                backoff
+                            }
+                        };
+                    match stopped.recv_timeout(wait) {
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        _ => break,
+                    }
+                }
+            })?;
+        Ok(Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for ShipperJob {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_aws_signing_key() {
+        // Example from the AWS Signature V4 documentation.
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        let probe = hex::encode(hmac::sign(&key, b"probe"));
+        let expected = hmac::Key::new(
+            hmac::HMAC_SHA256,
+            &hex::decode("f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d")
+                .unwrap(),
+        );
+        assert_eq!(probe, hex::encode(hmac::sign(&expected, b"probe")));
+    }
+}
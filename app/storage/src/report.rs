@@ -0,0 +1,807 @@
+//! Shareable offline report over a period: alerts, incidents, top talkers,
+//! new LAN devices and ATT&CK coverage, rendered as HTML, Markdown or PDF.
+
+use std::{collections::BTreeMap, fmt::Write as _, str::FromStr};
+
+use analyzer::{dsl::Rule, is_lan, mitre::CoverageMatrix, Alert, Severity};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    rollup::{RankBy, RollupDimension, RollupPeriod, RollupTotal, TopQuery},
+    AlertQuery, Incident, IncidentQuery, SortOrder, Storage,
+};
+
+/// Alerts listed one by one; the totals count all of them.
+const LISTED_ALERTS: usize = 50;
+const LISTED_INCIDENTS: usize = 50;
+const LISTED_DEVICES: usize = 100;
+const TOP_ROWS: usize = 10;
+/// Alerts read to compute the coverage matrix.
+const COVERAGE_ALERTS: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    Html,
+    #[serde(alias = "md")]
+    Markdown,
+    Pdf,
+}
+
+impl ReportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ReportFormat::Html => "html",
+            ReportFormat::Markdown => "md",
+            ReportFormat::Pdf => "pdf",
+        }
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "html" | "htm" => Ok(ReportFormat::Html),
+            "md" | "markdown" => Ok(ReportFormat::Markdown),
+            "pdf" => Ok(ReportFormat::Pdf),
+            other => Err(anyhow!("unknown report format: {other}")),
+        }
+    }
+}
+
+/// A LAN address first seen in the period, named after a discovered
+/// service on it when there is one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NewDevice {
+    pub address: String,
+    pub name: Option<String>,
+    pub first_seen: DateTime<Utc>,
+    /// Flows the address took part in during the period.
+    pub flows: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    pub generated_at: DateTime<Utc>,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub flows: u64,
+    /// Alert counts by severity, all of them included.
+    pub alerts_by_severity: BTreeMap<Severity, usize>,
+    /// Most severe first, then newest; at most [`LISTED_ALERTS`].
+    pub alerts: Vec<Alert>,
+    pub incidents: Vec<Incident>,
+    /// Destinations by bytes.
+    pub top_talkers: Vec<RollupTotal>,
+    /// Processes by bytes.
+    pub top_processes: Vec<RollupTotal>,
+    pub new_devices: Vec<NewDevice>,
+    pub coverage: CoverageMatrix,
+}
+
+impl Storage {
+    /// Gathers a [`Report`] over `[from, to]`. `rules` are the loaded DSL
+    /// rules, for the coverage matrix.
+    pub fn report(&self, from: DateTime<Utc>, to: DateTime<Utc>, rules: &[Rule]) -> Result<Report> {
+        let flows: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM flows WHERE ts_first >= ?1 AND ts_first <= ?2",
+            params![from.to_rfc3339(), to.to_rfc3339()],
+            |row| row.get(0),
+        )?;
+        let mut alerts = self.query_alerts(&AlertQuery {
+            from: Some(from),
+            to: Some(to),
+            limit: COVERAGE_ALERTS,
+            order: SortOrder::NewestFirst,
+            ..AlertQuery::default()
+        })?;
+        let coverage = CoverageMatrix::build(rules, &alerts, from, to);
+        let mut alerts_by_severity = BTreeMap::new();
+        for severity in [Severity::High, Severity::Medium, Severity::Low] {
+            let count = self.count_alerts(&AlertQuery {
+                from: Some(from),
+                to: Some(to),
+                severities: vec![severity.clone()],
+                ..AlertQuery::default()
+            })?;
+            alerts_by_severity.insert(severity, count);
+        }
+        // Newest first already; a stable sort keeps that within a severity.
+        alerts.sort_by(|a, b| b.severity.cmp(&a.severity));
+        alerts.truncate(LISTED_ALERTS);
+
+        let incidents = self.query_incidents(&IncidentQuery {
+            from: Some(from),
+            to: Some(to),
+            limit: LISTED_INCIDENTS,
+            ..IncidentQuery::default()
+        })?;
+        let top = |dimension| {
+            self.top(&TopQuery {
+                dimension,
+                period: RollupPeriod::for_span(to - from),
+                from,
+                to,
+                rank_by: RankBy::Bytes,
+                limit: TOP_ROWS,
+            })
+        };
+        Ok(Report {
+            generated_at: Utc::now(),
+            from,
+            to,
+            flows: flows as u64,
+            alerts_by_severity,
+            alerts,
+            incidents,
+            top_talkers: top(RollupDimension::Destination)?,
+            top_processes: top(RollupDimension::Process)?,
+            new_devices: self.new_devices(from, to)?,
+            coverage,
+        })
+    }
+
+    /// LAN addresses whose first flow, or first discovered service, falls
+    /// in `[from, to]`.
+    fn new_devices(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<NewDevice>> {
+        let (from_text, to_text) = (from.to_rfc3339(), to.to_rfc3339());
+        let mut devices: BTreeMap<String, NewDevice> = BTreeMap::new();
+        let mut stmt = self.conn.prepare(
+            "SELECT ip, MIN(ts), SUM(ts >= ?1 AND ts <= ?2) FROM (
+                 SELECT src_ip AS ip, ts_first AS ts FROM flows
+                 UNION ALL SELECT dst_ip, ts_first FROM flows)
+             GROUP BY ip HAVING MIN(ts) >= ?1 AND MIN(ts) <= ?2",
+        )?;
+        let rows = stmt
+            .query_map(params![from_text, to_text], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    crate::timestamp_column(row, 1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        for (address, first_seen, flows) in rows {
+            if is_lan(&address) {
+                devices.insert(
+                    address.clone(),
+                    NewDevice {
+                        address,
+                        name: None,
+                        first_seen,
+                        flows: flows as u64,
+                    },
+                );
+            }
+        }
+        for service in self.list_services(u32::MAX as usize)? {
+            if !is_lan(&service.address) {
+                continue;
+            }
+            match devices.get_mut(&service.address) {
+                Some(device) => {
+                    device.name.get_or_insert(service.name);
+                }
+                None if service.first_seen >= from && service.first_seen <= to => {
+                    devices.insert(
+                        service.address.clone(),
+                        NewDevice {
+                            address: service.address,
+                            name: Some(service.name),
+                            first_seen: service.first_seen,
+                            flows: 0,
+                        },
+                    );
+                }
+                None => {}
+            }
+        }
+        let mut devices: Vec<NewDevice> = devices.into_values().collect();
+        devices.sort_by(|a, b| {
+            a.first_seen
+                .cmp(&b.first_seen)
+                .then_with(|| a.address.cmp(&b.address))
+        });
+        devices.truncate(LISTED_DEVICES);
+        Ok(devices)
+    }
+}
+
+/// Format-neutral building blocks every renderer understands.
+enum Block {
+    Title(String),
+    Heading(String),
+    Paragraph(String),
+    Table {
+        headers: Vec<&'static str>,
+        rows: Vec<Vec<String>>,
+    },
+}
+
+impl Report {
+    pub fn render(&self, format: ReportFormat) -> Vec<u8> {
+        let blocks = self.blocks();
+        match format {
+            ReportFormat::Html => html(&blocks).into_bytes(),
+            ReportFormat::Markdown => markdown(&blocks).into_bytes(),
+            ReportFormat::Pdf => pdf::render(&blocks),
+        }
+    }
+
+    fn blocks(&self) -> Vec<Block> {
+        let time = |ts: &DateTime<Utc>| ts.format("%Y-%m-%d %H:%M").to_string();
+        let mut blocks = vec![
+            Block::Title("Nets network report".into()),
+            Block::Paragraph(format!(
+                "Period {} to {} UTC, generated {} UTC.",
+                time(&self.from),
+                time(&self.to),
+                time(&self.generated_at)
+            )),
+            Block::Heading("Summary".into()),
+            Block::Table {
+                headers: vec!["Metric", "Value"],
+                rows: {
+                    let mut rows = vec![vec!["Flows".into(), self.flows.to_string()]];
+                    for (severity, count) in self.alerts_by_severity.iter().rev() {
+                        rows.push(vec![
+                            format!("{} alerts", severity.as_str()),
+                            count.to_string(),
+                        ]);
+                    }
+                    rows.push(vec!["Incidents".into(), self.incidents.len().to_string()]);
+                    rows.push(vec![
+                        "New devices".into(),
+                        self.new_devices.len().to_string(),
+                    ]);
+                    rows
+                },
+            },
+        ];
+
+        let total: usize = self.alerts_by_severity.values().sum();
+        blocks.push(Block::Heading("Alerts".into()));
+        if self.alerts.is_empty() {
+            blocks.push(Block::Paragraph("No alerts in this period.".into()));
+        } else {
+            if total > self.alerts.len() {
+                blocks.push(Block::Paragraph(format!(
+                    "The {} most severe of {total} alerts.",
+                    self.alerts.len()
+                )));
+            }
+            blocks.push(Block::Table {
+                headers: vec!["Time", "Severity", "Rule", "Summary", "Status"],
+                rows: self
+                    .alerts
+                    .iter()
+                    .map(|alert| {
+                        vec![
+                            time(&alert.ts),
+                            alert.severity.as_str().into(),
+                            alert.rule_id.clone(),
+                            alert.summary.clone(),
+                            alert.status.as_str().into(),
+                        ]
+                    })
+                    .collect(),
+            });
+        }
+
+        blocks.push(Block::Heading("Incidents".into()));
+        if self.incidents.is_empty() {
+            blocks.push(Block::Paragraph("No incidents in this period.".into()));
+        } else {
+            blocks.push(Block::Table {
+                headers: vec![
+                    "First alert",
+                    "Last alert",
+                    "Severity",
+                    "Title",
+                    "Alerts",
+                    "Status",
+                ],
+                rows: self
+                    .incidents
+                    .iter()
+                    .map(|incident| {
+                        vec![
+                            time(&incident.first_alert),
+                            time(&incident.last_alert),
+                            incident.severity.as_str().into(),
+                            incident.title.clone(),
+                            incident.alert_ids.len().to_string(),
+                            incident.status.as_str().into(),
+                        ]
+                    })
+                    .collect(),
+            });
+        }
+
+        for (heading, column, totals) in [
+            ("Top talkers", "Destination", &self.top_talkers),
+            ("Top processes", "Process", &self.top_processes),
+        ] {
+            blocks.push(Block::Heading(heading.into()));
+            if totals.is_empty() {
+                blocks.push(Block::Paragraph("No traffic in this period.".into()));
+                continue;
+            }
+            blocks.push(Block::Table {
+                headers: vec![column, "Bytes", "Flows"],
+                rows: totals
+                    .iter()
+                    .map(|total| {
+                        vec![
+                            total.key.clone(),
+                            total.bytes.to_string(),
+                            total.flows.to_string(),
+                        ]
+                    })
+                    .collect(),
+            });
+        }
+
+        blocks.push(Block::Heading("New devices".into()));
+        if self.new_devices.is_empty() {
+            blocks.push(Block::Paragraph(
+                "No new devices on the local network.".into(),
+            ));
+        } else {
+            blocks.push(Block::Table {
+                headers: vec!["First seen", "Address", "Name", "Flows"],
+                rows: self
+                    .new_devices
+                    .iter()
+                    .map(|device| {
+                        vec![
+                            time(&device.first_seen),
+                            device.address.clone(),
+                            device.name.clone().unwrap_or_default(),
+                            device.flows.to_string(),
+                        ]
+                    })
+                    .collect(),
+            });
+        }
+
+        blocks.push(Block::Heading("MITRE ATT&CK coverage".into()));
+        blocks.push(Block::Table {
+            headers: vec!["Technique", "Rules", "Alerts", "Last alert"],
+            rows: self
+                .coverage
+                .techniques
+                .iter()
+                .map(|technique| {
+                    vec![
+                        technique.technique.clone(),
+                        technique.rules.join(", "),
+                        technique.alerts.to_string(),
+                        technique.last_alert.as_ref().map(time).unwrap_or_default(),
+                    ]
+                })
+                .collect(),
+        });
+        if !self.coverage.untagged_rules.is_empty() {
+            blocks.push(Block::Paragraph(format!(
+                "Rules without a technique tag: {}.",
+                self.coverage.untagged_rules.join(", ")
+            )));
+        }
+        blocks
+    }
+}
+
+fn markdown(blocks: &[Block]) -> String {
+    let cell = |text: &str| text.replace('|', "\\|").replace('\n', " ");
+    let mut out = String::new();
+    for block in blocks {
+        match block {
+            Block::Title(text) => {
+                let _ = writeln!(out, "# {text}\n");
+            }
+            Block::Heading(text) => {
+                let _ = writeln!(out, "## {text}\n");
+            }
+            Block::Paragraph(text) => {
+                let _ = writeln!(out, "{text}\n");
+            }
+            Block::Table { headers, rows } => {
+                let _ = writeln!(out, "| {} |", headers.join(" | "));
+                let _ = writeln!(out, "|{}", "---|".repeat(headers.len()));
+                for row in rows {
+                    let cells: Vec<String> = row.iter().map(|text| cell(text)).collect();
+                    let _ = writeln!(out, "| {} |", cells.join(" | "));
+                }
+                out.push('\n');
+            }
+        }
+    }
+    out
+}
+
+fn html(blocks: &[Block]) -> String {
+    let escape = |text: &str| {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    };
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"/><title>Nets network report</title>\n<style>\
+         body{font-family:system-ui,sans-serif;margin:2em auto;max-width:70em;color:#222}\
+         table{border-collapse:collapse;margin-bottom:1.5em;width:100%}\
+         th,td{border:1px solid #ccc;padding:.3em .6em;text-align:left;vertical-align:top}\
+         th{background:#f0f0f0}h2{margin-top:1.5em}\
+         </style></head><body>\n",
+    );
+    for block in blocks {
+        match block {
+            Block::Title(text) => {
+                let _ = writeln!(out, "<h1>{}</h1>", escape(text));
+            }
+            Block::Heading(text) => {
+                let _ = writeln!(out, "<h2>{}</h2>", escape(text));
+            }
+            Block::Paragraph(text) => {
+                let _ = writeln!(out, "<p>{}</p>", escape(text));
+            }
+            Block::Table { headers, rows } => {
+                out.push_str("<table><tr>");
+                for header in headers {
+                    let _ = write!(out, "<th>{}</th>", escape(header));
+                }
+                out.push_str("</tr>\n");
+                for row in rows {
+                    out.push_str("<tr>");
+                    for text in row {
+                        let _ = write!(out, "<td>{}</td>", escape(text));
+                    }
+                    out.push_str("</tr>\n");
+                }
+                out.push_str("</table>\n");
+            }
+        }
+    }
+    out.push_str("</body></html>\n");
+    out
+}
+
+/// Minimal PDF writer: A4 pages of text in the standard Helvetica and
+/// Courier fonts, so nothing has to be embedded. Those fonts only cover
+/// Latin-1, so Cyrillic is transliterated and anything else becomes `?`.
+mod pdf {
+    use std::fmt::Write as _;
+
+    use super::Block;
+
+    const PAGE_WIDTH: f32 = 595.0;
+    const PAGE_HEIGHT: f32 = 842.0;
+    const MARGIN: f32 = 40.0;
+    /// Courier glyphs are 0.6 em wide.
+    const TABLE_SIZE: f32 = 7.5;
+    const TABLE_CHARS: usize = ((PAGE_WIDTH - 2.0 * MARGIN) / (TABLE_SIZE * 0.6)) as usize;
+    /// Helvetica averages a little over 0.5 em; wrap conservatively.
+    const TEXT_SIZE: f32 = 10.0;
+    const TEXT_CHARS: usize = 95;
+
+    #[derive(Clone, Copy)]
+    enum Font {
+        Regular,
+        Bold,
+        Mono,
+    }
+
+    impl Font {
+        fn name(self) -> &'static str {
+            match self {
+                Font::Regular => "F1",
+                Font::Bold => "F2",
+                Font::Mono => "F3",
+            }
+        }
+    }
+
+    struct Layout {
+        pages: Vec<String>,
+        y: f32,
+    }
+
+    impl Layout {
+        fn line(&mut self, font: Font, size: f32, text: &str) {
+            let leading = size * 1.35;
+            if self.pages.is_empty() || self.y - leading < MARGIN {
+                self.pages.push(String::new());
+                self.y = PAGE_HEIGHT - MARGIN;
+            }
+            self.y -= leading;
+            let page = self.pages.last_mut().expect("a page was just pushed");
+            let _ = writeln!(
+                page,
+                "BT /{} {size} Tf {MARGIN} {:.1} Td ({}) Tj ET",
+                font.name(),
+                self.y,
+                escape(text)
+            );
+        }
+
+        fn gap(&mut self, points: f32) {
+            self.y -= points;
+        }
+    }
+
+    pub(super) fn render(blocks: &[Block]) -> Vec<u8> {
+        let mut layout = Layout {
+            pages: Vec::new(),
+            y: 0.0,
+        };
+        for block in blocks {
+            match block {
+                Block::Title(text) => {
+                    layout.line(Font::Bold, 16.0, &latin(text));
+                    layout.gap(6.0);
+                }
+                Block::Heading(text) => {
+                    layout.gap(8.0);
+                    layout.line(Font::Bold, 12.0, &latin(text));
+                    layout.gap(2.0);
+                }
+                Block::Paragraph(text) => {
+                    for line in wrap(&latin(text), TEXT_CHARS) {
+                        layout.line(Font::Regular, TEXT_SIZE, &line);
+                    }
+                    layout.gap(4.0);
+                }
+                Block::Table { headers, rows } => {
+                    let rows: Vec<Vec<String>> = rows
+                        .iter()
+                        .map(|row| row.iter().map(|cell| latin(cell)).collect())
+                        .collect();
+                    let widths = column_widths(headers, &rows);
+                    let headers: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+                    layout.line(Font::Mono, TABLE_SIZE, &table_row(&headers, &widths));
+                    let rule: usize = widths.iter().sum::<usize>() + 2 * (widths.len() - 1);
+                    layout.line(Font::Mono, TABLE_SIZE, &"-".repeat(rule));
+                    for row in &rows {
+                        layout.line(Font::Mono, TABLE_SIZE, &table_row(row, &widths));
+                    }
+                    layout.gap(4.0);
+                }
+            }
+        }
+        document(&layout.pages)
+    }
+
+    /// Natural widths, with the widest columns trimmed until the row fits.
+    fn column_widths(headers: &[&str], rows: &[Vec<String>]) -> Vec<usize> {
+        let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+        for row in rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.len());
+            }
+        }
+        let budget = TABLE_CHARS.saturating_sub(2 * (widths.len() - 1));
+        while widths.iter().sum::<usize>() > budget {
+            let Some(widest) = widths.iter_mut().max() else {
+                break;
+            };
+            if *widest <= 8 {
+                break;
+            }
+            *widest -= 1;
+        }
+        widths
+    }
+
+    fn table_row(cells: &[String], widths: &[usize]) -> String {
+        cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| {
+                if cell.len() > *width {
+                    format!("{}...", &cell[..width.saturating_sub(3)])
+                } else {
+                    format!("{cell:<width$}")
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    }
+
+    fn wrap(text: &str, width: usize) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut line = String::new();
+        for word in text.split_whitespace() {
+            if !line.is_empty() && line.len() + 1 + word.len() > width {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Printable ASCII only, one byte per char, so lengths are widths and
+    /// slicing is safe.
+    fn latin(text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        for c in text.chars() {
+            match c {
+                ' '..='~' => out.push(c),
+                '\t' | '\n' | '\r' => out.push(' '),
+                _ => match transliterate(c) {
+                    Some(latin) => out.push_str(&latin),
+                    None => out.push('?'),
+                },
+            }
+        }
+        out
+    }
+
+    fn transliterate(c: char) -> Option<String> {
+        const LOWER: [&str; 32] = [
+            "a", "b", "v", "g", "d", "e", "zh", "z", "i", "y", "k", "l", "m", "n", "o", "p", "r",
+            "s", "t", "u", "f", "kh", "ts", "ch", "sh", "shch", "", "y", "", "e", "yu", "ya",
+        ];
+        let latin = match c {
+            'а'..='я' => LOWER[c as usize - 'а' as usize].to_string(),
+            'А'..='Я' => {
+                let lower = LOWER[c as usize - 'А' as usize];
+                let mut chars = lower.chars();
+                match chars.next() {
+                    Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                    None => String::new(),
+                }
+            }
+            'ё' => "e".into(),
+            'Ё' => "E".into(),
+            '—' | '–' => "-".into(),
+            '«' | '»' | '“' | '”' => "\"".into(),
+            '…' => "...".into(),
+            _ => return None,
+        };
+        Some(latin)
+    }
+
+    fn escape(text: &str) -> String {
+        text.replace('\\', "\\\\")
+            .replace('(', "\\(")
+            .replace(')', "\\)")
+    }
+
+    /// Catalog, page tree and fonts first, then a page and its content
+    /// stream per page, then the cross-reference table.
+    fn document(pages: &[String]) -> Vec<u8> {
+        let pages: Vec<&str> = if pages.is_empty() {
+            vec![""]
+        } else {
+            pages.iter().map(String::as_str).collect()
+        };
+        let first_page = 6;
+        let kids: Vec<String> = (0..pages.len())
+            .map(|n| format!("{} 0 R", first_page + 2 * n))
+            .collect();
+        let mut objects = vec![
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                kids.join(" "),
+                pages.len()
+            ),
+        ];
+        for font in ["Helvetica", "Helvetica-Bold", "Courier"] {
+            objects.push(format!(
+                "<< /Type /Font /Subtype /Type1 /BaseFont /{font} /Encoding /WinAnsiEncoding >>"
+            ));
+        }
+        for (n, content) in pages.iter().enumerate() {
+            objects.push(format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {PAGE_WIDTH} {PAGE_HEIGHT}] \
+                 /Resources << /Font << /F1 3 0 R /F2 4 0 R /F3 5 0 R >> >> /Contents {} 0 R >>",
+                first_page + 2 * n + 1
+            ));
+            objects.push(format!(
+                "<< /Length {} >>\nstream\n{content}endstream",
+                content.len()
+            ));
+        }
+        let mut out = String::from("%PDF-1.4\n");
+        let mut offsets = Vec::with_capacity(objects.len());
+        for (n, object) in objects.iter().enumerate() {
+            offsets.push(out.len());
+            let _ = write!(out, "{} 0 obj\n{object}\nendobj\n", n + 1);
+        }
+        let xref = out.len();
+        let _ = write!(out, "xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+        for offset in offsets {
+            let _ = writeln!(out, "{offset:010} 00000 n ");
+        }
+        let _ = write!(
+            out,
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+            objects.len() + 1
+        );
+        out.into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use collector::FlowEvent;
+
+    #[test]
+    fn gathers_and_renders_every_format() {
+        let storage = Storage::open(":memory:", &[5u8; 32]).unwrap();
+        let now = Utc::now();
+        let flow = |src: &str, dst: &str, age: Duration| FlowEvent {
+            ts_first: now - age,
+            ts_last: now - age,
+            proto: "TCP".into(),
+            src_ip: src.into(),
+            dst_ip: dst.into(),
+            dst_port: 443,
+            bytes: 1000,
+            ..FlowEvent::default()
+        };
+        storage
+            .put_flows(&[
+                flow("192.168.1.10", "203.0.113.7", Duration::days(30)),
+                flow("192.168.1.10", "203.0.113.7", Duration::hours(2)),
+                flow("192.168.1.77", "203.0.113.8", Duration::hours(1)),
+            ])
+            .unwrap();
+        storage
+            .put_alert(&Alert {
+                id: "a-1".into(),
+                ts: now - Duration::hours(1),
+                severity: Severity::High,
+                rule_id: "lateral.smb".into(),
+                summary: "Всплеск SMB <в подсети>".into(),
+                flow_refs: Vec::new(),
+                process_ref: None,
+                rationale: String::new(),
+                suggested_action: None,
+                status: Default::default(),
+                assignee: None,
+                notes: Vec::new(),
+                evidence: Default::default(),
+            })
+            .unwrap();
+
+        let report = storage.report(now - Duration::days(7), now, &[]).unwrap();
+        assert_eq!(report.flows, 2);
+        assert_eq!(report.alerts_by_severity[&Severity::High], 1);
+        let devices: Vec<&str> = report
+            .new_devices
+            .iter()
+            .map(|device| device.address.as_str())
+            .collect();
+        assert_eq!(devices, ["192.168.1.77"]);
+        assert_eq!(report.top_talkers[0].key, "203.0.113.7");
+
+        let markdown = String::from_utf8(report.render(ReportFormat::Markdown)).unwrap();
+        assert!(markdown.contains("## New devices"));
+        assert!(markdown.contains("| 192.168.1.77 |"));
+        let html = String::from_utf8(report.render(ReportFormat::Html)).unwrap();
+        assert!(html.contains("Всплеск SMB &lt;в подсети&gt;"));
+        let pdf = report.render(ReportFormat::Pdf);
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.starts_with("%PDF-1.4"));
+        assert!(text.ends_with("%%EOF\n"));
+        assert!(text.contains("Vsplesk SMB <v podseti>"));
+    }
+}
@@ -0,0 +1,320 @@
+use std::{
+    path::PathBuf,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use chrono::{Duration as ChronoDuration, Utc};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+
+use crate::{audit, Storage, StorageOptions};
+
+/// How much history to keep; mirrors the `[storage]` section of the config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// Flows and closed alerts older than this are deleted.
+    pub retention_days: Option<u32>,
+    /// Oldest flows are deleted while the live data exceeds this size.
+    pub max_size_mb: Option<u64>,
+    /// Open (New/Acknowledged) alerts survive age-based pruning.
+    pub keep_open_alerts: bool,
+    /// Rows deleted per transaction.
+    pub batch_size: usize,
+    /// Free pages returned to the filesystem per incremental vacuum step.
+    pub vacuum_pages: u32,
+    pub prune_interval_minutes: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            retention_days: Some(14),
+            max_size_mb: Some(1024),
+            keep_open_alerts: true,
+            batch_size: 1000,
+            vacuum_pages: 1024,
+            prune_interval_minutes: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PruneReport {
+    pub flows_deleted: usize,
+    pub alerts_deleted: usize,
+    /// Database file size before and after pruning.
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    pub elapsed: Duration,
+}
+
+impl PruneReport {
+    pub fn reclaimed_bytes(&self) -> u64 {
+        self.bytes_before.saturating_sub(self.bytes_after)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VacuumReport {
+    /// Database file size before and after the rebuild.
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    pub elapsed: Duration,
+}
+
+impl VacuumReport {
+    pub fn reclaimed_bytes(&self) -> u64 {
+        self.bytes_before.saturating_sub(self.bytes_after)
+    }
+}
+
+impl Storage {
+    /// Size of the database file and of the pages actually holding data.
+    pub fn database_size(&self) -> Result<(u64, u64)> {
+        let pragma = |name: &str| -> Result<u64> {
+            let value: i64 = self
+                .conn
+                .query_row(&format!("PRAGMA {name}"), [], |row| row.get(0))?;
+            Ok(value as u64)
+        };
+        let page_size = pragma("page_size")?;
+        let pages = pragma("page_count")?;
+        let free = pragma("freelist_count")?;
+        Ok((pages * page_size, (pages - free) * page_size))
+    }
+
+    /// Applies `config` once: age-based deletion first, then oldest flows
+    /// until the size cap holds, vacuuming incrementally after each batch.
+    pub fn prune(&self, config: &RetentionConfig) -> Result<PruneReport> {
+        let started = Instant::now();
+        let (bytes_before, _) = self.database_size()?;
+        let mut report = PruneReport {
+            bytes_before,
+            ..PruneReport::default()
+        };
+        let batch = config.batch_size.max(1) as i64;
+
+        if let Some(days) = config.retention_days {
+            let cutoff = (Utc::now() - ChronoDuration::days(days as i64)).to_rfc3339();
+            loop {
+                let deleted = self.delete_flow_batch(Some(&cutoff), batch)?;
+                report.flows_deleted += deleted;
+                self.incremental_vacuum(config.vacuum_pages)?;
+                if deleted == 0 {
+                    break;
+                }
+            }
+            loop {
+                let deleted = self.delete_alert_batch(&cutoff, config.keep_open_alerts, batch)?;
+                report.alerts_deleted += deleted;
+                self.incremental_vacuum(config.vacuum_pages)?;
+                if deleted == 0 {
+                    break;
+                }
+            }
+        }
+
+        if let Some(max_mb) = config.max_size_mb {
+            let limit = max_mb * 1024 * 1024;
+            while self.database_size()?.1 > limit {
+                let deleted = self.delete_flow_batch(None, batch)?;
+                report.flows_deleted += deleted;
+                self.incremental_vacuum(config.vacuum_pages)?;
+                if deleted == 0 {
+                    tracing::warn!(max_mb, "database over size cap with no flows left to prune");
+                    break;
+                }
+            }
+        }
+
+        // Hand back whatever free pages the batches left behind.
+        self.incremental_vacuum(0)?;
+        report.bytes_after = self.database_size()?.0;
+        report.elapsed = started.elapsed();
+        Ok(report)
+    }
+
+    /// Rebuilds the database file, defragmenting tables and indexes, and
+    /// truncates the WAL. Pruning only returns free pages; this also packs
+    /// half-empty ones. Blocks writers and needs free disk space about the
+    /// size of the database while it runs.
+    pub fn vacuum(&self) -> Result<VacuumReport> {
+        let started = Instant::now();
+        let (bytes_before, _) = self.database_size()?;
+        self.conn
+            .execute_batch("VACUUM; PRAGMA wal_checkpoint(TRUNCATE);")?;
+        Ok(VacuumReport {
+            bytes_before,
+            bytes_after: self.database_size()?.0,
+            elapsed: started.elapsed(),
+        })
+    }
+
+    /// Deletes up to `limit` of the oldest flows (older than `before`, when
+    /// given) together with their alert links.
+    fn delete_flow_batch(&self, before: Option<&str>, limit: i64) -> Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "CREATE TEMP TABLE IF NOT EXISTS prune_ids (id INTEGER PRIMARY KEY)",
+            [],
+        )?;
+        tx.execute("DELETE FROM prune_ids", [])?;
+        tx.execute(
+            "INSERT INTO prune_ids SELECT id FROM flows WHERE ?1 IS NULL OR ts_first < ?1 ORDER BY ts_first LIMIT ?2",
+            params![before, limit],
+        )?;
+        tx.execute(
+            "DELETE FROM alert_flows WHERE flow_id IN (SELECT id FROM prune_ids)",
+            [],
+        )?;
+        let deleted = tx.execute(
+            "DELETE FROM flows WHERE id IN (SELECT id FROM prune_ids)",
+            [],
+        )?;
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    fn delete_alert_batch(&self, before: &str, keep_open: bool, limit: i64) -> Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        let open_filter = if keep_open {
+            " AND status NOT IN ('New', 'Acknowledged')"
+        } else {
+            ""
+        };
+        let ids = {
+            let mut stmt = tx.prepare(&format!(
+                "SELECT id FROM alerts WHERE ts < ?1{open_filter} ORDER BY ts LIMIT ?2"
+            ))?;
+            let ids = stmt
+                .query_map(params![before, limit], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            ids
+        };
+        for id in &ids {
+            tx.execute("DELETE FROM alert_flows WHERE alert_id = ?1", params![id])?;
+            tx.execute(
+                "DELETE FROM incident_alerts WHERE alert_id = ?1",
+                params![id],
+            )?;
+            tx.execute("DELETE FROM alerts WHERE id = ?1", params![id])?;
+            audit::append(
+                &tx,
+                &self.cipher,
+                audit::AUDIT_ALERT_PRUNED,
+                id,
+                &serde_json::json!({ "before": before }),
+            )?;
+        }
+        tx.commit()?;
+        Ok(ids.len())
+    }
+
+    /// Returns up to `pages` free pages to the filesystem (all when 0).
+    fn incremental_vacuum(&self, pages: u32) -> Result<()> {
+        // Each step frees one page, so the statement must run to completion.
+        let mut stmt = self
+            .conn
+            .prepare(&format!("PRAGMA incremental_vacuum({pages})"))?;
+        let mut rows = stmt.query([])?;
+        while rows.next()?.is_some() {}
+        Ok(())
+    }
+}
+
+/// Background thread pruning the database every `prune_interval_minutes`
+/// over its own connection. Dropping the handle stops it.
+pub struct RetentionJob {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RetentionJob {
+    pub fn spawn(
+        path: impl Into<PathBuf>,
+        key: Vec<u8>,
+        options: StorageOptions,
+        config: RetentionConfig,
+    ) -> Result<Self> {
+        let storage = Storage::open_with_options(path.into(), &key, options)?;
+        let (stop, stopped) = mpsc::channel::<()>();
+        let interval = Duration::from_secs(config.prune_interval_minutes.max(1) * 60);
+        let thread = thread::Builder::new()
+            .name("nets-retention".into())
+            .spawn(move || loop {
+                match storage.prune(&config) {
+                    Ok(report) => tracing::info!(
+                        flows = report.flows_deleted,
+                        alerts = report.alerts_deleted,
+                        reclaimed = report.reclaimed_bytes(),
+                        "retention pruning finished"
+                    ),
+                    Err(err) => tracing::warn!(%err, "retention pruning failed"),
+                }
+                match stopped.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    _ => break,
+                }
+            })?;
+        Ok(Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for RetentionJob {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use collector::FlowEvent;
+
+    #[test]
+    fn prunes_old_flows_and_vacuums() {
+        let dir = std::env::temp_dir().join(format!(
+            "nets-retention-{}-{}",
+            std::process::id(),
+            Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let storage = Storage::open(dir.join("nets.db"), &[1u8; 32]).unwrap();
+        let flow = |age: ChronoDuration| FlowEvent {
+            ts_first: Utc::now() - age,
+            ts_last: Utc::now() - age,
+            ..FlowEvent::default()
+        };
+        storage
+            .put_flows(&vec![flow(ChronoDuration::days(30)); 500])
+            .unwrap();
+        storage
+            .put_flows(&vec![flow(ChronoDuration::zero()); 5])
+            .unwrap();
+
+        let report = storage
+            .prune(&RetentionConfig {
+                retention_days: Some(7),
+                max_size_mb: None,
+                ..RetentionConfig::default()
+            })
+            .unwrap();
+        assert_eq!(report.flows_deleted, 500);
+        let vacuum = storage.vacuum().unwrap();
+        assert!(vacuum.bytes_after <= vacuum.bytes_before);
+        let flows = storage.query_flows(&crate::FlowQuery::default()).unwrap();
+        assert_eq!(flows.len(), 5);
+        drop(storage);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
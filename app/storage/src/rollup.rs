@@ -0,0 +1,417 @@
+use std::borrow::Cow;
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use collector::FlowEvent;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use crate::{flow_domain, Storage};
+
+const META_ROLLUPS_BUILT: &str = "rollups_built";
+/// Top-N queries over longer spans read daily instead of hourly buckets.
+const HOURLY_TOP_LIMIT: Duration = Duration::days(2);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RollupPeriod {
+    #[default]
+    Hour,
+    Day,
+}
+
+impl RollupPeriod {
+    /// Hourly buckets for spans up to two days, daily ones beyond, so long
+    /// top-N queries stay cheap.
+    pub fn for_span(span: Duration) -> Self {
+        if span <= HOURLY_TOP_LIMIT {
+            RollupPeriod::Hour
+        } else {
+            RollupPeriod::Day
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            RollupPeriod::Hour => "hour",
+            RollupPeriod::Day => "day",
+        }
+    }
+
+    /// Start of the bucket holding `ts`, in the RFC 3339 form flows use.
+    fn bucket(self, ts: DateTime<Utc>) -> String {
+        match self {
+            RollupPeriod::Hour => ts.format("%Y-%m-%dT%H:00:00+00:00").to_string(),
+            RollupPeriod::Day => ts.format("%Y-%m-%dT00:00:00+00:00").to_string(),
+        }
+    }
+
+    /// SQL equivalent of [`RollupPeriod::bucket`] over `flows.ts_first`.
+    fn bucket_sql(self) -> &'static str {
+        match self {
+            RollupPeriod::Hour => "substr(ts_first, 1, 13) || ':00:00+00:00'",
+            RollupPeriod::Day => "substr(ts_first, 1, 10) || 'T00:00:00+00:00'",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RollupDimension {
+    Process,
+    Destination,
+    Protocol,
+    /// Destination port.
+    Port,
+    /// TLS SNI, else the DNS query name.
+    Domain,
+}
+
+impl RollupDimension {
+    const ALL: [RollupDimension; 5] = [
+        RollupDimension::Process,
+        RollupDimension::Destination,
+        RollupDimension::Protocol,
+        RollupDimension::Port,
+        RollupDimension::Domain,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            RollupDimension::Process => "process",
+            RollupDimension::Destination => "destination",
+            RollupDimension::Protocol => "protocol",
+            RollupDimension::Port => "port",
+            RollupDimension::Domain => "domain",
+        }
+    }
+
+    fn key(self, flow: &FlowEvent) -> Option<Cow<'_, str>> {
+        match self {
+            RollupDimension::Process => flow
+                .process
+                .as_ref()
+                .and_then(|p| p.name.as_deref())
+                .map(Cow::Borrowed),
+            RollupDimension::Destination => Some(Cow::Borrowed(flow.dst_ip.as_str())),
+            RollupDimension::Protocol => Some(Cow::Borrowed(flow.proto.as_str())),
+            RollupDimension::Port => Some(Cow::Owned(flow.dst_port.to_string())),
+            RollupDimension::Domain => flow_domain(flow).map(Cow::Owned),
+        }
+        .filter(|key| !key.is_empty())
+    }
+
+    /// Plaintext `flows` column the dimension is rebuilt from.
+    fn column(self) -> &'static str {
+        match self {
+            RollupDimension::Process => "process",
+            RollupDimension::Destination => "dst_ip",
+            RollupDimension::Protocol => "proto",
+            RollupDimension::Port => "dst_port",
+            RollupDimension::Domain => "domain",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RankBy {
+    #[default]
+    Bytes,
+    Flows,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopQuery {
+    pub dimension: RollupDimension,
+    /// Coarser periods scan fewer rows; hourly is exact to the hour.
+    #[serde(default)]
+    pub period: RollupPeriod,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    #[serde(default)]
+    pub rank_by: RankBy,
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RollupTotal {
+    pub key: String,
+    pub flows: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RollupBucket {
+    pub bucket: DateTime<Utc>,
+    pub flows: u64,
+    pub bytes: u64,
+}
+
+pub(crate) fn create_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS flow_rollups (
+            period TEXT NOT NULL,
+            bucket TEXT NOT NULL,
+            dimension TEXT NOT NULL,
+            key TEXT NOT NULL,
+            flows INTEGER NOT NULL,
+            bytes INTEGER NOT NULL,
+            PRIMARY KEY (period, dimension, bucket, key)
+        ) WITHOUT ROWID;
+        "#,
+    )?;
+    Ok(())
+}
+
+/// Adds one stored flow to every rollup; runs inside the insert transaction.
+pub(crate) fn record(conn: &Connection, flow: &FlowEvent) -> Result<()> {
+    add(conn, flow, 1)
+}
+
+/// Takes a stored flow back out of the rollups, e.g. before it is replaced
+/// by a merged version.
+pub(crate) fn retract(conn: &Connection, flow: &FlowEvent) -> Result<()> {
+    add(conn, flow, -1)
+}
+
+fn add(conn: &Connection, flow: &FlowEvent, sign: i64) -> Result<()> {
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO flow_rollups (period, bucket, dimension, key, flows, bytes) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT (period, dimension, bucket, key) DO UPDATE SET flows = flows + excluded.flows, bytes = bytes + excluded.bytes",
+    )?;
+    for period in [RollupPeriod::Hour, RollupPeriod::Day] {
+        let bucket = period.bucket(flow.ts_first);
+        for dimension in RollupDimension::ALL {
+            if let Some(key) = dimension.key(flow) {
+                stmt.execute(params![
+                    period.as_str(),
+                    bucket,
+                    dimension.as_str(),
+                    key,
+                    sign,
+                    sign * flow.bytes as i64
+                ])?;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Storage {
+    /// Builds the rollups from stored flows the first time a database
+    /// predating them is opened.
+    pub(crate) fn ensure_rollups(&self) -> Result<()> {
+        let built: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM storage_meta WHERE key = ?1",
+            params![META_ROLLUPS_BUILT],
+            |row| row.get(0),
+        )?;
+        if built == 0 {
+            self.rebuild_rollups()?;
+        }
+        Ok(())
+    }
+
+    /// Recomputes the rollups from the raw `flows` table, from the bucket
+    /// of the oldest stored flow onward. Older buckets, whose flows were
+    /// already pruned by retention, are kept, so aggregates outlive the raw
+    /// rows; the oldest recomputed bucket only counts the flows still stored.
+    pub fn rebuild_rollups(&self) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        let oldest: Option<String> =
+            tx.query_row("SELECT MIN(ts_first) FROM flows", [], |row| row.get(0))?;
+        if let Some(oldest) = oldest {
+            for period in [RollupPeriod::Hour, RollupPeriod::Day] {
+                let first_bucket =
+                    period.bucket(DateTime::parse_from_rfc3339(&oldest)?.with_timezone(&Utc));
+                tx.execute(
+                    "DELETE FROM flow_rollups WHERE period = ?1 AND bucket >= ?2",
+                    params![period.as_str(), first_bucket],
+                )?;
+                for dimension in RollupDimension::ALL {
+                    // Older schemas, mid-migration, lack the later columns.
+                    if !self.has_column("flows", dimension.column())? {
+                        continue;
+                    }
+                    tx.execute(
+                        &format!(
+                            "INSERT INTO flow_rollups (period, bucket, dimension, key, flows, bytes)
+                             SELECT ?1, {bucket}, ?2, {column}, COUNT(*), SUM(bytes) FROM flows
+                             WHERE {column} IS NOT NULL AND {column} != '' GROUP BY {bucket}, {column}",
+                            bucket = period.bucket_sql(),
+                            column = dimension.column(),
+                        ),
+                        params![period.as_str(), dimension.as_str()],
+                    )?;
+                }
+            }
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO storage_meta (key, value) VALUES (?1, ?2)",
+            params![META_ROLLUPS_BUILT, Utc::now().to_rfc3339()],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Heaviest keys of a dimension over `[from, to)`, summed over the
+    /// buckets starting in that range.
+    pub fn top(&self, query: &TopQuery) -> Result<Vec<RollupTotal>> {
+        let order = match query.rank_by {
+            RankBy::Bytes => "bytes",
+            RankBy::Flows => "flows",
+        };
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT key, SUM(flows) AS flows, SUM(bytes) AS bytes FROM flow_rollups
+             WHERE period = ?1 AND dimension = ?2 AND bucket >= ?3 AND bucket < ?4
+             GROUP BY key ORDER BY {order} DESC, key LIMIT ?5"
+        ))?;
+        let rows = stmt
+            .query_map(
+                params![
+                    query.period.as_str(),
+                    query.dimension.as_str(),
+                    query.period.bucket(query.from),
+                    query.to.to_rfc3339(),
+                    query.limit as i64
+                ],
+                |row| {
+                    Ok(RollupTotal {
+                        key: row.get(0)?,
+                        flows: row.get::<_, i64>(1)? as u64,
+                        bytes: row.get::<_, i64>(2)? as u64,
+                    })
+                },
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Destination addresses moving the most bytes over the last `period`.
+    pub fn top_talkers(&self, period: Duration, limit: usize) -> Result<Vec<RollupTotal>> {
+        self.top_recent(RollupDimension::Destination, period, limit)
+    }
+
+    /// Destination ports moving the most bytes over the last `period`.
+    pub fn top_ports(&self, period: Duration, limit: usize) -> Result<Vec<RollupTotal>> {
+        self.top_recent(RollupDimension::Port, period, limit)
+    }
+
+    /// Domains (SNI or DNS name) moving the most bytes over the last
+    /// `period`.
+    pub fn top_domains(&self, period: Duration, limit: usize) -> Result<Vec<RollupTotal>> {
+        self.top_recent(RollupDimension::Domain, period, limit)
+    }
+
+    /// [`Storage::top`] over the last `period`, by bytes. Spans up to two
+    /// days read hourly buckets and include the start of the first hour;
+    /// longer ones read daily buckets from midnight UTC of the first day.
+    fn top_recent(
+        &self,
+        dimension: RollupDimension,
+        period: Duration,
+        limit: usize,
+    ) -> Result<Vec<RollupTotal>> {
+        let to = Utc::now();
+        self.top(&TopQuery {
+            dimension,
+            period: RollupPeriod::for_span(period),
+            from: to - period,
+            to,
+            rank_by: RankBy::Bytes,
+            limit,
+        })
+    }
+
+    /// Per-bucket totals of one key, oldest first, for charts.
+    pub fn rollup_series(
+        &self,
+        period: RollupPeriod,
+        dimension: RollupDimension,
+        key: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<RollupBucket>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT bucket, flows, bytes FROM flow_rollups
+             WHERE period = ?1 AND dimension = ?2 AND key = ?3 AND bucket >= ?4 AND bucket < ?5
+             ORDER BY bucket",
+        )?;
+        let rows = stmt
+            .query_map(
+                params![
+                    period.as_str(),
+                    dimension.as_str(),
+                    key,
+                    period.bucket(from),
+                    to.to_rfc3339()
+                ],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, i64>(2)?,
+                    ))
+                },
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.into_iter()
+            .map(|(bucket, flows, bytes)| {
+                Ok(RollupBucket {
+                    bucket: DateTime::parse_from_rfc3339(&bucket)?.with_timezone(&Utc),
+                    flows: flows as u64,
+                    bytes: bytes as u64,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranks_ports_and_domains_and_survives_rebuild() {
+        let storage = Storage::open(":memory:", &[8u8; 32]).unwrap();
+        let now = Utc::now();
+        let flow = |dst_port: u16, sni: Option<&str>, dns: Option<&str>, bytes: u64| FlowEvent {
+            ts_first: now - Duration::minutes(5),
+            ts_last: now,
+            proto: "tcp".into(),
+            dst_ip: "198.51.100.4".into(),
+            dst_port,
+            sni: sni.map(str::to_string),
+            dns_qname: dns.map(str::to_string),
+            bytes,
+            ..FlowEvent::default()
+        };
+        storage
+            .put_flows(&[
+                flow(443, Some("Api.Example.com"), None, 500),
+                flow(443, None, Some("api.example.com."), 300),
+                flow(53, None, Some("cdn.example.org"), 40),
+                flow(22, None, None, 900),
+            ])
+            .unwrap();
+
+        let ports = storage.top_ports(Duration::hours(1), 10).unwrap();
+        let keys: Vec<&str> = ports.iter().map(|total| total.key.as_str()).collect();
+        assert_eq!(keys, ["22", "443", "53"]);
+        let domains = storage.top_domains(Duration::hours(1), 10).unwrap();
+        assert_eq!(domains[0].key, "api.example.com");
+        assert_eq!((domains[0].flows, domains[0].bytes), (2, 800));
+        assert_eq!(domains.len(), 2);
+        let talkers = storage.top_talkers(Duration::days(7), 10).unwrap();
+        assert_eq!(talkers[0].bytes, 1740);
+
+        storage.rebuild_rollups().unwrap();
+        assert_eq!(storage.top_ports(Duration::hours(1), 10).unwrap(), ports);
+        assert_eq!(
+            storage.top_domains(Duration::hours(1), 10).unwrap(),
+            domains
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use collector::FlowEvent;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crypto::FlowCipher, decompress, integrity, stored_flow, SealedRow, Storage, StoredFlow,
+    STORED_FLOW_COLUMNS, STORED_FLOW_COLUMN_COUNT,
+};
+
+/// Rows re-encrypted per transaction during a key rotation.
+const ROTATION_BATCH: usize = 500;
+const META_ROTATION_TARGET: &str = "rotation_target";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RotationProgress {
+    pub rotated: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RotationReport {
+    pub old_key_id: String,
+    pub new_key_id: String,
+    pub rotated: usize,
+    /// Rows that could not be decrypted (tampered with, or sealed under a
+    /// key this connection does not know). They are left as they were and
+    /// stay unreadable; `db check` lists them too.
+    #[serde(default)]
+    pub failed: Vec<i64>,
+    pub elapsed: Duration,
+}
+
+impl Storage {
+    pub fn rotate_key(&mut self, old: &[u8], new: &[u8]) -> Result<RotationReport> {
+        self.rotate_key_with_progress(old, new, |_| {})
+    }
+
+    /// Re-encrypts every flow blob under `new` and re-signs its row MAC,
+    /// committing in batches. A row whose columns fail their MAC is
+    /// re-sealed without one, so it keeps failing verification; a row that
+    /// never got one is signed if its columns match the payload; a row that
+    /// cannot be decrypted is skipped and listed in the report. Each
+    /// row records the id of the key it is sealed under, so a rotation that
+    /// was interrupted leaves a readable mixed-key database (open it with
+    /// the new key and [`Storage::add_read_key`] the old one) and is resumed
+    /// by calling this again.
+    pub fn rotate_key_with_progress(
+        &mut self,
+        old: &[u8],
+        new: &[u8],
+        mut progress: impl FnMut(RotationProgress),
+    ) -> Result<RotationReport> {
+        let started = Instant::now();
+        let old = FlowCipher::new(old)?;
+        let new = FlowCipher::new(new)?;
+        let old_key_id = old.key_id().to_string();
+        let new_key_id = new.key_id().to_string();
+        if old_key_id == new_key_id {
+            return Err(anyhow!("new key is identical to the old key"));
+        }
+
+        // Rows without a key id are sealed under the key the database was
+        // opened with; pin them before that stops being the active key.
+        self.conn.execute(
+            "UPDATE flows SET key_id = ?1 WHERE key_id IS NULL AND ciphertext IS NOT NULL",
+            params![self.cipher.key_id()],
+        )?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO storage_meta (key, value) VALUES (?1, ?2)",
+            params![META_ROTATION_TARGET, new_key_id],
+        )?;
+        let previous = std::mem::replace(&mut self.cipher, new);
+        self.retired.remove(&new_key_id);
+        for cipher in [previous, old] {
+            if cipher.key_id() != new_key_id {
+                self.retired.insert(cipher.key_id().to_string(), cipher);
+            }
+        }
+
+        let total: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM flows WHERE ciphertext IS NOT NULL AND key_id != ?1",
+            params![new_key_id],
+            |row| row.get(0),
+        )?;
+        let total = total as usize;
+        let mut rotated = 0;
+        let mut failed = Vec::new();
+        let mut after = 0i64;
+        progress(RotationProgress { rotated, total });
+        loop {
+            let tx = self.conn.unchecked_transaction()?;
+            let rows = {
+                let mut stmt = tx.prepare(&format!(
+                    "SELECT {STORED_FLOW_COLUMNS}, row_mac, {} FROM flows WHERE ciphertext IS NOT NULL AND key_id != ?1 AND id > ?2 ORDER BY id LIMIT ?3",
+                    SealedRow::COLUMNS
+                ))?;
+                let rows = stmt
+                    .query_map(params![new_key_id, after, ROTATION_BATCH as i64], |row| {
+                        Ok((
+                            stored_flow(row)?,
+                            row.get::<_, Option<Vec<u8>>>(STORED_FLOW_COLUMN_COUNT)?,
+                            SealedRow::read(row, STORED_FLOW_COLUMN_COUNT + 1)?,
+                        ))
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                rows
+            };
+            let Some((last, _, _)) = rows.last() else {
+                break;
+            };
+            after = last.id;
+            for (columns, row_mac, sealed) in rows {
+                let id = columns.id;
+                let compression = sealed.compression;
+                let verified = self.verify_row_mac(
+                    &columns,
+                    row_mac.as_deref(),
+                    sealed.key_id.as_deref(),
+                    sealed.nonce.as_deref(),
+                );
+                let plaintext = match self.open_sealed(id, sealed) {
+                    Ok(plaintext) => plaintext,
+                    Err(err) => {
+                        tracing::warn!(id, %err, "cannot decrypt the row; leaving it as it is");
+                        failed.push(id);
+                        continue;
+                    }
+                };
+                let intact = match (verified, row_mac) {
+                    (Ok(()), _) => true,
+                    // Left unsigned by the upgrade, which could not decrypt it.
+                    (Err(_), None) => decompress(id, compression, plaintext.clone())
+                        .ok()
+                        .and_then(|payload| serde_json::from_slice::<FlowEvent>(&payload).ok())
+                        .is_some_and(|flow| columns == StoredFlow::columns(id, &flow)),
+                    (Err(_), Some(_)) => false,
+                };
+                let resealed = self.cipher.seal(id, &plaintext)?;
+                let row_mac = intact
+                    .then(|| integrity::row_mac(&self.cipher, &columns, Some(&resealed.nonce)));
+                if !intact {
+                    tracing::warn!(
+                        id,
+                        "row MAC does not verify; re-sealing the row without one"
+                    );
+                }
+                tx.execute(
+                    "UPDATE flows SET ciphertext = ?1, nonce = ?2, enc_version = ?3, key_id = ?4, row_mac = ?5 WHERE id = ?6",
+                    params![
+                        resealed.ciphertext,
+                        resealed.nonce,
+                        resealed.version,
+                        resealed.key_id,
+                        row_mac,
+                        id
+                    ],
+                )?;
+                rotated += 1;
+            }
+            tx.commit()?;
+            progress(RotationProgress {
+                rotated,
+                total: total.saturating_sub(failed.len()).max(rotated),
+            });
+        }
+
+        let resigned = self.resign_audit_log()?;
+        tracing::debug!(resigned, "audit log re-signed under the new key");
+        self.conn.execute(
+            "DELETE FROM storage_meta WHERE key = ?1",
+            params![META_ROTATION_TARGET],
+        )?;
+        tracing::info!(rotated, failed = failed.len(), old = %old_key_id, new = %new_key_id, "key rotation complete");
+        Ok(RotationReport {
+            old_key_id,
+            new_key_id,
+            rotated,
+            failed,
+            elapsed: started.elapsed(),
+        })
+    }
+
+    /// Id of the key an unfinished rotation was moving to, if any.
+    pub fn pending_rotation(&self) -> Result<Option<String>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT value FROM storage_meta WHERE key = ?1",
+                params![META_ROTATION_TARGET],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    pub fn active_key_id(&self) -> &str {
+        self.cipher.key_id()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_rows_it_cannot_decrypt() {
+        let mut storage = Storage::open(":memory:", &[1u8; 32]).unwrap();
+        let flow = FlowEvent {
+            dst_ip: "203.0.113.9".into(),
+            ..FlowEvent::default()
+        };
+        let ids = storage.put_flows(&vec![flow; 3]).unwrap();
+        storage
+            .conn
+            .execute(
+                "UPDATE flows SET ciphertext = zeroblob(length(ciphertext)) WHERE id = ?1",
+                params![ids[1]],
+            )
+            .unwrap();
+
+        let report = storage.rotate_key(&[1u8; 32], &[2u8; 32]).unwrap();
+        assert_eq!(report.rotated, 2);
+        assert_eq!(report.failed, [ids[1]]);
+        assert_eq!(storage.pending_rotation().unwrap(), None);
+        assert!(storage.get_flow(ids[0]).is_ok());
+        assert!(storage.get_flow(ids[2]).is_ok());
+        let key_id: String = storage
+            .conn
+            .query_row(
+                "SELECT key_id FROM flows WHERE id = ?1",
+                params![ids[1]],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(key_id, report.old_key_id);
+    }
+}
@@ -0,0 +1,146 @@
+use analyzer::Alert;
+use anyhow::Result;
+use rusqlite::{types::Value, Connection, OptionalExtension};
+
+use crate::{Storage, ALERT_COLUMNS};
+
+/// FTS5 index over alert text, kept in sync with `alerts` by triggers.
+pub(crate) fn create_index(conn: &Connection) -> Result<()> {
+    let exists = conn
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE name = 'alerts_fts'",
+            [],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some();
+    conn.execute_batch(
+        r#"
+        CREATE VIRTUAL TABLE IF NOT EXISTS alerts_fts USING fts5(
+            rule_id, summary, rationale,
+            content = 'alerts', content_rowid = 'rowid'
+        );
+        CREATE TRIGGER IF NOT EXISTS alerts_fts_insert AFTER INSERT ON alerts BEGIN
+            INSERT INTO alerts_fts (rowid, rule_id, summary, rationale)
+            VALUES (new.rowid, new.rule_id, new.summary, new.rationale);
+        END;
+        CREATE TRIGGER IF NOT EXISTS alerts_fts_delete AFTER DELETE ON alerts BEGIN
+            INSERT INTO alerts_fts (alerts_fts, rowid, rule_id, summary, rationale)
+            VALUES ('delete', old.rowid, old.rule_id, old.summary, old.rationale);
+        END;
+        CREATE TRIGGER IF NOT EXISTS alerts_fts_update AFTER UPDATE OF rule_id, summary, rationale ON alerts BEGIN
+            INSERT INTO alerts_fts (alerts_fts, rowid, rule_id, summary, rationale)
+            VALUES ('delete', old.rowid, old.rule_id, old.summary, old.rationale);
+            INSERT INTO alerts_fts (rowid, rule_id, summary, rationale)
+            VALUES (new.rowid, new.rule_id, new.summary, new.rationale);
+        END;
+        "#,
+    )?;
+    if !exists {
+        conn.execute_batch("INSERT INTO alerts_fts (alerts_fts) VALUES ('rebuild');")?;
+    }
+    Ok(())
+}
+
+/// Turns free text into an FTS5 query: every whitespace-separated term must
+/// match, as a phrase (so `evil.example.com` or `cmd.exe` need no escaping),
+/// and a trailing `*` keeps prefix matching (`power*`).
+fn match_expression(text: &str) -> Option<String> {
+    let terms: Vec<String> = text
+        .split_whitespace()
+        .filter_map(|term| {
+            let (body, prefix) = match term.strip_suffix('*') {
+                Some(body) => (body, "*"),
+                None => (term, ""),
+            };
+            (!body.is_empty()).then(|| format!("\"{}\"{prefix}", body.replace('"', "\"\"")))
+        })
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+impl Storage {
+    /// Alerts whose rule id, summary or rationale contain every term of
+    /// `text`, best matches first.
+    pub fn search_alerts(&self, text: &str, limit: usize) -> Result<Vec<Alert>> {
+        let Some(expression) = match_expression(text) else {
+            return Ok(Vec::new());
+        };
+        self.read_alerts(
+            &format!(
+                "SELECT {ALERT_COLUMNS} FROM alerts JOIN (SELECT rowid AS hit, rank FROM alerts_fts WHERE alerts_fts MATCH ?) ON alerts.rowid = hit ORDER BY rank, ts DESC LIMIT ?"
+            ),
+            vec![Value::Text(expression), Value::Integer(limit as i64)],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use analyzer::{AlertStatus, Severity};
+    use chrono::Utc;
+
+    fn alert(id: &str, rule_id: &str, summary: &str) -> Alert {
+        Alert {
+            id: id.into(),
+            ts: Utc::now(),
+            severity: Severity::Medium,
+            rule_id: rule_id.into(),
+            summary: summary.into(),
+            flow_refs: Vec::new(),
+            process_ref: None,
+            rationale: String::new(),
+            suggested_action: None,
+            status: AlertStatus::New,
+            assignee: None,
+            notes: Vec::new(),
+            evidence: Default::default(),
+        }
+    }
+
+    #[test]
+    fn finds_terms_and_domains_and_follows_updates() {
+        let storage = Storage::open(":memory:", &[9u8; 32]).unwrap();
+        storage
+            .put_alert(&alert(
+                "a",
+                "proc.lolbin",
+                "powershell.exe contacted evil.example.com",
+            ))
+            .unwrap();
+        storage
+            .put_alert(&alert(
+                "b",
+                "dns.nxdomain",
+                "burst of NXDOMAIN for example.org",
+            ))
+            .unwrap();
+
+        let ids = |text: &str| -> Vec<String> {
+            storage
+                .search_alerts(text, 10)
+                .unwrap()
+                .into_iter()
+                .map(|alert| alert.id)
+                .collect()
+        };
+        assert_eq!(ids("PowerShell"), ["a"]);
+        assert_eq!(ids("evil.example.com"), ["a"]);
+        assert_eq!(ids("nxdom*"), ["b"]);
+        let mut both = ids("example");
+        both.sort();
+        assert_eq!(both, ["a", "b"]);
+        assert!(ids("\"").is_empty());
+
+        storage
+            .put_alert(&alert(
+                "a",
+                "proc.lolbin",
+                "rundll32 contacted evil.example.com",
+            ))
+            .unwrap();
+        assert!(ids("powershell").is_empty());
+        assert_eq!(ids("rundll32"), ["a"]);
+    }
+}
@@ -0,0 +1,276 @@
+use analyzer::Alert;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use collector::FlowEvent;
+use rusqlite::{params, types::Value, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::{timestamp_column, FlowReadError, Storage, ALERT_COLUMNS};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpoolKind {
+    Flow,
+    Alert,
+}
+
+impl SpoolKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            SpoolKind::Flow => "flow",
+            SpoolKind::Alert => "alert",
+        }
+    }
+}
+
+/// One batch as sent to the remote end, JSON-encoded. Delivery is at
+/// least once: a retried batch may have grown by newer entries, and a
+/// merged flow is sent again, so receivers de-duplicate records by host
+/// and id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportBatch {
+    /// Machine the records come from.
+    pub host: String,
+    pub first_seq: i64,
+    pub last_seq: i64,
+    pub flows: Vec<ExportedFlow>,
+    pub alerts: Vec<Alert>,
+}
+
+impl ExportBatch {
+    pub fn is_empty(&self) -> bool {
+        self.flows.is_empty() && self.alerts.is_empty()
+    }
+}
+
+/// A flow with its row id on the sending machine; a flow merged by
+/// [`Storage::upsert_flows`] after it was sent is sent again under the same id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedFlow {
+    pub id: i64,
+    #[serde(flatten)]
+    pub flow: FlowEvent,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpoolStatus {
+    pub pending: u64,
+    pub oldest: Option<DateTime<Utc>>,
+    /// Failed delivery attempts of the oldest entry, and the last error.
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+
+/// Delivers a batch to the remote end; `Ok` means it was accepted and the
+/// entries may be dropped from the spool.
+pub trait ExportTransport: Send {
+    fn send(&self, batch: &ExportBatch, body: &[u8]) -> Result<()>;
+}
+
+pub(crate) fn create_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS export_spool (
+            seq INTEGER PRIMARY KEY AUTOINCREMENT,
+            kind TEXT NOT NULL,
+            record_id TEXT NOT NULL,
+            enqueued_at TEXT NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            last_error TEXT
+        );
+        "#,
+    )?;
+    Ok(())
+}
+
+pub(crate) fn drop_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch("DROP TABLE IF EXISTS export_spool;")?;
+    Ok(())
+}
+
+/// Queues a stored record for shipping; runs inside the write transaction.
+/// The spool holds references, so the record goes out in its current form
+/// and the payload stays encrypted at rest.
+pub(crate) fn enqueue(conn: &Connection, kind: SpoolKind, record_id: &str) -> Result<()> {
+    conn.prepare_cached(
+        "INSERT INTO export_spool (kind, record_id, enqueued_at) VALUES (?1, ?2, ?3)",
+    )?
+    .execute(params![kind.as_str(), record_id, Utc::now().to_rfc3339()])?;
+    Ok(())
+}
+
+impl Storage {
+    pub fn spool_status(&self) -> Result<SpoolStatus> {
+        let pending: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM export_spool", [], |row| row.get(0))?;
+        let head = self
+            .conn
+            .query_row(
+                "SELECT enqueued_at, attempts, last_error FROM export_spool ORDER BY seq LIMIT 1",
+                [],
+                |row| Ok((timestamp_column(row, 0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?;
+        let (oldest, attempts, last_error) = match head {
+            Some((oldest, attempts, last_error)) => (Some(oldest), attempts, last_error),
+            None => (None, 0, None),
+        };
+        Ok(SpoolStatus {
+            pending: pending as u64,
+            oldest,
+            attempts,
+            last_error,
+        })
+    }
+
+    /// The oldest `limit` spool entries with their records read back.
+    /// Records deleted since they were queued (e.g. by retention) or that
+    /// cannot be read are left out, but their entries still count towards
+    /// the batch so they are dropped with it. `None` if the spool is empty.
+    pub fn next_export_batch(&self, host: &str, limit: usize) -> Result<Option<ExportBatch>> {
+        let entries = self
+            .conn
+            .prepare_cached("SELECT seq, kind, record_id FROM export_spool ORDER BY seq LIMIT ?1")?
+            .query_map(params![limit as i64], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let (Some(first), Some(last)) = (entries.first(), entries.last()) else {
+            return Ok(None);
+        };
+        let mut batch = ExportBatch {
+            host: host.to_string(),
+            first_seq: first.0,
+            last_seq: last.0,
+            flows: Vec::new(),
+            alerts: Vec::new(),
+        };
+        for (seq, kind, record_id) in entries {
+            match kind.as_str() {
+                "flow" => {
+                    let id: i64 = record_id.parse()?;
+                    match self.get_flow(id) {
+                        Ok(flow) => batch.flows.push(ExportedFlow { id, flow }),
+                        Err(err) => match err.downcast_ref::<FlowReadError>() {
+                            Some(FlowReadError::NotFound(_)) => {}
+                            _ => tracing::warn!(seq, id, %err, "skipping unreadable spooled flow"),
+                        },
+                    }
+                }
+                "alert" => batch.alerts.extend(self.read_alerts(
+                    &format!("SELECT {ALERT_COLUMNS} FROM alerts WHERE id = ?"),
+                    vec![Value::Text(record_id)],
+                )?),
+                other => tracing::warn!(seq, kind = other, "skipping unknown spool entry"),
+            }
+        }
+        Ok(Some(batch))
+    }
+
+    /// Sends the next batch through `transport` and drops its entries once
+    /// accepted. A failure is recorded on the entries and returned; they
+    /// stay queued for the next attempt. Returns the number of entries
+    /// shipped, 0 when the spool is empty.
+    pub fn ship_export_batch(
+        &self,
+        transport: &dyn ExportTransport,
+        host: &str,
+        limit: usize,
+    ) -> Result<usize> {
+        let Some(batch) = self.next_export_batch(host, limit)? else {
+            return Ok(0);
+        };
+        if !batch.is_empty() {
+            let body = serde_json::to_vec(&batch)?;
+            if let Err(err) = transport.send(&batch, &body) {
+                self.conn.execute(
+                    "UPDATE export_spool SET attempts = attempts + 1, last_error = ?3 WHERE seq BETWEEN ?1 AND ?2",
+                    params![batch.first_seq, batch.last_seq, format!("{err:#}")],
+                )?;
+                return Err(err);
+            }
+        }
+        let shipped = self.conn.execute(
+            "DELETE FROM export_spool WHERE seq BETWEEN ?1 AND ?2",
+            params![batch.first_seq, batch.last_seq],
+        )?;
+        Ok(shipped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    };
+
+    use super::*;
+    use crate::StorageOptions;
+
+    #[derive(Default)]
+    struct Recorder {
+        down: AtomicBool,
+        sent: Mutex<Vec<ExportBatch>>,
+    }
+
+    impl ExportTransport for Recorder {
+        fn send(&self, batch: &ExportBatch, body: &[u8]) -> Result<()> {
+            if self.down.load(Ordering::SeqCst) {
+                anyhow::bail!("connection refused");
+            }
+            let decoded: ExportBatch = serde_json::from_slice(body)?;
+            assert_eq!(decoded.last_seq, batch.last_seq);
+            self.sent.lock().unwrap().push(decoded);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn keeps_entries_until_delivered() {
+        let options = StorageOptions {
+            spool: true,
+            ..StorageOptions::default()
+        };
+        let storage = Storage::open_with_options(":memory:", &[9u8; 32], options).unwrap();
+        let ids = storage.put_flows(&vec![FlowEvent::default(); 3]).unwrap();
+        storage
+            .conn
+            .execute("DELETE FROM flows WHERE id = ?1", params![ids[2]])
+            .unwrap();
+        assert_eq!(storage.spool_status().unwrap().pending, 3);
+
+        let transport = Recorder::default();
+        transport.down.store(true, Ordering::SeqCst);
+        assert!(storage.ship_export_batch(&transport, "host-a", 2).is_err());
+        let status = storage.spool_status().unwrap();
+        assert_eq!((status.pending, status.attempts), (3, 1));
+        assert!(status.last_error.unwrap().contains("refused"));
+
+        transport.down.store(false, Ordering::SeqCst);
+        assert_eq!(
+            storage.ship_export_batch(&transport, "host-a", 2).unwrap(),
+            2
+        );
+        // The pruned flow's entry is dropped without anything to send.
+        assert_eq!(
+            storage.ship_export_batch(&transport, "host-a", 2).unwrap(),
+            1
+        );
+        assert_eq!(
+            storage.ship_export_batch(&transport, "host-a", 2).unwrap(),
+            0
+        );
+        let sent = transport.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(
+            sent[0].flows.iter().map(|f| f.id).collect::<Vec<_>>(),
+            ids[..2]
+        );
+    }
+}
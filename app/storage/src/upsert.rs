@@ -0,0 +1,239 @@
+use anyhow::Result;
+use chrono::Duration;
+use collector::{FlowEvent, FlowRisk};
+use rusqlite::{params, OptionalExtension};
+
+use crate::{
+    insert_flow, integrity, rollup, stored_flow, SealedRow, Storage, StoredFlow,
+    STORED_FLOW_COLUMNS, STORED_FLOW_COLUMN_COUNT,
+};
+
+impl Storage {
+    /// Stores `flows` like [`Storage::put_flows`], except that an
+    /// observation of a 5-tuple already stored with a gap of at most
+    /// `window` to it is merged into that row instead of adding one: bytes
+    /// and packets add up and the row's time span grows to cover both.
+    /// Meant for polling collectors that report each live connection on
+    /// every poll with the traffic since the previous one. Returns the row
+    /// id each observation ended up in; merged rows keep their id, so alert
+    /// references stay valid.
+    pub fn upsert_flows(&self, flows: &[FlowEvent], window: Duration) -> Result<Vec<i64>> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut ids = Vec::with_capacity(flows.len());
+        for flow in flows {
+            let id = match self.merge_target(flow, window)? {
+                Some((id, stored)) => {
+                    self.replace_flow(id, &stored, &merge(stored.clone(), flow))?;
+                    id
+                }
+                None => insert_flow(&tx, &self.cipher, self.compression, flow)?,
+            };
+            ids.push(id);
+        }
+        let mut touched = ids.clone();
+        touched.sort_unstable();
+        touched.dedup();
+        self.spool_flows(&tx, &touched)?;
+        tx.commit()?;
+        self.writes.borrow_mut().flows(flows.len());
+        Ok(ids)
+    }
+
+    /// Records the risk the analyzer assessed for a stored flow. A row that
+    /// already carries a higher risk keeps it, as merged rows do.
+    pub fn set_flow_risk(&self, id: i64, risk: FlowRisk) -> Result<()> {
+        let stored = self.get_flow(id)?;
+        if stored
+            .risk
+            .as_ref()
+            .is_some_and(|current| current.score >= risk.score)
+        {
+            return Ok(());
+        }
+        let assessed = FlowEvent {
+            risk: Some(risk),
+            ..stored.clone()
+        };
+        let tx = self.conn.unchecked_transaction()?;
+        self.replace_flow(id, &stored, &assessed)?;
+        self.spool_flows(&tx, &[id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Latest stored flow of the same 5-tuple within `window` of `flow`.
+    fn merge_target(&self, flow: &FlowEvent, window: Duration) -> Result<Option<(i64, FlowEvent)>> {
+        let row = self
+            .conn
+            .prepare_cached(&format!(
+                "SELECT {STORED_FLOW_COLUMNS}, row_mac, {} FROM flows
+                 WHERE dst_ip = ?1 AND dst_port = ?2 AND src_ip = ?3 AND src_port = ?4 AND proto = ?5
+                   AND ts_last >= ?6 AND ts_first <= ?7
+                 ORDER BY ts_last DESC LIMIT 1",
+                SealedRow::COLUMNS
+            ))?
+            .query_row(
+                params![
+                    flow.dst_ip,
+                    flow.dst_port,
+                    flow.src_ip,
+                    flow.src_port,
+                    flow.proto,
+                    (flow.ts_first - window).to_rfc3339(),
+                    (flow.ts_last + window).to_rfc3339(),
+                ],
+                |row| {
+                    Ok((
+                        stored_flow(row)?,
+                        row.get::<_, Option<Vec<u8>>>(STORED_FLOW_COLUMN_COUNT)?,
+                        SealedRow::read(row, STORED_FLOW_COLUMN_COUNT + 1)?,
+                    ))
+                },
+            )
+            .optional()?;
+        let Some((meta, row_mac, sealed)) = row else {
+            return Ok(None);
+        };
+        let id = meta.id;
+        match self.report_tampering(self.verified_flow(&meta, row_mac.as_deref(), sealed)) {
+            Ok(stored) => Ok(Some((id, stored))),
+            Err(err) => {
+                // Never fold new traffic into a row that fails verification;
+                // leave it for `check` and start a new one.
+                tracing::warn!(id, %err, "cannot merge into stored flow");
+                Ok(None)
+            }
+        }
+    }
+
+    fn replace_flow(&self, id: i64, stored: &FlowEvent, merged: &FlowEvent) -> Result<()> {
+        rollup::retract(&self.conn, stored)?;
+        let sealed = self
+            .cipher
+            .seal(id, &self.compression.compress(serde_json::to_vec(merged)?)?)?;
+        let columns = StoredFlow::columns(id, merged);
+        self.conn
+            .prepare_cached(
+                "UPDATE flows SET ts_first = ?2, ts_last = ?3, bytes = ?4, ciphertext = ?5, nonce = ?6, enc_version = ?7, key_id = ?8, direction = ?9, process = ?10, compression = ?11, domain = ?12, row_mac = ?13 WHERE id = ?1",
+            )?
+            .execute(params![
+                id,
+                columns.ts_first.to_rfc3339(),
+                columns.ts_last.to_rfc3339(),
+                columns.bytes,
+                sealed.ciphertext,
+                sealed.nonce,
+                sealed.version,
+                sealed.key_id,
+                columns.direction,
+                columns.process,
+                self.compression.flag(),
+                columns.domain,
+                integrity::row_mac(&self.cipher, &columns, Some(&sealed.nonce)),
+            ])?;
+        rollup::record(&self.conn, merged)
+    }
+}
+
+/// Folds a re-observation into the stored flow. Counters add up, the
+/// latest connection state wins, the highest risk is kept, and fields only
+/// one observation carried (process, TLS, DNS) are filled in.
+fn merge(mut stored: FlowEvent, observed: &FlowEvent) -> FlowEvent {
+    stored.ts_first = stored.ts_first.min(observed.ts_first);
+    stored.ts_last = stored.ts_last.max(observed.ts_last);
+    stored.bytes = stored.bytes.saturating_add(observed.bytes);
+    stored.packets = stored.packets.saturating_add(observed.packets);
+    if observed.state.is_some() {
+        stored.state = observed.state.clone();
+    }
+    let riskier = match (&stored.risk, &observed.risk) {
+        (Some(stored), Some(observed)) => observed.score > stored.score,
+        (None, Some(_)) => true,
+        _ => false,
+    };
+    if riskier {
+        stored.risk = observed.risk.clone();
+    }
+    fill(&mut stored.iface, &observed.iface);
+    fill(&mut stored.process, &observed.process);
+    fill(&mut stored.layer2, &observed.layer2);
+    fill(&mut stored.sni, &observed.sni);
+    fill(&mut stored.alpn, &observed.alpn);
+    fill(&mut stored.ja3, &observed.ja3);
+    fill(&mut stored.ja4, &observed.ja4);
+    fill(&mut stored.dns_qname, &observed.dns_qname);
+    fill(&mut stored.dns_qtype, &observed.dns_qtype);
+    fill(&mut stored.dns_rcode, &observed.dns_rcode);
+    for answer in &observed.dns_answers {
+        if !stored.dns_answers.contains(answer) {
+            stored.dns_answers.push(answer.clone());
+        }
+    }
+    stored
+}
+
+fn fill<T: Clone>(slot: &mut Option<T>, other: &Option<T>) {
+    if slot.is_none() {
+        *slot = other.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rollup::{RollupDimension, RollupPeriod, TopQuery};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn merges_observations_within_window() {
+        let storage = Storage::open(":memory:", &[6u8; 32]).unwrap();
+        let start = Utc.with_ymd_and_hms(2024, 5, 1, 10, 0, 0).unwrap();
+        let poll = |secs: i64, bytes: u64| FlowEvent {
+            ts_first: start + Duration::seconds(secs),
+            ts_last: start + Duration::seconds(secs + 5),
+            proto: "tcp".into(),
+            dst_ip: "203.0.113.7".into(),
+            dst_port: 443,
+            bytes,
+            packets: 2,
+            ..FlowEvent::default()
+        };
+        let window = Duration::seconds(30);
+        let mut with_sni = poll(10, 200);
+        with_sni.sni = Some("example.net".into());
+        let ids = storage
+            .upsert_flows(&[poll(0, 100), with_sni], window)
+            .unwrap();
+        assert_eq!(ids[0], ids[1]);
+        let merged_id = ids[0];
+        let merged = storage.get_flow(merged_id).unwrap();
+        assert_eq!((merged.bytes, merged.packets), (300, 4));
+        assert_eq!(merged.ts_first, start);
+        assert_eq!(merged.ts_last, start + Duration::seconds(15));
+        assert_eq!(merged.sni.as_deref(), Some("example.net"));
+
+        // A later poll past the window, and another port, get rows of their own.
+        let other_port = FlowEvent {
+            dst_port: 8443,
+            ..poll(20, 50)
+        };
+        let ids = storage
+            .upsert_flows(&[poll(120, 10), other_port], window)
+            .unwrap();
+        assert!(!ids.contains(&merged_id));
+        assert_ne!(ids[0], ids[1]);
+        assert_eq!(storage.query_flows(&Default::default()).unwrap().len(), 3);
+
+        let top = storage
+            .top(&TopQuery {
+                dimension: RollupDimension::Destination,
+                period: RollupPeriod::Hour,
+                from: start,
+                to: start + Duration::hours(1),
+                rank_by: Default::default(),
+                limit: 5,
+            })
+            .unwrap();
+        assert_eq!((top[0].flows, top[0].bytes), (3, 360));
+    }
+}
@@ -0,0 +1,246 @@
+use std::thread;
+
+use analyzer::Alert;
+use anyhow::{anyhow, Result};
+use collector::{FlowEvent, FlowRisk};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{AlertQuery, FlowQuery, Storage, StorageMetrics, StoredFlow};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WriterConfig {
+    /// Queued writes before `put_*` starts waiting for the writer.
+    pub channel_capacity: usize,
+    /// Flows committed per transaction.
+    pub max_batch: usize,
+    /// Re-observations of a stored 5-tuple within this many seconds are
+    /// merged into its row ([`Storage::upsert_flows`]); 0 stores every
+    /// observation as a row of its own.
+    pub merge_window_secs: u64,
+}
+
+impl Default for WriterConfig {
+    fn default() -> Self {
+        Self {
+            channel_capacity: 4096,
+            max_batch: 1000,
+            merge_window_secs: 0,
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce(&mut Storage) + Send>;
+
+enum Command {
+    Flow(Box<FlowEvent>),
+    /// A flow whose row id the caller waits for.
+    Stored(Box<FlowEvent>, oneshot::Sender<Result<i64>>),
+    Risk(i64, Box<FlowRisk>),
+    Alert(Box<Alert>),
+    /// Runs after every write queued before it has been committed.
+    Call(Job),
+}
+
+/// Async facade over [`Storage`]: the connection lives on a dedicated
+/// thread that drains a bounded channel, committing queued flows in
+/// batches, so async callers never block on SQLite. Writes are
+/// fire-and-forget (failures are logged); queries see all earlier writes.
+#[derive(Clone)]
+pub struct AsyncStorage {
+    tx: mpsc::Sender<Command>,
+}
+
+impl AsyncStorage {
+    /// Moves `storage` onto a writer thread. The thread exits once every
+    /// handle is dropped and the queue is drained.
+    pub fn spawn(storage: Storage, config: WriterConfig) -> Result<Self> {
+        let (tx, rx) = mpsc::channel(config.channel_capacity.max(1));
+        thread::Builder::new()
+            .name("nets-storage".into())
+            .spawn(move || run_writer(storage, rx, config))?;
+        Ok(Self { tx })
+    }
+
+    pub async fn put_flow(&self, flow: FlowEvent) -> Result<()> {
+        self.send(Command::Flow(Box::new(flow))).await
+    }
+
+    /// Stores `flow` right away, after the flows queued before it, and
+    /// returns its row id (with a merge window, the id of the row it was
+    /// merged into), for callers whose alerts must reference the flow.
+    pub async fn store_flow(&self, flow: FlowEvent) -> Result<i64> {
+        let (reply, id) = oneshot::channel();
+        self.send(Command::Stored(Box::new(flow), reply)).await?;
+        id.await.map_err(|_| anyhow!("storage writer stopped"))?
+    }
+
+    /// Records the risk assessed for a stored flow ([`Storage::set_flow_risk`]).
+    pub async fn set_flow_risk(&self, id: i64, risk: FlowRisk) -> Result<()> {
+        self.send(Command::Risk(id, Box::new(risk))).await
+    }
+
+    pub async fn put_alert(&self, alert: Alert) -> Result<()> {
+        self.send(Command::Alert(Box::new(alert))).await
+    }
+
+    /// Runs `f` on the writer thread and returns its result.
+    pub async fn call<R, F>(&self, f: F) -> Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut Storage) -> Result<R> + Send + 'static,
+    {
+        let (reply, result) = oneshot::channel();
+        self.send(Command::Call(Box::new(move |storage| {
+            let _ = reply.send(f(storage));
+        })))
+        .await?;
+        result
+            .await
+            .map_err(|_| anyhow!("storage writer stopped"))?
+    }
+
+    /// Waits until every write queued so far is committed.
+    pub async fn flush(&self) -> Result<()> {
+        self.call(|_| Ok(())).await
+    }
+
+    pub async fn query_flows(&self, query: FlowQuery) -> Result<Vec<StoredFlow>> {
+        self.call(move |storage| storage.query_flows(&query)).await
+    }
+
+    pub async fn query_alerts(&self, query: AlertQuery) -> Result<Vec<Alert>> {
+        self.call(move |storage| storage.query_alerts(&query)).await
+    }
+
+    pub async fn count_alerts(&self, query: AlertQuery) -> Result<usize> {
+        self.call(move |storage| storage.count_alerts(&query)).await
+    }
+
+    /// Storage metrics, with the throughput of the writes queued here.
+    pub async fn metrics(&self) -> Result<StorageMetrics> {
+        self.call(|storage| storage.metrics()).await
+    }
+
+    async fn send(&self, command: Command) -> Result<()> {
+        self.tx
+            .send(command)
+            .await
+            .map_err(|_| anyhow!("storage writer stopped"))
+    }
+}
+
+fn run_writer(mut storage: Storage, mut rx: mpsc::Receiver<Command>, config: WriterConfig) {
+    let max_batch = config.max_batch.max(1);
+    let merge_window = (config.merge_window_secs > 0)
+        .then(|| chrono::Duration::seconds(config.merge_window_secs as i64));
+    let mut flows = Vec::with_capacity(max_batch);
+    while let Some(command) = rx.blocking_recv() {
+        let mut next = Some(command);
+        // Take whatever else is already queued, committing flows whenever
+        // the batch fills or something must observe them.
+        while let Some(command) = next.take() {
+            match command {
+                Command::Flow(flow) => {
+                    flows.push(*flow);
+                    if flows.len() >= max_batch {
+                        commit_flows(&storage, &mut flows, merge_window);
+                    }
+                }
+                Command::Stored(flow, reply) => {
+                    commit_flows(&storage, &mut flows, merge_window);
+                    let flow = std::slice::from_ref(flow.as_ref());
+                    let ids = match merge_window {
+                        Some(window) => storage.upsert_flows(flow, window),
+                        None => storage.put_flows(flow),
+                    };
+                    let _ = reply.send(ids.map(|ids| ids[0]));
+                }
+                Command::Risk(id, risk) => {
+                    commit_flows(&storage, &mut flows, merge_window);
+                    if let Err(err) = storage.set_flow_risk(id, *risk) {
+                        tracing::warn!(id, %err, "failed to store flow risk");
+                    }
+                }
+                Command::Alert(alert) => {
+                    commit_flows(&storage, &mut flows, merge_window);
+                    if let Err(err) = storage.put_alert(&alert) {
+                        tracing::warn!(alert = %alert.id, %err, "failed to store alert");
+                    }
+                }
+                Command::Call(job) => {
+                    commit_flows(&storage, &mut flows, merge_window);
+                    job(&mut storage);
+                }
+            }
+            next = rx.try_recv().ok();
+        }
+        commit_flows(&storage, &mut flows, merge_window);
+    }
+    tracing::debug!("storage writer stopped");
+}
+
+fn commit_flows(
+    storage: &Storage,
+    flows: &mut Vec<FlowEvent>,
+    merge_window: Option<chrono::Duration>,
+) {
+    if flows.is_empty() {
+        return;
+    }
+    let result = match merge_window {
+        Some(window) => storage.upsert_flows(flows, window),
+        None => storage.put_flows(flows),
+    };
+    if let Err(err) = result {
+        tracing::warn!(count = flows.len(), %err, "failed to store flow batch");
+    }
+    flows.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn queries_see_queued_writes() {
+        let storage = Storage::open(":memory:", &[3u8; 32]).unwrap();
+        let handle = AsyncStorage::spawn(
+            storage,
+            WriterConfig {
+                channel_capacity: 8,
+                max_batch: 4,
+                ..WriterConfig::default()
+            },
+        )
+        .unwrap();
+        for port in 0..10 {
+            handle
+                .put_flow(FlowEvent {
+                    dst_port: port,
+                    ..FlowEvent::default()
+                })
+                .await
+                .unwrap();
+        }
+        let flows = handle
+            .query_flows(FlowQuery {
+                limit: 100,
+                ..FlowQuery::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(flows.len(), 10);
+
+        let id = handle
+            .store_flow(FlowEvent {
+                dst_port: 445,
+                ..FlowEvent::default()
+            })
+            .await
+            .unwrap();
+        let stored = handle.call(move |storage| storage.get_flow(id)).await;
+        assert_eq!(stored.unwrap().dst_port, 445);
+    }
+}
@@ -1,9 +1,10 @@
-use std::{collections::HashMap, fs::File, io::Write, time::Duration};
+use std::{collections::HashMap, time::Duration};
 
+use analyzer::mitre::CoverageMatrix;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use tauri::{async_runtime::spawn, AppHandle, Emitter, State, WebviewWindow};
-use tokio::sync::RwLockWriteGuard;
+use tokio::sync::{broadcast, RwLockWriteGuard};
 use tokio::time::interval;
 
 use crate::{
@@ -156,21 +157,84 @@ pub async fn apply_preset(
 }
 
 #[tauri::command]
-pub async fn export_report(state: State<'_, UiState>) -> Result<String, String> {
-    let snapshot = state.snapshot.read().await.clone();
-    let exports_dir = state.exports_dir();
-    let file_path = exports_dir.join(format!(
-        "nets-report-{}.html",
-        Utc::now().format("%Y%m%d-%H%M%S")
+pub async fn set_alert_status(
+    state: State<'_, UiState>,
+    alert_id: String,
+    status: analyzer::AlertStatus,
+    assignee: Option<String>,
+    note: Option<String>,
+) -> Result<analyzer::Alert, String> {
+    let mut guard = state.snapshot.write().await;
+    let alert = guard
+        .alerts
+        .iter_mut()
+        .find(|alert| alert.id == alert_id)
+        .ok_or_else(|| format!("unknown alert {alert_id}"))?;
+    if alert.status != status {
+        alert
+            .transition(status, None, note.as_deref())
+            .map_err(|e| e.to_string())?;
+    } else if let Some(note) = note {
+        alert.add_note(None, note);
+    }
+    if assignee != alert.assignee {
+        alert.assign(assignee, None);
+    }
+    Ok(alert.clone())
+}
+
+fn bundled_rules() -> anyhow::Result<Vec<analyzer::dsl::Rule>> {
+    analyzer::dsl::load_rules_from_str(include_str!("../../../../rules/default.rules"))
+}
+
+fn coverage_matrix(alerts: &[analyzer::Alert], days: i64) -> Result<CoverageMatrix, String> {
+    let rules = bundled_rules().map_err(|e| e.to_string())?;
+    let to = Utc::now();
+    Ok(CoverageMatrix::build(
+        &rules,
+        alerts,
+        to - chrono::Duration::days(days),
+        to,
+    ))
+}
+
+#[tauri::command]
+pub async fn mitre_coverage(
+    state: State<'_, UiState>,
+    days: Option<i64>,
+) -> Result<CoverageMatrix, String> {
+    let snapshot = state.snapshot.read().await;
+    coverage_matrix(&snapshot.alerts, days.unwrap_or(30))
+}
+
+/// Writes the storage-backed report (see `nets-cli report`) over the last
+/// `days` days into the exports directory.
+#[tauri::command]
+pub async fn export_report(
+    state: State<'_, UiState>,
+    days: Option<i64>,
+    format: Option<storage::ReportFormat>,
+) -> Result<String, String> {
+    let path = state.database_path.clone();
+    let format = format.unwrap_or(storage::ReportFormat::Html);
+    let to = Utc::now();
+    let from = to - chrono::Duration::days(days.unwrap_or(7));
+    let file_path = state.exports_dir().join(format!(
+        "nets-report-{}.{}",
+        to.format("%Y%m%d-%H%M%S"),
+        format.extension()
     ));
-    let mut file = File::create(&file_path).map_err(|e| e.to_string())?;
-    write!(
-        file,
-        "<html><head><meta charset=\"utf-8\"/><title>Nets report</title></head><body><h1>Nets offline report</h1><p>Flows: {}<p><p>Alerts: {}<p></body></html>",
-        snapshot.flows.len(),
-        snapshot.alerts.len()
-    )
-    .map_err(|e| e.to_string())?;
+    let out = file_path.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let rules = bundled_rules()?;
+        let key = storage::keys::resolve_key(&path, None)?;
+        let report = storage::Storage::open(&path, &key)?.report(from, to, &rules)?;
+        std::fs::write(&out, report.render(format))?;
+        anyhow::Ok(())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("{e:#}"))?;
     Ok(file_path.display().to_string())
 }
 
@@ -188,6 +252,26 @@ pub async fn export_pcap(
     Ok(destination.display().to_string())
 }
 
+/// Runs [`storage::Storage::check`] for the diagnostics page.
+#[tauri::command]
+pub async fn check_database(
+    state: State<'_, UiState>,
+    repair: Option<bool>,
+) -> Result<storage::CheckReport, String> {
+    let path = state.database_path.clone();
+    let options = storage::CheckOptions {
+        repair: repair.unwrap_or(false),
+        ..storage::CheckOptions::default()
+    };
+    tauri::async_runtime::spawn_blocking(move || {
+        let key = storage::keys::resolve_key(&path, None)?;
+        storage::Storage::open(&path, &key)?.check(&options)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("{e:#}"))
+}
+
 #[tauri::command]
 pub async fn toggle_mode_command(state: State<'_, UiState>) -> Result<(), String> {
     toggle_mode(&*state);
@@ -246,7 +330,37 @@ pub fn spawn_status_heartbeat(handle: AppHandle, state: UiState) {
     });
 }
 
-pub fn emit_mock_flow(handle: &AppHandle, flow: collector::FlowEvent, state: &UiState) {
+/// Relays the policy engine's events into the UI stream and keeps the
+/// snapshot's enforcement state current.
+pub fn forward_policy_events(state: UiState) {
+    spawn(async move {
+        let mut rx = state.policy_events.subscribe();
+        loop {
+            let event = match rx.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "policy events dropped");
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            {
+                let mut snapshot = state.snapshot.write().await;
+                let enforcement = &mut snapshot.enforcement;
+                enforcement.retain(|known| known.id() != event.id());
+                enforcement.insert(0, event.clone());
+                enforcement.truncate(500);
+            }
+            let _ = state.sender.send(UiEvent::Policy(event));
+        }
+    });
+}
+
+pub fn emit_mock_flow(handle: &AppHandle, mut flow: collector::FlowEvent, state: &UiState) {
+    if flow.risk.is_none() {
+        analyzer::risk::RiskEngine::default()
+            .apply(&mut flow, &analyzer::risk::RiskInputs::default());
+    }
     let mut snapshot = futures::executor::block_on(state.snapshot.write());
     snapshot.flows.insert(0, flow.clone());
     if snapshot.flows.len() > 2000 {
@@ -309,7 +423,30 @@ pub fn bootstrap_snapshot() -> anyhow::Result<UiSnapshot> {
         graph,
         status,
         settings,
+        enforcement: Vec::new(),
+    })
+}
+
+/// Reads [`storage::Storage::metrics`] for the status heartbeat; failures
+/// are logged and leave the previous figures in place.
+pub async fn storage_metrics(state: &UiState) -> Option<storage::StorageMetrics> {
+    let path = state.database_path.clone();
+    let result = tauri::async_runtime::spawn_blocking(move || -> anyhow::Result<_> {
+        let key = storage::keys::resolve_key(&path, None)?;
+        storage::Storage::open(&path, &key)?.metrics()
     })
+    .await;
+    match result {
+        Ok(Ok(metrics)) => Some(metrics),
+        Ok(Err(err)) => {
+            tracing::warn!(error = %format!("{err:#}"), "cannot read storage metrics");
+            None
+        }
+        Err(err) => {
+            tracing::warn!(%err, "storage metrics task failed");
+            None
+        }
+    }
 }
 
 pub fn load_locale_from_disk(state: &UiState) -> anyhow::Result<Option<String>> {
@@ -7,9 +7,9 @@ mod state;
 use std::time::Duration;
 
 use commands::{
-    apply_preset, bootstrap_mock_stream, bootstrap_snapshot, export_pcap, export_report,
-    list_presets, load_snapshot, set_locale, start_event_stream, toggle_capture_command,
-    toggle_mode_command, update_settings,
+    apply_preset, bootstrap_mock_stream, bootstrap_snapshot, check_database, export_pcap,
+    export_report, list_presets, load_snapshot, mitre_coverage, set_alert_status, set_locale,
+    start_event_stream, toggle_capture_command, toggle_mode_command, update_settings,
 };
 use state::UiState;
 use tauri::{async_runtime::spawn, Manager};
@@ -26,11 +26,14 @@ fn main() {
             set_locale,
             export_report,
             export_pcap,
+            mitre_coverage,
             apply_preset,
+            set_alert_status,
             list_presets,
             start_event_stream,
             toggle_mode_command,
             toggle_capture_command,
+            check_database,
         ])
         .setup(|app| {
             let snapshot = bootstrap_snapshot()?;
@@ -46,6 +49,7 @@ fn main() {
             let handle = app.handle();
             bootstrap_mock_stream(handle.clone(), state_clone.clone());
             commands::spawn_status_heartbeat(handle.clone(), state_clone.clone());
+            commands::forward_policy_events(state_clone.clone());
 
             // Periodic daemon status simulation
             let status_state = state_clone.clone();
@@ -53,6 +57,7 @@ fn main() {
                 let mut ticker = interval(Duration::from_secs(30));
                 loop {
                     ticker.tick().await;
+                    let metrics = commands::storage_metrics(&status_state).await;
                     let status = {
                         let mut snapshot = status_state.snapshot.write().await;
                         snapshot.status.cpu_load = (snapshot.status.cpu_load * 0.7) + 1.3;
@@ -61,6 +66,9 @@ fn main() {
                         if snapshot.status.cpu_load > 20.0 {
                             snapshot.status.cpu_load = 4.0;
                         }
+                        if metrics.is_some() {
+                            snapshot.status.storage = metrics;
+                        }
                         snapshot.status.clone()
                     };
                     let _ = status_state.sender.send(state::UiEvent::Status(status));
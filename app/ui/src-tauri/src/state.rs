@@ -3,9 +3,13 @@ use std::{fs, path::PathBuf, sync::Arc};
 use analyzer::Alert;
 use chrono::{DateTime, Utc};
 use collector::FlowEvent;
+use policy::{PolicyEvent, PolicyEventBus};
 use serde::{Deserialize, Serialize};
 use tokio::sync::{broadcast, RwLock};
 
+/// Persisted by the storage crate; re-exported so the UI keeps one shape.
+pub use storage::{DnsRecord, ProcessActivity, ServiceRecord};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct DaemonStatus {
@@ -18,6 +22,9 @@ pub struct DaemonStatus {
     pub flows_per_second: f32,
     pub sample_ratio: String,
     pub drop_rate: f32,
+    /// Refreshed with the heartbeat; `None` until the database was read.
+    #[serde(default)]
+    pub storage: Option<storage::StorageMetrics>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,40 +43,6 @@ pub struct UiSettings {
     pub animations_enabled: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DnsRecord {
-    pub id: String,
-    pub qname: String,
-    pub qtype: String,
-    pub rcode: String,
-    pub count: u32,
-    pub last_observed: DateTime<Utc>,
-    pub channel: Option<String>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ServiceRecord {
-    pub id: String,
-    pub name: String,
-    pub protocol: String,
-    pub address: String,
-    pub port: u16,
-    pub process: Option<String>,
-    pub last_seen: DateTime<Utc>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ProcessActivity {
-    pub pid: i32,
-    pub name: String,
-    pub user: Option<String>,
-    pub signed: Option<bool>,
-    pub hash: Option<String>,
-    pub listening_ports: Vec<u16>,
-    pub total_flows: u64,
-    pub last_active: DateTime<Utc>,
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GraphNode {
     pub id: String,
@@ -112,6 +85,10 @@ pub struct UiSnapshot {
     pub graph: GraphSnapshot,
     pub status: DaemonStatus,
     pub settings: UiSettings,
+    /// Latest policy event per action id: what is pending, enforced or
+    /// failed right now.
+    #[serde(default)]
+    pub enforcement: Vec<PolicyEvent>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -120,6 +97,7 @@ pub enum UiEvent {
     Flow(FlowEvent),
     Alert(Alert),
     Status(DaemonStatus),
+    Policy(PolicyEvent),
 }
 
 #[derive(Clone)]
@@ -127,8 +105,11 @@ pub struct UiState {
     pub snapshot: Arc<RwLock<UiSnapshot>>,
     pub locale: Arc<RwLock<String>>,
     pub sender: broadcast::Sender<UiEvent>,
+    /// Handed to the policy engine; forwarded to `sender`.
+    pub policy_events: PolicyEventBus,
     pub config_path: PathBuf,
     pub exports_dir: PathBuf,
+    pub database_path: PathBuf,
 }
 
 impl UiState {
@@ -149,8 +130,11 @@ impl UiState {
             snapshot: Arc::new(RwLock::new(snapshot)),
             locale: Arc::new(RwLock::new(locale)),
             sender,
+            policy_events: PolicyEventBus::default(),
             config_path,
             exports_dir,
+            // Same default as `[storage] path` and the CLI.
+            database_path: PathBuf::from("./nets.db"),
         })
     }
 
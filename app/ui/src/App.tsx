@@ -22,6 +22,7 @@ import { DnsView } from './components/DnsView';
 import { GraphView } from './components/GraphView';
 import { ProcessesView } from './components/ProcessesView';
 import { SettingsView } from './components/SettingsView';
+import { DiagnosticsView } from './components/DiagnosticsView';
 import { StatusBar } from './components/StatusBar';
 import type {
   Alert,
@@ -45,7 +46,7 @@ const defaultFilters: SidebarFilters = {
   portExpression: ''
 };
 
-type Tab = 'flows' | 'alerts' | 'dns' | 'graph' | 'processes' | 'settings';
+type Tab = 'flows' | 'alerts' | 'dns' | 'graph' | 'processes' | 'diagnostics' | 'settings';
 
 const tabs: Array<{ id: Tab; hotkey: string; translationKey: string }> = [
   { id: 'flows', hotkey: 'F', translationKey: 'navigation.flows' },
@@ -53,6 +54,7 @@ const tabs: Array<{ id: Tab; hotkey: string; translationKey: string }> = [
   { id: 'dns', hotkey: 'D', translationKey: 'navigation.dns' },
   { id: 'graph', hotkey: 'G', translationKey: 'navigation.graph' },
   { id: 'processes', hotkey: 'P', translationKey: 'navigation.processes' },
+  { id: 'diagnostics', hotkey: 'I', translationKey: 'navigation.diagnostics' },
   { id: 'settings', hotkey: 'S', translationKey: 'navigation.settings' }
 ];
 
@@ -145,6 +147,12 @@ export default function App() {
             return { ...previous, alerts: [event.payload, ...previous.alerts].slice(0, 500) };
           case 'Status':
             return { ...previous, status: event.payload };
+          case 'Policy': {
+            const others = (previous.enforcement ?? []).filter(
+              (known) => known.id !== event.payload.id
+            );
+            return { ...previous, enforcement: [event.payload, ...others].slice(0, 500) };
+          }
           default:
             return previous;
         }
@@ -212,6 +220,7 @@ export default function App() {
     d: () => setTab('dns'),
     g: () => setTab('graph'),
     p: () => setTab('processes'),
+    i: () => setTab('diagnostics'),
     s: () => setTab('settings')
   });
 
@@ -438,6 +447,8 @@ export default function App() {
         return <GraphView graph={snapshot.graph} />;
       case 'processes':
         return <ProcessesView processes={snapshot.processes} />;
+      case 'diagnostics':
+        return <DiagnosticsView />;
       case 'settings':
         return <SettingsView settings={snapshot.settings} onSave={handleSettingsSave} exportsPath={exportPath} />;
       default:
@@ -4,7 +4,9 @@ import type {
   UiSnapshot,
   UiSettings,
   UiEvent,
-  PresetSummary
+  PresetSummary,
+  CoverageMatrix,
+  DatabaseCheckReport
 } from '../types/ui';
 import { mockSnapshot, mockSettings, mockPresets, mockEvents } from '../mocks/snapshot';
 
@@ -76,6 +78,15 @@ export async function exportReport(): Promise<string> {
   return url;
 }
 
+export async function mitreCoverage(days = 30): Promise<CoverageMatrix> {
+  if (isTauri) {
+    return invoke<CoverageMatrix>('mitre_coverage', { days });
+  }
+  const to = new Date();
+  const from = new Date(to.getTime() - days * 24 * 3600 * 1000);
+  return { from: from.toISOString(), to: to.toISOString(), techniques: [], untagged_rules: [] };
+}
+
 export async function exportPcap(flowId?: string): Promise<string> {
   if (isTauri) {
     return invoke<string>('export_pcap', { flowId });
@@ -84,6 +95,21 @@ export async function exportPcap(flowId?: string): Promise<string> {
   return URL.createObjectURL(blob);
 }
 
+export async function checkDatabase(repair = false): Promise<DatabaseCheckReport> {
+  if (isTauri) {
+    return invoke<DatabaseCheckReport>('check_database', { repair });
+  }
+  return Promise.resolve({
+    integrity_errors: [],
+    flows_sampled: 0,
+    flow_problems: [],
+    orphans: { alert_flows: 0, incident_alerts: 0 },
+    search_index_ok: true,
+    audit: { entries: 0, head: null, problems: [] },
+    repaired: repair
+  });
+}
+
 export async function startEventStream(handler: EventHandler): Promise<UnlistenFn | null> {
   if (isTauri) {
     await invoke('start_event_stream');
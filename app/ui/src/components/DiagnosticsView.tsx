@@ -0,0 +1,120 @@
+import { useState } from 'react';
+import { useTranslation } from 'react-i18next';
+import { checkDatabase } from '../api/client';
+import type { DatabaseCheckReport } from '../types/ui';
+
+function isHealthy(report: DatabaseCheckReport) {
+  const orphans = report.orphans.alert_flows + report.orphans.incident_alerts;
+  return (
+    report.integrity_errors.length === 0 &&
+    report.flow_problems.length === 0 &&
+    report.audit.problems.length === 0 &&
+    (report.repaired || (orphans === 0 && report.search_index_ok))
+  );
+}
+
+export function DiagnosticsView() {
+  const { t } = useTranslation();
+  const [report, setReport] = useState<DatabaseCheckReport | null>(null);
+  const [running, setRunning] = useState(false);
+  const [error, setError] = useState<string | null>(null);
+
+  const run = async (repair: boolean) => {
+    setRunning(true);
+    setError(null);
+    try {
+      setReport(await checkDatabase(repair));
+    } catch (err) {
+      setError(String(err));
+    } finally {
+      setRunning(false);
+    }
+  };
+
+  const tampered = report?.flow_problems.filter((problem) => problem.tampering).length ?? 0;
+
+  return (
+    <div className="settings-grid">
+      <div className="setting-card">
+        <label>{t('diagnostics.title')}</label>
+        <p>{t('diagnostics.description')}</p>
+        <div className="table-controls">
+          <button className="settings-button" onClick={() => run(false)} disabled={running}>
+            {running ? '…' : t('diagnostics.run')}
+          </button>
+          <button className="chip-button" onClick={() => run(true)} disabled={running}>
+            {t('diagnostics.repair')}
+          </button>
+        </div>
+        {error && <p role="alert">{error}</p>}
+      </div>
+      {report && (
+        <>
+          <div className="setting-card">
+            <label>{t('diagnostics.status')}</label>
+            <p>{isHealthy(report) ? t('diagnostics.healthy') : t('diagnostics.unhealthy')}</p>
+            {report.repaired && <p>{t('diagnostics.repaired')}</p>}
+          </div>
+          <div className="setting-card">
+            <label>{t('diagnostics.integrity')}</label>
+            {report.integrity_errors.length ? (
+              <ul>
+                {report.integrity_errors.map((message) => (
+                  <li key={message}>{message}</li>
+                ))}
+              </ul>
+            ) : (
+              <p>{t('diagnostics.ok')}</p>
+            )}
+          </div>
+          <div className="setting-card">
+            <label>{t('diagnostics.flows')}</label>
+            <p>
+              {t('diagnostics.flowsSummary', {
+                sampled: report.flows_sampled,
+                problems: report.flow_problems.length,
+                tampered
+              })}
+            </p>
+            <ul>
+              {report.flow_problems.map((problem) => (
+                <li key={problem.id}>
+                  #{problem.id}: {problem.error}
+                </li>
+              ))}
+            </ul>
+          </div>
+          <div className="setting-card">
+            <label>{t('diagnostics.orphans')}</label>
+            <p>
+              {t('diagnostics.orphansSummary', {
+                alertFlows: report.orphans.alert_flows,
+                incidentAlerts: report.orphans.incident_alerts
+              })}
+            </p>
+          </div>
+          <div className="setting-card">
+            <label>{t('diagnostics.searchIndex')}</label>
+            <p>{report.search_index_ok ? t('diagnostics.ok') : t('diagnostics.damaged')}</p>
+          </div>
+          <div className="setting-card">
+            <label>{t('diagnostics.audit')}</label>
+            <p>
+              {t('diagnostics.auditSummary', {
+                entries: report.audit.entries,
+                problems: report.audit.problems.length
+              })}
+            </p>
+            <ul>
+              {report.audit.problems.map((problem) => (
+                <li key={`${problem.kind}-${problem.seq}`}>
+                  {problem.kind} @ {problem.seq}
+                </li>
+              ))}
+            </ul>
+          </div>
+        </>
+      )}
+    </div>
+  );
+}
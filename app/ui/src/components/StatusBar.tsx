@@ -27,6 +27,14 @@ export function StatusBar({ status, queuedCount, theme, isPaused }: StatusBarPro
       <div className="status-bar-right">
         <span>{t('statusBar.sample', { ratio: status.sample_ratio })}</span>
         <span>{t('statusBar.dropRate', { value: status.drop_rate.toFixed(2) })}</span>
+        {status.storage && (
+          <span>
+            {t('statusBar.database', {
+              size: (status.storage.database_bytes / (1024 * 1024)).toFixed(1),
+              flows: status.storage.rows.flows ?? 0
+            })}
+          </span>
+        )}
         {queuedCount > 0 && (
           <span className="muted">{t('statusBar.queue', { count: queuedCount })}</span>
         )}
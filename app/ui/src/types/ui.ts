@@ -45,9 +45,11 @@ export interface FlowEvent {
   sni?: string | null;
   alpn?: string | null;
   ja3?: string | null;
+  ja4?: string | null;
   dns_qname?: string | null;
   dns_qtype?: string | null;
   dns_rcode?: string | null;
+  dns_answers?: string[];
 }
 
 export type Severity = 'Low' | 'Medium' | 'High';
@@ -58,10 +60,36 @@ export interface Alert {
   severity: Severity;
   rule_id: string;
   summary: string;
-  flow_refs: string[];
+  flow_refs: number[];
   process_ref?: string | null;
   rationale: string;
   suggested_action?: string | null;
+  status?: AlertStatus;
+  assignee?: string | null;
+  notes?: AlertNote[];
+  evidence?: Record<string, string>;
+}
+
+export type AlertStatus = 'New' | 'Acknowledged' | 'Resolved' | 'FalsePositive';
+
+export interface TechniqueCoverage {
+  technique: string;
+  rules: string[];
+  alerts: number;
+  last_alert?: string | null;
+}
+
+export interface CoverageMatrix {
+  from: string;
+  to: string;
+  techniques: TechniqueCoverage[];
+  untagged_rules: string[];
+}
+
+export interface AlertNote {
+  ts: string;
+  author?: string | null;
+  text: string;
 }
 
 export interface DnsRecord {
@@ -81,6 +109,7 @@ export interface ServiceRecord {
   address: string;
   port: number;
   process?: string | null;
+  first_seen: string;
   last_seen: string;
 }
 
@@ -129,6 +158,18 @@ export interface DaemonStatus {
   flows_per_second: number;
   sample_ratio: string;
   drop_rate: number;
+  storage?: StorageMetrics | null;
+}
+
+export interface StorageMetrics {
+  database_bytes: number;
+  used_bytes: number;
+  rows: Record<string, number>;
+  oldest_flow?: string | null;
+  newest_flow?: string | null;
+  oldest_alert?: string | null;
+  newest_alert?: string | null;
+  writes: { flows_written: number; alerts_written: number; flows_per_second: number };
 }
 
 export interface UiSettings {
@@ -148,12 +189,27 @@ export interface UiSnapshot {
   graph: GraphSnapshot;
   status: DaemonStatus;
   settings: UiSettings;
+  enforcement?: PolicyEvent[];
 }
 
+export type PolicyEvent =
+  | {
+      state: 'pending_approval';
+      id: string;
+      action: string;
+      description: string;
+      severity: Severity;
+      expires_at: string;
+    }
+  | { state: 'applied'; id: string; action: string; expires_at?: string | null }
+  | { state: 'rolled_back'; id: string; action: string; reason: string }
+  | { state: 'failed'; id: string; action: string; error: string };
+
 export type UiEvent =
   | { type: 'Flow'; payload: FlowEvent }
   | { type: 'Alert'; payload: Alert }
-  | { type: 'Status'; payload: DaemonStatus };
+  | { type: 'Status'; payload: DaemonStatus }
+  | { type: 'Policy'; payload: PolicyEvent };
 
 export interface SidebarFilters {
   protocol: string[];
@@ -174,3 +230,27 @@ export interface NotificationMessage {
   message: string;
   type: 'info' | 'success' | 'warning';
 }
+
+export interface DatabaseFlowProblem {
+  id: number;
+  tampering: boolean;
+  error: string;
+}
+
+export type AuditProblem =
+  | { kind: 'gap'; seq: number; expected: number }
+  | { kind: 'broken_link'; seq: number }
+  | { kind: 'hash_mismatch'; seq: number }
+  | { kind: 'bad_signature'; seq: number }
+  | { kind: 'unknown_key'; seq: number; key_id: string }
+  | { kind: 'missing_alert'; seq: number; alert_id: string };
+
+export interface DatabaseCheckReport {
+  integrity_errors: string[];
+  flows_sampled: number;
+  flow_problems: DatabaseFlowProblem[];
+  orphans: { alert_flows: number; incident_alerts: number };
+  search_index_ok: boolean;
+  audit: { entries: number; head?: number | null; problems: AuditProblem[] };
+  repaired: boolean;
+}